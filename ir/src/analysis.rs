@@ -0,0 +1,549 @@
+// Measurements of parts, and of models from parts baked beforehand. Parts a
+// model places that are missing from the baked set are left out of its
+// figures and listed, sorted by name, in `missing` of the result instead.
+
+use std::collections::{HashMap, HashSet};
+
+use cgmath::{InnerSpace, Matrix, Matrix3, SquareMatrix};
+use ldraw::{
+    document::{Document, MultipartDocument},
+    library::ResolutionResult,
+    Matrix4, PartAlias, Vector3,
+};
+
+use crate::mesh::{BakedPart, IndexedMesh};
+
+// Length of one LDU in millimetres.
+pub const MM_PER_LDU: f32 = 0.4;
+
+// Geometric properties of a part or model, in LDU.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Metrics {
+    // Total area of all triangles.
+    pub surface_area: f32,
+    // Volume enclosed by the triangles. Parts are not guaranteed to be
+    // closed or consistently wound, so this is an estimate.
+    pub volume: f32,
+}
+
+impl Metrics {
+    pub fn surface_area_mm2(&self) -> f32 {
+        self.surface_area * MM_PER_LDU * MM_PER_LDU
+    }
+
+    pub fn volume_mm3(&self) -> f32 {
+        self.volume * MM_PER_LDU * MM_PER_LDU * MM_PER_LDU
+    }
+}
+
+fn triangles<'a>(
+    mesh: &'a IndexedMesh,
+    matrix: &'a Matrix3<f32>,
+) -> impl Iterator<Item = [Vector3; 3]> + 'a {
+    mesh.indices
+        .chunks_exact(3)
+        .map(move |t| [t[0], t[1], t[2]].map(|e| matrix * mesh.positions[e as usize]))
+}
+
+fn surface_area(part: &BakedPart, matrix: &Matrix3<f32>) -> f32 {
+    part.groups
+        .iter()
+        .flat_map(|e| triangles(&e.mesh, matrix))
+        .map(|[a, b, c]| (b - a).cross(c - a).magnitude() * 0.5)
+        .sum()
+}
+
+// Sum of tetrahedra spanned by the origin and each triangle (divergence
+// theorem). Positive for outward facing triangles.
+fn signed_volume(mesh: &IndexedMesh) -> f32 {
+    triangles(mesh, &Matrix3::identity())
+        .map(|[a, b, c]| a.dot(b.cross(c)) / 6.0)
+        .sum()
+}
+
+// Surface area of `mesh` in LDU².
+pub fn mesh_surface_area(mesh: &IndexedMesh) -> f32 {
+    triangles(mesh, &Matrix3::identity())
+        .map(|[a, b, c]| (b - a).cross(c - a).magnitude() * 0.5)
+        .sum()
+}
+
+// Volume enclosed by `mesh` in LDU³, assuming it is closed.
+pub fn mesh_volume(mesh: &IndexedMesh) -> f32 {
+    signed_volume(mesh).abs()
+}
+
+// Area and volume of a single part. The volume is taken over all color
+// groups together, as a part is usually only closed as a whole.
+pub fn part_metrics(part: &BakedPart) -> Metrics {
+    Metrics {
+        surface_area: surface_area(part, &Matrix3::identity()),
+        volume: part
+            .groups
+            .iter()
+            .map(|e| signed_volume(&e.mesh))
+            .sum::<f32>()
+            .abs(),
+    }
+}
+
+// Center of the volume enclosed by `part`. Falls back to the center of
+// the bounding box for parts without volume.
+pub fn part_centroid(part: &BakedPart) -> Vector3 {
+    let identity = Matrix3::identity();
+    let mut volume = 0.0;
+    let mut moment = Vector3::new(0.0, 0.0, 0.0);
+    for [a, b, c] in part
+        .groups
+        .iter()
+        .flat_map(|e| triangles(&e.mesh, &identity))
+    {
+        let v = a.dot(b.cross(c)) / 6.0;
+        volume += v;
+        moment += (a + b + c) * (v / 4.0);
+    }
+
+    if volume.abs() < 1e-6 {
+        part.bounding_box.center()
+    } else {
+        moment / volume
+    }
+}
+
+// Density of ABS in grams per mm³.
+pub const ABS_DENSITY: f32 = 0.00105;
+
+// Known part weights in grams. Parts without an entry are weighed by
+// their volume instead.
+#[derive(Clone, Debug)]
+pub struct PartWeights {
+    weights: HashMap<PartAlias, f32>,
+    density: f32,
+}
+
+impl Default for PartWeights {
+    fn default() -> Self {
+        PartWeights {
+            weights: HashMap::new(),
+            density: ABS_DENSITY,
+        }
+    }
+}
+
+impl PartWeights {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Density in grams per mm³ for parts without a known weight.
+    pub fn with_density(density: f32) -> Self {
+        PartWeights {
+            weights: HashMap::new(),
+            density,
+        }
+    }
+
+    pub fn insert(&mut self, alias: PartAlias, grams: f32) {
+        self.weights.insert(alias, grams);
+    }
+
+    pub fn get(&self, alias: &PartAlias) -> Option<f32> {
+        self.weights.get(alias).copied()
+    }
+
+    pub fn density(&self) -> f32 {
+        self.density
+    }
+}
+
+// Weight and balance of a model.
+#[derive(Clone, Debug)]
+pub struct MassProperties {
+    // Total weight in grams.
+    pub weight: f32,
+    // Center of mass in model coordinates (LDU).
+    pub center_of_mass: Vector3,
+    // Number of part instances weighed from the weight table.
+    pub weighed: usize,
+    // Number of part instances weighed by their volume.
+    pub estimated: usize,
+    // Parts left out of the weight.
+    pub missing: Vec<PartAlias>,
+}
+
+// Totals over a whole model.
+#[derive(Clone, Debug, Default)]
+pub struct ModelMetrics {
+    pub metrics: Metrics,
+    // Number of part instances that were counted.
+    pub part_count: usize,
+    // Parts left out of the totals.
+    pub missing: Vec<PartAlias>,
+}
+
+fn linear(matrix: &Matrix4) -> Matrix3<f32> {
+    Matrix3::from_cols(
+        matrix.x.truncate(),
+        matrix.y.truncate(),
+        matrix.z.truncate(),
+    )
+}
+
+fn is_rigid(matrix: &Matrix3<f32>) -> bool {
+    let product = matrix.transpose() * matrix;
+    (0..3).all(|i| {
+        (0..3).all(|j| {
+            let expected = if i == j { 1.0 } else { 0.0 };
+            (product[i][j] - expected).abs() < 1e-4
+        })
+    })
+}
+
+pub(crate) fn collect_instances(
+    document: &Document,
+    parent: &MultipartDocument,
+    matrix: Matrix4,
+    stack: &mut Vec<PartAlias>,
+    instances: &mut Vec<(PartAlias, Matrix4)>,
+) {
+    for (e, _) in document.iter_refs_with_mode() {
+        if let Some(subpart) = parent.subparts.get(&e.name) {
+            if stack.contains(&e.name) {
+                continue;
+            }
+            stack.push(e.name.clone());
+            collect_instances(subpart, parent, matrix * e.matrix, stack, instances);
+            stack.pop();
+        } else {
+            instances.push((e.name.clone(), matrix * e.matrix));
+        }
+    }
+}
+
+// Every part placed by the model, with its matrix relative to the model.
+pub(crate) fn instances(document: &MultipartDocument) -> Vec<(PartAlias, Matrix4)> {
+    document.placements()
+}
+
+// Sums up area and volume over every part placed by `document`, with
+// parts taken from `parts` (e.g. the output of `bake_document`).
+pub fn model_metrics(
+    document: &MultipartDocument,
+    parts: &HashMap<PartAlias, BakedPart>,
+) -> ModelMetrics {
+    let mut cache: HashMap<&PartAlias, Metrics> = HashMap::new();
+    let mut missing = HashSet::new();
+    let mut result = ModelMetrics::default();
+
+    for (alias, matrix) in instances(document) {
+        let part = match parts.get_key_value(&alias) {
+            Some(e) => e,
+            None => {
+                missing.insert(alias);
+                continue;
+            }
+        };
+        let metrics = *cache.entry(part.0).or_insert_with(|| part_metrics(part.1));
+        let linear = linear(&matrix);
+
+        // Rotations keep the area; anything else has to be measured again.
+        result.metrics.surface_area += if is_rigid(&linear) {
+            metrics.surface_area
+        } else {
+            surface_area(part.1, &linear)
+        };
+        result.metrics.volume += metrics.volume * linear.determinant().abs();
+        result.part_count += 1;
+    }
+
+    result.missing = missing.into_iter().collect();
+    result
+        .missing
+        .sort_by(|a, b| a.normalized.cmp(&b.normalized));
+    result
+}
+
+// Computes weight and center of mass of every part placed by `document`.
+// Each part's mass sits at the centroid of its volume.
+pub fn mass_properties(
+    document: &MultipartDocument,
+    parts: &HashMap<PartAlias, BakedPart>,
+    weights: &PartWeights,
+) -> MassProperties {
+    let mut cache: HashMap<&PartAlias, (Metrics, Vector3)> = HashMap::new();
+    let mut missing = HashSet::new();
+    let mut moment = Vector3::new(0.0, 0.0, 0.0);
+    let mut result = MassProperties {
+        weight: 0.0,
+        center_of_mass: Vector3::new(0.0, 0.0, 0.0),
+        weighed: 0,
+        estimated: 0,
+        missing: Vec::new(),
+    };
+
+    for (alias, matrix) in instances(document) {
+        let (alias, part) = match parts.get_key_value(&alias) {
+            Some(e) => e,
+            None => {
+                missing.insert(alias);
+                continue;
+            }
+        };
+        let (metrics, centroid) = *cache
+            .entry(alias)
+            .or_insert_with(|| (part_metrics(part), part_centroid(part)));
+
+        let weight = match weights.get(alias) {
+            Some(weight) => {
+                result.weighed += 1;
+                weight
+            }
+            None => {
+                result.estimated += 1;
+                metrics.volume_mm3() * linear(&matrix).determinant().abs() * weights.density()
+            }
+        };
+        result.weight += weight;
+        moment += (matrix * centroid.extend(1.0)).truncate() * weight;
+    }
+
+    if result.weight > 0.0 {
+        result.center_of_mass = moment / result.weight;
+    }
+    result.missing = missing.into_iter().collect();
+    result
+        .missing
+        .sort_by(|a, b| a.normalized.cmp(&b.normalized));
+    result
+}
+
+// Adds the textures used by `document`, returning whether there were any.
+fn add_textures(textures: &mut Vec<String>, document: &Document) -> bool {
+    let names = document.textures();
+    for name in names.iter() {
+        if !textures.iter().any(|e| e == name) {
+            textures.push(name.to_string());
+        }
+    }
+    !names.is_empty()
+}
+
+// Counts describing a model, e.g. for an info panel.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ModelStatistics {
+    // Number of part instances placed by the model.
+    pub part_count: usize,
+    // Number of distinct parts placed by the model.
+    pub unique_parts: usize,
+    // Triangles, vertices and line segments summed over all instances.
+    pub triangles: usize,
+    pub vertices: usize,
+    pub edges: usize,
+    pub optional_edges: usize,
+    // Image files used by `!TEXMAP` in the model or its parts.
+    pub textures: Vec<String>,
+    // Number of part instances whose part uses a texture.
+    pub textured_parts: usize,
+    // Parts left out of the counts.
+    pub missing: Vec<PartAlias>,
+}
+
+// Gathers `ModelStatistics` from an already baked set of parts. Texture
+// usage is read from the model and the part files in `resolutions`; files
+// further down the part hierarchy are not inspected.
+pub fn model_statistics(
+    document: &MultipartDocument,
+    parts: &HashMap<PartAlias, BakedPart>,
+    resolutions: &ResolutionResult,
+) -> ModelStatistics {
+    let mut result = ModelStatistics::default();
+    add_textures(&mut result.textures, &document.body);
+    let mut subparts = document.subparts.iter().collect::<Vec<_>>();
+    subparts.sort_by(|(a, _), (b, _)| a.normalized.cmp(&b.normalized));
+    for (_, subpart) in subparts {
+        add_textures(&mut result.textures, subpart);
+    }
+
+    let mut textured: HashMap<PartAlias, bool> = HashMap::new();
+    let mut missing = HashSet::new();
+    for (alias, _) in instances(document) {
+        let part = match parts.get(&alias) {
+            Some(e) => e,
+            None => {
+                missing.insert(alias);
+                continue;
+            }
+        };
+
+        result.part_count += 1;
+        result.triangles += part.triangle_count();
+        result.vertices += part
+            .groups
+            .iter()
+            .map(|e| e.mesh.vertex_count())
+            .sum::<usize>();
+        result.edges += part.edges.vertices.len() / 6;
+        result.optional_edges += part.optional_edges.vertices.len() / 6;
+
+        let is_textured = *textured.entry(alias).or_insert_with_key(|alias| {
+            match resolutions.query(alias, true) {
+                Some((document, _)) => {
+                    std::iter::once(&document.body)
+                        .chain(document.subparts.values())
+                        .filter(|e| add_textures(&mut result.textures, e))
+                        .count()
+                        > 0
+                }
+                None => false,
+            }
+        });
+        if is_textured {
+            result.textured_parts += 1;
+        }
+    }
+
+    result.unique_parts = textured.len();
+    result.missing = missing.into_iter().collect();
+    result
+        .missing
+        .sort_by(|a, b| a.normalized.cmp(&b.normalized));
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use cgmath::{AbsDiffEq, SquareMatrix};
+    use ldraw::{
+        color::ColorReference,
+        document::{Document, DocumentBuilder, MultipartDocument},
+        elements::{Command, Meta, PartReference},
+        library::ResolutionResult,
+        Matrix4, PartAlias, Vector3,
+    };
+
+    use crate::test_support::cube;
+
+    use super::{
+        mass_properties, model_metrics, model_statistics, part_centroid, part_metrics, PartWeights,
+    };
+
+    fn reference(name: &str, matrix: Matrix4) -> Command {
+        Command::PartReference(PartReference {
+            color: ColorReference::Current,
+            matrix,
+            name: PartAlias::from(name),
+        })
+    }
+
+    fn document(name: &str, commands: Vec<Command>) -> Document {
+        DocumentBuilder::new(name, name, "")
+            .commands(commands)
+            .build()
+    }
+
+    #[test]
+    fn test_part_metrics() {
+        let metrics = part_metrics(&cube(1.0));
+        assert!((metrics.surface_area - 6.0).abs() < 1e-5);
+        assert!((metrics.volume - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_model_metrics() {
+        let submodel = document(
+            "sub.ldr",
+            vec![
+                reference("cube.dat", Matrix4::from_scale(2.0)),
+                reference("missing.dat", Matrix4::identity()),
+            ],
+        );
+        let document = MultipartDocument::new(
+            document(
+                "model.ldr",
+                vec![
+                    reference("cube.dat", Matrix4::identity()),
+                    reference(
+                        "sub.ldr",
+                        Matrix4::from_translation(Vector3::new(5.0, 0.0, 0.0)),
+                    ),
+                ],
+            ),
+            HashMap::from([(PartAlias::from("sub.ldr"), submodel)]),
+        );
+        let parts = HashMap::from([(PartAlias::from("cube.dat"), cube(1.0))]);
+
+        let result = model_metrics(&document, &parts);
+        assert_eq!(result.part_count, 2);
+        assert_eq!(result.missing, vec![PartAlias::from("missing.dat")]);
+        assert!((result.metrics.surface_area - 30.0).abs() < 1e-4);
+        assert!((result.metrics.volume - 9.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_mass_properties() {
+        let document = MultipartDocument::new(
+            document(
+                "model.ldr",
+                vec![
+                    reference("cube.dat", Matrix4::identity()),
+                    reference(
+                        "cube.dat",
+                        Matrix4::from_translation(Vector3::new(4.0, 0.0, 0.0)),
+                    ),
+                    reference(
+                        "heavy.dat",
+                        Matrix4::from_translation(Vector3::new(0.0, 8.0, 0.0)),
+                    ),
+                ],
+            ),
+            HashMap::new(),
+        );
+        let parts = HashMap::from([
+            (PartAlias::from("cube.dat"), cube(1.0)),
+            (PartAlias::from("heavy.dat"), cube(1.0)),
+        ]);
+        assert!(part_centroid(&cube(1.0)).abs_diff_eq(&Vector3::new(0.5, 0.5, 0.5), 1e-5));
+
+        let mut weights = PartWeights::with_density(1.0 / 0.064);
+        weights.insert(PartAlias::from("heavy.dat"), 2.0);
+        let result = mass_properties(&document, &parts, &weights);
+        assert_eq!(result.weighed, 1);
+        assert_eq!(result.estimated, 2);
+        assert!((result.weight - 4.0).abs() < 1e-4);
+        assert!(result
+            .center_of_mass
+            .abs_diff_eq(&Vector3::new(1.5, 4.5, 0.5), 1e-4));
+    }
+
+    #[test]
+    fn test_model_statistics() {
+        let mut body = document(
+            "model.ldr",
+            vec![
+                reference("cube.dat", Matrix4::identity()),
+                reference("cube.dat", Matrix4::from_scale(2.0)),
+                reference("missing.dat", Matrix4::identity()),
+            ],
+        );
+        body.commands.push(Command::Meta(Meta::TexMap(
+            "START PLANAR 0 0 0 1 0 0 0 0 1 poster.png".parse().unwrap(),
+        )));
+        let document = MultipartDocument::new(body, HashMap::new());
+        let mut cube = cube(1.0);
+        cube.edges.vertices = vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+        let parts = HashMap::from([(PartAlias::from("cube.dat"), cube)]);
+
+        let result = model_statistics(&document, &parts, &ResolutionResult::new());
+        assert_eq!(result.part_count, 2);
+        assert_eq!(result.unique_parts, 1);
+        assert_eq!(result.triangles, 24);
+        assert_eq!(result.vertices, 72);
+        assert_eq!(result.edges, 2);
+        assert_eq!(result.optional_edges, 0);
+        assert_eq!(result.textures, vec!["poster.png".to_string()]);
+        assert_eq!(result.textured_parts, 0);
+        assert_eq!(result.missing, vec![PartAlias::from("missing.dat")]);
+    }
+}
@@ -0,0 +1,448 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read, Seek, SeekFrom},
+    ops::Range,
+};
+
+use futures::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt};
+use ldraw::{
+    color::{ColorReference, MaterialRegistry},
+    Matrix4, PartAlias, Vector3,
+};
+
+use crate::{
+    bvh::transform_bounds,
+    cache::{decode, encode, put_f32s, put_str, put_u32, put_vectors, Reader},
+    geometry::{BoundingBox3, BoundingSphere},
+    lod::{LodLevel, LodPart},
+    mesh::BakedPart,
+};
+
+const MAGIC: &[u8; 4] = b"LDAS";
+
+pub const ASSET_VERSION: u32 = 1;
+
+// Bytes to read before `index_length` can tell how large the index is.
+pub const HEADER_LENGTH: usize = 12;
+
+// Where one detail level of a part is stored. The bytes in `range` are a
+// part in the `cache` format, readable with `cache::decode`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AssetLevel {
+    pub min_screen_size: f32,
+    pub range: Range<usize>,
+}
+
+#[derive(Clone, Debug)]
+pub struct AssetPart {
+    pub alias: PartAlias,
+    pub bounding_box: BoundingBox3,
+    pub bounding_sphere: BoundingSphere,
+    // Most detailed first, as in `LodPart`.
+    pub levels: Vec<AssetLevel>,
+}
+
+impl AssetPart {
+    // The level to fetch for a part spanning `screen_size` pixels, see
+    // `LodPart::select`.
+    pub fn select(&self, screen_size: f32) -> Option<&AssetLevel> {
+        self.levels
+            .iter()
+            .find(|e| screen_size >= e.min_screen_size)
+            .or_else(|| self.levels.last())
+    }
+}
+
+// A placement of `parts[part]` in the scene.
+#[derive(Clone, Debug)]
+pub struct AssetInstance {
+    pub part: usize,
+    pub color: ColorReference,
+    pub matrix: Matrix4,
+}
+
+// Everything in an asset file but the geometry: the parts with their
+// bounding volumes and where their levels are stored, and the scene placing
+// them. Viewers read the index first and then fetch only the levels they
+// draw, in whatever order suits them.
+#[derive(Clone, Debug)]
+pub struct AssetIndex {
+    pub bounding_box: BoundingBox3,
+    pub parts: Vec<AssetPart>,
+    pub instances: Vec<AssetInstance>,
+}
+
+// Serializes baked parts and the placements of a scene (as returned by
+// `SceneNode::flatten`) into an asset file.
+//
+// The file starts with the magic, the format version and the length of
+// the index, followed by the index and then every level of every part.
+// Like the cache format, everything is little endian and 4 byte aligned.
+// Placements of parts missing from `parts` are left out. Fails if the
+// file would grow past the 4 GiB offsets can address.
+pub fn encode_asset(
+    parts: &HashMap<PartAlias, LodPart>,
+    instances: &[(PartAlias, ColorReference, Matrix4)],
+) -> io::Result<Vec<u8>> {
+    let mut aliases = parts
+        .iter()
+        .filter(|(_, e)| !e.levels.is_empty())
+        .collect::<Vec<_>>();
+    aliases.sort_by(|(a, _), (b, _)| a.normalized.cmp(&b.normalized));
+    let lookup = aliases
+        .iter()
+        .enumerate()
+        .map(|(i, (alias, _))| (*alias, i))
+        .collect::<HashMap<_, _>>();
+
+    let mut index = Vec::new();
+    let mut data = Vec::new();
+    let mut scene_bounds: Option<BoundingBox3> = None;
+    let placed = instances
+        .iter()
+        .filter_map(|(alias, color, matrix)| Some((*lookup.get(alias)?, color, matrix)))
+        .collect::<Vec<_>>();
+    for (part, _, matrix) in placed.iter() {
+        let bounds = transform_bounds(&aliases[*part].1.finest().bounding_box, matrix);
+        match &mut scene_bounds {
+            Some(e) => e.update(&bounds),
+            None => scene_bounds = Some(bounds),
+        }
+    }
+    let scene_bounds = scene_bounds.unwrap_or_else(BoundingBox3::zero);
+    put_vectors(&mut index, &[scene_bounds.min, scene_bounds.max]);
+
+    put_u32(&mut index, to_u32(aliases.len())?);
+    for (alias, part) in aliases.iter() {
+        let finest = part.finest();
+        put_str(&mut index, Some(&alias.original));
+        put_vectors(
+            &mut index,
+            &[finest.bounding_box.min, finest.bounding_box.max],
+        );
+        let sphere =
+            BoundingSphere::from_points(finest.groups.iter().flat_map(|e| e.mesh.positions.iter()));
+        let center = sphere.center;
+        put_f32s(&mut index, &[center.x, center.y, center.z, sphere.radius]);
+        put_u32(&mut index, to_u32(part.levels.len())?);
+        for level in part.levels.iter() {
+            let chunk = encode(&level.part);
+            put_u32(&mut index, level.min_screen_size.to_bits());
+            put_u32(&mut index, to_u32(data.len())?);
+            put_u32(&mut index, to_u32(chunk.len())?);
+            data.extend(chunk);
+        }
+    }
+
+    put_u32(&mut index, to_u32(placed.len())?);
+    for (part, color, matrix) in placed {
+        put_u32(&mut index, to_u32(part)?);
+        put_u32(&mut index, color.code());
+        put_f32s(&mut index, AsRef::<[f32; 16]>::as_ref(matrix));
+    }
+
+    let mut buffer = Vec::with_capacity(HEADER_LENGTH + index.len() + data.len());
+    buffer.extend_from_slice(MAGIC);
+    put_u32(&mut buffer, ASSET_VERSION);
+    put_u32(&mut buffer, to_u32(HEADER_LENGTH + index.len())?);
+    buffer.extend(index);
+    buffer.extend(data);
+    Ok(buffer)
+}
+
+fn to_u32(value: usize) -> io::Result<u32> {
+    u32::try_from(value)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "asset file too large"))
+}
+
+// Length of the header and the index together, from the first
+// `HEADER_LENGTH` bytes of a file. Returns `None` if they are not the
+// header of an asset file of this version.
+pub fn index_length(header: &[u8]) -> Option<usize> {
+    let mut reader = Reader { bytes: header };
+    if reader.take(4)? != MAGIC || reader.u32()? != ASSET_VERSION {
+        return None;
+    }
+    let length = reader.u32()? as usize;
+    (length >= HEADER_LENGTH).then_some(length)
+}
+
+impl AssetIndex {
+    // Parses the index from the start of an asset file; `bytes` must span
+    // at least `index_length` bytes. Colors are resolved against
+    // `materials`.
+    pub fn parse(bytes: &[u8], materials: &MaterialRegistry) -> Option<Self> {
+        let length = index_length(bytes)?;
+        let mut reader = Reader {
+            bytes: bytes.get(HEADER_LENGTH..length)?,
+        };
+        let bounds = |reader: &mut Reader| {
+            let values = reader.vectors()?;
+            match values[..] {
+                [min, max] => Some(BoundingBox3 { min, max }),
+                _ => None,
+            }
+        };
+        let bounding_box = bounds(&mut reader)?;
+
+        let part_count = reader.u32()?;
+        let mut parts = Vec::new();
+        for _ in 0..part_count {
+            let alias = PartAlias::from(reader.str()??);
+            let bounding_box = bounds(&mut reader)?;
+            let sphere = reader.f32s()?;
+            if sphere.len() != 4 {
+                return None;
+            }
+            let level_count = reader.u32()?;
+            let mut levels = Vec::new();
+            for _ in 0..level_count {
+                let min_screen_size = f32::from_bits(reader.u32()?);
+                let start = length.checked_add(reader.u32()? as usize)?;
+                let end = start.checked_add(reader.u32()? as usize)?;
+                levels.push(AssetLevel {
+                    min_screen_size,
+                    range: start..end,
+                });
+            }
+            parts.push(AssetPart {
+                alias,
+                bounding_box,
+                bounding_sphere: BoundingSphere {
+                    center: Vector3::new(sphere[0], sphere[1], sphere[2]),
+                    radius: sphere[3],
+                },
+                levels,
+            });
+        }
+
+        let instance_count = reader.u32()?;
+        let mut instances = Vec::new();
+        for _ in 0..instance_count {
+            let part = reader.u32()? as usize;
+            let color = ColorReference::resolve(reader.u32()?, materials);
+            let values = reader.f32s()?;
+            if part >= parts.len() || values.len() != 16 {
+                return None;
+            }
+            let values: &[f32; 16] = values[..].try_into().ok()?;
+            let matrix: &Matrix4 = values.into();
+            instances.push(AssetInstance {
+                part,
+                color,
+                matrix: *matrix,
+            });
+        }
+
+        Some(AssetIndex {
+            bounding_box,
+            parts,
+            instances,
+        })
+    }
+
+    // Reads the index from the start of `reader`.
+    pub fn read<R: Read + Seek>(reader: &mut R, materials: &MaterialRegistry) -> io::Result<Self> {
+        let mut bytes = vec![0; HEADER_LENGTH];
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_exact(&mut bytes)?;
+        let length = index_length(&bytes).ok_or_else(invalid)?;
+        bytes.resize(length, 0);
+        reader.read_exact(&mut bytes[HEADER_LENGTH..])?;
+        AssetIndex::parse(&bytes, materials).ok_or_else(invalid)
+    }
+
+    // Reads the index from the start of `reader`, without blocking.
+    pub async fn read_async<R: AsyncRead + AsyncSeek + Unpin>(
+        reader: &mut R,
+        materials: &MaterialRegistry,
+    ) -> io::Result<Self> {
+        let mut bytes = vec![0; HEADER_LENGTH];
+        reader.seek(SeekFrom::Start(0)).await?;
+        reader.read_exact(&mut bytes).await?;
+        let length = index_length(&bytes).ok_or_else(invalid)?;
+        bytes.resize(length, 0);
+        reader.read_exact(&mut bytes[HEADER_LENGTH..]).await?;
+        AssetIndex::parse(&bytes, materials).ok_or_else(invalid)
+    }
+
+    pub fn get(&self, alias: &PartAlias) -> Option<&AssetPart> {
+        self.parts.iter().find(|e| e.alias == *alias)
+    }
+}
+
+fn invalid() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "malformed asset file")
+}
+
+// Reads one level of a part from anywhere in `reader`, for loading parts
+// on demand from a file.
+pub fn read_level<R: Read + Seek>(
+    reader: &mut R,
+    level: &AssetLevel,
+    materials: &MaterialRegistry,
+) -> io::Result<BakedPart> {
+    let mut bytes = vec![0; level.range.len()];
+    reader.seek(SeekFrom::Start(level.range.start as u64))?;
+    reader.read_exact(&mut bytes)?;
+    decode(&bytes, materials).ok_or_else(invalid)
+}
+
+// Reads one level of a part like `read_level`, without blocking.
+pub async fn read_level_async<R: AsyncRead + AsyncSeek + Unpin>(
+    reader: &mut R,
+    level: &AssetLevel,
+    materials: &MaterialRegistry,
+) -> io::Result<BakedPart> {
+    let mut bytes = vec![0; level.range.len()];
+    reader
+        .seek(SeekFrom::Start(level.range.start as u64))
+        .await?;
+    reader.read_exact(&mut bytes).await?;
+    decode(&bytes, materials).ok_or_else(invalid)
+}
+
+// Decodes a whole asset file held in memory, with every level of every
+// part.
+pub fn decode_asset(
+    bytes: &[u8],
+    materials: &MaterialRegistry,
+) -> Option<(AssetIndex, HashMap<PartAlias, LodPart>)> {
+    let index = AssetIndex::parse(bytes, materials)?;
+    let mut parts = HashMap::new();
+    for part in index.parts.iter() {
+        let mut levels = Vec::new();
+        for level in part.levels.iter() {
+            levels.push(LodLevel {
+                min_screen_size: level.min_screen_size,
+                part: decode(bytes.get(level.range.clone())?, materials)?,
+            });
+        }
+        parts.insert(part.alias.clone(), LodPart { levels });
+    }
+    Some((index, parts))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, io::Cursor};
+
+    use futures::executor::block_on;
+    use ldraw::{
+        color::{ColorReference, MaterialRegistry},
+        Matrix4, PartAlias, Vector3,
+    };
+
+    use crate::{
+        geometry::BoundingBox3,
+        lod::{LodLevel, LodPart},
+        mesh::{BakedMeshGroup, BakedPart, IndexedMesh},
+        part::{EdgeBufferBuilder, OptionalEdgeBufferBuilder},
+        MeshGroup,
+    };
+
+    use super::{
+        decode_asset, encode_asset, index_length, read_level, read_level_async, AssetIndex,
+        HEADER_LENGTH,
+    };
+
+    fn part(size: f32) -> BakedPart {
+        BakedPart {
+            groups: vec![BakedMeshGroup {
+                group: MeshGroup {
+                    color_ref: ColorReference::Current,
+                    bfc: true,
+                },
+                mesh: IndexedMesh {
+                    positions: vec![
+                        Vector3::new(0.0, 0.0, 0.0),
+                        Vector3::new(size, 0.0, 0.0),
+                        Vector3::new(0.0, 0.0, size),
+                    ],
+                    normals: vec![Vector3::new(0.0, -1.0, 0.0); 3],
+                    indices: vec![0, 1, 2],
+                    ..Default::default()
+                },
+                texture: None,
+            }],
+            edges: EdgeBufferBuilder::default(),
+            optional_edges: OptionalEdgeBufferBuilder::default(),
+            bounding_box: BoundingBox3::new(
+                &Vector3::new(0.0, 0.0, 0.0),
+                &Vector3::new(size, 0.0, size),
+            ),
+        }
+    }
+
+    #[test]
+    fn test_encode_decode_asset() {
+        let materials = MaterialRegistry::default();
+        let lod = LodPart {
+            levels: vec![
+                LodLevel {
+                    min_screen_size: 64.0,
+                    part: part(20.0),
+                },
+                LodLevel {
+                    min_screen_size: 0.0,
+                    part: part(10.0),
+                },
+            ],
+        };
+        let parts = HashMap::from([(PartAlias::from("3024.dat"), lod)]);
+        let instances = vec![
+            (
+                PartAlias::from("3024.dat"),
+                ColorReference::Current,
+                Matrix4::from_translation(Vector3::new(40.0, 0.0, 0.0)),
+            ),
+            (
+                PartAlias::from("missing.dat"),
+                ColorReference::Current,
+                Matrix4::from_translation(Vector3::new(0.0, 0.0, 0.0)),
+            ),
+        ];
+        let bytes = encode_asset(&parts, &instances).unwrap();
+        assert_eq!(bytes.len() % 4, 0);
+
+        let length = index_length(&bytes[..HEADER_LENGTH]).unwrap();
+        let index = AssetIndex::parse(&bytes[..length], &materials).unwrap();
+        assert_eq!(index.parts.len(), 1);
+        assert_eq!(index.instances.len(), 1);
+        assert!(index.instances[0].color.is_current());
+        assert_eq!(index.bounding_box.min, Vector3::new(40.0, 0.0, 0.0));
+        assert_eq!(index.bounding_box.max, Vector3::new(60.0, 0.0, 20.0));
+
+        let part = index.get(&PartAlias::from("3024.dat")).unwrap();
+        assert_eq!(part.bounding_sphere.center, Vector3::new(10.0, 0.0, 10.0));
+        assert_eq!(part.select(8.0).unwrap().min_screen_size, 0.0);
+        let level = part.select(100.0).unwrap();
+        assert_eq!(level.range.start % 4, 0);
+
+        let mut file = Cursor::new(bytes.clone());
+        let read = AssetIndex::read(&mut file, &materials).unwrap();
+        assert_eq!(read.parts[0].levels, part.levels);
+        let finest = read_level(&mut file, level, &materials).unwrap();
+        assert_eq!(finest.bounding_box.max, Vector3::new(20.0, 0.0, 20.0));
+
+        let mut file = futures::io::Cursor::new(bytes.clone());
+        let (read, finest) = block_on(async {
+            let read = AssetIndex::read_async(&mut file, &materials).await.unwrap();
+            let level = &read.parts[0].levels[1];
+            let part = read_level_async(&mut file, level, &materials)
+                .await
+                .unwrap();
+            (read, part)
+        });
+        assert_eq!(read.parts[0].levels, part.levels);
+        assert_eq!(finest.bounding_box.max, Vector3::new(10.0, 0.0, 10.0));
+
+        let (_, decoded) = decode_asset(&bytes, &materials).unwrap();
+        let decoded = &decoded[&PartAlias::from("3024.dat")];
+        assert_eq!(decoded.levels.len(), 2);
+        assert_eq!(decoded.levels[1].part.groups[0].mesh.positions[1].x, 10.0);
+
+        assert!(index_length(b"LDAS\x02\x00\x00\x00\x0c\x00\x00\x00").is_none());
+        assert!(decode_asset(&bytes[..bytes.len() - 4], &materials).is_none());
+    }
+}
@@ -0,0 +1,536 @@
+use std::ops::Range;
+
+use cgmath::InnerSpace;
+use ldraw::{Matrix4, Vector3};
+
+use crate::{geometry::BoundingBox3, mesh::BakedPart};
+
+// Triangles or boxes per leaf.
+const LEAF_SIZE: usize = 4;
+
+#[derive(Clone, Debug)]
+enum Content {
+    Leaf(Range<usize>),
+    Children(usize, usize),
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    bounds: BoundingBox3,
+    content: Content,
+}
+
+fn bounds_of<'a>(points: impl IntoIterator<Item = &'a Vector3>) -> BoundingBox3 {
+    let mut points = points.into_iter();
+    let first = *points.next().unwrap();
+    points.fold(BoundingBox3::new(&first, &first), |mut b, p| {
+        b.min = Vector3::new(b.min.x.min(p.x), b.min.y.min(p.y), b.min.z.min(p.z));
+        b.max = Vector3::new(b.max.x.max(p.x), b.max.y.max(p.y), b.max.z.max(p.z));
+        b
+    })
+}
+
+// Axis aligned box around `bounds` after transforming it by `matrix`.
+pub fn transform_bounds(bounds: &BoundingBox3, matrix: &Matrix4) -> BoundingBox3 {
+    let points = bounds.points().map(|e| (matrix * e.extend(1.0)).truncate());
+    bounds_of(points.iter())
+}
+
+// Distance along `direction`, in multiples of it, at which a ray from
+// `origin` enters `bounds`, or zero if it starts inside. `None` if it
+// misses.
+pub fn ray_hits_bounds(
+    origin: &Vector3,
+    direction: &Vector3,
+    bounds: &BoundingBox3,
+) -> Option<f32> {
+    let (mut near, mut far) = (0.0f32, f32::INFINITY);
+    for axis in 0..3 {
+        let inverse = 1.0 / direction[axis];
+        let a = (bounds.min[axis] - origin[axis]) * inverse;
+        let b = (bounds.max[axis] - origin[axis]) * inverse;
+        // NaN, from a ray running along a face, leaves the range as it is.
+        near = near.max(a.min(b));
+        far = far.min(a.max(b));
+    }
+    if near <= far {
+        Some(near)
+    } else {
+        None
+    }
+}
+
+// Distance along `direction`, in multiples of it, at which a ray from
+// `origin` hits either side of `triangle` (Möller-Trumbore).
+pub fn ray_hits_triangle(
+    origin: &Vector3,
+    direction: &Vector3,
+    triangle: &[Vector3; 3],
+) -> Option<f32> {
+    let (e1, e2) = (triangle[1] - triangle[0], triangle[2] - triangle[0]);
+    let h = direction.cross(e2);
+    let det = e1.dot(h);
+    if det.abs() < f32::EPSILON {
+        return None;
+    }
+    let s = origin - triangle[0];
+    let u = s.dot(h) / det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let r = s.cross(e1);
+    let v = direction.dot(r) / det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = e2.dot(r) / det;
+    if t >= 0.0 {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+fn volume(bounds: &BoundingBox3) -> f32 {
+    bounds.len_x() * bounds.len_y() * bounds.len_z()
+}
+
+// Bounding volume hierarchy over a set of triangles, for finding the
+// triangles near a region without testing every one of them.
+#[derive(Clone, Debug, Default)]
+pub struct TriangleBvh {
+    triangles: Vec<[Vector3; 3]>,
+    // The root is the first node, if there are any triangles at all.
+    nodes: Vec<Node>,
+}
+
+impl TriangleBvh {
+    pub fn new(mut triangles: Vec<[Vector3; 3]>) -> Self {
+        let mut nodes = Vec::new();
+        if !triangles.is_empty() {
+            let len = triangles.len();
+            build(
+                &mut nodes,
+                &mut triangles,
+                0..len,
+                &|e| bounds_of(e.iter().flatten()),
+                &|t| (t[0] + t[1] + t[2]) / 3.0,
+            );
+        }
+        TriangleBvh { triangles, nodes }
+    }
+
+    // Collects the triangles of every group of `part`.
+    pub fn from_part(part: &BakedPart) -> Self {
+        let triangles = part
+            .groups
+            .iter()
+            .flat_map(|group| {
+                let positions = &group.mesh.positions;
+                group
+                    .mesh
+                    .indices
+                    .chunks_exact(3)
+                    .map(move |t| [t[0], t[1], t[2]].map(|e| positions[e as usize]))
+            })
+            .collect();
+        Self::new(triangles)
+    }
+
+    pub fn triangles(&self) -> &[[Vector3; 3]] {
+        &self.triangles
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.triangles.is_empty()
+    }
+
+    // Box around all triangles, or `None` if there are none.
+    pub fn bounds(&self) -> Option<&BoundingBox3> {
+        self.nodes.first().map(|e| &e.bounds)
+    }
+
+    // Distance along `direction`, in multiples of it, to the nearest
+    // triangle a ray from `origin` hits, if any.
+    pub fn cast_ray(&self, origin: &Vector3, direction: &Vector3) -> Option<f32> {
+        let mut nearest: Option<f32> = None;
+        let mut stack = if self.nodes.is_empty() {
+            vec![]
+        } else {
+            vec![0]
+        };
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            match ray_hits_bounds(origin, direction, &node.bounds) {
+                Some(t) if nearest.is_none_or(|e| t <= e) => {}
+                _ => continue,
+            }
+
+            match &node.content {
+                Content::Leaf(range) => {
+                    for triangle in self.triangles[range.clone()].iter() {
+                        if let Some(t) = ray_hits_triangle(origin, direction, triangle) {
+                            if nearest.is_none_or(|e| t < e) {
+                                nearest = Some(t);
+                            }
+                        }
+                    }
+                }
+                Content::Children(l, r) => stack.extend([*l, *r]),
+            }
+        }
+        nearest
+    }
+
+    // Number of triangles a ray from `origin` along `direction` hits.
+    pub fn count_ray_hits(&self, origin: &Vector3, direction: &Vector3) -> usize {
+        let mut count = 0;
+        let mut stack = if self.nodes.is_empty() {
+            vec![]
+        } else {
+            vec![0]
+        };
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if ray_hits_bounds(origin, direction, &node.bounds).is_none() {
+                continue;
+            }
+
+            match &node.content {
+                Content::Leaf(range) => {
+                    count += self.triangles[range.clone()]
+                        .iter()
+                        .filter(|e| ray_hits_triangle(origin, direction, e).is_some())
+                        .count();
+                }
+                Content::Children(l, r) => stack.extend([*l, *r]),
+            }
+        }
+        count
+    }
+
+    // Calls `test` for pairs of triangles from both hierarchies whose
+    // bounds overlap, until it returns `true`. `matrix` moves `other` into
+    // the coordinates of `self`, and is applied to its triangles before
+    // they are passed on.
+    pub fn intersects<F>(&self, other: &TriangleBvh, matrix: &Matrix4, mut test: F) -> bool
+    where
+        F: FnMut(&[Vector3; 3], &[Vector3; 3]) -> bool,
+    {
+        if self.nodes.is_empty() || other.nodes.is_empty() {
+            return false;
+        }
+
+        let mut stack = vec![(0, 0)];
+        while let Some((a, b)) = stack.pop() {
+            let (node_a, node_b) = (&self.nodes[a], &other.nodes[b]);
+            let bounds_b = transform_bounds(&node_b.bounds, matrix);
+            if !node_a.bounds.intersects(&bounds_b) {
+                continue;
+            }
+
+            match (&node_a.content, &node_b.content) {
+                (Content::Leaf(range_a), Content::Leaf(range_b)) => {
+                    for tb in other.triangles[range_b.clone()].iter() {
+                        let tb = tb.map(|e| (matrix * e.extend(1.0)).truncate());
+                        let around = bounds_of(tb.iter());
+                        for ta in self.triangles[range_a.clone()].iter() {
+                            if around.intersects(&bounds_of(ta.iter())) && test(ta, &tb) {
+                                return true;
+                            }
+                        }
+                    }
+                }
+                // Splits whichever of the two nodes is larger.
+                (Content::Children(l, r), Content::Leaf(_)) => {
+                    stack.extend([(*l, b), (*r, b)]);
+                }
+                (Content::Children(l, r), Content::Children(..))
+                    if volume(&node_a.bounds) >= volume(&bounds_b) =>
+                {
+                    stack.extend([(*l, b), (*r, b)]);
+                }
+                (_, Content::Children(l, r)) => {
+                    stack.extend([(a, *l), (a, *r)]);
+                }
+            }
+        }
+        false
+    }
+}
+
+fn union(boxes: &[(BoundingBox3, usize)]) -> BoundingBox3 {
+    bounds_of(boxes.iter().flat_map(|(e, _)| [&e.min, &e.max]))
+}
+
+// Bounding volume hierarchy over boxes, such as those of placed part
+// instances. `refit` follows boxes that moved without building it anew.
+#[derive(Clone, Debug, Default)]
+pub struct BoundsBvh {
+    // Boxes with the index they were given at, in the order of the leaves.
+    boxes: Vec<(BoundingBox3, usize)>,
+    nodes: Vec<Node>,
+}
+
+impl BoundsBvh {
+    pub fn new(boxes: Vec<BoundingBox3>) -> Self {
+        let mut boxes = boxes
+            .into_iter()
+            .enumerate()
+            .map(|(i, e)| (e, i))
+            .collect::<Vec<_>>();
+        let mut nodes = Vec::new();
+        if !boxes.is_empty() {
+            let len = boxes.len();
+            build(&mut nodes, &mut boxes, 0..len, &union, &|(e, _)| e.center());
+        }
+        BoundsBvh { boxes, nodes }
+    }
+
+    pub fn len(&self) -> usize {
+        self.boxes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.boxes.is_empty()
+    }
+
+    // Moves every box to where `boxes` has it now, by the index it was
+    // given at, and grows or shrinks the nodes above it to match. The
+    // hierarchy stays as it was built, so it gets slower the further boxes
+    // move from where they were.
+    pub fn refit(&mut self, boxes: &[BoundingBox3]) {
+        assert_eq!(boxes.len(), self.boxes.len());
+        for (e, i) in self.boxes.iter_mut() {
+            *e = boxes[*i].clone();
+        }
+        // Children always come after their parents.
+        for index in (0..self.nodes.len()).rev() {
+            let bounds = match &self.nodes[index].content {
+                Content::Leaf(range) => union(&self.boxes[range.clone()]),
+                Content::Children(l, r) => {
+                    let mut bounds = self.nodes[*l].bounds.clone();
+                    bounds.update(&self.nodes[*r].bounds);
+                    bounds
+                }
+            };
+            self.nodes[index].bounds = bounds;
+        }
+    }
+
+    // Distance along `direction`, in multiples of it, to the nearest hit
+    // of a ray from `origin`. `hit` tells where the ray hits whatever box
+    // `i` holds, and is asked about boxes nearest first, as long as the
+    // ray enters them before the nearest hit so far.
+    pub fn cast_ray<F>(&self, origin: &Vector3, direction: &Vector3, mut hit: F) -> Option<f32>
+    where
+        F: FnMut(usize) -> Option<f32>,
+    {
+        let mut nearest: Option<f32> = None;
+        let mut stack = match self.nodes.first() {
+            Some(e) => ray_hits_bounds(origin, direction, &e.bounds)
+                .map(|t| vec![(t, 0)])
+                .unwrap_or_default(),
+            None => vec![],
+        };
+        while let Some((entry, index)) = stack.pop() {
+            if nearest.is_some_and(|e| entry > e) {
+                continue;
+            }
+
+            match &self.nodes[index].content {
+                Content::Leaf(range) => {
+                    let mut boxes = self.boxes[range.clone()]
+                        .iter()
+                        .filter_map(|(e, i)| ray_hits_bounds(origin, direction, e).map(|t| (t, *i)))
+                        .collect::<Vec<_>>();
+                    boxes.sort_by(|a, b| a.0.total_cmp(&b.0));
+                    for (entry, i) in boxes {
+                        if nearest.is_some_and(|e| entry > e) {
+                            break;
+                        }
+                        if let Some(t) = hit(i) {
+                            if nearest.is_none_or(|e| t < e) {
+                                nearest = Some(t);
+                            }
+                        }
+                    }
+                }
+                Content::Children(l, r) => {
+                    let mut children = [*l, *r]
+                        .into_iter()
+                        .filter_map(|e| {
+                            ray_hits_bounds(origin, direction, &self.nodes[e].bounds)
+                                .map(|t| (t, e))
+                        })
+                        .collect::<Vec<_>>();
+                    // The nearer child is popped first.
+                    children.sort_by(|a, b| b.0.total_cmp(&a.0));
+                    stack.extend(children);
+                }
+            }
+        }
+        nearest
+    }
+}
+
+// Builds the node over `items[range]` and those below it, returning its
+// index.
+fn build<T>(
+    nodes: &mut Vec<Node>,
+    items: &mut [T],
+    range: Range<usize>,
+    bounds: &impl Fn(&[T]) -> BoundingBox3,
+    centroid: &impl Fn(&T) -> Vector3,
+) -> usize {
+    let index = nodes.len();
+    let slice = &mut items[range.clone()];
+    nodes.push(Node {
+        bounds: bounds(slice),
+        content: Content::Leaf(range.clone()),
+    });
+    if slice.len() <= LEAF_SIZE {
+        return index;
+    }
+
+    // Splits at the median along the longest axis of the centroids.
+    let centroids = slice.iter().map(centroid).collect::<Vec<_>>();
+    let extent = bounds_of(centroids.iter());
+    let lengths = [extent.len_x(), extent.len_y(), extent.len_z()];
+    let axis = (0..3).fold(
+        0,
+        |best, i| if lengths[i] > lengths[best] { i } else { best },
+    );
+    slice.sort_by(|a, b| centroid(a)[axis].total_cmp(&centroid(b)[axis]));
+
+    let middle = range.start + slice.len() / 2;
+    let left = build(nodes, items, range.start..middle, bounds, centroid);
+    let right = build(nodes, items, middle..range.end, bounds, centroid);
+    nodes[index].content = Content::Children(left, right);
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::SquareMatrix;
+    use ldraw::{Matrix4, Vector3};
+
+    use crate::geometry::BoundingBox3;
+
+    use super::{ray_hits_bounds, BoundsBvh, TriangleBvh};
+
+    // A flat n x n grid of unit squares on y = 0.
+    fn grid(n: usize) -> Vec<[Vector3; 3]> {
+        let mut triangles = Vec::new();
+        for z in 0..n {
+            for x in 0..n {
+                let p = |dx: usize, dz: usize| Vector3::new((x + dx) as f32, 0.0, (z + dz) as f32);
+                triangles.push([p(0, 0), p(1, 0), p(1, 1)]);
+                triangles.push([p(0, 0), p(1, 1), p(0, 1)]);
+            }
+        }
+        triangles
+    }
+
+    #[test]
+    fn test_intersects() {
+        let bvh = TriangleBvh::new(grid(10));
+        let bounds = bvh.bounds().unwrap();
+        assert_eq!(bounds.max, Vector3::new(10.0, 0.0, 10.0));
+
+        let probe = TriangleBvh::new(vec![[
+            Vector3::new(0.2, -1.0, 0.2),
+            Vector3::new(0.4, -1.0, 0.2),
+            Vector3::new(0.2, 1.0, 0.2),
+        ]]);
+        let mut tested = 0;
+        let hit = bvh.intersects(&probe, &Matrix4::identity(), |_, _| {
+            tested += 1;
+            false
+        });
+        assert!(!hit);
+        // Only the triangles of the first square are close enough.
+        assert!(tested > 0 && tested <= 2);
+
+        let moved = Matrix4::from_translation(Vector3::new(5.0, 0.0, 5.0));
+        assert!(bvh.intersects(&probe, &moved, |_, b| b[0].x > 5.0));
+        let away = Matrix4::from_translation(Vector3::new(20.0, 0.0, 0.0));
+        assert!(!bvh.intersects(&probe, &away, |_, _| true));
+        assert!(!TriangleBvh::default().intersects(&probe, &moved, |_, _| true));
+    }
+
+    #[test]
+    fn test_cast_ray() {
+        let bvh = TriangleBvh::new(grid(10));
+        let down = Vector3::new(0.0, -2.0, 0.0);
+        assert_eq!(
+            bvh.cast_ray(&Vector3::new(2.5, 10.0, 3.5), &down),
+            Some(5.0)
+        );
+        // Either side is hit, but nothing behind or beside.
+        assert_eq!(
+            bvh.cast_ray(&Vector3::new(2.5, -4.0, 3.5), &-down),
+            Some(2.0)
+        );
+        assert_eq!(bvh.cast_ray(&Vector3::new(2.5, -4.0, 3.5), &down), None);
+        assert_eq!(bvh.cast_ray(&Vector3::new(12.5, 10.0, 3.5), &down), None);
+        assert_eq!(
+            TriangleBvh::default().cast_ray(&Vector3::new(0.0, 0.0, 0.0), &down),
+            None
+        );
+
+        let bounds = bvh.bounds().unwrap();
+        let along = Vector3::new(1.0, 0.0, 0.0);
+        assert_eq!(
+            ray_hits_bounds(&Vector3::new(-3.0, 0.0, 5.0), &along, bounds),
+            Some(3.0)
+        );
+        assert_eq!(
+            ray_hits_bounds(&Vector3::new(5.0, 0.0, 5.0), &along, bounds),
+            Some(0.0)
+        );
+        assert_eq!(
+            ray_hits_bounds(&Vector3::new(-3.0, 0.0, 15.0), &along, bounds),
+            None
+        );
+    }
+
+    #[test]
+    fn test_bounds_bvh() {
+        // Unit cubes every 3 units along x.
+        let cube = |x: f32| {
+            BoundingBox3::new(&Vector3::new(x, 0.0, 0.0), &Vector3::new(x + 1.0, 1.0, 1.0))
+        };
+        let mut boxes = (0..20).map(|i| cube(i as f32 * 3.0)).collect::<Vec<_>>();
+        let mut bvh = BoundsBvh::new(boxes.clone());
+        assert_eq!(bvh.len(), 20);
+
+        // Boxes behind the first hit are never asked about.
+        let origin = Vector3::new(-5.0, 0.5, 0.5);
+        let along = Vector3::new(1.0, 0.0, 0.0);
+        let mut asked = vec![];
+        let nearest = bvh.cast_ray(&origin, &along, |i| {
+            asked.push(i);
+            Some(boxes[i].min.x - origin.x)
+        });
+        assert_eq!(nearest, Some(5.0));
+        assert_eq!(asked, vec![0]);
+
+        // Rays keep finding boxes moved after the hierarchy was built.
+        boxes[0] = cube(100.0);
+        boxes[19] = cube(-3.0);
+        bvh.refit(&boxes);
+        let mut asked = vec![];
+        bvh.cast_ray(&origin, &along, |i| {
+            asked.push(i);
+            None
+        });
+        assert_eq!(asked[0], 19);
+        assert!(asked.contains(&0));
+        assert_eq!(asked.len(), 20);
+        assert_eq!(
+            bvh.cast_ray(&Vector3::new(0.5, 5.0, 0.5), &along, |_| Some(0.0)),
+            None
+        );
+    }
+}
@@ -0,0 +1,606 @@
+use std::{collections::HashSet, io};
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    fs,
+    io::Write as _,
+    path::{Path, PathBuf},
+};
+
+use ldraw::{
+    color::{ColorReference, MaterialRegistry},
+    document::MultipartDocument,
+    library::ResolutionResult,
+    PartAlias, Vector2, Vector3, Vector4,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::mesh::bake_with_options;
+use crate::{
+    geometry::BoundingBox3,
+    mesh::{BakeOptions, BakedMeshGroup, BakedPart, IndexedMesh},
+    part::{EdgeBufferBuilder, OptionalEdgeBufferBuilder},
+    texture::Texture,
+    MeshGroup,
+};
+
+const MAGIC: &[u8; 4] = b"LDBK";
+
+// Bumped whenever the file layout or the baking output changes, which
+// invalidates every cached file.
+pub const FORMAT_VERSION: u32 = 2;
+
+// FNV-1a, which unlike `DefaultHasher` is stable across builds.
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Fnv(0xcbf29ce484222325)
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    pub fn str(&mut self, s: &str) {
+        self.bytes(s.as_bytes());
+        self.bytes(&[0]);
+    }
+}
+
+impl io::Write for Fnv {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.bytes(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Hash of the serialized body and subparts of a file, the latter ordered by
+// name.
+fn content_hash(document: &MultipartDocument) -> u64 {
+    let mut hasher = Fnv::new();
+    let mut subparts = document.subparts.iter().collect::<Vec<_>>();
+    subparts.sort_by(|(a, _), (b, _)| a.normalized.cmp(&b.normalized));
+    for document in std::iter::once(&document.body).chain(subparts.into_iter().map(|e| e.1)) {
+        serde_json::to_writer(&mut hasher, document).expect("documents serialize");
+        hasher.bytes(&[0]);
+    }
+    hasher.0
+}
+
+// Identifies one part baked with one set of options. Covers the contents
+// of the part and every file it pulls in, so editing any of them gives a
+// new key. Colors of edges are baked in, so switching to a different
+// `LDConfig.ldr` needs a fresh cache directory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CacheKey(u64);
+
+impl CacheKey {
+    // Returns `None` if `alias` or any file it pulls in cannot be resolved,
+    // as the part would come out different once it is.
+    pub fn new(
+        alias: &PartAlias,
+        resolutions: &ResolutionResult,
+        options: &BakeOptions,
+    ) -> Option<Self> {
+        let mut hasher = Fnv::new();
+        hasher.bytes(&FORMAT_VERSION.to_le_bytes());
+        for value in [options.weld, options.seam_width, options.decimate] {
+            hasher.bytes(&value.map_or(u32::MAX, f32::to_bits).to_le_bytes());
+        }
+        hasher.bytes(&[options.remove_t_junctions as u8]);
+
+        // Walks the files the same way baking does: substitute first, then
+        // fall back to the original.
+        let mut queue = vec![alias.clone()];
+        let mut visited = HashSet::new();
+        while let Some(alias) = queue.pop() {
+            if !visited.insert(alias.clone()) {
+                continue;
+            }
+            let substitute = options.substitutions.get(&alias);
+            let (document, _) = substitute
+                .as_ref()
+                .and_then(|e| resolutions.query(e, true))
+                .or_else(|| resolutions.query(&alias, true))?;
+
+            hasher.str(&alias.normalized);
+            if let Some(substitute) = substitute {
+                hasher.str(&substitute.normalized);
+            }
+            hasher.bytes(&content_hash(&document).to_le_bytes());
+
+            let mut dependencies = document.list_dependencies().into_iter().collect::<Vec<_>>();
+            dependencies.sort_by(|a, b| b.normalized.cmp(&a.normalized));
+            queue.extend(dependencies);
+        }
+
+        Some(CacheKey(hasher.0))
+    }
+
+    pub fn file_name(&self) -> String {
+        format!("{:016x}.bin", self.0)
+    }
+}
+
+pub(crate) fn put_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn put_f32s(buffer: &mut Vec<u8>, values: &[f32]) {
+    put_u32(buffer, values.len() as u32);
+    for value in values {
+        buffer.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+pub(crate) fn put_vectors(buffer: &mut Vec<u8>, values: &[Vector3]) {
+    put_u32(buffer, values.len() as u32 * 3);
+    for value in values {
+        for e in [value.x, value.y, value.z] {
+            buffer.extend_from_slice(&e.to_le_bytes());
+        }
+    }
+}
+
+// Strings are padded with zeros to keep the alignment. `None` is stored as
+// a length of `u32::MAX`.
+pub(crate) fn put_str(buffer: &mut Vec<u8>, value: Option<&str>) {
+    match value {
+        Some(value) => {
+            put_u32(buffer, value.len() as u32);
+            buffer.extend_from_slice(value.as_bytes());
+            buffer.resize(buffer.len().next_multiple_of(4), 0);
+        }
+        None => put_u32(buffer, u32::MAX),
+    }
+}
+
+// Serializes `part` into the cache format. All values are little endian
+// and 4 byte aligned.
+pub fn encode(part: &BakedPart) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    buffer.extend_from_slice(MAGIC);
+    put_u32(&mut buffer, FORMAT_VERSION);
+    put_vectors(&mut buffer, &[part.bounding_box.min, part.bounding_box.max]);
+
+    put_u32(&mut buffer, part.groups.len() as u32);
+    for group in part.groups.iter() {
+        put_u32(&mut buffer, group.group.color_ref.code());
+        put_u32(&mut buffer, group.group.bfc as u32);
+        put_vectors(&mut buffer, &group.mesh.positions);
+        put_vectors(&mut buffer, &group.mesh.normals);
+        put_u32(&mut buffer, group.mesh.indices.len() as u32);
+        for index in group.mesh.indices.iter() {
+            put_u32(&mut buffer, *index);
+        }
+        let uvs = group.mesh.uvs.iter().flat_map(|e| [e.x, e.y]);
+        put_f32s(&mut buffer, &uvs.collect::<Vec<_>>());
+        let tangents = group
+            .mesh
+            .tangents
+            .iter()
+            .flat_map(|e| [e.x, e.y, e.z, e.w]);
+        put_f32s(&mut buffer, &tangents.collect::<Vec<_>>());
+        let texture = group.texture.as_ref();
+        put_str(&mut buffer, texture.map(|e| e.texture.as_str()));
+        put_str(&mut buffer, texture.and_then(|e| e.glossmap.as_deref()));
+    }
+
+    put_f32s(&mut buffer, &part.edges.vertices);
+    put_f32s(&mut buffer, &part.edges.colors);
+    let optional_edges = &part.optional_edges;
+    put_f32s(&mut buffer, &optional_edges.vertices);
+    put_f32s(&mut buffer, &optional_edges.controls_1);
+    put_f32s(&mut buffer, &optional_edges.controls_2);
+    put_f32s(&mut buffer, &optional_edges.direction);
+    put_f32s(&mut buffer, &optional_edges.colors);
+
+    buffer
+}
+
+pub(crate) struct Reader<'a> {
+    pub(crate) bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.bytes.len() < len {
+            return None;
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Some(head)
+    }
+
+    pub fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    pub fn u32s(&mut self) -> Option<Vec<u32>> {
+        let len = self.u32()? as usize;
+        let bytes = self.take(len.checked_mul(4)?)?;
+        Some(
+            bytes
+                .chunks_exact(4)
+                .map(|e| u32::from_le_bytes([e[0], e[1], e[2], e[3]]))
+                .collect(),
+        )
+    }
+
+    pub fn f32s(&mut self) -> Option<Vec<f32>> {
+        Some(self.u32s()?.into_iter().map(f32::from_bits).collect())
+    }
+
+    pub fn str(&mut self) -> Option<Option<String>> {
+        let len = self.u32()?;
+        if len == u32::MAX {
+            return Some(None);
+        }
+        let bytes = self.take((len as usize).checked_next_multiple_of(4)?)?;
+        let value = std::str::from_utf8(&bytes[..len as usize]).ok()?;
+        Some(Some(value.to_string()))
+    }
+
+    pub fn vectors(&mut self) -> Option<Vec<Vector3>> {
+        let values = self.f32s()?;
+        if values.len() % 3 != 0 {
+            return None;
+        }
+        Some(
+            values
+                .chunks_exact(3)
+                .map(|e| Vector3::new(e[0], e[1], e[2]))
+                .collect(),
+        )
+    }
+}
+
+// Reads a part written by `encode`, resolving colors against `materials`.
+// Returns `None` for truncated files or files of another format version.
+pub fn decode(bytes: &[u8], materials: &MaterialRegistry) -> Option<BakedPart> {
+    let mut reader = Reader { bytes };
+    if reader.take(4)? != MAGIC || reader.u32()? != FORMAT_VERSION {
+        return None;
+    }
+    let bounds = reader.vectors()?;
+    if bounds.len() != 2 {
+        return None;
+    }
+
+    let group_count = reader.u32()?;
+    let mut groups = Vec::new();
+    for _ in 0..group_count {
+        let color_ref = ColorReference::resolve(reader.u32()?, materials);
+        let bfc = reader.u32()? != 0;
+        let positions = reader.vectors()?;
+        let normals = reader.vectors()?;
+        let indices = reader.u32s()?;
+        let uvs = reader.f32s()?;
+        let tangents = reader.f32s()?;
+        let texture = reader.str()?;
+        let glossmap = reader.str()?;
+        let textured = !uvs.is_empty();
+        if normals.len() != positions.len()
+            || indices.iter().any(|e| *e as usize >= positions.len())
+            || textured
+                && (uvs.len() != positions.len() * 2 || tangents.len() != positions.len() * 4)
+        {
+            return None;
+        }
+        groups.push(BakedMeshGroup {
+            group: MeshGroup { color_ref, bfc },
+            mesh: IndexedMesh {
+                positions,
+                normals,
+                uvs: uvs
+                    .chunks_exact(2)
+                    .map(|e| Vector2::new(e[0], e[1]))
+                    .collect(),
+                tangents: tangents
+                    .chunks_exact(4)
+                    .map(|e| Vector4::new(e[0], e[1], e[2], e[3]))
+                    .collect(),
+                indices,
+            },
+            texture: texture.map(|texture| Texture { texture, glossmap }),
+        });
+    }
+
+    let edges = EdgeBufferBuilder {
+        vertices: reader.f32s()?,
+        colors: reader.f32s()?,
+    };
+    let optional_edges = OptionalEdgeBufferBuilder {
+        vertices: reader.f32s()?,
+        controls_1: reader.f32s()?,
+        controls_2: reader.f32s()?,
+        direction: reader.f32s()?,
+        colors: reader.f32s()?,
+    };
+
+    Some(BakedPart {
+        groups,
+        edges,
+        optional_edges,
+        bounding_box: BoundingBox3 {
+            min: bounds[0],
+            max: bounds[1],
+        },
+    })
+}
+
+// A directory of baked parts, one file per `CacheKey`.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone, Debug)]
+pub struct BakeCache {
+    directory: PathBuf,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl BakeCache {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Self {
+        BakeCache {
+            directory: directory.into(),
+        }
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    // Unreadable or outdated entries count as misses.
+    pub fn load(&self, key: &CacheKey, materials: &MaterialRegistry) -> Option<BakedPart> {
+        let bytes = fs::read(self.directory.join(key.file_name())).ok()?;
+        decode(&bytes, materials)
+    }
+
+    // Writes to a temporary file first, so concurrent readers never see a
+    // partially written entry.
+    pub fn store(&self, key: &CacheKey, part: &BakedPart) -> io::Result<()> {
+        fs::create_dir_all(&self.directory)?;
+        let path = self.directory.join(key.file_name());
+        let temporary = path.with_extension(format!("{}.tmp", std::process::id()));
+        fs::File::create(&temporary)?.write_all(&encode(part))?;
+        fs::rename(&temporary, &path)
+    }
+
+    // Loads `alias` from the cache, baking and storing it on a miss. Failing
+    // to store is not an error; the part is simply baked again next time.
+    // Parts with unresolved files are baked without going through the
+    // cache.
+    pub fn get_or_bake(
+        &self,
+        alias: &PartAlias,
+        resolutions: &ResolutionResult,
+        materials: &MaterialRegistry,
+        options: &BakeOptions,
+    ) -> Option<BakedPart> {
+        let key = match CacheKey::new(alias, resolutions, options) {
+            Some(e) => e,
+            None => return bake_with_options(alias, resolutions, materials, options),
+        };
+        if let Some(part) = self.load(&key, materials) {
+            return Some(part);
+        }
+        let part = bake_with_options(alias, resolutions, materials, options)?;
+        let _ = self.store(&key, &part);
+        Some(part)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ldraw::{
+        color::{ColorReference, MaterialRegistry},
+        Vector2, Vector3, Vector4,
+    };
+
+    use crate::{
+        geometry::BoundingBox3,
+        mesh::{BakedMeshGroup, BakedPart, IndexedMesh},
+        part::{EdgeBufferBuilder, OptionalEdgeBufferBuilder},
+        texture::Texture,
+        MeshGroup,
+    };
+
+    use super::{decode, encode, BakeCache, CacheKey};
+
+    fn part() -> BakedPart {
+        BakedPart {
+            groups: vec![BakedMeshGroup {
+                group: MeshGroup {
+                    color_ref: ColorReference::Current,
+                    bfc: true,
+                },
+                mesh: IndexedMesh {
+                    positions: vec![
+                        Vector3::new(0.0, 0.0, 0.0),
+                        Vector3::new(1.0, 0.0, 0.0),
+                        Vector3::new(0.0, 0.0, 1.0),
+                    ],
+                    normals: vec![Vector3::new(0.0, -1.0, 0.0); 3],
+                    uvs: vec![
+                        Vector2::new(0.0, 0.0),
+                        Vector2::new(1.0, 0.0),
+                        Vector2::new(0.0, 1.0),
+                    ],
+                    tangents: vec![Vector4::new(1.0, 0.0, 0.0, 1.0); 3],
+                    indices: vec![0, 1, 2],
+                },
+                texture: Some(Texture {
+                    texture: "logo.png".to_string(),
+                    glossmap: None,
+                }),
+            }],
+            edges: EdgeBufferBuilder {
+                vertices: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0],
+                colors: vec![-1.0; 6],
+            },
+            optional_edges: OptionalEdgeBufferBuilder::default(),
+            bounding_box: BoundingBox3::new(
+                &Vector3::new(0.0, 0.0, 0.0),
+                &Vector3::new(1.0, 0.0, 1.0),
+            ),
+        }
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let materials = MaterialRegistry::default();
+        let bytes = encode(&part());
+        assert_eq!(bytes.len() % 4, 0);
+
+        let decoded = decode(&bytes, &materials).unwrap();
+        assert_eq!(decoded.groups.len(), 1);
+        assert_eq!(
+            decoded.groups[0].mesh.positions,
+            part().groups[0].mesh.positions
+        );
+        assert_eq!(decoded.groups[0].mesh.indices, vec![0, 1, 2]);
+        assert_eq!(decoded.groups[0].mesh.uvs, part().groups[0].mesh.uvs);
+        assert_eq!(
+            decoded.groups[0].mesh.tangents,
+            part().groups[0].mesh.tangents
+        );
+        assert_eq!(decoded.groups[0].texture, part().groups[0].texture);
+        assert!(decoded.groups[0].group.color_ref.is_current());
+        assert_eq!(decoded.edges.vertices, part().edges.vertices);
+        assert_eq!(decoded.bounding_box.max, Vector3::new(1.0, 0.0, 1.0));
+
+        assert!(decode(&bytes[..bytes.len() - 4], &materials).is_none());
+        assert!(decode(b"LDBK\x00\x00\x00\x00", &materials).is_none());
+    }
+
+    #[test]
+    fn test_cache_key() {
+        use std::{collections::HashMap, sync::Arc};
+
+        use cgmath::SquareMatrix;
+        use ldraw::{
+            document::{DocumentBuilder, MultipartDocument},
+            elements::{Command, PartReference, Triangle},
+            library::{PartCache, PartKind, ResolutionResult},
+            Matrix4, PartAlias,
+        };
+
+        use crate::mesh::BakeOptions;
+
+        let document = |name: &str, command: Command| {
+            Arc::new(MultipartDocument::new(
+                DocumentBuilder::new(name, name, "")
+                    .commands(vec![command])
+                    .build(),
+                HashMap::new(),
+            ))
+        };
+        let reference = |name: &str| {
+            Command::PartReference(PartReference {
+                color: ColorReference::Current,
+                matrix: Matrix4::identity(),
+                name: PartAlias::from(name),
+            })
+        };
+        let triangle = |y: f32| {
+            Command::Triangle(Triangle {
+                color: ColorReference::Current,
+                a: Vector4::new(0.0, y, 0.0, 1.0),
+                b: Vector4::new(1.0, y, 0.0, 1.0),
+                c: Vector4::new(0.0, y, 1.0, 1.0),
+            })
+        };
+        let part = PartAlias::from("part.dat");
+        let options = BakeOptions::default();
+        let key = |cache: &PartCache| {
+            CacheKey::new(&part, &ResolutionResult::from_cache(cache), &options)
+        };
+
+        let mut cache = PartCache::new();
+        cache.register(
+            PartKind::Part,
+            part.clone(),
+            document("part.dat", reference("tri.dat")),
+        );
+        assert_eq!(key(&cache), None);
+
+        cache.register(
+            PartKind::Primitive,
+            PartAlias::from("tri.dat"),
+            document("tri.dat", triangle(0.0)),
+        );
+        let first = key(&cache).unwrap();
+        assert_eq!(key(&cache), Some(first));
+
+        // Editing a file the part pulls in changes the key.
+        cache.register(
+            PartKind::Primitive,
+            PartAlias::from("tri.dat"),
+            document("tri.dat", triangle(-1.0)),
+        );
+        assert_ne!(key(&cache), Some(first));
+    }
+
+    #[test]
+    fn test_get_or_bake_unresolved() {
+        use std::{collections::HashMap, sync::Arc};
+
+        use ldraw::{
+            document::{DocumentBuilder, MultipartDocument},
+            library::{PartCache, PartKind, ResolutionResult},
+            PartAlias,
+        };
+
+        use crate::mesh::BakeOptions;
+
+        let mut cache = PartCache::new();
+        cache.register(
+            PartKind::Part,
+            PartAlias::from("part.dat"),
+            Arc::new(MultipartDocument::new(
+                DocumentBuilder::new("part.dat", "part.dat", "")
+                    .command(ldraw::elements::Command::PartReference(
+                        ldraw::elements::PartReference {
+                            color: ColorReference::Current,
+                            matrix: ldraw::Matrix4::from_scale(1.0),
+                            name: PartAlias::from("missing.dat"),
+                        },
+                    ))
+                    .build(),
+                HashMap::new(),
+            )),
+        );
+        let directory =
+            std::env::temp_dir().join(format!("ldraw-bake-unresolved-{}", std::process::id()));
+        let bake_cache = BakeCache::new(&directory);
+
+        let part = bake_cache.get_or_bake(
+            &PartAlias::from("part.dat"),
+            &ResolutionResult::from_cache(&cache),
+            &MaterialRegistry::default(),
+            &BakeOptions::default(),
+        );
+        assert!(part.is_some());
+        assert!(!directory.exists());
+    }
+
+    #[test]
+    fn test_store_and_load() {
+        let directory =
+            std::env::temp_dir().join(format!("ldraw-bake-cache-{}", std::process::id()));
+        let cache = BakeCache::new(&directory);
+        let key = CacheKey(0x1234);
+
+        assert!(cache.load(&key, &MaterialRegistry::default()).is_none());
+        cache.store(&key, &part()).unwrap();
+        let loaded = cache.load(&key, &MaterialRegistry::default()).unwrap();
+        assert_eq!(loaded.triangle_count(), 1);
+
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+}
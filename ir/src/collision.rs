@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+
+use cgmath::{InnerSpace, SquareMatrix};
+use ldraw::{document::MultipartDocument, Matrix4, PartAlias, Vector3};
+
+use crate::{
+    analysis::instances,
+    bvh::{transform_bounds, TriangleBvh},
+    mesh::BakedPart,
+};
+
+// Default depth, in LDU, parts may sink into each other before they count
+// as overlapping. Enough to let studs sit in their anti-studs and parts
+// rest on each other.
+pub const DEFAULT_OVERLAP_TOLERANCE: f32 = 0.1;
+
+// Two parts of a model intersecting each other.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Overlap {
+    pub parts: [(PartAlias, Matrix4); 2],
+}
+
+// Whether the segment from `p` to `q` passes through `triangle`
+// (Möller-Trumbore).
+fn segment_hits_triangle(p: &Vector3, q: &Vector3, triangle: &[Vector3; 3]) -> bool {
+    let direction = q - p;
+    let (e1, e2) = (triangle[1] - triangle[0], triangle[2] - triangle[0]);
+    let h = direction.cross(e2);
+    let det = e1.dot(h);
+    if det.abs() < f32::EPSILON {
+        return false;
+    }
+    let s = p - triangle[0];
+    let u = s.dot(h) / det;
+    if !(0.0..=1.0).contains(&u) {
+        return false;
+    }
+    let r = s.cross(e1);
+    let v = direction.dot(r) / det;
+    if v < 0.0 || u + v > 1.0 {
+        return false;
+    }
+    (0.0..=1.0).contains(&(e2.dot(r) / det))
+}
+
+// Whether two triangles cross each other. Triangles merely lying in the
+// same plane do not count.
+pub fn triangles_intersect(a: &[Vector3; 3], b: &[Vector3; 3]) -> bool {
+    let crosses = |a: &[Vector3; 3], b: &[Vector3; 3]| {
+        (0..3).any(|i| segment_hits_triangle(&a[i], &a[(i + 1) % 3], b))
+    };
+    crosses(a, b) || crosses(b, a)
+}
+
+// Triangles of `part`, each pushed `tolerance` towards the inside and
+// pulled in at its corners by as much, so faces that only touch the faces
+// of another part drift apart. Triangles smaller than that are left out.
+pub fn shrunk_bvh(part: &BakedPart, tolerance: f32) -> TriangleBvh {
+    let triangles = TriangleBvh::from_part(part)
+        .triangles()
+        .iter()
+        .filter_map(|t| {
+            let normal = (t[1] - t[0]).cross(t[2] - t[0]);
+            let center = (t[0] + t[1] + t[2]) / 3.0;
+            if normal.magnitude2() <= f32::EPSILON
+                || t.iter().any(|e| (center - e).magnitude() <= tolerance)
+            {
+                return None;
+            }
+            let offset = normal.normalize() * -tolerance;
+            Some(t.map(|e| e + offset + (center - e).normalize() * tolerance))
+        })
+        .collect();
+    TriangleBvh::new(triangles)
+}
+
+// Whether the first corner of `inner`, moved by `matrix`, lies inside the
+// closed surface of `outer`, going by the parity of faces a ray crosses.
+// The ray is skewed so it is unlikely to graze an edge.
+fn corner_inside(outer: &TriangleBvh, inner: &TriangleBvh, matrix: &Matrix4) -> bool {
+    let corner = match inner.triangles().first() {
+        Some(e) => (matrix * e[0].extend(1.0)).truncate(),
+        None => return false,
+    };
+    let direction = Vector3::new(0.267, 0.802, 0.535);
+    outer.count_ray_hits(&corner, &direction) % 2 == 1
+}
+
+// Whether two parts, given as hierarchies from `shrunk_bvh` and placed
+// with `matrix_a` and `matrix_b`, intersect. A part entirely inside the
+// other counts as well.
+pub fn parts_overlap(
+    a: &TriangleBvh,
+    matrix_a: &Matrix4,
+    b: &TriangleBvh,
+    matrix_b: &Matrix4,
+) -> bool {
+    let (inverse_a, inverse_b) = match (matrix_a.invert(), matrix_b.invert()) {
+        (Some(a), Some(b)) => (a, b),
+        _ => return false,
+    };
+    let b_to_a = inverse_a * matrix_b;
+    a.intersects(b, &b_to_a, triangles_intersect)
+        || corner_inside(a, b, &b_to_a)
+        || corner_inside(b, a, &(inverse_b * matrix_a))
+}
+
+// Finds every pair of parts in `document` that intersect by more than
+// `tolerance`, with parts taken from `parts`. Parts missing from `parts`
+// are ignored. Relies on faces being wound outwards; parts without BFC
+// information may be reported when they merely touch.
+pub fn find_overlaps(
+    document: &MultipartDocument,
+    parts: &HashMap<PartAlias, BakedPart>,
+    tolerance: f32,
+) -> Vec<Overlap> {
+    let mut hierarchies = HashMap::new();
+    let mut placed = Vec::new();
+    for (alias, matrix) in instances(document) {
+        let part = match parts.get(&alias) {
+            Some(e) => e,
+            None => continue,
+        };
+        let bvh = hierarchies
+            .entry(alias.clone())
+            .or_insert_with(|| shrunk_bvh(part, tolerance));
+        if let Some(bounds) = bvh.bounds() {
+            placed.push((alias, matrix, transform_bounds(bounds, &matrix)));
+        }
+    }
+
+    // Sweeps along x, so only parts with overlapping boxes are compared.
+    placed.sort_by(|a, b| a.2.min.x.total_cmp(&b.2.min.x));
+    let mut overlaps = Vec::new();
+    for (i, (alias_a, matrix_a, bounds_a)) in placed.iter().enumerate() {
+        for (alias_b, matrix_b, bounds_b) in placed[i + 1..].iter() {
+            if bounds_b.min.x > bounds_a.max.x {
+                break;
+            }
+            if bounds_a.intersects(bounds_b)
+                && parts_overlap(
+                    &hierarchies[alias_a],
+                    matrix_a,
+                    &hierarchies[alias_b],
+                    matrix_b,
+                )
+            {
+                overlaps.push(Overlap {
+                    parts: [(alias_a.clone(), *matrix_a), (alias_b.clone(), *matrix_b)],
+                });
+            }
+        }
+    }
+    overlaps
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use cgmath::Deg;
+    use ldraw::{
+        color::ColorReference,
+        document::{DocumentBuilder, MultipartDocument},
+        elements::{Command, PartReference},
+        Matrix4, PartAlias, Vector3,
+    };
+
+    use crate::test_support::cube;
+
+    use super::{find_overlaps, triangles_intersect, DEFAULT_OVERLAP_TOLERANCE};
+
+    fn placed(x: f32, y: f32, z: f32) -> Command {
+        Command::PartReference(PartReference {
+            color: ColorReference::Current,
+            matrix: Matrix4::from_translation(Vector3::new(x, y, z)),
+            name: PartAlias::from("box.dat"),
+        })
+    }
+
+    fn overlaps(commands: Vec<Command>) -> usize {
+        let document = MultipartDocument::new(
+            DocumentBuilder::new("model.ldr", "model.ldr", "")
+                .commands(commands)
+                .build(),
+            HashMap::new(),
+        );
+        let parts = HashMap::from([(PartAlias::from("box.dat"), cube(20.0))]);
+        find_overlaps(&document, &parts, DEFAULT_OVERLAP_TOLERANCE).len()
+    }
+
+    #[test]
+    fn test_triangles_intersect() {
+        let a = [
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(2.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, 2.0),
+        ];
+        let through = [
+            Vector3::new(0.5, -1.0, 0.5),
+            Vector3::new(0.5, 1.0, 0.5),
+            Vector3::new(0.6, 1.0, 0.6),
+        ];
+        let beside = through.map(|e| e + Vector3::new(5.0, 0.0, 0.0));
+        let coplanar = a.map(|e| e + Vector3::new(0.5, 0.0, 0.5));
+        assert!(triangles_intersect(&a, &through));
+        assert!(!triangles_intersect(&a, &beside));
+        assert!(!triangles_intersect(&a, &coplanar));
+    }
+
+    #[test]
+    fn test_find_overlaps() {
+        // Stacked, side by side and diagonally touching.
+        let touching = vec![
+            placed(0.0, 0.0, 0.0),
+            placed(0.0, -20.0, 0.0),
+            placed(20.0, 0.0, 0.0),
+            placed(20.0, 20.0, 20.0),
+        ];
+        assert_eq!(overlaps(touching), 0);
+
+        let sunk = vec![placed(0.0, 0.0, 0.0), placed(10.0, -15.0, 5.0)];
+        assert_eq!(overlaps(sunk), 1);
+
+        // Turned about a corner placed inside the first one.
+        let mut turned = vec![placed(0.0, 0.0, 0.0)];
+        turned.push(Command::PartReference(PartReference {
+            color: ColorReference::Current,
+            matrix: Matrix4::from_translation(Vector3::new(15.0, 0.0, 10.0))
+                * Matrix4::from_angle_y(Deg(45.0)),
+            name: PartAlias::from("box.dat"),
+        }));
+        assert_eq!(overlaps(turned), 1);
+
+        // Shrunk to fit inside the first one without touching its faces.
+        let inside = Command::PartReference(PartReference {
+            color: ColorReference::Current,
+            matrix: Matrix4::from_translation(Vector3::new(8.0, 8.0, 8.0))
+                * Matrix4::from_scale(0.2),
+            name: PartAlias::from("box.dat"),
+        });
+        assert_eq!(overlaps(vec![placed(0.0, 0.0, 0.0), inside]), 1);
+    }
+}
@@ -0,0 +1,582 @@
+use std::collections::BTreeMap;
+
+use cgmath::{InnerSpace, Matrix, SquareMatrix};
+use ldraw::{
+    document::{Document, MultipartDocument},
+    elements::Header,
+    library::ResolutionResult,
+    Matrix4, PartAlias, Vector3,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ConnectorKind {
+    Stud,
+    // Tubes and rims on the underside of a part gripping studs.
+    AntiStud,
+    PinHole,
+    AxleHole,
+}
+
+impl ConnectorKind {
+    // Recognizes the primitives connection points are modeled with.
+    pub fn from_primitive(alias: &PartAlias) -> Option<Self> {
+        let name = alias.normalized.rsplit('/').next()?;
+        let name = name.strip_suffix(".dat").unwrap_or(name);
+        match name {
+            "stud" | "stud2" | "stud2a" | "stud6" | "stud6a" | "stud10" | "stud15" | "studa" => {
+                Some(ConnectorKind::Stud)
+            }
+            e if e.starts_with("stud3") || e.starts_with("stud4") => Some(ConnectorKind::AntiStud),
+            e if ["peghole", "npeghol", "beamhole", "connhol"]
+                .iter()
+                .any(|p| e.starts_with(p)) =>
+            {
+                Some(ConnectorKind::PinHole)
+            }
+            e if ["axlehol", "axl2hol", "axl3hol"]
+                .iter()
+                .any(|p| e.starts_with(p)) =>
+            {
+                Some(ConnectorKind::AxleHole)
+            }
+            _ => None,
+        }
+    }
+}
+
+// A connection point, placed by the matrix of the primitive it was found
+// through.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Connector {
+    pub kind: ConnectorKind,
+    pub primitive: PartAlias,
+    pub matrix: Matrix4,
+}
+
+impl Connector {
+    pub fn position(&self) -> Vector3 {
+        self.matrix.w.truncate()
+    }
+
+    // Up direction of the primitive (-Y in LDraw), which is where studs
+    // point to and holes run along.
+    pub fn axis(&self) -> Vector3 {
+        (-self.matrix.y.truncate()).normalize()
+    }
+
+    // The same connector on a part placed with `matrix`.
+    pub fn transform(&self, matrix: &Matrix4) -> Self {
+        Connector {
+            matrix: matrix * self.matrix,
+            ..self.clone()
+        }
+    }
+}
+
+// Calls `visit` with the file `alias` refers to, looking at the subparts of
+// `parent` first.
+fn visit_reference<F>(
+    alias: &PartAlias,
+    parent: &MultipartDocument,
+    resolutions: &ResolutionResult,
+    local: bool,
+    visit: F,
+) where
+    F: FnOnce(&Document, &MultipartDocument, bool),
+{
+    if let Some(subpart) = parent.get_subpart(alias) {
+        visit(subpart, parent, local);
+    } else if let Some((document, local)) = resolutions.query(alias, local) {
+        visit(&document.body, &document, local);
+    }
+}
+
+fn collect_connectors(
+    document: &Document,
+    parent: &MultipartDocument,
+    resolutions: &ResolutionResult,
+    matrix: Matrix4,
+    local: bool,
+    stack: &mut Vec<PartAlias>,
+    connectors: &mut Vec<Connector>,
+) {
+    for (e, _) in document.iter_refs_with_mode() {
+        let matrix = matrix * e.matrix;
+        if let Some(kind) = ConnectorKind::from_primitive(&e.name) {
+            connectors.push(Connector {
+                kind,
+                primitive: e.name.clone(),
+                matrix,
+            });
+            continue;
+        }
+        if stack.contains(&e.name) {
+            continue;
+        }
+
+        stack.push(e.name.clone());
+        visit_reference(
+            &e.name,
+            parent,
+            resolutions,
+            local,
+            |document, parent, local| {
+                collect_connectors(
+                    document,
+                    parent,
+                    resolutions,
+                    matrix,
+                    local,
+                    stack,
+                    connectors,
+                )
+            },
+        );
+        stack.pop();
+    }
+}
+
+// Finds the connection points of a part by walking the files it is built
+// from. Works on whole models as well, giving connectors in model
+// coordinates.
+pub fn find_connectors(
+    document: &MultipartDocument,
+    resolutions: &ResolutionResult,
+    local: bool,
+) -> Vec<Connector> {
+    let mut connectors = Vec::new();
+    collect_connectors(
+        &document.body,
+        document,
+        resolutions,
+        Matrix4::identity(),
+        local,
+        &mut Vec::new(),
+        &mut connectors,
+    );
+    connectors
+}
+
+// Shape of a connection declared with an LDCad `SNAP_*` meta.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SnapShape {
+    Cylinder,
+    Clip,
+    Finger,
+    Generic,
+    Sphere,
+}
+
+impl SnapShape {
+    fn from_meta(name: &str) -> Option<Self> {
+        match name {
+            "SNAP_CYL" => Some(SnapShape::Cylinder),
+            "SNAP_CLP" => Some(SnapShape::Clip),
+            "SNAP_FGR" => Some(SnapShape::Finger),
+            "SNAP_GEN" => Some(SnapShape::Generic),
+            "SNAP_SPH" => Some(SnapShape::Sphere),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Gender {
+    Male,
+    Female,
+}
+
+// A connection point editors can snap other parts onto.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SnapConnector {
+    pub shape: SnapShape,
+    pub gender: Option<Gender>,
+    pub id: Option<String>,
+    pub matrix: Matrix4,
+    // Remaining parameters of the meta, e.g. `secs` or `caps`, keyed by
+    // their lowercase names.
+    pub properties: BTreeMap<String, String>,
+    // The primitive this connector was derived from, for parts without
+    // snap metas.
+    pub detected: Option<ConnectorKind>,
+}
+
+impl SnapConnector {
+    pub fn position(&self) -> Vector3 {
+        self.matrix.w.truncate()
+    }
+
+    // The Y axis of the meta, which cylinders and fingers run along.
+    pub fn axis(&self) -> Vector3 {
+        self.matrix.y.truncate().normalize()
+    }
+
+    // The same connector on a part placed with `matrix`.
+    pub fn transform(&self, matrix: &Matrix4) -> Self {
+        SnapConnector {
+            matrix: matrix * self.matrix,
+            ..self.clone()
+        }
+    }
+}
+
+impl From<&Connector> for SnapConnector {
+    fn from(connector: &Connector) -> Self {
+        let gender = match connector.kind {
+            ConnectorKind::Stud => Gender::Male,
+            _ => Gender::Female,
+        };
+        SnapConnector {
+            shape: SnapShape::Cylinder,
+            gender: Some(gender),
+            id: None,
+            matrix: connector.matrix,
+            properties: BTreeMap::new(),
+            detected: Some(connector.kind),
+        }
+    }
+}
+
+// `[key=value]` parameters following the name of a meta.
+pub(crate) fn ldcad_parameters(text: &str) -> BTreeMap<String, String> {
+    text.split('[')
+        .skip(1)
+        .filter_map(|e| {
+            let (key, value) = e.split_once(']')?.0.split_once('=')?;
+            Some((key.trim().to_lowercase(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+pub(crate) fn floats(text: &str) -> Option<Vec<f32>> {
+    text.split_whitespace().map(|e| e.parse().ok()).collect()
+}
+
+// `pos` and `ori` of a meta, the same as the position and matrix of a type
+// 1 line.
+fn snap_placement(parameters: &mut BTreeMap<String, String>) -> Matrix4 {
+    let pos = parameters.remove("pos").and_then(|e| floats(&e));
+    let ori = parameters.remove("ori").and_then(|e| floats(&e));
+    let [x, y, z] = match pos.as_deref() {
+        Some(&[x, y, z]) => [x, y, z],
+        _ => [0.0; 3],
+    };
+    match ori.as_deref() {
+        Some(&[a, b, c, d, e, f, g, h, i]) => {
+            Matrix4::new(a, b, c, x, d, e, f, y, g, h, i, z, 0.0, 0.0, 0.0, 1.0).transpose()
+        }
+        _ => Matrix4::from_translation(Vector3::new(x, y, z)),
+    }
+}
+
+// Copies a `grid` parameter asks for, as offsets in the XZ plane of the
+// meta: `[C] count [C] count step step`, where `C` centers the row.
+fn snap_grid(parameters: &mut BTreeMap<String, String>) -> Vec<Vector3> {
+    let grid = match parameters.remove("grid") {
+        Some(e) => e,
+        None => return vec![Vector3::new(0.0, 0.0, 0.0)],
+    };
+    let mut tokens = grid.split_whitespace().peekable();
+    let mut axes = Vec::new();
+    for _ in 0..2 {
+        let centered = tokens.next_if(|e| e.eq_ignore_ascii_case("C")).is_some();
+        let count = tokens
+            .next()
+            .and_then(|e| e.parse::<usize>().ok())
+            .unwrap_or(1);
+        axes.push((centered, count));
+    }
+    let steps = floats(&tokens.collect::<Vec<_>>().join(" ")).unwrap_or_default();
+    let offsets = |(centered, count): (bool, usize), step: f32| {
+        let start = if centered {
+            (count.max(1) - 1) as f32 * step * -0.5
+        } else {
+            0.0
+        };
+        (0..count).map(move |i| start + i as f32 * step)
+    };
+    let (x_step, z_step) = match steps.as_slice() {
+        &[x, z] => (x, z),
+        _ => (0.0, 0.0),
+    };
+    offsets(axes[0], x_step)
+        .flat_map(|x| offsets(axes[1], z_step).map(move |z| Vector3::new(x, 0.0, z)))
+        .collect()
+}
+
+fn collect_snaps(
+    document: &Document,
+    parent: &MultipartDocument,
+    resolutions: &ResolutionResult,
+    matrix: Matrix4,
+    local: bool,
+    stack: &mut Vec<PartAlias>,
+    snaps: &mut Vec<SnapConnector>,
+) {
+    let start = snaps.len();
+    for (e, _) in document.iter_refs_with_mode() {
+        if stack.contains(&e.name) {
+            continue;
+        }
+        stack.push(e.name.clone());
+        visit_reference(
+            &e.name,
+            parent,
+            resolutions,
+            local,
+            |document, parent, local| {
+                collect_snaps(
+                    document,
+                    parent,
+                    resolutions,
+                    matrix * e.matrix,
+                    local,
+                    stack,
+                    snaps,
+                )
+            },
+        );
+        stack.pop();
+    }
+
+    let metas = document
+        .headers
+        .iter()
+        .filter(|Header(key, _)| key == "LDCAD")
+        .map(|Header(_, value)| value.split_once(' ').unwrap_or((value, "")))
+        .collect::<Vec<_>>();
+
+    // Headers lose their place between the references, so clearing always
+    // applies to everything inherited from them.
+    for (_, parameters) in metas.iter().filter(|(name, _)| *name == "SNAP_CLEAR") {
+        match ldcad_parameters(parameters).get("id") {
+            Some(id) => {
+                let inherited = snaps.split_off(start);
+                snaps.extend(inherited.into_iter().filter(|e| e.id.as_ref() != Some(id)));
+            }
+            None => snaps.truncate(start),
+        }
+    }
+
+    for (name, parameters) in metas {
+        let mut parameters = ldcad_parameters(parameters);
+        let placement = matrix * snap_placement(&mut parameters);
+        let grid = snap_grid(&mut parameters);
+        let matrices = grid
+            .into_iter()
+            .map(|e| placement * Matrix4::from_translation(e));
+
+        if name == "SNAP_INCL" {
+            let alias = match parameters.get("ref") {
+                Some(e) => PartAlias::from(e),
+                None => continue,
+            };
+            if stack.contains(&alias) {
+                continue;
+            }
+            stack.push(alias.clone());
+            for matrix in matrices {
+                visit_reference(
+                    &alias,
+                    parent,
+                    resolutions,
+                    local,
+                    |document, parent, local| {
+                        collect_snaps(document, parent, resolutions, matrix, local, stack, snaps)
+                    },
+                );
+            }
+            stack.pop();
+        } else if let Some(shape) = SnapShape::from_meta(name) {
+            let gender = match parameters.remove("gender").as_deref() {
+                Some("M") | Some("m") => Some(Gender::Male),
+                Some("F") | Some("f") => Some(Gender::Female),
+                _ => None,
+            };
+            let id = parameters.remove("id");
+            for matrix in matrices {
+                snaps.push(SnapConnector {
+                    shape,
+                    gender,
+                    id: id.clone(),
+                    matrix,
+                    properties: parameters.clone(),
+                    detected: None,
+                });
+            }
+        }
+    }
+}
+
+// Collects the connectors LDCad `SNAP_*` metas declare for a part and the
+// files it references. Parts without any fall back to `find_connectors`.
+pub fn find_snaps(
+    document: &MultipartDocument,
+    resolutions: &ResolutionResult,
+    local: bool,
+) -> Vec<SnapConnector> {
+    let mut snaps = Vec::new();
+    collect_snaps(
+        &document.body,
+        document,
+        resolutions,
+        Matrix4::identity(),
+        local,
+        &mut Vec::new(),
+        &mut snaps,
+    );
+    if snaps.is_empty() {
+        snaps = find_connectors(document, resolutions, local)
+            .iter()
+            .map(SnapConnector::from)
+            .collect();
+    }
+    snaps
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use cgmath::{AbsDiffEq, Deg, SquareMatrix};
+    use ldraw::{
+        color::ColorReference,
+        document::{DocumentBuilder, MultipartDocument},
+        elements::{Command, Header, PartReference},
+        library::ResolutionResult,
+        Matrix4, PartAlias, Vector3,
+    };
+
+    use super::{find_connectors, find_snaps, ldcad_parameters, ConnectorKind, Gender, SnapShape};
+
+    fn reference(name: &str, matrix: Matrix4) -> Command {
+        Command::PartReference(PartReference {
+            color: ColorReference::Current,
+            matrix,
+            name: PartAlias::from(name),
+        })
+    }
+
+    #[test]
+    fn test_connector_kind() {
+        let kind = |e: &str| ConnectorKind::from_primitive(&PartAlias::from(e));
+        assert_eq!(kind("stud.dat"), Some(ConnectorKind::Stud));
+        assert_eq!(kind("8\\STUD2A.DAT"), Some(ConnectorKind::Stud));
+        assert_eq!(kind("stud4o.dat"), Some(ConnectorKind::AntiStud));
+        assert_eq!(kind("npeghol6a.dat"), Some(ConnectorKind::PinHole));
+        assert_eq!(kind("axlehol8.dat"), Some(ConnectorKind::AxleHole));
+        assert_eq!(kind("studline.dat"), None);
+        assert_eq!(kind("3001.dat"), None);
+    }
+
+    #[test]
+    fn test_find_connectors() {
+        let sideways = Matrix4::from_translation(Vector3::new(0.0, 10.0, 0.0))
+            * Matrix4::from_angle_z(Deg(90.0));
+        let body = DocumentBuilder::new("beam.ldr", "beam.ldr", "")
+            .commands(vec![
+                reference(
+                    "stud.dat",
+                    Matrix4::from_translation(Vector3::new(10.0, 0.0, 0.0)),
+                ),
+                reference("holes.ldr", Matrix4::identity()),
+                reference("3001.dat", Matrix4::identity()),
+            ])
+            .build();
+        let holes = DocumentBuilder::new("holes.ldr", "holes.ldr", "")
+            .commands(vec![
+                reference("peghole.dat", sideways),
+                reference("stud4.dat", Matrix4::identity()),
+            ])
+            .build();
+        let document =
+            MultipartDocument::new(body, HashMap::from([(PartAlias::from("holes.ldr"), holes)]));
+
+        let connectors = find_connectors(&document, &ResolutionResult::new(), false);
+        let kinds = connectors.iter().map(|e| e.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                ConnectorKind::Stud,
+                ConnectorKind::PinHole,
+                ConnectorKind::AntiStud
+            ]
+        );
+        assert_eq!(connectors[0].position(), Vector3::new(10.0, 0.0, 0.0));
+        assert_eq!(connectors[0].axis(), Vector3::new(0.0, -1.0, 0.0));
+        assert_eq!(connectors[1].position(), Vector3::new(0.0, 10.0, 0.0));
+        assert!(connectors[1]
+            .axis()
+            .abs_diff_eq(&Vector3::new(1.0, 0.0, 0.0), 1e-5));
+
+        let moved =
+            connectors[0].transform(&Matrix4::from_translation(Vector3::new(0.0, 0.0, 5.0)));
+        assert_eq!(moved.position(), Vector3::new(10.0, 0.0, 5.0));
+    }
+
+    #[test]
+    fn test_ldcad_parameters() {
+        let parameters = ldcad_parameters("[gender=M] [secs=R 6 4] [Pos=0 -4 0] [broken");
+        assert_eq!(parameters.len(), 3);
+        assert_eq!(parameters["secs"], "R 6 4");
+        assert_eq!(parameters["pos"], "0 -4 0");
+    }
+
+    #[test]
+    fn test_find_snaps() {
+        let pin = DocumentBuilder::new("pin.ldr", "pin.ldr", "")
+            .header(
+                "LDCAD",
+                "SNAP_CYL [id=pin] [gender=F] [ori=1 0 0 0 0 1 0 -1 0]",
+            )
+            .build();
+        let body = DocumentBuilder::new("plate.ldr", "plate.ldr", "")
+            .header(
+                "LDCAD",
+                "SNAP_CYL [id=stud] [gender=M] [secs=R 6 4] [grid=C 2 1 20 20]",
+            )
+            .header("LDCAD", "SNAP_INCL [ref=pin.ldr] [pos=0 20 0]")
+            .commands(vec![
+                reference(
+                    "pin.ldr",
+                    Matrix4::from_translation(Vector3::new(0.0, 10.0, 0.0)),
+                ),
+                reference("stud.dat", Matrix4::identity()),
+            ])
+            .build();
+        let mut document =
+            MultipartDocument::new(body, HashMap::from([(PartAlias::from("pin.ldr"), pin)]));
+
+        let snaps = find_snaps(&document, &ResolutionResult::new(), false);
+        assert_eq!(snaps.len(), 4);
+        assert!(snaps.iter().all(|e| e.shape == SnapShape::Cylinder));
+        // Inherited from the reference first.
+        assert_eq!(snaps[0].gender, Some(Gender::Female));
+        assert_eq!(snaps[0].position(), Vector3::new(0.0, 10.0, 0.0));
+        assert!(snaps[0]
+            .axis()
+            .abs_diff_eq(&Vector3::new(0.0, 0.0, -1.0), 1e-5));
+        assert_eq!(snaps[1].position(), Vector3::new(-10.0, 0.0, 0.0));
+        assert_eq!(snaps[2].position(), Vector3::new(10.0, 0.0, 0.0));
+        assert_eq!(snaps[2].properties["secs"], "R 6 4");
+        assert_eq!(snaps[3].id.as_deref(), Some("pin"));
+        assert_eq!(snaps[3].position(), Vector3::new(0.0, 20.0, 0.0));
+
+        document
+            .body
+            .headers
+            .push(Header("LDCAD".into(), "SNAP_CLEAR [id=pin]".into()));
+        let snaps = find_snaps(&document, &ResolutionResult::new(), false);
+        assert_eq!(snaps.len(), 3);
+        assert_eq!(snaps[0].id.as_deref(), Some("stud"));
+
+        // Without metas, the stud primitive is all there is.
+        document.body.headers.clear();
+        document.subparts.clear();
+        let snaps = find_snaps(&document, &ResolutionResult::new(), false);
+        assert_eq!(snaps.len(), 1);
+        assert_eq!(snaps[0].gender, Some(Gender::Male));
+        assert_eq!(snaps[0].detected, Some(ConnectorKind::Stud));
+    }
+}
@@ -0,0 +1,381 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+};
+
+use cgmath::InnerSpace;
+use ldraw::Vector3;
+
+use crate::mesh::IndexedMesh;
+
+// Symmetric 4x4 error quadric of Garland and Heckbert, upper triangle only.
+#[derive(Clone, Copy, Debug, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn plane(normal: Vector3, d: f32, weight: f64) -> Self {
+        let (a, b, c, d) = (normal.x as f64, normal.y as f64, normal.z as f64, d as f64);
+        let mut q = [
+            a * a,
+            a * b,
+            a * c,
+            a * d,
+            b * b,
+            b * c,
+            b * d,
+            c * c,
+            c * d,
+            d * d,
+        ];
+        for e in q.iter_mut() {
+            *e *= weight;
+        }
+        Quadric(q)
+    }
+
+    fn add(&self, other: &Quadric) -> Quadric {
+        let mut q = self.0;
+        for (e, o) in q.iter_mut().zip(other.0.iter()) {
+            *e += o;
+        }
+        Quadric(q)
+    }
+
+    fn error(&self, v: &Vector3) -> f64 {
+        let q = &self.0;
+        let (x, y, z) = (v.x as f64, v.y as f64, v.z as f64);
+        q[0] * x * x
+            + 2.0 * q[1] * x * y
+            + 2.0 * q[2] * x * z
+            + 2.0 * q[3] * x
+            + q[4] * y * y
+            + 2.0 * q[5] * y * z
+            + 2.0 * q[6] * y
+            + q[7] * z * z
+            + 2.0 * q[8] * z
+            + q[9]
+    }
+}
+
+// Cost bits, both ends and their versions when the entry was pushed.
+type Candidate = Reverse<(u64, u32, u32, u32, u32)>;
+
+struct Collapse {
+    keep: u32,
+    remove: u32,
+    position: Vector3,
+}
+
+struct Decimator<'a> {
+    mesh: &'a mut IndexedMesh,
+    faces: Vec<[u32; 3]>,
+    alive: Vec<bool>,
+    vertex_faces: Vec<Vec<usize>>,
+    quadrics: Vec<Quadric>,
+    locked: Vec<bool>,
+    removed: Vec<bool>,
+    versions: Vec<u32>,
+}
+
+impl<'a> Decimator<'a> {
+    fn new(mesh: &'a mut IndexedMesh) -> Self {
+        let faces = mesh
+            .indices
+            .chunks_exact(3)
+            .map(|t| [t[0], t[1], t[2]])
+            .collect::<Vec<_>>();
+        let count = mesh.positions.len();
+
+        let mut vertex_faces = vec![Vec::new(); count];
+        let mut quadrics = vec![Quadric::default(); count];
+        let mut edges: HashMap<(u32, u32), usize> = HashMap::new();
+        for (i, face) in faces.iter().enumerate() {
+            let [a, b, c] = face.map(|e| mesh.positions[e as usize]);
+            let cross = (b - a).cross(c - a);
+            let area = cross.magnitude() as f64 * 0.5;
+            if area > 0.0 {
+                let normal = cross.normalize();
+                let quadric = Quadric::plane(normal, -normal.dot(a), area);
+                for v in face.iter() {
+                    quadrics[*v as usize] = quadrics[*v as usize].add(&quadric);
+                }
+            }
+            for e in 0..3 {
+                let (a, b) = (face[e], face[(e + 1) % 3]);
+                *edges.entry((a.min(b), a.max(b))).or_default() += 1;
+                vertex_faces[face[e] as usize].push(i);
+            }
+        }
+
+        // Vertices on open or non-manifold edges stay put. Welding keeps
+        // vertices on either side of a hard edge apart, so this covers hard
+        // edges as well as the outline of the mesh, and no cracks open up.
+        let mut locked = vec![false; count];
+        for ((a, b), uses) in edges {
+            if uses != 2 {
+                locked[a as usize] = true;
+                locked[b as usize] = true;
+            }
+        }
+
+        Decimator {
+            alive: vec![true; faces.len()],
+            faces,
+            vertex_faces,
+            quadrics,
+            locked,
+            removed: vec![false; count],
+            versions: vec![0; count],
+            mesh,
+        }
+    }
+
+    fn plan(&self, a: u32, b: u32) -> Option<(f64, Collapse)> {
+        let (pa, pb) = (
+            self.mesh.positions[a as usize],
+            self.mesh.positions[b as usize],
+        );
+        let quadric = self.quadrics[a as usize].add(&self.quadrics[b as usize]);
+
+        let candidates = match (self.locked[a as usize], self.locked[b as usize]) {
+            (true, true) => return None,
+            (true, false) => vec![(a, b, pa)],
+            (false, true) => vec![(b, a, pb)],
+            // Midpoints would need new texture coordinates as well.
+            (false, false) if self.mesh.is_textured() => vec![(a, b, pa), (b, a, pb)],
+            (false, false) => vec![(a, b, pa), (b, a, pb), (a, b, (pa + pb) * 0.5)],
+        };
+        candidates
+            .into_iter()
+            .map(|(keep, remove, position)| {
+                (
+                    quadric.error(&position).max(0.0),
+                    Collapse {
+                        keep,
+                        remove,
+                        position,
+                    },
+                )
+            })
+            .min_by(|(a, _), (b, _)| a.total_cmp(b))
+    }
+
+    fn push(&self, heap: &mut BinaryHeap<Candidate>, a: u32, b: u32) {
+        if let Some((cost, _)) = self.plan(a, b) {
+            heap.push(Reverse((
+                cost.to_bits(),
+                a,
+                b,
+                self.versions[a as usize],
+                self.versions[b as usize],
+            )));
+        }
+    }
+
+    fn normal(&self, face: &[u32; 3]) -> Vector3 {
+        let [a, b, c] = face.map(|e| self.mesh.positions[e as usize]);
+        (b - a).cross(c - a)
+    }
+
+    // Moving `remove` onto `keep` must not turn any remaining face around.
+    fn flips(&self, collapse: &Collapse) -> bool {
+        self.vertex_faces[collapse.remove as usize]
+            .iter()
+            .chain(self.vertex_faces[collapse.keep as usize].iter())
+            .filter(|f| self.alive[**f])
+            .any(|f| {
+                let face = self.faces[*f];
+                if face.contains(&collapse.keep) && face.contains(&collapse.remove) {
+                    return false;
+                }
+                let before = self.normal(&face);
+                let [a, b, c] = face.map(|e| {
+                    if e == collapse.keep || e == collapse.remove {
+                        collapse.position
+                    } else {
+                        self.mesh.positions[e as usize]
+                    }
+                });
+                let after = (b - a).cross(c - a);
+                before.dot(after) <= 0.0
+            })
+    }
+
+    fn run(&mut self, target: usize) {
+        let mut heap = BinaryHeap::new();
+        for face in self.faces.iter() {
+            for e in 0..3 {
+                let (a, b) = (face[e], face[(e + 1) % 3]);
+                if a < b {
+                    self.push(&mut heap, a, b);
+                }
+            }
+        }
+
+        let mut count = self.faces.len();
+        while count > target {
+            let Reverse((_, a, b, version_a, version_b)) = match heap.pop() {
+                Some(e) => e,
+                None => break,
+            };
+            if self.removed[a as usize]
+                || self.removed[b as usize]
+                || self.versions[a as usize] != version_a
+                || self.versions[b as usize] != version_b
+            {
+                continue;
+            }
+            let (_, collapse) = match self.plan(a, b) {
+                Some(e) => e,
+                None => continue,
+            };
+            if self.flips(&collapse) {
+                continue;
+            }
+
+            let (keep, remove) = (collapse.keep as usize, collapse.remove as usize);
+            self.mesh.positions[keep] = collapse.position;
+            self.quadrics[keep] = self.quadrics[keep].add(&self.quadrics[remove]);
+            self.removed[remove] = true;
+            self.versions[keep] += 1;
+
+            for f in std::mem::take(&mut self.vertex_faces[remove]) {
+                if !self.alive[f] {
+                    continue;
+                }
+                if self.faces[f].contains(&collapse.keep) {
+                    self.alive[f] = false;
+                    count -= 1;
+                } else {
+                    for v in self.faces[f].iter_mut() {
+                        if *v == collapse.remove {
+                            *v = collapse.keep;
+                        }
+                    }
+                    self.vertex_faces[keep].push(f);
+                }
+            }
+            self.vertex_faces[keep].retain(|f| self.alive[*f]);
+
+            let neighbours = self.vertex_faces[keep]
+                .iter()
+                .flat_map(|f| self.faces[*f])
+                .filter(|v| *v != collapse.keep)
+                .collect::<Vec<_>>();
+            for v in neighbours {
+                self.push(&mut heap, collapse.keep.min(v), collapse.keep.max(v));
+            }
+        }
+    }
+
+    fn finish(self) {
+        let mut remap = vec![u32::MAX; self.mesh.positions.len()];
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut uvs = Vec::new();
+        let mut tangents = Vec::new();
+        let mut indices = Vec::new();
+        for (face, _) in self
+            .faces
+            .iter()
+            .zip(self.alive.iter())
+            .filter(|(_, e)| **e)
+        {
+            for v in face.iter() {
+                let v = *v as usize;
+                if remap[v] == u32::MAX {
+                    remap[v] = positions.len() as u32;
+                    positions.push(self.mesh.positions[v]);
+                    normals.push(self.mesh.normals[v]);
+                    if self.mesh.is_textured() {
+                        uvs.push(self.mesh.uvs[v]);
+                        tangents.push(self.mesh.tangents[v]);
+                    }
+                }
+                indices.push(remap[v]);
+            }
+        }
+
+        self.mesh.positions = positions;
+        self.mesh.normals = normals;
+        self.mesh.uvs = uvs;
+        self.mesh.tangents = tangents;
+        self.mesh.indices = indices;
+    }
+}
+
+// Reduces `mesh` towards `target_triangles` by collapsing the edges whose
+// removal changes the surface least (quadric error metrics). Vertices on
+// the outline or on hard edges never move, so the result may stay above
+// the target. Weld the mesh first, or every triangle is its own island and
+// nothing can be collapsed.
+pub fn decimate(mesh: &mut IndexedMesh, target_triangles: usize) {
+    if mesh.triangle_count() <= target_triangles {
+        return;
+    }
+
+    let mut decimator = Decimator::new(mesh);
+    decimator.run(target_triangles);
+    decimator.finish();
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::InnerSpace;
+    use ldraw::Vector3;
+
+    use crate::mesh::IndexedMesh;
+
+    use super::decimate;
+
+    // A flat 4 x 4 grid of quads with a bump in the middle.
+    fn grid(bump: f32) -> IndexedMesh {
+        let mut mesh = IndexedMesh::default();
+        for z in 0..5 {
+            for x in 0..5 {
+                let y = if x == 2 && z == 2 { bump } else { 0.0 };
+                mesh.positions.push(Vector3::new(x as f32, y, z as f32));
+                mesh.normals.push(Vector3::new(0.0, -1.0, 0.0));
+            }
+        }
+        for z in 0..4 {
+            for x in 0..4 {
+                let i = z * 5 + x;
+                mesh.indices
+                    .extend_from_slice(&[i, i + 5, i + 1, i + 1, i + 5, i + 6]);
+            }
+        }
+        mesh
+    }
+
+    fn area(mesh: &IndexedMesh) -> f32 {
+        mesh.indices
+            .chunks_exact(3)
+            .map(|t| {
+                let [a, b, c] = [t[0], t[1], t[2]].map(|e| mesh.positions[e as usize]);
+                (b - a).cross(c - a).magnitude() * 0.5
+            })
+            .sum()
+    }
+
+    #[test]
+    fn test_decimate_flat() {
+        let mut mesh = grid(0.0);
+        decimate(&mut mesh, 0);
+
+        // Only the outline is left; 16 boundary vertices need 14 triangles.
+        assert_eq!(mesh.triangle_count(), 14);
+        assert_eq!(mesh.vertex_count(), 16);
+        assert!((area(&mesh) - 16.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_decimate_keeps_features() {
+        let mut mesh = grid(2.0);
+        decimate(&mut mesh, 16);
+
+        assert!(mesh.triangle_count() <= 16);
+        // The bump costs the most to remove, so it goes last.
+        assert!(mesh.positions.contains(&Vector3::new(2.0, 2.0, 2.0)));
+    }
+}
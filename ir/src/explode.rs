@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+
+use cgmath::InnerSpace;
+use ldraw::{
+    document::MultipartDocument,
+    elements::{Command, Meta},
+    Matrix4, PartAlias, Vector3,
+};
+
+use crate::{analysis::collect_instances, bvh::transform_bounds, mesh::BakedPart};
+
+// Direction parts move in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExplodeDirection {
+    // Away from the center of the assembly.
+    Radial,
+    // Along the stud axis (Y) of each part, which most connections run
+    // along, towards the outside of the assembly.
+    ConnectionAxis,
+}
+
+#[derive(Clone, Debug)]
+pub struct ExplodeOptions {
+    pub direction: ExplodeDirection,
+    // Distance in LDU every part moves at full explosion.
+    pub distance: f32,
+    // Moves the parts of each step together, away from everything placed
+    // in earlier steps. The first step stays in place.
+    pub by_step: bool,
+}
+
+impl Default for ExplodeOptions {
+    fn default() -> Self {
+        ExplodeOptions {
+            direction: ExplodeDirection::Radial,
+            distance: 40.0,
+            by_step: false,
+        }
+    }
+}
+
+// A part of an exploded model.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExplodedPart {
+    pub alias: PartAlias,
+    // Placement in the assembled model.
+    pub matrix: Matrix4,
+    // Index of the top level step placing the part.
+    pub step: usize,
+    // Offset at full explosion, in model coordinates.
+    pub offset: Vector3,
+}
+
+impl ExplodedPart {
+    // Placement with `factor` of the offset applied, for animating between
+    // the assembled (0) and the exploded (1) model.
+    pub fn exploded_matrix(&self, factor: f32) -> Matrix4 {
+        Matrix4::from_translation(self.offset * factor) * self.matrix
+    }
+}
+
+// Center of the bounding box of `part`, or its origin if it is missing from
+// `parts`.
+fn part_center(part: &ExplodedPart, parts: &HashMap<PartAlias, BakedPart>) -> Vector3 {
+    match parts.get(&part.alias) {
+        Some(baked) => transform_bounds(&baked.bounding_box, &part.matrix).center(),
+        None => part.matrix.w.truncate(),
+    }
+}
+
+fn center(exploded: &[ExplodedPart], parts: &HashMap<PartAlias, BakedPart>) -> Option<Vector3> {
+    if exploded.is_empty() {
+        return None;
+    }
+    let sum = exploded
+        .iter()
+        .map(|e| part_center(e, parts))
+        .sum::<Vector3>();
+    Some(sum / exploded.len() as f32)
+}
+
+fn direction_or_up(v: Vector3) -> Vector3 {
+    if v.magnitude2() > f32::EPSILON {
+        v.normalize()
+    } else {
+        -Vector3::unit_y()
+    }
+}
+
+// Computes how far and where each part placed by `document` moves in an
+// exploded view. Bounding boxes come from `parts`; parts missing there are
+// treated as points at their origin.
+pub fn explode(
+    document: &MultipartDocument,
+    parts: &HashMap<PartAlias, BakedPart>,
+    options: &ExplodeOptions,
+) -> Vec<ExplodedPart> {
+    let mut exploded = Vec::new();
+    let mut step = 0;
+    for command in document.body.commands.iter() {
+        match command {
+            Command::Meta(Meta::Step | Meta::RotStep(_)) => step += 1,
+            Command::PartReference(e) => {
+                let mut instances = Vec::new();
+                match document.subparts.get(&e.name) {
+                    Some(subpart) => collect_instances(
+                        subpart,
+                        document,
+                        e.matrix,
+                        &mut vec![e.name.clone()],
+                        &mut instances,
+                    ),
+                    None => instances.push((e.name.clone(), e.matrix)),
+                }
+                exploded.extend(instances.into_iter().map(|(alias, matrix)| ExplodedPart {
+                    alias,
+                    matrix,
+                    step,
+                    offset: Vector3::new(0.0, 0.0, 0.0),
+                }));
+            }
+            _ => {}
+        }
+    }
+
+    let assembly = match center(&exploded, parts) {
+        Some(e) => e,
+        None => return exploded,
+    };
+
+    if options.by_step {
+        let mut start = 0;
+        while start < exploded.len() {
+            let step = exploded[start].step;
+            let end = start
+                + exploded[start..]
+                    .iter()
+                    .take_while(|e| e.step == step)
+                    .count();
+            // Everything before this step, if any.
+            if let Some(placed) = center(&exploded[..start], parts) {
+                let group = center(&exploded[start..end], parts).unwrap();
+                let offset = direction_or_up(group - placed) * options.distance;
+                for e in exploded[start..end].iter_mut() {
+                    e.offset = offset;
+                }
+            }
+            start = end;
+        }
+        return exploded;
+    }
+
+    for e in exploded.iter_mut() {
+        let outward = part_center(e, parts) - assembly;
+        let direction = match options.direction {
+            ExplodeDirection::Radial => direction_or_up(outward),
+            ExplodeDirection::ConnectionAxis => {
+                let axis = direction_or_up(-e.matrix.y.truncate());
+                if axis.dot(outward) < 0.0 {
+                    -axis
+                } else {
+                    axis
+                }
+            }
+        };
+        e.offset = direction * options.distance;
+    }
+    exploded
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use cgmath::{AbsDiffEq, Deg, SquareMatrix};
+    use ldraw::{
+        color::ColorReference,
+        document::{DocumentBuilder, MultipartDocument},
+        elements::{Command, Meta, PartReference},
+        Matrix4, PartAlias, Vector3,
+    };
+
+    use super::{explode, ExplodeDirection, ExplodeOptions};
+
+    fn placed(x: f32, y: f32, z: f32) -> Command {
+        Command::PartReference(PartReference {
+            color: ColorReference::Current,
+            matrix: Matrix4::from_translation(Vector3::new(x, y, z)),
+            name: PartAlias::from("3001.dat"),
+        })
+    }
+
+    fn model(commands: Vec<Command>) -> MultipartDocument {
+        MultipartDocument::new(
+            DocumentBuilder::new("model.ldr", "model.ldr", "")
+                .commands(commands)
+                .build(),
+            HashMap::new(),
+        )
+    }
+
+    #[test]
+    fn test_explode_radial() {
+        let document = model(vec![placed(-20.0, 0.0, 0.0), placed(20.0, 0.0, 0.0)]);
+        let exploded = explode(&document, &HashMap::new(), &ExplodeOptions::default());
+        assert_eq!(exploded[0].offset, Vector3::new(-40.0, 0.0, 0.0));
+        assert_eq!(exploded[1].offset, Vector3::new(40.0, 0.0, 0.0));
+        assert_eq!(
+            exploded[1].exploded_matrix(0.5).w.truncate(),
+            Vector3::new(40.0, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn test_explode_connection_axis() {
+        let mut tilted = placed(0.0, 0.0, 0.0);
+        if let Command::PartReference(e) = &mut tilted {
+            e.matrix = Matrix4::from_translation(Vector3::new(30.0, -10.0, 0.0))
+                * Matrix4::from_angle_z(Deg(90.0));
+        }
+        let document = model(vec![placed(0.0, 0.0, 0.0), placed(0.0, -24.0, 0.0), tilted]);
+        let options = ExplodeOptions {
+            direction: ExplodeDirection::ConnectionAxis,
+            distance: 10.0,
+            by_step: false,
+        };
+        let exploded = explode(&document, &HashMap::new(), &options);
+        assert_eq!(exploded[0].offset, Vector3::new(0.0, 10.0, 0.0));
+        assert_eq!(exploded[1].offset, Vector3::new(0.0, -10.0, 0.0));
+        // Its stud axis lies along x, pointing away from the others.
+        assert!(exploded[2]
+            .offset
+            .abs_diff_eq(&Vector3::new(10.0, 0.0, 0.0), 1e-5));
+    }
+
+    #[test]
+    fn test_explode_by_step() {
+        let submodel = DocumentBuilder::new("top.ldr", "top.ldr", "")
+            .commands(vec![placed(-20.0, 0.0, 0.0), placed(20.0, 0.0, 0.0)])
+            .build();
+        let mut document = model(vec![
+            placed(0.0, 0.0, 0.0),
+            Command::Meta(Meta::Step),
+            Command::PartReference(PartReference {
+                color: ColorReference::Current,
+                matrix: Matrix4::from_translation(Vector3::new(0.0, -24.0, 0.0)),
+                name: PartAlias::from("top.ldr"),
+            }),
+        ]);
+        document
+            .subparts
+            .insert(PartAlias::from("top.ldr"), submodel);
+
+        let options = ExplodeOptions {
+            by_step: true,
+            ..Default::default()
+        };
+        let exploded = explode(&document, &HashMap::new(), &options);
+        assert_eq!(exploded.len(), 3);
+        assert_eq!(exploded[0].offset, Vector3::new(0.0, 0.0, 0.0));
+        for e in exploded[1..].iter() {
+            assert_eq!(e.step, 1);
+            assert_eq!(e.offset, Vector3::new(0.0, -40.0, 0.0));
+        }
+        assert_eq!(
+            exploded[2].matrix,
+            Matrix4::from_translation(Vector3::new(20.0, -24.0, 0.0))
+        );
+        assert!(exploded[0].exploded_matrix(1.0).is_identity());
+    }
+}
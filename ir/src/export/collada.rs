@@ -0,0 +1,393 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io::{self, Write},
+};
+
+use cgmath::SquareMatrix;
+use ldraw::{color::ColorReference, Matrix4, PartAlias, Vector3};
+
+use crate::{analysis::MM_PER_LDU, mesh::BakedPart};
+
+use super::{material_name, xml_escape, y_up, ExportScene, MeshExporter, SceneNode};
+
+// Writes COLLADA 1.4.1 documents. Submodels become nested nodes, parts
+// nodes instancing one geometry per part and color, and colors materials
+// with their transparency. Textures and edges are not exported.
+#[derive(Clone, Debug)]
+pub struct ColladaExporter {
+    // Metres per LDU.
+    pub scale: f32,
+}
+
+impl Default for ColladaExporter {
+    fn default() -> Self {
+        ColladaExporter {
+            scale: MM_PER_LDU / 1000.0,
+        }
+    }
+}
+
+// COLLADA stores matrices row by row.
+fn rows(matrix: &Matrix4) -> String {
+    let mut values = Vec::with_capacity(16);
+    for r in 0..4 {
+        for c in 0..4 {
+            values.push(matrix[c][r].to_string());
+        }
+    }
+    values.join(" ")
+}
+
+fn floats(values: &[Vector3]) -> String {
+    values
+        .iter()
+        .map(|e| format!("{} {} {}", e.x, e.y, e.z))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// A geometry and the material bound to each of its triangle sets.
+type GeometryRef = (usize, Vec<usize>);
+
+#[derive(Default)]
+struct Builder {
+    // Writing into a String cannot fail, so results are ignored.
+    effects: String,
+    materials: String,
+    geometries: String,
+    material_lookup: HashMap<u32, usize>,
+    geometry_lookup: HashMap<(PartAlias, u32), Option<GeometryRef>>,
+    nodes: usize,
+}
+
+impl Builder {
+    fn material(&mut self, color: &ColorReference) -> usize {
+        if let Some(e) = self.material_lookup.get(&color.code()) {
+            return *e;
+        }
+        let id = self.material_lookup.len();
+        let (r, g, b, a) = match color.get_material() {
+            Some(m) => {
+                let c = m.color;
+                let channel = |e: u8| f32::from(e) / 255.0;
+                (
+                    channel(c.red()),
+                    channel(c.green()),
+                    channel(c.blue()),
+                    channel(c.alpha()),
+                )
+            }
+            None => (0.5, 0.5, 0.5, 1.0),
+        };
+        let _ = write!(
+            self.effects,
+            "  <effect id=\"effect-{id}\">\n   <profile_COMMON>\n    <technique sid=\"common\">\n     <phong>\n\
+             \x20     <diffuse><color>{r} {g} {b} 1</color></diffuse>\n\
+             \x20     <specular><color>0.2 0.2 0.2 1</color></specular>\n\
+             \x20     <shininess><float>20</float></shininess>\n\
+             \x20     <transparent opaque=\"A_ONE\"><color>1 1 1 1</color></transparent>\n\
+             \x20     <transparency><float>{a}</float></transparency>\n\
+             \x20    </phong>\n    </technique>\n   </profile_COMMON>\n  </effect>\n"
+        );
+        let _ = writeln!(
+            self.materials,
+            "  <material id=\"material-{}\" name=\"{}\"><instance_effect url=\"#effect-{}\"/></material>",
+            id,
+            xml_escape(&material_name(color)),
+            id
+        );
+        self.material_lookup.insert(color.code(), id);
+        id
+    }
+
+    // One geometry per part and color, as groups of the current color take
+    // the color of the placement.
+    fn geometry(
+        &mut self,
+        alias: &PartAlias,
+        part: &BakedPart,
+        color: &ColorReference,
+    ) -> Option<GeometryRef> {
+        let key = (alias.clone(), color.code());
+        if let Some(e) = self.geometry_lookup.get(&key) {
+            return e.clone();
+        }
+
+        let groups = part
+            .groups
+            .iter()
+            .filter(|e| !e.mesh.is_empty())
+            .collect::<Vec<_>>();
+        let result = if groups.is_empty() {
+            None
+        } else {
+            let id = self.geometry_lookup.len();
+            let positions = groups
+                .iter()
+                .flat_map(|e| e.mesh.positions.iter().copied())
+                .collect::<Vec<_>>();
+            let normals = groups
+                .iter()
+                .flat_map(|e| e.mesh.normals.iter().copied())
+                .collect::<Vec<_>>();
+            let _ = writeln!(
+                self.geometries,
+                "  <geometry id=\"geometry-{}\" name=\"{}\">\n   <mesh>",
+                id,
+                xml_escape(&alias.original)
+            );
+            for (kind, values) in [("positions", &positions), ("normals", &normals)] {
+                let _ = writeln!(
+                    self.geometries,
+                    "    <source id=\"geometry-{id}-{kind}\">\n\
+                     \x20    <float_array id=\"geometry-{id}-{kind}-array\" count=\"{}\">{}</float_array>\n\
+                     \x20    <technique_common>\n\
+                     \x20     <accessor source=\"#geometry-{id}-{kind}-array\" count=\"{}\" stride=\"3\">\n\
+                     \x20      <param name=\"X\" type=\"float\"/><param name=\"Y\" type=\"float\"/><param name=\"Z\" type=\"float\"/>\n\
+                     \x20     </accessor>\n\
+                     \x20    </technique_common>\n    </source>",
+                    values.len() * 3,
+                    floats(values),
+                    values.len()
+                );
+            }
+            let _ = writeln!(
+                self.geometries,
+                "    <vertices id=\"geometry-{id}-vertices\"><input semantic=\"POSITION\" source=\"#geometry-{id}-positions\"/></vertices>"
+            );
+
+            let mut materials = Vec::new();
+            let mut base = 0;
+            for (i, group) in groups.iter().enumerate() {
+                let group_color = if group.group.color_ref.is_current() {
+                    color
+                } else {
+                    &group.group.color_ref
+                };
+                materials.push(self.material(group_color));
+                let indices = group
+                    .mesh
+                    .indices
+                    .iter()
+                    .map(|e| {
+                        let index = base + *e as usize;
+                        format!("{index} {index}")
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                let _ = writeln!(
+                    self.geometries,
+                    "    <triangles material=\"m{i}\" count=\"{}\">\n\
+                     \x20    <input semantic=\"VERTEX\" source=\"#geometry-{id}-vertices\" offset=\"0\"/>\n\
+                     \x20    <input semantic=\"NORMAL\" source=\"#geometry-{id}-normals\" offset=\"1\"/>\n\
+                     \x20    <p>{indices}</p>\n    </triangles>",
+                    group.mesh.triangle_count()
+                );
+                base += group.mesh.positions.len();
+            }
+            self.geometries.push_str("   </mesh>\n  </geometry>\n");
+            Some((id, materials))
+        };
+        self.geometry_lookup.insert(key, result.clone());
+        result
+    }
+
+    fn node(&mut self, scene: &ExportScene, node: &SceneNode, depth: usize, out: &mut String) {
+        let indent = " ".repeat(depth);
+        match node {
+            SceneNode::Model {
+                name,
+                matrix,
+                children,
+            } => {
+                self.nodes += 1;
+                let _ = writeln!(
+                    out,
+                    "{indent}<node id=\"node-{}\" name=\"{}\" type=\"NODE\">",
+                    self.nodes,
+                    xml_escape(name)
+                );
+                if *matrix != Matrix4::identity() {
+                    let _ = writeln!(
+                        out,
+                        "{indent} <matrix sid=\"transform\">{}</matrix>",
+                        rows(matrix)
+                    );
+                }
+                for child in children {
+                    self.node(scene, child, depth + 1, out);
+                }
+                let _ = writeln!(out, "{indent}</node>");
+            }
+            SceneNode::Parts {
+                alias,
+                color,
+                matrices,
+            } => {
+                let geometry = match scene.parts.get(alias) {
+                    Some(part) => self.geometry(alias, part, color),
+                    None => None,
+                };
+                let (geometry, materials) = match geometry {
+                    Some(e) => e,
+                    None => return,
+                };
+                for matrix in matrices {
+                    self.nodes += 1;
+                    let _ = writeln!(
+                        out,
+                        "{indent}<node id=\"node-{}\" name=\"{}\" type=\"NODE\">\n\
+                         {indent} <matrix sid=\"transform\">{}</matrix>\n\
+                         {indent} <instance_geometry url=\"#geometry-{geometry}\">\n\
+                         {indent}  <bind_material><technique_common>",
+                        self.nodes,
+                        xml_escape(&alias.original),
+                        rows(matrix)
+                    );
+                    for (i, material) in materials.iter().enumerate() {
+                        let _ = writeln!(
+                            out,
+                            "{indent}   <instance_material symbol=\"m{i}\" target=\"#material-{material}\"/>"
+                        );
+                    }
+                    let _ = writeln!(
+                        out,
+                        "{indent}  </technique_common></bind_material>\n{indent} </instance_geometry>\n{indent}</node>"
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl MeshExporter for ColladaExporter {
+    fn export(&self, scene: &ExportScene, writer: &mut dyn Write) -> io::Result<()> {
+        let mut builder = Builder::default();
+        // The root node turns the model Y up; the unit takes care of scale.
+        let root = match scene.root() {
+            SceneNode::Model { name, children, .. } => SceneNode::Model {
+                name,
+                matrix: y_up(1.0),
+                children,
+            },
+            e => e,
+        };
+        let mut nodes = String::new();
+        builder.node(scene, &root, 3, &mut nodes);
+
+        let mut document = String::new();
+        let _ = write!(
+            document,
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+             <COLLADA xmlns=\"http://www.collada.org/2005/11/COLLADASchema\" version=\"1.4.1\">\n\
+             \x20<asset>\n\
+             \x20 <contributor><authoring_tool>ldraw.rs</authoring_tool></contributor>\n\
+             \x20 <unit name=\"ldu\" meter=\"{}\"/>\n\
+             \x20 <up_axis>Y_UP</up_axis>\n\
+             \x20</asset>\n\
+             \x20<library_effects>\n{}\x20</library_effects>\n\
+             \x20<library_materials>\n{}\x20</library_materials>\n\
+             \x20<library_geometries>\n{}\x20</library_geometries>\n\
+             \x20<library_visual_scenes>\n\
+             \x20 <visual_scene id=\"scene\" name=\"{}\">\n{}\x20 </visual_scene>\n\
+             \x20</library_visual_scenes>\n\
+             \x20<scene><instance_visual_scene url=\"#scene\"/></scene>\n\
+             </COLLADA>\n",
+            self.scale,
+            builder.effects,
+            builder.materials,
+            builder.geometries,
+            xml_escape(&scene.document.body.name),
+            nodes
+        );
+        writer.write_all(document.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ldraw::{
+        color::{ColorReference, Material, Rgba},
+        document::{DocumentBuilder, MultipartDocument},
+        elements::{Command, PartReference},
+        Matrix4, PartAlias, Vector3,
+    };
+
+    use crate::{
+        analysis::MM_PER_LDU,
+        export::{part, ExportScene, MeshExporter},
+    };
+
+    use super::{rows, ColladaExporter};
+
+    fn trans_clear() -> ColorReference {
+        ColorReference::Material(Material {
+            code: 47,
+            name: String::from("Trans Clear"),
+            color: Rgba::new(0xfc, 0xfc, 0xfc, 0x80),
+            ..Default::default()
+        })
+    }
+
+    fn placed(name: &str, x: f32) -> Command {
+        Command::PartReference(PartReference {
+            color: ColorReference::Current,
+            matrix: Matrix4::from_translation(Vector3::new(x, 0.0, 0.0)),
+            name: PartAlias::from(name),
+        })
+    }
+
+    #[test]
+    fn test_rows() {
+        let matrix = Matrix4::from_translation(Vector3::new(1.0, 2.0, 3.0));
+        assert_eq!(rows(&matrix), "1 0 0 1 0 1 0 2 0 0 1 3 0 0 0 1");
+    }
+
+    #[test]
+    fn test_export_collada() {
+        let submodel = DocumentBuilder::new("door.ldr", "door.ldr", "")
+            .commands(vec![placed("3024.dat", 0.0), placed("3024.dat", 20.0)])
+            .build();
+        let document = MultipartDocument::new(
+            DocumentBuilder::new("house.ldr", "house.ldr", "")
+                .commands(vec![placed("door.ldr", 40.0), placed("3024.dat", 0.0)])
+                .build(),
+            HashMap::from([(PartAlias::from("door.ldr"), submodel)]),
+        );
+        let parts = HashMap::from([(
+            PartAlias::from("3024.dat"),
+            part(&[ColorReference::Current, trans_clear()]),
+        )]);
+        let scene = ExportScene {
+            document: &document,
+            parts: &parts,
+            default_color: ColorReference::Current,
+        };
+
+        let mut dae = Vec::new();
+        ColladaExporter::default().export(&scene, &mut dae).unwrap();
+        let dae = String::from_utf8(dae).unwrap();
+
+        // One geometry and two materials, shared by all three placements.
+        assert_eq!(dae.matches("<geometry ").count(), 1);
+        assert_eq!(dae.matches("<material ").count(), 2);
+        assert_eq!(dae.matches("<instance_geometry ").count(), 3);
+        assert!(dae.contains("name=\"Trans_Clear\""));
+        assert!(dae.contains("<transparency><float>0.5019608</float></transparency>"));
+        let unit = format!("<unit name=\"ldu\" meter=\"{}\"/>", MM_PER_LDU / 1000.0);
+        assert!(dae.contains(&unit));
+        // The door is a node of its own holding its parts.
+        let door = dae.find("name=\"door.ldr\"").unwrap();
+        let parts = dae[door..].find("name=\"3024.dat\"").unwrap() + door;
+        assert!(dae[door..parts].contains("<matrix sid=\"transform\">1 0 0 40"));
+        assert!(dae.contains("<triangles material=\"m1\" count=\"1\">"));
+        assert!(dae.contains("<p>3 3 4 4 5 5</p>"));
+        assert_eq!(
+            dae.matches("<node ").count(),
+            dae.matches("</node>").count()
+        );
+    }
+}
@@ -0,0 +1,342 @@
+use ldraw::Vector3;
+
+use crate::mesh::IndexedMesh;
+
+const MAGIC: &[u8; 5] = b"DRACO";
+const VERSION: [u8; 2] = [2, 2];
+const TRIANGULAR_MESH: u8 = 1;
+const MESH_SEQUENTIAL_ENCODING: u8 = 0;
+const SEQUENTIAL_UNCOMPRESSED_INDICES: u8 = 1;
+
+const POSITION: u8 = 0;
+const NORMAL: u8 = 1;
+const DT_FLOAT32: u8 = 9;
+const SEQUENTIAL_ATTRIBUTE_ENCODER_QUANTIZATION: u8 = 2;
+const PREDICTION_NONE: i8 = -2;
+
+// Unique ids of the attributes in the encoded mesh, as referenced by
+// `KHR_draco_mesh_compression`.
+pub const POSITION_ID: u32 = 0;
+pub const NORMAL_ID: u32 = 1;
+
+// Quantization of Draco-compressed meshes. Fewer bits give smaller files
+// at the cost of precision; 14 bits keep positions of a 2x4 brick within
+// a hundredth of an LDU.
+#[derive(Clone, Debug)]
+pub struct DracoOptions {
+    pub position_bits: u8,
+    pub normal_bits: u8,
+}
+
+impl Default for DracoOptions {
+    fn default() -> Self {
+        DracoOptions {
+            position_bits: 14,
+            normal_bits: 10,
+        }
+    }
+}
+
+fn write_varint(buffer: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        buffer.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+struct Quantized {
+    min: [f32; 3],
+    range: f32,
+    values: Vec<u32>,
+}
+
+fn quantize(vectors: &[Vector3], bits: u8) -> Quantized {
+    let mut min = [f32::MAX; 3];
+    let mut max = [f32::MIN; 3];
+    for v in vectors {
+        for (i, e) in [v.x, v.y, v.z].into_iter().enumerate() {
+            min[i] = min[i].min(e);
+            max[i] = max[i].max(e);
+        }
+    }
+    let mut range = (0..3).map(|i| max[i] - min[i]).fold(0.0, f32::max);
+    if range <= 0.0 {
+        range = 1.0;
+    }
+    let max_quantized = ((1u32 << bits) - 1) as f32;
+    let values = vectors
+        .iter()
+        .flat_map(|v| [v.x, v.y, v.z].into_iter().zip(min))
+        .map(|(e, min)| {
+            ((e - min) * max_quantized / range + 0.5)
+                .floor()
+                .clamp(0.0, max_quantized) as u32
+        })
+        .collect();
+    Quantized { min, range, values }
+}
+
+// Quantized values go uncompressed, without prediction, as the non-negative
+// half of Draco's signed symbols.
+fn write_values(buffer: &mut Vec<u8>, quantized: &Quantized) {
+    buffer.push(PREDICTION_NONE as u8);
+    buffer.push(0);
+    let max = quantized.values.iter().copied().max().unwrap_or(0) << 1;
+    let width = match max {
+        0..=0xff => 1,
+        0x100..=0xffff => 2,
+        0x1_0000..=0xff_ffff => 3,
+        _ => 4,
+    };
+    buffer.push(width as u8);
+    for e in quantized.values.iter() {
+        buffer.extend_from_slice(&(e << 1).to_le_bytes()[..width]);
+    }
+}
+
+fn write_parameters(buffer: &mut Vec<u8>, quantized: &Quantized, bits: u8) {
+    for e in quantized.min {
+        buffer.extend_from_slice(&e.to_le_bytes());
+    }
+    buffer.extend_from_slice(&quantized.range.to_le_bytes());
+    buffer.push(bits);
+}
+
+// Encodes the positions, normals and triangles of a mesh as a Draco 2.2
+// bitstream.
+//
+// Connectivity is stored sequentially and attributes are quantized, but
+// nothing is entropy coded, so most of the saving comes from the narrower
+// values; compressing the transfer on top of it pays off.
+pub fn encode_mesh(mesh: &IndexedMesh, options: &DracoOptions) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    buffer.extend_from_slice(MAGIC);
+    buffer.extend_from_slice(&VERSION);
+    buffer.push(TRIANGULAR_MESH);
+    buffer.push(MESH_SEQUENTIAL_ENCODING);
+    buffer.extend_from_slice(&0u16.to_le_bytes());
+
+    let num_points = mesh.positions.len() as u32;
+    write_varint(&mut buffer, (mesh.indices.len() / 3) as u32);
+    write_varint(&mut buffer, num_points);
+    buffer.push(SEQUENTIAL_UNCOMPRESSED_INDICES);
+    for &e in mesh.indices.iter() {
+        if num_points < 1 << 8 {
+            buffer.push(e as u8);
+        } else if num_points < 1 << 16 {
+            buffer.extend_from_slice(&(e as u16).to_le_bytes());
+        } else if num_points < 1 << 21 {
+            write_varint(&mut buffer, e);
+        } else {
+            buffer.extend_from_slice(&e.to_le_bytes());
+        }
+    }
+
+    // A single sequential attributes decoder for both attributes.
+    buffer.push(1);
+    write_varint(&mut buffer, 2);
+    for (kind, id) in [(POSITION, POSITION_ID), (NORMAL, NORMAL_ID)] {
+        buffer.extend_from_slice(&[kind, DT_FLOAT32, 3, 0]);
+        write_varint(&mut buffer, id);
+    }
+    buffer.extend_from_slice(&[SEQUENTIAL_ATTRIBUTE_ENCODER_QUANTIZATION; 2]);
+
+    let positions = quantize(&mesh.positions, options.position_bits);
+    let normals = quantize(&mesh.normals, options.normal_bits);
+    write_values(&mut buffer, &positions);
+    write_values(&mut buffer, &normals);
+    write_parameters(&mut buffer, &positions, options.position_bits);
+    write_parameters(&mut buffer, &normals, options.normal_bits);
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, fs,
+        process::{self, Command},
+    };
+
+    use ldraw::Vector3;
+
+    use crate::mesh::IndexedMesh;
+
+    use super::{encode_mesh, DracoOptions};
+
+    struct Reader<'a>(&'a [u8]);
+
+    impl Reader<'_> {
+        fn bytes(&mut self, len: usize) -> &[u8] {
+            let (head, tail) = self.0.split_at(len);
+            self.0 = tail;
+            head
+        }
+
+        fn u8(&mut self) -> u8 {
+            self.bytes(1)[0]
+        }
+
+        fn uint(&mut self, len: usize) -> u32 {
+            let mut value = [0; 4];
+            value[..len].copy_from_slice(self.bytes(len));
+            u32::from_le_bytes(value)
+        }
+
+        fn f32(&mut self) -> f32 {
+            f32::from_bits(self.uint(4))
+        }
+
+        fn varint(&mut self) -> u32 {
+            let mut value = 0;
+            let mut shift = 0;
+            loop {
+                let byte = self.u8();
+                value |= ((byte & 0x7f) as u32) << shift;
+                if byte & 0x80 == 0 {
+                    return value;
+                }
+                shift += 7;
+            }
+        }
+    }
+
+    // Follows the sequential decoder of the Draco reference implementation.
+    fn decode(data: &[u8]) -> (Vec<u32>, Vec<Vec<f32>>) {
+        let mut r = Reader(data);
+        assert_eq!(r.bytes(5), b"DRACO");
+        assert_eq!(r.bytes(6), [2, 2, 1, 0, 0, 0]);
+        let num_faces = r.varint() as usize;
+        let num_points = r.varint() as usize;
+        assert_eq!(r.u8(), 1);
+        let width = if num_points < 256 { 1 } else { 2 };
+        let indices = (0..num_faces * 3).map(|_| r.uint(width)).collect();
+
+        assert_eq!(r.u8(), 1);
+        assert_eq!(r.varint(), 2);
+        for id in 0..2 {
+            assert_eq!(r.bytes(4), [id as u8, 9, 3, 0]);
+            assert_eq!(r.varint(), id);
+        }
+        assert_eq!(r.bytes(2), [2, 2]);
+
+        let mut quantized = Vec::new();
+        for _ in 0..2 {
+            assert_eq!(r.u8() as i8, -2);
+            assert_eq!(r.u8(), 0);
+            let width = r.u8() as usize;
+            let values = (0..num_points * 3)
+                .map(|_| r.uint(width))
+                .map(|e| {
+                    assert_eq!(e & 1, 0);
+                    e >> 1
+                })
+                .collect::<Vec<_>>();
+            quantized.push(values);
+        }
+        let mut attributes = Vec::new();
+        for values in quantized {
+            let min = [r.f32(), r.f32(), r.f32()];
+            let range = r.f32();
+            let bits = r.u8();
+            let delta = range / ((1u32 << bits) - 1) as f32;
+            attributes.push(
+                values
+                    .iter()
+                    .enumerate()
+                    .map(|(i, e)| min[i % 3] + *e as f32 * delta)
+                    .collect(),
+            );
+        }
+        assert!(r.0.is_empty());
+        (indices, attributes)
+    }
+
+    fn mesh() -> IndexedMesh {
+        IndexedMesh {
+            positions: vec![
+                Vector3::new(-10.0, 0.0, 4.0),
+                Vector3::new(30.0, -24.0, 4.0),
+                Vector3::new(0.0, 0.0, -6.0),
+                Vector3::new(13.3, 1.7, 0.25),
+            ],
+            normals: vec![
+                Vector3::new(0.0, -1.0, 0.0),
+                Vector3::new(0.6, 0.8, 0.0),
+                Vector3::new(0.0, 0.0, 1.0),
+                Vector3::new(-1.0, 0.0, 0.0),
+            ],
+            indices: vec![0, 1, 2, 2, 3, 0],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_encode_mesh() {
+        let mesh = mesh();
+        let options = DracoOptions::default();
+        let (indices, attributes) = decode(&encode_mesh(&mesh, &options));
+        assert_eq!(indices, mesh.indices);
+
+        for (values, (vectors, tolerance)) in attributes.iter().zip([
+            (&mesh.positions, 40.0 / 16383.0),
+            (&mesh.normals, 2.0 / 1023.0),
+        ]) {
+            let expected = vectors.iter().flat_map(|v| [v.x, v.y, v.z]);
+            for (value, expected) in values.iter().zip(expected) {
+                assert!((value - expected).abs() <= tolerance * 0.5 + 1e-5);
+            }
+        }
+    }
+    // Decodes with `draco_decoder` from the Draco reference implementation,
+    // or whatever `DRACO_DECODER` points at, and compares the triangles it
+    // writes out as OBJ.
+    #[test]
+    #[ignore = "needs draco_decoder of the Draco reference implementation"]
+    fn test_reference_decoder() {
+        let mesh = mesh();
+        let directory = env::temp_dir().join(format!("ldraw-draco-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let (input, output) = (directory.join("mesh.drc"), directory.join("mesh.obj"));
+        fs::write(&input, encode_mesh(&mesh, &DracoOptions::default())).unwrap();
+
+        let decoder = env::var("DRACO_DECODER").unwrap_or_else(|_| "draco_decoder".into());
+        let status = Command::new(decoder)
+            .arg("-i")
+            .arg(&input)
+            .arg("-o")
+            .arg(&output)
+            .status()
+            .expect("draco_decoder could not be run");
+        assert!(status.success());
+        let obj = fs::read_to_string(&output).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        let mut positions: Vec<Vec<f32>> = Vec::new();
+        let mut triangles = Vec::new();
+        for line in obj.lines() {
+            let mut fields = line.split_whitespace();
+            match fields.next() {
+                Some("v") => positions.push(fields.map(|e| e.parse::<f32>().unwrap()).collect()),
+                Some("f") => triangles.push(
+                    fields
+                        .map(|e| e.split('/').next().unwrap().parse::<usize>().unwrap() - 1)
+                        .collect::<Vec<_>>(),
+                ),
+                _ => {}
+            }
+        }
+        assert_eq!(triangles.len(), mesh.indices.len() / 3);
+        for (triangle, expected) in triangles.iter().zip(mesh.indices.chunks_exact(3)) {
+            for (&index, &expected) in triangle.iter().zip(expected) {
+                let expected = mesh.positions[expected as usize];
+                for (value, expected) in positions[index]
+                    .iter()
+                    .zip([expected.x, expected.y, expected.z])
+                {
+                    assert!((value - expected).abs() <= 40.0 / 16383.0);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,597 @@
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+
+use cgmath::{InnerSpace, Matrix, Matrix3, Quaternion, SquareMatrix};
+use ldraw::{color::ColorReference, Matrix4, PartAlias, Vector3};
+use serde_json::{json, Value};
+
+use crate::{analysis::MM_PER_LDU, geometry::BoundingBox3, mesh::BakedPart};
+
+use super::{
+    columns,
+    draco::{encode_mesh, DracoOptions, NORMAL_ID, POSITION_ID},
+    metallic_roughness, y_up, ExportScene, MeshExporter, SceneNode,
+};
+
+const GLB_MAGIC: u32 = 0x4654_6c67;
+const GLB_VERSION: u32 = 2;
+const CHUNK_JSON: u32 = 0x4e4f_534a;
+const CHUNK_BIN: u32 = 0x004e_4942;
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+
+const INSTANCING: &str = "EXT_mesh_gpu_instancing";
+const DRACO: &str = "KHR_draco_mesh_compression";
+
+// Writes binary glTF 2.0 (.glb) files.
+//
+// Every submodel becomes a node, and every color a material. Parts placed
+// more than once in the same submodel and color are instanced with
+// `EXT_mesh_gpu_instancing` if `instancing` is set. With `draco`, meshes
+// are compressed with `KHR_draco_mesh_compression`, which viewers must then
+// support. Textures and edges are not exported.
+#[derive(Clone, Debug)]
+pub struct GltfExporter {
+    // Metres per LDU.
+    pub scale: f32,
+    pub instancing: bool,
+    pub draco: Option<DracoOptions>,
+}
+
+impl Default for GltfExporter {
+    fn default() -> Self {
+        GltfExporter {
+            scale: MM_PER_LDU / 1000.0,
+            instancing: true,
+            draco: None,
+        }
+    }
+}
+
+// Splits a matrix into translation, rotation (as x, y, z, w) and scale.
+// Fails for sheared matrices, which glTF cannot instance.
+fn decompose(matrix: &Matrix4) -> Option<([f32; 3], [f32; 4], [f32; 3])> {
+    let axes = [matrix.x, matrix.y, matrix.z].map(|e| e.truncate());
+    let mut scale = axes.map(|e| e.magnitude());
+    if scale.iter().any(|e| *e <= f32::EPSILON) {
+        return None;
+    }
+    let linear = Matrix3::from_cols(axes[0], axes[1], axes[2]);
+    if linear.determinant() < 0.0 {
+        scale[0] = -scale[0];
+    }
+    let rotation = Matrix3::from_cols(axes[0] / scale[0], axes[1] / scale[1], axes[2] / scale[2]);
+    let orthogonal = rotation.transpose() * rotation;
+    for i in 0..3 {
+        for j in 0..3 {
+            let expected = if i == j { 1.0 } else { 0.0 };
+            if (orthogonal[i][j] - expected).abs() > 1e-3 {
+                return None;
+            }
+        }
+    }
+    let q = Quaternion::from(rotation).normalize();
+    let t = matrix.w.truncate();
+    Some(([t.x, t.y, t.z], [q.v.x, q.v.y, q.v.z, q.s], scale))
+}
+
+#[derive(Default)]
+struct Builder {
+    buffer: Vec<u8>,
+    buffer_views: Vec<Value>,
+    accessors: Vec<Value>,
+    materials: Vec<Value>,
+    meshes: Vec<Value>,
+    nodes: Vec<Value>,
+    material_lookup: HashMap<(u32, bool), usize>,
+    mesh_lookup: HashMap<(PartAlias, u32), Option<usize>>,
+    instanced: bool,
+    draco: Option<DracoOptions>,
+}
+
+impl Builder {
+    fn buffer_view(&mut self, data: &[u8], target: Option<u32>) -> usize {
+        while !self.buffer.len().is_multiple_of(4) {
+            self.buffer.push(0);
+        }
+        let mut view = json!({
+            "buffer": 0,
+            "byteOffset": self.buffer.len(),
+            "byteLength": data.len(),
+        });
+        if let Some(target) = target {
+            view["target"] = json!(target);
+        }
+        self.buffer.extend_from_slice(data);
+        self.buffer_views.push(view);
+        self.buffer_views.len() - 1
+    }
+
+    fn accessor(
+        &mut self,
+        data: &[u8],
+        target: Option<u32>,
+        component_type: u32,
+        count: usize,
+        kind: &str,
+    ) -> usize {
+        let view = self.buffer_view(data, target);
+        self.accessors.push(json!({
+            "bufferView": view,
+            "componentType": component_type,
+            "count": count,
+            "type": kind,
+        }));
+        self.accessors.len() - 1
+    }
+
+    fn floats(&mut self, values: &[f32], target: Option<u32>, width: usize) -> usize {
+        let data = values
+            .iter()
+            .flat_map(|e| e.to_le_bytes())
+            .collect::<Vec<_>>();
+        let kind = match width {
+            1 => "SCALAR",
+            2 => "VEC2",
+            3 => "VEC3",
+            _ => "VEC4",
+        };
+        self.accessor(&data, target, FLOAT, values.len() / width, kind)
+    }
+
+    fn vectors(&mut self, values: &[Vector3], target: Option<u32>) -> usize {
+        let flat = values
+            .iter()
+            .flat_map(|e| [e.x, e.y, e.z])
+            .collect::<Vec<_>>();
+        self.floats(&flat, target, 3)
+    }
+
+    fn material(&mut self, color: &ColorReference, double_sided: bool) -> usize {
+        let key = (color.code(), double_sided);
+        if let Some(e) = self.material_lookup.get(&key) {
+            return *e;
+        }
+
+        let mut material = match color.get_material() {
+            Some(m) => {
+                let c = m.color.to_linear();
+                let (metallic, roughness) = metallic_roughness(&m.finish);
+                let mut material = json!({
+                    "name": m.name,
+                    "pbrMetallicRoughness": {
+                        "baseColorFactor": [c.x, c.y, c.z, c.w],
+                        "metallicFactor": metallic,
+                        "roughnessFactor": roughness,
+                    },
+                });
+                if m.is_translucent() {
+                    material["alphaMode"] = json!("BLEND");
+                }
+                material
+            }
+            None => json!({
+                "name": format!("Color {}", color.code()),
+                "pbrMetallicRoughness": {
+                    "baseColorFactor": [0.5, 0.5, 0.5, 1.0],
+                    "metallicFactor": 0.0,
+                    "roughnessFactor": 0.4,
+                },
+            }),
+        };
+        if double_sided {
+            material["doubleSided"] = json!(true);
+        }
+        self.materials.push(material);
+        self.material_lookup.insert(key, self.materials.len() - 1);
+        self.materials.len() - 1
+    }
+
+    // One mesh per part and color, as groups of the current color take
+    // the color of the placement.
+    fn mesh(
+        &mut self,
+        alias: &PartAlias,
+        part: &BakedPart,
+        color: &ColorReference,
+    ) -> Option<usize> {
+        let key = (alias.clone(), color.code());
+        if let Some(e) = self.mesh_lookup.get(&key) {
+            return *e;
+        }
+
+        let mut primitives = Vec::new();
+        for group in part.groups.iter().filter(|e| !e.mesh.is_empty()) {
+            let mesh = &group.mesh;
+            let mut bounds = BoundingBox3::new(&mesh.positions[0], &mesh.positions[0]);
+            for p in mesh.positions.iter() {
+                bounds.update_point(p);
+            }
+            let (position, normal, indices, extensions) = match &self.draco {
+                Some(options) => {
+                    let data = encode_mesh(mesh, options);
+                    let view = self.buffer_view(&data, None);
+                    // Decoders fill in the accessors, which only describe
+                    // the decoded data.
+                    let mut accessor = |component_type, count, kind| {
+                        self.accessors.push(json!({
+                            "componentType": component_type,
+                            "count": count,
+                            "type": kind,
+                        }));
+                        self.accessors.len() - 1
+                    };
+                    let position = accessor(FLOAT, mesh.positions.len(), "VEC3");
+                    let normal = accessor(FLOAT, mesh.normals.len(), "VEC3");
+                    let indices = accessor(UNSIGNED_INT, mesh.indices.len(), "SCALAR");
+                    let extensions = json!({
+                        DRACO: {
+                            "bufferView": view,
+                            "attributes": { "POSITION": POSITION_ID, "NORMAL": NORMAL_ID },
+                        }
+                    });
+                    (position, normal, indices, Some(extensions))
+                }
+                None => {
+                    let position = self.vectors(&mesh.positions, Some(ARRAY_BUFFER));
+                    let normal = self.vectors(&mesh.normals, Some(ARRAY_BUFFER));
+                    let data = mesh
+                        .indices
+                        .iter()
+                        .flat_map(|e| e.to_le_bytes())
+                        .collect::<Vec<_>>();
+                    let indices = self.accessor(
+                        &data,
+                        Some(ELEMENT_ARRAY_BUFFER),
+                        UNSIGNED_INT,
+                        mesh.indices.len(),
+                        "SCALAR",
+                    );
+                    (position, normal, indices, None)
+                }
+            };
+            self.accessors[position]["min"] = json!([bounds.min.x, bounds.min.y, bounds.min.z]);
+            self.accessors[position]["max"] = json!([bounds.max.x, bounds.max.y, bounds.max.z]);
+
+            let group_color = if group.group.color_ref.is_current() {
+                color
+            } else {
+                &group.group.color_ref
+            };
+            let mut primitive = json!({
+                "attributes": { "POSITION": position, "NORMAL": normal },
+                "indices": indices,
+                "material": self.material(group_color, !group.group.bfc),
+            });
+            if let Some(extensions) = extensions {
+                primitive["extensions"] = extensions;
+            }
+            primitives.push(primitive);
+        }
+
+        let mesh = if primitives.is_empty() {
+            None
+        } else {
+            self.meshes.push(json!({
+                "name": alias.original.to_string(),
+                "primitives": primitives,
+            }));
+            Some(self.meshes.len() - 1)
+        };
+        self.mesh_lookup.insert(key, mesh);
+        mesh
+    }
+
+    fn node(&mut self, node: Value) -> usize {
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    // Adds `node` and returns the glTF nodes it became.
+    fn scene_node(
+        &mut self,
+        scene: &ExportScene,
+        exporter: &GltfExporter,
+        node: &SceneNode,
+    ) -> Vec<usize> {
+        match node {
+            SceneNode::Model {
+                name,
+                matrix,
+                children,
+            } => {
+                let children = children
+                    .iter()
+                    .flat_map(|e| self.scene_node(scene, exporter, e))
+                    .collect::<Vec<_>>();
+                let mut model = json!({ "name": name });
+                if *matrix != Matrix4::identity() {
+                    model["matrix"] = json!(columns(matrix));
+                }
+                if !children.is_empty() {
+                    model["children"] = json!(children);
+                }
+                vec![self.node(model)]
+            }
+            SceneNode::Parts {
+                alias,
+                color,
+                matrices,
+            } => {
+                let mesh = match scene.parts.get(alias) {
+                    Some(part) => self.mesh(alias, part, color),
+                    None => None,
+                };
+                let mesh = match mesh {
+                    Some(e) => e,
+                    None => return Vec::new(),
+                };
+
+                let decomposed = matrices.iter().map(decompose).collect::<Option<Vec<_>>>();
+                match decomposed {
+                    Some(trs) if exporter.instancing && trs.len() > 1 => {
+                        let translation = trs.iter().flat_map(|e| e.0).collect::<Vec<_>>();
+                        let rotation = trs.iter().flat_map(|e| e.1).collect::<Vec<_>>();
+                        let scale = trs.iter().flat_map(|e| e.2).collect::<Vec<_>>();
+                        let attributes = json!({
+                            "TRANSLATION": self.floats(&translation, None, 3),
+                            "ROTATION": self.floats(&rotation, None, 4),
+                            "SCALE": self.floats(&scale, None, 3),
+                        });
+                        self.instanced = true;
+                        vec![self.node(json!({
+                            "name": alias.original.to_string(),
+                            "mesh": mesh,
+                            "extensions": { INSTANCING: { "attributes": attributes } },
+                        }))]
+                    }
+                    _ => matrices
+                        .iter()
+                        .map(|matrix| {
+                            self.node(json!({
+                                "name": alias.original.to_string(),
+                                "mesh": mesh,
+                                "matrix": columns(matrix),
+                            }))
+                        })
+                        .collect(),
+                }
+            }
+        }
+    }
+}
+
+fn write_chunk(writer: &mut dyn Write, kind: u32, data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_le_bytes())?;
+    writer.write_all(&kind.to_le_bytes())?;
+    writer.write_all(data)
+}
+
+impl MeshExporter for GltfExporter {
+    fn export(&self, scene: &ExportScene, writer: &mut dyn Write) -> io::Result<()> {
+        let mut builder = Builder {
+            draco: self.draco.clone(),
+            ..Default::default()
+        };
+        let mut roots = builder.scene_node(scene, self, &scene.root());
+
+        let root = roots.pop().unwrap();
+        builder.nodes[root]["matrix"] = json!(columns(&y_up(self.scale)));
+
+        while !builder.buffer.len().is_multiple_of(4) {
+            builder.buffer.push(0);
+        }
+        let mut document = json!({
+            "asset": { "version": "2.0", "generator": "ldraw.rs" },
+            "scene": 0,
+            "scenes": [{ "nodes": [root] }],
+            "nodes": builder.nodes,
+        });
+        let compressed = builder.draco.is_some() && !builder.meshes.is_empty();
+        for (key, values) in [
+            ("materials", builder.materials),
+            ("meshes", builder.meshes),
+            ("accessors", builder.accessors),
+            ("bufferViews", builder.buffer_views),
+        ] {
+            if !values.is_empty() {
+                document[key] = Value::Array(values);
+            }
+        }
+        if !builder.buffer.is_empty() {
+            document["buffers"] = json!([{ "byteLength": builder.buffer.len() }]);
+        }
+        let mut extensions = Vec::new();
+        if builder.instanced {
+            extensions.push(INSTANCING);
+        }
+        if compressed {
+            extensions.push(DRACO);
+            document["extensionsRequired"] = json!([DRACO]);
+        }
+        if !extensions.is_empty() {
+            document["extensionsUsed"] = json!(extensions);
+        }
+
+        let mut json = serde_json::to_vec(&document)?;
+        while !json.len().is_multiple_of(4) {
+            json.push(b' ');
+        }
+        let mut length = 12 + 8 + json.len();
+        if !builder.buffer.is_empty() {
+            length += 8 + builder.buffer.len();
+        }
+
+        writer.write_all(&GLB_MAGIC.to_le_bytes())?;
+        writer.write_all(&GLB_VERSION.to_le_bytes())?;
+        writer.write_all(&(length as u32).to_le_bytes())?;
+        write_chunk(writer, CHUNK_JSON, &json)?;
+        if !builder.buffer.is_empty() {
+            write_chunk(writer, CHUNK_BIN, &builder.buffer)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use cgmath::Deg;
+    use ldraw::{
+        color::{ColorReference, Material, Rgba},
+        document::{DocumentBuilder, MultipartDocument},
+        elements::{Command, PartReference},
+        Matrix4, PartAlias, Vector3,
+    };
+    use serde_json::Value;
+
+    use crate::export::{part, ExportScene, MeshExporter};
+
+    use super::{decompose, DracoOptions, GltfExporter, CHUNK_BIN, CHUNK_JSON, GLB_MAGIC};
+
+    fn red() -> ColorReference {
+        ColorReference::Material(Material {
+            code: 4,
+            name: String::from("Red"),
+            color: Rgba::new(0xc9, 0x1a, 0x09, 0xff),
+            ..Default::default()
+        })
+    }
+
+    fn placed(name: &str, color: ColorReference, x: f32) -> Command {
+        Command::PartReference(PartReference {
+            color,
+            matrix: Matrix4::from_translation(Vector3::new(x, 0.0, 0.0)),
+            name: PartAlias::from(name),
+        })
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_decompose() {
+        let matrix = Matrix4::from_translation(Vector3::new(1.0, 2.0, 3.0))
+            * Matrix4::from_angle_y(Deg(90.0))
+            * Matrix4::from_nonuniform_scale(-1.0, 2.0, 1.0);
+        let (t, r, s) = decompose(&matrix).unwrap();
+        assert_eq!(t, [1.0, 2.0, 3.0]);
+        assert!((s[0] + 1.0).abs() < 1e-5 && (s[1] - 2.0).abs() < 1e-5);
+        assert!((r[1].abs() - (0.5f32).sqrt()).abs() < 1e-5);
+
+        let mut sheared = Matrix4::from_scale(1.0);
+        sheared.y.x = 1.0;
+        assert!(decompose(&sheared).is_none());
+    }
+
+    #[test]
+    fn test_export_glb() {
+        let submodel = DocumentBuilder::new("wing.ldr", "wing.ldr", "")
+            .commands(vec![placed("3001.dat", ColorReference::Current, 0.0)])
+            .build();
+        let document = MultipartDocument::new(
+            DocumentBuilder::new("model.ldr", "model.ldr", "")
+                .commands(vec![
+                    placed("3001.dat", ColorReference::Current, 0.0),
+                    placed("3001.dat", ColorReference::Current, 40.0),
+                    placed("wing.ldr", red(), 0.0),
+                    placed("missing.dat", ColorReference::Current, 0.0),
+                ])
+                .build(),
+            HashMap::from([(PartAlias::from("wing.ldr"), submodel)]),
+        );
+        let parts = HashMap::from([(
+            PartAlias::from("3001.dat"),
+            part(&[ColorReference::Current, red()]),
+        )]);
+        let scene = ExportScene {
+            document: &document,
+            parts: &parts,
+            default_color: ColorReference::Current,
+        };
+
+        let mut glb = Vec::new();
+        GltfExporter::default().export(&scene, &mut glb).unwrap();
+        assert_eq!(u32_at(&glb, 0), GLB_MAGIC);
+        assert_eq!(u32_at(&glb, 8) as usize, glb.len());
+        let json_length = u32_at(&glb, 12) as usize;
+        assert_eq!(u32_at(&glb, 16), CHUNK_JSON);
+        assert_eq!(u32_at(&glb, 20 + json_length + 4), CHUNK_BIN);
+        let json: Value = serde_json::from_slice(&glb[20..20 + json_length]).unwrap();
+
+        // The part in the default color and in red, as the wing is red.
+        assert_eq!(json["meshes"].as_array().unwrap().len(), 2);
+        assert_eq!(json["materials"].as_array().unwrap().len(), 2);
+        assert_eq!(json["extensionsUsed"][0], "EXT_mesh_gpu_instancing");
+
+        let nodes = json["nodes"].as_array().unwrap();
+        let root = &nodes[json["scenes"][0]["nodes"][0].as_u64().unwrap() as usize];
+        assert_eq!(root["name"], "model.ldr");
+        let children = root["children"].as_array().unwrap();
+        assert_eq!(children.len(), 2);
+        let instanced = &nodes[children[0].as_u64().unwrap() as usize];
+        let translation = instanced["extensions"]["EXT_mesh_gpu_instancing"]["attributes"]
+            ["TRANSLATION"]
+            .as_u64()
+            .unwrap() as usize;
+        assert_eq!(json["accessors"][translation]["count"], 2);
+        let wing = &nodes[children[1].as_u64().unwrap() as usize];
+        assert_eq!(wing["name"], "wing.ldr");
+        assert_eq!(wing["children"].as_array().unwrap().len(), 1);
+
+        let position = json["meshes"][0]["primitives"][0]["attributes"]["POSITION"]
+            .as_u64()
+            .unwrap() as usize;
+        assert_eq!(json["accessors"][position]["max"][0], 20.0);
+        let scale = GltfExporter::default().scale;
+        assert!((root["matrix"][5].as_f64().unwrap() as f32 + scale).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_export_draco() {
+        let document = MultipartDocument::new(
+            DocumentBuilder::new("model.ldr", "model.ldr", "")
+                .commands(vec![placed("3001.dat", ColorReference::Current, 0.0)])
+                .build(),
+            HashMap::new(),
+        );
+        let parts = HashMap::from([(
+            PartAlias::from("3001.dat"),
+            part(&[ColorReference::Current, red()]),
+        )]);
+        let scene = ExportScene {
+            document: &document,
+            parts: &parts,
+            default_color: ColorReference::Current,
+        };
+
+        let exporter = GltfExporter {
+            draco: Some(DracoOptions::default()),
+            ..Default::default()
+        };
+        let mut glb = Vec::new();
+        exporter.export(&scene, &mut glb).unwrap();
+        let json_length = u32_at(&glb, 12) as usize;
+        let json: Value = serde_json::from_slice(&glb[20..20 + json_length]).unwrap();
+        assert_eq!(json["extensionsRequired"][0], "KHR_draco_mesh_compression");
+        assert_eq!(json["extensionsUsed"][0], "KHR_draco_mesh_compression");
+
+        let primitive = &json["meshes"][0]["primitives"][0];
+        let draco = &primitive["extensions"]["KHR_draco_mesh_compression"];
+        assert_eq!(draco["attributes"]["POSITION"], 0);
+        let position = primitive["attributes"]["POSITION"].as_u64().unwrap() as usize;
+        assert!(json["accessors"][position].get("bufferView").is_none());
+        assert_eq!(json["accessors"][position]["max"][0], 20.0);
+
+        let view = &json["bufferViews"][draco["bufferView"].as_u64().unwrap() as usize];
+        let offset = 20 + json_length + 8 + view["byteOffset"].as_u64().unwrap() as usize;
+        assert_eq!(&glb[offset..offset + 5], b"DRACO");
+    }
+}
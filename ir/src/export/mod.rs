@@ -0,0 +1,329 @@
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+
+use cgmath::{InnerSpace, Matrix, Matrix3, SquareMatrix};
+use ldraw::{
+    color::{ColorReference, Finish, Material},
+    document::{Document, MultipartDocument},
+    Matrix4, PartAlias, Vector3,
+};
+
+use crate::{
+    mesh::{BakedPart, IndexedMesh},
+    MeshGroup,
+};
+
+pub mod collada;
+pub mod draco;
+pub mod gltf;
+pub mod obj;
+pub mod povray;
+pub mod stl;
+pub mod threejs;
+pub mod threemf;
+
+// A baked model to be written out: the model itself and the geometry of
+// every part it places, as returned by `bake_document`.
+pub struct ExportScene<'a> {
+    pub document: &'a MultipartDocument,
+    pub parts: &'a HashMap<PartAlias, BakedPart>,
+    // Color of parts placed with the current color at the top level.
+    pub default_color: ColorReference,
+}
+
+// Writes baked models into an interchange format.
+pub trait MeshExporter {
+    fn export(&self, scene: &ExportScene, writer: &mut dyn Write) -> io::Result<()>;
+}
+
+// The model hierarchy as exporters see it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SceneNode {
+    // A model or submodel, placed with `matrix` in its parent.
+    Model {
+        name: String,
+        matrix: Matrix4,
+        children: Vec<SceneNode>,
+    },
+    // Every placement of one part in one color within a model, so that
+    // exporters can instance them.
+    Parts {
+        alias: PartAlias,
+        color: ColorReference,
+        matrices: Vec<Matrix4>,
+    },
+}
+
+fn model_children(
+    document: &Document,
+    parent: &MultipartDocument,
+    color: &ColorReference,
+    stack: &mut Vec<PartAlias>,
+) -> Vec<SceneNode> {
+    let mut children = Vec::new();
+    let mut lookup = HashMap::new();
+    for (e, _) in document.iter_refs_with_mode() {
+        let color = if e.color.is_current() || e.color.is_complement() {
+            color.clone()
+        } else {
+            e.color.clone()
+        };
+        if let Some(subpart) = parent.subparts.get(&e.name) {
+            if stack.contains(&e.name) {
+                continue;
+            }
+            stack.push(e.name.clone());
+            children.push(SceneNode::Model {
+                name: e.name.original.to_string(),
+                matrix: e.matrix,
+                children: model_children(subpart, parent, &color, stack),
+            });
+            stack.pop();
+        } else {
+            let index = *lookup
+                .entry((e.name.clone(), color.code()))
+                .or_insert_with(|| {
+                    children.push(SceneNode::Parts {
+                        alias: e.name.clone(),
+                        color,
+                        matrices: Vec::new(),
+                    });
+                    children.len() - 1
+                });
+            if let SceneNode::Parts { matrices, .. } = &mut children[index] {
+                matrices.push(e.matrix);
+            }
+        }
+    }
+    children
+}
+
+impl SceneNode {
+    // Every part placement under this node, with its color and its matrix
+    // relative to the node's parent.
+    pub fn flatten(&self) -> Vec<(PartAlias, ColorReference, Matrix4)> {
+        match self {
+            SceneNode::Model {
+                matrix, children, ..
+            } => children
+                .iter()
+                .flat_map(|e| e.flatten())
+                .map(|(alias, color, m)| (alias, color, matrix * m))
+                .collect(),
+            SceneNode::Parts {
+                alias,
+                color,
+                matrices,
+            } => matrices
+                .iter()
+                .map(|m| (alias.clone(), color.clone(), *m))
+                .collect(),
+        }
+    }
+}
+
+// Turns LDraw coordinates (-Y up, in LDU) into the +Y up coordinates most
+// formats expect, `scale` units per LDU.
+pub fn y_up(scale: f32) -> Matrix4 {
+    Matrix4::from_nonuniform_scale(scale, -scale, -scale)
+}
+
+// Turns LDraw coordinates into the +Z up coordinates of 3D printing and
+// CAD formats, `scale` units per LDU.
+pub fn z_up(scale: f32) -> Matrix4 {
+    Matrix4::new(
+        scale, 0.0, 0.0, 0.0, 0.0, 0.0, -scale, 0.0, 0.0, scale, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0,
+    )
+}
+
+// Name a color is exported under: its name from `LDConfig.ldr` if it is
+// known, `Color_<code>` otherwise. Spaces are replaced with underscores.
+pub fn material_name(color: &ColorReference) -> String {
+    match color.get_material() {
+        Some(m) => exported_name(m),
+        None => format!("Color_{}", color.code()),
+    }
+}
+
+pub(crate) fn exported_name(material: &Material) -> String {
+    material.name.replace(char::is_whitespace, "_")
+}
+
+pub(crate) fn columns(matrix: &Matrix4) -> Vec<f32> {
+    AsRef::<[f32; 16]>::as_ref(matrix).to_vec()
+}
+
+// Approximates the finishes of LDraw colors for PBR materials.
+pub(crate) fn metallic_roughness(finish: &Finish) -> (f32, f32) {
+    match finish {
+        Finish::Chrome => (1.0, 0.1),
+        Finish::Metal => (1.0, 0.3),
+        Finish::MatteMetallic => (1.0, 0.6),
+        Finish::Rubber => (0.0, 0.9),
+        _ => (0.0, 0.4),
+    }
+}
+
+pub(crate) fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Geometry of a placed part, moved into model coordinates for formats
+// without instancing. Colors are resolved against the placement.
+#[derive(Clone, Debug)]
+pub struct FlatPart {
+    pub alias: PartAlias,
+    pub groups: Vec<(MeshGroup, IndexedMesh)>,
+}
+
+impl FlatPart {
+    pub fn triangles(&self) -> impl Iterator<Item = [Vector3; 3]> + '_ {
+        self.groups.iter().flat_map(|(_, mesh)| {
+            mesh.indices
+                .chunks_exact(3)
+                .map(|t| [t[0], t[1], t[2]].map(|e| mesh.positions[e as usize]))
+        })
+    }
+}
+
+// Name of the `index`th placed part, without whitespace.
+pub fn placement_name(alias: &PartAlias, index: usize) -> String {
+    format!(
+        "{}_{}",
+        alias.original.replace(char::is_whitespace, "_"),
+        index
+    )
+}
+
+fn transform_mesh(mesh: &IndexedMesh, matrix: &Matrix4) -> IndexedMesh {
+    let linear = Matrix3::from_cols(
+        matrix.x.truncate(),
+        matrix.y.truncate(),
+        matrix.z.truncate(),
+    );
+    let normal_matrix = linear.invert().unwrap_or(linear).transpose();
+    let mut indices = mesh.indices.clone();
+    // Mirrored placements would turn the triangles inside out.
+    if linear.determinant() < 0.0 {
+        for t in indices.chunks_exact_mut(3) {
+            t.swap(1, 2);
+        }
+    }
+    IndexedMesh {
+        positions: mesh
+            .positions
+            .iter()
+            .map(|e| (matrix * e.extend(1.0)).truncate())
+            .collect(),
+        normals: mesh
+            .normals
+            .iter()
+            .map(|e| {
+                let n: Vector3 = normal_matrix * e;
+                if n.magnitude2() > f32::EPSILON {
+                    n.normalize()
+                } else {
+                    n
+                }
+            })
+            .collect(),
+        indices,
+        uvs: mesh.uvs.clone(),
+        tangents: Vec::new(),
+    }
+}
+
+impl<'a> ExportScene<'a> {
+    // Every placed part with its geometry moved by its placement and then
+    // by `transform`. Parts missing from `parts` are left out.
+    pub fn flatten(&self, transform: &Matrix4) -> Vec<FlatPart> {
+        let mut flattened = Vec::new();
+        for (alias, color, matrix) in self.root().flatten() {
+            let part = match self.parts.get(&alias) {
+                Some(e) => e,
+                None => continue,
+            };
+            let matrix = transform * matrix;
+            let groups = part
+                .groups
+                .iter()
+                .filter(|e| !e.mesh.is_empty())
+                .map(|e| {
+                    let color_ref = if e.group.color_ref.is_current() {
+                        color.clone()
+                    } else {
+                        e.group.color_ref.clone()
+                    };
+                    (
+                        MeshGroup {
+                            color_ref,
+                            bfc: e.group.bfc,
+                        },
+                        transform_mesh(&e.mesh, &matrix),
+                    )
+                })
+                .collect();
+            flattened.push(FlatPart { alias, groups });
+        }
+        flattened
+    }
+
+    // The model as a tree of submodels, with parts grouped by name and
+    // color within each of them. Parts placed with the current color take
+    // the color of the submodel placing them.
+    pub fn root(&self) -> SceneNode {
+        SceneNode::Model {
+            name: self.document.body.name.clone(),
+            matrix: Matrix4::identity(),
+            children: model_children(
+                &self.document.body,
+                self.document,
+                &self.default_color,
+                &mut Vec::new(),
+            ),
+        }
+    }
+}
+
+// A triangle facing up (-Y) for each of `colors`, as a part for exporter
+// tests.
+#[cfg(test)]
+pub(crate) fn part(colors: &[ColorReference]) -> BakedPart {
+    use crate::{
+        geometry::BoundingBox3,
+        mesh::BakedMeshGroup,
+        part::{EdgeBufferBuilder, OptionalEdgeBufferBuilder},
+    };
+
+    let group = |color_ref: &ColorReference| BakedMeshGroup {
+        group: MeshGroup {
+            color_ref: color_ref.clone(),
+            bfc: true,
+        },
+        mesh: IndexedMesh {
+            positions: vec![
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(20.0, 0.0, 0.0),
+                Vector3::new(0.0, 0.0, 20.0),
+            ],
+            normals: vec![-Vector3::unit_y(); 3],
+            indices: vec![0, 1, 2],
+            ..Default::default()
+        },
+        texture: None,
+    };
+    BakedPart {
+        groups: colors.iter().map(group).collect(),
+        edges: EdgeBufferBuilder::default(),
+        optional_edges: OptionalEdgeBufferBuilder::default(),
+        bounding_box: BoundingBox3::new(
+            &Vector3::new(0.0, 0.0, 0.0),
+            &Vector3::new(20.0, 0.0, 20.0),
+        ),
+    }
+}
@@ -0,0 +1,176 @@
+use std::io::{self, Write};
+
+use ldraw::color::MaterialRegistry;
+
+use crate::analysis::MM_PER_LDU;
+
+use super::{exported_name, material_name, placement_name, y_up, ExportScene, MeshExporter};
+
+// Writes Wavefront OBJ files, with every placed part an object of its own
+// and faces grouped by color into materials. Materials are written
+// separately by `write_mtl`.
+#[derive(Clone, Debug)]
+pub struct ObjExporter {
+    // Output units per LDU.
+    pub scale: f32,
+    // MTL file referenced with `mtllib`, if any.
+    pub mtl: Option<String>,
+}
+
+impl Default for ObjExporter {
+    fn default() -> Self {
+        ObjExporter {
+            scale: MM_PER_LDU,
+            mtl: None,
+        }
+    }
+}
+
+impl MeshExporter for ObjExporter {
+    fn export(&self, scene: &ExportScene, writer: &mut dyn Write) -> io::Result<()> {
+        writeln!(writer, "# {}", scene.document.body.name)?;
+        if let Some(mtl) = &self.mtl {
+            writeln!(writer, "mtllib {}", mtl)?;
+        }
+
+        // Indices are 1-based and count from the start of the file.
+        let mut base = 1;
+        for (i, part) in scene.flatten(&y_up(self.scale)).iter().enumerate() {
+            writeln!(writer, "o {}", placement_name(&part.alias, i))?;
+            for (group, mesh) in part.groups.iter() {
+                writeln!(writer, "usemtl {}", material_name(&group.color_ref))?;
+                for p in mesh.positions.iter() {
+                    writeln!(writer, "v {} {} {}", p.x, p.y, p.z)?;
+                }
+                for n in mesh.normals.iter() {
+                    writeln!(writer, "vn {} {} {}", n.x, n.y, n.z)?;
+                }
+                for t in mesh.indices.chunks_exact(3) {
+                    let [a, b, c] = [t[0], t[1], t[2]].map(|e| e as usize + base);
+                    writeln!(writer, "f {a}//{a} {b}//{b} {c}//{c}")?;
+                }
+                base += mesh.positions.len();
+            }
+        }
+        Ok(())
+    }
+}
+
+// Writes an MTL file with a material for every color in `materials`.
+// Translucent colors get a dissolve (`d`) below 1.
+pub fn write_mtl(materials: &MaterialRegistry, writer: &mut dyn Write) -> io::Result<()> {
+    let mut materials = materials.values().collect::<Vec<_>>();
+    materials.sort_by_key(|e| e.code);
+    for m in materials {
+        let channel = |e: u8| f32::from(e) / 255.0;
+        let alpha = channel(m.color.alpha());
+        writeln!(writer, "newmtl {}", exported_name(m))?;
+        writeln!(
+            writer,
+            "Kd {} {} {}",
+            channel(m.color.red()),
+            channel(m.color.green()),
+            channel(m.color.blue())
+        )?;
+        writeln!(writer, "Ks 0.2 0.2 0.2")?;
+        writeln!(writer, "Ns 50")?;
+        writeln!(writer, "d {}", alpha)?;
+        writeln!(writer, "Tr {}", 1.0 - alpha)?;
+        writeln!(writer, "illum 2")?;
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ldraw::{
+        color::{ColorReference, Material, MaterialRegistry, Rgba},
+        document::{DocumentBuilder, MultipartDocument},
+        elements::{Command, PartReference},
+        Matrix4, PartAlias,
+    };
+
+    use crate::export::{part, ExportScene, MeshExporter};
+
+    use super::{write_mtl, ObjExporter};
+
+    fn material(code: u32, name: &str, color: Rgba) -> Material {
+        Material {
+            code,
+            name: String::from(name),
+            color,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_export_obj() {
+        let red = material(4, "Red", Rgba::new(0xc9, 0x1a, 0x09, 0xff));
+        let placed = |matrix: Matrix4, color: ColorReference| {
+            Command::PartReference(PartReference {
+                color,
+                matrix,
+                name: PartAlias::from("3024.dat"),
+            })
+        };
+        let document = MultipartDocument::new(
+            DocumentBuilder::new("model.ldr", "model.ldr", "")
+                .commands(vec![
+                    placed(Matrix4::from_scale(1.0), ColorReference::Material(red)),
+                    placed(
+                        Matrix4::from_nonuniform_scale(-1.0, 1.0, 1.0),
+                        ColorReference::Current,
+                    ),
+                ])
+                .build(),
+            HashMap::new(),
+        );
+        let parts = HashMap::from([(
+            PartAlias::from("3024.dat"),
+            part(&[ColorReference::Current]),
+        )]);
+        let scene = ExportScene {
+            document: &document,
+            parts: &parts,
+            default_color: ColorReference::Current,
+        };
+
+        let exporter = ObjExporter {
+            scale: 1.0,
+            mtl: Some(String::from("model.mtl")),
+        };
+        let mut obj = Vec::new();
+        exporter.export(&scene, &mut obj).unwrap();
+        let obj = String::from_utf8(obj).unwrap();
+        let lines = obj.lines().collect::<Vec<_>>();
+        assert_eq!(lines[1], "mtllib model.mtl");
+        assert_eq!(lines[2], "o 3024.dat_0");
+        assert_eq!(lines[3], "usemtl Red");
+        // Facing up in Y up coordinates.
+        assert!(lines[7..10].iter().all(|e| e.ends_with(" 1 0")));
+        assert_eq!(lines[10], "f 1//1 2//2 3//3");
+        assert_eq!(lines[12], "usemtl Color_16");
+        // The mirrored copy is wound the other way around.
+        assert_eq!(lines[19], "f 4//4 6//6 5//5");
+    }
+
+    #[test]
+    fn test_write_mtl() {
+        let materials = MaterialRegistry::from([
+            (4, material(4, "Red", Rgba::new(255, 0, 0, 255))),
+            (
+                47,
+                material(47, "Trans Clear", Rgba::new(255, 255, 255, 51)),
+            ),
+        ]);
+        let mut mtl = Vec::new();
+        write_mtl(&materials, &mut mtl).unwrap();
+        let mtl = String::from_utf8(mtl).unwrap();
+        assert!(mtl.starts_with("newmtl Red\nKd 1 0 0\n"));
+        assert!(mtl.contains("newmtl Trans_Clear\nKd 1 1 1\n"));
+        assert!(mtl.contains("d 0.2\nTr 0.8\n"));
+    }
+}
@@ -0,0 +1,379 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, Write},
+};
+
+use cgmath::{Deg, InnerSpace, Matrix3, Rad};
+use ldraw::{
+    color::{ColorReference, Finish},
+    Matrix4, PartAlias, Vector3,
+};
+
+use crate::{bvh::transform_bounds, geometry::BoundingBox3, mesh::BakedPart};
+
+use super::{ExportScene, MeshExporter};
+
+// LGEO, the library of detailed POV-Ray parts, as installed next to the
+// scene.
+#[derive(Clone, Debug)]
+pub struct LgeoLibrary {
+    // Objects the library provides, e.g. `lg_3001`, each declared in an
+    // include file of the same name.
+    pub objects: HashSet<String>,
+    // Moves LGEO geometry into LDraw coordinates. The default turns Z up
+    // into -Y up and scales by 25.
+    pub transform: Matrix4,
+}
+
+impl LgeoLibrary {
+    pub fn new<I: IntoIterator<Item = String>>(objects: I) -> Self {
+        LgeoLibrary {
+            objects: objects.into_iter().collect(),
+            transform: Matrix4::from(Matrix3::new(1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, -1.0, 0.0))
+                * Matrix4::from_scale(25.0),
+        }
+    }
+
+    // Name LGEO gives to a part, which it provides if it is in `objects`.
+    pub fn object_name(alias: &PartAlias) -> String {
+        let name = alias.normalized.trim_end_matches(".dat");
+        format!("lg_{}", identifier(name))
+    }
+
+    fn get(&self, alias: &PartAlias) -> Option<String> {
+        let name = LgeoLibrary::object_name(alias);
+        self.objects.contains(&name).then_some(name)
+    }
+}
+
+// Writes a POV-Ray 3.7 scene with a camera and lights framing the model.
+//
+// Parts become `mesh2` objects, or LGEO objects where `lgeo` has them, and
+// colors become materials following their finish. The model keeps LDraw
+// coordinates inside a union flipping Y, so one POV-Ray unit is one LDU.
+#[derive(Clone, Debug)]
+pub struct PovRayExporter {
+    pub lgeo: Option<LgeoLibrary>,
+    // Width over height of the image to be rendered.
+    pub aspect_ratio: f32,
+    // Vertical field of view of the camera.
+    pub fov: Deg<f32>,
+}
+
+impl Default for PovRayExporter {
+    fn default() -> Self {
+        PovRayExporter {
+            lgeo: None,
+            aspect_ratio: 4.0 / 3.0,
+            fov: Deg(30.0),
+        }
+    }
+}
+
+// Identifiers may only hold letters, digits and underscores.
+fn identifier(name: &str) -> String {
+    name.chars()
+        .map(|e| if e.is_ascii_alphanumeric() { e } else { '_' })
+        .collect()
+}
+
+fn part_name(alias: &PartAlias) -> String {
+    format!("LDX_{}", identifier(&alias.normalized))
+}
+
+fn material_identifier(color: &ColorReference) -> String {
+    format!("LDX_Color_{}", color.code())
+}
+
+fn vector(v: &Vector3) -> String {
+    format!("<{}, {}, {}>", v.x, v.y, v.z)
+}
+
+// POV-Ray transforms row vectors, so the rows of its matrix are the columns
+// of ours.
+fn matrix(m: &Matrix4) -> String {
+    let values = [m.x, m.y, m.z, m.w]
+        .iter()
+        .flat_map(|e| [e.x, e.y, e.z])
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>();
+    format!("matrix <{}>", values.join(", "))
+}
+
+fn write_material(writer: &mut dyn Write, color: &ColorReference) -> io::Result<()> {
+    let name = material_identifier(color);
+    let m = match color.get_material() {
+        Some(m) => m,
+        None => {
+            return writeln!(
+                writer,
+                "#declare {} = material {{ texture {{ pigment {{ rgb 0.5 }} }} }}",
+                name
+            );
+        }
+    };
+    let c = m.color.to_linear();
+    let finish = match m.finish {
+        Finish::Chrome => "diffuse 0.3 brilliance 5 metallic specular 0.8 roughness 0.01 reflection 0.6",
+        Finish::Metal => "diffuse 0.5 brilliance 3 metallic specular 0.6 roughness 0.02 reflection 0.3",
+        Finish::MatteMetallic => "diffuse 0.6 metallic specular 0.3 roughness 0.05 reflection 0.1",
+        Finish::Pearlescent => {
+            "diffuse 0.7 specular 0.5 roughness 0.02 reflection 0.1 irid { 0.15 thickness 0.2 turbulence 0.5 }"
+        }
+        Finish::Rubber => "diffuse 0.9 specular 0.05 roughness 0.2",
+        _ => "diffuse 0.8 phong 0.5 phong_size 40 reflection 0.05",
+    };
+    writeln!(writer, "// {}", m.name)?;
+    writeln!(writer, "#declare {} = material {{", name)?;
+    writeln!(writer, "  texture {{")?;
+    if m.is_translucent() {
+        writeln!(
+            writer,
+            "    pigment {{ rgbf <{}, {}, {}, {}> }}",
+            c.x,
+            c.y,
+            c.z,
+            1.0 - c.w
+        )?;
+    } else {
+        writeln!(writer, "    pigment {{ rgb <{}, {}, {}> }}", c.x, c.y, c.z)?;
+    }
+    write!(writer, "    finish {{ ambient 0 {}", finish)?;
+    if m.luminance > 0 {
+        write!(writer, " emission {}", m.luminance as f32 / 255.0)?;
+    }
+    writeln!(writer, " }}")?;
+    writeln!(writer, "  }}")?;
+    if m.is_translucent() {
+        writeln!(writer, "  interior {{ ior 1.5 }}")?;
+    }
+    writeln!(writer, "}}")
+}
+
+fn write_part(writer: &mut dyn Write, alias: &PartAlias, part: &BakedPart) -> io::Result<()> {
+    writeln!(writer, "#declare {} = union {{", part_name(alias))?;
+    for group in part.groups.iter().filter(|e| !e.mesh.is_empty()) {
+        let mesh = &group.mesh;
+        writeln!(writer, "  mesh2 {{")?;
+        for (kind, values) in [
+            ("vertex_vectors", &mesh.positions),
+            ("normal_vectors", &mesh.normals),
+        ] {
+            write!(writer, "    {} {{ {}", kind, values.len())?;
+            for v in values.iter() {
+                write!(writer, ", {}", vector(v))?;
+            }
+            writeln!(writer, " }}")?;
+        }
+        write!(writer, "    face_indices {{ {}", mesh.triangle_count())?;
+        for t in mesh.indices.chunks_exact(3) {
+            write!(writer, ", <{}, {}, {}>", t[0], t[1], t[2])?;
+        }
+        writeln!(writer, " }}")?;
+        // Groups in the current color take the material of the placement.
+        if !group.group.color_ref.is_current() {
+            writeln!(
+                writer,
+                "    material {{ {} }}",
+                material_identifier(&group.group.color_ref)
+            )?;
+        }
+        writeln!(writer, "  }}")?;
+    }
+    writeln!(writer, "}}")
+}
+
+impl MeshExporter for PovRayExporter {
+    fn export(&self, scene: &ExportScene, writer: &mut dyn Write) -> io::Result<()> {
+        let placements = scene
+            .root()
+            .flatten()
+            .into_iter()
+            .filter(|(alias, _, _)| scene.parts.contains_key(alias))
+            .collect::<Vec<_>>();
+
+        writeln!(writer, "// {}", scene.document.body.name)?;
+        writeln!(writer, "#version 3.7;")?;
+        writeln!(writer, "global_settings {{ assumed_gamma 1.0 }}")?;
+        writeln!(writer)?;
+
+        // Colors used by placements and by the parts themselves.
+        let mut colors = Vec::new();
+        let mut seen = HashSet::new();
+        let group_colors = placements.iter().flat_map(|(alias, color, _)| {
+            std::iter::once(color.clone()).chain(
+                scene.parts[alias]
+                    .groups
+                    .iter()
+                    .map(|e| e.group.color_ref.clone())
+                    .filter(|e| !e.is_current()),
+            )
+        });
+        for color in group_colors {
+            if seen.insert(color.code()) {
+                colors.push(color);
+            }
+        }
+        for color in colors.iter() {
+            write_material(writer, color)?;
+        }
+        writeln!(writer)?;
+
+        let mut objects = HashMap::new();
+        for (alias, _, _) in placements.iter() {
+            if objects.contains_key(alias) {
+                continue;
+            }
+            let lgeo = self.lgeo.as_ref().and_then(|e| Some((e, e.get(alias)?)));
+            let object = match lgeo {
+                Some((library, name)) => {
+                    writeln!(writer, "#include \"{}.inc\"", name)?;
+                    format!("object {{ {} {} }}", name, matrix(&library.transform))
+                }
+                None => {
+                    write_part(writer, alias, &scene.parts[alias])?;
+                    format!("object {{ {} }}", part_name(alias))
+                }
+            };
+            objects.insert(alias.clone(), object);
+        }
+        writeln!(writer)?;
+
+        writeln!(writer, "union {{")?;
+        for (alias, color, m) in placements.iter() {
+            writeln!(
+                writer,
+                "  object {{ {} {} material {{ {} }} }}",
+                objects[alias],
+                matrix(m),
+                material_identifier(color)
+            )?;
+        }
+        writeln!(writer, "  matrix <1, 0, 0, 0, -1, 0, 0, 0, 1, 0, 0, 0>")?;
+        writeln!(writer, "}}")?;
+        writeln!(writer)?;
+
+        let mut bounds: Option<BoundingBox3> = None;
+        for (alias, _, m) in placements.iter() {
+            let b = transform_bounds(&scene.parts[alias].bounding_box, m);
+            match &mut bounds {
+                Some(e) => e.update(&b),
+                None => bounds = Some(b),
+            }
+        }
+        let bounds = bounds.unwrap_or_else(BoundingBox3::zero);
+        let center = bounds.center();
+        let center = Vector3::new(center.x, -center.y, center.z);
+        let radius = ((bounds.max - bounds.min).magnitude() * 0.5).max(1.0);
+        // From the front, above and to the left, far enough to fit the
+        // bounding sphere.
+        let fov = Rad::from(self.fov).0;
+        let distance = radius / (fov * 0.5).sin();
+        let direction = Vector3::new(-1.0, 0.8, -1.0).normalize();
+        let location = center + direction * distance;
+        writeln!(writer, "camera {{")?;
+        writeln!(writer, "  location {}", vector(&location))?;
+        writeln!(writer, "  up <0, 1, 0>")?;
+        writeln!(writer, "  right <{}, 0, 0>", self.aspect_ratio)?;
+        // POV-Ray takes the horizontal field of view.
+        let horizontal = 2.0 * ((fov * 0.5).tan() * self.aspect_ratio).atan();
+        writeln!(writer, "  angle {}", horizontal.to_degrees())?;
+        writeln!(writer, "  look_at {}", vector(&center))?;
+        writeln!(writer, "}}")?;
+
+        for (offset, intensity) in [
+            (Vector3::new(-2.0, 3.0, -2.0), 0.8),
+            (Vector3::new(3.0, 2.0, -1.0), 0.4),
+            (Vector3::new(0.0, 1.0, 3.0), 0.3),
+        ] {
+            let light = center + offset * radius;
+            writeln!(
+                writer,
+                "light_source {{ {} color rgb {} }}",
+                vector(&light),
+                intensity
+            )?;
+        }
+        writeln!(writer, "background {{ color rgb <1, 1, 1> }}")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ldraw::{
+        color::{ColorReference, Finish, Material, Rgba},
+        document::{DocumentBuilder, MultipartDocument},
+        elements::{Command, PartReference},
+        Matrix4, PartAlias, Vector3,
+    };
+
+    use crate::export::{part, ExportScene, MeshExporter};
+
+    use super::{LgeoLibrary, PovRayExporter};
+
+    fn chrome() -> ColorReference {
+        ColorReference::Material(Material {
+            code: 383,
+            name: String::from("Chrome_Silver"),
+            color: Rgba::new(0xe0, 0xe0, 0xe0, 0xff),
+            finish: Finish::Chrome,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_export_povray() {
+        let placed = |name: &str, x: f32| {
+            Command::PartReference(PartReference {
+                color: chrome(),
+                matrix: Matrix4::from_translation(Vector3::new(x, 0.0, 0.0)),
+                name: PartAlias::from(name),
+            })
+        };
+        let document = MultipartDocument::new(
+            DocumentBuilder::new("model.ldr", "model.ldr", "")
+                .commands(vec![placed("3024.dat", 0.0), placed("3001.dat", 40.0)])
+                .build(),
+            HashMap::new(),
+        );
+        let parts = HashMap::from([
+            (
+                PartAlias::from("3024.dat"),
+                part(&[ColorReference::Current]),
+            ),
+            (
+                PartAlias::from("3001.dat"),
+                part(&[ColorReference::Current]),
+            ),
+        ]);
+        let scene = ExportScene {
+            document: &document,
+            parts: &parts,
+            default_color: ColorReference::Current,
+        };
+
+        let exporter = PovRayExporter {
+            lgeo: Some(LgeoLibrary::new([String::from("lg_3001")])),
+            ..Default::default()
+        };
+        let mut pov = Vec::new();
+        exporter.export(&scene, &mut pov).unwrap();
+        let pov = String::from_utf8(pov).unwrap();
+
+        assert!(pov.contains("#declare LDX_Color_383 = material {"));
+        assert!(pov.contains("metallic"));
+        assert!(pov.contains("#declare LDX_3024_dat = union {"));
+        assert!(pov.contains("    face_indices { 1, <0, 1, 2> }"));
+        assert!(pov.contains("#include \"lg_3001.inc\""));
+        assert!(!pov.contains("LDX_3001_dat"));
+        assert!(pov.contains(
+            "  object { object { LDX_3024_dat } matrix <1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0> \
+             material { LDX_Color_383 } }"
+        ));
+        assert!(pov.contains("camera {"));
+        assert_eq!(pov.matches("light_source").count(), 3);
+    }
+}
@@ -0,0 +1,219 @@
+use std::io::{self, Write};
+
+use cgmath::InnerSpace;
+use ldraw::Vector3;
+
+use crate::analysis::MM_PER_LDU;
+
+use super::{placement_name, z_up, ExportScene, FlatPart, MeshExporter};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StlFormat {
+    Binary,
+    Ascii,
+}
+
+// Writes the geometry of a model as a single STL solid, +Z up. Colors and
+// normals of the parts are dropped; facet normals follow the winding.
+#[derive(Clone, Debug)]
+pub struct StlExporter {
+    pub format: StlFormat,
+    // Output units per LDU. Slicers read STL in millimetres.
+    pub scale: f32,
+}
+
+impl Default for StlExporter {
+    fn default() -> Self {
+        StlExporter {
+            format: StlFormat::Binary,
+            scale: MM_PER_LDU,
+        }
+    }
+}
+
+fn facet_normal(t: &[Vector3; 3]) -> Vector3 {
+    let normal = (t[1] - t[0]).cross(t[2] - t[0]);
+    if normal.magnitude2() > f32::EPSILON {
+        normal.normalize()
+    } else {
+        Vector3::new(0.0, 0.0, 0.0)
+    }
+}
+
+impl StlExporter {
+    fn write(
+        &self,
+        name: &str,
+        triangles: &[[Vector3; 3]],
+        writer: &mut dyn Write,
+    ) -> io::Result<()> {
+        match self.format {
+            StlFormat::Binary => {
+                // The header must not start with "solid", or readers take
+                // the file for ASCII.
+                let mut header = [0u8; 80];
+                let title = format!("binary STL: {}", name);
+                let len = title.len().min(header.len());
+                header[..len].copy_from_slice(&title.as_bytes()[..len]);
+                writer.write_all(&header)?;
+                writer.write_all(&(triangles.len() as u32).to_le_bytes())?;
+                for t in triangles {
+                    let normal = facet_normal(t);
+                    for v in std::iter::once(&normal).chain(t.iter()) {
+                        for e in [v.x, v.y, v.z] {
+                            writer.write_all(&e.to_le_bytes())?;
+                        }
+                    }
+                    writer.write_all(&[0, 0])?;
+                }
+            }
+            StlFormat::Ascii => {
+                writeln!(writer, "solid {}", name)?;
+                for t in triangles {
+                    let n = facet_normal(t);
+                    writeln!(writer, "  facet normal {:e} {:e} {:e}", n.x, n.y, n.z)?;
+                    writeln!(writer, "    outer loop")?;
+                    for v in t {
+                        writeln!(writer, "      vertex {:e} {:e} {:e}", v.x, v.y, v.z)?;
+                    }
+                    writeln!(writer, "    endloop")?;
+                    writeln!(writer, "  endfacet")?;
+                }
+                writeln!(writer, "endsolid {}", name)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Writes every placed part as a solid of its own, into the writer
+    // `open` returns for its name (see `placement_name`). Parts stay where
+    // they are in the model.
+    pub fn export_split<F>(&self, scene: &ExportScene, mut open: F) -> io::Result<()>
+    where
+        F: FnMut(&str) -> io::Result<Box<dyn Write>>,
+    {
+        for (i, part) in scene.flatten(&z_up(self.scale)).iter().enumerate() {
+            let name = placement_name(&part.alias, i);
+            let mut writer = open(&name)?;
+            self.write(&name, &part.triangles().collect::<Vec<_>>(), &mut writer)?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl MeshExporter for StlExporter {
+    fn export(&self, scene: &ExportScene, writer: &mut dyn Write) -> io::Result<()> {
+        let triangles = scene
+            .flatten(&z_up(self.scale))
+            .iter()
+            .flat_map(FlatPart::triangles)
+            .collect::<Vec<_>>();
+        let name = scene.document.body.name.replace(char::is_whitespace, "_");
+        self.write(&name, &triangles, writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell,
+        collections::HashMap,
+        io::{self, Write},
+        rc::Rc,
+    };
+
+    use ldraw::{
+        color::ColorReference,
+        document::{DocumentBuilder, MultipartDocument},
+        elements::{Command, PartReference},
+        Matrix4, PartAlias, Vector3,
+    };
+
+    use crate::export::{part, ExportScene, MeshExporter};
+
+    use super::{StlExporter, StlFormat};
+
+    fn document() -> MultipartDocument {
+        let placed = |y: f32| {
+            Command::PartReference(PartReference {
+                color: ColorReference::Current,
+                matrix: Matrix4::from_translation(Vector3::new(0.0, y, 0.0)),
+                name: PartAlias::from("3024.dat"),
+            })
+        };
+        MultipartDocument::new(
+            DocumentBuilder::new("model.ldr", "model.ldr", "")
+                .commands(vec![placed(0.0), placed(-8.0)])
+                .build(),
+            HashMap::new(),
+        )
+    }
+
+    fn f32_at(bytes: &[u8], offset: usize) -> f32 {
+        f32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_export_stl() {
+        let document = document();
+        let parts = HashMap::from([(
+            PartAlias::from("3024.dat"),
+            part(&[ColorReference::Current]),
+        )]);
+        let scene = ExportScene {
+            document: &document,
+            parts: &parts,
+            default_color: ColorReference::Current,
+        };
+
+        let mut stl = Vec::new();
+        StlExporter::default().export(&scene, &mut stl).unwrap();
+        assert_eq!(stl.len(), 84 + 2 * 50);
+        assert_eq!(u32::from_le_bytes(stl[80..84].try_into().unwrap()), 2);
+        // Facing up, in millimetres.
+        assert_eq!(f32_at(&stl, 84 + 8), 1.0);
+        let second = 84 + 50;
+        assert!((f32_at(&stl, second + 12 + 8) - 3.2).abs() < 1e-5);
+
+        let ascii = StlExporter {
+            format: StlFormat::Ascii,
+            scale: 1.0,
+        };
+        let mut stl = Vec::new();
+        ascii.export(&scene, &mut stl).unwrap();
+        let stl = String::from_utf8(stl).unwrap();
+        assert!(stl.starts_with("solid model.ldr\n  facet normal 0e0 0e0 1e0\n"));
+        assert!(stl.contains("      vertex 2e1 0e0 0e0\n"));
+        assert!(stl.ends_with("endsolid model.ldr\n"));
+        assert_eq!(stl.matches("endfacet").count(), 2);
+
+        let files = RefCell::new(Vec::new());
+        ascii
+            .export_split(&scene, |name| {
+                let file = Shared::default();
+                files.borrow_mut().push((name.to_string(), file.clone()));
+                Ok(Box::new(file))
+            })
+            .unwrap();
+        let files = files.borrow();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[1].0, "3024.dat_1");
+        let content = String::from_utf8(files[1].1 .0.borrow().clone()).unwrap();
+        assert!(content.contains("vertex 0e0 0e0 8e0"));
+    }
+}
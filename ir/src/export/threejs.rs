@@ -0,0 +1,326 @@
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+
+use ldraw::{color::ColorReference, Matrix4, PartAlias, Vector3};
+use serde_json::{json, Value};
+
+use crate::{analysis::MM_PER_LDU, mesh::BakedPart};
+
+use super::{columns, metallic_roughness, y_up, ExportScene, MeshExporter};
+
+const FRONT_SIDE: u32 = 0;
+const DOUBLE_SIDE: u32 = 2;
+
+// Writes a model in the JSON object format of three.js, which
+// `THREE.ObjectLoader` turns into a scene graph without any further code.
+//
+// Like the display list of the renderer, the scene is flat: every part
+// becomes one geometry with a group per color, and its placements become
+// meshes under a single root group. Parts placed more than once in the
+// same color become an `InstancedMesh` if `instancing` is set. Edges are
+// not exported.
+#[derive(Clone, Debug)]
+pub struct ThreeJsExporter {
+    // Scene units per LDU; metres by default.
+    pub scale: f32,
+    pub instancing: bool,
+}
+
+impl Default for ThreeJsExporter {
+    fn default() -> Self {
+        ThreeJsExporter {
+            scale: MM_PER_LDU / 1000.0,
+            instancing: true,
+        }
+    }
+}
+
+fn float_array(values: impl Iterator<Item = f32>, item_size: usize) -> Value {
+    json!({
+        "itemSize": item_size,
+        "type": "Float32Array",
+        "array": values.collect::<Vec<_>>(),
+        "normalized": false,
+    })
+}
+
+#[derive(Default)]
+struct Builder {
+    geometries: Vec<Value>,
+    materials: Vec<Value>,
+    geometry_lookup: HashMap<PartAlias, Option<String>>,
+    material_lookup: HashMap<(u32, bool), String>,
+}
+
+impl Builder {
+    fn material(&mut self, color: &ColorReference, double_sided: bool) -> String {
+        let key = (color.code(), double_sided);
+        if let Some(e) = self.material_lookup.get(&key) {
+            return e.clone();
+        }
+
+        let uuid = format!("material-{}", self.materials.len());
+        let side = if double_sided {
+            DOUBLE_SIDE
+        } else {
+            FRONT_SIDE
+        };
+        let material = match color.get_material() {
+            Some(m) => {
+                let (metalness, roughness) = metallic_roughness(&m.finish);
+                let c = m.color;
+                let mut material = json!({
+                    "uuid": uuid,
+                    "type": "MeshStandardMaterial",
+                    "name": m.name,
+                    "color": u32::from_be_bytes([0, c.red(), c.green(), c.blue()]),
+                    "metalness": metalness,
+                    "roughness": roughness,
+                    "side": side,
+                });
+                if m.is_translucent() {
+                    material["transparent"] = json!(true);
+                    material["opacity"] = json!(c.alpha() as f32 / 255.0);
+                    material["depthWrite"] = json!(false);
+                }
+                material
+            }
+            None => json!({
+                "uuid": uuid,
+                "type": "MeshStandardMaterial",
+                "name": format!("Color {}", color.code()),
+                "color": 0x808080,
+                "roughness": 0.4,
+                "side": side,
+            }),
+        };
+        self.materials.push(material);
+        self.material_lookup.insert(key, uuid.clone());
+        uuid
+    }
+
+    // All groups of a part in one geometry, drawn with one material each.
+    fn geometry(&mut self, alias: &PartAlias, part: &BakedPart) -> Option<String> {
+        if let Some(e) = self.geometry_lookup.get(alias) {
+            return e.clone();
+        }
+
+        let mut positions: Vec<Vector3> = Vec::new();
+        let mut normals: Vec<Vector3> = Vec::new();
+        let mut indices = Vec::new();
+        let mut groups = Vec::new();
+        for group in part.groups.iter().filter(|e| !e.mesh.is_empty()) {
+            let mesh = &group.mesh;
+            let base = positions.len() as u32;
+            groups.push(json!({
+                "start": indices.len(),
+                "count": mesh.indices.len(),
+                "materialIndex": groups.len(),
+            }));
+            positions.extend(mesh.positions.iter());
+            normals.extend(mesh.normals.iter());
+            indices.extend(mesh.indices.iter().map(|e| e + base));
+        }
+
+        let uuid = if groups.is_empty() {
+            None
+        } else {
+            let uuid = format!("geometry-{}", self.geometries.len());
+            self.geometries.push(json!({
+                "uuid": uuid,
+                "type": "BufferGeometry",
+                "name": alias.original.to_string(),
+                "data": {
+                    "attributes": {
+                        "position": float_array(positions.iter().flat_map(|e| [e.x, e.y, e.z]), 3),
+                        "normal": float_array(normals.iter().flat_map(|e| [e.x, e.y, e.z]), 3),
+                    },
+                    "index": { "type": "Uint32Array", "array": indices },
+                    "groups": groups,
+                },
+            }));
+            Some(uuid)
+        };
+        self.geometry_lookup.insert(alias.clone(), uuid.clone());
+        uuid
+    }
+
+    fn materials(&mut self, part: &BakedPart, color: &ColorReference) -> Vec<String> {
+        part.groups
+            .iter()
+            .filter(|e| !e.mesh.is_empty())
+            .map(|e| {
+                let group_color = if e.group.color_ref.is_current() {
+                    color
+                } else {
+                    &e.group.color_ref
+                };
+                self.material(group_color, !e.group.bfc)
+            })
+            .collect()
+    }
+}
+
+impl MeshExporter for ThreeJsExporter {
+    fn export(&self, scene: &ExportScene, writer: &mut dyn Write) -> io::Result<()> {
+        let mut builder = Builder::default();
+
+        // Placements grouped the way the display list groups them.
+        let mut placements: Vec<(PartAlias, ColorReference, Vec<Matrix4>)> = Vec::new();
+        let mut lookup = HashMap::new();
+        for (alias, color, matrix) in scene.root().flatten() {
+            let index = *lookup
+                .entry((alias.clone(), color.code()))
+                .or_insert_with(|| {
+                    placements.push((alias, color, Vec::new()));
+                    placements.len() - 1
+                });
+            placements[index].2.push(matrix);
+        }
+
+        let mut children = Vec::new();
+        for (alias, color, matrices) in placements.iter() {
+            let part = match scene.parts.get(alias) {
+                Some(e) => e,
+                None => continue,
+            };
+            let geometry = match builder.geometry(alias, part) {
+                Some(e) => e,
+                None => continue,
+            };
+            let materials = builder.materials(part, color);
+            let object = |kind: &str, index: usize| {
+                json!({
+                    "uuid": format!("object-{}", index),
+                    "type": kind,
+                    "name": alias.original.to_string(),
+                    "geometry": geometry,
+                    "material": materials,
+                })
+            };
+            if self.instancing && matrices.len() > 1 {
+                let mut mesh = object("InstancedMesh", children.len() + 1);
+                mesh["count"] = json!(matrices.len());
+                mesh["instanceMatrix"] = float_array(matrices.iter().flat_map(columns), 16);
+                children.push(mesh);
+            } else {
+                for matrix in matrices {
+                    let mut mesh = object("Mesh", children.len() + 1);
+                    mesh["matrix"] = json!(columns(matrix));
+                    children.push(mesh);
+                }
+            }
+        }
+
+        let document = json!({
+            "metadata": {
+                "version": 4.6,
+                "type": "Object",
+                "generator": "ldraw.rs",
+            },
+            "geometries": builder.geometries,
+            "materials": builder.materials,
+            "object": {
+                "uuid": "object-0",
+                "type": "Group",
+                "name": scene.document.body.name,
+                "matrix": columns(&y_up(self.scale)),
+                "children": children,
+            },
+        });
+        serde_json::to_writer(&mut *writer, &document)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ldraw::{
+        color::{ColorReference, Material, Rgba},
+        document::{DocumentBuilder, MultipartDocument},
+        elements::{Command, PartReference},
+        Matrix4, PartAlias, Vector3,
+    };
+    use serde_json::Value;
+
+    use crate::export::{part, ExportScene, MeshExporter};
+
+    use super::ThreeJsExporter;
+
+    fn red() -> ColorReference {
+        ColorReference::Material(Material {
+            code: 4,
+            name: "Red".to_string(),
+            color: Rgba::new(0xc9, 0x1a, 0x09, 255),
+            edge: Rgba::new(0x33, 0x33, 0x33, 255),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_export_threejs() {
+        let placed = |color: ColorReference, x: f32| {
+            Command::PartReference(PartReference {
+                color,
+                matrix: Matrix4::from_translation(Vector3::new(x, 0.0, 0.0)),
+                name: PartAlias::from("3024.dat"),
+            })
+        };
+        let document = MultipartDocument::new(
+            DocumentBuilder::new("model.ldr", "model.ldr", "")
+                .commands(vec![
+                    placed(ColorReference::Current, 0.0),
+                    placed(ColorReference::Current, 20.0),
+                    placed(red(), 40.0),
+                ])
+                .build(),
+            HashMap::new(),
+        );
+        let parts = HashMap::from([(
+            PartAlias::from("3024.dat"),
+            part(&[ColorReference::Current, red()]),
+        )]);
+        let scene = ExportScene {
+            document: &document,
+            parts: &parts,
+            default_color: ColorReference::Current,
+        };
+
+        let mut output = Vec::new();
+        ThreeJsExporter::default()
+            .export(&scene, &mut output)
+            .unwrap();
+        let json: Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(json["metadata"]["type"], "Object");
+
+        // One geometry shared by both colors, with a group per color.
+        let geometries = json["geometries"].as_array().unwrap();
+        assert_eq!(geometries.len(), 1);
+        let data = &geometries[0]["data"];
+        assert_eq!(
+            data["attributes"]["position"]["array"]
+                .as_array()
+                .unwrap()
+                .len(),
+            18
+        );
+        assert_eq!(data["index"]["array"][5], 5);
+        assert_eq!(data["groups"][1]["start"], 3);
+        assert_eq!(json["materials"].as_array().unwrap().len(), 2);
+        assert_eq!(json["materials"][1]["color"], 0xc91a09);
+
+        let children = json["object"]["children"].as_array().unwrap();
+        assert_eq!(children.len(), 2);
+        assert_eq!(children[0]["type"], "InstancedMesh");
+        assert_eq!(children[0]["count"], 2);
+        assert_eq!(children[0]["instanceMatrix"]["array"][12 + 16], 20.0);
+        assert_eq!(children[1]["type"], "Mesh");
+        assert_eq!(children[1]["matrix"][12], 40.0);
+        // Both groups of the red placement are red.
+        assert_eq!(children[1]["material"][0], children[1]["material"][1]);
+    }
+}
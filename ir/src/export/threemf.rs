@@ -0,0 +1,260 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io::{self, Cursor, Write},
+};
+
+use ldraw::color::ColorReference;
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use crate::analysis::MM_PER_LDU;
+
+use super::{material_name, placement_name, xml_escape, z_up, ExportScene, MeshExporter};
+
+const CONTENT_TYPES: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">
+ <Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/>
+ <Default Extension="model" ContentType="application/vnd.ms-package.3dmanufacturing-3dmodel+xml"/>
+</Types>
+"#;
+
+const RELATIONSHIPS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+ <Relationship Target="/3D/3dmodel.model" Id="rel0" Type="http://schemas.microsoft.com/3dmanufacturing/2013/01/3dmodel"/>
+</Relationships>
+"#;
+
+const MODEL_PATH: &str = "3D/3dmodel.model";
+
+// Writes 3MF packages, in millimetres. Every placed part becomes an object
+// of its own, with the color of each triangle taken from a base material
+// per LDraw color.
+#[derive(Clone, Debug)]
+pub struct ThreeMfExporter {
+    // Millimetres per LDU.
+    pub scale: f32,
+}
+
+impl Default for ThreeMfExporter {
+    fn default() -> Self {
+        ThreeMfExporter { scale: MM_PER_LDU }
+    }
+}
+
+// Packs `entries` into a deflated ZIP archive, the container of an OPC
+// package like 3MF. ZIP needs to seek back, so it is assembled in memory.
+fn write_zip(entries: &[(&str, &[u8])], writer: &mut dyn Write) -> io::Result<()> {
+    let large = entries.iter().any(|(_, e)| e.len() > u32::MAX as usize);
+    let options = FileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .large_file(large);
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, data) in entries {
+        zip.start_file(*name, options)?;
+        zip.write_all(data)?;
+    }
+    writer.write_all(zip.finish()?.get_ref())
+}
+
+fn display_color(color: &ColorReference) -> String {
+    match color.get_material() {
+        Some(m) => format!(
+            "#{:02X}{:02X}{:02X}{:02X}",
+            m.color.red(),
+            m.color.green(),
+            m.color.blue(),
+            m.color.alpha()
+        ),
+        None => String::from("#808080FF"),
+    }
+}
+
+impl ThreeMfExporter {
+    fn model(&self, scene: &ExportScene) -> String {
+        let parts = scene.flatten(&z_up(self.scale));
+
+        let mut colors = Vec::new();
+        let mut lookup = HashMap::new();
+        for (group, _) in parts.iter().flat_map(|e| e.groups.iter()) {
+            lookup.entry(group.color_ref.code()).or_insert_with(|| {
+                colors.push(group.color_ref.clone());
+                colors.len() - 1
+            });
+        }
+
+        // Writing into a String cannot fail.
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(
+            "<model unit=\"millimeter\" xml:lang=\"en-US\" \
+             xmlns=\"http://schemas.microsoft.com/3dmanufacturing/core/2015/02\">\n",
+        );
+        let body = &scene.document.body;
+        for (name, value) in [("Title", &body.description), ("Designer", &body.author)] {
+            if !value.is_empty() {
+                let _ = writeln!(
+                    xml,
+                    " <metadata name=\"{}\">{}</metadata>",
+                    name,
+                    xml_escape(value)
+                );
+            }
+        }
+        xml.push_str(" <resources>\n  <basematerials id=\"1\">\n");
+        for color in colors.iter() {
+            let _ = writeln!(
+                xml,
+                "   <base name=\"{}\" displaycolor=\"{}\"/>",
+                xml_escape(&material_name(color)),
+                display_color(color)
+            );
+        }
+        xml.push_str("  </basematerials>\n");
+
+        let mut objects = Vec::new();
+        for (i, part) in parts.iter().enumerate() {
+            let first = match part.groups.first() {
+                Some(e) => lookup[&e.0.color_ref.code()],
+                None => continue,
+            };
+            let id = i + 2;
+            objects.push(id);
+            let _ = writeln!(
+                xml,
+                "  <object id=\"{}\" type=\"model\" name=\"{}\" pid=\"1\" pindex=\"{}\">",
+                id,
+                xml_escape(&placement_name(&part.alias, i)),
+                first
+            );
+            xml.push_str("   <mesh>\n    <vertices>\n");
+            for (_, mesh) in part.groups.iter() {
+                for p in mesh.positions.iter() {
+                    let _ = writeln!(
+                        xml,
+                        "     <vertex x=\"{}\" y=\"{}\" z=\"{}\"/>",
+                        p.x, p.y, p.z
+                    );
+                }
+            }
+            xml.push_str("    </vertices>\n    <triangles>\n");
+            let mut base = 0;
+            for (group, mesh) in part.groups.iter() {
+                let color = lookup[&group.color_ref.code()];
+                for t in mesh.indices.chunks_exact(3) {
+                    let _ = writeln!(
+                        xml,
+                        "     <triangle v1=\"{}\" v2=\"{}\" v3=\"{}\" pid=\"1\" p1=\"{}\"/>",
+                        base + t[0] as usize,
+                        base + t[1] as usize,
+                        base + t[2] as usize,
+                        color
+                    );
+                }
+                base += mesh.positions.len();
+            }
+            xml.push_str("    </triangles>\n   </mesh>\n  </object>\n");
+        }
+        xml.push_str(" </resources>\n <build>\n");
+        for id in objects {
+            let _ = writeln!(xml, "  <item objectid=\"{}\"/>", id);
+        }
+        xml.push_str(" </build>\n</model>\n");
+        xml
+    }
+}
+
+impl MeshExporter for ThreeMfExporter {
+    fn export(&self, scene: &ExportScene, writer: &mut dyn Write) -> io::Result<()> {
+        let model = self.model(scene);
+        write_zip(
+            &[
+                ("[Content_Types].xml", CONTENT_TYPES.as_bytes()),
+                ("_rels/.rels", RELATIONSHIPS.as_bytes()),
+                (MODEL_PATH, model.as_bytes()),
+            ],
+            writer,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        io::{Cursor, Read},
+    };
+
+    use ldraw::{
+        color::{ColorReference, Material, Rgba},
+        document::{DocumentBuilder, MultipartDocument},
+        elements::{Command, PartReference},
+        Matrix4, PartAlias, Vector3,
+    };
+    use zip::{CompressionMethod, ZipArchive};
+
+    use crate::export::{part, ExportScene, MeshExporter};
+
+    use super::{ThreeMfExporter, MODEL_PATH};
+
+    fn red() -> ColorReference {
+        ColorReference::Material(Material {
+            code: 4,
+            name: String::from("Red"),
+            color: Rgba::new(0xc9, 0x1a, 0x09, 0xff),
+            ..Default::default()
+        })
+    }
+
+    fn entries(package: &[u8]) -> HashMap<String, String> {
+        let mut archive = ZipArchive::new(Cursor::new(package)).unwrap();
+        (0..archive.len())
+            .map(|i| {
+                let mut file = archive.by_index(i).unwrap();
+                assert_eq!(file.compression(), CompressionMethod::Deflated);
+                let mut contents = String::new();
+                file.read_to_string(&mut contents).unwrap();
+                (file.name().to_string(), contents)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_export_3mf() {
+        let document = MultipartDocument::new(
+            DocumentBuilder::new("model.ldr", "Tom & Jerry", "")
+                .commands(vec![Command::PartReference(PartReference {
+                    color: ColorReference::Current,
+                    matrix: Matrix4::from_translation(Vector3::new(0.0, -8.0, 0.0)),
+                    name: PartAlias::from("3024.dat"),
+                })])
+                .build(),
+            HashMap::new(),
+        );
+        let parts = HashMap::from([(
+            PartAlias::from("3024.dat"),
+            part(&[ColorReference::Current, red()]),
+        )]);
+        let scene = ExportScene {
+            document: &document,
+            parts: &parts,
+            default_color: red(),
+        };
+
+        let mut package = Vec::new();
+        ThreeMfExporter::default()
+            .export(&scene, &mut package)
+            .unwrap();
+        let entries = entries(&package);
+        assert!(entries.contains_key("[Content_Types].xml"));
+        assert!(entries["_rels/.rels"].contains("/3D/3dmodel.model"));
+
+        let model = &entries[MODEL_PATH];
+        assert!(model.contains("<metadata name=\"Title\">Tom &amp; Jerry</metadata>"));
+        // Both groups end up red, as the part is placed in red.
+        assert_eq!(model.matches("<base ").count(), 1);
+        assert!(model.contains("<base name=\"Red\" displaycolor=\"#C91A09FF\"/>"));
+        assert!(model.contains("<vertex x=\"0\" y=\"0\" z=\"3.2\"/>"));
+        assert!(model.contains("<triangle v1=\"3\" v2=\"4\" v3=\"5\" pid=\"1\" p1=\"0\"/>"));
+        assert!(model.contains("<item objectid=\"2\"/>"));
+    }
+}
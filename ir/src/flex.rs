@@ -0,0 +1,359 @@
+use cgmath::InnerSpace;
+use ldraw::{
+    color::ColorReference,
+    document::Document,
+    elements::{Header, PartReference},
+    Matrix4, PartAlias, Vector2, Vector3,
+};
+
+use crate::{
+    connectivity::{floats, ldcad_parameters},
+    mesh::IndexedMesh,
+};
+
+// Points sampled per span of the curve before measuring it.
+const SAMPLES_PER_SPAN: usize = 16;
+
+// A point along a path, with the direction of the path and a normal that
+// turns as little as possible from one point to the next.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PathFrame {
+    pub position: Vector3,
+    pub tangent: Vector3,
+    pub normal: Vector3,
+}
+
+impl PathFrame {
+    // Places a part with its Y axis along the path and its X axis along
+    // the normal.
+    pub fn matrix(&self) -> Matrix4 {
+        let z = self.normal.cross(self.tangent);
+        Matrix4::from_cols(
+            self.normal.extend(0.0),
+            self.tangent.extend(0.0),
+            z.extend(0.0),
+            self.position.extend(1.0),
+        )
+    }
+}
+
+// A smooth curve through a list of points (Catmull-Rom), e.g. the route of
+// a hose or a chain.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FlexPath {
+    pub points: Vec<Vector3>,
+}
+
+impl FlexPath {
+    pub fn new(points: Vec<Vector3>) -> Self {
+        FlexPath { points }
+    }
+
+    // Reads the points of LDCad `PATH_POINT` metas in the order they
+    // appear. Returns `None` if there are fewer than two.
+    pub fn from_metas(document: &Document) -> Option<Self> {
+        let points = document
+            .headers
+            .iter()
+            .filter_map(|Header(key, value)| {
+                let parameters = value
+                    .strip_prefix("PATH_POINT")
+                    .filter(|_| key == "LDCAD")?;
+                let values = floats(ldcad_parameters(parameters).get("posori")?)?;
+                match values.as_slice() {
+                    [x, y, z, ..] => Some(Vector3::new(*x, *y, *z)),
+                    _ => None,
+                }
+            })
+            .collect::<Vec<_>>();
+        if points.len() < 2 {
+            return None;
+        }
+        Some(FlexPath { points })
+    }
+
+    fn point(&self, i: isize) -> Vector3 {
+        self.points[i.clamp(0, self.points.len() as isize - 1) as usize]
+    }
+
+    // The curve as a dense polyline.
+    fn polyline(&self) -> Vec<Vector3> {
+        let mut polyline = Vec::new();
+        if let Some(first) = self.points.first() {
+            polyline.push(*first);
+        }
+        for i in 0..self.points.len().saturating_sub(1) as isize {
+            let (p0, p1, p2, p3) = (
+                self.point(i - 1),
+                self.point(i),
+                self.point(i + 1),
+                self.point(i + 2),
+            );
+            for s in 1..=SAMPLES_PER_SPAN {
+                let t = s as f32 / SAMPLES_PER_SPAN as f32;
+                let (t2, t3) = (t * t, t * t * t);
+                polyline.push(
+                    (p1 * 2.0
+                        + (p2 - p0) * t
+                        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+                        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+                        * 0.5,
+                );
+            }
+        }
+        polyline.dedup();
+        polyline
+    }
+
+    pub fn length(&self) -> f32 {
+        self.polyline()
+            .windows(2)
+            .map(|e| (e[1] - e[0]).magnitude())
+            .sum()
+    }
+
+    // Frames `spacing` LDU apart along the curve, starting at its first
+    // point. The last frame is at the end of the curve, even if closer.
+    pub fn frames(&self, spacing: f32) -> Vec<PathFrame> {
+        let polyline = self.polyline();
+        if polyline.len() < 2 || spacing <= 0.0 {
+            return Vec::new();
+        }
+
+        // Positions and tangents at every multiple of `spacing`.
+        let mut samples = Vec::new();
+        let mut travelled = 0.0;
+        let mut next = 0.0;
+        for e in polyline.windows(2) {
+            let length = (e[1] - e[0]).magnitude();
+            let tangent = (e[1] - e[0]) / length;
+            while next <= travelled + length {
+                samples.push((e[0] + tangent * (next - travelled), tangent));
+                next += spacing;
+            }
+            travelled += length;
+        }
+        let end = polyline[polyline.len() - 1];
+        if !samples
+            .last()
+            .is_some_and(|e| (e.0 - end).magnitude() <= 1e-3)
+        {
+            let tangent = (end - polyline[polyline.len() - 2]).normalize();
+            samples.push((end, tangent));
+        }
+
+        // Parallel transport: every normal is the previous one with the
+        // part along the new tangent removed.
+        let first = samples[0].1;
+        let other = if first.x.abs() < 0.9 {
+            Vector3::unit_x()
+        } else {
+            Vector3::unit_y()
+        };
+        let mut normal = (other - first * other.dot(first)).normalize();
+        samples
+            .into_iter()
+            .map(|(position, tangent)| {
+                let projected = normal - tangent * normal.dot(tangent);
+                if projected.magnitude2() > f32::EPSILON {
+                    normal = projected.normalize();
+                }
+                PathFrame {
+                    position,
+                    tangent,
+                    normal,
+                }
+            })
+            .collect()
+    }
+}
+
+// One `segment` part per frame, e.g. the links of a chain or the pieces of
+// a ribbed hose.
+pub fn segment_references(
+    frames: &[PathFrame],
+    segment: &PartAlias,
+    color: &ColorReference,
+) -> Vec<PartReference> {
+    frames
+        .iter()
+        .map(|e| PartReference {
+            color: color.clone(),
+            matrix: e.matrix(),
+            name: segment.clone(),
+        })
+        .collect()
+}
+
+// Cross section swept along a path, as pairs of points forming its edges
+// with a normal for each point. Points are in the plane of the frame's
+// normal (x) and the tangent crossed with it (y), counter-clockwise.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Profile {
+    pub points: Vec<Vector2>,
+    pub normals: Vec<Vector2>,
+}
+
+impl Profile {
+    // A round hose, shaded smooth.
+    pub fn circle(radius: f32, sides: usize) -> Self {
+        let mut profile = Profile::default();
+        for i in 0..sides {
+            for j in [i, i + 1] {
+                let angle = std::f32::consts::TAU * j as f32 / sides as f32;
+                let normal = Vector2::new(angle.cos(), angle.sin());
+                profile.points.push(normal * radius);
+                profile.normals.push(normal);
+            }
+        }
+        profile
+    }
+
+    // A flat band with sharp edges.
+    pub fn band(width: f32, thickness: f32) -> Self {
+        let (w, t) = (width * 0.5, thickness * 0.5);
+        let corners = [
+            Vector2::new(w, -t),
+            Vector2::new(w, t),
+            Vector2::new(-w, t),
+            Vector2::new(-w, -t),
+        ];
+        let mut profile = Profile::default();
+        for i in 0..4 {
+            let (a, b) = (corners[i], corners[(i + 1) % 4]);
+            let edge = (b - a).normalize();
+            let normal = Vector2::new(edge.y, -edge.x);
+            profile.points.extend([a, b]);
+            profile.normals.extend([normal, normal]);
+        }
+        profile
+    }
+}
+
+// Sweeps `profile` along `frames`. The ends are left open, as hoses and
+// bands usually end in separate end pieces.
+pub fn sweep(frames: &[PathFrame], profile: &Profile) -> IndexedMesh {
+    let mut mesh = IndexedMesh::default();
+    for frame in frames {
+        let binormal = frame.tangent.cross(frame.normal);
+        for (point, normal) in profile.points.iter().zip(profile.normals.iter()) {
+            mesh.positions
+                .push(frame.position + frame.normal * point.x + binormal * point.y);
+            mesh.normals
+                .push((frame.normal * normal.x + binormal * normal.y).normalize());
+        }
+    }
+
+    let ring = profile.points.len() as u32;
+    for f in 0..frames.len().saturating_sub(1) as u32 {
+        for e in (0..ring).step_by(2) {
+            let (a, b) = (f * ring + e, f * ring + e + 1);
+            let (c, d) = (a + ring, b + ring);
+            mesh.indices.extend([a, b, c, b, d, c]);
+        }
+    }
+    mesh
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{AbsDiffEq, InnerSpace, SquareMatrix};
+    use ldraw::{color::ColorReference, document::DocumentBuilder, PartAlias, Vector3};
+
+    use super::{segment_references, sweep, FlexPath, Profile};
+
+    #[test]
+    fn test_frames() {
+        let straight = FlexPath::new(vec![
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, 95.0),
+        ]);
+        let frames = straight.frames(10.0);
+        assert_eq!(frames.len(), 11);
+        assert_eq!(frames[10].position, Vector3::new(0.0, 0.0, 95.0));
+        for frame in frames.iter() {
+            assert!(frame.tangent.abs_diff_eq(&Vector3::unit_z(), 1e-5));
+        }
+
+        let bent = FlexPath::new(vec![
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(40.0, 0.0, 0.0),
+            Vector3::new(40.0, 0.0, 40.0),
+            Vector3::new(80.0, -20.0, 40.0),
+        ]);
+        let frames = bent.frames(5.0);
+        assert!(bent.length() > 120.0);
+        for pair in frames.windows(2).take(frames.len() - 2) {
+            let step = (pair[1].position - pair[0].position).magnitude();
+            assert!(step > 4.5 && step <= 5.0 + 1e-3);
+            assert!(pair[1].normal.dot(pair[1].tangent).abs() < 1e-4);
+            // Normals only turn as much as the path does.
+            assert!(pair[0].normal.dot(pair[1].normal) > 0.9);
+        }
+    }
+
+    #[test]
+    fn test_segment_references() {
+        let path = FlexPath::new(vec![
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(30.0, 0.0, 0.0),
+        ]);
+        let references = segment_references(
+            &path.frames(10.0),
+            &PartAlias::from("3711.dat"),
+            &ColorReference::Current,
+        );
+        assert_eq!(references.len(), 4);
+        for e in references.iter() {
+            assert!((e.matrix.determinant() - 1.0).abs() < 1e-5);
+            assert!(e.matrix.y.truncate().abs_diff_eq(&Vector3::unit_x(), 1e-5));
+        }
+        assert_eq!(
+            references[3].matrix.w.truncate(),
+            Vector3::new(30.0, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn test_sweep() {
+        let path = FlexPath::new(vec![
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, -40.0, 0.0),
+        ]);
+        let frames = path.frames(10.0);
+        let mesh = sweep(&frames, &Profile::circle(4.0, 8));
+        assert_eq!(mesh.triangle_count(), 4 * 8 * 2);
+        // Every face points away from the axis.
+        for t in mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [t[0], t[1], t[2]].map(|e| mesh.positions[e as usize]);
+            let center = (a + b + c) / 3.0;
+            let outward = Vector3::new(center.x, 0.0, center.z);
+            assert!((b - a).cross(c - a).dot(outward) > 0.0);
+        }
+
+        let band = sweep(&frames, &Profile::band(6.0, 1.0));
+        assert_eq!(band.triangle_count(), 4 * 4 * 2);
+        for t in band.indices.chunks_exact(3) {
+            let [a, b, c] = [t[0], t[1], t[2]].map(|e| band.positions[e as usize]);
+            let normal = band.normals[t[0] as usize];
+            assert!((b - a).cross(c - a).normalize().abs_diff_eq(&normal, 1e-4));
+        }
+    }
+
+    #[test]
+    fn test_from_metas() {
+        let document = DocumentBuilder::new("hose.ldr", "hose.ldr", "")
+            .header(
+                "LDCAD",
+                "PATH_POINT [type=rigid] [posOri=0 0 0 1 0 0 0 1 0 0 0 1]",
+            )
+            .header("LDCAD", "PATH_CAP [group=start]")
+            .header("LDCAD", "PATH_POINT [posOri=0 -20 10 1 0 0 0 1 0 0 0 1]")
+            .build();
+        let path = FlexPath::from_metas(&document).unwrap();
+        assert_eq!(
+            path.points,
+            vec![Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, -20.0, 10.0)]
+        );
+    }
+}
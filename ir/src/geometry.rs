@@ -1,3 +1,4 @@
+use cgmath::InnerSpace;
 use ldraw::{Vector2, Vector3};
 use serde::{Deserialize, Serialize};
 
@@ -169,4 +170,47 @@ impl BoundingBox3 {
             Vector3::new(self.max.x, self.max.y, self.max.z),
         ]
     }
+
+    pub fn intersects(&self, other: &BoundingBox3) -> bool {
+        self.min.x <= other.max.x
+            && other.min.x <= self.max.x
+            && self.min.y <= other.max.y
+            && other.min.y <= self.max.y
+            && self.min.z <= other.max.z
+            && other.min.z <= self.max.z
+    }
+}
+
+// A sphere enclosing a set of points, for culling and streaming.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct BoundingSphere {
+    pub center: Vector3,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    // Centered on the bounding box of the points, which is not the
+    // smallest sphere but close enough for bricks.
+    pub fn from_points<'a, I: IntoIterator<Item = &'a Vector3> + Clone>(points: I) -> Self {
+        let mut points_iter = points.clone().into_iter();
+        let first = match points_iter.next() {
+            Some(e) => e,
+            None => {
+                return BoundingSphere {
+                    center: Vector3::new(0.0, 0.0, 0.0),
+                    radius: 0.0,
+                }
+            }
+        };
+        let mut bounds = BoundingBox3::new(first, first);
+        for p in points_iter {
+            bounds.update_point(p);
+        }
+        let center = bounds.center();
+        let radius = points
+            .into_iter()
+            .map(|e| (e - center).magnitude())
+            .fold(0.0, f32::max);
+        BoundingSphere { center, radius }
+    }
 }
@@ -0,0 +1,365 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, Read},
+};
+
+use cgmath::{InnerSpace, Rad};
+use ldraw::{
+    color::ColorReference,
+    document::{Document, DocumentBuilder},
+    elements::{Command, Line, Quad, Triangle},
+    error::{ImportError, ParseError},
+    Vector3,
+};
+
+use crate::{
+    analysis::MM_PER_LDU,
+    mesh::IndexedMesh,
+    outline::{edge_key, FaceEdges},
+};
+
+// Cosine of the angle below which neighbouring triangles count as coplanar
+// when pairing them into quads.
+const COPLANAR_COS: f32 = 0.9999;
+
+// Axis pointing up in the source file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpAxis {
+    // Most modelling tools and OBJ files.
+    Y,
+    // 3D printing and CAD, which STL files mostly come from.
+    Z,
+}
+
+#[derive(Clone, Debug)]
+pub struct MeshImportOptions {
+    // LDU per unit of the source file. The default takes it for
+    // millimetres.
+    pub scale: f32,
+    pub up: UpAxis,
+    // Pairs up coplanar triangles into type 4 lines.
+    pub quads: bool,
+    // Adds type 2 lines along edges where the surface folds by more than
+    // this angle, and along open edges.
+    pub edge_angle: Option<Rad<f32>>,
+    pub color: ColorReference,
+}
+
+impl Default for MeshImportOptions {
+    fn default() -> Self {
+        MeshImportOptions {
+            scale: 1.0 / MM_PER_LDU,
+            up: UpAxis::Y,
+            quads: true,
+            edge_angle: Some(Rad(std::f32::consts::FRAC_PI_6)),
+            color: ColorReference::Current,
+        }
+    }
+}
+
+fn invalid(token: &str) -> ImportError {
+    ImportError::ParseError(ParseError::InvalidToken(token.to_string()))
+}
+
+// Every triangle gets vertices of its own, with the face normal.
+fn push_triangle(mesh: &mut IndexedMesh, t: [Vector3; 3]) {
+    let normal = (t[1] - t[0]).cross(t[2] - t[0]);
+    if normal.magnitude2() <= f32::EPSILON * f32::EPSILON {
+        return;
+    }
+    let normal = normal.normalize();
+    for v in t {
+        mesh.indices.push(mesh.positions.len() as u32);
+        mesh.positions.push(v);
+        mesh.normals.push(normal);
+    }
+}
+
+// Reads the faces of a Wavefront OBJ file, fanning out polygons. Groups,
+// materials and texture coordinates are ignored.
+pub fn read_obj<R: BufRead>(reader: R) -> Result<IndexedMesh, ImportError> {
+    let mut vertices = Vec::new();
+    let mut mesh = IndexedMesh::default();
+    for line in reader.lines() {
+        let line = line?;
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("v") => {
+                let mut coordinate = || -> Result<f32, ImportError> {
+                    let token = tokens.next().ok_or_else(|| invalid(&line))?;
+                    token.parse().map_err(|_| invalid(token))
+                };
+                vertices.push(Vector3::new(coordinate()?, coordinate()?, coordinate()?));
+            }
+            Some("f") => {
+                let face = tokens
+                    .map(|token| {
+                        // Indices are 1-based, or relative to the end if negative.
+                        let index = token.split('/').next().unwrap_or(token);
+                        let index: i64 = index.parse().map_err(|_| invalid(token))?;
+                        let index = if index < 0 {
+                            vertices.len() as i64 + index
+                        } else {
+                            index - 1
+                        };
+                        vertices
+                            .get(usize::try_from(index).map_err(|_| invalid(token))?)
+                            .copied()
+                            .ok_or_else(|| invalid(token))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                for i in 2..face.len() {
+                    push_triangle(&mut mesh, [face[0], face[i - 1], face[i]]);
+                }
+            }
+            _ => (),
+        }
+    }
+    Ok(mesh)
+}
+
+// Reads a binary or ASCII STL file. Facet normals are recomputed from the
+// winding.
+pub fn read_stl<R: Read>(mut reader: R) -> Result<IndexedMesh, ImportError> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let mut mesh = IndexedMesh::default();
+
+    // ASCII files start with "solid", but so do some binary ones; the size
+    // of a binary file is known from its header.
+    if bytes.len() >= 84 {
+        let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+        if count.checked_mul(50).and_then(|e| e.checked_add(84)) == Some(bytes.len()) {
+            for facet in bytes[84..].chunks_exact(50) {
+                let value = |i: usize| {
+                    let offset = 12 + i * 4;
+                    f32::from_le_bytes([
+                        facet[offset],
+                        facet[offset + 1],
+                        facet[offset + 2],
+                        facet[offset + 3],
+                    ])
+                };
+                let vertex =
+                    |i: usize| Vector3::new(value(i * 3), value(i * 3 + 1), value(i * 3 + 2));
+                push_triangle(&mut mesh, [vertex(0), vertex(1), vertex(2)]);
+            }
+            return Ok(mesh);
+        }
+    }
+
+    let text = std::str::from_utf8(&bytes).map_err(|_| invalid("binary STL"))?;
+    if !text.trim_start().starts_with("solid") {
+        return Err(invalid("solid"));
+    }
+    let mut facet = Vec::new();
+    for line in text.lines() {
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("vertex") => {
+                let values = tokens
+                    .map(|e| e.parse::<f32>().map_err(|_| invalid(e)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if values.len() != 3 {
+                    return Err(invalid(line));
+                }
+                facet.push(Vector3::new(values[0], values[1], values[2]));
+            }
+            Some("endloop") => {
+                for i in 2..facet.len() {
+                    push_triangle(&mut mesh, [facet[0], facet[i - 1], facet[i]]);
+                }
+                facet.clear();
+            }
+            _ => (),
+        }
+    }
+    Ok(mesh)
+}
+
+fn to_ldraw(v: &Vector3, options: &MeshImportOptions) -> Vector3 {
+    let v = v * options.scale;
+    // Both are rotations, so the winding stays as it is.
+    match options.up {
+        UpAxis::Y => Vector3::new(v.x, -v.y, -v.z),
+        UpAxis::Z => Vector3::new(v.x, -v.z, v.y),
+    }
+}
+
+// The fourth corner of a quad made from triangle `t` and its neighbour
+// `other` across edge `t[i]`-`t[i + 1]`, if the two are coplanar and form a
+// convex quad. Corners come back in winding order.
+fn quad_with(t: &[Vector3; 3], i: usize, other: &[Vector3; 3]) -> Option<[Vector3; 4]> {
+    let normal = |t: &[Vector3; 3]| (t[1] - t[0]).cross(t[2] - t[0]).normalize();
+    let n = normal(t);
+    if n.dot(normal(other)) < COPLANAR_COS {
+        return None;
+    }
+    let (a, b) = (t[i], t[(i + 1) % 3]);
+    let key = edge_key(&a, &b);
+    let apex = (0..3)
+        .find(|&j| edge_key(&other[j], &other[(j + 1) % 3]) == key)
+        .map(|j| other[(j + 2) % 3])?;
+    let quad = [a, apex, b, t[(i + 2) % 3]];
+    let convex = (0..4).all(|j| {
+        let (p, c, q) = (quad[j], quad[(j + 1) % 4], quad[(j + 2) % 4]);
+        (c - p).cross(q - c).dot(n) > 0.0
+    });
+    convex.then_some(quad)
+}
+
+fn point(v: &Vector3) -> ldraw::Vector4 {
+    v.extend(1.0)
+}
+
+// Turns an imported mesh into a part, BFC certified counter-clockwise.
+pub fn mesh_to_document(
+    mesh: &IndexedMesh,
+    name: &str,
+    description: &str,
+    options: &MeshImportOptions,
+) -> Document {
+    let triangles = mesh
+        .indices
+        .chunks_exact(3)
+        .map(|t| [t[0], t[1], t[2]].map(|e| to_ldraw(&mesh.positions[e as usize], options)))
+        .filter(|t| (t[1] - t[0]).cross(t[2] - t[0]).magnitude2() > f32::EPSILON)
+        .collect::<Vec<_>>();
+
+    let mut commands = Vec::new();
+    let mut merged = vec![false; triangles.len()];
+    if options.quads {
+        let mut lookup: HashMap<_, Vec<usize>> = HashMap::new();
+        for (i, t) in triangles.iter().enumerate() {
+            for j in 0..3 {
+                lookup
+                    .entry(edge_key(&t[j], &t[(j + 1) % 3]))
+                    .or_default()
+                    .push(i);
+            }
+        }
+        for (i, t) in triangles.iter().enumerate() {
+            if merged[i] {
+                continue;
+            }
+            let quad = (0..3).find_map(|j| {
+                let neighbours = &lookup[&edge_key(&t[j], &t[(j + 1) % 3])];
+                neighbours
+                    .iter()
+                    .filter(|&&k| k != i && !merged[k])
+                    .find_map(|&k| Some((k, quad_with(t, j, &triangles[k])?)))
+            });
+            if let Some((k, [a, b, c, d])) = quad {
+                merged[i] = true;
+                merged[k] = true;
+                commands.push(Command::Quad(Quad {
+                    color: options.color.clone(),
+                    a: point(&a),
+                    b: point(&b),
+                    c: point(&c),
+                    d: point(&d),
+                }));
+            }
+        }
+    }
+    for (t, _) in triangles.iter().zip(merged).filter(|(_, merged)| !merged) {
+        commands.push(Command::Triangle(Triangle {
+            color: options.color.clone(),
+            a: point(&t[0]),
+            b: point(&t[1]),
+            c: point(&t[2]),
+        }));
+    }
+
+    if let Some(angle) = options.edge_angle {
+        let flat = IndexedMesh {
+            positions: triangles.iter().flatten().copied().collect(),
+            indices: (0..triangles.len() as u32 * 3).collect(),
+            ..Default::default()
+        };
+        for (a, b) in FaceEdges::from_meshes([&flat]).features(angle).segments() {
+            commands.push(Command::Line(Line {
+                color: ColorReference::Complement,
+                a: point(&a),
+                b: point(&b),
+            }));
+        }
+    }
+
+    DocumentBuilder::new(name, description, "")
+        .commands(commands)
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use ldraw::{elements::Command, Vector3};
+
+    use super::{mesh_to_document, read_obj, read_stl, MeshImportOptions, UpAxis};
+
+    // A unit cube in millimetres, as quads.
+    const CUBE: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+                        v 0 0 1\nv 1 0 1\nv 1 1 1\nv 0 1 1\n\
+                        f 1 4 3 2\nf 5 6 7 8\nf 1 2 6 5\n\
+                        f 2 3 7 6\nf 3 4 8 7\nf 4/1 1/2 5/3 8/4\n";
+
+    #[test]
+    fn test_read_obj() {
+        let mesh = read_obj(CUBE.as_bytes()).unwrap();
+        assert_eq!(mesh.triangle_count(), 12);
+        // The bottom face points down.
+        assert_eq!(mesh.normals[0], Vector3::new(0.0, 0.0, -1.0));
+        assert!(read_obj("f 1 2 3\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_read_stl() {
+        let ascii = "solid test\n  facet normal 0 0 1\n    outer loop\n      \
+                     vertex 0 0 0\n      vertex 1 0 0\n      vertex 0 1 0\n    \
+                     endloop\n  endfacet\nendsolid test\n";
+        let mesh = read_stl(ascii.as_bytes()).unwrap();
+        assert_eq!(mesh.triangle_count(), 1);
+        assert_eq!(mesh.positions[2], Vector3::new(0.0, 1.0, 0.0));
+
+        let mut binary = vec![0u8; 80];
+        binary.extend_from_slice(&1u32.to_le_bytes());
+        for value in [
+            0.0f32, 0.0, 1.0, 0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 2.0, 0.0,
+        ] {
+            binary.extend_from_slice(&value.to_le_bytes());
+        }
+        binary.extend_from_slice(&[0, 0]);
+        let mesh = read_stl(&binary[..]).unwrap();
+        assert_eq!(mesh.positions[1], Vector3::new(2.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_mesh_to_document() {
+        let mesh = read_obj(CUBE.as_bytes()).unwrap();
+        let options = MeshImportOptions {
+            scale: 20.0,
+            ..Default::default()
+        };
+        let document = mesh_to_document(&mesh, "cube.dat", "Cube", &options);
+        let count = |f: fn(&Command) -> bool| document.commands.iter().filter(|e| f(e)).count();
+        assert_eq!(count(|e| matches!(e, Command::Quad(_))), 6);
+        assert_eq!(count(|e| matches!(e, Command::Triangle(_))), 0);
+        assert_eq!(count(|e| matches!(e, Command::Line(_))), 12);
+
+        // +Y up turns into -Y up.
+        let top = document.commands.iter().find_map(|e| match e {
+            Command::Quad(q) if q.a.y == -20.0 && q.b.y == -20.0 && q.c.y == -20.0 => Some(q),
+            _ => None,
+        });
+        assert!(top.is_some());
+
+        let options = MeshImportOptions {
+            up: UpAxis::Z,
+            quads: false,
+            edge_angle: None,
+            ..Default::default()
+        };
+        let document = mesh_to_document(&mesh, "cube.dat", "Cube", &options);
+        assert_eq!(document.commands.len(), 12);
+    }
+}
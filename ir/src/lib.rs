@@ -7,11 +7,30 @@ use std::{
 use ldraw::color::{ColorReference, MaterialRegistry};
 use serde::{Deserialize, Serialize};
 
+pub mod analysis;
+pub mod asset;
+pub mod bvh;
+pub mod cache;
+pub mod collision;
+pub mod connectivity;
 pub mod constraints;
+pub mod decimate;
 pub mod document;
 pub mod editor;
+pub mod explode;
+pub mod export;
+pub mod flex;
 pub mod geometry;
+pub mod import;
+pub mod lod;
+pub mod mesh;
+pub mod outline;
 pub mod part;
+pub mod section;
+pub mod substitution;
+#[cfg(test)]
+pub(crate) mod test_support;
+pub mod texture;
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct MeshGroup {
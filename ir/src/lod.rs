@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+
+use cgmath::{InnerSpace, Rad};
+use ldraw::{
+    color::MaterialRegistry, document::MultipartDocument, library::ResolutionResult, PartAlias,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    geometry::BoundingBox3,
+    mesh::{bake_document_with_options, BakeOptions, BakedPart},
+    substitution::{PrimitiveResolution, SubstitutionTable},
+};
+
+// One detail level: used while the part covers at least `min_screen_size`
+// pixels on screen, measured across its bounding box.
+#[derive(Clone, Debug)]
+pub struct LodLevelOptions {
+    pub min_screen_size: f32,
+    pub options: BakeOptions,
+}
+
+// Detail levels to generate, most detailed first. The default drops to
+// low resolution primitives and half the triangles below 64 pixels.
+#[derive(Clone, Debug)]
+pub struct LodOptions {
+    pub levels: Vec<LodLevelOptions>,
+}
+
+impl Default for LodOptions {
+    // Full detail up close, then 8-segment primitives and simplified studs.
+    fn default() -> Self {
+        LodOptions {
+            levels: vec![
+                LodLevelOptions {
+                    min_screen_size: 64.0,
+                    options: BakeOptions::default(),
+                },
+                LodLevelOptions {
+                    min_screen_size: 0.0,
+                    options: BakeOptions {
+                        substitutions: SubstitutionTable::with_resolution(PrimitiveResolution::Low),
+                        decimate: Some(0.5),
+                        ..Default::default()
+                    },
+                },
+            ],
+        }
+    }
+}
+
+impl LodOptions {
+    // Every substitution table used by the levels, so their substitutes can
+    // be resolved up front.
+    pub fn substitutions(&self) -> impl Iterator<Item = &SubstitutionTable> {
+        self.levels.iter().map(|e| &e.options.substitutions)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LodLevel {
+    pub min_screen_size: f32,
+    pub part: BakedPart,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LodPart {
+    pub levels: Vec<LodLevel>,
+}
+
+impl LodPart {
+    // The level to draw for a part spanning `screen_size` pixels. Parts
+    // smaller than every threshold get the coarsest level.
+    pub fn select(&self, screen_size: f32) -> &BakedPart {
+        self.levels
+            .iter()
+            .find(|e| screen_size >= e.min_screen_size)
+            .or_else(|| self.levels.last())
+            .map(|e| &e.part)
+            .expect("LodPart without levels")
+    }
+
+    pub fn finest(&self) -> &BakedPart {
+        &self.levels[0].part
+    }
+}
+
+// Approximate on-screen size in pixels of a bounding box `distance` LDU
+// away from a perspective camera with vertical field of view `fov_y`.
+pub fn projected_size(
+    bounding_box: &BoundingBox3,
+    distance: f32,
+    fov_y: Rad<f32>,
+    viewport_height: f32,
+) -> f32 {
+    let diameter = (bounding_box.max - bounding_box.min).magnitude();
+    if distance <= 0.0 {
+        return f32::INFINITY;
+    }
+    diameter / (2.0 * distance * (fov_y.0 * 0.5).tan()) * viewport_height
+}
+
+// Bakes every part `document` depends on once per level of `lod`. Levels
+// substituting primitives need those resolved beforehand, see
+// `SubstitutionTable::dependency_document`; otherwise they fall back to
+// the original primitives.
+pub fn bake_document_lod(
+    document: &MultipartDocument,
+    resolutions: &ResolutionResult,
+    materials: &MaterialRegistry,
+    lod: &LodOptions,
+) -> HashMap<PartAlias, LodPart> {
+    let mut parts: HashMap<PartAlias, LodPart> = HashMap::new();
+
+    for level in lod.levels.iter() {
+        let baked = bake_document_with_options(document, resolutions, materials, &level.options);
+        for (alias, part) in baked {
+            parts
+                .entry(alias)
+                .or_insert_with(|| LodPart { levels: Vec::new() })
+                .levels
+                .push(LodLevel {
+                    min_screen_size: level.min_screen_size,
+                    part,
+                });
+        }
+    }
+
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        geometry::BoundingBox3,
+        mesh::BakedPart,
+        part::{EdgeBufferBuilder, OptionalEdgeBufferBuilder},
+    };
+
+    use super::{LodLevel, LodPart};
+
+    fn level(min_screen_size: f32) -> LodLevel {
+        LodLevel {
+            min_screen_size,
+            part: BakedPart {
+                groups: Vec::new(),
+                edges: EdgeBufferBuilder::default(),
+                optional_edges: OptionalEdgeBufferBuilder::default(),
+                bounding_box: BoundingBox3::zero(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_lod_select() {
+        let part = LodPart {
+            levels: vec![level(128.0), level(32.0), level(8.0)],
+        };
+        let index = |size: f32| {
+            let selected = part.select(size);
+            part.levels
+                .iter()
+                .position(|e| std::ptr::eq(&e.part, selected))
+                .unwrap()
+        };
+
+        assert_eq!(index(500.0), 0);
+        assert_eq!(index(128.0), 0);
+        assert_eq!(index(100.0), 1);
+        assert_eq!(index(10.0), 2);
+        assert_eq!(index(1.0), 2);
+    }
+}
@@ -0,0 +1,818 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+};
+
+use cgmath::{ElementWise, InnerSpace, Rad};
+
+use ldraw::{
+    color::{ColorReference, MaterialRegistry},
+    document::MultipartDocument,
+    library::ResolutionResult,
+    PartAlias, Vector2, Vector3, Vector4,
+};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "parallel")]
+use ldraw::library::PartCache;
+
+use crate::{
+    decimate::decimate,
+    geometry::BoundingBox3,
+    part::{
+        bake_part_with_substitutions, EdgeBufferBuilder, MeshBufferBuilder,
+        OptionalEdgeBufferBuilder, PartBufferBuilder, PartBuilder,
+    },
+    substitution::SubstitutionTable,
+    texture::Texture,
+    MeshGroup,
+};
+
+// Texture coordinates further apart than this are a seam `weld` keeps.
+const UV_EPSILON: f32 = 1e-3;
+
+// Default distance, in LDU, below which `weld` merges vertices. Primitives
+// are stored with a few decimals, so their seams rarely line up exactly.
+pub const DEFAULT_WELD_EPSILON: f32 = 0.01;
+// Vertices meeting at a sharper angle than this keep separate normals.
+pub const DEFAULT_WELD_ANGLE: Rad<f32> = Rad(std::f32::consts::FRAC_PI_6);
+
+// Bucket size, in LDU, of the vertex lookup used for T-junction removal.
+const T_JUNCTION_CELL_SIZE: f32 = 4.0;
+
+// Triangle list with shared vertices. Every three entries of `indices` form
+// a triangle, wound counter-clockwise.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct IndexedMesh {
+    pub positions: Vec<Vector3>,
+    pub normals: Vec<Vector3>,
+    pub indices: Vec<u32>,
+    // Texture coordinates and tangents (`w` being the handedness of the
+    // bitangent) per vertex. Empty unless the mesh is textured.
+    #[serde(default)]
+    pub uvs: Vec<Vector2>,
+    #[serde(default)]
+    pub tangents: Vec<Vector4>,
+}
+
+impl IndexedMesh {
+    // Merges vertices whose position and normal are bitwise identical.
+    pub fn from_buffer(buffer: &MeshBufferBuilder) -> Self {
+        Self::from_range(buffer, 0..buffer.len(), buffer.is_textured())
+    }
+
+    fn from_range(buffer: &MeshBufferBuilder, range: Range<usize>, textured: bool) -> Self {
+        let mut mesh = IndexedMesh::default();
+        let mut lookup: HashMap<[u32; 12], u32> = HashMap::new();
+
+        for i in range {
+            let position = &buffer.vertices[i * 3..i * 3 + 3];
+            let normal = &buffer.normals[i * 3..i * 3 + 3];
+            let (uv, tangent) = if textured {
+                (
+                    &buffer.uvs[i * 2..i * 2 + 2],
+                    &buffer.tangents[i * 4..i * 4 + 4],
+                )
+            } else {
+                (&[0.0; 2][..], &[0.0; 4][..])
+            };
+            let mut key = [0; 12];
+            for (k, v) in key
+                .iter_mut()
+                .zip(position.iter().chain(normal).chain(uv).chain(tangent))
+            {
+                *k = v.to_bits();
+            }
+            let index = *lookup.entry(key).or_insert_with(|| {
+                mesh.positions
+                    .push(Vector3::new(position[0], position[1], position[2]));
+                mesh.normals
+                    .push(Vector3::new(normal[0], normal[1], normal[2]));
+                if textured {
+                    mesh.uvs.push(Vector2::new(uv[0], uv[1]));
+                    mesh.tangents
+                        .push(Vector4::new(tangent[0], tangent[1], tangent[2], tangent[3]));
+                }
+                (mesh.positions.len() - 1) as u32
+            });
+            mesh.indices.push(index);
+        }
+
+        mesh
+    }
+
+    pub fn is_textured(&self) -> bool {
+        !self.uvs.is_empty()
+    }
+
+    // Merges vertices closer than `epsilon` whose normals are within
+    // `angle` of each other, averaging their normals, then drops triangles
+    // that became degenerate or appear more than once.
+    pub fn weld(&mut self, epsilon: f32, angle: Rad<f32>) {
+        let cos = angle.0.cos();
+        let cell = |v: &Vector3| {
+            [
+                (v.x / epsilon).floor() as i64,
+                (v.y / epsilon).floor() as i64,
+                (v.z / epsilon).floor() as i64,
+            ]
+        };
+
+        let textured = self.is_textured();
+        let mut grid: HashMap<[i64; 3], Vec<u32>> = HashMap::new();
+        let mut positions: Vec<Vector3> = Vec::new();
+        let mut normals: Vec<Vector3> = Vec::new();
+        let mut uvs: Vec<Vector2> = Vec::new();
+        let mut tangents: Vec<Vector4> = Vec::new();
+        let mut remap = Vec::with_capacity(self.positions.len());
+        for (v, (position, normal)) in self.positions.iter().zip(self.normals.iter()).enumerate() {
+            let [x, y, z] = cell(position);
+            let existing = (-1..=1)
+                .flat_map(|dx| (-1..=1).flat_map(move |dy| (-1..=1).map(move |dz| (dx, dy, dz))))
+                .filter_map(|(dx, dy, dz)| grid.get(&[x + dx, y + dy, z + dz]))
+                .flatten()
+                .copied()
+                .find(|i| {
+                    let i = *i as usize;
+                    (positions[i] - position).magnitude() <= epsilon
+                        && normals[i].normalize().dot(*normal) >= cos
+                        // Vertices on a texture seam stay apart.
+                        && (!textured || (uvs[i] - self.uvs[v]).magnitude() <= UV_EPSILON)
+                });
+
+            let index = match existing {
+                Some(i) => {
+                    normals[i as usize] += *normal;
+                    i
+                }
+                None => {
+                    positions.push(*position);
+                    normals.push(*normal);
+                    if textured {
+                        uvs.push(self.uvs[v]);
+                        tangents.push(self.tangents[v]);
+                    }
+                    let i = (positions.len() - 1) as u32;
+                    grid.entry([x, y, z]).or_default().push(i);
+                    i
+                }
+            };
+            remap.push(index);
+        }
+
+        let mut seen = HashSet::new();
+        let mut indices = Vec::with_capacity(self.indices.len());
+        for triangle in self.indices.chunks_exact(3) {
+            let t = [
+                remap[triangle[0] as usize],
+                remap[triangle[1] as usize],
+                remap[triangle[2] as usize],
+            ];
+            if t[0] == t[1] || t[1] == t[2] || t[2] == t[0] {
+                continue;
+            }
+            // Rotate so the smallest index comes first; the winding is kept,
+            // so a back-to-back pair of triangles survives.
+            let first = (0..3).min_by_key(|i| t[*i]).unwrap();
+            let key = [t[first], t[(first + 1) % 3], t[(first + 2) % 3]];
+            if seen.insert(key) {
+                indices.extend_from_slice(&t);
+            }
+        }
+
+        for normal in normals.iter_mut() {
+            *normal = normal.normalize();
+        }
+        self.positions = positions;
+        self.normals = normals;
+        self.uvs = uvs;
+        self.tangents = tangents;
+        self.indices = indices;
+    }
+
+    // A vertex other than the end points lying on the edge from `a` to `b`,
+    // with its position along the edge.
+    fn find_on_edge(
+        &self,
+        grid: &HashMap<[i64; 3], Vec<u32>>,
+        a: u32,
+        b: u32,
+        epsilon: f32,
+    ) -> Option<(u32, f32)> {
+        let (pa, pb) = (self.positions[a as usize], self.positions[b as usize]);
+        let d = pb - pa;
+        let length2 = d.magnitude2();
+        if length2 <= epsilon * epsilon {
+            return None;
+        }
+
+        let cell = |v: f32| ((v - epsilon) / T_JUNCTION_CELL_SIZE).floor() as i64;
+        let cell_max = |v: f32| ((v + epsilon) / T_JUNCTION_CELL_SIZE).floor() as i64;
+        let (min, max) = (
+            [
+                cell(pa.x.min(pb.x)),
+                cell(pa.y.min(pb.y)),
+                cell(pa.z.min(pb.z)),
+            ],
+            [
+                cell_max(pa.x.max(pb.x)),
+                cell_max(pa.y.max(pb.y)),
+                cell_max(pa.z.max(pb.z)),
+            ],
+        );
+
+        let mut best: Option<(u32, f32)> = None;
+        for x in min[0]..=max[0] {
+            for y in min[1]..=max[1] {
+                for z in min[2]..=max[2] {
+                    for &i in grid.get(&[x, y, z]).into_iter().flatten() {
+                        let p = self.positions[i as usize];
+                        if (p - pa).magnitude() <= epsilon || (p - pb).magnitude() <= epsilon {
+                            continue;
+                        }
+                        let t = (p - pa).dot(d) / length2;
+                        if t <= 0.0 || t >= 1.0 || (pa + d * t - p).magnitude() > epsilon {
+                            continue;
+                        }
+                        if best.is_none_or(|(_, e)| t < e) {
+                            best = Some((i, t));
+                        }
+                    }
+                }
+            }
+        }
+        best
+    }
+
+    // Splits triangles wherever a vertex of another triangle lies on one of
+    // their edges, so neighbouring triangles share all their vertices and
+    // no cracks show along the seam. Run `weld` first so coincident
+    // vertices are shared. The vertices added take the position of the
+    // vertex on the edge and a normal interpolated along the edge.
+    pub fn remove_t_junctions(&mut self, epsilon: f32) {
+        let mut grid: HashMap<[i64; 3], Vec<u32>> = HashMap::new();
+        for (i, p) in self.positions.iter().enumerate() {
+            let key = [
+                (p.x / T_JUNCTION_CELL_SIZE).floor() as i64,
+                (p.y / T_JUNCTION_CELL_SIZE).floor() as i64,
+                (p.z / T_JUNCTION_CELL_SIZE).floor() as i64,
+            ];
+            grid.entry(key).or_default().push(i as u32);
+        }
+
+        let mut splits: HashMap<(u32, u32, u32), u32> = HashMap::new();
+        let mut work = self
+            .indices
+            .chunks_exact(3)
+            .map(|t| [t[0], t[1], t[2]])
+            .collect::<Vec<_>>();
+        let mut indices = Vec::with_capacity(self.indices.len());
+        'triangles: while let Some(t) = work.pop() {
+            for e in 0..3 {
+                let (a, b, c) = (t[e], t[(e + 1) % 3], t[(e + 2) % 3]);
+                let (p, along) = match self.find_on_edge(&grid, a, b, epsilon) {
+                    Some(e) => e,
+                    None => continue,
+                };
+
+                let key = (a.min(b), a.max(b), p);
+                let vertex = match splits.get(&key) {
+                    Some(e) => *e,
+                    None => {
+                        let (na, nb) = (self.normals[a as usize], self.normals[b as usize]);
+                        let normal = na + (nb - na) * along;
+                        self.positions.push(self.positions[p as usize]);
+                        self.normals.push(if normal.magnitude2() > 0.0 {
+                            normal.normalize()
+                        } else {
+                            na
+                        });
+                        if self.is_textured() {
+                            let (ua, ub) = (self.uvs[a as usize], self.uvs[b as usize]);
+                            let (ta, tb) = (self.tangents[a as usize], self.tangents[b as usize]);
+                            let tangent = ta.truncate() + (tb.truncate() - ta.truncate()) * along;
+                            self.uvs.push(ua + (ub - ua) * along);
+                            self.tangents.push(if tangent.magnitude2() > 0.0 {
+                                tangent.normalize().extend(ta.w)
+                            } else {
+                                ta
+                            });
+                        }
+                        let vertex = (self.positions.len() - 1) as u32;
+                        splits.insert(key, vertex);
+                        vertex
+                    }
+                };
+                work.push([a, vertex, c]);
+                work.push([vertex, b, c]);
+                continue 'triangles;
+            }
+            indices.extend_from_slice(&t);
+        }
+
+        self.indices = indices;
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+}
+
+// Geometry of one color group of a part. Groups colored with
+// `ColorReference::Current` take the color of the part reference placing
+// them; `bfc` tells whether back faces may be culled.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BakedMeshGroup {
+    pub group: MeshGroup,
+    pub mesh: IndexedMesh,
+    // Set for `!TEXMAP` geometry, whose mesh then carries UVs and tangents.
+    #[serde(default)]
+    pub texture: Option<Texture>,
+}
+
+impl BakedMeshGroup {
+    // Splits a buffer into its untextured vertices and one group per texture.
+    fn split(group: &MeshGroup, buffer: &MeshBufferBuilder) -> Vec<Self> {
+        let plain = buffer.textures.first().map_or(buffer.len(), |e| e.start);
+        let mut groups = Vec::new();
+        if plain > 0 {
+            groups.push(BakedMeshGroup {
+                group: group.clone(),
+                mesh: IndexedMesh::from_range(buffer, 0..plain, false),
+                texture: None,
+            });
+        }
+        for span in buffer.textures.iter() {
+            groups.push(BakedMeshGroup {
+                group: group.clone(),
+                mesh: IndexedMesh::from_range(buffer, span.start..span.start + span.span, true),
+                texture: Some(span.texture.clone()),
+            });
+        }
+        groups
+    }
+}
+
+// A part flattened into renderable geometry, with no dependency on a
+// graphics API.
+//
+// `groups` are ordered the way they should be drawn: opaque groups first,
+// translucent ones last. Edge colors are RGB triples, where `-1` stands for
+// the color of the part reference and `-2` for its edge color.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BakedPart {
+    pub groups: Vec<BakedMeshGroup>,
+    pub edges: EdgeBufferBuilder,
+    pub optional_edges: OptionalEdgeBufferBuilder,
+    pub bounding_box: BoundingBox3,
+}
+
+impl BakedPart {
+    // Converts the output of `bake_part`. Color groups are resolved against
+    // `materials` first so they can be told apart from each other.
+    pub fn from_builder(mut builder: PartBuilder, materials: &MaterialRegistry) -> Self {
+        builder.part_builder.resolve_colors(materials);
+        let buffers = builder.part_builder;
+
+        let mut groups = Vec::new();
+        for (bfc, buffer) in [
+            (true, &buffers.uncolored_mesh),
+            (false, &buffers.uncolored_without_bfc_mesh),
+        ] {
+            let group = MeshGroup {
+                color_ref: ColorReference::Current,
+                bfc,
+            };
+            groups.extend(BakedMeshGroup::split(&group, buffer));
+        }
+        for meshes in [&buffers.opaque_meshes, &buffers.translucent_meshes] {
+            let mut meshes = meshes
+                .iter()
+                .filter(|(_, buffer)| !buffer.is_empty())
+                .collect::<Vec<_>>();
+            meshes.sort_by_key(|(group, _)| *group);
+            for (group, buffer) in meshes {
+                groups.extend(BakedMeshGroup::split(group, buffer));
+            }
+        }
+
+        BakedPart {
+            groups,
+            edges: buffers.edges,
+            optional_edges: buffers.optional_edges,
+            bounding_box: builder.bounding_box,
+        }
+    }
+
+    pub fn weld(&mut self, epsilon: f32, angle: Rad<f32>) {
+        for group in self.groups.iter_mut() {
+            group.mesh.weld(epsilon, angle);
+        }
+    }
+
+    pub fn remove_t_junctions(&mut self, epsilon: f32) {
+        for group in self.groups.iter_mut() {
+            group.mesh.remove_t_junctions(epsilon);
+        }
+    }
+
+    // Decimates every group down to `ratio` of its triangles, see
+    // `decimate::decimate`. Edges are left alone.
+    pub fn decimate(&mut self, ratio: f32) {
+        for group in self.groups.iter_mut() {
+            let target = (group.mesh.triangle_count() as f32 * ratio.clamp(0.0, 1.0)) as usize;
+            decimate(&mut group.mesh, target);
+        }
+    }
+
+    // Shrinks the part towards the center of its bounding box so that
+    // `width` LDU of space opens up between neighbouring parts, the seams
+    // that make bricks read as separate pieces. Axes shorter than `width`
+    // are left alone.
+    pub fn apply_seam(&mut self, width: f32) {
+        let center = self.bounding_box.center();
+        let scale_axis = |length: f32| {
+            if length > width {
+                (length - width) / length
+            } else {
+                1.0
+            }
+        };
+        let scale = Vector3::new(
+            scale_axis(self.bounding_box.len_x()),
+            scale_axis(self.bounding_box.len_y()),
+            scale_axis(self.bounding_box.len_z()),
+        );
+        let transform = |v: Vector3| center + (v - center).mul_element_wise(scale);
+        let transform_flat = |buffer: &mut Vec<f32>, offset: bool| {
+            for v in buffer.chunks_exact_mut(3) {
+                let p = Vector3::new(v[0], v[1], v[2]);
+                let p = if offset {
+                    transform(p)
+                } else {
+                    p.mul_element_wise(scale)
+                };
+                v.copy_from_slice(&[p.x, p.y, p.z]);
+            }
+        };
+
+        for group in self.groups.iter_mut() {
+            for position in group.mesh.positions.iter_mut() {
+                *position = transform(*position);
+            }
+        }
+        transform_flat(&mut self.edges.vertices, true);
+        transform_flat(&mut self.optional_edges.vertices, true);
+        transform_flat(&mut self.optional_edges.controls_1, true);
+        transform_flat(&mut self.optional_edges.controls_2, true);
+        transform_flat(&mut self.optional_edges.direction, false);
+
+        self.bounding_box = BoundingBox3::new(
+            &transform(self.bounding_box.min),
+            &transform(self.bounding_box.max),
+        );
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.groups.iter().map(|e| e.mesh.triangle_count()).sum()
+    }
+
+    // Expands the part back into unindexed buffers, the form the renderer
+    // uploads. Features are not baked, so the result has none; the rotation
+    // center is the center of the bounding box.
+    pub fn to_builder(&self) -> PartBuilder {
+        let mut buffers = PartBufferBuilder::default();
+        for group in self.groups.iter() {
+            let buffer = match buffers.query_mesh(&group.group) {
+                Some(e) => e,
+                None => continue,
+            };
+            let mesh = &group.mesh;
+            for &index in mesh.indices.iter() {
+                let i = index as usize;
+                match &group.texture {
+                    Some(texture) if mesh.is_textured() => buffer.add_textured(
+                        &mesh.positions[i],
+                        &mesh.normals[i],
+                        &mesh.uvs[i],
+                        &mesh.tangents[i],
+                        texture,
+                    ),
+                    _ => buffer.add(&mesh.positions[i], &mesh.normals[i]),
+                }
+            }
+        }
+        buffers.edges = self.edges.clone();
+        buffers.optional_edges = self.optional_edges.clone();
+        PartBuilder::new(
+            buffers,
+            HashMap::new(),
+            self.bounding_box.clone(),
+            &self.bounding_box.center(),
+        )
+    }
+}
+
+// Bakes every part `document` depends on, keyed by the name it is referenced
+// with. `resolutions` is what `resolve_dependencies` returned for
+// `document`; parts it could not resolve are left out.
+pub fn bake_document(
+    document: &MultipartDocument,
+    resolutions: &ResolutionResult,
+    materials: &MaterialRegistry,
+) -> HashMap<PartAlias, BakedPart> {
+    bake_document_with_options(document, resolutions, materials, &BakeOptions::default())
+}
+
+// Quality settings for `bake_document_with_options`. The default bakes
+// the library as is, without any post-processing.
+#[derive(Clone, Debug, Default)]
+pub struct BakeOptions {
+    // Library files to swap for other versions, see `SubstitutionTable`.
+    pub substitutions: SubstitutionTable,
+    // Weld vertices closer than this many LDU, see `IndexedMesh::weld`.
+    pub weld: Option<f32>,
+    // Split triangles at T-junctions after welding.
+    pub remove_t_junctions: bool,
+    // Gap to leave between parts in LDU, see `BakedPart::apply_seam`.
+    pub seam_width: Option<f32>,
+    // Fraction of triangles to keep, see `BakedPart::decimate`. Implies
+    // welding.
+    pub decimate: Option<f32>,
+}
+
+// Bakes one part with the post-processing requested by `options`.
+pub(crate) fn bake_with_options(
+    alias: &PartAlias,
+    resolutions: &ResolutionResult,
+    materials: &MaterialRegistry,
+    options: &BakeOptions,
+) -> Option<BakedPart> {
+    let substitutions = Some(&options.substitutions).filter(|e| !e.is_empty());
+    let (part, local) = substitutions
+        .and_then(|e| e.get(alias))
+        .and_then(|e| resolutions.query(&e, true))
+        .or_else(|| resolutions.query(alias, true))?;
+    let builder = bake_part_with_substitutions(resolutions, None, substitutions, part, local);
+
+    let mut baked = BakedPart::from_builder(builder, materials);
+    let epsilon = options.weld.unwrap_or(DEFAULT_WELD_EPSILON);
+    if options.weld.is_some() || options.decimate.is_some() {
+        baked.weld(epsilon, DEFAULT_WELD_ANGLE);
+    }
+    if options.remove_t_junctions {
+        baked.remove_t_junctions(epsilon);
+    }
+    if let Some(ratio) = options.decimate {
+        baked.decimate(ratio);
+    }
+    if let Some(width) = options.seam_width {
+        baked.apply_seam(width);
+    }
+    Some(baked)
+}
+
+pub fn bake_document_with_options(
+    document: &MultipartDocument,
+    resolutions: &ResolutionResult,
+    materials: &MaterialRegistry,
+    options: &BakeOptions,
+) -> HashMap<PartAlias, BakedPart> {
+    document
+        .list_dependencies()
+        .into_iter()
+        .filter_map(|alias| {
+            let baked = bake_with_options(&alias, resolutions, materials, options)?;
+            Some((alias, baked))
+        })
+        .collect()
+}
+
+// Bakes every part (not primitive) in `cache` on the rayon thread pool.
+// Subfiles missing from the cache are skipped like in `bake_part`.
+#[cfg(feature = "parallel")]
+pub fn bake_all_parallel(
+    cache: &PartCache,
+    materials: &MaterialRegistry,
+    options: &BakeOptions,
+) -> HashMap<PartAlias, BakedPart> {
+    use rayon::prelude::*;
+
+    let resolutions = ResolutionResult::from_cache(cache);
+    let aliases = cache.parts().collect::<Vec<_>>();
+    aliases
+        .into_par_iter()
+        .filter_map(|alias| {
+            let baked = bake_with_options(alias, &resolutions, materials, options)?;
+            Some((alias.clone(), baked))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use ldraw::Vector3;
+
+    use cgmath::AbsDiffEq;
+    use ldraw::color::ColorReference;
+
+    use crate::{
+        geometry::BoundingBox3,
+        part::{EdgeBufferBuilder, MeshBufferBuilder, OptionalEdgeBufferBuilder},
+        MeshGroup,
+    };
+
+    use super::{BakedMeshGroup, BakedPart, IndexedMesh, DEFAULT_WELD_ANGLE, DEFAULT_WELD_EPSILON};
+
+    #[test]
+    fn test_indexed_mesh() {
+        let normal = Vector3::new(0.0, -1.0, 0.0);
+        let mut buffer = MeshBufferBuilder::default();
+        for vertex in [
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 1.0),
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 1.0),
+            Vector3::new(0.0, 0.0, 1.0),
+        ] {
+            buffer.add(&vertex, &normal);
+        }
+        // Same position, different normal: kept apart.
+        buffer.add(&Vector3::new(0.0, 0.0, 0.0), &Vector3::new(0.0, 1.0, 0.0));
+
+        let mesh = IndexedMesh::from_buffer(&buffer);
+        assert_eq!(mesh.vertex_count(), 5);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_weld() {
+        let up = Vector3::new(0.0, -1.0, 0.0);
+        let side = Vector3::new(1.0, 0.0, 0.0);
+        let mut mesh = IndexedMesh {
+            positions: vec![
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(1.0, 0.0, 0.0),
+                Vector3::new(0.0, 0.0, 1.0),
+                // The same triangle again, slightly off.
+                Vector3::new(0.001, 0.0, 0.0),
+                Vector3::new(1.0, 0.0, 0.001),
+                Vector3::new(0.0, 0.0, 1.0),
+                // A wall sharing an edge, but not its normals.
+                Vector3::new(1.0, 0.0, 0.0),
+                Vector3::new(1.0, -1.0, 0.0),
+                Vector3::new(1.0, 0.0, 1.0),
+            ],
+            normals: vec![up, up, up, up, up, up, side, side, side],
+            indices: (0..9).collect(),
+            ..Default::default()
+        };
+
+        mesh.weld(DEFAULT_WELD_EPSILON, DEFAULT_WELD_ANGLE);
+        assert_eq!(mesh.vertex_count(), 6);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(mesh.normals[1], up);
+        assert_eq!(mesh.normals[3], side);
+    }
+
+    #[test]
+    fn test_remove_t_junctions() {
+        let up = Vector3::new(0.0, -1.0, 0.0);
+        let mut mesh = IndexedMesh {
+            positions: vec![
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(2.0, 0.0, 0.0),
+                Vector3::new(0.0, 0.0, 2.0),
+                // Two triangles on the other side of the long edge, meeting
+                // halfway along it.
+                Vector3::new(1.0, 0.0, 0.0),
+                Vector3::new(1.0, 0.0, -1.0),
+            ],
+            normals: vec![up; 5],
+            indices: vec![0, 1, 2, 0, 4, 3, 3, 4, 1],
+            ..Default::default()
+        };
+
+        mesh.remove_t_junctions(DEFAULT_WELD_EPSILON);
+        assert_eq!(mesh.triangle_count(), 4);
+        assert_eq!(mesh.vertex_count(), 6);
+        assert_eq!(mesh.positions[5], Vector3::new(1.0, 0.0, 0.0));
+
+        let split = mesh
+            .indices
+            .chunks_exact(3)
+            .filter(|t| t.contains(&5))
+            .collect::<Vec<_>>();
+        assert_eq!(split.len(), 2);
+        assert!(split.iter().all(|t| t.contains(&2)));
+    }
+
+    #[test]
+    fn test_apply_seam() {
+        let mut part = BakedPart {
+            groups: vec![BakedMeshGroup {
+                group: MeshGroup {
+                    color_ref: ColorReference::Current,
+                    bfc: true,
+                },
+                mesh: IndexedMesh {
+                    positions: vec![
+                        Vector3::new(-20.0, 0.0, -10.0),
+                        Vector3::new(20.0, 0.0, -10.0),
+                        Vector3::new(20.0, 24.0, 10.0),
+                    ],
+                    normals: vec![Vector3::new(0.0, 0.0, 1.0); 3],
+                    indices: vec![0, 1, 2],
+                    ..Default::default()
+                },
+                texture: None,
+            }],
+            edges: EdgeBufferBuilder::default(),
+            optional_edges: OptionalEdgeBufferBuilder::default(),
+            bounding_box: BoundingBox3::new(
+                &Vector3::new(-20.0, 0.0, -10.0),
+                &Vector3::new(20.0, 24.0, 10.0),
+            ),
+        };
+        part.edges.vertices = vec![20.0, 24.0, 10.0];
+
+        part.apply_seam(0.5);
+        let positions = &part.groups[0].mesh.positions;
+        assert!(positions[0].abs_diff_eq(&Vector3::new(-19.75, 0.25, -9.75), 1e-4));
+        assert!(positions[2].abs_diff_eq(&Vector3::new(19.75, 23.75, 9.75), 1e-4));
+        assert_eq!(part.edges.vertices, vec![19.75, 23.75, 9.75]);
+        assert!((part.bounding_box.len_x() - 39.5).abs() < 1e-4);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_bake_all_parallel() {
+        use std::{collections::HashMap, sync::Arc};
+
+        use cgmath::SquareMatrix;
+        use ldraw::{
+            color::MaterialRegistry,
+            document::{DocumentBuilder, MultipartDocument},
+            elements::{Command, PartReference, Triangle},
+            library::{PartCache, PartKind},
+            Matrix4, PartAlias, Vector4,
+        };
+
+        use super::{bake_all_parallel, BakeOptions};
+
+        let document = |name: &str, command: Command| {
+            Arc::new(MultipartDocument::new(
+                DocumentBuilder::new(name, name, "")
+                    .commands(vec![command])
+                    .build(),
+                HashMap::new(),
+            ))
+        };
+        let mut cache = PartCache::new();
+        cache.register(
+            PartKind::Primitive,
+            PartAlias::from("tri.dat"),
+            document(
+                "tri.dat",
+                Command::Triangle(Triangle {
+                    color: ColorReference::Current,
+                    a: Vector4::new(0.0, 0.0, 0.0, 1.0),
+                    b: Vector4::new(1.0, 0.0, 0.0, 1.0),
+                    c: Vector4::new(0.0, 0.0, 1.0, 1.0),
+                }),
+            ),
+        );
+        for name in ["a.dat", "b.dat"] {
+            cache.register(
+                PartKind::Part,
+                PartAlias::from(name),
+                document(
+                    name,
+                    Command::PartReference(PartReference {
+                        color: ColorReference::Current,
+                        matrix: Matrix4::identity(),
+                        name: PartAlias::from("tri.dat"),
+                    }),
+                ),
+            );
+        }
+
+        let baked = bake_all_parallel(
+            &cache,
+            &MaterialRegistry::default(),
+            &BakeOptions::default(),
+        );
+        assert_eq!(baked.len(), 2);
+        assert_eq!(baked[&PartAlias::from("a.dat")].triangle_count(), 1);
+        assert_eq!(baked[&PartAlias::from("b.dat")].triangle_count(), 1);
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::{HashMap, HashSet};
+
+use cgmath::{Angle, InnerSpace, Rad, SquareMatrix};
+use ldraw::{Matrix4, Vector3};
+use serde::{Deserialize, Serialize};
+
+use crate::mesh::{BakedPart, IndexedMesh, DEFAULT_WELD_EPSILON};
+
+// Line segments, stored as pairs of consecutive vertices.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct LineList {
+    pub vertices: Vec<Vector3>,
+}
+
+impl LineList {
+    pub fn push(&mut self, a: Vector3, b: Vector3) {
+        self.vertices.push(a);
+        self.vertices.push(b);
+    }
+
+    // Number of segments.
+    pub fn len(&self) -> usize {
+        self.vertices.len() / 2
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    pub fn segments(&self) -> impl Iterator<Item = (Vector3, Vector3)> + '_ {
+        self.vertices.chunks_exact(2).map(|e| (e[0], e[1]))
+    }
+}
+
+// Where a part is looked at from, in the coordinates of the part.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum View {
+    // An orthographic camera looking along this direction.
+    Direction(Vector3),
+    // A perspective camera at this position.
+    Eye(Vector3),
+}
+
+impl View {
+    // Converts a view given in model coordinates into the coordinates of a
+    // part placed with `matrix`. Returns `None` if `matrix` is singular.
+    pub fn to_local(&self, matrix: &Matrix4) -> Option<View> {
+        let inverse = matrix.invert()?;
+        Some(match self {
+            View::Direction(e) => View::Direction((inverse * e.extend(0.0)).truncate()),
+            View::Eye(e) => View::Eye((inverse * e.extend(1.0)).truncate()),
+        })
+    }
+
+    // Whether a conditional line from `a` to `b` is drawn: only if both
+    // control points fall on the same side of it on screen.
+    pub fn shows_conditional(&self, a: &Vector3, b: &Vector3, c1: &Vector3, c2: &Vector3) -> bool {
+        // Normal of the plane through the line and the viewer.
+        let normal = match self {
+            View::Direction(e) => (b - a).cross(*e),
+            View::Eye(e) => (b - a).cross(a - e),
+        };
+        normal.dot(c1 - a) * normal.dot(c2 - a) > 0.0
+    }
+
+    fn faces(&self, normal: &Vector3, point: &Vector3) -> bool {
+        match self {
+            View::Direction(e) => normal.dot(*e) < 0.0,
+            View::Eye(e) => normal.dot(e - point) > 0.0,
+        }
+    }
+}
+
+type Key = [i64; 3];
+
+// Positions closer than the weld distance end up on the same key, so faces
+// of different color groups still find each other.
+fn key(v: &Vector3) -> Key {
+    [v.x, v.y, v.z].map(|e| (e / DEFAULT_WELD_EPSILON).round() as i64)
+}
+
+pub(crate) fn edge_key(a: &Vector3, b: &Vector3) -> (Key, Key) {
+    let (a, b) = (key(a), key(b));
+    if a <= b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct FaceEdge {
+    a: Vector3,
+    b: Vector3,
+    // Normals of the first two faces sharing the edge.
+    normals: [Vector3; 2],
+    faces: usize,
+}
+
+// Edges between the triangles of a part, together with the faces on
+// either side. Built once per part, so silhouettes can be extracted for
+// every new view cheaply.
+#[derive(Clone, Debug)]
+pub struct FaceEdges {
+    edges: Vec<FaceEdge>,
+}
+
+impl FaceEdges {
+    pub fn new(part: &BakedPart) -> Self {
+        FaceEdges::from_meshes(part.groups.iter().map(|e| &e.mesh))
+    }
+
+    pub fn from_meshes<'a, I: IntoIterator<Item = &'a IndexedMesh>>(meshes: I) -> Self {
+        let mut lookup = HashMap::new();
+        let mut edges: Vec<FaceEdge> = Vec::new();
+        for mesh in meshes {
+            let positions = &mesh.positions;
+            for t in mesh.indices.chunks_exact(3) {
+                let [a, b, c] = [t[0], t[1], t[2]].map(|e| positions[e as usize]);
+                let normal = (b - a).cross(c - a);
+                if normal.magnitude2() <= f32::EPSILON {
+                    continue;
+                }
+                let normal = normal.normalize();
+                for (a, b) in [(a, b), (b, c), (c, a)] {
+                    let k = edge_key(&a, &b);
+                    if k.0 == k.1 {
+                        continue;
+                    }
+                    let i = *lookup.entry(k).or_insert_with(|| {
+                        edges.push(FaceEdge {
+                            a,
+                            b,
+                            normals: [normal; 2],
+                            faces: 0,
+                        });
+                        edges.len() - 1
+                    });
+                    let edge = &mut edges[i];
+                    if edge.faces < 2 {
+                        edge.normals[edge.faces] = normal;
+                    }
+                    edge.faces += 1;
+                }
+            }
+        }
+        FaceEdges { edges }
+    }
+
+    // Edges where the surface folds by more than `angle`, plus open edges
+    // and edges shared by more than two faces.
+    pub fn features(&self, angle: Rad<f32>) -> LineList {
+        let cos = angle.cos();
+        let mut lines = LineList::default();
+        for edge in self.edges.iter() {
+            if edge.faces != 2 || edge.normals[0].dot(edge.normals[1]) < cos {
+                lines.push(edge.a, edge.b);
+            }
+        }
+        lines
+    }
+
+    // Edges between a face turned towards `view` and one turned away,
+    // i.e. the outline of the part as seen from there.
+    pub fn silhouette(&self, view: &View) -> LineList {
+        let mut lines = LineList::default();
+        for edge in self.edges.iter().filter(|e| e.faces == 2) {
+            let [n1, n2] = &edge.normals;
+            if view.faces(n1, &edge.a) != view.faces(n2, &edge.a) {
+                lines.push(edge.a, edge.b);
+            }
+        }
+        lines
+    }
+}
+
+// Type 2 lines of `part` together with the feature edges of its faces
+// sharper than `angle`. Feature edges already drawn by a line are left out.
+pub fn feature_edges(part: &BakedPart, angle: Rad<f32>) -> LineList {
+    let mut lines = LineList::default();
+    let mut drawn = HashSet::new();
+    for e in part.edges.vertices.chunks_exact(6) {
+        let (a, b) = (
+            Vector3::new(e[0], e[1], e[2]),
+            Vector3::new(e[3], e[4], e[5]),
+        );
+        drawn.insert(edge_key(&a, &b));
+        lines.push(a, b);
+    }
+    for (a, b) in FaceEdges::new(part).features(angle).segments() {
+        if !drawn.contains(&edge_key(&a, &b)) {
+            lines.push(a, b);
+        }
+    }
+    lines
+}
+
+// Type 5 lines of `part` that are visible from `view`, for drawing them
+// without the shader the renderer uses.
+pub fn conditional_lines(part: &BakedPart, view: &View) -> LineList {
+    let edges = &part.optional_edges;
+    let mut lines = LineList::default();
+    // Every line is stored as two vertices, with its control points repeated.
+    for (i, e) in edges.vertices.chunks_exact(6).enumerate() {
+        let (a, b) = (
+            Vector3::new(e[0], e[1], e[2]),
+            Vector3::new(e[3], e[4], e[5]),
+        );
+        let control =
+            |values: &[f32]| Vector3::new(values[i * 6], values[i * 6 + 1], values[i * 6 + 2]);
+        if view.shows_conditional(
+            &a,
+            &b,
+            &control(&edges.controls_1),
+            &control(&edges.controls_2),
+        ) {
+            lines.push(a, b);
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, Matrix4};
+    use ldraw::{color::ColorReference, Vector3};
+
+    use crate::test_support::cube;
+
+    use super::{conditional_lines, feature_edges, FaceEdges, View};
+
+    #[test]
+    fn test_feature_edges() {
+        let mut part = cube(1.0);
+        let edges = FaceEdges::new(&part);
+        assert_eq!(edges.features(Deg(30.0).into()).len(), 12);
+        assert_eq!(edges.features(Deg(120.0).into()).len(), 0);
+
+        // One line on an edge of the cube, one away from it.
+        part.edges.vertices = vec![
+            0.0, 0.0, 0.0, 1.0, 0.0, 0.0, //
+            0.0, 2.0, 0.0, 1.0, 2.0, 0.0,
+        ];
+        assert_eq!(feature_edges(&part, Deg(30.0).into()).len(), 13);
+    }
+
+    #[test]
+    fn test_silhouette() {
+        let edges = FaceEdges::new(&cube(1.0));
+        // Seen from a corner, the outline is a hexagon.
+        let corner = View::Direction(Vector3::new(1.0, 2.0, 3.0));
+        assert_eq!(edges.silhouette(&corner).len(), 6);
+
+        // Straight in front of one face, only its border remains.
+        let front = View::Eye(Vector3::new(0.5, 0.5, -10.0));
+        let silhouette = edges.silhouette(&front);
+        assert_eq!(silhouette.len(), 4);
+        assert!(silhouette.vertices.iter().all(|e| e.z == 0.0));
+
+        let moved = Matrix4::from_translation(Vector3::new(0.0, 0.0, 100.0));
+        let eye = View::Eye(Vector3::new(0.5, 0.5, 90.0)).to_local(&moved);
+        assert_eq!(eye, Some(front));
+        assert_eq!(corner.to_local(&moved), Some(corner));
+    }
+
+    #[test]
+    fn test_conditional_lines() {
+        // A convex corner running along y, between faces towards +x and +z.
+        let mut part = cube(1.0);
+        let (a, b) = (Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0));
+        part.optional_edges.add(
+            &a,
+            &b,
+            &Vector3::new(1.0, 0.0, 0.0),
+            &Vector3::new(0.0, 0.0, 1.0),
+            &ColorReference::Current,
+            &ColorReference::Current,
+        );
+
+        // Looking at the edge head on, the faces are on both sides of it.
+        let head_on = View::Direction(Vector3::new(1.0, 0.0, 1.0));
+        assert!(conditional_lines(&part, &head_on).is_empty());
+        // From the side, both faces lie beyond the edge; it is an outline.
+        let side = View::Direction(Vector3::new(1.0, 0.0, -1.0));
+        assert_eq!(conditional_lines(&part, &side).vertices, vec![a, b]);
+        let eye = View::Eye(Vector3::new(-10.0, 0.5, -10.0));
+        assert!(conditional_lines(&part, &eye).is_empty());
+        let eye = View::Eye(Vector3::new(-10.0, 0.5, 10.0));
+        assert_eq!(conditional_lines(&part, &eye).len(), 1);
+    }
+}
@@ -4,6 +4,7 @@ use std::{
     fmt::Debug,
     mem,
     ops::Deref,
+    rc::Rc,
     sync::Arc,
     vec::Vec,
 };
@@ -13,20 +14,62 @@ use kdtree::{distance::squared_euclidean, KdTree};
 use ldraw::{
     color::{ColorReference, MaterialRegistry},
     document::{Document, MultipartDocument},
-    elements::{BfcStatement, Command, Meta},
+    elements::{BfcStatement, Command, Meta, TexMap, TexMapProjection, TexMapStatement},
     library::ResolutionResult,
-    Matrix4, PartAlias, Vector3, Vector4, Winding,
+    Matrix4, PartAlias, Vector2, Vector3, Vector4, Winding,
 };
 use serde::{Deserialize, Serialize};
 
-use crate::{geometry::BoundingBox3, MeshGroup};
+use crate::{
+    geometry::BoundingBox3,
+    substitution::SubstitutionTable,
+    texture::{project, transform_projection, triangle_tangents, Texture},
+    MeshGroup,
+};
 
 const NORMAL_BLEND_THRESHOLD: Rad<f32> = Rad(f32::consts::FRAC_PI_6);
+// Edge lines are matched against face edges at this precision (in LDU).
+const CREASE_PRECISION: f32 = 1000.0;
+
+type EdgeKey = ([i64; 3], [i64; 3]);
+
+fn edge_key(a: &Vector3, b: &Vector3) -> EdgeKey {
+    let quantize = |v: &Vector3| {
+        [
+            (v.x * CREASE_PRECISION).round() as i64,
+            (v.y * CREASE_PRECISION).round() as i64,
+            (v.z * CREASE_PRECISION).round() as i64,
+        ]
+    };
+    let (a, b) = (quantize(a), quantize(b));
+    if a <= b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+// A run of vertices in a MeshBufferBuilder drawn with a texture.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TextureSpan {
+    pub start: usize,
+    pub span: usize,
+    pub texture: Texture,
+}
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct MeshBufferBuilder {
     pub vertices: Vec<f32>,
     pub normals: Vec<f32>,
+    // Two texture coordinates and a tangent with the handedness of the
+    // bitangent in w for every vertex, once any textured vertex was added.
+    // Vertices outside of `textures` get zeros.
+    #[serde(default)]
+    pub uvs: Vec<f32>,
+    #[serde(default)]
+    pub tangents: Vec<f32>,
+    #[serde(default)]
+    pub textures: Vec<TextureSpan>,
 }
 
 impl MeshBufferBuilder {
@@ -45,6 +88,41 @@ impl MeshBufferBuilder {
         self.normals.push(normal.x);
         self.normals.push(normal.y);
         self.normals.push(normal.z);
+        if !self.uvs.is_empty() {
+            self.uvs.extend(&[0.0; 2]);
+            self.tangents.extend(&[0.0; 4]);
+        }
+    }
+
+    pub fn add_textured(
+        &mut self,
+        vertex: &Vector3,
+        normal: &Vector3,
+        uv: &Vector2,
+        tangent: &Vector4,
+        texture: &Texture,
+    ) {
+        let start = self.len();
+        self.add(vertex, normal);
+        // Replaces the padding `add` left, if any.
+        self.uvs.resize(start * 2, 0.0);
+        self.tangents.resize(start * 4, 0.0);
+        self.uvs.extend(&[uv.x, uv.y]);
+        self.tangents
+            .extend(&[tangent.x, tangent.y, tangent.z, tangent.w]);
+
+        match self.textures.last_mut() {
+            Some(e) if e.start + e.span == start && e.texture == *texture => e.span += 1,
+            _ => self.textures.push(TextureSpan {
+                start,
+                span: 1,
+                texture: texture.clone(),
+            }),
+        }
+    }
+
+    pub fn is_textured(&self) -> bool {
+        !self.textures.is_empty()
     }
 }
 
@@ -252,10 +330,30 @@ enum FaceVertices {
     Quad([Vector3; 4]),
 }
 
+// A `!TEXMAP` projection moved into the coordinates of the part.
+#[derive(Debug, PartialEq)]
+struct FaceTexture {
+    projection: TexMapProjection,
+    texture: Texture,
+}
+
+impl FaceTexture {
+    fn new(texmap: &TexMap, matrix: &Matrix4) -> Self {
+        FaceTexture {
+            projection: transform_projection(&texmap.projection, matrix),
+            texture: Texture {
+                texture: texmap.texture.clone(),
+                glossmap: texmap.glossmap.clone(),
+            },
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 struct Face {
     vertices: FaceVertices,
     winding: Winding,
+    texture: Option<Rc<FaceTexture>>,
 }
 
 impl AbsDiffEq for FaceVertices {
@@ -294,6 +392,35 @@ impl AsRef<[Vector3]> for FaceVertices {
 
 const TRIANGLE_INDEX_ORDER: &[usize] = &[0, 1, 2];
 const QUAD_INDEX_ORDER: &[usize] = &[0, 1, 2, 2, 3, 0];
+const QUAD_INDEX_ORDER_ALT: &[usize] = &[0, 1, 3, 1, 2, 3];
+
+// Picks the diagonal to split a quad along. Splitting along the wrong one
+// folds a non-planar quad against its own outline, or for a concave quad
+// produces a triangle outside of it facing backwards, so take the split whose
+// worst triangle lines up best with the quad as a whole.
+fn quad_index_order(v: &[Vector3; 4]) -> &'static [usize] {
+    let normal = (0..4).map(|i| v[i].cross(v[(i + 1) % 4])).sum::<Vector3>();
+    let score = |order: &[usize]| {
+        order
+            .chunks_exact(3)
+            .map(|t| {
+                let n = (v[t[1]] - v[t[0]]).cross(v[t[2]] - v[t[0]]);
+                if n.magnitude2() < f32::default_epsilon() {
+                    // Degenerate triangles cover nothing, so they do no harm.
+                    1.0
+                } else {
+                    n.normalize().dot(normal.normalize())
+                }
+            })
+            .fold(f32::INFINITY, f32::min)
+    };
+
+    if score(QUAD_INDEX_ORDER_ALT) > score(QUAD_INDEX_ORDER) + f32::default_epsilon() {
+        QUAD_INDEX_ORDER_ALT
+    } else {
+        QUAD_INDEX_ORDER
+    }
+}
 
 struct FaceIterator<'a> {
     face: &'a [Vector3],
@@ -322,7 +449,7 @@ impl<'a> FaceVertices {
     pub fn triangles(&self, reverse: bool) -> FaceIterator {
         let order = match self {
             FaceVertices::Triangle(_) => TRIANGLE_INDEX_ORDER,
-            FaceVertices::Quad(_) => QUAD_INDEX_ORDER,
+            FaceVertices::Quad(v) => quad_index_order(v),
         };
 
         let iterator: Box<dyn Iterator<Item = &'static usize>> = if reverse {
@@ -344,6 +471,20 @@ impl<'a> FaceVertices {
         }
     }
 
+    // The two edges meeting at the corner closest to `vec`.
+    pub fn edges_at(&'a self, vec: &Vector3) -> [(&'a Vector3, &'a Vector3); 2] {
+        let v = self.as_ref();
+        let index = (0..v.len())
+            .min_by(|a, b| {
+                (v[*a] - vec)
+                    .magnitude2()
+                    .total_cmp(&(v[*b] - vec).magnitude2())
+            })
+            .unwrap();
+        let prev = (index + v.len() - 1) % v.len();
+        [self.edge(prev), self.edge(index)]
+    }
+
     pub fn contains(&self, vec: &Vector3) -> bool {
         match self {
             FaceVertices::Triangle(v) => {
@@ -365,12 +506,15 @@ impl<'a> FaceVertices {
     }
 
     pub fn normal(&self) -> Vector3 {
-        let r = match self {
-            FaceVertices::Triangle(v) => v.as_ref(),
-            FaceVertices::Quad(v) => v.as_ref(),
-        };
-
-        (r[1] - r[2]).cross(r[1] - r[0]).normalize()
+        match self {
+            FaceVertices::Triangle(r) => (r[1] - r[2]).cross(r[1] - r[0]).normalize(),
+            // Newell's method, which stays right for concave and slightly
+            // non-planar quads where any three corners could be misleading.
+            FaceVertices::Quad(r) => (0..4)
+                .map(|i| r[i].cross(r[(i + 1) % 4]))
+                .sum::<Vector3>()
+                .normalize(),
+        }
     }
 }
 
@@ -397,6 +541,7 @@ impl<'a> Adjacency {
 struct MeshBuilder {
     pub faces: HashMap<MeshGroup, Vec<Face>>,
     point_cloud: KdTree<f32, Adjacency, [f32; 3]>,
+    creases: HashSet<EdgeKey>,
 }
 
 impl MeshBuilder {
@@ -404,6 +549,55 @@ impl MeshBuilder {
         MeshBuilder {
             faces: HashMap::new(),
             point_cloud: KdTree::new(3),
+            creases: HashSet::new(),
+        }
+    }
+
+    // Type 2 lines mark hard edges; normals are never smoothed across them.
+    pub fn add_crease(&mut self, a: &Vector3, b: &Vector3) {
+        self.creases.insert(edge_key(a, b));
+    }
+
+    fn is_creased(&self, face: &FaceVertices, other: &FaceVertices, vertex: &Vector3) -> bool {
+        face.edges_at(vertex).iter().any(|(a, b)| {
+            self.creases.contains(&edge_key(a, b)) && other.contains(a) && other.contains(b)
+        })
+    }
+
+    // Averages the normals of the faces around `vertex` that meet `face` at a
+    // shallow enough angle and aren't separated from it by a hard edge.
+    fn smooth_normal(&self, face: &Face, vertex: &Vector3) -> Vector3 {
+        let normal = face.vertices.normal();
+
+        let r: &[f32; 3] = vertex.as_ref();
+        let adjacency = match self.point_cloud.iter_nearest(r, &squared_euclidean) {
+            Ok(mut matches) => match matches.next() {
+                Some((distance, adjacency)) if distance < f32::default_epsilon() => adjacency,
+                _ => return normal,
+            },
+            Err(_) => return normal,
+        };
+
+        let mut sum = normal;
+        for other in adjacency.faces.iter() {
+            if other
+                .vertices
+                .abs_diff_eq(&face.vertices, f32::default_epsilon())
+            {
+                continue;
+            }
+            let other_normal = other.vertices.normal();
+            if normal.angle(other_normal) < NORMAL_BLEND_THRESHOLD
+                && !self.is_creased(&face.vertices, &other.vertices, vertex)
+            {
+                sum += other_normal;
+            }
+        }
+
+        if sum.magnitude2() > f32::default_epsilon() {
+            sum.normalize()
+        } else {
+            normal
         }
     }
 
@@ -411,7 +605,7 @@ impl MeshBuilder {
         let list = self.faces.entry(group_key.clone()).or_insert_with(Vec::new);
         list.push(face.clone());
 
-        for vertex in face.vertices.triangles(false) {
+        for vertex in face.vertices.as_ref() {
             let r: &[f32; 3] = vertex.as_ref();
             let nearest = match self.point_cloud.iter_nearest_mut(r, &squared_euclidean) {
                 Ok(mut v) => match v.next() {
@@ -452,10 +646,17 @@ impl MeshBuilder {
             }
             let mesh = mesh.unwrap();
 
-            for face in faces.iter() {
-                let mut normal = face.vertices.normal();
-
-                for vertex in face.vertices.triangles(false) {
+            // Textured faces go last, grouped by texture, so that each
+            // texture covers a single run of vertices.
+            let mut faces = faces.iter().collect::<Vec<_>>();
+            faces.sort_by(|a, b| {
+                let key = |face: &Face| face.texture.as_ref().map(|e| e.texture.clone());
+                key(a).cmp(&key(b))
+            });
+
+            for face in faces {
+                let triangles = face.vertices.triangles(false).collect::<Vec<_>>();
+                for vertex in triangles.iter().copied() {
                     match bounding_box_min {
                         None => {
                             bounding_box_min = Some(*vertex);
@@ -488,23 +689,31 @@ impl MeshBuilder {
                             }
                         }
                     }
+                }
 
-                    let r: &[f32; 3] = vertex.as_ref();
-                    if let Ok(mut matches) = self.point_cloud.iter_nearest(r, &squared_euclidean) {
-                        if let Some(first_match) = matches.next() {
-                            if first_match.0 < f32::default_epsilon() {
-                                for face in first_match.1.faces.iter() {
-                                    let fnormal = face.vertices.normal();
-                                    if normal.angle(fnormal) < NORMAL_BLEND_THRESHOLD {
-                                        normal = (normal + fnormal) * 0.5;
-                                    }
-                                }
-                                normal = normal.normalize();
+                for t in triangles.chunks_exact(3) {
+                    let positions = [*t[0], *t[1], *t[2]];
+                    let normals = positions.map(|v| self.smooth_normal(face, &v));
+                    match &face.texture {
+                        Some(texture) => {
+                            let uvs = positions.map(|v| project(&texture.projection, &v));
+                            let tangents = triangle_tangents(&positions, &uvs, &normals);
+                            for i in 0..3 {
+                                mesh.add_textured(
+                                    &positions[i],
+                                    &normals[i],
+                                    &uvs[i],
+                                    &tangents[i],
+                                    &texture.texture,
+                                );
                             }
                         }
-                    };
-
-                    mesh.add(vertex, &normal);
+                        None => {
+                            for i in 0..3 {
+                                mesh.add(&positions[i], &normals[i]);
+                            }
+                        }
+                    }
                 }
             }
         }
@@ -521,15 +730,25 @@ impl MeshBuilder {
 struct PartBaker<'a> {
     resolutions: &'a ResolutionResult,
     enabled_features: Option<&'a HashSet<PartAlias>>,
+    substitutions: Option<&'a SubstitutionTable>,
 
     builder: PartBufferBuilder,
     mesh_builder: MeshBuilder,
     color_stack: Vec<ColorReference>,
+    texture_stack: Vec<Option<Rc<FaceTexture>>>,
     features: FeatureMap,
     bounding_box: BoundingBox3,
 }
 
 impl<'a> PartBaker<'a> {
+    fn query(&self, alias: &PartAlias, local: bool) -> Option<(Arc<MultipartDocument>, bool)> {
+        let substitution = self
+            .substitutions
+            .and_then(|e| e.get(alias))
+            .and_then(|e| self.resolutions.query(&e, local));
+        substitution.or_else(|| self.resolutions.query(alias, local))
+    }
+
     pub fn traverse<M: Deref<Target = MultipartDocument>>(
         &mut self,
         document: &Document,
@@ -540,32 +759,58 @@ impl<'a> PartBaker<'a> {
         local: bool,
     ) {
         let mut local_cull = true;
-        let mut winding = Winding::Ccw;
-        let bfc_certified = document.bfc.is_certified().unwrap_or(true);
+        // Files without `0 BFC CERTIFY` have no reliable winding and are drawn
+        // double-sided. That doesn't carry over to the files they reference,
+        // so a model can still have its certified parts culled.
+        let bfc_certified = document.bfc.is_certified().unwrap_or(false);
         let mut invert_next = false;
-
-        if bfc_certified {
-            winding = match document.bfc.get_winding() {
-                Some(e) => e,
-                None => Winding::Ccw,
-            } ^ invert;
-        }
+        // `!TEXMAP START` applies until `FALLBACK` or `END`, and to files
+        // referenced in between; `NEXT` to the following line only. Fallback
+        // geometry is for renderers without textures and skipped.
+        let mut texture_start: Option<Rc<FaceTexture>> = None;
+        let mut texture_next: Option<Rc<FaceTexture>> = None;
+        let mut in_fallback = false;
+        let inherited = self.texture_stack.last().cloned().flatten();
+
+        // Uncertified geometry is double-sided anyway, but it still gets
+        // mirrored so its normals keep facing the way they were drawn.
+        let mut winding = match document.bfc.get_winding() {
+            Some(e) => e,
+            None => Winding::Ccw,
+        } ^ invert;
 
         for cmd in document.commands.iter() {
+            // `0 !:` lines are regular geometry to renderers with textures.
+            let cmd = match cmd {
+                Command::Meta(Meta::TexMap(TexMapStatement::Geometry(inner))) => inner.as_ref(),
+                e => e,
+            };
+
+            // INVERTNEXT only applies if a type 1 line follows right away.
+            let inverting = invert_next;
+            let mut texture = texture_start.as_ref().or(inherited.as_ref());
+            let texturing_next;
+            if !matches!(cmd, Command::Meta(_)) {
+                invert_next = false;
+                texturing_next = texture_next.take();
+                if texturing_next.is_some() {
+                    texture = texturing_next.as_ref();
+                }
+                if in_fallback {
+                    continue;
+                }
+            }
+
             match cmd {
                 Command::PartReference(cmd) => {
                     let matrix = matrix * cmd.matrix;
                     let invert_child = if cmd.matrix.determinant() < -f32::default_epsilon() {
-                        invert == invert_next
+                        invert == inverting
                     } else {
-                        invert != invert_next
+                        invert != inverting
                     };
 
-                    let cull_next = if bfc_certified {
-                        cull && local_cull
-                    } else {
-                        false
-                    };
+                    let cull_next = cull && local_cull;
 
                     let color: ColorReference = match &cmd.color {
                         ColorReference::Current => self.color_stack.last().unwrap().clone(),
@@ -583,11 +828,13 @@ impl<'a> PartBaker<'a> {
                         .push((color.clone(), matrix));
                     } else if let Some(part) = parent.get_subpart(&cmd.name) {
                         self.color_stack.push(color);
+                        self.texture_stack.push(texture.cloned());
                         self.traverse(part, &*parent, matrix, cull_next, invert_child, local);
+                        self.texture_stack.pop();
                         self.color_stack.pop();
-                    } else if let Some((document, local)) = self.resolutions.query(&cmd.name, local)
-                    {
+                    } else if let Some((document, local)) = self.query(&cmd.name, local) {
                         self.color_stack.push(color);
+                        self.texture_stack.push(texture.cloned());
                         self.traverse(
                             &document.body,
                             Arc::clone(&document),
@@ -596,20 +843,18 @@ impl<'a> PartBaker<'a> {
                             invert_child,
                             local,
                         );
+                        self.texture_stack.pop();
                         self.color_stack.pop();
                     }
-
-                    invert_next = false;
                 }
                 Command::Line(cmd) => {
                     let top = self.color_stack.last().unwrap();
+                    let a = (matrix * cmd.a).truncate();
+                    let b = (matrix * cmd.b).truncate();
 
-                    self.builder
-                        .edges
-                        .add(&(matrix * cmd.a).truncate(), &cmd.color, top);
-                    self.builder
-                        .edges
-                        .add(&(matrix * cmd.b).truncate(), &cmd.color, top);
+                    self.builder.edges.add(&a, &cmd.color, top);
+                    self.builder.edges.add(&b, &cmd.color, top);
+                    self.mesh_builder.add_crease(&a, &b);
                 }
                 Command::OptionalLine(cmd) => {
                     let top = self.color_stack.last().unwrap();
@@ -637,6 +882,7 @@ impl<'a> PartBaker<'a> {
                                 (matrix * cmd.c).truncate(),
                             ]),
                             winding: Winding::Ccw,
+                            texture: texture.cloned(),
                         },
                         Winding::Cw => Face {
                             vertices: FaceVertices::Triangle([
@@ -645,6 +891,7 @@ impl<'a> PartBaker<'a> {
                                 (matrix * cmd.a).truncate(),
                             ]),
                             winding: Winding::Cw,
+                            texture: texture.cloned(),
                         },
                     };
 
@@ -674,6 +921,7 @@ impl<'a> PartBaker<'a> {
                                 (matrix * cmd.d).truncate(),
                             ]),
                             winding: Winding::Ccw,
+                            texture: texture.cloned(),
                         },
                         Winding::Cw => Face {
                             vertices: FaceVertices::Quad([
@@ -683,6 +931,7 @@ impl<'a> PartBaker<'a> {
                                 (matrix * cmd.a).truncate(),
                             ]),
                             winding: Winding::Cw,
+                            texture: texture.cloned(),
                         },
                     };
 
@@ -697,8 +946,25 @@ impl<'a> PartBaker<'a> {
 
                     self.mesh_builder.add(&category, face);
                 }
+                Command::Meta(Meta::TexMap(statement)) => match statement {
+                    TexMapStatement::Start(texmap) => {
+                        texture_start = Some(Rc::new(FaceTexture::new(texmap, &matrix)));
+                        in_fallback = false;
+                    }
+                    TexMapStatement::Next(texmap) => {
+                        texture_next = Some(Rc::new(FaceTexture::new(texmap, &matrix)));
+                    }
+                    TexMapStatement::Fallback => {
+                        in_fallback = texture_start.is_some();
+                    }
+                    TexMapStatement::End => {
+                        texture_start = None;
+                        in_fallback = false;
+                    }
+                    TexMapStatement::Geometry(_) => {}
+                },
                 Command::Meta(cmd) => {
-                    if let Meta::Bfc(statement) = cmd {
+                    if let (Meta::Bfc(statement), true) = (cmd, bfc_certified) {
                         match statement {
                             BfcStatement::InvertNext => {
                                 invert_next = true;
@@ -737,14 +1003,17 @@ impl<'a> PartBaker<'a> {
     pub fn new(
         resolutions: &'a ResolutionResult,
         enabled_features: Option<&'a HashSet<PartAlias>>,
+        substitutions: Option<&'a SubstitutionTable>,
     ) -> Self {
         let mut mb = PartBaker {
             resolutions,
             enabled_features,
+            substitutions,
 
             builder: PartBufferBuilder::default(),
             mesh_builder: MeshBuilder::new(),
             color_stack: Vec::new(),
+            texture_stack: Vec::new(),
             features: HashMap::new(),
             bounding_box: BoundingBox3::zero(),
         };
@@ -761,7 +1030,19 @@ pub fn bake_part<D: Deref<Target = MultipartDocument>>(
     document: D,
     local: bool,
 ) -> PartBuilder {
-    let mut baker = PartBaker::new(resolutions, enabled_features);
+    bake_part_with_substitutions(resolutions, enabled_features, None, document, local)
+}
+
+// Same as bake_part, swapping referenced files for their substitutes where
+// those were resolved.
+pub fn bake_part_with_substitutions<D: Deref<Target = MultipartDocument>>(
+    resolutions: &ResolutionResult,
+    enabled_features: Option<&HashSet<PartAlias>>,
+    substitutions: Option<&SubstitutionTable>,
+    document: D,
+    local: bool,
+) -> PartBuilder {
+    let mut baker = PartBaker::new(resolutions, enabled_features, substitutions);
 
     baker.traverse(
         &document.body,
@@ -773,3 +1054,277 @@ pub fn bake_part<D: Deref<Target = MultipartDocument>>(
     );
     baker.bake()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use cgmath::{AbsDiffEq, InnerSpace, SquareMatrix};
+    use ldraw::{
+        color::ColorReference,
+        document::{BfcCertification, Document, DocumentBuilder, MultipartDocument},
+        elements::{BfcStatement, Command, Meta, PartReference, TexMapStatement, Triangle},
+        library::ResolutionResult,
+        Matrix4, PartAlias, Vector3, Vector4, Winding,
+    };
+
+    use crate::{geometry::BoundingBox3, MeshGroup};
+
+    use super::{bake_part, Face, FaceVertices, MeshBuilder, PartBufferBuilder};
+
+    fn triangle() -> Command {
+        Command::Triangle(Triangle {
+            color: ColorReference::Current,
+            a: Vector4::new(0.0, 0.0, 0.0, 1.0),
+            b: Vector4::new(1.0, 0.0, 0.0, 1.0),
+            c: Vector4::new(0.0, 0.0, 1.0, 1.0),
+        })
+    }
+
+    fn reference(name: &str) -> Command {
+        Command::PartReference(PartReference {
+            color: ColorReference::Current,
+            matrix: Matrix4::identity(),
+            name: PartAlias::from(name),
+        })
+    }
+
+    fn document(name: &str, bfc: BfcCertification, commands: Vec<Command>) -> Document {
+        DocumentBuilder::new(name, name, "")
+            .bfc(bfc)
+            .commands(commands)
+            .build()
+    }
+
+    fn fold(crease: bool) -> Vec<Vector3> {
+        let group = MeshGroup {
+            color_ref: ColorReference::Current,
+            bfc: true,
+        };
+        let mut builder = MeshBuilder::new();
+        for vertices in [
+            [
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(1.0, 0.0, 0.0),
+                Vector3::new(0.0, 0.0, 1.0),
+            ],
+            [
+                Vector3::new(1.0, 0.0, 0.0),
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(0.0, 0.2, -1.0),
+            ],
+        ] {
+            builder.add(
+                &group,
+                Face {
+                    vertices: FaceVertices::Triangle(vertices),
+                    winding: Winding::Ccw,
+                    texture: None,
+                },
+            );
+        }
+        if crease {
+            builder.add_crease(&Vector3::new(1.0, 0.0, 0.0), &Vector3::new(0.0, 0.0, 0.0));
+        }
+
+        let mut buffer = PartBufferBuilder::default();
+        builder.bake(&mut buffer, &mut BoundingBox3::zero());
+        buffer
+            .uncolored_mesh
+            .normals
+            .chunks_exact(3)
+            .map(|e| Vector3::new(e[0], e[1], e[2]))
+            .collect()
+    }
+
+    #[test]
+    fn test_smooth_normals() {
+        let flat = Vector3::new(0.0, 1.0, 0.0);
+
+        let smooth = fold(false);
+        assert!(!smooth[0].abs_diff_eq(&flat, 1e-4) && !smooth[0].abs_diff_eq(&-flat, 1e-4));
+        assert!(smooth[0].abs_diff_eq(&smooth[4], 1e-4));
+        // The far corner has nothing to blend with.
+        assert!(smooth[2].abs_diff_eq(&flat, 1e-4) || smooth[2].abs_diff_eq(&-flat, 1e-4));
+        assert!((smooth[0].magnitude() - 1.0).abs() < 1e-4);
+
+        let creased = fold(true);
+        assert!(creased[0].abs_diff_eq(&creased[2], 1e-4));
+        assert!(creased[0].abs_diff_eq(&creased[1], 1e-4));
+    }
+
+    #[test]
+    fn test_bfc_certification() {
+        let certified = BfcCertification::Certify(Winding::Ccw);
+        let document = MultipartDocument::new(
+            document(
+                "model.ldr",
+                BfcCertification::NotApplicable,
+                vec![reference("certified.dat"), reference("uncertified.dat")],
+            ),
+            HashMap::from([
+                (
+                    PartAlias::from("certified.dat"),
+                    document("certified.dat", certified.clone(), vec![triangle()]),
+                ),
+                (
+                    PartAlias::from("uncertified.dat"),
+                    document(
+                        "uncertified.dat",
+                        BfcCertification::NoCertify,
+                        vec![triangle(), reference("certified.dat")],
+                    ),
+                ),
+            ]),
+        );
+
+        let part = bake_part(&ResolutionResult::new(), None, &document, false);
+        // Certified geometry is culled even under uncertified files.
+        assert_eq!(part.part_builder.uncolored_mesh.len(), 6);
+        assert_eq!(part.part_builder.uncolored_without_bfc_mesh.len(), 3);
+    }
+
+    fn baked_normal(bfc: BfcCertification, invert_next: bool, matrix: Matrix4) -> Vector3 {
+        let mut commands = Vec::new();
+        if invert_next {
+            commands.push(Command::Meta(Meta::Bfc(BfcStatement::InvertNext)));
+        }
+        commands.push(Command::PartReference(PartReference {
+            color: ColorReference::Current,
+            matrix,
+            name: PartAlias::from("sub.dat"),
+        }));
+        let document = MultipartDocument::new(
+            document(
+                "model.ldr",
+                BfcCertification::Certify(Winding::Ccw),
+                commands,
+            ),
+            HashMap::from([(
+                PartAlias::from("sub.dat"),
+                document("sub.dat", bfc, vec![triangle()]),
+            )]),
+        );
+
+        let part = bake_part(&ResolutionResult::new(), None, &document, false);
+        let buffer = &part.part_builder;
+        let normals = if buffer.uncolored_mesh.is_empty() {
+            &buffer.uncolored_without_bfc_mesh.normals
+        } else {
+            &buffer.uncolored_mesh.normals
+        };
+        Vector3::new(normals[0], normals[1], normals[2])
+    }
+
+    #[test]
+    fn test_mirrored_references() {
+        let down = Vector3::new(0.0, -1.0, 0.0);
+        let mirror = Matrix4::from_nonuniform_scale(-1.0, 1.0, 1.0);
+        let certified = BfcCertification::Certify(Winding::Ccw);
+
+        assert!(
+            baked_normal(certified.clone(), false, Matrix4::identity()).abs_diff_eq(&down, 1e-4)
+        );
+        assert!(baked_normal(certified.clone(), false, mirror).abs_diff_eq(&down, 1e-4));
+        assert!(
+            baked_normal(certified.clone(), true, Matrix4::identity()).abs_diff_eq(&-down, 1e-4)
+        );
+        assert!(baked_normal(certified, true, mirror).abs_diff_eq(&-down, 1e-4));
+        assert!(baked_normal(BfcCertification::NoCertify, false, mirror).abs_diff_eq(&down, 1e-4));
+    }
+
+    fn quad_triangles(vertices: [Vector3; 4]) -> Vec<Vector3> {
+        FaceVertices::Quad(vertices)
+            .triangles(false)
+            .copied()
+            .collect()
+    }
+
+    #[test]
+    fn test_quad_triangulation() {
+        let square = [
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 1.0),
+            Vector3::new(0.0, 0.0, 1.0),
+        ];
+        assert_eq!(
+            quad_triangles(square),
+            vec![square[0], square[1], square[2], square[2], square[3], square[0]]
+        );
+
+        // Concave at the second corner, so only the 1-3 diagonal stays inside.
+        let dart = [
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 0.5),
+            Vector3::new(2.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 2.0),
+        ];
+        let triangles = quad_triangles(dart);
+        assert_eq!(
+            triangles,
+            vec![dart[0], dart[1], dart[3], dart[1], dart[2], dart[3]]
+        );
+        let normal = FaceVertices::Quad(dart).normal();
+        for t in triangles.chunks_exact(3) {
+            let n = (t[1] - t[0]).cross(t[2] - t[0]).normalize();
+            assert!(n.abs_diff_eq(&normal, 1e-4));
+        }
+
+        // Lifting one corner of a square folds it; the split along the lifted
+        // corner's diagonal keeps both halves closer to the quad's normal.
+        let folded = [
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 1.0),
+            Vector3::new(0.0, 0.5, 1.0),
+        ];
+        assert_eq!(quad_triangles(folded)[2], folded[3]);
+    }
+
+    #[test]
+    fn test_texmap() {
+        let texmap = |e: &str| Command::Meta(Meta::TexMap(e.parse().unwrap()));
+        let document = MultipartDocument::new(
+            document(
+                "sticker.dat",
+                BfcCertification::NotApplicable,
+                vec![
+                    texmap("START PLANAR 0 0 0 2 0 0 0 0 2 logo.png"),
+                    Command::Meta(Meta::TexMap(TexMapStatement::Geometry(
+                        Box::new(triangle()),
+                    ))),
+                    texmap("FALLBACK"),
+                    triangle(),
+                    texmap("END"),
+                    texmap("NEXT PLANAR 0 0 0 1 0 0 0 0 1 other.png GLOSSMAP shine.png"),
+                    triangle(),
+                    triangle(),
+                ],
+            ),
+            HashMap::new(),
+        );
+
+        let part = bake_part(&ResolutionResult::new(), None, &document, false);
+        let mesh = &part.part_builder.uncolored_without_bfc_mesh;
+        // The fallback triangle is left out.
+        assert_eq!(mesh.len(), 9);
+        assert!(mesh.is_textured());
+
+        // Untextured vertices come first.
+        let spans = &mesh.textures;
+        assert_eq!(spans.len(), 2);
+        assert_eq!((spans[0].start, spans[0].span), (3, 3));
+        assert_eq!(spans[0].texture.texture, "logo.png");
+        assert_eq!((spans[1].start, spans[1].span), (6, 3));
+        assert_eq!(spans[1].texture.glossmap.as_deref(), Some("shine.png"));
+
+        let uvs = mesh.uvs.chunks_exact(2).collect::<Vec<_>>();
+        assert_eq!(uvs[3..6], [[0.0, 0.0], [0.5, 0.0], [0.0, 0.5]]);
+        assert_eq!(uvs[6..9], [[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]]);
+        for tangent in mesh.tangents.chunks_exact(4).skip(3) {
+            assert!(Vector3::new(tangent[0], tangent[1], tangent[2])
+                .abs_diff_eq(&Vector3::unit_x(), 1e-5));
+        }
+    }
+}
@@ -0,0 +1,727 @@
+use std::collections::HashMap;
+
+use cgmath::{InnerSpace, Matrix, Matrix3, SquareMatrix};
+use ldraw::{
+    color::ColorReference, document::MultipartDocument, Matrix4, PartAlias, Vector2, Vector3,
+};
+
+use crate::{
+    analysis::instances,
+    geometry::BoundingBox3,
+    mesh::{BakedMeshGroup, BakedPart, IndexedMesh, DEFAULT_WELD_EPSILON},
+    part::{EdgeBufferBuilder, OptionalEdgeBufferBuilder},
+    MeshGroup,
+};
+
+// A plane cutting through a model, made of the points `p` where
+// `normal.dot(p) == distance`. Everything on the side `normal` points to is
+// cut away.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClipPlane {
+    pub normal: Vector3,
+    pub distance: f32,
+}
+
+impl ClipPlane {
+    // Plane through `point`, cutting away the side `normal` points to.
+    pub fn new(normal: Vector3, point: Vector3) -> Self {
+        let normal = normal.normalize();
+        ClipPlane {
+            normal,
+            distance: normal.dot(point),
+        }
+    }
+
+    // Positive on the side that is cut away.
+    pub fn signed_distance(&self, point: &Vector3) -> f32 {
+        self.normal.dot(*point) - self.distance
+    }
+
+    // The same plane in the coordinates of a part placed with `matrix`.
+    // Returns `None` if `matrix` is singular.
+    pub fn to_local(&self, matrix: &Matrix4) -> Option<Self> {
+        if !matrix.is_invertible() {
+            return None;
+        }
+        let linear = Matrix3::from_cols(
+            matrix.x.truncate(),
+            matrix.y.truncate(),
+            matrix.z.truncate(),
+        );
+        let normal = linear.transpose() * self.normal;
+        let length = normal.magnitude();
+        Some(ClipPlane {
+            normal: normal / length,
+            distance: (self.distance - self.normal.dot(matrix.w.truncate())) / length,
+        })
+    }
+}
+
+type Key = [i64; 3];
+
+fn key(v: &Vector3) -> Key {
+    [v.x, v.y, v.z].map(|e| (e / DEFAULT_WELD_EPSILON).round() as i64)
+}
+
+// Copies the vertices of a mesh on the kept side of a plane, and creates new
+// ones where its edges cross the plane.
+struct Clipper<'a> {
+    source: &'a IndexedMesh,
+    distances: Vec<f32>,
+    mesh: IndexedMesh,
+    kept: Vec<Option<u32>>,
+    cuts: HashMap<(u32, u32), u32>,
+}
+
+impl<'a> Clipper<'a> {
+    fn new(source: &'a IndexedMesh, plane: &ClipPlane) -> Self {
+        Clipper {
+            source,
+            distances: source
+                .positions
+                .iter()
+                .map(|e| plane.signed_distance(e))
+                .collect(),
+            mesh: IndexedMesh::default(),
+            kept: vec![None; source.positions.len()],
+            cuts: HashMap::new(),
+        }
+    }
+
+    fn kept(&mut self, i: u32) -> u32 {
+        if let Some(e) = self.kept[i as usize] {
+            return e;
+        }
+        let (source, i) = (self.source, i as usize);
+        let index = self.mesh.positions.len() as u32;
+        self.mesh.positions.push(source.positions[i]);
+        self.mesh.normals.push(source.normals[i]);
+        if source.is_textured() {
+            self.mesh.uvs.push(source.uvs[i]);
+            self.mesh.tangents.push(source.tangents[i]);
+        }
+        self.kept[i] = Some(index);
+        index
+    }
+
+    fn cut(&mut self, a: u32, b: u32) -> u32 {
+        let (a, b) = (a.min(b), a.max(b));
+        if let Some(e) = self.cuts.get(&(a, b)) {
+            return *e;
+        }
+        let (source, a, b) = (self.source, a as usize, b as usize);
+        let t = self.distances[a] / (self.distances[a] - self.distances[b]);
+        let index = self.mesh.positions.len() as u32;
+        self.mesh
+            .positions
+            .push(source.positions[a] + (source.positions[b] - source.positions[a]) * t);
+        let normal = source.normals[a] + (source.normals[b] - source.normals[a]) * t;
+        self.mesh
+            .normals
+            .push(if normal.magnitude2() > f32::EPSILON {
+                normal.normalize()
+            } else {
+                source.normals[a]
+            });
+        if source.is_textured() {
+            self.mesh
+                .uvs
+                .push(source.uvs[a] + (source.uvs[b] - source.uvs[a]) * t);
+            let tangent = source.tangents[a].truncate()
+                + (source.tangents[b].truncate() - source.tangents[a].truncate()) * t;
+            self.mesh
+                .tangents
+                .push(if tangent.magnitude2() > f32::EPSILON {
+                    tangent.normalize().extend(source.tangents[a].w)
+                } else {
+                    source.tangents[a]
+                });
+        }
+        self.cuts.insert((a as u32, b as u32), index);
+        index
+    }
+
+    // Clips every triangle, returning the clipped mesh and the segments
+    // where triangles cross the plane, wound counter-clockwise around the
+    // plane normal for outward facing triangles.
+    fn clip(mut self, plane: &ClipPlane) -> (IndexedMesh, Vec<(Vector3, Vector3)>) {
+        let mut segments = Vec::new();
+        for t in self.source.indices.chunks_exact(3) {
+            let mut polygon = Vec::with_capacity(4);
+            let mut crossings = Vec::with_capacity(2);
+            for k in 0..3 {
+                let (a, b) = (t[k], t[(k + 1) % 3]);
+                let inside = self.distances[a as usize] <= 0.0;
+                if inside {
+                    polygon.push(self.kept(a));
+                }
+                if inside != (self.distances[b as usize] <= 0.0) {
+                    let cut = self.cut(a, b);
+                    polygon.push(cut);
+                    crossings.push(cut);
+                }
+            }
+
+            let positions = &self.mesh.positions;
+            for i in 1..polygon.len().saturating_sub(1) {
+                let [a, b, c] = [polygon[0], polygon[i], polygon[i + 1]];
+                let [pa, pb, pc] = [a, b, c].map(|e| positions[e as usize]);
+                if (pb - pa).cross(pc - pa).magnitude2() > f32::EPSILON {
+                    self.mesh.indices.extend([a, b, c]);
+                }
+            }
+
+            if let [p, q] = crossings[..] {
+                let (p, q) = (positions[p as usize], positions[q as usize]);
+                let [a, b, c] = [t[0], t[1], t[2]].map(|e| self.source.positions[e as usize]);
+                let direction = plane.normal.cross((b - a).cross(c - a));
+                if key(&p) != key(&q) {
+                    segments.push(if (q - p).dot(direction) >= 0.0 {
+                        (p, q)
+                    } else {
+                        (q, p)
+                    });
+                }
+            }
+        }
+        (self.mesh, segments)
+    }
+}
+
+// Joins segments into closed loops. Chains that do not close are dropped.
+fn loops(segments: &[(Vector3, Vector3, usize)]) -> Vec<Vec<(Vector3, usize)>> {
+    let mut by_start: HashMap<Key, Vec<usize>> = HashMap::new();
+    for (i, e) in segments.iter().enumerate() {
+        by_start.entry(key(&e.0)).or_default().push(i);
+    }
+
+    let mut used = vec![false; segments.len()];
+    let mut loops = Vec::new();
+    for first in 0..segments.len() {
+        if used[first] {
+            continue;
+        }
+        used[first] = true;
+        let start = key(&segments[first].0);
+        let mut chain = vec![first];
+        let mut end = key(&segments[first].1);
+        let closed = loop {
+            if end == start {
+                break true;
+            }
+            let next = by_start
+                .get(&end)
+                .and_then(|e| e.iter().find(|i| !used[**i]).copied());
+            match next {
+                Some(i) => {
+                    used[i] = true;
+                    chain.push(i);
+                    end = key(&segments[i].1);
+                }
+                None => break false,
+            }
+        };
+        if closed && chain.len() >= 3 {
+            loops.push(
+                chain
+                    .into_iter()
+                    .map(|i| (segments[i].0, segments[i].2))
+                    .collect(),
+            );
+        }
+    }
+    loops
+}
+
+fn cross(o: &Vector2, a: &Vector2, b: &Vector2) -> f32 {
+    (a.x - o.x) * (b.y - o.y) - (a.y - o.y) * (b.x - o.x)
+}
+
+fn signed_area(points: &[Vector2], polygon: &[usize]) -> f32 {
+    (0..polygon.len())
+        .map(|i| {
+            let (a, b) = (points[polygon[i]], points[polygon[(i + 1) % polygon.len()]]);
+            a.x * b.y - b.x * a.y
+        })
+        .sum::<f32>()
+        * 0.5
+}
+
+fn contains(points: &[Vector2], polygon: &[usize], p: &Vector2) -> bool {
+    let mut inside = false;
+    for i in 0..polygon.len() {
+        let (a, b) = (points[polygon[i]], points[polygon[(i + 1) % polygon.len()]]);
+        if (a.y > p.y) != (b.y > p.y) && p.x < a.x + (p.y - a.y) / (b.y - a.y) * (b.x - a.x) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+fn segments_cross(a: &Vector2, b: &Vector2, c: &Vector2, d: &Vector2) -> bool {
+    cross(a, b, c) * cross(a, b, d) < 0.0 && cross(c, d, a) * cross(c, d, b) < 0.0
+}
+
+// Joins a hole into the outer polygon through the nearest outer vertex that
+// can be reached without crossing any edge.
+fn bridge(points: &[Vector2], outer: &mut Vec<usize>, hole: &[usize], others: &[Vec<usize>]) {
+    let h = (0..hole.len())
+        .max_by(|a, b| points[hole[*a]].x.total_cmp(&points[hole[*b]].x))
+        .unwrap();
+    let from = points[hole[h]];
+    let crosses = |polygon: &[usize], to: &Vector2| {
+        (0..polygon.len()).any(|i| {
+            let (a, b) = (points[polygon[i]], points[polygon[(i + 1) % polygon.len()]]);
+            segments_cross(&from, to, &a, &b)
+        })
+    };
+
+    let mut candidates = (0..outer.len()).collect::<Vec<_>>();
+    candidates.sort_by(|a, b| {
+        let distance = |i: &usize| (points[outer[*i]] - from).magnitude2();
+        distance(a).total_cmp(&distance(b))
+    });
+    let o = candidates
+        .iter()
+        .copied()
+        .find(|i| {
+            let to = points[outer[*i]];
+            !crosses(outer, &to) && !others.iter().any(|e| crosses(e, &to))
+        })
+        .unwrap_or(candidates[0]);
+
+    let mut joined = outer[..=o].to_vec();
+    joined.extend(hole[h..].iter().chain(hole[..=h].iter()));
+    joined.extend(outer[o..].iter());
+    *outer = joined;
+}
+
+fn inside_triangle(p: &Vector2, a: &Vector2, b: &Vector2, c: &Vector2) -> bool {
+    cross(a, b, p) >= 0.0 && cross(b, c, p) >= 0.0 && cross(c, a, p) >= 0.0
+}
+
+// Ear clipping of a counter-clockwise polygon.
+fn ear_clip(points: &[Vector2], mut polygon: Vec<usize>) -> Vec<[usize; 3]> {
+    let mut triangles = Vec::new();
+    let mut i = 0;
+    let mut misses = 0;
+    while polygon.len() > 3 && misses <= polygon.len() {
+        let n = polygon.len();
+        let (a, b, c) = (polygon[(i + n - 1) % n], polygon[i], polygon[(i + 1) % n]);
+        let (pa, pb, pc) = (points[a], points[b], points[c]);
+        let turn = cross(&pa, &pb, &pc);
+        let ear = turn.abs() <= f32::EPSILON
+            || (turn > 0.0
+                && !polygon.iter().any(|e| {
+                    let p = points[*e];
+                    p != pa && p != pb && p != pc && inside_triangle(&p, &pa, &pb, &pc)
+                }));
+        if ear {
+            // Collinear corners are removed without leaving a triangle.
+            if turn > f32::EPSILON {
+                triangles.push([a, b, c]);
+            }
+            polygon.remove(i);
+            i %= polygon.len();
+            misses = 0;
+        } else {
+            i = (i + 1) % n;
+            misses += 1;
+        }
+    }
+    if let [a, b, c] = polygon[..] {
+        if cross(&points[a], &points[b], &points[c]) > 0.0 {
+            triangles.push([a, b, c]);
+        }
+    }
+    triangles
+}
+
+// Triangulates the area enclosed by `loops`, points lying in a plane with
+// normal `normal`. Loops wound counter-clockwise around the normal are
+// outlines, clockwise ones are holes in the outline around them.
+// Returns, per outline, the index of the loop and the triangles as indices
+// into the flattened points of all loops.
+pub fn triangulate_loops(
+    loops: &[Vec<Vector3>],
+    normal: &Vector3,
+) -> Vec<(usize, Vec<[usize; 3]>)> {
+    let other = if normal.x.abs() < 0.9 {
+        Vector3::unit_x()
+    } else {
+        Vector3::unit_y()
+    };
+    let u = normal.cross(other).normalize();
+    let v = normal.cross(u);
+    let points = loops
+        .iter()
+        .flatten()
+        .map(|e| Vector2::new(e.dot(u), e.dot(v)))
+        .collect::<Vec<_>>();
+
+    let mut offset = 0;
+    let mut polygons = Vec::new();
+    for e in loops.iter() {
+        polygons.push((offset..offset + e.len()).collect::<Vec<_>>());
+        offset += e.len();
+    }
+    let areas = polygons
+        .iter()
+        .map(|e| signed_area(&points, e))
+        .collect::<Vec<_>>();
+
+    // Every hole belongs to the smallest outline around it.
+    let mut holes: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (hole, polygon) in polygons.iter().enumerate() {
+        if areas[hole] >= 0.0 {
+            continue;
+        }
+        let outline = (0..polygons.len())
+            .filter(|i| areas[*i] > 0.0 && contains(&points, &polygons[*i], &points[polygon[0]]))
+            .min_by(|a, b| areas[*a].total_cmp(&areas[*b]));
+        if let Some(outline) = outline {
+            holes.entry(outline).or_default().push(hole);
+        }
+    }
+
+    let mut result = Vec::new();
+    for (i, polygon) in polygons.iter().enumerate() {
+        if areas[i] <= 0.0 {
+            continue;
+        }
+        let mut outer = polygon.clone();
+        let mut inner = holes
+            .remove(&i)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|e| polygons[e].clone())
+            .collect::<Vec<_>>();
+        let right = |e: &Vec<usize>| e.iter().map(|p| points[*p].x).fold(f32::MIN, f32::max);
+        inner.sort_by(|a, b| right(b).total_cmp(&right(a)));
+        for k in 0..inner.len() {
+            bridge(&points, &mut outer, &inner[k], &inner[k + 1..]);
+        }
+        result.push((i, ear_clip(&points, outer)));
+    }
+    result
+}
+
+fn clip_edges(edges: &EdgeBufferBuilder, plane: &ClipPlane) -> EdgeBufferBuilder {
+    let mut clipped = EdgeBufferBuilder::default();
+    for (v, c) in edges
+        .vertices
+        .chunks_exact(6)
+        .zip(edges.colors.chunks_exact(6))
+    {
+        let mut a = Vector3::new(v[0], v[1], v[2]);
+        let mut b = Vector3::new(v[3], v[4], v[5]);
+        let (da, db) = (plane.signed_distance(&a), plane.signed_distance(&b));
+        if da > 0.0 && db > 0.0 {
+            continue;
+        } else if da > 0.0 {
+            a = a + (b - a) * (da / (da - db));
+        } else if db > 0.0 {
+            b = a + (b - a) * (da / (da - db));
+        }
+        clipped.vertices.extend([a.x, a.y, a.z, b.x, b.y, b.z]);
+        clipped.colors.extend_from_slice(c);
+    }
+    clipped
+}
+
+fn clip_optional_edges(
+    edges: &OptionalEdgeBufferBuilder,
+    plane: &ClipPlane,
+) -> OptionalEdgeBufferBuilder {
+    let mut clipped = OptionalEdgeBufferBuilder::default();
+    for (i, v) in edges.vertices.chunks_exact(6).enumerate() {
+        let (a, b) = (
+            Vector3::new(v[0], v[1], v[2]),
+            Vector3::new(v[3], v[4], v[5]),
+        );
+        if plane.signed_distance(&a) > 0.0 || plane.signed_distance(&b) > 0.0 {
+            continue;
+        }
+        let range = i * 6..i * 6 + 6;
+        clipped.vertices.extend_from_slice(v);
+        clipped
+            .controls_1
+            .extend_from_slice(&edges.controls_1[range.clone()]);
+        clipped
+            .controls_2
+            .extend_from_slice(&edges.controls_2[range.clone()]);
+        clipped
+            .direction
+            .extend_from_slice(&edges.direction[range.clone()]);
+        clipped.colors.extend_from_slice(&edges.colors[range]);
+    }
+    clipped
+}
+
+// Cuts away the side of `plane` it points to from `part`, given in the
+// coordinates of the part, and closes the cut with flat caps. Each cap
+// takes the color of the faces around it, and its outline is added to the
+// edges. Only closed outlines can be capped; parts that are not watertight
+// may be left open. Returns `None` if nothing is left.
+pub fn section_part(part: &BakedPart, plane: &ClipPlane) -> Option<BakedPart> {
+    let mut groups = Vec::new();
+    let mut segments = Vec::new();
+    for (i, group) in part.groups.iter().enumerate() {
+        let (mesh, cut) = Clipper::new(&group.mesh, plane).clip(plane);
+        segments.extend(cut.into_iter().map(|(a, b)| (a, b, i)));
+        groups.push(BakedMeshGroup {
+            group: group.group.clone(),
+            mesh,
+            texture: group.texture.clone(),
+        });
+    }
+
+    // A loop gets the color of the group most of its outline comes from.
+    let loops = loops(&segments);
+    let colors = loops
+        .iter()
+        .map(|e| {
+            let mut lengths = vec![0.0; part.groups.len()];
+            for i in 0..e.len() {
+                lengths[e[i].1] += (e[(i + 1) % e.len()].0 - e[i].0).magnitude();
+            }
+            (0..lengths.len())
+                .max_by(|a, b| lengths[*a].total_cmp(&lengths[*b]))
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    let points = loops
+        .iter()
+        .map(|e| e.iter().map(|p| p.0).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let flattened = points.iter().flatten().copied().collect::<Vec<_>>();
+
+    let mut caps: Vec<IndexedMesh> = vec![IndexedMesh::default(); part.groups.len()];
+    let mut edges = clip_edges(&part.edges, plane);
+    for (outline, triangles) in triangulate_loops(&points, &plane.normal) {
+        let cap = &mut caps[colors[outline]];
+        let mut lookup = HashMap::new();
+        for i in triangles.into_iter().flatten() {
+            let index = *lookup.entry(i).or_insert_with(|| {
+                cap.positions.push(flattened[i]);
+                cap.normals.push(plane.normal);
+                cap.positions.len() as u32 - 1
+            });
+            cap.indices.push(index);
+        }
+    }
+    for (e, color) in points.iter().zip(colors.iter()) {
+        let top = &part.groups[*color].group.color_ref;
+        for i in 0..e.len() {
+            edges.add(&e[i], &ColorReference::Complement, top);
+            edges.add(&e[(i + 1) % e.len()], &ColorReference::Complement, top);
+        }
+    }
+
+    // Caps are drawn right after the group they take their color from.
+    let mut sectioned = Vec::new();
+    for (group, cap) in groups.into_iter().zip(caps) {
+        let color_ref = group.group.color_ref.clone();
+        if !group.mesh.is_empty() {
+            sectioned.push(group);
+        }
+        if !cap.is_empty() {
+            sectioned.push(BakedMeshGroup {
+                group: MeshGroup {
+                    color_ref,
+                    bfc: true,
+                },
+                mesh: cap,
+                texture: None,
+            });
+        }
+    }
+
+    let mut bounding_box = BoundingBox3::zero();
+    for p in sectioned.iter().flat_map(|e| e.mesh.positions.iter()) {
+        bounding_box.update_point(p);
+    }
+    for v in edges.vertices.chunks_exact(3) {
+        bounding_box.update_point(&Vector3::new(v[0], v[1], v[2]));
+    }
+    if sectioned.is_empty() && edges.is_empty() {
+        return None;
+    }
+    Some(BakedPart {
+        groups: sectioned,
+        edges,
+        optional_edges: clip_optional_edges(&part.optional_edges, plane),
+        bounding_box,
+    })
+}
+
+// What is left of a part after sectioning.
+#[derive(Clone, Debug)]
+pub enum Section {
+    // The part lies entirely on the kept side and is drawn as it is.
+    Whole,
+    // The plane cuts through the part; this is the capped remainder.
+    Cut(Box<BakedPart>),
+}
+
+#[derive(Clone, Debug)]
+pub struct SectionedPart {
+    pub alias: PartAlias,
+    pub matrix: Matrix4,
+    pub section: Section,
+}
+
+// Sections every part placed by `document` with `plane`, given in model
+// coordinates. Parts lying entirely on the cut away side, or missing from
+// `parts`, are left out.
+pub fn section_model(
+    document: &MultipartDocument,
+    parts: &HashMap<PartAlias, BakedPart>,
+    plane: &ClipPlane,
+) -> Vec<SectionedPart> {
+    let mut sectioned = Vec::new();
+    for (alias, matrix) in instances(document) {
+        let (part, local) = match (parts.get(&alias), plane.to_local(&matrix)) {
+            (Some(part), Some(local)) => (part, local),
+            _ => continue,
+        };
+        let distances = part
+            .bounding_box
+            .points()
+            .map(|e| local.signed_distance(&e));
+        let section = if distances.iter().all(|e| *e <= 0.0) {
+            Section::Whole
+        } else if distances.iter().all(|e| *e > 0.0) {
+            continue;
+        } else {
+            match section_part(part, &local) {
+                Some(e) => Section::Cut(Box::new(e)),
+                None => continue,
+            }
+        };
+        sectioned.push(SectionedPart {
+            alias,
+            matrix,
+            section,
+        });
+    }
+    sectioned
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use cgmath::{AbsDiffEq, InnerSpace};
+    use ldraw::{
+        color::ColorReference,
+        document::{DocumentBuilder, MultipartDocument},
+        elements::{Command, PartReference},
+        Matrix4, PartAlias, Vector3,
+    };
+
+    use crate::{mesh::BakedPart, test_support::cube};
+
+    use super::{section_model, section_part, triangulate_loops, ClipPlane, Section};
+
+    // Signed volume of all groups; only matches the solid if it is closed
+    // and wound outwards.
+    fn volume(part: &BakedPart) -> f32 {
+        part.groups
+            .iter()
+            .flat_map(|g| {
+                g.mesh
+                    .indices
+                    .chunks_exact(3)
+                    .map(|t| [t[0], t[1], t[2]].map(|e| g.mesh.positions[e as usize]))
+            })
+            .map(|[a, b, c]| a.dot(b.cross(c)) / 6.0)
+            .sum()
+    }
+
+    #[test]
+    fn test_section_part() {
+        let plane = ClipPlane::new(Vector3::new(1.0, 0.5, 0.0), Vector3::new(10.0, 10.0, 10.0));
+        let sectioned = section_part(&cube(20.0), &plane).unwrap();
+        // Half of the cube remains, closed by a cap facing the plane normal.
+        assert!((volume(&sectioned) - 4000.0).abs() < 1e-2);
+        assert_eq!(sectioned.groups.len(), 2);
+        let cap = &sectioned.groups[1];
+        assert!(cap.group.color_ref.is_current());
+        assert!(cap.mesh.normals.iter().all(|e| *e == plane.normal));
+        // The outline of the cap is drawn as well.
+        assert!(!sectioned.edges.is_empty());
+        for v in sectioned.edges.vertices.chunks_exact(3) {
+            assert!(plane.signed_distance(&Vector3::new(v[0], v[1], v[2])).abs() < 1e-4);
+        }
+        assert!(sectioned
+            .bounding_box
+            .max
+            .abs_diff_eq(&Vector3::new(15.0, 20.0, 20.0), 1e-4));
+
+        let away = ClipPlane::new(Vector3::new(0.0, -1.0, 0.0), Vector3::new(0.0, 30.0, 0.0));
+        assert!(section_part(&cube(20.0), &away).is_none());
+    }
+
+    #[test]
+    fn test_triangulate_loops() {
+        let square = |size: f32, clockwise: bool| {
+            let mut points = vec![
+                Vector3::new(-size, 0.0, -size),
+                Vector3::new(-size, 0.0, size),
+                Vector3::new(size, 0.0, size),
+                Vector3::new(size, 0.0, -size),
+            ];
+            if clockwise {
+                points.reverse();
+            }
+            points
+        };
+        let loops = vec![square(1.0, true), square(2.0, false)];
+        let up = Vector3::unit_y();
+        let triangulated = triangulate_loops(&loops, &up);
+        assert_eq!(triangulated.len(), 1);
+        assert_eq!(triangulated[0].0, 1);
+
+        let points = loops.iter().flatten().collect::<Vec<_>>();
+        let area = triangulated[0]
+            .1
+            .iter()
+            .map(|t| {
+                let [a, b, c] = t.map(|e| *points[e]);
+                (b - a).cross(c - a).dot(up) * 0.5
+            })
+            .sum::<f32>();
+        assert!((area - 12.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_section_model() {
+        let placed = |x: f32| {
+            Command::PartReference(PartReference {
+                color: ColorReference::Current,
+                matrix: Matrix4::from_translation(Vector3::new(x, 0.0, 0.0)),
+                name: PartAlias::from("box.dat"),
+            })
+        };
+        let document = MultipartDocument::new(
+            DocumentBuilder::new("model.ldr", "model.ldr", "")
+                .commands(vec![placed(0.0), placed(30.0), placed(60.0)])
+                .build(),
+            HashMap::new(),
+        );
+        let parts = HashMap::from([(PartAlias::from("box.dat"), cube(20.0))]);
+        let plane = ClipPlane::new(Vector3::unit_x(), Vector3::new(40.0, 0.0, 0.0));
+
+        let sectioned = section_model(&document, &parts, &plane);
+        assert_eq!(sectioned.len(), 2);
+        assert!(matches!(sectioned[0].section, Section::Whole));
+        match &sectioned[1].section {
+            Section::Cut(part) => {
+                assert_eq!(part.bounding_box.max.x, 10.0);
+                assert!((volume(part) - 4000.0).abs() < 1e-2);
+            }
+            Section::Whole => panic!("the second part is cut"),
+        }
+    }
+}
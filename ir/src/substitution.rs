@@ -0,0 +1,263 @@
+use std::collections::{HashMap, HashSet};
+
+use cgmath::SquareMatrix;
+use ldraw::{
+    color::ColorReference,
+    document::{BfcCertification, Document, MultipartDocument},
+    elements::{Command, PartReference},
+    Matrix4, PartAlias,
+};
+use serde::{Deserialize, Serialize};
+
+// Which version of the circular primitives (`4-4cyli.dat` and the like) to
+// use. The library ships 16-segment ones in `p/`, 48-segment ones in
+// `p/48/` and 8-segment ones in `p/8/`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PrimitiveResolution {
+    Low,
+    #[default]
+    Standard,
+    High,
+}
+
+impl PrimitiveResolution {
+    fn directory(&self) -> Option<&'static str> {
+        match self {
+            PrimitiveResolution::Low => Some("8/"),
+            PrimitiveResolution::Standard => None,
+            PrimitiveResolution::High => Some("48/"),
+        }
+    }
+}
+
+// Studs with the LEGO logo embossed, as shipped in the library. They cost
+// a lot more triangles than plain ones, so they are off unless asked for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StudLogo {
+    // stud-logo3.dat
+    Flattened,
+    // stud-logo4.dat
+    Rounded,
+    // stud-logo5.dat
+    Sharp,
+}
+
+impl StudLogo {
+    fn suffix(&self) -> &'static str {
+        match self {
+            StudLogo::Flattened => "logo3",
+            StudLogo::Rounded => "logo4",
+            StudLogo::Sharp => "logo5",
+        }
+    }
+}
+
+// Solid and hollow studs have logo versions; the other stud primitives
+// don't.
+const LOGO_STUDS: &[&str] = &["stud", "stud2"];
+
+// Matches names like `4-4cyli.dat` or `1-8edge.dat`.
+fn is_circular_primitive(name: &str) -> bool {
+    let (numerator, rest) = match name.split_once('-') {
+        Some(e) => e,
+        None => return false,
+    };
+    let denominator = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .map(|i| &rest[..i])
+        .unwrap_or(rest);
+
+    !name.contains('/')
+        && !numerator.is_empty()
+        && numerator.chars().all(|c| c.is_ascii_digit())
+        && !denominator.is_empty()
+        && rest[denominator.len()..].starts_with(|c: char| c.is_ascii_alphabetic())
+}
+
+fn is_stud(name: &str) -> bool {
+    !name.contains('/') && name.starts_with("stud")
+}
+
+// Replaces library files with alternative versions while baking, e.g. to
+// trade geometric detail for speed. Explicit entries win over the
+// resolution rule.
+//
+// Substitutes have to be resolved like any other dependency: resolve
+// `dependency_document` for the aliases the model pulled in and `merge`
+// the result into the model's `ResolutionResult`. Substitutes that could
+// not be resolved fall back to the original file.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SubstitutionTable {
+    entries: HashMap<PartAlias, PartAlias>,
+    resolution: PrimitiveResolution,
+    stud_logo: Option<StudLogo>,
+}
+
+impl SubstitutionTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_resolution(resolution: PrimitiveResolution) -> Self {
+        SubstitutionTable {
+            resolution,
+            ..Default::default()
+        }
+    }
+
+    pub fn insert(&mut self, from: PartAlias, to: PartAlias) {
+        self.entries.insert(from, to);
+    }
+
+    pub fn remove(&mut self, from: &PartAlias) {
+        self.entries.remove(from);
+    }
+
+    pub fn set_resolution(&mut self, resolution: PrimitiveResolution) {
+        self.resolution = resolution;
+    }
+
+    pub fn resolution(&self) -> PrimitiveResolution {
+        self.resolution
+    }
+
+    pub fn set_stud_logo(&mut self, stud_logo: Option<StudLogo>) {
+        self.stud_logo = stud_logo;
+    }
+
+    pub fn stud_logo(&self) -> Option<StudLogo> {
+        self.stud_logo
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty() && self.resolution.directory().is_none() && self.stud_logo.is_none()
+    }
+
+    pub fn get(&self, alias: &PartAlias) -> Option<PartAlias> {
+        if let Some(e) = self.entries.get(alias) {
+            return Some(e.clone());
+        }
+
+        let name = &*alias.normalized;
+        if let Some(logo) = self.stud_logo {
+            let stud = name.strip_suffix(".dat").unwrap_or(name);
+            if LOGO_STUDS.contains(&stud) {
+                return Some(PartAlias::from(format!("{}-{}.dat", stud, logo.suffix())));
+            }
+        }
+
+        let directory = self.resolution.directory()?;
+        // Only the low resolution set has simplified studs.
+        if is_circular_primitive(name)
+            || (self.resolution == PrimitiveResolution::Low && is_stud(name))
+        {
+            Some(PartAlias::from(format!("{}{}", directory, name)))
+        } else {
+            None
+        }
+    }
+
+    pub fn targets<'a, I: IntoIterator<Item = &'a PartAlias>>(
+        &self,
+        aliases: I,
+    ) -> HashSet<PartAlias> {
+        aliases
+            .into_iter()
+            .filter_map(|alias| self.get(alias))
+            .collect()
+    }
+
+    // A document referencing the substitutes for `aliases`, to be handed to
+    // the resolver.
+    pub fn dependency_document<'a, I: IntoIterator<Item = &'a PartAlias>>(
+        &self,
+        aliases: I,
+    ) -> MultipartDocument {
+        let mut targets = self.targets(aliases).into_iter().collect::<Vec<_>>();
+        targets.sort_by(|a, b| a.normalized.cmp(&b.normalized));
+
+        MultipartDocument::new(
+            Document {
+                name: String::new(),
+                description: String::new(),
+                author: String::new(),
+                bfc: BfcCertification::NotApplicable,
+                headers: Vec::new(),
+                commands: targets
+                    .into_iter()
+                    .map(|name| {
+                        Command::PartReference(PartReference {
+                            color: ColorReference::Current,
+                            matrix: Matrix4::identity(),
+                            name,
+                        })
+                    })
+                    .collect(),
+            },
+            HashMap::new(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ldraw::PartAlias;
+
+    use super::{PrimitiveResolution, StudLogo, SubstitutionTable};
+
+    #[test]
+    fn test_substitution_table() {
+        let cylinder = PartAlias::from("4-4cyli.dat");
+        let stud = PartAlias::from("stud.dat");
+        let part = PartAlias::from("3001.dat");
+
+        let standard = SubstitutionTable::new();
+        assert!(standard.is_empty());
+        assert_eq!(standard.get(&cylinder), None);
+
+        let high = SubstitutionTable::with_resolution(PrimitiveResolution::High);
+        assert_eq!(high.get(&cylinder), Some(PartAlias::from("48/4-4cyli.dat")));
+        assert_eq!(high.get(&stud), None);
+        assert_eq!(high.get(&PartAlias::from("48/4-4cyli.dat")), None);
+
+        let mut low = SubstitutionTable::with_resolution(PrimitiveResolution::Low);
+        assert_eq!(
+            low.get(&PartAlias::from("1-8EDGE.DAT")),
+            Some(PartAlias::from("8/1-8edge.dat"))
+        );
+        assert_eq!(low.get(&stud), Some(PartAlias::from("8/stud.dat")));
+        assert_eq!(low.get(&part), None);
+
+        low.insert(stud.clone(), PartAlias::from("stud-logo4.dat"));
+        assert_eq!(low.get(&stud), Some(PartAlias::from("stud-logo4.dat")));
+
+        let document = low.dependency_document([&cylinder, &stud, &part]);
+        assert_eq!(document.list_dependencies().len(), 2);
+    }
+
+    #[test]
+    fn test_stud_logo() {
+        let mut table = SubstitutionTable::with_resolution(PrimitiveResolution::Low);
+        table.set_stud_logo(Some(StudLogo::Rounded));
+
+        assert_eq!(
+            table.get(&PartAlias::from("stud.dat")),
+            Some(PartAlias::from("stud-logo4.dat"))
+        );
+        assert_eq!(
+            table.get(&PartAlias::from("STUD2.DAT")),
+            Some(PartAlias::from("stud2-logo4.dat"))
+        );
+        // Other studs still follow the resolution.
+        assert_eq!(
+            table.get(&PartAlias::from("stud4.dat")),
+            Some(PartAlias::from("8/stud4.dat"))
+        );
+
+        table.set_stud_logo(None);
+        assert_eq!(
+            table.get(&PartAlias::from("stud.dat")),
+            Some(PartAlias::from("8/stud.dat"))
+        );
+    }
+}
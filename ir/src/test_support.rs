@@ -0,0 +1,60 @@
+use cgmath::InnerSpace;
+use ldraw::{color::ColorReference, Vector3};
+
+use crate::{
+    geometry::BoundingBox3,
+    mesh::{BakedMeshGroup, BakedPart, IndexedMesh},
+    part::{EdgeBufferBuilder, OptionalEdgeBufferBuilder},
+    MeshGroup,
+};
+
+// A cube spanning the origin to `size` on every axis, with outward facing
+// triangles; every face split along a diagonal.
+pub(crate) fn cube(size: f32) -> BakedPart {
+    let mut mesh = IndexedMesh::default();
+    for axis in 0..3 {
+        for side in [0.0, size] {
+            let corner = |u: f32, v: f32| {
+                let mut p = [0.0; 3];
+                p[axis] = side;
+                p[(axis + 1) % 3] = u;
+                p[(axis + 2) % 3] = v;
+                Vector3::from(p)
+            };
+            let mut outward = Vector3::new(0.0, 0.0, 0.0);
+            outward[axis] = side * 2.0 - size;
+            let outward = outward.normalize();
+            for [a, b, c] in [
+                [corner(0.0, 0.0), corner(size, 0.0), corner(size, size)],
+                [corner(0.0, 0.0), corner(size, size), corner(0.0, size)],
+            ] {
+                let t = if (b - a).cross(c - a).dot(outward) > 0.0 {
+                    [a, b, c]
+                } else {
+                    [a, c, b]
+                };
+                for p in t {
+                    mesh.indices.push(mesh.positions.len() as u32);
+                    mesh.positions.push(p);
+                    mesh.normals.push(outward);
+                }
+            }
+        }
+    }
+    BakedPart {
+        groups: vec![BakedMeshGroup {
+            group: MeshGroup {
+                color_ref: ColorReference::Current,
+                bfc: true,
+            },
+            mesh,
+            texture: None,
+        }],
+        edges: EdgeBufferBuilder::default(),
+        optional_edges: OptionalEdgeBufferBuilder::default(),
+        bounding_box: BoundingBox3::new(
+            &Vector3::new(0.0, 0.0, 0.0),
+            &Vector3::new(size, size, size),
+        ),
+    }
+}
@@ -0,0 +1,202 @@
+use cgmath::InnerSpace;
+use ldraw::{elements::TexMapProjection, Matrix4, Vector2, Vector3, Vector4};
+use serde::{Deserialize, Serialize};
+
+// Image files a run of textured vertices is drawn with.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Texture {
+    pub texture: String,
+    pub glossmap: Option<String>,
+}
+
+// Moves the reference points of `projection` by `matrix`, e.g. from the
+// file a `!TEXMAP` appears in into the coordinates of the part.
+pub fn transform_projection(projection: &TexMapProjection, matrix: &Matrix4) -> TexMapProjection {
+    let t = |p: &Vector3| (matrix * p.extend(1.0)).truncate();
+    match projection {
+        TexMapProjection::Planar { p1, p2, p3 } => TexMapProjection::Planar {
+            p1: t(p1),
+            p2: t(p2),
+            p3: t(p3),
+        },
+        TexMapProjection::Cylindrical { p1, p2, p3, angle } => TexMapProjection::Cylindrical {
+            p1: t(p1),
+            p2: t(p2),
+            p3: t(p3),
+            angle: *angle,
+        },
+        TexMapProjection::Spherical {
+            p1,
+            p2,
+            p3,
+            angle1,
+            angle2,
+        } => TexMapProjection::Spherical {
+            p1: t(p1),
+            p2: t(p2),
+            p3: t(p3),
+            angle1: *angle1,
+            angle2: *angle2,
+        },
+    }
+}
+
+// Angle from `from` to `to` around `axis`, all perpendicular to `axis`.
+fn signed_angle(from: Vector3, to: Vector3, axis: Vector3) -> f32 {
+    axis.cross(from).dot(to).atan2(from.dot(to))
+}
+
+// Texture coordinates of `point` under `projection`, with (0, 0) at the
+// top left corner of the image. Points outside of the image get values
+// outside of 0..1.
+pub fn project(projection: &TexMapProjection, point: &Vector3) -> Vector2 {
+    match projection {
+        TexMapProjection::Planar { p1, p2, p3 } => {
+            let (u, v) = (p2 - p1, p3 - p1);
+            let d = point - p1;
+            Vector2::new(d.dot(u) / u.magnitude2(), d.dot(v) / v.magnitude2())
+        }
+        TexMapProjection::Cylindrical { p1, p2, p3, angle } => {
+            let axis = p2 - p1;
+            let up = axis.normalize();
+            let d = point - p1;
+            let height = d.dot(axis) / axis.magnitude2();
+
+            let reference = (p3 - p1) - up * (p3 - p1).dot(up);
+            let around = d - up * d.dot(up);
+            let theta = signed_angle(reference, around, up);
+            Vector2::new(0.5 + theta / angle.to_radians(), 1.0 - height)
+        }
+        TexMapProjection::Spherical {
+            p1,
+            p2,
+            p3,
+            angle1,
+            angle2,
+        } => {
+            let forward = (p2 - p1).normalize();
+            let up = (p2 - p1).cross(p3 - p1).normalize();
+            let d = (point - p1).normalize();
+
+            let flat = d - up * d.dot(up);
+            let longitude = signed_angle(forward, flat, up);
+            let latitude = d.dot(up).clamp(-1.0, 1.0).asin();
+            Vector2::new(
+                0.5 + longitude / angle1.to_radians(),
+                0.5 - latitude / angle2.to_radians(),
+            )
+        }
+    }
+}
+
+// Tangents for the corners of a triangle, following the direction of
+// increasing U. `w` is the handedness of the bitangent, to be rebuilt as
+// `cross(normal, tangent) * w`.
+pub fn triangle_tangents(
+    positions: &[Vector3; 3],
+    uvs: &[Vector2; 3],
+    normals: &[Vector3; 3],
+) -> [Vector4; 3] {
+    let (e1, e2) = (positions[1] - positions[0], positions[2] - positions[0]);
+    let (d1, d2) = (uvs[1] - uvs[0], uvs[2] - uvs[0]);
+    let det = d1.x * d2.y - d2.x * d1.y;
+    let (tangent, bitangent) = if det.abs() > f32::EPSILON {
+        ((e1 * d2.y - e2 * d1.y) / det, (e2 * d1.x - e1 * d2.x) / det)
+    } else {
+        // The texture is squashed to a line or point here; any direction
+        // along the face will do.
+        (e1, e2)
+    };
+
+    normals.map(|normal| {
+        let t = tangent - normal * normal.dot(tangent);
+        let t = if t.magnitude2() > f32::EPSILON {
+            t.normalize()
+        } else {
+            // Any direction perpendicular to the normal.
+            let other = if normal.x.abs() < 0.9 {
+                Vector3::unit_x()
+            } else {
+                Vector3::unit_y()
+            };
+            normal.cross(other).normalize()
+        };
+        let w = if normal.cross(t).dot(bitangent) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        t.extend(w)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{AbsDiffEq, InnerSpace};
+    use ldraw::{elements::TexMapProjection, Vector2, Vector3};
+
+    use super::{project, triangle_tangents};
+
+    #[test]
+    fn test_project() {
+        let planar = TexMapProjection::Planar {
+            p1: Vector3::new(-20.0, 0.0, -10.0),
+            p2: Vector3::new(20.0, 0.0, -10.0),
+            p3: Vector3::new(-20.0, 0.0, 10.0),
+        };
+        assert!(project(&planar, &Vector3::new(0.0, 5.0, 5.0))
+            .abs_diff_eq(&Vector2::new(0.5, 0.75), 1e-5));
+
+        // A half cylinder of radius 10, 24 high.
+        let cylindrical = TexMapProjection::Cylindrical {
+            p1: Vector3::new(0.0, 0.0, 0.0),
+            p2: Vector3::new(0.0, -24.0, 0.0),
+            p3: Vector3::new(0.0, 0.0, -10.0),
+            angle: 180.0,
+        };
+        let uv = project(&cylindrical, &Vector3::new(0.0, -6.0, -10.0));
+        assert!(uv.abs_diff_eq(&Vector2::new(0.5, 0.75), 1e-5));
+        let left = project(&cylindrical, &Vector3::new(-10.0, -12.0, 0.0));
+        let right = project(&cylindrical, &Vector3::new(10.0, -12.0, 0.0));
+        assert!((left.x - right.x).abs().abs_diff_eq(&1.0, 1e-5));
+        assert!((left.x + right.x).abs_diff_eq(&1.0, 1e-5));
+
+        let spherical = TexMapProjection::Spherical {
+            p1: Vector3::new(0.0, 0.0, 0.0),
+            p2: Vector3::new(0.0, 0.0, -10.0),
+            p3: Vector3::new(10.0, 0.0, 0.0),
+            angle1: 180.0,
+            angle2: 90.0,
+        };
+        assert!(project(&spherical, &Vector3::new(0.0, 0.0, -10.0))
+            .abs_diff_eq(&Vector2::new(0.5, 0.5), 1e-5));
+        let pole = project(&spherical, &Vector3::new(0.0, 10.0, 0.0));
+        assert!((pole.y - 0.5).abs().abs_diff_eq(&1.0, 1e-5));
+    }
+
+    #[test]
+    fn test_triangle_tangents() {
+        let normal = Vector3::new(0.0, -1.0, 0.0);
+        let tangents = triangle_tangents(
+            &[
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(2.0, 0.0, 0.0),
+                Vector3::new(0.0, 0.0, 2.0),
+            ],
+            &[
+                Vector2::new(0.0, 0.0),
+                Vector2::new(1.0, 0.0),
+                Vector2::new(0.0, 1.0),
+            ],
+            &[normal; 3],
+        );
+        for tangent in tangents {
+            assert!(tangent
+                .truncate()
+                .abs_diff_eq(&Vector3::new(1.0, 0.0, 0.0), 1e-5));
+            let bitangent = normal.cross(tangent.truncate()) * tangent.w;
+            assert!(bitangent.abs_diff_eq(&Vector3::new(0.0, 0.0, 1.0), 1e-5));
+            assert!(bitangent.magnitude().abs_diff_eq(&1.0, 1e-5));
+        }
+    }
+}
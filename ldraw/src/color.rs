@@ -8,7 +8,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::Vector4;
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Rgba {
     value: [u8; 4],
 }
@@ -45,6 +45,136 @@ impl Rgba {
     pub fn alpha(self) -> u8 {
         self.value[3]
     }
+
+    // Color channels converted to linear RGB for lighting; alpha is kept as is.
+    pub fn to_linear(self) -> Vector4 {
+        Vector4::new(
+            srgb_to_linear(f32::from(self.red()) / 255.0),
+            srgb_to_linear(f32::from(self.green()) / 255.0),
+            srgb_to_linear(f32::from(self.blue()) / 255.0),
+            f32::from(self.alpha()) / 255.0,
+        )
+    }
+
+    pub fn from_linear(value: Vector4) -> Rgba {
+        Rgba::new(
+            unit_to_u8(linear_to_srgb(value.x)),
+            unit_to_u8(linear_to_srgb(value.y)),
+            unit_to_u8(linear_to_srgb(value.z)),
+            unit_to_u8(value.w),
+        )
+    }
+
+    pub fn to_hsv(self) -> Hsv {
+        let r = f32::from(self.red()) / 255.0;
+        let g = f32::from(self.green()) / 255.0;
+        let b = f32::from(self.blue()) / 255.0;
+        let max = r.max(g).max(b);
+        let delta = max - r.min(g).min(b);
+
+        let hue = if delta == 0.0 {
+            0.0
+        } else if max == r {
+            60.0 * ((g - b) / delta).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / delta + 2.0)
+        } else {
+            60.0 * ((r - g) / delta + 4.0)
+        };
+
+        Hsv {
+            hue,
+            saturation: if max == 0.0 { 0.0 } else { delta / max },
+            value: max,
+        }
+    }
+
+    pub fn from_hsv(hsv: Hsv, alpha: u8) -> Rgba {
+        let hue = hsv.hue.rem_euclid(360.0) / 60.0;
+        let saturation = hsv.saturation.clamp(0.0, 1.0);
+        let value = hsv.value.clamp(0.0, 1.0);
+
+        let chroma = value * saturation;
+        let x = chroma * (1.0 - (hue.rem_euclid(2.0) - 1.0).abs());
+        let (r, g, b) = match hue as u32 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        let m = value - chroma;
+
+        Rgba::new(
+            unit_to_u8(r + m),
+            unit_to_u8(g + m),
+            unit_to_u8(b + m),
+            alpha,
+        )
+    }
+
+    // Relative luminance as defined by WCAG, from 0 (black) to 1 (white).
+    pub fn luminance(self) -> f32 {
+        let linear = self.to_linear();
+        0.2126 * linear.x + 0.7152 * linear.y + 0.0722 * linear.z
+    }
+
+    // CIE L*a*b* under the D65 white point.
+    pub fn to_lab(self) -> (f32, f32, f32) {
+        let linear = self.to_linear();
+        let x = (0.4124 * linear.x + 0.3576 * linear.y + 0.1805 * linear.z) / 0.95047;
+        let y = 0.2126 * linear.x + 0.7152 * linear.y + 0.0722 * linear.z;
+        let z = (0.0193 * linear.x + 0.1192 * linear.y + 0.9505 * linear.z) / 1.08883;
+
+        let f = |t: f32| {
+            if t > 216.0 / 24389.0 {
+                t.cbrt()
+            } else {
+                (24389.0 / 27.0 * t + 16.0) / 116.0
+            }
+        };
+        let (fx, fy, fz) = (f(x), f(y), f(z));
+
+        (116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz))
+    }
+
+    // Perceptual distance (CIE76 delta E) between the color channels.
+    // Differences below ~2.3 are generally not noticeable.
+    pub fn distance(self, other: Rgba) -> f32 {
+        let (l1, a1, b1) = self.to_lab();
+        let (l2, a2, b2) = other.to_lab();
+
+        ((l1 - l2).powi(2) + (a1 - a2).powi(2) + (b1 - b2).powi(2)).sqrt()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hsv {
+    // In degrees, 0 to 360.
+    pub hue: f32,
+    pub saturation: f32,
+    pub value: f32,
+}
+
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn unit_to_u8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
 }
 
 impl From<&Rgba> for Vector4 {
@@ -69,7 +199,7 @@ impl From<Rgba> for Vector4 {
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct MaterialGlitter {
     pub value: Rgba,
     pub luminance: u8,
@@ -80,7 +210,7 @@ pub struct MaterialGlitter {
     pub maxsize: f32,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct MaterialSpeckle {
     pub value: Rgba,
     pub luminance: u8,
@@ -90,13 +220,13 @@ pub struct MaterialSpeckle {
     pub maxsize: f32,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum CustomizedMaterial {
     Glitter(MaterialGlitter),
     Speckle(MaterialSpeckle),
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Finish {
     Plastic,
     Chrome,
@@ -107,7 +237,7 @@ pub enum Finish {
     Custom(CustomizedMaterial),
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Material {
     pub code: u32,
     pub name: String,
@@ -134,10 +264,82 @@ impl Material {
     pub fn is_translucent(&self) -> bool {
         self.color.alpha() < 255u8
     }
+
+    pub fn linear_color(&self) -> Vector4 {
+        self.color.to_linear()
+    }
+
+    pub fn linear_edge(&self) -> Vector4 {
+        self.edge.to_linear()
+    }
 }
 
 pub type MaterialRegistry = HashMap<u32, Material>;
 
+// LDConfig names use underscores (`Bright_Green`), while most other sources
+// spell them with spaces and arbitrary case.
+fn normalize_material_name(name: &str) -> impl Iterator<Item = char> + '_ {
+    name.chars()
+        .filter(|ch| !matches!(ch, '_' | ' ' | '-'))
+        .flat_map(char::to_lowercase)
+}
+
+// Perceptual distance plus a penalty for differing opacity, so that solid
+// colors prefer solid matches.
+fn color_distance(a: Rgba, b: Rgba) -> f32 {
+    let alpha = (f32::from(a.alpha()) - f32::from(b.alpha())).abs() / 255.0;
+    a.distance(b) + 100.0 * alpha
+}
+
+pub trait MaterialRegistryExt {
+    // Materials ordered by color code.
+    fn sorted(&self) -> Vec<&Material>;
+
+    fn find_by_name(&self, name: &str) -> Option<&Material>;
+
+    fn nearest(&self, color: Rgba) -> Option<&Material>;
+
+    // Commonly used colors from the official LDConfig.ldr, bundled with
+    // this crate for rendering without an LDraw installation. Parsed once
+    // and shared.
+    #[cfg(feature = "default-ldconfig")]
+    fn default_official() -> &'static Self
+    where
+        Self: Sized;
+}
+
+impl MaterialRegistryExt for MaterialRegistry {
+    fn sorted(&self) -> Vec<&Material> {
+        let mut materials = self.values().collect::<Vec<_>>();
+        materials.sort_by_key(|e| e.code);
+        materials
+    }
+
+    fn find_by_name(&self, name: &str) -> Option<&Material> {
+        self.sorted()
+            .into_iter()
+            .find(|e| normalize_material_name(&e.name).eq(normalize_material_name(name)))
+    }
+
+    fn nearest(&self, color: Rgba) -> Option<&Material> {
+        self.sorted().into_iter().min_by(|a, b| {
+            color_distance(a.color, color).total_cmp(&color_distance(b.color, color))
+        })
+    }
+
+    #[cfg(feature = "default-ldconfig")]
+    fn default_official() -> &'static Self {
+        static REGISTRY: std::sync::OnceLock<MaterialRegistry> = std::sync::OnceLock::new();
+
+        REGISTRY
+            .get_or_init(|| {
+                let mut ldconfig: &[u8] = include_bytes!("../data/LDConfig-subset.ldr");
+                futures::executor::block_on(crate::parser::parse_color_definition(&mut ldconfig))
+                    .expect("bundled LDConfig-subset.ldr is valid")
+            })
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum ColorReference {
     Unknown(u32),
@@ -164,6 +366,10 @@ impl<'de> Visitor<'de> for U32Visitor {
     fn visit_u32<E: DeError>(self, value: u32) -> Result<Self::Value, E> {
         Ok(value)
     }
+
+    fn visit_u64<E: DeError>(self, value: u64) -> Result<Self::Value, E> {
+        u32::try_from(value).map_err(|_| E::custom(format!("color code out of range: {}", value)))
+    }
 }
 
 impl<'de> Deserialize<'de> for ColorReference {
@@ -317,3 +523,104 @@ impl ColorReference {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Finish, Hsv, Material, MaterialRegistry, MaterialRegistryExt, Rgba};
+
+    fn material(code: u32, name: &str, color: Rgba) -> Material {
+        Material {
+            code,
+            name: name.into(),
+            color,
+            edge: Rgba::new(0x33, 0x33, 0x33, 255),
+            luminance: 0,
+            finish: Finish::Plastic,
+        }
+    }
+
+    fn sample_materials() -> MaterialRegistry {
+        [
+            material(4, "Red", Rgba::new(0xc9, 0x1a, 0x09, 255)),
+            material(2, "Green", Rgba::new(0x23, 0x78, 0x41, 255)),
+            material(10, "Bright_Green", Rgba::new(0x4b, 0x9f, 0x4a, 255)),
+            material(36, "Trans_Red", Rgba::new(0xc9, 0x1a, 0x09, 128)),
+        ]
+        .into_iter()
+        .map(|e| (e.code, e))
+        .collect()
+    }
+
+    #[test]
+    fn test_rgba_color_spaces() {
+        let color = Rgba::new(0xc9, 0x1a, 0x09, 128);
+
+        let linear = color.to_linear();
+        assert!((linear.x - 0.5841).abs() < 1e-3);
+        assert_eq!(linear.w, 128.0 / 255.0);
+        assert_eq!(Rgba::from_linear(linear), color);
+
+        let hsv = color.to_hsv();
+        assert!((hsv.hue - 5.3125).abs() < 1e-3);
+        assert_eq!(Rgba::from_hsv(hsv, 128), color);
+        assert_eq!(
+            Rgba::from_hsv(
+                Hsv {
+                    hue: 240.0,
+                    saturation: 1.0,
+                    value: 1.0
+                },
+                255
+            ),
+            Rgba::new(0, 0, 255, 255)
+        );
+
+        assert_eq!(Rgba::new(0, 0, 0, 255).luminance(), 0.0);
+        assert!((Rgba::new(255, 255, 255, 255).luminance() - 1.0).abs() < 1e-4);
+
+        let white = Rgba::new(255, 255, 255, 255);
+        let (l, a, b) = white.to_lab();
+        assert!((l - 100.0).abs() < 0.1 && a.abs() < 0.1 && b.abs() < 0.1);
+        assert_eq!(white.distance(white), 0.0);
+        assert!(white.distance(Rgba::new(254, 255, 255, 255)) < 1.0);
+        assert!(white.distance(Rgba::new(0, 0, 0, 255)) > 99.0);
+    }
+
+    #[test]
+    fn test_material_registry_queries() {
+        let materials = sample_materials();
+
+        let codes = materials
+            .sorted()
+            .iter()
+            .map(|e| e.code)
+            .collect::<Vec<_>>();
+        assert_eq!(codes, vec![2, 4, 10, 36]);
+
+        assert_eq!(materials.find_by_name("bright green").unwrap().code, 10);
+        assert_eq!(materials.find_by_name("Trans-Red").unwrap().code, 36);
+        assert!(materials.find_by_name("Blue").is_none());
+
+        let nearest = |color| materials.nearest(color).unwrap().code;
+        assert_eq!(nearest(Rgba::new(0xd0, 0x20, 0x10, 255)), 4);
+        assert_eq!(nearest(Rgba::new(0xd0, 0x20, 0x10, 100)), 36);
+        assert_eq!(nearest(Rgba::new(0x50, 0xa0, 0x50, 255)), 10);
+        assert!(MaterialRegistry::new()
+            .nearest(Rgba::new(0, 0, 0, 255))
+            .is_none());
+    }
+
+    #[cfg(feature = "default-ldconfig")]
+    #[test]
+    fn test_default_official_materials() {
+        let materials = MaterialRegistry::default_official();
+
+        assert_eq!(materials[&4].name, "Red");
+        assert_eq!(
+            materials.find_by_name("Light Bluish Grey").unwrap().code,
+            71
+        );
+        assert!(materials[&47].is_translucent());
+        assert!(std::ptr::eq(materials, MaterialRegistry::default_official()));
+    }
+}
@@ -4,12 +4,21 @@ use std::{
     vec::Vec,
 };
 
+use cgmath::SquareMatrix;
+use serde::{Deserialize, Serialize};
+
 use crate::{
-    elements::{Command, Header, Line, Meta, OptionalLine, PartReference, Quad, Triangle},
-    PartAlias, Winding,
+    color::{ColorReference, MaterialRegistry},
+    elements::{
+        CmdLine, Command, Header, History, Line, Meta, MlcadMode, OptionalLine, PartReference,
+        Quad, TexMapStatement, Triangle,
+    },
+    error::{ParseError, ResolutionError},
+    library::ResolutionResult,
+    matrix_to_f32, matrix_to_f64, Matrix4, Matrix4d, PartAlias, Winding,
 };
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum BfcCertification {
     NotApplicable,
     NoCertify,
@@ -33,7 +42,7 @@ impl BfcCertification {
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Document {
     pub name: String,
     pub description: String,
@@ -43,19 +52,18 @@ pub struct Document {
     pub commands: Vec<Command>,
 }
 
-fn traverse_dependencies(
-    document: &Document,
-    parent: Option<&MultipartDocument>,
+fn traverse_dependencies<'a>(
+    document: &'a Document,
+    parent: Option<&'a MultipartDocument>,
+    visited: &mut HashSet<&'a PartAlias>,
     list: &mut HashSet<PartAlias>,
 ) {
-    for part_ref in document.iter_refs() {
+    for (part_ref, _) in document.iter_refs_with_mode() {
         if let Some(parent) = parent {
-            if parent.subparts.contains_key(&part_ref.name) {
-                traverse_dependencies(
-                    parent.subparts.get(&part_ref.name).unwrap(),
-                    Some(parent),
-                    list,
-                );
+            if let Some(subpart) = parent.subparts.get(&part_ref.name) {
+                if visited.insert(&part_ref.name) {
+                    traverse_dependencies(subpart, Some(parent), visited, list);
+                }
                 continue;
             }
         }
@@ -63,6 +71,64 @@ fn traverse_dependencies(
     }
 }
 
+fn collect_placements(
+    document: &Document,
+    parent: &MultipartDocument,
+    matrix: Matrix4d,
+    stack: &mut Vec<PartAlias>,
+    placements: &mut Vec<(PartAlias, Matrix4d)>,
+) {
+    for (part_ref, _) in document.iter_refs_with_mode() {
+        let matrix = matrix * matrix_to_f64(&part_ref.matrix);
+        match parent.subparts.get(&part_ref.name) {
+            Some(_) if stack.contains(&part_ref.name) => {}
+            Some(subpart) => {
+                stack.push(part_ref.name.clone());
+                collect_placements(subpart, parent, matrix, stack, placements);
+                stack.pop();
+            }
+            None => placements.push((part_ref.name.clone(), matrix)),
+        }
+    }
+}
+
+fn find_circular_reference<'a>(
+    document: &'a Document,
+    parent: &'a MultipartDocument,
+    path: &mut Vec<&'a PartAlias>,
+    finished: &mut HashSet<&'a PartAlias>,
+) -> Option<Vec<PartAlias>> {
+    for (part_ref, _) in document.iter_refs_with_mode() {
+        let alias = &part_ref.name;
+        let subpart = match parent.subparts.get(alias) {
+            Some(e) => e,
+            None => continue,
+        };
+
+        if let Some(index) = path.iter().position(|e| *e == alias) {
+            let mut cycle = path[index..]
+                .iter()
+                .map(|e| (*e).clone())
+                .collect::<Vec<_>>();
+            cycle.push(alias.clone());
+            return Some(cycle);
+        }
+        if finished.contains(alias) {
+            continue;
+        }
+
+        path.push(alias);
+        let cycle = find_circular_reference(subpart, parent, path, finished);
+        if cycle.is_some() {
+            return cycle;
+        }
+        path.pop();
+        finished.insert(alias);
+    }
+
+    None
+}
+
 impl Document {
     pub fn has_geometry(&self) -> bool {
         for item in self.commands.iter() {
@@ -80,13 +146,185 @@ impl Document {
         false
     }
 
+    // Renamed official parts are kept as stubs described as `~Moved to xxx`
+    // that contain nothing but a single untransformed reference to the new
+    // part. Returns the new part's name for such stubs.
+    pub fn moved_to(&self) -> Option<&PartAlias> {
+        if !self.description.trim_start().starts_with("~Moved to") {
+            return None;
+        }
+
+        let mut target = None;
+        for command in self.commands.iter() {
+            match command {
+                Command::PartReference(e) if target.is_none() => target = Some(e),
+                Command::Meta(_) => (),
+                _ => return None,
+            }
+        }
+
+        target
+            .filter(|e| e.matrix == Matrix4::identity())
+            .map(|e| &e.name)
+    }
+
+    // Falls back to the first word of the description for parts predating
+    // `!CATEGORY`, skipping the `~`, `_`, `=` and `|` name prefixes.
+    pub fn category(&self) -> Option<&str> {
+        let header = self
+            .headers
+            .iter()
+            .find(|Header(key, _)| key == "CATEGORY")
+            .map(|Header(_, value)| value.trim())
+            .filter(|value| !value.is_empty());
+        if header.is_some() {
+            return header;
+        }
+
+        self.description
+            .trim_start_matches(['~', '_', '=', '|'])
+            .split_whitespace()
+            .next()
+    }
+
+    pub fn keywords(&self) -> Vec<&str> {
+        self.headers
+            .iter()
+            .filter(|Header(key, _)| key == "KEYWORDS")
+            .flat_map(|Header(_, value)| value.split(','))
+            .map(str::trim)
+            .filter(|keyword| !keyword.is_empty())
+            .collect()
+    }
+
+    // Image files named by `!TEXMAP START` and `!TEXMAP NEXT`, glossmaps
+    // included, in order of first use.
+    pub fn textures(&self) -> Vec<&str> {
+        let mut result = Vec::new();
+        for command in self.commands.iter() {
+            let texmap = match command {
+                Command::Meta(Meta::TexMap(
+                    TexMapStatement::Start(texmap) | TexMapStatement::Next(texmap),
+                )) => texmap,
+                _ => continue,
+            };
+            for name in std::iter::once(&texmap.texture).chain(texmap.glossmap.iter()) {
+                if !result.contains(&name.as_str()) {
+                    result.push(name.as_str());
+                }
+            }
+        }
+        result
+    }
+
+    pub fn cmdline(&self) -> Option<CmdLine> {
+        self.headers
+            .iter()
+            .find(|Header(key, _)| key == "CMDLINE")
+            .and_then(|Header(_, value)| value.parse().ok())
+    }
+
+    // The color implied by `!CMDLINE -cN`, if any.
+    pub fn default_color(&self, materials: &MaterialRegistry) -> Option<ColorReference> {
+        self.cmdline()
+            .and_then(|e| e.color_code())
+            .map(|code| ColorReference::resolve(code, materials))
+    }
+
+    pub fn history(&self) -> impl Iterator<Item = Result<History, ParseError>> + '_ {
+        self.headers
+            .iter()
+            .filter(|Header(key, _)| key == "HISTORY")
+            .map(|Header(_, value)| value.parse())
+    }
+
+    // Keeps history lines grouped by inserting after the last existing one.
+    pub fn push_history(&mut self, history: &History) {
+        let position = self
+            .headers
+            .iter()
+            .rposition(|Header(key, _)| key == "HISTORY")
+            .map_or(self.headers.len(), |e| e + 1);
+        self.headers.insert(position, history.to_header());
+    }
+
+    // Part references, including `0 !:` TEXMAP geometry but not those behind
+    // MLCad prefixes.
+    pub fn iter_refs(&self) -> impl Iterator<Item = &PartReference> + '_ {
+        self.commands.iter().filter_map(|value| match value {
+            Command::PartReference(e) => Some(e),
+            Command::Meta(Meta::TexMap(TexMapStatement::Geometry(inner))) => match inner.as_ref() {
+                Command::PartReference(e) => Some(e),
+                _ => None,
+            },
+            _ => None,
+        })
+    }
+
+    pub fn iter_refs_mut(&mut self) -> impl Iterator<Item = &mut PartReference> + '_ {
+        self.commands.iter_mut().filter_map(|value| match value {
+            Command::PartReference(e) => Some(e),
+            Command::Meta(Meta::TexMap(TexMapStatement::Geometry(inner))) => match inner.as_mut() {
+                Command::PartReference(e) => Some(e),
+                _ => None,
+            },
+            _ => None,
+        })
+    }
+
+    // Part references, those behind MLCad prefixes included, with the prefix
+    // they are behind if any.
+    pub fn iter_refs_with_mode(
+        &self,
+    ) -> impl Iterator<Item = (&PartReference, Option<MlcadMode>)> + '_ {
+        self.commands.iter().filter_map(Command::part_reference)
+    }
+
+    // Commands of each step, split at `0 STEP` and `0 ROTSTEP`. Anything
+    // after the last of them makes a step of its own unless it is empty.
+    pub fn steps(&self) -> impl Iterator<Item = &[Command]> + '_ {
+        let mut steps = self
+            .commands
+            .split(|e| matches!(e, Command::Meta(Meta::Step | Meta::RotStep(_))))
+            .peekable();
+        std::iter::from_fn(move || {
+            let step = steps.next()?;
+            if step.is_empty() && steps.peek().is_none() {
+                None
+            } else {
+                Some(step)
+            }
+        })
+    }
+
     pub fn list_dependencies(&self) -> HashSet<PartAlias> {
         let mut result = HashSet::new();
 
-        traverse_dependencies(self, None, &mut result);
+        traverse_dependencies(self, None, &mut HashSet::new(), &mut result);
 
         result
     }
+
+    // Color references are serialized as bare codes, so documents coming from
+    // serde need to be resolved against a material registry again.
+    pub fn resolve_colors(&mut self, materials: &MaterialRegistry) {
+        for mut command in self.commands.iter_mut() {
+            if let Command::Meta(Meta::Mlcad(_, inner)) = command {
+                command = inner.as_mut();
+            }
+            let color = match command {
+                Command::PartReference(e) => &mut e.color,
+                Command::Line(e) => &mut e.color,
+                Command::Triangle(e) => &mut e.color,
+                Command::Quad(e) => &mut e.color,
+                Command::OptionalLine(e) => &mut e.color,
+                Command::Meta(_) => continue,
+            };
+            if let ColorReference::Unknown(code) = color {
+                *color = ColorReference::resolve(*code, materials);
+            }
+        }
+    }
 }
 
 macro_rules! define_iterator(
@@ -110,12 +348,6 @@ macro_rules! define_iterator(
 );
 
 define_iterator!(iter_meta, iter_meta_mut, Command::Meta, Meta);
-define_iterator!(
-    iter_refs,
-    iter_refs_mut,
-    Command::PartReference,
-    PartReference
-);
 define_iterator!(iter_lines, iter_lines_mut, Command::Line, Line);
 define_iterator!(
     iter_triangles,
@@ -131,13 +363,235 @@ define_iterator!(
     OptionalLine
 );
 
-#[derive(Clone, Debug, PartialEq)]
+// Header order recommended by the LDraw file format specification.
+const HEADER_ORDER: &[&str] = &[
+    "LDRAW_ORG",
+    "LICENSE",
+    "HELP",
+    "CATEGORY",
+    "KEYWORDS",
+    "CMDLINE",
+    "HISTORY",
+];
+
+const DEFAULT_LICENSE: &str = "Licensed under CC BY 4.0 : see CAreadme.txt";
+
+#[derive(Clone, Debug)]
+pub struct DocumentBuilder {
+    name: String,
+    description: String,
+    author: String,
+    part_type: String,
+    license: String,
+    bfc: BfcCertification,
+    headers: Vec<Header>,
+    commands: Vec<Command>,
+}
+
+impl DocumentBuilder {
+    pub fn new(name: &str, description: &str, author: &str) -> Self {
+        DocumentBuilder {
+            name: name.to_string(),
+            description: description.to_string(),
+            author: author.to_string(),
+            part_type: "Unofficial_Part".to_string(),
+            license: DEFAULT_LICENSE.to_string(),
+            bfc: BfcCertification::Certify(Winding::Ccw),
+            headers: Vec::new(),
+            commands: Vec::new(),
+        }
+    }
+
+    pub fn part_type(mut self, part_type: &str) -> Self {
+        self.part_type = part_type.to_string();
+        self
+    }
+
+    pub fn license(mut self, license: &str) -> Self {
+        self.license = license.to_string();
+        self
+    }
+
+    pub fn bfc(mut self, bfc: BfcCertification) -> Self {
+        self.bfc = bfc;
+        self
+    }
+
+    pub fn header(mut self, key: &str, value: &str) -> Self {
+        self.headers
+            .push(Header(key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn command(mut self, command: Command) -> Self {
+        self.commands.push(command);
+        self
+    }
+
+    pub fn commands<I: IntoIterator<Item = Command>>(mut self, commands: I) -> Self {
+        self.commands.extend(commands);
+        self
+    }
+
+    pub fn build(self) -> Document {
+        let mut headers = self.headers;
+        if !headers.iter().any(|Header(key, _)| key == "LDRAW_ORG") {
+            headers.push(Header("LDRAW_ORG".to_string(), self.part_type));
+        }
+        if !headers.iter().any(|Header(key, _)| key == "LICENSE") {
+            headers.push(Header("LICENSE".to_string(), self.license));
+        }
+        // Stable, so repeated headers like !HISTORY keep their relative order
+        // and unknown headers stay at the end in insertion order.
+        headers.sort_by_key(|Header(key, _)| {
+            HEADER_ORDER
+                .iter()
+                .position(|e| e == key)
+                .unwrap_or(HEADER_ORDER.len())
+        });
+
+        Document {
+            name: self.name,
+            description: self.description,
+            author: self.author,
+            bfc: self.bfc,
+            headers,
+            commands: self.commands,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReferenceLocation {
+    // None for the main body of the document.
+    pub subpart: Option<PartAlias>,
+    // Index into the commands of the referencing document.
+    pub command: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnresolvedReference {
+    pub alias: PartAlias,
+    pub locations: Vec<ReferenceLocation>,
+}
+
+impl UnresolvedReference {
+    pub fn count(&self) -> usize {
+        self.locations.len()
+    }
+}
+
+// Which documents of a MultipartDocument reference which subparts. The main
+// body is keyed by its own name; references to files outside the document
+// are not part of the graph.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DependencyGraph {
+    pub root: PartAlias,
+    // Referenced subparts of each document, ordered by name, with the number
+    // of times each is referenced.
+    pub edges: HashMap<PartAlias, Vec<(PartAlias, usize)>>,
+}
+
+impl DependencyGraph {
+    pub fn children(&self, alias: &PartAlias) -> &[(PartAlias, usize)] {
+        self.edges.get(alias).map_or(&[], Vec::as_slice)
+    }
+
+    // Total number of references to the subpart across all documents.
+    pub fn reference_count(&self, alias: &PartAlias) -> usize {
+        self.edges
+            .values()
+            .flatten()
+            .filter(|(child, _)| child == alias)
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    // Subparts that can't be reached from the main body, ordered by name.
+    pub fn unused(&self) -> Vec<PartAlias> {
+        let mut reachable = HashSet::new();
+        let mut queue = vec![&self.root];
+        while let Some(alias) = queue.pop() {
+            if reachable.insert(alias) {
+                queue.extend(self.children(alias).iter().map(|(child, _)| child));
+            }
+        }
+
+        let mut unused = self
+            .edges
+            .keys()
+            .filter(|e| !reachable.contains(e))
+            .cloned()
+            .collect::<Vec<_>>();
+        unused.sort_by(|a, b| a.normalized.cmp(&b.normalized));
+        unused
+    }
+
+    // Documents reachable from the main body, each listed before every
+    // subpart it references.
+    pub fn topological_order(&self) -> Result<Vec<PartAlias>, ResolutionError> {
+        fn visit<'a>(
+            graph: &'a DependencyGraph,
+            alias: &'a PartAlias,
+            path: &mut Vec<&'a PartAlias>,
+            finished: &mut HashSet<&'a PartAlias>,
+            order: &mut Vec<PartAlias>,
+        ) -> Result<(), ResolutionError> {
+            if let Some(index) = path.iter().position(|e| *e == alias) {
+                let mut cycle = path[index..]
+                    .iter()
+                    .map(|e| (*e).clone())
+                    .collect::<Vec<_>>();
+                cycle.push(alias.clone());
+                return Err(ResolutionError::CircularReference(cycle));
+            }
+            if finished.contains(alias) {
+                return Ok(());
+            }
+
+            path.push(alias);
+            for (child, _) in graph.children(alias) {
+                visit(graph, child, path, finished, order)?;
+            }
+            path.pop();
+            finished.insert(alias);
+            order.push(alias.clone());
+
+            Ok(())
+        }
+
+        let mut order = Vec::new();
+        visit(
+            self,
+            &self.root,
+            &mut Vec::new(),
+            &mut HashSet::new(),
+            &mut order,
+        )?;
+        order.reverse();
+
+        Ok(order)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct MultipartDocument {
     pub body: Document,
     pub subparts: HashMap<PartAlias, Document>,
+    /// Files embedded with `!DATA`, such as texture images, by name.
+    #[serde(default)]
+    pub data: HashMap<String, Vec<u8>>,
 }
 
 impl MultipartDocument {
+    pub fn new(body: Document, subparts: HashMap<PartAlias, Document>) -> Self {
+        MultipartDocument {
+            body,
+            subparts,
+            data: HashMap::new(),
+        }
+    }
+
     pub fn get_subpart(&self, alias: &PartAlias) -> Option<&Document> {
         self.subparts.get(alias)
     }
@@ -145,8 +599,750 @@ impl MultipartDocument {
     pub fn list_dependencies(&self) -> HashSet<PartAlias> {
         let mut result = HashSet::new();
 
-        traverse_dependencies(&self.body, Some(self), &mut result);
+        traverse_dependencies(&self.body, Some(self), &mut HashSet::new(), &mut result);
 
         result
     }
+
+    // Name of the main model, or `fallback` for a document without a
+    // `Name:` line, e.g. the name of the file it was read from.
+    fn main_name<'a>(&'a self, fallback: &'a str) -> &'a str {
+        match self.body.name.as_str() {
+            "" => fallback,
+            name => name,
+        }
+    }
+
+    // The main model is keyed by its name, or `fallback` if it has none.
+    // Every file outside the document placed by the main model, directly or
+    // through subparts, with its transform relative to the model. Transforms
+    // are composed in f64, so deeply nested subparts do not drift the way
+    // f32 products do. Subparts referring back to themselves are not followed.
+    pub fn placements_f64(&self) -> Vec<(PartAlias, Matrix4d)> {
+        let mut placements = Vec::new();
+        collect_placements(
+            &self.body,
+            self,
+            Matrix4d::identity(),
+            &mut Vec::new(),
+            &mut placements,
+        );
+        placements
+    }
+
+    // Same as placements_f64, rounded to f32 once composed. Placements out of
+    // the range of f32 are left out.
+    pub fn placements(&self) -> Vec<(PartAlias, Matrix4)> {
+        self.placements_f64()
+            .into_iter()
+            .filter_map(|(alias, matrix)| Some((alias, matrix_to_f32(&matrix)?)))
+            .collect()
+    }
+
+    pub fn dependency_graph(&self, fallback: &str) -> DependencyGraph {
+        let edges_of = |document: &Document| {
+            let mut counts: HashMap<PartAlias, usize> = HashMap::new();
+            for (part_ref, _) in document.iter_refs_with_mode() {
+                if self.subparts.contains_key(&part_ref.name) {
+                    *counts.entry(part_ref.name.clone()).or_default() += 1;
+                }
+            }
+            let mut edges = counts.into_iter().collect::<Vec<_>>();
+            edges.sort_by(|(a, _), (b, _)| a.normalized.cmp(&b.normalized));
+            edges
+        };
+
+        let root = PartAlias::from(self.main_name(fallback));
+        let mut edges = self
+            .subparts
+            .iter()
+            .map(|(alias, document)| (alias.clone(), edges_of(document)))
+            .collect::<HashMap<_, _>>();
+        edges.insert(root.clone(), edges_of(&self.body));
+
+        DependencyGraph { root, edges }
+    }
+
+    // Splits the document into individual files, main model first and
+    // subparts ordered by name. File names are the names used to reference
+    // each part, and that of the main model, or `fallback` if it has none.
+    pub fn unpack(&self, fallback: &str) -> Vec<(String, Document)> {
+        let mut subparts = self.subparts.iter().collect::<Vec<_>>();
+        subparts.sort_by(|(a, _), (b, _)| a.normalized.cmp(&b.normalized));
+
+        let main = (self.main_name(fallback).to_string(), self.body.clone());
+        std::iter::once(main)
+            .chain(
+                subparts
+                    .into_iter()
+                    .map(|(alias, document)| (alias.original.to_string(), document.clone())),
+            )
+            .collect()
+    }
+
+    // Embeds every locally resolved file referenced from `root`, directly or
+    // through other local files, as a subpart. Library parts stay external.
+    pub fn pack(root: &MultipartDocument, resolution: &ResolutionResult) -> MultipartDocument {
+        let mut packed = root.clone();
+        let mut queue = packed.list_dependencies().into_iter().collect::<Vec<_>>();
+
+        while let Some(alias) = queue.pop() {
+            if packed.subparts.contains_key(&alias) {
+                continue;
+            }
+            let document = match resolution.query(&alias, true) {
+                Some((document, true)) => document,
+                _ => continue,
+            };
+
+            for (name, subpart) in document.subparts.iter() {
+                packed
+                    .subparts
+                    .entry(name.clone())
+                    .or_insert_with(|| subpart.clone());
+            }
+            packed.subparts.insert(alias, document.body.clone());
+            queue.extend(document.list_dependencies());
+        }
+
+        packed
+    }
+
+    // Lists references found neither in subparts nor in the resolved
+    // library, sorted by name. Those behind MLCad prefixes and `!TEXMAP`
+    // geometry lines count, as the resolver follows them too.
+    pub fn unresolved_references(&self, library: &ResolutionResult) -> Vec<UnresolvedReference> {
+        let mut result: HashMap<&PartAlias, Vec<ReferenceLocation>> = HashMap::new();
+
+        let documents = std::iter::once((None, &self.body))
+            .chain(self.subparts.iter().map(|(k, v)| (Some(k), v)));
+        for (subpart, document) in documents {
+            for (index, command) in document.commands.iter().enumerate() {
+                let alias = match command.part_reference() {
+                    Some((e, _)) => &e.name,
+                    None => continue,
+                };
+                if self.subparts.contains_key(alias) || library.query(alias, true).is_some() {
+                    continue;
+                }
+                result.entry(alias).or_default().push(ReferenceLocation {
+                    subpart: subpart.cloned(),
+                    command: index,
+                });
+            }
+        }
+
+        let mut result = result
+            .into_iter()
+            .map(|(alias, locations)| UnresolvedReference {
+                alias: alias.clone(),
+                locations,
+            })
+            .collect::<Vec<_>>();
+        result.sort_by(|a, b| a.alias.normalized.cmp(&b.alias.normalized));
+        result
+    }
+
+    // Returns the chain of subparts forming a cycle, starting and ending with
+    // the same alias. Consumers recursing into subparts would never terminate
+    // on such documents.
+    pub fn find_circular_reference(&self) -> Option<Vec<PartAlias>> {
+        find_circular_reference(&self.body, self, &mut Vec::new(), &mut HashSet::new())
+    }
+
+    pub fn resolve_colors(&mut self, materials: &MaterialRegistry) {
+        self.body.resolve_colors(materials);
+        for subpart in self.subparts.values_mut() {
+            subpart.resolve_colors(materials);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        color::{ColorReference, Finish, Material, MaterialRegistry, Rgba},
+        elements::{
+            Command, Header, History, HistoryAuthor, HistoryDate, Meta, MlcadMode, PartReference,
+            RotStep, TexMapStatement,
+        },
+        matrix_to_f64, Matrix4, Matrix4d, PartAlias, Vector3, Winding,
+    };
+    use cgmath::{Deg, SquareMatrix};
+
+    use super::{
+        BfcCertification, Document, DocumentBuilder, MultipartDocument, ReferenceLocation,
+    };
+    use crate::error::ResolutionError;
+    use crate::library::ResolutionResult;
+    use std::sync::Arc;
+
+    fn sample_materials() -> MaterialRegistry {
+        let mut materials = MaterialRegistry::new();
+        materials.insert(
+            4,
+            Material {
+                code: 4,
+                name: "Red".into(),
+                color: Rgba::new(0xc9, 0x1a, 0x09, 255),
+                edge: Rgba::new(0x33, 0x33, 0x33, 255),
+                luminance: 0,
+                finish: Finish::Plastic,
+            },
+        );
+        materials
+    }
+
+    #[test]
+    fn test_multipart_document_serde_roundtrip() {
+        let materials = sample_materials();
+        let mut subparts = HashMap::new();
+        subparts.insert(
+            PartAlias::from("sub.ldr"),
+            Document {
+                name: "sub.ldr".into(),
+                description: "Submodel".into(),
+                author: "LDraw.rs".into(),
+                bfc: BfcCertification::Certify(Winding::Ccw),
+                headers: vec![],
+                commands: vec![Command::Meta(Meta::Step)],
+            },
+        );
+        let document = MultipartDocument {
+            body: Document {
+                name: "main.ldr".into(),
+                description: "Main".into(),
+                author: "LDraw.rs".into(),
+                bfc: BfcCertification::NotApplicable,
+                headers: vec![Header("LDRAW_ORG".into(), "Model".into())],
+                commands: vec![Command::PartReference(PartReference {
+                    color: ColorReference::resolve(4, &materials),
+                    matrix: Matrix4::from_scale(2.0),
+                    name: PartAlias::from("Sub.ldr"),
+                })],
+            },
+            subparts,
+            data: HashMap::from([("logo.png".to_string(), vec![0x89, 0x50])]),
+        };
+
+        let json = serde_json::to_string(&document).unwrap();
+        let mut deserialized: MultipartDocument = serde_json::from_str(&json).unwrap();
+
+        let color = &deserialized.body.iter_refs().next().unwrap().color;
+        assert!(matches!(color, ColorReference::Unknown(4)));
+
+        deserialized.resolve_colors(&materials);
+        assert_eq!(deserialized, document);
+        assert_eq!(
+            deserialized
+                .body
+                .iter_refs()
+                .next()
+                .unwrap()
+                .color
+                .get_material(),
+            materials.get(&4)
+        );
+    }
+
+    #[test]
+    fn test_moved_to() {
+        let materials = sample_materials();
+        let mut document = Document {
+            name: "3001old.dat".into(),
+            description: "~Moved to 3001".into(),
+            author: "LDraw.rs".into(),
+            bfc: BfcCertification::NotApplicable,
+            headers: vec![],
+            commands: vec![
+                Command::Meta(Meta::Comment("Redirect".into())),
+                Command::PartReference(PartReference {
+                    color: ColorReference::resolve(16, &materials),
+                    matrix: Matrix4::from_scale(1.0),
+                    name: PartAlias::from("3001.dat"),
+                }),
+            ],
+        };
+        assert_eq!(document.moved_to(), Some(&PartAlias::from("3001.dat")));
+
+        document.commands.push(document.commands[1].clone());
+        assert_eq!(document.moved_to(), None);
+
+        document.commands.pop();
+        document.description = "Brick 2 x 4".into();
+        assert_eq!(document.moved_to(), None);
+    }
+
+    #[test]
+    fn test_category_and_keywords() {
+        let mut document = Document {
+            name: "3001.dat".into(),
+            description: "~Brick  2 x  4".into(),
+            author: "LDraw.rs".into(),
+            bfc: BfcCertification::NotApplicable,
+            headers: vec![
+                Header("KEYWORDS".into(), "Pirates, Caribbean, Ship".into()),
+                Header("KEYWORDS".into(), " Set 6285,".into()),
+            ],
+            commands: vec![],
+        };
+        assert_eq!(document.category(), Some("Brick"));
+        assert_eq!(
+            document.keywords(),
+            vec!["Pirates", "Caribbean", "Ship", "Set 6285"]
+        );
+
+        document
+            .headers
+            .push(Header("CATEGORY".into(), "Animal".into()));
+        assert_eq!(document.category(), Some("Animal"));
+    }
+
+    #[test]
+    fn test_textures() {
+        let document = Document {
+            name: "sticker.dat".into(),
+            description: "Sticker".into(),
+            author: "LDraw.rs".into(),
+            bfc: BfcCertification::NotApplicable,
+            headers: vec![],
+            commands: [
+                "START PLANAR -20 0 10 20 0 10 -20 0 -10 front.png",
+                "FALLBACK",
+                "END",
+                "NEXT CYLINDRICAL 0 0 0 0 -24 0 0 0 10 360 side.png GLOSSMAP shine.png",
+                "START PLANAR -20 0 10 20 0 10 -20 0 -10 front.png",
+            ]
+            .into_iter()
+            .map(|e| Command::Meta(Meta::TexMap(e.parse().unwrap())))
+            .collect(),
+        };
+        assert_eq!(
+            document.textures(),
+            vec!["front.png", "side.png", "shine.png"]
+        );
+    }
+
+    #[test]
+    fn test_default_color_from_cmdline() {
+        let materials = sample_materials();
+        let mut document = Document {
+            name: "73590a.dat".into(),
+            description: "Hose Flexible  8.5L without Tabs".into(),
+            author: "LDraw.rs".into(),
+            bfc: BfcCertification::NotApplicable,
+            headers: vec![],
+            commands: vec![],
+        };
+        assert!(document.default_color(&materials).is_none());
+
+        document
+            .headers
+            .push(Header("CMDLINE".into(), "-c4".into()));
+        let color = document.default_color(&materials).unwrap();
+        assert_eq!(color.get_material(), materials.get(&4));
+    }
+
+    #[test]
+    fn test_history_append() {
+        let mut document = Document {
+            name: "3001.dat".into(),
+            description: "Brick 2 x 4".into(),
+            author: "LDraw.rs".into(),
+            bfc: BfcCertification::NotApplicable,
+            headers: vec![
+                Header(
+                    "HISTORY".into(),
+                    "2002-04-25 [PTadmin] Official update 2002-02".into(),
+                ),
+                Header("HISTORY".into(), "2002-05-01 Missing author".into()),
+                Header("KEYWORDS".into(), "Brick".into()),
+            ],
+            commands: vec![],
+        };
+        let entry = History {
+            date: HistoryDate {
+                year: 2024,
+                month: 3,
+                day: 9,
+            },
+            author: HistoryAuthor::RealName("Jane Doe".into()),
+            message: "Fixed winding".into(),
+        };
+        document.push_history(&entry);
+
+        assert_eq!(
+            document.headers[2],
+            Header(
+                "HISTORY".into(),
+                "2024-03-09 {Jane Doe} Fixed winding".into()
+            )
+        );
+        let history = document.history().collect::<Vec<_>>();
+        assert_eq!(history.len(), 3);
+        assert!(history[1].is_err());
+        assert_eq!(history[2].as_ref().unwrap(), &entry);
+    }
+
+    #[test]
+    fn test_steps() {
+        let comment = |text: &str| Command::Meta(Meta::Comment(text.into()));
+        let document = DocumentBuilder::new("model.ldr", "Model", "")
+            .command(comment("a"))
+            .command(Command::Meta(Meta::Step))
+            .command(Command::Meta(Meta::Step))
+            .command(comment("b"))
+            .command(Command::Meta(Meta::RotStep(RotStep::End)))
+            .command(comment("c"))
+            .command(Command::Meta(Meta::Step))
+            .build();
+
+        assert_eq!(
+            document.steps().collect::<Vec<_>>(),
+            vec![&[comment("a")][..], &[], &[comment("b")], &[comment("c")]]
+        );
+        assert_eq!(
+            DocumentBuilder::new("empty.ldr", "Empty", "")
+                .build()
+                .steps()
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_document_builder_completes_headers() {
+        let document = DocumentBuilder::new("3001.dat", "Brick  2 x  4", "Jane Doe [jdoe]")
+            .header("HISTORY", "2024-03-09 [jdoe] Initial version")
+            .header("CATEGORY", "Brick")
+            .header("MYTOOL_SETTING", "1")
+            .header("HISTORY", "2024-03-10 [jdoe] Fixed winding")
+            .command(Command::Meta(Meta::Step))
+            .build();
+
+        assert_eq!(document.name, "3001.dat");
+        assert_eq!(document.bfc, BfcCertification::Certify(Winding::Ccw));
+        assert_eq!(
+            document
+                .headers
+                .iter()
+                .map(|Header(key, _)| key.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "LDRAW_ORG",
+                "LICENSE",
+                "CATEGORY",
+                "HISTORY",
+                "HISTORY",
+                "MYTOOL_SETTING"
+            ]
+        );
+        assert_eq!(document.headers[0].1, "Unofficial_Part");
+        assert_eq!(document.headers[3].1, "2024-03-09 [jdoe] Initial version");
+        assert_eq!(document.commands, vec![Command::Meta(Meta::Step)]);
+
+        let document = DocumentBuilder::new("s/3001s01.dat", "~Brick  2 x  4 Side", "Jane Doe")
+            .part_type("Unofficial_Subpart")
+            .header(
+                "LICENSE",
+                "Redistributable under CCAL version 2.0 : see CAreadme.txt",
+            )
+            .bfc(BfcCertification::Certify(Winding::Cw))
+            .build();
+        assert_eq!(
+            document.headers,
+            vec![
+                Header("LDRAW_ORG".into(), "Unofficial_Subpart".into()),
+                Header(
+                    "LICENSE".into(),
+                    "Redistributable under CCAL version 2.0 : see CAreadme.txt".into()
+                ),
+            ]
+        );
+    }
+
+    fn reference(name: &str) -> Command {
+        Command::PartReference(PartReference {
+            color: ColorReference::Current,
+            matrix: Matrix4::from_scale(1.0),
+            name: PartAlias::from(name),
+        })
+    }
+
+    fn subpart(name: &str, commands: Vec<Command>) -> (PartAlias, Document) {
+        (
+            PartAlias::from(name),
+            Document {
+                name: name.into(),
+                description: name.into(),
+                author: "LDraw.rs".into(),
+                bfc: BfcCertification::NotApplicable,
+                headers: vec![],
+                commands,
+            },
+        )
+    }
+
+    #[test]
+    fn test_find_circular_reference() {
+        let (_, body) = subpart("main.ldr", vec![reference("a.ldr"), reference("c.ldr")]);
+        let mut document = MultipartDocument {
+            body,
+            subparts: HashMap::from([
+                subpart("a.ldr", vec![reference("b.ldr"), reference("3001.dat")]),
+                subpart("b.ldr", vec![reference("3002.dat")]),
+                subpart("c.ldr", vec![reference("b.ldr")]),
+            ]),
+            data: HashMap::new(),
+        };
+        assert_eq!(document.find_circular_reference(), None);
+
+        document
+            .subparts
+            .get_mut(&PartAlias::from("b.ldr"))
+            .unwrap()
+            .commands
+            .push(reference("A.ldr"));
+        let cycle = document.find_circular_reference().unwrap();
+        assert_eq!(
+            cycle,
+            vec![
+                PartAlias::from("a.ldr"),
+                PartAlias::from("b.ldr"),
+                PartAlias::from("a.ldr")
+            ]
+        );
+
+        let dependencies = document.list_dependencies();
+        assert_eq!(dependencies.len(), 2);
+        assert!(dependencies.contains(&PartAlias::from("3002.dat")));
+    }
+
+    #[test]
+    fn test_unresolved_references() {
+        let (_, body) = subpart(
+            "main.ldr",
+            vec![
+                reference("a.ldr"),
+                Command::Meta(Meta::Step),
+                reference("3001.dat"),
+            ],
+        );
+        let document = MultipartDocument {
+            body,
+            subparts: HashMap::from([subpart(
+                "a.ldr",
+                vec![
+                    reference("3001.DAT"),
+                    reference("3002.dat"),
+                    Command::Meta(Meta::Mlcad(
+                        MlcadMode::Ghost,
+                        Box::new(reference("3003.dat")),
+                    )),
+                    Command::Meta(Meta::TexMap(TexMapStatement::Geometry(Box::new(
+                        reference("3004.dat"),
+                    )))),
+                ],
+            )]),
+            data: HashMap::new(),
+        };
+
+        let missing = document.unresolved_references(&ResolutionResult::new());
+        assert_eq!(missing.len(), 4);
+        assert_eq!(missing[0].alias, PartAlias::from("3001.dat"));
+        assert_eq!(missing[0].count(), 2);
+        assert!(missing[0].locations.contains(&ReferenceLocation {
+            subpart: None,
+            command: 2
+        }));
+        assert!(missing[0].locations.contains(&ReferenceLocation {
+            subpart: Some(PartAlias::from("a.ldr")),
+            command: 0
+        }));
+        assert_eq!(missing[1].alias, PartAlias::from("3002.dat"));
+        assert_eq!(missing[2].alias, PartAlias::from("3003.dat"));
+        assert_eq!(
+            missing[2].locations,
+            vec![ReferenceLocation {
+                subpart: Some(PartAlias::from("a.ldr")),
+                command: 2
+            }]
+        );
+        assert_eq!(missing[3].alias, PartAlias::from("3004.dat"));
+    }
+
+    #[test]
+    fn test_pack_and_unpack() {
+        let (_, body) = subpart(
+            "main.ldr",
+            vec![reference("wing.ldr"), reference("3001.dat")],
+        );
+        let root = MultipartDocument {
+            body,
+            subparts: HashMap::new(),
+            data: HashMap::new(),
+        };
+
+        let (_, wing) = subpart(
+            "wing.ldr",
+            vec![reference("engine.ldr"), reference("3002.dat")],
+        );
+        let (_, engine) = subpart("engine.ldr", vec![reference("inner.ldr")]);
+        let local = HashMap::from([
+            (
+                PartAlias::from("wing.ldr"),
+                Arc::new(MultipartDocument {
+                    body: wing,
+                    subparts: HashMap::new(),
+                    data: HashMap::new(),
+                }),
+            ),
+            (
+                PartAlias::from("engine.ldr"),
+                Arc::new(MultipartDocument {
+                    body: engine,
+                    subparts: HashMap::from([subpart("inner.ldr", vec![reference("3003.dat")])]),
+                    data: HashMap::new(),
+                }),
+            ),
+        ]);
+        let resolution = ResolutionResult::from_entries(HashMap::new(), local);
+
+        let mut packed = MultipartDocument::pack(&root, &resolution);
+        assert_eq!(packed.body, root.body);
+        assert_eq!(packed.subparts.len(), 3);
+        assert_eq!(
+            packed.list_dependencies(),
+            ["3001.dat", "3002.dat", "3003.dat"]
+                .into_iter()
+                .map(PartAlias::from)
+                .collect()
+        );
+
+        let files = packed
+            .unpack("model.ldr")
+            .into_iter()
+            .map(|(name, document)| (name, document.name))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            files,
+            vec![
+                ("main.ldr".to_string(), "main.ldr".to_string()),
+                ("engine.ldr".to_string(), "engine.ldr".to_string()),
+                ("inner.ldr".to_string(), "inner.ldr".to_string()),
+                ("wing.ldr".to_string(), "wing.ldr".to_string()),
+            ]
+        );
+
+        packed.body.name.clear();
+        assert_eq!(packed.unpack("model.ldr")[0].0, "model.ldr");
+        assert_eq!(
+            packed.dependency_graph("model.ldr").root,
+            PartAlias::from("model.ldr")
+        );
+    }
+
+    #[test]
+    fn test_placements_precision() {
+        // Each subpart places the next turned by 10 degrees and moved aside.
+        let step = Matrix4::from_translation(Vector3::new(10.0, 0.0, 0.0))
+            * Matrix4::from_angle_y(Deg(10.0));
+        let placed = |name: &str| {
+            Command::PartReference(PartReference {
+                color: ColorReference::Current,
+                matrix: step,
+                name: PartAlias::from(name),
+            })
+        };
+        let depth = 200;
+        let (_, body) = subpart("main.ldr", vec![placed("s0.ldr")]);
+        let document = MultipartDocument {
+            body,
+            subparts: (0..depth)
+                .map(|i| {
+                    let next = match i + 1 {
+                        e if e == depth => "3001.dat".to_string(),
+                        e => format!("s{}.ldr", e),
+                    };
+                    subpart(&format!("s{}.ldr", i), vec![placed(&next)])
+                })
+                .collect(),
+            data: HashMap::new(),
+        };
+
+        let (mut exact, mut single) = (Matrix4d::identity(), Matrix4::identity());
+        for _ in 0..=depth {
+            exact = exact * matrix_to_f64(&step);
+            single = single * step;
+        }
+        let error = |matrix: &Matrix4| {
+            (0..4)
+                .flat_map(|c| (0..4).map(move |r| (c, r)))
+                .map(|(c, r)| (matrix[c][r] as f64 - exact[c][r]).abs())
+                .fold(0.0, f64::max)
+        };
+
+        let placements = document.placements();
+        assert_eq!(placements.len(), 1);
+        assert_eq!(placements[0].0, PartAlias::from("3001.dat"));
+        assert!(error(&placements[0].1) * 10.0 < error(&single));
+        assert_eq!(document.placements_f64()[0].1, exact);
+    }
+
+    #[test]
+    fn test_dependency_graph() {
+        let (_, body) = subpart(
+            "main.ldr",
+            vec![
+                reference("wheel.ldr"),
+                reference("wheel.ldr"),
+                reference("chassis.ldr"),
+                reference("3001.dat"),
+            ],
+        );
+        let mut document = MultipartDocument {
+            body,
+            subparts: HashMap::from([
+                subpart(
+                    "chassis.ldr",
+                    vec![reference("wheel.ldr"), reference("3002.dat")],
+                ),
+                subpart("wheel.ldr", vec![reference("3003.dat")]),
+                subpart("spare.ldr", vec![reference("wheel.ldr")]),
+            ]),
+            data: HashMap::new(),
+        };
+
+        let graph = document.dependency_graph("model.ldr");
+        assert_eq!(
+            graph.children(&PartAlias::from("main.ldr")),
+            [
+                (PartAlias::from("chassis.ldr"), 1),
+                (PartAlias::from("wheel.ldr"), 2)
+            ]
+        );
+        assert_eq!(graph.reference_count(&PartAlias::from("wheel.ldr")), 4);
+        assert_eq!(graph.unused(), vec![PartAlias::from("spare.ldr")]);
+        assert_eq!(
+            graph.topological_order().unwrap(),
+            vec![
+                PartAlias::from("main.ldr"),
+                PartAlias::from("chassis.ldr"),
+                PartAlias::from("wheel.ldr")
+            ]
+        );
+
+        document
+            .subparts
+            .get_mut(&PartAlias::from("wheel.ldr"))
+            .unwrap()
+            .commands
+            .push(reference("chassis.ldr"));
+        assert!(matches!(
+            document.dependency_graph("model.ldr").topological_order(),
+            Err(ResolutionError::CircularReference(_))
+        ));
+    }
 }
@@ -1,10 +1,150 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
 use crate::color::ColorReference;
-use crate::{Matrix4, PartAlias, Vector4, Winding};
+use crate::error::ParseError;
+use crate::{Matrix4, PartAlias, Vector3, Vector4, Winding};
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Header(pub String, pub String);
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct HistoryDate {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+}
+
+impl FromStr for HistoryDate {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mismatch = || ParseError::TypeMismatch("HistoryDate", s.to_string());
+
+        let mut parts = s.splitn(3, '-');
+        let mut next = |len: usize| match parts.next() {
+            Some(e) if e.len() == len && e.bytes().all(|ch| ch.is_ascii_digit()) => {
+                e.parse::<u16>().map_err(|_| mismatch())
+            }
+            _ => Err(mismatch()),
+        };
+        let (year, month, day) = (next(4)?, next(2)?, next(2)?);
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+            return Err(mismatch());
+        }
+
+        Ok(HistoryDate {
+            year,
+            month: month as u8,
+            day: day as u8,
+        })
+    }
+}
+
+impl fmt::Display for HistoryDate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HistoryAuthor {
+    // `{Real Name}`, used for authors without an LDraw.org account.
+    RealName(String),
+    // `[username]`
+    Username(String),
+}
+
+impl fmt::Display for HistoryAuthor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HistoryAuthor::RealName(name) => write!(f, "{{{}}}", name),
+            HistoryAuthor::Username(name) => write!(f, "[{}]", name),
+        }
+    }
+}
+
+// `0 !HISTORY 2002-04-25 [PTadmin] Official update 2002-02`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct History {
+    pub date: HistoryDate,
+    pub author: HistoryAuthor,
+    pub message: String,
+}
+
+impl History {
+    pub fn to_header(&self) -> Header {
+        Header("HISTORY".to_string(), self.to_string())
+    }
+}
+
+impl FromStr for History {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (date, rest) = s
+            .split_once(char::is_whitespace)
+            .ok_or(ParseError::EndOfLine)?;
+        let date = date.parse()?;
+
+        let rest = rest.trim_start();
+        let (close, kind): (char, fn(String) -> HistoryAuthor) = match rest.chars().next() {
+            Some('{') => ('}', HistoryAuthor::RealName),
+            Some('[') => (']', HistoryAuthor::Username),
+            _ => return Err(ParseError::InvalidToken(rest.to_string())),
+        };
+        let (name, message) = rest[1..]
+            .split_once(close)
+            .ok_or_else(|| ParseError::InvalidToken(rest.to_string()))?;
+
+        Ok(History {
+            date,
+            author: kind(name.trim().to_string()),
+            message: message.trim().to_string(),
+        })
+    }
+}
+
+impl fmt::Display for History {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {}", self.date, self.author, self.message)
+    }
+}
+
+// `0 !CMDLINE -c1`, the LDRAW.EXE options a part is meant to be rendered with.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CmdLine {
+    pub arguments: Vec<String>,
+}
+
+impl CmdLine {
+    // `-cN` sets the default color of flexible part shortcuts.
+    pub fn color_code(&self) -> Option<u32> {
+        self.arguments.iter().find_map(|e| {
+            e.strip_prefix("-c")
+                .or_else(|| e.strip_prefix("-C"))
+                .and_then(|code| code.parse().ok())
+        })
+    }
+}
+
+impl FromStr for CmdLine {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let arguments = s.split_whitespace().map(str::to_string).collect::<Vec<_>>();
+        if arguments.is_empty() {
+            return Err(ParseError::EndOfLine);
+        }
+
+        Ok(CmdLine { arguments })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum BfcStatement {
     Winding(Winding),
     Clip(Option<Winding>),
@@ -12,33 +152,261 @@ pub enum BfcStatement {
     InvertNext,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+// Projection of a `!TEXMAP` texture onto the geometry, with points in the
+// coordinates of the file the statement appears in.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TexMapProjection {
+    // p1 is the origin, p1-p2 spans U and p1-p3 spans V.
+    Planar {
+        p1: Vector3,
+        p2: Vector3,
+        p3: Vector3,
+    },
+    // p1 is the bottom center and p2 the top center; p3 is on the surface
+    // where U is 0.5. `angle` is the extent of the texture in degrees.
+    Cylindrical {
+        p1: Vector3,
+        p2: Vector3,
+        p3: Vector3,
+        angle: f32,
+    },
+    // p1 is the center; p2 is on the surface in the middle of the texture,
+    // p3 forms the plane of the equator with them. The angles are the
+    // horizontal and vertical extent in degrees.
+    Spherical {
+        p1: Vector3,
+        p2: Vector3,
+        p3: Vector3,
+        angle1: f32,
+        angle2: f32,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TexMap {
+    pub projection: TexMapProjection,
+    pub texture: String,
+    pub glossmap: Option<String>,
+}
+
+impl FromStr for TexMap {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tokens = s.split_whitespace();
+        let method = tokens.next().ok_or(ParseError::EndOfLine)?;
+        let count = match method {
+            "PLANAR" => 9,
+            "CYLINDRICAL" => 10,
+            "SPHERICAL" => 11,
+            _ => return Err(ParseError::InvalidToken(method.to_string())),
+        };
+        let mut values = Vec::with_capacity(count);
+        for _ in 0..count {
+            let token = tokens.next().ok_or(ParseError::EndOfLine)?;
+            values.push(
+                token
+                    .parse::<f32>()
+                    .map_err(|_| ParseError::TypeMismatch("f32", token.to_string()))?,
+            );
+        }
+        let (p1, p2, p3) = (
+            Vector3::new(values[0], values[1], values[2]),
+            Vector3::new(values[3], values[4], values[5]),
+            Vector3::new(values[6], values[7], values[8]),
+        );
+        let projection = match method {
+            "PLANAR" => TexMapProjection::Planar { p1, p2, p3 },
+            "CYLINDRICAL" => TexMapProjection::Cylindrical {
+                p1,
+                p2,
+                p3,
+                angle: values[9],
+            },
+            _ => TexMapProjection::Spherical {
+                p1,
+                p2,
+                p3,
+                angle1: values[9],
+                angle2: values[10],
+            },
+        };
+
+        let texture = tokens.next().ok_or(ParseError::EndOfLine)?.to_string();
+        let glossmap = match (tokens.next(), tokens.next()) {
+            (Some("GLOSSMAP"), Some(glossmap)) => Some(glossmap.to_string()),
+            _ => None,
+        };
+
+        Ok(TexMap {
+            projection,
+            texture,
+            glossmap,
+        })
+    }
+}
+
+impl fmt::Display for TexMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (method, p1, p2, p3, angles) = match &self.projection {
+            TexMapProjection::Planar { p1, p2, p3 } => ("PLANAR", p1, p2, p3, vec![]),
+            TexMapProjection::Cylindrical { p1, p2, p3, angle } => {
+                ("CYLINDRICAL", p1, p2, p3, vec![*angle])
+            }
+            TexMapProjection::Spherical {
+                p1,
+                p2,
+                p3,
+                angle1,
+                angle2,
+            } => ("SPHERICAL", p1, p2, p3, vec![*angle1, *angle2]),
+        };
+        write!(f, "{}", method)?;
+        for p in [p1, p2, p3] {
+            write!(f, " {} {} {}", p.x, p.y, p.z)?;
+        }
+        for angle in angles {
+            write!(f, " {}", angle)?;
+        }
+        write!(f, " {}", self.texture)?;
+        if let Some(glossmap) = &self.glossmap {
+            write!(f, " GLOSSMAP {}", glossmap)?;
+        }
+        Ok(())
+    }
+}
+
+// `0 !TEXMAP` statements. `Start` applies to everything up to `Fallback`
+// (or `End`), `Next` to the following line only. Lines between `Fallback`
+// and `End` are for renderers without texture support, and `Geometry`
+// holds `0 !:` lines meant only for renderers with it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TexMapStatement {
+    Start(TexMap),
+    Next(TexMap),
+    Fallback,
+    End,
+    Geometry(Box<Command>),
+}
+
+impl FromStr for TexMapStatement {
+    type Err = ParseError;
+
+    // Parses everything after `0 !TEXMAP`; `0 !:` lines are handled by the
+    // document parser.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (keyword, rest) = s.split_once(char::is_whitespace).unwrap_or((s, ""));
+        match keyword {
+            "START" => Ok(TexMapStatement::Start(rest.parse()?)),
+            "NEXT" => Ok(TexMapStatement::Next(rest.parse()?)),
+            "FALLBACK" => Ok(TexMapStatement::Fallback),
+            "END" => Ok(TexMapStatement::End),
+            "" => Err(ParseError::EndOfLine),
+            _ => Err(ParseError::InvalidToken(keyword.to_string())),
+        }
+    }
+}
+
+// How the angles of a `0 ROTSTEP` combine with the view rotation in effect.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RotStepMode {
+    // Relative to the default view.
+    Relative,
+    Absolute,
+    // Added to the current rotation.
+    Additive,
+}
+
+// `0 ROTSTEP` ends a step like `0 STEP` and rotates the view by the given
+// angles in degrees around x, y and z. `End` goes back to the default view.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RotStep {
+    Rotation(Vector3, RotStepMode),
+    End,
+}
+
+impl FromStr for RotStep {
+    type Err = ParseError;
+
+    // Parses everything after `0 ROTSTEP`. The mode defaults to `REL`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim() == "END" {
+            return Ok(RotStep::End);
+        }
+        let mut tokens = s.split_whitespace();
+        let mut angle = || {
+            let token = tokens.next().ok_or(ParseError::EndOfLine)?;
+            token
+                .parse::<f32>()
+                .map_err(|_| ParseError::TypeMismatch("f32", token.to_string()))
+        };
+        let rotation = Vector3::new(angle()?, angle()?, angle()?);
+        let mode = match tokens.next() {
+            None | Some("REL") => RotStepMode::Relative,
+            Some("ABS") => RotStepMode::Absolute,
+            Some("ADD") => RotStepMode::Additive,
+            Some(token) => return Err(ParseError::InvalidToken(token.to_string())),
+        };
+
+        Ok(RotStep::Rotation(rotation, mode))
+    }
+}
+
+impl fmt::Display for RotStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RotStep::Rotation(rotation, mode) => {
+                let mode = match mode {
+                    RotStepMode::Relative => "REL",
+                    RotStepMode::Absolute => "ABS",
+                    RotStepMode::Additive => "ADD",
+                };
+                write!(f, "{} {} {} {}", rotation.x, rotation.y, rotation.z, mode)
+            }
+            RotStep::End => write!(f, "END"),
+        }
+    }
+}
+
+// MLCad prefixes drawing the rest of the line grayed out (`0 GHOST`) or not
+// at all (`0 MLCAD HIDE`).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub enum MlcadMode {
+    Ghost,
+    Hide,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Meta {
     Comment(String),
     Step,
+    RotStep(RotStep),
     Write(String),
     Print(String),
     Clear,
     Pause,
     Save,
     Bfc(BfcStatement),
+    TexMap(TexMapStatement),
+    Mlcad(MlcadMode, Box<Command>),
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct PartReference {
     pub color: ColorReference,
     pub matrix: Matrix4,
     pub name: PartAlias,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Line {
     pub color: ColorReference,
     pub a: Vector4,
     pub b: Vector4,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Triangle {
     pub color: ColorReference,
     pub a: Vector4,
@@ -46,7 +414,7 @@ pub struct Triangle {
     pub c: Vector4,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Quad {
     pub color: ColorReference,
     pub a: Vector4,
@@ -55,7 +423,7 @@ pub struct Quad {
     pub d: Vector4,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct OptionalLine {
     pub color: ColorReference,
     pub a: Vector4,
@@ -64,7 +432,7 @@ pub struct OptionalLine {
     pub d: Vector4,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Command {
     Meta(Meta),
     PartReference(PartReference),
@@ -73,3 +441,83 @@ pub enum Command {
     Quad(Quad),
     OptionalLine(OptionalLine),
 }
+
+impl Command {
+    // The part reference of the command, if it is one or one behind an MLCad
+    // prefix or `0 !:`, with the MLCad prefix.
+    pub fn part_reference(&self) -> Option<(&PartReference, Option<MlcadMode>)> {
+        match self {
+            Command::PartReference(e) => Some((e, None)),
+            Command::Meta(Meta::Mlcad(mode, inner)) => match inner.as_ref() {
+                Command::PartReference(e) => Some((e, Some(*mode))),
+                _ => None,
+            },
+            Command::Meta(Meta::TexMap(TexMapStatement::Geometry(inner))) => match inner.as_ref() {
+                Command::PartReference(e) => Some((e, None)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CmdLine, History, HistoryAuthor, HistoryDate};
+
+    #[test]
+    fn test_cmdline_color_code() {
+        let cmdline = "-c1".parse::<CmdLine>().unwrap();
+        assert_eq!(cmdline.color_code(), Some(1));
+
+        let cmdline = "-s2  -C14".parse::<CmdLine>().unwrap();
+        assert_eq!(cmdline.arguments, vec!["-s2", "-C14"]);
+        assert_eq!(cmdline.color_code(), Some(14));
+
+        assert_eq!("-s2".parse::<CmdLine>().unwrap().color_code(), None);
+        assert!(" ".parse::<CmdLine>().is_err());
+    }
+
+    #[test]
+    fn test_history_roundtrip() {
+        let cases = [
+            (
+                "2002-04-25 [PTadmin] Official update 2002-02",
+                HistoryAuthor::Username("PTadmin".into()),
+                "Official update 2002-02",
+            ),
+            (
+                "1999-07-05 {Steve Bliss} Made BFC compliant",
+                HistoryAuthor::RealName("Steve Bliss".into()),
+                "Made BFC compliant",
+            ),
+        ];
+        for (input, author, message) in cases {
+            let history = input.parse::<History>().unwrap();
+            assert_eq!(history.author, author);
+            assert_eq!(history.message, message);
+            assert_eq!(history.to_string(), input);
+        }
+    }
+
+    #[test]
+    fn test_history_rejects_malformed_entries() {
+        for input in [
+            "2002-4-25 [PTadmin] Update",
+            "2002-13-01 [PTadmin] Update",
+            "2002-04-25 PTadmin Update",
+            "2002-04-25 [PTadmin Update",
+            "2002-04-25",
+        ] {
+            assert!(input.parse::<History>().is_err(), "{}", input);
+        }
+        assert_eq!(
+            "2010-01-31".parse::<HistoryDate>().unwrap(),
+            HistoryDate {
+                year: 2010,
+                month: 1,
+                day: 31
+            }
+        );
+    }
+}
@@ -1,5 +1,7 @@
 use std::{error::Error, fmt, io::Error as IoError};
 
+use crate::PartAlias;
+
 #[cfg(any(target_arch = "wasm32", feature = "http"))]
 use reqwest::Error as ReqwestError;
 
@@ -36,6 +38,7 @@ pub enum ParseError {
     UnexpectedCommand(String),
     InvalidToken(String),
     MultipartDocument,
+    Cancelled,
 }
 
 impl From<IoError> for ParseError {
@@ -57,6 +60,7 @@ impl fmt::Display for ParseError {
             ParseError::UnexpectedCommand(cmd) => write!(f, "Unexpected command: {}", cmd),
             ParseError::InvalidToken(token) => write!(f, "Invalid token: {}", token),
             ParseError::MultipartDocument => write!(f, "Unexpected multipart document."),
+            ParseError::Cancelled => write!(f, "Parsing was cancelled."),
         }
     }
 }
@@ -127,6 +131,47 @@ impl Error for ColorDefinitionParseError {
     }
 }
 
+#[derive(Debug)]
+pub enum ImportError {
+    IoError(Box<IoError>),
+    MalformedXml(String),
+    ParseError(ParseError),
+    MissingModel,
+}
+
+impl From<IoError> for ImportError {
+    fn from(e: IoError) -> ImportError {
+        ImportError::IoError(Box::new(e))
+    }
+}
+
+impl From<ParseError> for ImportError {
+    fn from(e: ParseError) -> ImportError {
+        ImportError::ParseError(e)
+    }
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportError::IoError(err) => write!(f, "{}", err),
+            ImportError::MalformedXml(err) => write!(f, "Malformed XML: {}", err),
+            ImportError::ParseError(err) => write!(f, "{}", err),
+            ImportError::MissingModel => write!(f, "No model found in the archive."),
+        }
+    }
+}
+
+impl Error for ImportError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ImportError::IoError(e) => Some(e),
+            ImportError::ParseError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum SerializeError {
     NoSerializable,
@@ -165,6 +210,8 @@ pub enum ResolutionError {
     DocumentParseError(DocumentParseError),
     ColorDefinitionParseError(ColorDefinitionParseError),
     RemoteError(ReqwestError),
+    CircularReference(Vec<PartAlias>),
+    Cancelled,
 }
 
 impl From<IoError> for ResolutionError {
@@ -200,6 +247,11 @@ impl fmt::Display for ResolutionError {
             ResolutionError::DocumentParseError(err) => write!(f, "{}", err),
             ResolutionError::ColorDefinitionParseError(err) => write!(f, "{}", err),
             ResolutionError::RemoteError(err) => write!(f, "{}", err),
+            ResolutionError::CircularReference(cycle) => {
+                let names = cycle.iter().map(|e| &*e.original).collect::<Vec<_>>();
+                write!(f, "Circular reference: {}", names.join(" -> "))
+            }
+            ResolutionError::Cancelled => write!(f, "Resolution was cancelled."),
         }
     }
 }
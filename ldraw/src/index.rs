@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+
+use async_std::io::{Write, WriteExt};
+
+use crate::{
+    color::MaterialRegistry,
+    document::Document,
+    error::SerializeError,
+    parser::{parse_single_document_with_options, ParseOptions},
+    PartAlias,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PartIndexEntry {
+    pub alias: PartAlias,
+    pub description: String,
+    pub category: Option<String>,
+    pub keywords: Vec<String>,
+}
+
+// Alias to description/category index of a parts library, the in-memory
+// equivalent of parts.lst.
+#[derive(Clone, Debug, Default)]
+pub struct PartIndex {
+    entries: HashMap<PartAlias, PartIndexEntry>,
+}
+
+impl PartIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, alias: PartAlias, document: &Document) {
+        let entry = PartIndexEntry {
+            alias: alias.clone(),
+            description: document.description.clone(),
+            category: document.category().map(str::to_string),
+            keywords: document
+                .keywords()
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+        };
+        self.entries.insert(alias, entry);
+    }
+
+    pub fn get(&self, alias: &PartAlias) -> Option<&PartIndexEntry> {
+        self.entries.get(alias)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Entries ordered by name.
+    pub fn entries(&self) -> Vec<&PartIndexEntry> {
+        let mut entries = self.entries.values().collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.alias.normalized.cmp(&b.alias.normalized));
+        entries
+    }
+
+    pub fn in_category<'a>(&'a self, category: &str) -> Vec<&'a PartIndexEntry> {
+        self.entries()
+            .into_iter()
+            .filter(|e| e.category.as_deref() == Some(category))
+            .collect()
+    }
+
+    // Like mklist, parts marked with `~` (subparts, moved and obsolete
+    // files) are left out.
+    pub fn to_parts_lst(&self) -> String {
+        self.entries()
+            .into_iter()
+            .filter(|e| !e.description.starts_with('~'))
+            .map(|e| format!("{:<30} {}\n", e.alias.original, e.description))
+            .collect()
+    }
+
+    pub async fn write_parts_lst(
+        &self,
+        writer: &mut (dyn Write + Unpin + Send),
+    ) -> Result<(), SerializeError> {
+        writer.write_all(self.to_parts_lst().as_bytes()).await?;
+        Ok(())
+    }
+}
+
+// Only the header is needed, so stop at the first line that isn't a meta
+// command and parse leniently; broken geometry shouldn't drop a part.
+async fn parse_header(contents: &[u8]) -> Option<Document> {
+    let contents = String::from_utf8_lossy(contents);
+    let header = contents
+        .lines()
+        .take_while(|line| matches!(line.trim_start().chars().next(), None | Some('0')))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    parse_single_document_with_options(
+        &MaterialRegistry::new(),
+        &ParseOptions::lenient(),
+        &mut header.as_bytes(),
+    )
+    .await
+    .ok()
+}
+
+fn is_part_file(name: &str) -> bool {
+    name.to_ascii_lowercase().ends_with(".dat")
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod scan {
+    use async_std::{fs, path::Path};
+    use futures::StreamExt;
+
+    use super::{is_part_file, parse_header, PartIndex};
+    use crate::{error::ResolutionError, PartAlias};
+
+    // Indexes the `parts` directory of an LDraw library, without descending
+    // into subdirectories, the same set of files mklist lists.
+    pub async fn scan_library(ldrawdir: &Path) -> Result<PartIndex, ResolutionError> {
+        let parts = ldrawdir.join("parts");
+        if !parts.exists().await {
+            return Err(ResolutionError::NoLDrawDir);
+        }
+
+        let mut index = PartIndex::new();
+        let mut entries = fs::read_dir(&parts).await?;
+        while let Some(entry) = entries.next().await {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !is_part_file(&name) || !entry.file_type().await?.is_file() {
+                continue;
+            }
+
+            let contents = fs::read(entry.path()).await?;
+            if let Some(document) = parse_header(&contents).await {
+                index.insert(PartAlias::from(name), &document);
+            }
+        }
+
+        Ok(index)
+    }
+
+    // Indexes an LDraw library archive such as complete.zip, where parts are
+    // stored under `ldraw/parts/`.
+    #[cfg(feature = "zip")]
+    pub async fn scan_archive<R: std::io::Read + std::io::Seek>(
+        reader: R,
+    ) -> Result<PartIndex, ResolutionError> {
+        use std::io::{Error as IoError, Read};
+
+        let mut archive = zip::ZipArchive::new(reader).map_err(IoError::from)?;
+        let mut index = PartIndex::new();
+        for i in 0..archive.len() {
+            let (name, contents) = {
+                let mut file = archive.by_index(i).map_err(IoError::from)?;
+                let path = file.name().replace('\\', "/");
+                let path = path.strip_prefix("ldraw/").unwrap_or(&path);
+                let name = match path.strip_prefix("parts/") {
+                    Some(e) if !e.contains('/') && is_part_file(e) => e.to_string(),
+                    _ => continue,
+                };
+
+                let mut contents = Vec::new();
+                file.read_to_end(&mut contents)?;
+                (name, contents)
+            };
+            if let Some(document) = parse_header(&contents).await {
+                index.insert(PartAlias::from(name), &document);
+            }
+        }
+
+        Ok(index)
+    }
+}
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "zip"))]
+pub use scan::scan_archive;
+#[cfg(not(target_arch = "wasm32"))]
+pub use scan::scan_library;
+
+#[cfg(test)]
+mod tests {
+    use crate::PartAlias;
+
+    use super::{parse_header, PartIndex};
+
+    #[async_std::test]
+    async fn test_part_index() {
+        let mut index = PartIndex::new();
+        for (name, contents) in [
+            (
+                "3001.dat",
+                "0 Brick  2 x  4\n0 Name: 3001.dat\n0 !CATEGORY Brick\n1 16 0 0 0 1 0 0 0 1 0 0 0 1 s/3001s01.dat\n",
+            ),
+            ("3002.dat", "0 Brick  2 x  3\n0 Name: 3002.dat\n7 broken line\n"),
+            ("973p01.dat", "0 Minifig Torso with Pattern\n0 Name: 973p01.dat\n"),
+            ("3001old.dat", "0 ~Moved to 3001\n0 Name: 3001old.dat\n"),
+        ] {
+            let document = parse_header(contents.as_bytes()).await.unwrap();
+            index.insert(PartAlias::from(name), &document);
+        }
+
+        assert_eq!(index.len(), 4);
+        let entry = index.get(&PartAlias::from("3002.DAT")).unwrap();
+        assert_eq!(entry.description, "Brick  2 x  3");
+        assert_eq!(entry.category.as_deref(), Some("Brick"));
+        assert_eq!(index.in_category("Brick").len(), 2);
+        assert_eq!(
+            index.to_parts_lst(),
+            format!(
+                "{:<30} Brick  2 x  4\n{:<30} Brick  2 x  3\n{:<30} Minifig Torso with Pattern\n",
+                "3001.dat", "3002.dat", "973p01.dat"
+            )
+        );
+    }
+    #[cfg(all(not(target_arch = "wasm32"), feature = "zip"))]
+    #[async_std::test]
+    async fn test_scan_archive() {
+        use std::io::{Cursor, Write};
+
+        use zip::{write::FileOptions, ZipWriter};
+
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, contents) in [
+            ("ldraw/parts/3001.dat", "0 Brick  2 x  4\n0 Name: 3001.dat\n"),
+            ("ldraw/parts/s/3001s01.dat", "0 ~Brick  2 x  4 without Front Face\n"),
+            ("ldraw/p/stud.dat", "0 Stud\n"),
+        ] {
+            writer.start_file(name, FileOptions::default()).unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        }
+        let archive = writer.finish().unwrap();
+
+        let index = super::scan_archive(archive).await.unwrap();
+        assert_eq!(index.len(), 1);
+        assert_eq!(
+            index.get(&PartAlias::from("3001.dat")).unwrap().description,
+            "Brick  2 x  4"
+        );
+    }
+}
@@ -0,0 +1,389 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::Read,
+};
+
+use cgmath::{InnerSpace, Rad, SquareMatrix, Zero};
+use xml::{
+    attribute::OwnedAttribute,
+    reader::{EventReader, XmlEvent},
+};
+
+use crate::{
+    color::{ColorReference, MaterialRegistry},
+    document::{BfcCertification, Document, DocumentBuilder},
+    elements::{Command, PartReference},
+    error::{ImportError, ParseError},
+    Matrix4, PartAlias, Vector3,
+};
+
+// LDD measures in units where a stud is 0.8 wide, LDraw in LDU where it is 20.
+const LDU_PER_LDD_UNIT: f32 = 25.0;
+
+// The most common LDD materials; anything else needs an ldraw.xml mapping.
+const DEFAULT_MATERIALS: &[(u32, u32)] = &[
+    (1, 15),
+    (5, 19),
+    (21, 4),
+    (23, 1),
+    (24, 14),
+    (26, 0),
+    (28, 2),
+    (37, 10),
+    (40, 47),
+    (41, 36),
+    (43, 33),
+    (44, 46),
+    (48, 34),
+    (102, 73),
+    (106, 25),
+    (119, 27),
+    (138, 28),
+    (140, 272),
+    (141, 288),
+    (154, 320),
+    (192, 70),
+    (194, 71),
+    (199, 72),
+];
+
+// Design ID and material translation, in the format of the ldraw.xml file
+// LDD itself ships. Design IDs without an entry map to `<id>.dat`.
+#[derive(Clone, Debug)]
+pub struct LddMapping {
+    bricks: HashMap<String, String>,
+    materials: HashMap<u32, u32>,
+    transformations: HashMap<String, Matrix4>,
+}
+
+impl Default for LddMapping {
+    fn default() -> Self {
+        LddMapping {
+            bricks: HashMap::new(),
+            materials: DEFAULT_MATERIALS.iter().copied().collect(),
+            transformations: HashMap::new(),
+        }
+    }
+}
+
+fn attribute<'a>(attributes: &'a [OwnedAttribute], name: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|e| e.name.local_name.eq_ignore_ascii_case(name))
+        .map(|e| e.value.as_str())
+}
+
+fn parse_value<T: std::str::FromStr>(value: &str) -> Result<T, ParseError> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| ParseError::TypeMismatch(std::any::type_name::<T>(), value.to_string()))
+}
+
+fn elements<R: Read>(
+    reader: R,
+) -> impl Iterator<Item = Result<(String, Vec<OwnedAttribute>), ImportError>> {
+    EventReader::new(reader)
+        .into_iter()
+        .filter_map(|event| match event {
+            Ok(XmlEvent::StartElement {
+                name, attributes, ..
+            }) => Some(Ok((name.local_name, attributes))),
+            Ok(_) => None,
+            Err(e) => Some(Err(ImportError::MalformedXml(e.to_string()))),
+        })
+}
+
+impl LddMapping {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Entries from ldraw.xml are added on top of the built-in materials.
+    pub fn from_ldraw_xml<R: Read>(reader: R) -> Result<Self, ImportError> {
+        let mut mapping = Self::default();
+
+        for element in elements(reader) {
+            let (name, attributes) = element?;
+            let ldraw = match attribute(&attributes, "ldraw") {
+                Some(e) => e,
+                None => continue,
+            };
+            match name.as_str() {
+                "Material" => {
+                    if let Some(lego) = attribute(&attributes, "lego") {
+                        mapping.insert_material(parse_value(lego)?, parse_value(ldraw)?);
+                    }
+                }
+                "Brick" => {
+                    if let Some(lego) = attribute(&attributes, "lego") {
+                        mapping.insert_brick(lego, ldraw);
+                    }
+                }
+                "Transformation" => {
+                    let value = |name: &str| -> Result<f32, ParseError> {
+                        parse_value(attribute(&attributes, name).unwrap_or("0"))
+                    };
+                    let translation = Vector3::new(value("tx")?, value("ty")?, value("tz")?);
+                    let axis = Vector3::new(value("ax")?, value("ay")?, value("az")?);
+                    let rotation = if axis.is_zero() {
+                        Matrix4::identity()
+                    } else {
+                        Matrix4::from_axis_angle(axis.normalize(), Rad(value("angle")?))
+                    };
+                    mapping.transformations.insert(
+                        PartAlias::normalize(ldraw),
+                        Matrix4::from_translation(translation * LDU_PER_LDD_UNIT) * rotation,
+                    );
+                }
+                _ => (),
+            }
+        }
+
+        Ok(mapping)
+    }
+
+    pub fn insert_brick(&mut self, design_id: &str, ldraw: &str) {
+        self.bricks.insert(design_id.to_string(), ldraw.to_string());
+    }
+
+    pub fn insert_material(&mut self, material: u32, code: u32) {
+        self.materials.insert(material, code);
+    }
+
+    pub fn part(&self, design_id: &str) -> PartAlias {
+        match self.bricks.get(design_id) {
+            Some(e) => PartAlias::from(e),
+            None => PartAlias::from(format!("{}.dat", design_id)),
+        }
+    }
+
+    pub fn color(&self, material: u32) -> Option<u32> {
+        self.materials.get(&material).copied()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LddModel {
+    pub document: Document,
+    // LDD materials without an LDraw color; these parts use color 16.
+    pub unmapped_materials: BTreeSet<u32>,
+}
+
+// LDD is Y-up with Z towards the viewer, LDraw is Y-down with Z away.
+fn ldd_to_ldraw(ldd: Matrix4) -> Matrix4 {
+    let flip = Matrix4::from_nonuniform_scale(1.0, -1.0, -1.0);
+    flip * ldd * flip
+}
+
+fn parse_transformation(value: &str) -> Result<Matrix4, ParseError> {
+    let v = value
+        .split(',')
+        .map(parse_value::<f32>)
+        .collect::<Result<Vec<_>, _>>()?;
+    if v.len() != 12 {
+        return Err(ParseError::InvalidToken(value.to_string()));
+    }
+
+    let s = LDU_PER_LDD_UNIT;
+    Ok(Matrix4::new(
+        v[0],
+        v[1],
+        v[2],
+        0.0,
+        v[3],
+        v[4],
+        v[5],
+        0.0,
+        v[6],
+        v[7],
+        v[8],
+        0.0,
+        v[9] * s,
+        v[10] * s,
+        v[11] * s,
+        1.0,
+    ))
+}
+
+// Converts the brick placements of an LXFML file into a flat model. Only the
+// first bone of each part is used, so flexible parts come out straight.
+pub fn import_lxfml<R: Read>(
+    reader: R,
+    mapping: &LddMapping,
+    materials: &MaterialRegistry,
+) -> Result<LddModel, ImportError> {
+    let mut name = String::from("Untitled");
+    let mut commands = Vec::new();
+    let mut unmapped_materials = BTreeSet::new();
+    let mut part: Option<(PartAlias, u32)> = None;
+
+    for element in elements(reader) {
+        let (element, attributes) = element?;
+        match element.as_str() {
+            "LXFML" => {
+                if let Some(e) = attribute(&attributes, "name").filter(|e| !e.is_empty()) {
+                    name = e.to_string();
+                }
+            }
+            "Part" => {
+                let design_id = attribute(&attributes, "designID")
+                    .ok_or_else(|| ParseError::InvalidToken(String::from("designID")))?;
+                let material = attribute(&attributes, "materials")
+                    .and_then(|e| e.split(',').next())
+                    .unwrap_or("0");
+                part = Some((mapping.part(design_id), parse_value(material)?));
+            }
+            "Bone" => {
+                let (alias, material) = match part.take() {
+                    Some(e) => e,
+                    None => continue,
+                };
+                let transformation = attribute(&attributes, "transformation")
+                    .ok_or_else(|| ParseError::InvalidToken(String::from("transformation")))?;
+                let mut matrix = parse_transformation(transformation)?;
+                if let Some(correction) = mapping.transformations.get(&*alias.normalized) {
+                    matrix = matrix * correction;
+                }
+
+                let color = match mapping.color(material) {
+                    Some(code) => ColorReference::resolve(code, materials),
+                    None => {
+                        unmapped_materials.insert(material);
+                        ColorReference::Current
+                    }
+                };
+                commands.push(Command::PartReference(PartReference {
+                    color,
+                    matrix: ldd_to_ldraw(matrix),
+                    name: alias,
+                }));
+            }
+            _ => (),
+        }
+    }
+
+    let document = DocumentBuilder::new(&format!("{}.ldr", name), &name, "")
+        .part_type("Unofficial_Model")
+        .bfc(BfcCertification::NotApplicable)
+        .commands(commands)
+        .build();
+
+    Ok(LddModel {
+        document,
+        unmapped_materials,
+    })
+}
+
+// An .lxf file is a zip archive holding the LXFML model and a thumbnail.
+#[cfg(all(not(target_arch = "wasm32"), feature = "zip"))]
+pub fn import_lxf<R: Read + std::io::Seek>(
+    reader: R,
+    mapping: &LddMapping,
+    materials: &MaterialRegistry,
+) -> Result<LddModel, ImportError> {
+    use std::io::Error as IoError;
+
+    let mut archive = zip::ZipArchive::new(reader).map_err(IoError::from)?;
+    let name = archive
+        .file_names()
+        .find(|e| e.to_ascii_lowercase().ends_with(".lxfml"))
+        .ok_or(ImportError::MissingModel)?
+        .to_string();
+    let file = archive.by_name(&name).map_err(IoError::from)?;
+
+    import_lxfml(file, mapping, materials)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        color::{ColorReference, MaterialRegistry},
+        elements::Command,
+        Matrix4, Vector3,
+    };
+
+    use super::{import_lxfml, LddMapping};
+
+    const LXFML: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="no" ?>
+<LXFML versionMajor="5" versionMinor="0" name="Sample">
+  <Meta>
+    <Application name="LEGO Digital Designer" versionMajor="4" versionMinor="3"/>
+  </Meta>
+  <Bricks cameraRef="0">
+    <Brick refID="0" designID="3001">
+      <Part refID="0" designID="3001" materials="21,0">
+        <Bone refID="0" transformation="1,0,0,0,1,0,0,0,1,0.8,0.96,-1.6">
+        </Bone>
+      </Part>
+    </Brick>
+    <Brick refID="1" designID="3024">
+      <Part refID="1" designID="3024" materials="999">
+        <Bone refID="1" transformation="1,0,0,0,1,0,0,0,1,0,0,0">
+        </Bone>
+      </Part>
+    </Brick>
+  </Bricks>
+</LXFML>
+"#;
+
+    const LDRAW_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<LDrawMapping>
+  <Material ldraw="320" lego="999" />
+  <Brick ldraw="3024.dat" lego="3024" />
+  <Brick ldraw="3001old.dat" lego="3001" />
+  <Transformation ldraw="3001old.dat" tx="0" ty="0.96" tz="0" ax="0" ay="0" az="0" angle="0" />
+</LDrawMapping>
+"#;
+
+    fn references(document: &crate::document::Document) -> Vec<(String, ColorReference, Matrix4)> {
+        document
+            .commands
+            .iter()
+            .filter_map(|e| match e {
+                Command::PartReference(e) => {
+                    Some((e.name.original.to_string(), e.color.clone(), e.matrix))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_import_lxfml() {
+        let materials = MaterialRegistry::new();
+        let model = import_lxfml(LXFML.as_bytes(), &LddMapping::new(), &materials).unwrap();
+        assert_eq!(model.document.name, "Sample.ldr");
+        assert_eq!(
+            model.unmapped_materials.into_iter().collect::<Vec<_>>(),
+            vec![999]
+        );
+
+        let references = references(&model.document);
+        assert_eq!(references.len(), 2);
+        assert_eq!(references[0].0, "3001.dat");
+        assert_eq!(references[0].1, ColorReference::Unknown(4));
+        assert_eq!(
+            references[0].2,
+            Matrix4::from_translation(Vector3::new(20.0, -24.0, 40.0))
+        );
+        assert_eq!(references[1].1, ColorReference::Current);
+    }
+
+    #[test]
+    fn test_import_lxfml_with_mapping() {
+        let materials = MaterialRegistry::new();
+        let mapping = LddMapping::from_ldraw_xml(LDRAW_XML.as_bytes()).unwrap();
+        let model = import_lxfml(LXFML.as_bytes(), &mapping, &materials).unwrap();
+        assert!(model.unmapped_materials.is_empty());
+
+        let references = references(&model.document);
+        assert_eq!(references[0].0, "3001old.dat");
+        assert_eq!(
+            references[0].2,
+            Matrix4::from_translation(Vector3::new(20.0, -48.0, 40.0))
+        );
+        assert_eq!(references[1].0, "3024.dat");
+        assert_eq!(references[1].1, ColorReference::Unknown(320));
+    }
+}
@@ -1,7 +1,13 @@
 use std::cmp;
+use std::collections::{hash_map::DefaultHasher, HashMap, HashSet};
 use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
+use std::future::Future;
 use std::hash::{Hash, Hasher};
 use std::ops::BitXor;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
+use std::task::{Context, Poll, Waker};
 
 use cgmath::{
     Matrix3 as Matrix3_, Matrix4 as Matrix4_, Point2 as Point2_, Point3 as Point3_,
@@ -14,9 +20,18 @@ pub mod color;
 pub mod document;
 pub mod elements;
 pub mod error;
+pub mod index;
+#[cfg(feature = "lxf")]
+pub mod ldd;
 pub mod library;
+pub mod omr;
 pub mod parser;
 pub mod resolvers;
+pub mod search;
+#[cfg(all(not(target_arch = "wasm32"), feature = "zip"))]
+pub mod studio;
+#[cfg(test)]
+pub(crate) mod test_support;
 pub mod writer;
 
 pub type Matrix3 = Matrix3_<f32>;
@@ -27,16 +42,213 @@ pub type Vector4 = Vector4_<f32>;
 pub type Point2 = Point2_<f32>;
 pub type Point3 = Point3_<f32>;
 
+// Double precision counterparts for geometric analysis, where multiplying
+// f32 matrices through deeply nested subparts accumulates visible error.
+pub type Matrix3d = Matrix3_<f64>;
+pub type Matrix4d = Matrix4_<f64>;
+pub type Vector2d = Vector2_<f64>;
+pub type Vector3d = Vector3_<f64>;
+pub type Vector4d = Vector4_<f64>;
+pub type Point2d = Point2_<f64>;
+pub type Point3d = Point3_<f64>;
+
+pub fn matrix_to_f64(matrix: &Matrix4) -> Matrix4d {
+    matrix.cast().unwrap()
+}
+
+// Returns None if any component does not fit into f32.
+pub fn matrix_to_f32(matrix: &Matrix4d) -> Option<Matrix4> {
+    matrix.cast().filter(Matrix4::is_finite)
+}
+
+pub fn vector_to_f64(vector: &Vector3) -> Vector3d {
+    vector.cast().unwrap()
+}
+
+// Returns None if any component does not fit into f32.
+pub fn vector_to_f32(vector: &Vector3d) -> Option<Vector3> {
+    vector
+        .cast()
+        .filter(|e: &Vector3| e.x.is_finite() && e.y.is_finite() && e.z.is_finite())
+}
+
+#[derive(Debug, Default)]
+struct Waiters {
+    next: usize,
+    wakers: HashMap<usize, Waker>,
+}
+
+#[derive(Debug, Default)]
+struct CancellationState {
+    cancelled: AtomicBool,
+    waiters: Mutex<Waiters>,
+}
+
+impl CancellationState {
+    fn waiters(&self) -> MutexGuard<'_, Waiters> {
+        self.waiters.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// Shared flag for aborting long running parses and resolutions. Clones
+// observe the same state.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<CancellationState>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        let wakers = std::mem::take(&mut self.0.waiters().wakers);
+        for waker in wakers.into_values() {
+            waker.wake();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    // Completes once cancel() is called.
+    pub fn cancelled(&self) -> Cancelled<'_> {
+        Cancelled {
+            token: self,
+            key: None,
+        }
+    }
+}
+
+// Future returned by CancellationToken::cancelled(). Keeps one waker
+// registered with the token while pending and unregisters it when dropped.
+#[derive(Debug)]
+pub struct Cancelled<'a> {
+    token: &'a CancellationToken,
+    key: Option<usize>,
+}
+
+impl Future for Cancelled<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut waiters = self.token.0.waiters();
+        // cancel() sets the flag before taking the wakers, so checking under
+        // the lock never misses it.
+        if self.token.is_cancelled() {
+            return Poll::Ready(());
+        }
+        match self.key.and_then(|e| waiters.wakers.get_mut(&e)) {
+            Some(waker) => {
+                if !waker.will_wake(cx.waker()) {
+                    *waker = cx.waker().clone();
+                }
+            }
+            None => {
+                let key = waiters.next;
+                waiters.next += 1;
+                waiters.wakers.insert(key, cx.waker().clone());
+                drop(waiters);
+                self.key = Some(key);
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Cancelled<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key {
+            self.token.0.waiters().wakers.remove(&key);
+        }
+    }
+}
+
+// Aliases are interned so that the same part name referenced thousands of
+// times across a model shares a single allocation. The pool is split into
+// shards picked by hash to keep threads parsing in parallel from contending
+// on one lock, and names only the pool still holds are swept out whenever a
+// shard doubles in size.
+const INTERN_SHARDS: usize = 16;
+
+#[derive(Default)]
+struct InternShard {
+    names: HashSet<Arc<str>>,
+    sweep_at: usize,
+}
+
+impl InternShard {
+    fn sweep(&mut self) -> usize {
+        let before = self.names.len();
+        self.names.retain(|e| Arc::strong_count(e) > 1);
+        self.sweep_at = (self.names.len() * 2).max(64);
+        before - self.names.len()
+    }
+}
+
+fn intern_pool() -> &'static [Mutex<InternShard>; INTERN_SHARDS] {
+    static POOL: OnceLock<[Mutex<InternShard>; INTERN_SHARDS]> = OnceLock::new();
+
+    POOL.get_or_init(Default::default)
+}
+
+fn intern(value: &str, hash: u64) -> Arc<str> {
+    let mut shard = intern_pool()[hash as usize % INTERN_SHARDS]
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    if let Some(e) = shard.names.get(value) {
+        return Arc::clone(e);
+    }
+    if shard.names.len() >= shard.sweep_at {
+        shard.sweep();
+    }
+    let interned: Arc<str> = Arc::from(value);
+    shard.names.insert(Arc::clone(&interned));
+    interned
+}
+
+// Frees interned names no alias refers to anymore and returns how many. This
+// happens on its own as the pool grows; calling it after dropping a large
+// library releases the memory right away.
+pub fn release_unused_aliases() -> usize {
+    intern_pool()
+        .iter()
+        .map(|e| e.lock().unwrap_or_else(PoisonError::into_inner).sweep())
+        .sum()
+}
+
+fn hash_alias(normalized: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    normalized.hash(&mut hasher);
+    hasher.finish()
+}
+
 #[derive(Clone, Debug)]
 pub struct PartAlias {
-    pub normalized: String,
-    pub original: String,
+    pub normalized: Arc<str>,
+    pub original: Arc<str>,
+    hash: u64,
 }
 
 impl PartAlias {
+    pub fn new(alias: &str) -> PartAlias {
+        let normalized = Self::normalize(alias);
+        let hash = hash_alias(&normalized);
+
+        PartAlias {
+            original: if normalized == alias {
+                intern(&normalized, hash)
+            } else {
+                intern(alias, hash_alias(alias))
+            },
+            normalized: intern(&normalized, hash),
+            hash,
+        }
+    }
+
     pub fn set(&mut self, alias: String) {
-        self.normalized = Self::normalize(&alias);
-        self.original = alias;
+        *self = PartAlias::new(&alias);
     }
 
     pub fn normalize(alias: &str) -> String {
@@ -46,30 +258,19 @@ impl PartAlias {
 
 impl From<String> for PartAlias {
     fn from(alias: String) -> PartAlias {
-        PartAlias {
-            normalized: Self::normalize(&alias),
-            original: alias,
-        }
+        PartAlias::new(&alias)
     }
 }
 
 impl From<&String> for PartAlias {
     fn from(alias: &String) -> PartAlias {
-        PartAlias {
-            normalized: Self::normalize(alias),
-            original: alias.clone(),
-        }
+        PartAlias::new(alias)
     }
 }
 
 impl From<&str> for PartAlias {
     fn from(alias: &str) -> PartAlias {
-        let string = alias.to_string();
-
-        PartAlias {
-            normalized: Self::normalize(&string),
-            original: string,
-        }
+        PartAlias::new(alias)
     }
 }
 
@@ -95,7 +296,7 @@ impl<'a> Visitor<'a> for StringVisitor {
 
 impl Serialize for PartAlias {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        serializer.serialize_str(self.original.as_str())
+        serializer.serialize_str(&self.original)
     }
 }
 
@@ -111,17 +312,20 @@ impl cmp::Eq for PartAlias {}
 
 impl cmp::PartialEq for PartAlias {
     fn eq(&self, other: &PartAlias) -> bool {
-        self.normalized.eq(&other.normalized)
+        if Arc::ptr_eq(&self.normalized, &other.normalized) {
+            return true;
+        }
+        self.hash == other.hash && self.normalized.eq(&other.normalized)
     }
 }
 
 impl Hash for PartAlias {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.normalized.hash(state)
+        state.write_u64(self.hash)
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Winding {
     Ccw,
     Cw,
@@ -165,20 +369,86 @@ impl BitXor<bool> for &Winding {
 #[cfg(test)]
 mod tests {
     use crate::PartAlias;
-    
+
     #[test]
     fn test_part_alias_directory_sep_normalization() {
         let alias = PartAlias::from("test\\directory\\disc.dat".to_string());
 
-        assert_eq!(alias.normalized, "test/directory/disc.dat");
-        assert_eq!(alias.original, "test\\directory\\disc.dat");
+        assert_eq!(&*alias.normalized, "test/directory/disc.dat");
+        assert_eq!(&*alias.original, "test\\directory\\disc.dat");
     }
 
     #[test]
     fn test_part_alias_case_normalization() {
         let alias = PartAlias::from("Disc.dat".to_string());
 
-        assert_eq!(alias.normalized, "disc.dat");
-        assert_eq!(alias.original, "Disc.dat");
+        assert_eq!(&*alias.normalized, "disc.dat");
+        assert_eq!(&*alias.original, "Disc.dat");
+    }
+
+    #[test]
+    fn test_part_alias_shares_interned_storage() {
+        let a = PartAlias::from("3001.dat");
+        let b = PartAlias::from("3001.DAT".to_string());
+
+        assert_eq!(a, b);
+        assert!(std::sync::Arc::ptr_eq(&a.normalized, &b.normalized));
+        assert!(std::sync::Arc::ptr_eq(&a.normalized, &a.original));
+    }
+
+    #[test]
+    fn test_release_unused_aliases() {
+        let alias = PartAlias::from("release-unused-alias.dat");
+        let weak = std::sync::Arc::downgrade(&alias.normalized);
+
+        crate::release_unused_aliases();
+        assert!(weak.upgrade().is_some());
+
+        drop(alias);
+        crate::release_unused_aliases();
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_cancelled_keeps_one_waker() {
+        use std::{
+            future::Future,
+            pin::pin,
+            task::{Context, Poll},
+        };
+
+        use futures::task::noop_waker;
+
+        let token = crate::CancellationToken::new();
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        {
+            let mut cancelled = pin!(token.cancelled());
+            for _ in 0..10 {
+                assert_eq!(cancelled.as_mut().poll(&mut cx), Poll::Pending);
+            }
+            assert_eq!(token.0.waiters().wakers.len(), 1);
+        }
+        assert!(token.0.waiters().wakers.is_empty());
+
+        let mut cancelled = pin!(token.cancelled());
+        assert_eq!(cancelled.as_mut().poll(&mut cx), Poll::Pending);
+        token.cancel();
+        assert_eq!(cancelled.as_mut().poll(&mut cx), Poll::Ready(()));
+    }
+
+    #[test]
+    fn test_matrix_precision_conversion() {
+        use crate::{matrix_to_f32, matrix_to_f64, Matrix4, Matrix4d};
+
+        let matrix = Matrix4::from_translation(crate::Vector3::new(1.5, -20.0, 0.25));
+        let precise = matrix_to_f64(&matrix);
+        assert_eq!(
+            precise,
+            Matrix4d::from_translation(crate::Vector3d::new(1.5, -20.0, 0.25))
+        );
+        assert_eq!(matrix_to_f32(&precise), Some(matrix));
+
+        assert_eq!(matrix_to_f32(&Matrix4d::from_scale(1e300)), None);
     }
 }
@@ -1,18 +1,16 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     ops::Deref,
     sync::{Arc, RwLock},
 };
 
 use async_trait::async_trait;
-use futures::future::{join_all};
+use futures::future::{join_all, select, Either};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    color::MaterialRegistry,
-    document::{MultipartDocument},
-    error::ResolutionError,
-    PartAlias,
+    color::MaterialRegistry, document::MultipartDocument, error::ResolutionError,
+    CancellationToken, PartAlias,
 };
 
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, Hash)]
@@ -46,6 +44,12 @@ pub trait LibraryLoader {
         alias: PartAlias,
         local: bool,
     ) -> Result<(FileLocation, MultipartDocument), ResolutionError>;
+
+    // Image file `name` from the `textures` folders of the library, named
+    // like `PartAlias::normalized`.
+    async fn load_texture(&self, _name: &str) -> Result<Vec<u8>, ResolutionError> {
+        Err(ResolutionError::FileNotFound)
+    }
 }
 
 #[derive(Debug, Default)]
@@ -86,6 +90,17 @@ impl PartCache {
         }
     }
 
+    pub fn remove(&mut self, alias: &PartAlias) -> Option<Arc<MultipartDocument>> {
+        match self.parts.remove(alias) {
+            Some(part) => Some(part),
+            None => self.primitives.remove(alias),
+        }
+    }
+
+    pub fn parts(&self) -> impl Iterator<Item = &PartAlias> {
+        self.parts.keys()
+    }
+
     fn collect_round(&mut self, collection_strategy: CacheCollectionStrategy) -> usize {
         let prev_size = self.parts.len() + self.primitives.len();
         match collection_strategy {
@@ -133,6 +148,10 @@ impl TransientDocumentCache {
     pub fn query(&self, alias: &PartAlias) -> Option<Arc<MultipartDocument>> {
         self.documents.get(alias).map(Arc::clone)
     }
+
+    pub fn remove(&mut self, alias: &PartAlias) {
+        self.documents.remove(alias);
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -143,32 +162,56 @@ pub enum ResolutionState {
     Associated(Arc<MultipartDocument>),
 }
 
-struct DependencyResolver<'a, F> {
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ResolutionProgress {
+    // Files loaded successfully so far.
+    pub resolved: usize,
+    // Files that could not be loaded.
+    pub failed: usize,
+    // Files discovered but not loaded yet. Grows as new dependencies are
+    // found, so the total is only known once it reaches zero.
+    pub pending: usize,
+}
+
+// A file, or the document resolved if None, with whether it was loaded
+// locally, and one of its subparts, or its body if None.
+type ReferenceNode<'a> = (Option<(&'a PartAlias, bool)>, Option<&'a PartAlias>);
+
+struct DependencyResolver<'a, F, P> {
     materials: &'a MaterialRegistry,
     cache: Arc<RwLock<PartCache>>,
     local_cache: TransientDocumentCache,
     on_update: &'a F,
-    loader: &'a Box<dyn LibraryLoader>,
+    on_progress: &'a P,
+    loader: &'a dyn LibraryLoader,
+    progress: ResolutionProgress,
+    // Files loaded by this run, as opposed to served from a cache, and
+    // whether they were loaded locally.
+    loaded: HashSet<(PartAlias, bool)>,
 
     pub map: HashMap<PartAlias, ResolutionState>,
     pub local_map: HashMap<PartAlias, ResolutionState>,
 }
 
-impl<'a, F: Fn(PartAlias, Result<(), ResolutionError>)>
-    DependencyResolver<'a, F>
+impl<'a, F: Fn(PartAlias, Result<(), ResolutionError>), P: Fn(ResolutionProgress)>
+    DependencyResolver<'a, F, P>
 {
     pub fn new(
         materials: &'a MaterialRegistry,
         cache: Arc<RwLock<PartCache>>,
         on_update: &'a F,
-        loader: &'a Box<dyn LibraryLoader>,
-    ) -> DependencyResolver<'a, F> {
+        on_progress: &'a P,
+        loader: &'a dyn LibraryLoader,
+    ) -> DependencyResolver<'a, F, P> {
         DependencyResolver {
             materials,
             cache,
             local_cache: TransientDocumentCache::default(),
             on_update,
+            on_progress,
             loader,
+            progress: ResolutionProgress::default(),
+            loaded: HashSet::new(),
             map: HashMap::new(),
             local_map: HashMap::new(),
         }
@@ -182,27 +225,39 @@ impl<'a, F: Fn(PartAlias, Result<(), ResolutionError>)>
         }
     }
 
+    fn track_pending(&mut self, previous: Option<ResolutionState>, pending: bool) {
+        if matches!(previous, Some(ResolutionState::Pending)) {
+            self.progress.pending -= 1;
+        }
+        if pending {
+            self.progress.pending += 1;
+        }
+    }
+
     pub fn put_state(&mut self, alias: PartAlias, local: bool, state: ResolutionState) {
-        if local {
-            self.local_map.insert(alias, state);
+        let pending = matches!(state, ResolutionState::Pending);
+        let previous = if local {
+            self.local_map.insert(alias, state)
         } else {
-            self.map.insert(alias, state);
-        }
+            self.map.insert(alias, state)
+        };
+        self.track_pending(previous, pending);
     }
 
     pub fn clear_state(&mut self, alias: &PartAlias, local: bool) {
-        if local {
-            self.local_map.remove(alias);
+        let previous = if local {
+            self.local_map.remove(alias)
         } else {
-            self.map.remove(alias);
-        }
+            self.map.remove(alias)
+        };
+        self.track_pending(previous, false);
     }
 
     pub fn scan_dependencies<D: Deref<Target = MultipartDocument> + Clone>(
         &mut self,
         alias: Option<&PartAlias>,
         parent: D,
-        local: bool
+        local: bool,
     ) {
         let document = match alias {
             Some(e) => match parent.subparts.get(e) {
@@ -212,7 +267,7 @@ impl<'a, F: Fn(PartAlias, Result<(), ResolutionError>)>
             None => &parent.body,
         };
 
-        for r in document.iter_refs() {
+        for (r, _) in document.iter_refs_with_mode() {
             let alias = &r.name;
 
             if self.contains_state(alias, local) {
@@ -227,64 +282,190 @@ impl<'a, F: Fn(PartAlias, Result<(), ResolutionError>)>
 
             if local {
                 if let Some(cached) = self.local_cache.query(alias) {
-                    self.scan_dependencies(None, Arc::clone(&cached), true);
-
+                    // Associated before scanning, so that files referring
+                    // back to it are not followed into again.
                     self.put_state(
                         alias.clone(),
                         true,
                         ResolutionState::Associated(Arc::clone(&cached)),
                     );
+                    self.scan_dependencies(None, cached, true);
                     continue;
                 }
             }
 
             let cached = self.cache.read().unwrap().query(alias);
             if let Some(cached) = cached {
-                self.scan_dependencies(None, Arc::clone(&cached), false);
-
                 self.put_state(
                     alias.clone(),
                     false,
                     ResolutionState::Associated(Arc::clone(&cached)),
                 );
+                self.scan_dependencies(None, cached, false);
                 continue;
             }
 
-            self.put_state(
+            self.put_state(alias.clone(), local, ResolutionState::Pending);
+        }
+    }
+
+    fn associated(
+        &self,
+        alias: &PartAlias,
+        local: bool,
+    ) -> Option<(&Arc<MultipartDocument>, bool)> {
+        let local_entry = if local {
+            self.local_map.get(alias)
+        } else {
+            None
+        };
+        match local_entry {
+            Some(ResolutionState::Associated(e)) => Some((e, true)),
+            _ => match self.map.get(alias) {
+                Some(ResolutionState::Associated(e)) => Some((e, false)),
+                _ => None,
+            },
+        }
+    }
+
+    // Walks references from the body or a subpart of `document`, loaded as
+    // `file` or being the document resolved if None, keeping the path
+    // followed across files. Returns the first cycle found along with the
+    // file it re-enters.
+    fn find_circular_reference<'b>(
+        &'b self,
+        file: Option<(&'b PartAlias, bool)>,
+        document: &'b MultipartDocument,
+        subpart: Option<&'b PartAlias>,
+        path: &mut Vec<(ReferenceNode<'b>, &'b PartAlias)>,
+        finished: &mut HashSet<ReferenceNode<'b>>,
+    ) -> Option<(Vec<PartAlias>, (PartAlias, bool))> {
+        let body = match subpart {
+            Some(e) => document.subparts.get(e)?,
+            None => &document.body,
+        };
+        for (r, _) in body.iter_refs_with_mode() {
+            let alias = &r.name;
+            let (node, next) = if document.subparts.contains_key(alias) {
+                ((file, Some(alias)), document)
+            } else {
+                let local = file.is_none_or(|e| e.1);
+                match self.associated(alias, local) {
+                    Some((next, local)) => ((Some((alias, local)), None), &**next),
+                    None => continue,
+                }
+            };
+
+            if let Some(index) = path.iter().position(|e| e.0 == node) {
+                // Subparts of the document resolved only refer to each other.
+                let file = match node.0 {
+                    Some((alias, local)) => (alias.clone(), local),
+                    None => continue,
+                };
+                let mut cycle = path[index..]
+                    .iter()
+                    .map(|e| e.1.clone())
+                    .collect::<Vec<_>>();
+                cycle.push(alias.clone());
+                return Some((cycle, file));
+            }
+            if finished.contains(&node) {
+                continue;
+            }
+
+            path.push((node, alias));
+            let cycle = self.find_circular_reference(node.0, next, node.1, path, finished);
+            if cycle.is_some() {
+                return cycle;
+            }
+            path.pop();
+            finished.insert(node);
+        }
+
+        None
+    }
+
+    // Drops files referring back to themselves through other files, which
+    // consumers recursing into references would never get out of. They are
+    // taken out of the caches too, for later runs to find the cycle anew.
+    pub fn break_circular_references(&mut self, document: &MultipartDocument) {
+        loop {
+            let found = self.find_circular_reference(
+                None,
+                document,
+                None,
+                &mut Vec::new(),
+                &mut HashSet::new(),
+            );
+            let (cycle, (alias, local)) = match found {
+                Some(e) => e,
+                None => break,
+            };
+            if self.loaded.remove(&(alias.clone(), local)) {
+                self.progress.resolved -= 1;
+            }
+            if local {
+                self.local_cache.remove(&alias);
+            } else {
+                self.cache.write().unwrap().remove(&alias);
+            }
+            self.progress.failed += 1;
+            (self.on_update)(
                 alias.clone(),
-                local,
-                ResolutionState::Pending
+                Err(ResolutionError::CircularReference(cycle)),
             );
+            self.put_state(alias, local, ResolutionState::Missing);
+            (self.on_progress)(self.progress);
         }
     }
 
-    pub async fn resolve_pending_dependencies(&mut self) -> bool {
-        let mut pending = self.local_map.iter().filter_map(|(k, v)| {
-            match v {
+    // Returns None if cancelled while files were being loaded. In-flight
+    // loads are dropped along with the joined future.
+    pub async fn resolve_pending_dependencies(
+        &mut self,
+        cancellation: Option<&CancellationToken>,
+    ) -> Option<bool> {
+        let mut pending = self
+            .local_map
+            .iter()
+            .filter_map(|(k, v)| match v {
                 ResolutionState::Pending => Some((k.clone(), true)),
                 _ => None,
-            }
-        }).collect::<Vec<_>>();
-        pending.extend(self.map.iter().filter_map(|(k, v)| {
-            match v {
-                ResolutionState::Pending => Some((k.clone(), false)),
-                _ => None,
-            }
+            })
+            .collect::<Vec<_>>();
+        pending.extend(self.map.iter().filter_map(|(k, v)| match v {
+            ResolutionState::Pending => Some((k.clone(), false)),
+            _ => None,
         }));
 
         if pending.is_empty() {
-            return false;
+            return Some(false);
         }
 
-        let futs = pending.iter().map(
-            |(alias, local)| self.loader.load_ref(self.materials, alias.clone(), *local)
-        ).collect::<Vec<_>>();
-        
-        let result = join_all(futs).await;
+        let futs = pending
+            .iter()
+            .map(|(alias, local)| self.loader.load_ref(self.materials, alias.clone(), *local))
+            .collect::<Vec<_>>();
+
+        let result = match cancellation {
+            Some(token) => match select(join_all(futs), Box::pin(token.cancelled())).await {
+                Either::Left((result, _)) => result,
+                Either::Right(_) => return None,
+            },
+            None => join_all(futs).await,
+        };
 
         for ((alias, mut local), result) in pending.iter().zip(result) {
+            let result =
+                result.and_then(
+                    |(location, document)| match document.find_circular_reference() {
+                        Some(cycle) => Err(ResolutionError::CircularReference(cycle)),
+                        None => Ok((location, document)),
+                    },
+                );
             let state = match result {
                 Ok((location, document)) => {
+                    self.progress.resolved += 1;
                     (self.on_update)(alias.clone(), Ok(()));
                     let document = Arc::new(document);
                     match location {
@@ -298,35 +479,37 @@ impl<'a, F: Fn(PartAlias, Result<(), ResolutionError>)>
                                 alias.clone(),
                                 Arc::clone(&document),
                             );
-                            
                         }
                         FileLocation::Local => {
                             self.local_cache
                                 .register(alias.clone(), Arc::clone(&document));
                         }
                     };
+                    self.loaded.insert((alias.clone(), local));
 
                     self.scan_dependencies(None, Arc::clone(&document), local);
 
                     ResolutionState::Associated(document)
-                },
+                }
                 Err(err) => {
+                    self.progress.failed += 1;
                     (self.on_update)(alias.clone(), Err(err));
                     ResolutionState::Missing
                 }
             };
             self.put_state(alias.clone(), local, state);
+            (self.on_progress)(self.progress);
         }
 
-        true
+        Some(true)
     }
-
 }
 
 #[derive(Debug, Default)]
 pub struct ResolutionResult {
     library_entries: HashMap<PartAlias, Arc<MultipartDocument>>,
     local_entries: HashMap<PartAlias, Arc<MultipartDocument>>,
+    substitutions: HashMap<PartAlias, PartAlias>,
 }
 
 impl ResolutionResult {
@@ -334,6 +517,50 @@ impl ResolutionResult {
         Self::default()
     }
 
+    pub(crate) fn from_entries(
+        library_entries: HashMap<PartAlias, Arc<MultipartDocument>>,
+        local_entries: HashMap<PartAlias, Arc<MultipartDocument>>,
+    ) -> Self {
+        let mut substitutions = HashMap::new();
+        for alias in library_entries.keys() {
+            // Follow chains of renames down to the final part, giving up on
+            // cycles or redirects to parts that could not be loaded.
+            let mut visited = HashSet::new();
+            let mut current = alias;
+            while let Some(next) = library_entries
+                .get(current)
+                .and_then(|e| e.body.moved_to())
+                .filter(|e| library_entries.contains_key(*e))
+            {
+                if !visited.insert(current) {
+                    break;
+                }
+                current = next;
+            }
+            if current != alias && library_entries[current].body.moved_to().is_none() {
+                substitutions.insert(alias.clone(), current.clone());
+            }
+        }
+
+        ResolutionResult {
+            library_entries,
+            local_entries,
+            substitutions,
+        }
+    }
+
+    // Everything in the cache as library entries, for working on the cache
+    // as a whole rather than on one document's dependencies.
+    pub fn from_cache(cache: &PartCache) -> Self {
+        let library_entries = cache
+            .parts
+            .iter()
+            .chain(cache.primitives.iter())
+            .map(|(alias, document)| (alias.clone(), Arc::clone(document)))
+            .collect();
+        Self::from_entries(library_entries, HashMap::new())
+    }
+
     pub fn query(&self, alias: &PartAlias, local: bool) -> Option<(Arc<MultipartDocument>, bool)> {
         if local {
             let local_entry = self.local_entries.get(alias);
@@ -341,10 +568,34 @@ impl ResolutionResult {
                 return Some((Arc::clone(e), true));
             }
         }
+        let alias = self.substitutions.get(alias).unwrap_or(alias);
         self.library_entries
             .get(alias)
             .map(|e| (Arc::clone(e), false))
     }
+
+    // Moved parts that were transparently replaced by their new names.
+    pub fn substitutions(&self) -> &HashMap<PartAlias, PartAlias> {
+        &self.substitutions
+    }
+
+    pub fn aliases(&self) -> impl Iterator<Item = &PartAlias> {
+        self.library_entries.keys().chain(self.local_entries.keys())
+    }
+
+    // Adds the files resolved for another document, e.g. extra parts that
+    // were resolved separately. Entries already present are kept.
+    pub fn merge(&mut self, other: ResolutionResult) {
+        for (alias, document) in other.library_entries {
+            self.library_entries.entry(alias).or_insert(document);
+        }
+        for (alias, document) in other.local_entries {
+            self.local_entries.entry(alias).or_insert(document);
+        }
+        for (alias, substitution) in other.substitutions {
+            self.substitutions.entry(alias).or_insert(substitution);
+        }
+    }
 }
 
 pub async fn resolve_dependencies<F>(
@@ -357,13 +608,135 @@ pub async fn resolve_dependencies<F>(
 where
     F: Fn(PartAlias, Result<(), ResolutionError>),
 {
-    let mut resolver = DependencyResolver::new(materials, cache, on_update, loader);
+    resolve_dependencies_with_progress(cache, materials, &**loader, document, on_update, &|_| {})
+        .await
+}
+
+// Same as resolve_dependencies, additionally reporting overall progress once
+// the document is scanned and after every loaded file.
+pub async fn resolve_dependencies_with_progress<F, P>(
+    cache: Arc<RwLock<PartCache>>,
+    materials: &MaterialRegistry,
+    loader: &dyn LibraryLoader,
+    document: &MultipartDocument,
+    on_update: &F,
+    on_progress: &P,
+) -> ResolutionResult
+where
+    F: Fn(PartAlias, Result<(), ResolutionError>),
+    P: Fn(ResolutionProgress),
+{
+    resolve_dependencies_inner(
+        cache,
+        materials,
+        loader,
+        document,
+        on_update,
+        on_progress,
+        None,
+    )
+    .await
+    .unwrap_or_default()
+}
+
+// Stops between and during loading rounds once the token is cancelled.
+// Documents loaded up to that point stay registered in the part cache.
+pub async fn resolve_dependencies_cancellable<F, P>(
+    cache: Arc<RwLock<PartCache>>,
+    materials: &MaterialRegistry,
+    loader: &dyn LibraryLoader,
+    document: &MultipartDocument,
+    on_update: &F,
+    on_progress: &P,
+    cancellation: &CancellationToken,
+) -> Result<ResolutionResult, ResolutionError>
+where
+    F: Fn(PartAlias, Result<(), ResolutionError>),
+    P: Fn(ResolutionProgress),
+{
+    resolve_dependencies_inner(
+        cache,
+        materials,
+        loader,
+        document,
+        on_update,
+        on_progress,
+        Some(cancellation),
+    )
+    .await
+    .ok_or(ResolutionError::Cancelled)
+}
+
+// Images `!TEXMAP` statements of `document` and the files resolved for it
+// project, by normalized name. Files embedded with `!DATA` come first, the
+// rest is loaded from the library; textures found in neither are left out.
+pub async fn load_textures(
+    loader: &dyn LibraryLoader,
+    document: &MultipartDocument,
+    resolution: &ResolutionResult,
+) -> HashMap<String, Vec<u8>> {
+    let documents = std::iter::once(document).chain(
+        resolution
+            .library_entries
+            .values()
+            .chain(resolution.local_entries.values())
+            .map(Arc::as_ref),
+    );
+
+    let mut textures = HashMap::new();
+    let mut names = HashSet::new();
+    for document in documents {
+        for (name, data) in document.data.iter() {
+            textures
+                .entry(PartAlias::normalize(name))
+                .or_insert_with(|| data.clone());
+        }
+        for body in std::iter::once(&document.body).chain(document.subparts.values()) {
+            names.extend(body.textures().into_iter().map(PartAlias::normalize));
+        }
+    }
+
+    for name in names {
+        if textures.contains_key(&name) {
+            continue;
+        }
+        if let Ok(data) = loader.load_texture(&name).await {
+            textures.insert(name, data);
+        }
+    }
+
+    textures
+}
+
+async fn resolve_dependencies_inner<F, P>(
+    cache: Arc<RwLock<PartCache>>,
+    materials: &MaterialRegistry,
+    loader: &dyn LibraryLoader,
+    document: &MultipartDocument,
+    on_update: &F,
+    on_progress: &P,
+    cancellation: Option<&CancellationToken>,
+) -> Option<ResolutionResult>
+where
+    F: Fn(PartAlias, Result<(), ResolutionError>),
+    P: Fn(ResolutionProgress),
+{
+    let mut resolver = DependencyResolver::new(materials, cache, on_update, on_progress, loader);
 
     resolver.scan_dependencies(None, document, true);
-    while resolver.resolve_pending_dependencies().await {}
+    on_progress(resolver.progress);
+    loop {
+        if cancellation.is_some_and(CancellationToken::is_cancelled) {
+            return None;
+        }
+        if !resolver.resolve_pending_dependencies(cancellation).await? {
+            break;
+        }
+    }
+    resolver.break_circular_references(document);
 
-    ResolutionResult {
-        library_entries: resolver
+    Some(ResolutionResult::from_entries(
+        resolver
             .map
             .into_iter()
             .filter_map(|(k, v)| match v {
@@ -371,7 +744,7 @@ where
                 _ => None,
             })
             .collect::<HashMap<_, _>>(),
-        local_entries: resolver
+        resolver
             .local_map
             .into_iter()
             .filter_map(|(k, v)| match v {
@@ -379,15 +752,54 @@ where
                 _ => None,
             })
             .collect::<HashMap<_, _>>(),
-    }
+    ))
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{collections::HashMap, sync::Arc};
+    use std::{
+        cell::RefCell,
+        collections::HashMap,
+        sync::{Arc, RwLock},
+    };
 
-    use crate::{PartAlias, document::{MultipartDocument, Document, BfcCertification}};
-    use super::{PartCache, PartKind};
+    use super::{
+        load_textures, resolve_dependencies_cancellable, resolve_dependencies_with_progress,
+        PartCache, PartKind, ResolutionProgress, ResolutionResult,
+    };
+    use crate::{
+        color::{ColorReference, MaterialRegistry},
+        document::{BfcCertification, Document, MultipartDocument},
+        elements::{Command, PartReference},
+        error::ResolutionError,
+        parser::parse_multipart_document,
+        test_support::MemoryLoader,
+        CancellationToken, Matrix4, PartAlias,
+    };
+
+    fn library_document(name: &str, description: &str, refs: &[&str]) -> Arc<MultipartDocument> {
+        Arc::new(MultipartDocument {
+            body: Document {
+                name: name.to_string(),
+                author: "Author".to_string(),
+                description: description.to_string(),
+                bfc: BfcCertification::NoCertify,
+                headers: vec![],
+                commands: refs
+                    .iter()
+                    .map(|e| {
+                        Command::PartReference(PartReference {
+                            color: ColorReference::Current,
+                            matrix: Matrix4::from_scale(1.0),
+                            name: PartAlias::from(*e),
+                        })
+                    })
+                    .collect(),
+            },
+            subparts: HashMap::new(),
+            data: HashMap::new(),
+        })
+    }
 
     #[test]
     fn test_part_cache_query_existing() {
@@ -401,6 +813,7 @@ mod tests {
                 commands: vec![],
             },
             subparts: HashMap::new(),
+            data: HashMap::new(),
         };
 
         let mut cache = PartCache::new();
@@ -410,10 +823,7 @@ mod tests {
 
         cache.register(PartKind::Primitive, existing_key.clone(), document.clone());
 
-        assert_eq!(
-            cache.query(&existing_key).unwrap(),
-            document
-        );
+        assert_eq!(cache.query(&existing_key).unwrap(), document);
     }
 
     #[test]
@@ -423,4 +833,288 @@ mod tests {
 
         assert!(cache.query(&missing_key).is_none());
     }
+
+    #[test]
+    fn test_resolution_result_follows_moved_parts() {
+        let mut entries = HashMap::new();
+        for (name, description, refs) in [
+            ("oldest.dat", "~Moved to old", &["old.dat"][..]),
+            ("old.dat", "~Moved to new", &["new.dat"][..]),
+            ("new.dat", "Brick", &[][..]),
+            ("loop-a.dat", "~Moved to loop-b", &["loop-b.dat"][..]),
+            ("loop-b.dat", "~Moved to loop-a", &["loop-a.dat"][..]),
+        ] {
+            entries.insert(
+                PartAlias::from(name),
+                library_document(name, description, refs),
+            );
+        }
+        let result = ResolutionResult::from_entries(entries, HashMap::new());
+
+        let (document, local) = result.query(&PartAlias::from("oldest.dat"), true).unwrap();
+        assert!(!local);
+        assert_eq!(document.body.name, "new.dat");
+        assert_eq!(
+            result.substitutions().get(&PartAlias::from("old.dat")),
+            Some(&PartAlias::from("new.dat"))
+        );
+        assert_eq!(result.substitutions().len(), 2);
+
+        let (document, _) = result.query(&PartAlias::from("loop-a.dat"), false).unwrap();
+        assert_eq!(document.body.name, "loop-a.dat");
+    }
+
+    #[async_std::test]
+    async fn test_resolution_progress() {
+        let loader = MemoryLoader::library([
+            library_document("a.dat", "A", &["b.dat"]),
+            library_document("b.dat", "B", &[]),
+        ]);
+        let document = library_document("model.ldr", "Model", &["a.dat", "missing.dat"]);
+        let events = RefCell::new(Vec::new());
+
+        let result = resolve_dependencies_with_progress(
+            Arc::new(RwLock::new(PartCache::new())),
+            &MaterialRegistry::new(),
+            &loader,
+            &document,
+            &|_, _| {},
+            &|progress| events.borrow_mut().push(progress),
+        )
+        .await;
+
+        let events = events.into_inner();
+        assert_eq!(events.len(), 4);
+        assert_eq!(
+            events[0],
+            ResolutionProgress {
+                resolved: 0,
+                failed: 0,
+                pending: 2
+            }
+        );
+        assert_eq!(
+            events[3],
+            ResolutionProgress {
+                resolved: 2,
+                failed: 1,
+                pending: 0
+            }
+        );
+        assert!(result.query(&PartAlias::from("b.dat"), false).is_some());
+    }
+
+    #[async_std::test]
+    async fn test_resolution_breaks_cycles_across_files() {
+        let loader = MemoryLoader::library([
+            library_document("a.dat", "A", &["b.dat"]),
+            library_document("b.dat", "B", &["c.dat", "a.dat"]),
+            library_document("c.dat", "C", &[]),
+        ]);
+        let document = library_document("model.ldr", "Model", &["a.dat"]);
+        let errors = RefCell::new(Vec::new());
+
+        let result = resolve_dependencies_with_progress(
+            Arc::new(RwLock::new(PartCache::new())),
+            &MaterialRegistry::new(),
+            &loader,
+            &document,
+            &|alias, result| {
+                if let Err(ResolutionError::CircularReference(cycle)) = result {
+                    errors.borrow_mut().push((alias, cycle));
+                }
+            },
+            &|_| {},
+        )
+        .await;
+
+        assert_eq!(
+            errors.into_inner(),
+            vec![(
+                PartAlias::from("a.dat"),
+                vec![
+                    PartAlias::from("a.dat"),
+                    PartAlias::from("b.dat"),
+                    PartAlias::from("a.dat")
+                ]
+            )]
+        );
+        assert!(result.query(&PartAlias::from("a.dat"), false).is_none());
+        assert!(result.query(&PartAlias::from("c.dat"), false).is_some());
+    }
+
+    #[async_std::test]
+    async fn test_resolution_breaks_cached_cycles() {
+        let loader = MemoryLoader::library([
+            library_document("a.dat", "A", &["b.dat"]),
+            library_document("b.dat", "B", &["a.dat"]),
+        ]);
+        let document = library_document("model.ldr", "Model", &["a.dat"]);
+        let cache = Arc::new(RwLock::new(PartCache::new()));
+
+        for _ in 0..2 {
+            let errors = RefCell::new(Vec::new());
+            let progress = RefCell::new(ResolutionProgress::default());
+            let result = resolve_dependencies_with_progress(
+                Arc::clone(&cache),
+                &MaterialRegistry::new(),
+                &loader,
+                &document,
+                &|alias, result| {
+                    if let Err(ResolutionError::CircularReference(_)) = result {
+                        errors.borrow_mut().push(alias);
+                    }
+                },
+                &|e| *progress.borrow_mut() = e,
+            )
+            .await;
+
+            assert_eq!(errors.into_inner(), vec![PartAlias::from("a.dat")]);
+            assert_eq!(progress.borrow().failed, 1);
+            assert!(result.query(&PartAlias::from("a.dat"), false).is_none());
+            assert!(result.query(&PartAlias::from("b.dat"), false).is_some());
+            assert!(cache
+                .read()
+                .unwrap()
+                .query(&PartAlias::from("a.dat"))
+                .is_none());
+        }
+    }
+
+    #[async_std::test]
+    async fn test_resolution_follows_texmap_geometry() {
+        let loader = MemoryLoader::library([
+            library_document("textured.dat", "Textured", &["stud.dat"]),
+            library_document("stud.dat", "Stud", &[]),
+            library_document("fallback.dat", "Fallback", &[]),
+        ]);
+        let document = parse_multipart_document(
+            &MaterialRegistry::new(),
+            &mut &b"0 Model\n\
+0 !TEXMAP START PLANAR -20 0 10 20 0 10 -20 0 -10 sticker.png\n\
+0 !: 1 16 0 0 0 1 0 0 0 1 0 0 0 1 textured.dat\n\
+0 !TEXMAP FALLBACK\n\
+1 16 0 0 0 1 0 0 0 1 0 0 0 1 fallback.dat\n\
+0 !TEXMAP END\n"[..],
+        )
+        .await
+        .unwrap();
+
+        let result = resolve_dependencies_with_progress(
+            Arc::new(RwLock::new(PartCache::new())),
+            &MaterialRegistry::new(),
+            &loader,
+            &document,
+            &|_, _| {},
+            &|_| {},
+        )
+        .await;
+
+        for name in ["textured.dat", "stud.dat", "fallback.dat"] {
+            assert!(result.query(&PartAlias::from(name), false).is_some());
+        }
+    }
+
+    #[async_std::test]
+    async fn test_resolution_follows_mlcad_prefixes() {
+        let loader = MemoryLoader::library([
+            library_document("ghost.dat", "Ghost", &["stud.dat"]),
+            library_document("stud.dat", "Stud", &[]),
+        ]);
+        let document = parse_multipart_document(
+            &MaterialRegistry::new(),
+            &mut &b"0 Model\n\
+0 GHOST 1 16 0 0 0 1 0 0 0 1 0 0 0 1 ghost.dat\n"[..],
+        )
+        .await
+        .unwrap();
+
+        let result = resolve_dependencies_with_progress(
+            Arc::new(RwLock::new(PartCache::new())),
+            &MaterialRegistry::new(),
+            &loader,
+            &document,
+            &|_, _| {},
+            &|_| {},
+        )
+        .await;
+
+        for name in ["ghost.dat", "stud.dat"] {
+            assert!(result.query(&PartAlias::from(name), false).is_some());
+        }
+    }
+
+    #[async_std::test]
+    async fn test_load_textures() {
+        let sticker = parse_multipart_document(
+            &MaterialRegistry::new(),
+            &mut &b"0 Sticker
+0 Name: sticker.dat
+0 !TEXMAP START PLANAR -20 0 10 20 0 10 -20 0 -10 Sticker.PNG GLOSSMAP missing.png
+0 !TEXMAP END
+"[..],
+        )
+        .await
+        .unwrap();
+        let loader = MemoryLoader::library([Arc::new(sticker)])
+            .texture("sticker.png", b"sticker")
+            .texture("logo.png", b"library logo");
+        let document = parse_multipart_document(
+            &MaterialRegistry::new(),
+            &mut &b"0 FILE model.ldr
+0 Model
+0 !TEXMAP START PLANAR -20 0 10 20 0 10 -20 0 -10 LOGO.png
+0 !TEXMAP END
+1 16 0 0 0 1 0 0 0 1 0 0 0 1 sticker.dat
+0 !DATA logo.png
+0 !: ZW1iZWRkZWQ=
+"[..],
+        )
+        .await
+        .unwrap();
+
+        let result = resolve_dependencies_with_progress(
+            Arc::new(RwLock::new(PartCache::new())),
+            &MaterialRegistry::new(),
+            &loader,
+            &document,
+            &|_, _| {},
+            &|_| {},
+        )
+        .await;
+        let textures = load_textures(&loader, &document, &result).await;
+
+        // Embedded images win over the library, and missing ones are left out.
+        assert_eq!(
+            textures,
+            HashMap::from([
+                ("logo.png".to_string(), b"embedded".to_vec()),
+                ("sticker.png".to_string(), b"sticker".to_vec()),
+            ])
+        );
+    }
+
+    #[async_std::test]
+    async fn test_resolution_cancelled() {
+        let loader = MemoryLoader::library([
+            library_document("a.dat", "A", &["b.dat"]),
+            library_document("b.dat", "B", &[]),
+        ]);
+        let document = library_document("model.ldr", "Model", &["a.dat"]);
+        let token = CancellationToken::new();
+
+        // Cancel as soon as the first file arrives so that b.dat is never loaded.
+        let result = resolve_dependencies_cancellable(
+            Arc::new(RwLock::new(PartCache::new())),
+            &MaterialRegistry::new(),
+            &loader,
+            &document,
+            &|_, _| token.cancel(),
+            &|_| {},
+            &token,
+        )
+        .await;
+
+        assert!(matches!(result, Err(ResolutionError::Cancelled)));
+    }
 }
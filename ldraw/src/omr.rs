@@ -0,0 +1,369 @@
+use std::fmt;
+
+use crate::{
+    document::{Document, MultipartDocument},
+    elements::Header,
+    PartAlias,
+};
+
+// A rule of the Official Model Repository specification that a model breaks.
+// `file` is None for the main model.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OmrViolation {
+    // The file should be named `<set number>-<version> - <set name>.mpd`.
+    InvalidFileName(String),
+    MissingDescription(Option<PartAlias>),
+    // `0 Name:` doesn't match the name the file is stored or referenced as.
+    NameMismatch {
+        file: Option<PartAlias>,
+        expected: String,
+        found: String,
+    },
+    MissingAuthor(Option<PartAlias>),
+    MissingHeader {
+        file: Option<PartAlias>,
+        header: &'static str,
+    },
+    InvalidLDrawOrg {
+        file: Option<PartAlias>,
+        value: String,
+    },
+    UnacceptableLicense {
+        file: Option<PartAlias>,
+        value: String,
+    },
+    UnusedSubfile(PartAlias),
+    CircularReference(Vec<PartAlias>),
+}
+
+fn describe(file: &Option<PartAlias>) -> String {
+    match file {
+        Some(alias) => format!("subfile {}", alias.original),
+        None => "main model".to_string(),
+    }
+}
+
+impl fmt::Display for OmrViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OmrViolation::InvalidFileName(name) => write!(
+                f,
+                "File name '{}' does not follow '<set number>-<version> - <set name>.mpd'.",
+                name
+            ),
+            OmrViolation::MissingDescription(file) => {
+                write!(f, "The {} has no description.", describe(file))
+            }
+            OmrViolation::NameMismatch {
+                file,
+                expected,
+                found,
+            } => write!(
+                f,
+                "The {} is named '{}' instead of '{}'.",
+                describe(file),
+                found,
+                expected
+            ),
+            OmrViolation::MissingAuthor(file) => {
+                write!(f, "The {} has no author.", describe(file))
+            }
+            OmrViolation::MissingHeader { file, header } => {
+                write!(f, "The {} has no !{} header.", describe(file), header)
+            }
+            OmrViolation::InvalidLDrawOrg { file, value } => write!(
+                f,
+                "The {} has an invalid !LDRAW_ORG type '{}'.",
+                describe(file),
+                value
+            ),
+            OmrViolation::UnacceptableLicense { file, value } => write!(
+                f,
+                "The {} has a license that is not accepted: '{}'.",
+                describe(file),
+                value
+            ),
+            OmrViolation::UnusedSubfile(alias) => {
+                write!(f, "Subfile {} is never referenced.", alias.original)
+            }
+            OmrViolation::CircularReference(cycle) => {
+                let names = cycle.iter().map(|e| &*e.original).collect::<Vec<_>>();
+                write!(f, "Circular reference: {}", names.join(" -> "))
+            }
+        }
+    }
+}
+
+const ACCEPTED_LICENSES: &[&str] = &[
+    "Licensed under CC BY 4.0 : see CAreadme.txt",
+    "Redistributable under CCAL version 2.0 : see CAreadme.txt",
+];
+
+// `6285-1 - Black Seas Barracuda.mpd`
+fn is_valid_file_name(name: &str) -> bool {
+    let stem = match name.strip_suffix(".mpd") {
+        Some(e) => e,
+        None => return false,
+    };
+    let (set, title) = match stem.split_once(" - ") {
+        Some(e) => e,
+        None => return false,
+    };
+    let (number, version) = match set.rsplit_once('-') {
+        Some(e) => e,
+        None => return false,
+    };
+
+    !number.is_empty()
+        && !number.contains(char::is_whitespace)
+        && !version.is_empty()
+        && version.bytes().all(|ch| ch.is_ascii_digit())
+        && !title.trim().is_empty()
+}
+
+fn header<'a>(document: &'a Document, key: &str) -> Option<&'a str> {
+    document
+        .headers
+        .iter()
+        .find(|Header(k, _)| k == key)
+        .map(|Header(_, v)| v.as_str())
+}
+
+fn check_document(
+    document: &Document,
+    file: Option<&PartAlias>,
+    expected_name: Option<&str>,
+    violations: &mut Vec<OmrViolation>,
+) {
+    let file = file.cloned();
+
+    if document.description.trim().is_empty() {
+        violations.push(OmrViolation::MissingDescription(file.clone()));
+    }
+    if let Some(expected) = expected_name {
+        if !document.name.eq_ignore_ascii_case(expected) {
+            violations.push(OmrViolation::NameMismatch {
+                file: file.clone(),
+                expected: expected.to_string(),
+                found: document.name.clone(),
+            });
+        }
+    }
+    if document.author.trim().is_empty() {
+        violations.push(OmrViolation::MissingAuthor(file.clone()));
+    }
+
+    // Unofficial parts a model needs are embedded as .dat subfiles, which
+    // keep the header of a part.
+    let part = file
+        .as_ref()
+        .is_some_and(|e| e.normalized.ends_with(".dat"));
+    match header(document, "LDRAW_ORG") {
+        Some(value) => {
+            let kind = value.split_whitespace().next().unwrap_or("");
+            let valid = if part {
+                kind.starts_with("Unofficial_") && kind != "Unofficial_Model"
+            } else {
+                kind == "Unofficial_Model" || kind == "Model"
+            };
+            if !valid {
+                violations.push(OmrViolation::InvalidLDrawOrg {
+                    file: file.clone(),
+                    value: value.to_string(),
+                });
+            }
+        }
+        None => violations.push(OmrViolation::MissingHeader {
+            file: file.clone(),
+            header: "LDRAW_ORG",
+        }),
+    }
+
+    match header(document, "LICENSE") {
+        Some(value) if ACCEPTED_LICENSES.contains(&value.trim()) => {}
+        Some(value) => violations.push(OmrViolation::UnacceptableLicense {
+            file: file.clone(),
+            value: value.to_string(),
+        }),
+        None => violations.push(OmrViolation::MissingHeader {
+            file: file.clone(),
+            header: "LICENSE",
+        }),
+    }
+
+    // Only the main model carries the theme.
+    if file.is_none() && header(document, "THEME").is_none() {
+        violations.push(OmrViolation::MissingHeader {
+            file,
+            header: "THEME",
+        });
+    }
+}
+
+// Checks a model against the naming and header rules of the LDraw.org
+// Official Model Repository. `file_name` is the name the model is stored as,
+// if known. An empty result means no violations were found.
+//
+// Not checked, as they need more than the document: that only official
+// colors are used, that embedded unofficial parts follow the part header
+// specification beyond !LDRAW_ORG (BFC certification, !HISTORY and so on),
+// the order of header lines, and that the model matches the set it is
+// named after.
+pub fn check_omr_compliance(
+    document: &MultipartDocument,
+    file_name: Option<&str>,
+) -> Vec<OmrViolation> {
+    let mut violations = Vec::new();
+
+    if let Some(file_name) = file_name {
+        if !is_valid_file_name(file_name) {
+            violations.push(OmrViolation::InvalidFileName(file_name.to_string()));
+        }
+    }
+
+    // The main model is named after the file it's stored in.
+    let main_name = file_name
+        .and_then(|e| e.strip_suffix(".mpd"))
+        .map(|e| format!("{}.ldr", e));
+    check_document(&document.body, None, main_name.as_deref(), &mut violations);
+
+    let mut subparts = document.subparts.iter().collect::<Vec<_>>();
+    subparts.sort_by(|(a, _), (b, _)| a.normalized.cmp(&b.normalized));
+    for (alias, subpart) in subparts {
+        check_document(subpart, Some(alias), Some(&alias.original), &mut violations);
+    }
+
+    let graph = document.dependency_graph(main_name.as_deref().unwrap_or("model.ldr"));
+    violations.extend(graph.unused().into_iter().map(OmrViolation::UnusedSubfile));
+    if let Some(cycle) = document.find_circular_reference() {
+        violations.push(OmrViolation::CircularReference(cycle));
+    }
+
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        color::ColorReference,
+        document::{BfcCertification, Document, MultipartDocument},
+        elements::{Command, Header, PartReference},
+        Matrix4, PartAlias,
+    };
+
+    use super::{check_omr_compliance, is_valid_file_name, OmrViolation};
+
+    fn model_document(name: &str, commands: Vec<Command>, theme: bool) -> Document {
+        let mut headers = vec![
+            Header("LDRAW_ORG".into(), "Unofficial_Model".into()),
+            Header(
+                "LICENSE".into(),
+                "Licensed under CC BY 4.0 : see CAreadme.txt".into(),
+            ),
+        ];
+        if theme {
+            headers.push(Header("THEME".into(), "Pirates".into()));
+        }
+
+        Document {
+            name: name.into(),
+            description: "Black Seas Barracuda".into(),
+            author: "Jane Doe [jdoe]".into(),
+            bfc: BfcCertification::NotApplicable,
+            headers,
+            commands,
+        }
+    }
+
+    fn reference(name: &str) -> Command {
+        Command::PartReference(PartReference {
+            color: ColorReference::Current,
+            matrix: Matrix4::from_scale(1.0),
+            name: PartAlias::from(name),
+        })
+    }
+
+    #[test]
+    fn test_file_name_scheme() {
+        assert!(is_valid_file_name("6285-1 - Black Seas Barracuda.mpd"));
+        assert!(is_valid_file_name("fig-000001-2 - Minifig.mpd"));
+        assert!(!is_valid_file_name("6285-1 - Black Seas Barracuda.ldr"));
+        assert!(!is_valid_file_name("6285 - Black Seas Barracuda.mpd"));
+        assert!(!is_valid_file_name("6285-1-Black Seas Barracuda.mpd"));
+        assert!(!is_valid_file_name("6285-a - Black Seas Barracuda.mpd"));
+    }
+
+    #[test]
+    fn test_check_omr_compliance() {
+        let main = "6285-1 - Black Seas Barracuda";
+        let mut document = MultipartDocument {
+            body: model_document(
+                &format!("{}.ldr", main),
+                vec![reference("6285-1 - Hull.ldr")],
+                true,
+            ),
+            subparts: HashMap::from([(
+                PartAlias::from("6285-1 - Hull.ldr"),
+                model_document("6285-1 - Hull.ldr", vec![reference("3001.dat")], false),
+            )]),
+            data: HashMap::new(),
+        };
+        let file_name = format!("{}.mpd", main);
+        assert_eq!(check_omr_compliance(&document, Some(&file_name)), vec![]);
+
+        document
+            .body
+            .headers
+            .retain(|Header(key, _)| key != "THEME");
+        let unused = PartAlias::from("6285-1 - Mast.ldr");
+        let mut mast = model_document("mast.ldr", vec![], false);
+        mast.headers[0].1 = "Part".into();
+        document.subparts.insert(unused.clone(), mast);
+        let part = PartAlias::from("6285-1 - sticker.dat");
+        let mut sticker = model_document("6285-1 - sticker.dat", vec![], false);
+        document.subparts.insert(part.clone(), sticker.clone());
+        document.body.commands.push(reference("6285-1 - sticker.dat"));
+
+        assert_eq!(
+            check_omr_compliance(&document, Some("Barracuda.mpd")),
+            vec![
+                OmrViolation::InvalidFileName("Barracuda.mpd".into()),
+                OmrViolation::NameMismatch {
+                    file: None,
+                    expected: "Barracuda.ldr".into(),
+                    found: format!("{}.ldr", main),
+                },
+                OmrViolation::MissingHeader {
+                    file: None,
+                    header: "THEME"
+                },
+                OmrViolation::NameMismatch {
+                    file: Some(unused.clone()),
+                    expected: "6285-1 - Mast.ldr".into(),
+                    found: "mast.ldr".into(),
+                },
+                OmrViolation::InvalidLDrawOrg {
+                    file: Some(unused.clone()),
+                    value: "Part".into(),
+                },
+                OmrViolation::InvalidLDrawOrg {
+                    file: Some(part.clone()),
+                    value: "Unofficial_Model".into(),
+                },
+                OmrViolation::UnusedSubfile(unused),
+            ]
+        );
+
+        sticker.headers[0].1 = "Unofficial_Part".into();
+        document.subparts.insert(part, sticker);
+        assert_eq!(
+            check_omr_compliance(&document, Some(&file_name))
+                .into_iter()
+                .filter(|e| matches!(e, OmrViolation::InvalidLDrawOrg { .. }))
+                .count(),
+            1
+        );
+    }
+}
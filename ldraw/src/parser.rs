@@ -1,4 +1,4 @@
-use std::{collections::HashMap, marker::Unpin, str::Chars};
+use std::{collections::HashMap, io::ErrorKind, marker::Unpin, str::Chars};
 
 use async_std::io::BufRead;
 use cgmath::Matrix;
@@ -11,123 +11,273 @@ use crate::{
     },
     document::{BfcCertification, Document, MultipartDocument},
     elements::{
-        BfcStatement, Command, Header, Line, Meta, OptionalLine, PartReference, Quad, Triangle,
+        BfcStatement, Command, Header, Line, Meta, MlcadMode, OptionalLine, PartReference, Quad,
+        TexMapStatement, Triangle,
     },
     error::{ColorDefinitionParseError, DocumentParseError, ParseError},
-    {Matrix4, PartAlias, Vector4, Winding},
+    {CancellationToken, Matrix4, PartAlias, Vector4, Winding},
 };
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NumberFormat {
+    // Plain decimals and scientific notation (`1e-4`, `2.5E1`).
+    #[default]
+    Strict,
+    // Additionally accepts decimal commas, `D` exponents and `f` suffixes
+    // produced by some old or machine-generated files.
+    Tolerant,
+}
+
+// Headers from the LDraw specification plus widely used tool extensions.
+const KNOWN_HEADERS: &[&str] = &[
+    "LDRAW_ORG",
+    "LICENSE",
+    "HELP",
+    "CATEGORY",
+    "KEYWORDS",
+    "CMDLINE",
+    "HISTORY",
+    "COLOUR",
+    "THEME",
+    "TEXMAP",
+    "DATA",
+    "AVATAR",
+    "LPUB",
+    "LEOCAD",
+    "LDCAD",
+];
+
+// The defaults keep the historical behavior: unknown line types and malformed
+// lines are errors, while every header and BFC placement is accepted.
+#[derive(Clone, Debug, Default)]
+pub struct ParseOptions {
+    pub number_format: NumberFormat,
+    // Checked before every line; a cancelled parse fails with
+    // ParseError::Cancelled.
+    pub cancellation: Option<CancellationToken>,
+    // Keep lines with an unknown line type as comments instead of failing.
+    pub unknown_lines_as_comments: bool,
+    // Keep type 1-5 lines that fail to parse as comments instead of failing.
+    pub malformed_lines_as_comments: bool,
+    // Discard `!` headers that are not part of the LDraw specification.
+    pub drop_unknown_headers: bool,
+    // Fail on `0 BFC` statements other than INVERTNEXT, i.e. certification,
+    // winding and clipping, appearing after the first reference or geometry
+    // line.
+    pub reject_late_bfc_statements: bool,
+}
+
+impl ParseOptions {
+    // For validating files, e.g. before submitting them to the library.
+    pub fn strict() -> Self {
+        ParseOptions {
+            reject_late_bfc_statements: true,
+            ..Default::default()
+        }
+    }
+
+    // For viewers that should show as much of a damaged file as possible.
+    pub fn lenient() -> Self {
+        ParseOptions {
+            number_format: NumberFormat::Tolerant,
+            unknown_lines_as_comments: true,
+            malformed_lines_as_comments: true,
+            ..Default::default()
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 enum Line0 {
     Header(Header),
     Meta(Meta),
     File(String),
+    NoFile,
     Name(String),
     Author(String),
     BfcCertification(BfcCertification),
+    // A line behind an MLCad prefix, left for the document parser.
+    Mlcad(MlcadMode, String),
 }
 
 fn is_whitespace(ch: char) -> bool {
     matches!(ch, ' ' | '\t' | '\r' | '\n')
 }
 
-fn next_token(iterator: &mut Chars, glob_remaining: bool) -> Result<String, ParseError> {
-    let mut buffer = String::new();
-    for v in iterator {
-        if !is_whitespace(v) {
-            buffer.push(v);
-        } else if !buffer.is_empty() {
-            if !glob_remaining {
-                break;
-            } else {
-                buffer.push(v);
-            }
+// Tokens are borrowed straight out of the line buffer; `iterator` is advanced
+// past the returned token so callers can keep pulling from the same line.
+fn next_token<'a>(iterator: &mut Chars<'a>, glob_remaining: bool) -> Result<&'a str, ParseError> {
+    let remaining = iterator.as_str().trim_start_matches(is_whitespace);
+
+    let (token, rest) = if glob_remaining {
+        (remaining.trim_end_matches(is_whitespace), "")
+    } else {
+        match remaining.find(is_whitespace) {
+            Some(index) => remaining.split_at(index),
+            None => (remaining, ""),
         }
-    }
+    };
+    *iterator = rest.chars();
 
-    match buffer.len() {
+    match token.len() {
         0 => Err(ParseError::EndOfLine),
-        _ => Ok(buffer.trim_end().to_string()),
+        _ => Ok(token),
     }
 }
 
 fn next_token_u32(iterator: &mut Chars) -> Result<u32, ParseError> {
     let token = next_token(iterator, false)?;
-    if token.starts_with("0x") {
-        let trimmed = token.chars().skip(2).collect::<String>();
-        return match u32::from_str_radix(trimmed.as_str(), 16) {
+    if let Some(hex) = token.strip_prefix("0x") {
+        return match u32::from_str_radix(hex, 16) {
             Ok(v) => Ok(v),
-            Err(_) => Err(ParseError::TypeMismatch("u32", token)),
+            Err(_) => Err(ParseError::TypeMismatch("u32", token.to_string())),
         };
     }
     match token.parse::<u32>() {
         Ok(v) => Ok(v),
-        Err(_) => Err(ParseError::TypeMismatch("u32", token)),
+        Err(_) => Err(ParseError::TypeMismatch("u32", token.to_string())),
     }
 }
 
-fn next_token_f32(iterator: &mut Chars) -> Result<f32, ParseError> {
-    let token = next_token(iterator, false)?;
-    match token.parse::<f32>() {
-        Ok(v) => Ok(v),
-        Err(_) => Err(ParseError::TypeMismatch("f32", token)),
-    }
-}
+const POWERS_OF_TEN: [f32; 11] = [1e0, 1e1, 1e2, 1e3, 1e4, 1e5, 1e6, 1e7, 1e8, 1e9, 1e10];
+
+// Fast path for plain decimals like `-12.23`, which is what nearly every
+// LDraw file contains. A mantissa below 2^24 and a power of ten up to 10^10
+// are both exact in f32, so a single division rounds correctly. Anything
+// else is left to the standard library.
+fn parse_f32_fast(token: &str) -> Option<f32> {
+    let bytes = token.as_bytes();
+    let (negative, digits) = match bytes.first()? {
+        b'-' => (true, &bytes[1..]),
+        b'+' => (false, &bytes[1..]),
+        _ => (false, bytes),
+    };
 
-fn next_token_rgb(iterator: &mut Chars) -> Result<(u8, u8, u8), ParseError> {
-    match iterator.next() {
-        Some(v) => {
-            if v != '#' {
-                return Err(ParseError::InvalidToken(v.to_string()));
+    let mut mantissa = 0u32;
+    let mut scale = 0usize;
+    let mut seen_dot = false;
+    let mut seen_digit = false;
+    for ch in digits {
+        match ch {
+            b'0'..=b'9' => {
+                mantissa = mantissa
+                    .checked_mul(10)?
+                    .checked_add(u32::from(ch - b'0'))?;
+                if seen_dot {
+                    scale += 1;
+                }
+                seen_digit = true;
             }
+            b'.' if !seen_dot => seen_dot = true,
+            _ => return None,
         }
-        None => {
-            return Err(ParseError::EndOfLine);
+    }
+
+    if !seen_digit || mantissa > 1 << 24 || scale >= POWERS_OF_TEN.len() {
+        return None;
+    }
+
+    let value = mantissa as f32 / POWERS_OF_TEN[scale];
+    Some(if negative { -value } else { value })
+}
+
+// Rewrites decimal commas (`1,5`), Fortran-style exponents (`2.5D1`) and C
+// float suffixes (`1.5f`) into something `str::parse` understands. Tokens
+// mixing commas and dots are rejected since the comma is then ambiguous.
+fn normalize_tolerant_f32(token: &str) -> Option<String> {
+    let token = token
+        .strip_suffix(|ch| matches!(ch, 'f' | 'F'))
+        .unwrap_or(token);
+    if token.contains('.') && token.contains(',') || token.matches(',').count() > 1 {
+        return None;
+    }
+
+    Some(
+        token
+            .chars()
+            .map(|ch| match ch {
+                ',' => '.',
+                'd' | 'D' => 'e',
+                ch => ch,
+            })
+            .collect(),
+    )
+}
+
+fn next_token_f32(iterator: &mut Chars, format: NumberFormat) -> Result<f32, ParseError> {
+    let token = next_token(iterator, false)?;
+    if let Some(v) = parse_f32_fast(token) {
+        return Ok(v);
+    }
+
+    let parsed = match token.parse::<f32>() {
+        Ok(v) => Some(v),
+        Err(_) if format == NumberFormat::Tolerant => {
+            normalize_tolerant_f32(token).and_then(|v| v.parse::<f32>().ok())
         }
+        Err(_) => None,
+    };
+    match parsed {
+        Some(v) if v.is_finite() => Ok(v),
+        _ => Err(ParseError::TypeMismatch("f32", token.to_string())),
     }
+}
 
-    let rs = iterator.take(2).collect::<String>();
-    let gs = iterator.take(2).collect::<String>();
-    let bs = iterator.take(2).collect::<String>();
+fn next_token_rgb(iterator: &mut Chars) -> Result<(u8, u8, u8), ParseError> {
+    let token = next_token(iterator, false)?;
+    let hex = match token.strip_prefix('#') {
+        Some(v) if v.len() == 6 && v.is_ascii() => v,
+        _ => return Err(ParseError::InvalidToken(token.to_string())),
+    };
+
+    let (rs, gs, bs) = (&hex[0..2], &hex[2..4], &hex[4..6]);
 
-    let r = match u8::from_str_radix(rs.as_str(), 16) {
+    let r = match u8::from_str_radix(rs, 16) {
         Ok(v) => v,
-        Err(_) => return Err(ParseError::TypeMismatch("u8", rs)),
+        Err(_) => return Err(ParseError::TypeMismatch("u8", rs.to_string())),
     };
-    let g = match u8::from_str_radix(gs.as_str(), 16) {
+    let g = match u8::from_str_radix(gs, 16) {
         Ok(v) => v,
-        Err(_) => return Err(ParseError::TypeMismatch("u8", gs)),
+        Err(_) => return Err(ParseError::TypeMismatch("u8", gs.to_string())),
     };
-    let b = match u8::from_str_radix(bs.as_str(), 16) {
+    let b = match u8::from_str_radix(bs, 16) {
         Ok(v) => v,
-        Err(_) => return Err(ParseError::TypeMismatch("u8", bs)),
+        Err(_) => return Err(ParseError::TypeMismatch("u8", bs.to_string())),
     };
 
     Ok((r, g, b))
 }
 
+fn has_geometry(commands: &[Command]) -> bool {
+    commands.iter().any(|e| !matches!(e, Command::Meta(_)))
+}
+
 fn parse_bfc_statement(iterator: &mut Chars) -> Result<Line0, ParseError> {
     let stmt = next_token(iterator, true)?;
-    match stmt.as_str() {
-        "NOCERTIFY" => Ok(Line0::BfcCertification(BfcCertification::NoCertify)),
-        "CERTIFY" | "CERTIFY CCW" => Ok(Line0::BfcCertification(BfcCertification::Certify(
+    let tokens = stmt
+        .split(is_whitespace)
+        .filter(|v| !v.is_empty())
+        .collect::<Vec<_>>();
+    match tokens.as_slice() {
+        ["NOCERTIFY"] => Ok(Line0::BfcCertification(BfcCertification::NoCertify)),
+        ["CERTIFY"] | ["CERTIFY", "CCW"] => Ok(Line0::BfcCertification(BfcCertification::Certify(
             Winding::Ccw,
         ))),
-        "CERTIFY CW" => Ok(Line0::BfcCertification(BfcCertification::Certify(
+        ["CERTIFY", "CW"] => Ok(Line0::BfcCertification(BfcCertification::Certify(
             Winding::Cw,
         ))),
-        "CW" => Ok(Line0::Meta(Meta::Bfc(BfcStatement::Winding(Winding::Cw)))),
-        "CCW" => Ok(Line0::Meta(Meta::Bfc(BfcStatement::Winding(Winding::Ccw)))),
-        "CLIP" => Ok(Line0::Meta(Meta::Bfc(BfcStatement::Clip(None)))),
-        "CLIP CW" => Ok(Line0::Meta(Meta::Bfc(BfcStatement::Clip(Some(
+        ["CW"] => Ok(Line0::Meta(Meta::Bfc(BfcStatement::Winding(Winding::Cw)))),
+        ["CCW"] => Ok(Line0::Meta(Meta::Bfc(BfcStatement::Winding(Winding::Ccw)))),
+        ["CLIP"] => Ok(Line0::Meta(Meta::Bfc(BfcStatement::Clip(None)))),
+        ["CLIP", "CW"] | ["CW", "CLIP"] => Ok(Line0::Meta(Meta::Bfc(BfcStatement::Clip(Some(
             Winding::Cw,
         ))))),
-        "CLIP CCW" => Ok(Line0::Meta(Meta::Bfc(BfcStatement::Clip(Some(
+        ["CLIP", "CCW"] | ["CCW", "CLIP"] => Ok(Line0::Meta(Meta::Bfc(BfcStatement::Clip(Some(
             Winding::Ccw,
         ))))),
-        "NOCLIP" => Ok(Line0::Meta(Meta::Bfc(BfcStatement::NoClip))),
-        "INVERTNEXT" => Ok(Line0::Meta(Meta::Bfc(BfcStatement::InvertNext))),
-        _ => Err(ParseError::InvalidBfcStatement(stmt)),
+        ["NOCLIP"] => Ok(Line0::Meta(Meta::Bfc(BfcStatement::NoClip))),
+        ["INVERTNEXT"] => Ok(Line0::Meta(Meta::Bfc(BfcStatement::InvertNext))),
+        _ => Err(ParseError::InvalidBfcStatement(stmt.to_string())),
     }
 }
 
@@ -137,65 +287,97 @@ fn parse_line_0(iterator: &mut Chars) -> Result<Line0, ParseError> {
         Err(ParseError::EndOfLine) => return Ok(Line0::Meta(Meta::Comment(String::new()))),
         Err(e) => return Err(e),
     };
+    if let Some(comment) = text.strip_prefix("//") {
+        return Ok(Line0::Meta(Meta::Comment(
+            comment.trim_start_matches(is_whitespace).to_string(),
+        )));
+    }
+
     let mut inner_iterator = text.chars();
     let cmd = next_token(&mut inner_iterator, false)?;
 
-    if cmd.starts_with('!') {
-        let key: String = cmd.chars().skip(1).collect();
-        let value = next_token(&mut inner_iterator, true)?;
-        return Ok(Line0::Header(Header(key, value)));
+    if let Some(key) = cmd.strip_prefix('!') {
+        let value = match next_token(&mut inner_iterator, true) {
+            Ok(v) => v,
+            Err(ParseError::EndOfLine) => "",
+            Err(e) => return Err(e),
+        };
+        // Malformed texture statements are kept as plain headers.
+        if key == "TEXMAP" {
+            if let Ok(statement) = value.parse() {
+                return Ok(Line0::Meta(Meta::TexMap(statement)));
+            }
+        }
+        return Ok(Line0::Header(Header(key.to_string(), value.to_string())));
     }
 
-    match cmd.as_str() {
+    match cmd {
         "BFC" => parse_bfc_statement(&mut inner_iterator),
         "Name:" => match next_token(&mut inner_iterator, true) {
-            Ok(msg) => Ok(Line0::Name(msg)),
+            Ok(msg) => Ok(Line0::Name(msg.to_string())),
             Err(_) => Ok(Line0::Name(String::from(""))),
         },
         "Author:" => match next_token(&mut inner_iterator, true) {
-            Ok(msg) => Ok(Line0::Author(msg)),
+            Ok(msg) => Ok(Line0::Author(msg.to_string())),
             Err(_) => Ok(Line0::Author(String::from(""))),
         },
         "FILE" => match next_token(&mut inner_iterator, true) {
-            Ok(msg) => Ok(Line0::File(msg)),
+            Ok(msg) => Ok(Line0::File(msg.to_string())),
             Err(e) => Err(e),
         },
+        "NOFILE" => Ok(Line0::NoFile),
         "STEP" => Ok(Line0::Meta(Meta::Step)),
+        "ROTSTEP" => match next_token(&mut inner_iterator, true).map(str::parse) {
+            Ok(Ok(statement)) => Ok(Line0::Meta(Meta::RotStep(statement))),
+            _ => Ok(Line0::Meta(Meta::Comment(text.to_string()))),
+        },
         "WRITE" => match next_token(&mut inner_iterator, true) {
-            Ok(msg) => Ok(Line0::Meta(Meta::Write(msg))),
+            Ok(msg) => Ok(Line0::Meta(Meta::Write(msg.to_string()))),
             Err(e) => Err(e),
         },
         "PRINT" => match next_token(&mut inner_iterator, true) {
-            Ok(msg) => Ok(Line0::Meta(Meta::Print(msg))),
+            Ok(msg) => Ok(Line0::Meta(Meta::Print(msg.to_string()))),
             Err(e) => Err(e),
         },
         "CLEAR" => Ok(Line0::Meta(Meta::Clear)),
         "PAUSE" => Ok(Line0::Meta(Meta::Pause)),
         "SAVE" => Ok(Line0::Meta(Meta::Save)),
-        _ => Ok(Line0::Meta(Meta::Comment(text))),
+        "GHOST" => match next_token(&mut inner_iterator, true) {
+            Ok(line) => Ok(Line0::Mlcad(MlcadMode::Ghost, line.to_string())),
+            Err(_) => Ok(Line0::Meta(Meta::Comment(text.to_string()))),
+        },
+        "MLCAD" => match (
+            next_token(&mut inner_iterator, false),
+            next_token(&mut inner_iterator, true),
+        ) {
+            (Ok("HIDE"), Ok(line)) => Ok(Line0::Mlcad(MlcadMode::Hide, line.to_string())),
+            _ => Ok(Line0::Meta(Meta::Comment(text.to_string()))),
+        },
+        _ => Ok(Line0::Meta(Meta::Comment(text.to_string()))),
     }
 }
 
 fn parse_line_1(
     materials: &MaterialRegistry,
+    options: &ParseOptions,
     iterator: &mut Chars,
 ) -> Result<PartReference, ParseError> {
     let color = next_token_u32(iterator)?;
-    let x = next_token_f32(iterator)?;
-    let y = next_token_f32(iterator)?;
-    let z = next_token_f32(iterator)?;
+    let x = next_token_f32(iterator, options.number_format)?;
+    let y = next_token_f32(iterator, options.number_format)?;
+    let z = next_token_f32(iterator, options.number_format)?;
     let matrix = Matrix4::new(
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
         x,
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
         y,
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
         z,
         0.0,
         0.0,
@@ -211,18 +393,22 @@ fn parse_line_1(
     })
 }
 
-fn parse_line_2(materials: &MaterialRegistry, iterator: &mut Chars) -> Result<Line, ParseError> {
+fn parse_line_2(
+    materials: &MaterialRegistry,
+    options: &ParseOptions,
+    iterator: &mut Chars,
+) -> Result<Line, ParseError> {
     let color = next_token_u32(iterator)?;
     let a = Vector4::new(
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
         1.0,
     );
     let b = Vector4::new(
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
         1.0,
     );
     Ok(Line {
@@ -234,25 +420,26 @@ fn parse_line_2(materials: &MaterialRegistry, iterator: &mut Chars) -> Result<Li
 
 fn parse_line_3(
     materials: &MaterialRegistry,
+    options: &ParseOptions,
     iterator: &mut Chars,
 ) -> Result<Triangle, ParseError> {
     let color = next_token_u32(iterator)?;
     let a = Vector4::new(
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
         1.0,
     );
     let b = Vector4::new(
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
         1.0,
     );
     let c = Vector4::new(
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
         1.0,
     );
     Ok(Triangle {
@@ -263,30 +450,34 @@ fn parse_line_3(
     })
 }
 
-fn parse_line_4(materials: &MaterialRegistry, iterator: &mut Chars) -> Result<Quad, ParseError> {
+fn parse_line_4(
+    materials: &MaterialRegistry,
+    options: &ParseOptions,
+    iterator: &mut Chars,
+) -> Result<Quad, ParseError> {
     let color = next_token_u32(iterator)?;
     let a = Vector4::new(
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
         1.0,
     );
     let b = Vector4::new(
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
         1.0,
     );
     let c = Vector4::new(
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
         1.0,
     );
     let d = Vector4::new(
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
         1.0,
     );
     Ok(Quad {
@@ -300,31 +491,32 @@ fn parse_line_4(materials: &MaterialRegistry, iterator: &mut Chars) -> Result<Qu
 
 fn parse_line_5(
     materials: &MaterialRegistry,
+    options: &ParseOptions,
     iterator: &mut Chars,
 ) -> Result<OptionalLine, ParseError> {
     let color = next_token_u32(iterator)?;
     let a = Vector4::new(
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
         1.0,
     );
     let b = Vector4::new(
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
         1.0,
     );
     let c = Vector4::new(
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
         1.0,
     );
     let d = Vector4::new(
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
-        next_token_f32(iterator)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
+        next_token_f32(iterator, options.number_format)?,
         1.0,
     );
     Ok(OptionalLine {
@@ -336,12 +528,141 @@ fn parse_line_5(
     })
 }
 
+// Parses a type 1-5 line after its line type token.
+fn parse_command(
+    token: &str,
+    materials: &MaterialRegistry,
+    options: &ParseOptions,
+    iterator: &mut Chars,
+) -> Result<Command, ParseError> {
+    match token {
+        "1" => parse_line_1(materials, options, iterator).map(Command::PartReference),
+        "2" => parse_line_2(materials, options, iterator).map(Command::Line),
+        "3" => parse_line_3(materials, options, iterator).map(Command::Triangle),
+        "4" => parse_line_4(materials, options, iterator).map(Command::Quad),
+        "5" => parse_line_5(materials, options, iterator).map(Command::OptionalLine),
+        _ => Err(ParseError::UnexpectedCommand(token.to_string())),
+    }
+}
+
+// What a multipart document continues with after the current file.
+#[derive(Debug, PartialEq)]
+enum NextFile {
+    Document(String),
+    Data(String),
+}
+
+impl NextFile {
+    fn from_line_0(line: Line0) -> Option<NextFile> {
+        match line {
+            Line0::File(file) => Some(NextFile::Document(file)),
+            Line0::Header(Header(key, value)) if key == "DATA" => Some(NextFile::Data(value)),
+            _ => None,
+        }
+    }
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn decode_base64(text: &str, output: &mut Vec<u8>) -> Result<(), ParseError> {
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for ch in text.trim_end_matches('=').bytes() {
+        let value = match BASE64_ALPHABET.iter().position(|&e| e == ch) {
+            Some(v) => v as u32,
+            None => return Err(ParseError::InvalidToken(text.to_string())),
+        };
+        buffer = (buffer << 6) | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    Ok(())
+}
+
+// Skips to the next `0 FILE` or `0 !DATA`. Anything goes past `0 NOFILE`;
+// for non-LDraw data some exporters append without it, `error` is returned
+// if geometry follows, as the data was not trailing after all.
+async fn skip_to_next_file<T: BufRead + Unpin>(
+    iterator: &mut Enumerate<Lines<T>>,
+    error: Option<DocumentParseError>,
+) -> Result<Option<NextFile>, DocumentParseError> {
+    while let Some((index, line)) = iterator.next().await {
+        let line = match line {
+            Ok(v) => v,
+            Err(e) if e.kind() == ErrorKind::InvalidData => continue,
+            Err(e) => {
+                return Err(DocumentParseError {
+                    line: index + 1,
+                    error: ParseError::from(e),
+                });
+            }
+        };
+        let mut it = line.chars();
+        match next_token(&mut it, false) {
+            Ok("0") => {
+                if let Some(next) = parse_line_0(&mut it).ok().and_then(NextFile::from_line_0) {
+                    return Ok(Some(next));
+                }
+            }
+            Ok("1" | "2" | "3" | "4" | "5") => {
+                if let Some(error) = error {
+                    return Err(error);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(None)
+}
+
+// Reads the base64 encoded `0 !:` lines of a `!DATA` block.
+async fn parse_data<T: BufRead + Unpin>(
+    iterator: &mut Enumerate<Lines<T>>,
+) -> Result<(Vec<u8>, Option<NextFile>), DocumentParseError> {
+    let mut data = Vec::new();
+
+    while let Some((index, line)) = iterator.next().await {
+        let line = line.map_err(|e| DocumentParseError {
+            line: index + 1,
+            error: ParseError::from(e),
+        })?;
+        let mut it = line.chars();
+        if let Ok("0") = next_token(&mut it, false) {
+            match parse_line_0(&mut it) {
+                Ok(Line0::Header(Header(key, value))) if key == ":" => {
+                    decode_base64(&value, &mut data).map_err(|error| DocumentParseError {
+                        line: index + 1,
+                        error,
+                    })?;
+                }
+                Ok(Line0::NoFile) => return Ok((data, skip_to_next_file(iterator, None).await?)),
+                Ok(line) => {
+                    if let Some(next) = NextFile::from_line_0(line) {
+                        return Ok((data, Some(next)));
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    Ok((data, None))
+}
+
 async fn parse_inner<T: BufRead + Unpin>(
     materials: &MaterialRegistry,
+    options: &ParseOptions,
     iterator: &mut Enumerate<Lines<T>>,
     multipart: bool,
-) -> Result<(Document, Option<String>), DocumentParseError> {
-    let mut next: Option<String> = None;
+) -> Result<(Document, Option<NextFile>), DocumentParseError> {
+    let mut next: Option<NextFile> = None;
     let mut name = String::new();
     let mut author = String::new();
     let mut description = String::new();
@@ -350,8 +671,26 @@ async fn parse_inner<T: BufRead + Unpin>(
     let mut headers = Vec::new();
 
     'read_loop: while let Some((index, line_)) = iterator.next().await {
+        if options
+            .cancellation
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+        {
+            return Err(DocumentParseError {
+                line: index + 1,
+                error: ParseError::Cancelled,
+            });
+        }
         let line = match line_ {
             Ok(v) => v,
+            Err(e) if multipart && e.kind() == ErrorKind::InvalidData => {
+                let error = DocumentParseError {
+                    line: index + 1,
+                    error: ParseError::from(e),
+                };
+                next = skip_to_next_file(iterator, Some(error)).await?;
+                break 'read_loop;
+            }
             Err(e) => {
                 return Err(DocumentParseError {
                     line: index + 1,
@@ -361,16 +700,24 @@ async fn parse_inner<T: BufRead + Unpin>(
         };
         let mut it = line.chars();
         match next_token(&mut it, false) {
-            Ok(token) => match token.as_str() {
+            Ok(token) => match token {
                 "0" => match parse_line_0(&mut it) {
                     Ok(val) => match val {
                         Line0::BfcCertification(bfc_) => {
+                            if options.reject_late_bfc_statements && has_geometry(&commands) {
+                                return Err(DocumentParseError {
+                                    line: index + 1,
+                                    error: ParseError::InvalidBfcStatement(
+                                        "certification after geometry".to_string(),
+                                    ),
+                                });
+                            }
                             bfc = bfc_;
                         }
                         Line0::File(file_) => {
                             if multipart {
                                 if !description.is_empty() {
-                                    next = Some(file_);
+                                    next = Some(NextFile::Document(file_));
                                     break 'read_loop;
                                 }
                             } else {
@@ -380,12 +727,34 @@ async fn parse_inner<T: BufRead + Unpin>(
                                 });
                             }
                         }
+                        // Anything between `0 NOFILE` and the next `0 FILE` is not part
+                        // of the model, including non-LDraw data some exporters append
+                        // after the last subfile.
+                        Line0::NoFile => {
+                            if multipart {
+                                next = skip_to_next_file(iterator, None).await?;
+                            }
+                            break 'read_loop;
+                        }
                         Line0::Name(name_) => {
                             name = name_;
                         }
                         Line0::Author(author_) => {
                             author = author_;
                         }
+                        Line0::Meta(Meta::Bfc(statement))
+                            if options.reject_late_bfc_statements
+                                && statement != BfcStatement::InvertNext
+                                && has_geometry(&commands) =>
+                        {
+                            return Err(DocumentParseError {
+                                line: index + 1,
+                                error: ParseError::InvalidBfcStatement(format!(
+                                    "{:?} after geometry",
+                                    statement
+                                )),
+                            });
+                        }
                         Line0::Meta(meta) => {
                             if let Meta::Comment(comment) = meta {
                                 if description.is_empty() {
@@ -397,8 +766,59 @@ async fn parse_inner<T: BufRead + Unpin>(
                                 commands.push(Command::Meta(meta));
                             }
                         }
+                        Line0::Header(Header(key, value)) if key == ":" => {
+                            let mut it = value.chars();
+                            let command = next_token(&mut it, false).and_then(|token| {
+                                parse_command(token, materials, options, &mut it)
+                            });
+                            match command {
+                                Ok(command) => commands.push(Command::Meta(Meta::TexMap(
+                                    TexMapStatement::Geometry(Box::new(command)),
+                                ))),
+                                Err(_) if options.malformed_lines_as_comments => {
+                                    commands.push(Command::Meta(Meta::Comment(
+                                        line.trim().to_string(),
+                                    )));
+                                }
+                                Err(e) => {
+                                    return Err(DocumentParseError {
+                                        line: index + 1,
+                                        error: e,
+                                    });
+                                }
+                            }
+                        }
+                        Line0::Mlcad(mode, value) => {
+                            let mut it = value.chars();
+                            let command = next_token(&mut it, false).and_then(|token| {
+                                parse_command(token, materials, options, &mut it)
+                            });
+                            match command {
+                                Ok(command) => commands
+                                    .push(Command::Meta(Meta::Mlcad(mode, Box::new(command)))),
+                                Err(_) if options.malformed_lines_as_comments => {
+                                    commands.push(Command::Meta(Meta::Comment(
+                                        line.trim().to_string(),
+                                    )));
+                                }
+                                Err(e) => {
+                                    return Err(DocumentParseError {
+                                        line: index + 1,
+                                        error: e,
+                                    });
+                                }
+                            }
+                        }
+                        Line0::Header(Header(key, value)) if multipart && key == "DATA" => {
+                            next = Some(NextFile::Data(value));
+                            break 'read_loop;
+                        }
                         Line0::Header(header) => {
-                            headers.push(header);
+                            if !options.drop_unknown_headers
+                                || KNOWN_HEADERS.contains(&header.0.as_str())
+                            {
+                                headers.push(header);
+                            }
                         }
                     },
                     Err(e) => {
@@ -408,55 +828,35 @@ async fn parse_inner<T: BufRead + Unpin>(
                         });
                     }
                 },
-                "1" => match parse_line_1(materials, &mut it) {
-                    Ok(val) => commands.push(Command::PartReference(val)),
-                    Err(e) => {
-                        return Err(DocumentParseError {
-                            line: index + 1,
-                            error: e,
-                        });
-                    }
-                },
-                "2" => match parse_line_2(materials, &mut it) {
-                    Ok(val) => commands.push(Command::Line(val)),
-                    Err(e) => {
-                        return Err(DocumentParseError {
-                            line: index + 1,
-                            error: e,
-                        });
-                    }
-                },
-                "3" => match parse_line_3(materials, &mut it) {
-                    Ok(val) => commands.push(Command::Triangle(val)),
-                    Err(e) => {
-                        return Err(DocumentParseError {
-                            line: index + 1,
-                            error: e,
-                        });
-                    }
-                },
-                "4" => match parse_line_4(materials, &mut it) {
-                    Ok(val) => commands.push(Command::Quad(val)),
-                    Err(e) => {
-                        return Err(DocumentParseError {
-                            line: index + 1,
-                            error: e,
-                        });
-                    }
-                },
-                "5" => match parse_line_5(materials, &mut it) {
-                    Ok(val) => commands.push(Command::OptionalLine(val)),
-                    Err(e) => {
-                        return Err(DocumentParseError {
-                            line: index + 1,
-                            error: e,
-                        });
+                "1" | "2" | "3" | "4" | "5" => {
+                    match parse_command(token, materials, options, &mut it) {
+                        Ok(command) => commands.push(command),
+                        Err(_) if options.malformed_lines_as_comments => {
+                            commands.push(Command::Meta(Meta::Comment(line.trim().to_string())));
+                        }
+                        Err(e) => {
+                            return Err(DocumentParseError {
+                                line: index + 1,
+                                error: e,
+                            });
+                        }
                     }
-                },
+                }
+                _ if options.unknown_lines_as_comments => {
+                    commands.push(Command::Meta(Meta::Comment(line.trim().to_string())));
+                }
+                _ if multipart => {
+                    let error = DocumentParseError {
+                        line: index + 1,
+                        error: ParseError::UnexpectedCommand(token.to_string()),
+                    };
+                    next = skip_to_next_file(iterator, Some(error)).await?;
+                    break 'read_loop;
+                }
                 _ => {
                     return Err(DocumentParseError {
                         line: index + 1,
-                        error: ParseError::UnexpectedCommand(token),
+                        error: ParseError::UnexpectedCommand(token.to_string()),
                     });
                 }
             },
@@ -486,9 +886,17 @@ async fn parse_inner<T: BufRead + Unpin>(
 pub async fn parse_single_document<T: BufRead + Unpin>(
     materials: &MaterialRegistry,
     reader: &mut T,
+) -> Result<Document, DocumentParseError> {
+    parse_single_document_with_options(materials, &ParseOptions::default(), reader).await
+}
+
+pub async fn parse_single_document_with_options<T: BufRead + Unpin>(
+    materials: &MaterialRegistry,
+    options: &ParseOptions,
+    reader: &mut T,
 ) -> Result<Document, DocumentParseError> {
     let mut it = reader.lines().enumerate();
-    let (document, _) = parse_inner(materials, &mut it, false).await?;
+    let (document, _) = parse_inner(materials, options, &mut it, false).await?;
 
     Ok(document)
 }
@@ -496,28 +904,46 @@ pub async fn parse_single_document<T: BufRead + Unpin>(
 pub async fn parse_multipart_document<T: BufRead + Unpin>(
     materials: &MaterialRegistry,
     reader: &mut T,
+) -> Result<MultipartDocument, DocumentParseError> {
+    parse_multipart_document_with_options(materials, &ParseOptions::default(), reader).await
+}
+
+pub async fn parse_multipart_document_with_options<T: BufRead + Unpin>(
+    materials: &MaterialRegistry,
+    options: &ParseOptions,
+    reader: &mut T,
 ) -> Result<MultipartDocument, DocumentParseError> {
     let mut it = reader.lines().enumerate();
-    let (document, mut next) = parse_inner(materials, &mut it, true).await?;
+    let (document, mut next) = parse_inner(materials, options, &mut it, true).await?;
     let mut subparts = HashMap::new();
-
-    while next.is_some() {
-        let (part, next_) = parse_inner(materials, &mut it, true).await?;
-
-        subparts.insert(PartAlias::from(&next.unwrap()), part);
-        next = next_;
+    let mut data = HashMap::new();
+
+    while let Some(current) = next {
+        next = match current {
+            NextFile::Document(name) => {
+                let (part, next_) = parse_inner(materials, options, &mut it, true).await?;
+                subparts.insert(PartAlias::from(&name), part);
+                next_
+            }
+            NextFile::Data(name) => {
+                let (bytes, next_) = parse_data(&mut it).await?;
+                data.insert(name, bytes);
+                next_
+            }
+        };
     }
 
     Ok(MultipartDocument {
         body: document,
         subparts,
+        data,
     })
 }
 
 fn parse_customized_material(
     iterator: &mut Chars,
 ) -> Result<CustomizedMaterial, ColorDefinitionParseError> {
-    match next_token(iterator, false)?.as_str() {
+    match next_token(iterator, false)? {
         "GLITTER" => {
             let mut alpha = 255u8;
             let mut luminance = 0u8;
@@ -526,7 +952,7 @@ fn parse_customized_material(
             let mut size = 0u32;
             let mut minsize = 0.0;
             let mut maxsize = 0.0;
-            match next_token(iterator, false)?.as_str() {
+            match next_token(iterator, false)? {
                 "VALUE" => (),
                 e => {
                     return Err(ColorDefinitionParseError::ParseError(
@@ -542,7 +968,7 @@ fn parse_customized_material(
                     Err(e) => return Err(ColorDefinitionParseError::ParseError(e)),
                 };
 
-                match token.as_str() {
+                match token {
                     "ALPHA" => {
                         alpha = next_token_u32(iterator)? as u8;
                     }
@@ -550,23 +976,23 @@ fn parse_customized_material(
                         luminance = next_token_u32(iterator)? as u8;
                     }
                     "FRACTION" => {
-                        fraction = next_token_f32(iterator)?;
+                        fraction = next_token_f32(iterator, NumberFormat::Strict)?;
                     }
                     "VFRACTION" => {
-                        vfraction = next_token_f32(iterator)?;
+                        vfraction = next_token_f32(iterator, NumberFormat::Strict)?;
                     }
                     "SIZE" => {
                         size = next_token_u32(iterator)?;
                     }
                     "MINSIZE" => {
-                        minsize = next_token_f32(iterator)?;
+                        minsize = next_token_f32(iterator, NumberFormat::Strict)?;
                     }
                     "MAXSIZE" => {
-                        maxsize = next_token_f32(iterator)?;
+                        maxsize = next_token_f32(iterator, NumberFormat::Strict)?;
                     }
                     _ => {
                         return Err(ColorDefinitionParseError::ParseError(
-                            ParseError::InvalidToken(token.clone()),
+                            ParseError::InvalidToken(token.to_string()),
                         ));
                     }
                 }
@@ -588,7 +1014,7 @@ fn parse_customized_material(
             let mut size = 0u32;
             let mut minsize = 0.0;
             let mut maxsize = 0.0;
-            match next_token(iterator, false)?.as_str() {
+            match next_token(iterator, false)? {
                 "VALUE" => (),
                 e => {
                     return Err(ColorDefinitionParseError::ParseError(
@@ -604,7 +1030,7 @@ fn parse_customized_material(
                     Err(e) => return Err(ColorDefinitionParseError::ParseError(e)),
                 };
 
-                match token.as_str() {
+                match token {
                     "ALPHA" => {
                         alpha = next_token_u32(iterator)? as u8;
                     }
@@ -612,20 +1038,20 @@ fn parse_customized_material(
                         luminance = next_token_u32(iterator)? as u8;
                     }
                     "FRACTION" => {
-                        fraction = next_token_f32(iterator)?;
+                        fraction = next_token_f32(iterator, NumberFormat::Strict)?;
                     }
                     "SIZE" => {
                         size = next_token_u32(iterator)?;
                     }
                     "MINSIZE" => {
-                        minsize = next_token_f32(iterator)?;
+                        minsize = next_token_f32(iterator, NumberFormat::Strict)?;
                     }
                     "MAXSIZE" => {
-                        maxsize = next_token_f32(iterator)?;
+                        maxsize = next_token_f32(iterator, NumberFormat::Strict)?;
                     }
                     _ => {
                         return Err(ColorDefinitionParseError::ParseError(
-                            ParseError::InvalidToken(token.clone()),
+                            ParseError::InvalidToken(token.to_string()),
                         ));
                     }
                 }
@@ -657,9 +1083,9 @@ pub async fn parse_color_definition<T: BufRead + Unpin>(
         let mut luminance = 0u8;
 
         let mut it = value.chars();
-        let name = next_token(&mut it, false)?;
+        let name = next_token(&mut it, false)?.to_string();
 
-        match next_token(&mut it, false)?.as_str() {
+        match next_token(&mut it, false)? {
             "CODE" => (),
             e => {
                 return Err(ColorDefinitionParseError::ParseError(
@@ -669,7 +1095,7 @@ pub async fn parse_color_definition<T: BufRead + Unpin>(
         };
         let code = next_token_u32(&mut it)?;
 
-        match next_token(&mut it, false)?.as_str() {
+        match next_token(&mut it, false)? {
             "VALUE" => (),
             e => {
                 return Err(ColorDefinitionParseError::ParseError(
@@ -679,7 +1105,7 @@ pub async fn parse_color_definition<T: BufRead + Unpin>(
         };
         let (cr, cg, cb) = next_token_rgb(&mut it)?;
 
-        match next_token(&mut it, false)?.as_str() {
+        match next_token(&mut it, false)? {
             "EDGE" => (),
             e => {
                 return Err(ColorDefinitionParseError::ParseError(
@@ -696,7 +1122,7 @@ pub async fn parse_color_definition<T: BufRead + Unpin>(
                 Err(e) => return Err(ColorDefinitionParseError::ParseError(e)),
             };
 
-            match token.as_str() {
+            match token {
                 "ALPHA" => {
                     alpha = next_token_u32(&mut it)? as u8;
                 }
@@ -723,7 +1149,7 @@ pub async fn parse_color_definition<T: BufRead + Unpin>(
                 }
                 _ => {
                     return Err(ColorDefinitionParseError::ParseError(
-                        ParseError::InvalidToken(token.clone()),
+                        ParseError::InvalidToken(token.to_string()),
                     ));
                 }
             }
@@ -748,6 +1174,10 @@ pub async fn parse_color_definition<T: BufRead + Unpin>(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        elements::{RotStep, RotStepMode, TexMap, TexMapProjection},
+        Vector3,
+    };
 
     fn parse_line_0_or_panic(input: &str) -> Line0 {
         match parse_line_0(&mut input.chars()) {
@@ -758,6 +1188,82 @@ mod tests {
         }
     }
 
+    #[test]
+    fn next_token_borrows_from_line() {
+        let line = "  1 16\t0 0 0   sub part.dat  ";
+        let mut it = line.chars();
+
+        let token = next_token(&mut it, false).unwrap();
+        assert_eq!(token, "1");
+        assert_eq!(token.as_ptr(), line[2..].as_ptr());
+        assert_eq!(next_token(&mut it, false).unwrap(), "16");
+        assert_eq!(next_token_u32(&mut it.clone()).unwrap(), 0);
+        for _ in 0..3 {
+            next_token(&mut it, false).unwrap();
+        }
+        assert_eq!(next_token(&mut it, true).unwrap(), "sub part.dat");
+        assert!(matches!(
+            next_token(&mut it, false),
+            Err(ParseError::EndOfLine)
+        ));
+    }
+
+    #[test]
+    fn parse_f32_fast_matches_std() {
+        let cases = [
+            "0", "-0", "1", "+1.5", "-12.23", ".67", "4.", "-0.25", "22.04", "0.000001",
+            "16777216", "123456.7", "0.0625", "-59.974",
+        ];
+        for input in cases {
+            assert_eq!(
+                parse_f32_fast(input).map(f32::to_bits),
+                Some(input.parse::<f32>().unwrap().to_bits()),
+                "{}",
+                input
+            );
+        }
+
+        for input in [
+            "",
+            "-",
+            ".",
+            "1.2.3",
+            "1e5",
+            "16777217",
+            "0.00000000001",
+            "abc",
+        ] {
+            assert_eq!(parse_f32_fast(input), None, "{}", input);
+        }
+    }
+
+    #[test]
+    fn next_token_f32_accepts_scientific_notation() {
+        for (input, output) in [("1e-4", 1e-4), ("2.5E1", 25.0), ("-1.0E+02", -100.0)] {
+            let parsed = next_token_f32(&mut input.chars(), NumberFormat::Strict).unwrap();
+            assert_eq!(parsed, output);
+        }
+    }
+
+    #[test]
+    fn next_token_f32_tolerant_formats() {
+        let cases = [
+            ("1,5", 1.5),
+            ("-0,25", -0.25),
+            ("2.5D1", 25.0),
+            ("1.5f", 1.5),
+        ];
+        for (input, output) in cases {
+            assert!(next_token_f32(&mut input.chars(), NumberFormat::Strict).is_err());
+            let parsed = next_token_f32(&mut input.chars(), NumberFormat::Tolerant).unwrap();
+            assert_eq!(parsed, output);
+        }
+
+        for input in ["1,000.5", "1,2,3", "inf", "NaN"] {
+            assert!(next_token_f32(&mut input.chars(), NumberFormat::Tolerant).is_err());
+        }
+    }
+
     #[test]
     fn parse_line_0_parses_comment() {
         let cases = [
@@ -778,6 +1284,22 @@ mod tests {
     fn parse_line_0_parses_offical_meta_commands_without_bfc() {
         let cases = [
             ("STEP", Meta::Step),
+            (
+                "ROTSTEP 30 -45 0 ABS",
+                Meta::RotStep(RotStep::Rotation(
+                    Vector3::new(30.0, -45.0, 0.0),
+                    RotStepMode::Absolute,
+                )),
+            ),
+            (
+                "ROTSTEP 0 90 0",
+                Meta::RotStep(RotStep::Rotation(
+                    Vector3::new(0.0, 90.0, 0.0),
+                    RotStepMode::Relative,
+                )),
+            ),
+            ("ROTSTEP END", Meta::RotStep(RotStep::End)),
+            ("ROTSTEP 0 90", Meta::Comment("ROTSTEP 0 90".into())),
             (
                 "WRITE any length of string",
                 Meta::Write("any length of string".into()),
@@ -1163,7 +1685,7 @@ mod tests {
             .await
             .unwrap();
         let line_1 = "1 11 -0.25 -16 2 0 0 0 1 0 0 0 -2 1-4disc.dat";
-        let parsed = parse_line_1(&colors, &mut line_1.chars()).unwrap();
+        let parsed = parse_line_1(&colors, &ParseOptions::default(), &mut line_1.chars()).unwrap();
         assert_eq!(
             parsed,
             PartReference {
@@ -1182,7 +1704,7 @@ mod tests {
             .await
             .unwrap();
         let line_2 = "16 3 2.7 8 -12.23 4.17 .67";
-        let parsed = parse_line_2(&colors, &mut line_2.chars()).unwrap();
+        let parsed = parse_line_2(&colors, &ParseOptions::default(), &mut line_2.chars()).unwrap();
         assert_eq!(
             parsed,
             Line {
@@ -1199,7 +1721,7 @@ mod tests {
             .await
             .unwrap();
         let line_3 = "15 22.04 -.25 -1.16 23.72 -.25 -4.49 23.72 -.25 -2.61";
-        let parsed = parse_line_3(&colors, &mut line_3.chars()).unwrap();
+        let parsed = parse_line_3(&colors, &ParseOptions::default(), &mut line_3.chars()).unwrap();
         assert_eq!(
             parsed,
             Triangle {
@@ -1217,7 +1739,7 @@ mod tests {
             .await
             .unwrap();
         let line_4 = "1 -11 -0.25 -18 11 -0.25 -18 11 -0.25 -12.7 -11 -0.25 -12.7";
-        let parsed = parse_line_4(&colors, &mut line_4.chars()).unwrap();
+        let parsed = parse_line_4(&colors, &ParseOptions::default(), &mut line_4.chars()).unwrap();
         assert_eq!(
             parsed,
             Quad {
@@ -1237,7 +1759,7 @@ mod tests {
             .unwrap();
         let line_5 =
             "24 0 -55.673 -15.623 0 -59.974 -18.831 4.233 -59.338 -18.968 -4.233 -59.338 -18.968";
-        let parsed = parse_line_5(&colors, &mut line_5.chars()).unwrap();
+        let parsed = parse_line_5(&colors, &ParseOptions::default(), &mut line_5.chars()).unwrap();
         assert_eq!(
             parsed,
             OptionalLine {
@@ -1293,6 +1815,91 @@ mod tests {
         );
     }
 
+    #[async_std::test]
+    async fn test_parse_texmap() {
+        let colors = parse_color_definition(&mut COLOR_DEFINITIONS.as_bytes())
+            .await
+            .unwrap();
+        let document = "0 Sticker
+0 Name: sticker.dat
+0 Author: LDraw.rs
+0 !TEXMAP START PLANAR -20 0 10 20 0 10 -20 0 -10 sticker.png GLOSSMAP gloss.png
+0 !: 3 16 -20 0 10 20 0 10 20 0 -10
+0 !TEXMAP FALLBACK
+3 16 -20 0 10 20 0 10 20 0 -10
+0 !TEXMAP END
+0 !TEXMAP BOGUS";
+        let parsed = parse_single_document(&colors, &mut document.as_bytes())
+            .await
+            .unwrap();
+
+        let triangle = Command::Triangle(Triangle {
+            color: ColorReference::Current,
+            a: Vector4::new(-20., 0., 10., 1.),
+            b: Vector4::new(20., 0., 10., 1.),
+            c: Vector4::new(20., 0., -10., 1.),
+        });
+        assert_eq!(
+            parsed.commands,
+            vec![
+                Command::Meta(Meta::TexMap(TexMapStatement::Start(TexMap {
+                    projection: TexMapProjection::Planar {
+                        p1: Vector3::new(-20., 0., 10.),
+                        p2: Vector3::new(20., 0., 10.),
+                        p3: Vector3::new(-20., 0., -10.),
+                    },
+                    texture: "sticker.png".into(),
+                    glossmap: Some("gloss.png".into()),
+                }))),
+                Command::Meta(Meta::TexMap(TexMapStatement::Geometry(Box::new(
+                    triangle.clone()
+                )))),
+                Command::Meta(Meta::TexMap(TexMapStatement::Fallback)),
+                triangle,
+                Command::Meta(Meta::TexMap(TexMapStatement::End)),
+            ]
+        );
+        assert_eq!(
+            parsed.headers,
+            vec![Header("TEXMAP".into(), "BOGUS".into())]
+        );
+    }
+
+    #[async_std::test]
+    async fn test_parse_mlcad_prefixes() {
+        let colors = parse_color_definition(&mut COLOR_DEFINITIONS.as_bytes())
+            .await
+            .unwrap();
+        let document = "0 Ghosts
+0 Name: ghosts.ldr
+0 Author: LDraw.rs
+0 GHOST 1 16 0 0 0 1 0 0 0 1 0 0 0 1 3001.dat
+0 MLCAD HIDE 1 16 0 -24 0 1 0 0 0 1 0 0 0 1 3003.dat
+0 MLCAD BTG Wall
+1 16 0 -48 0 1 0 0 0 1 0 0 0 1 3002.dat";
+        let parsed = parse_single_document(&colors, &mut document.as_bytes())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            parsed
+                .iter_refs_with_mode()
+                .map(|(e, mode)| (&*e.name.normalized, mode))
+                .collect::<Vec<_>>(),
+            vec![
+                ("3001.dat", Some(MlcadMode::Ghost)),
+                ("3003.dat", Some(MlcadMode::Hide)),
+                ("3002.dat", None),
+            ]
+        );
+        assert_eq!(parsed.iter_refs().count(), 1);
+        assert_eq!(parsed.list_dependencies().len(), 3);
+        assert_eq!(
+            parsed.commands[2],
+            Command::Meta(Meta::Comment("MLCAD BTG Wall".into()))
+        );
+    }
+
     #[async_std::test]
     async fn test_parse_multipart_document() {
         let colors = parse_color_definition(&mut COLOR_DEFINITIONS.as_bytes())
@@ -1366,7 +1973,256 @@ mod tests {
                     ]
                 },
                 subparts,
+                data: HashMap::new(),
             }
         )
     }
+
+    #[async_std::test]
+    async fn test_parse_cancelled() {
+        let colors = parse_color_definition(&mut COLOR_DEFINITIONS.as_bytes())
+            .await
+            .unwrap();
+        let token = CancellationToken::new();
+        let options = ParseOptions {
+            cancellation: Some(token.clone()),
+            ..Default::default()
+        };
+        let document = "0 Apple\n0 Name: apple.ldr\n2 24 100 24 80 80 24 20";
+
+        let parsed =
+            parse_multipart_document_with_options(&colors, &options, &mut document.as_bytes())
+                .await;
+        assert!(parsed.is_ok());
+
+        token.cancel();
+        let parsed =
+            parse_multipart_document_with_options(&colors, &options, &mut document.as_bytes())
+                .await;
+        assert!(matches!(
+            parsed,
+            Err(DocumentParseError {
+                line: 1,
+                error: ParseError::Cancelled
+            })
+        ));
+    }
+
+    #[async_std::test]
+    async fn test_parse_options_strictness() {
+        let colors = parse_color_definition(&mut COLOR_DEFINITIONS.as_bytes())
+            .await
+            .unwrap();
+        let document = "0 Broken
+0 Name: broken.dat
+0 !LDRAW_ORG Unofficial_Part
+0 !MYTOOL_SETTING 1
+2 24 0 0 0 1 1 1
+7 24 0 0 0
+3 24 0 0 0 1 1
+0 BFC CERTIFY CCW";
+
+        let parsed = parse_single_document(&colors, &mut document.as_bytes()).await;
+        assert!(matches!(
+            parsed,
+            Err(DocumentParseError {
+                line: 6,
+                error: ParseError::UnexpectedCommand(_)
+            })
+        ));
+
+        let parsed = parse_single_document_with_options(
+            &colors,
+            &ParseOptions::lenient(),
+            &mut document.as_bytes(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(parsed.headers.len(), 2);
+        assert_eq!(parsed.bfc, BfcCertification::Certify(Winding::Ccw));
+        assert_eq!(
+            parsed.commands[1..],
+            [
+                Command::Meta(Meta::Comment("7 24 0 0 0".into())),
+                Command::Meta(Meta::Comment("3 24 0 0 0 1 1".into())),
+            ]
+        );
+
+        let options = ParseOptions {
+            drop_unknown_headers: true,
+            ..ParseOptions::lenient()
+        };
+        let parsed =
+            parse_single_document_with_options(&colors, &options, &mut document.as_bytes())
+                .await
+                .unwrap();
+        assert_eq!(
+            parsed.headers,
+            vec![Header("LDRAW_ORG".into(), "Unofficial_Part".into())]
+        );
+
+        let options = ParseOptions {
+            reject_late_bfc_statements: true,
+            ..ParseOptions::lenient()
+        };
+        let parsed =
+            parse_single_document_with_options(&colors, &options, &mut document.as_bytes()).await;
+        assert!(matches!(
+            parsed,
+            Err(DocumentParseError {
+                line: 8,
+                error: ParseError::InvalidBfcStatement(_)
+            })
+        ));
+
+        for (statement, valid) in [
+            ("CW", false),
+            ("CCW", false),
+            ("CLIP", false),
+            ("NOCLIP", false),
+            ("INVERTNEXT", true),
+        ] {
+            let document = format!(
+                "0 Late\n0 Name: late.dat\n0 BFC CERTIFY CCW\n2 24 0 0 0 1 1 1\n0 BFC {}\n",
+                statement
+            );
+            let parsed = parse_single_document_with_options(
+                &colors,
+                &ParseOptions::strict(),
+                &mut document.as_bytes(),
+            )
+            .await;
+            assert_eq!(parsed.is_ok(), valid, "{}", statement);
+        }
+
+        // Unknown lines within the files of a multipart document.
+        let document = "0 FILE main.ldr\n0 Main\n0 Name: main.ldr\n7 24 0 0 0\n\
+1 16 0 0 0 1 0 0 0 1 0 0 0 1 3001.dat\n";
+        let parsed = parse_multipart_document_with_options(
+            &colors,
+            &ParseOptions::strict(),
+            &mut document.as_bytes(),
+        )
+        .await;
+        assert!(matches!(
+            parsed,
+            Err(DocumentParseError {
+                line: 4,
+                error: ParseError::UnexpectedCommand(_)
+            })
+        ));
+        let parsed = parse_multipart_document_with_options(
+            &colors,
+            &ParseOptions::lenient(),
+            &mut document.as_bytes(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            parsed.body.commands[0],
+            Command::Meta(Meta::Comment("7 24 0 0 0".into()))
+        );
+        assert_eq!(parsed.body.iter_refs().count(), 1);
+    }
+
+    #[async_std::test]
+    async fn test_parse_multipart_document_nofile() {
+        let colors = parse_color_definition(&mut COLOR_DEFINITIONS.as_bytes())
+            .await
+            .unwrap();
+        // Laid out the way Stud.io exports MPD files, with every subfile
+        // terminated by NOFILE and a non-LDraw trailer at the end.
+        let body = b"0 FILE main.ldr\r
+0 Main\r
+0 Name: main.ldr\r
+0 Author: \r
+0 BFC CERTIFY CCW\r
+1 7 0 0 0 1 0 0 0 1 0 0 0 1 sub.ldr\r
+0 NOFILE\r
+This line is ignored\r
+0 FILE sub.ldr\r
+0 Sub\r
+0 Name: sub.ldr\r
+0 Author: \r
+1 3 0 -24 0 1 0 0 0 1 0 0 0 1 3001.dat\r
+";
+        let document = [
+            &body[..],
+            b"0 NOFILE\r\nPK\x03\x04\xff\xfe\r\n<Model Version=\"1\" />\r\n",
+        ]
+        .concat();
+
+        let parsed = parse_multipart_document(&colors, &mut document.as_slice())
+            .await
+            .unwrap();
+        assert_eq!(parsed.body.name, "main.ldr");
+        assert_eq!(parsed.body.commands.len(), 1);
+        assert_eq!(parsed.subparts.len(), 1);
+        let sub = &parsed.subparts[&PartAlias::from("sub.ldr")];
+        assert_eq!(sub.description, "Sub");
+        assert_eq!(
+            sub.iter_refs().next().unwrap().name,
+            PartAlias::from("3001.dat")
+        );
+
+        // Without NOFILE, trailing data is skipped up to the next file.
+        let document = [
+            &body[..],
+            b"PK\x03\x04\xff\xfe\r\nTrailing data\r\n0 FILE other.ldr\r\n0 Other\r\n",
+        ]
+        .concat();
+        let parsed = parse_multipart_document(&colors, &mut document.as_slice())
+            .await
+            .unwrap();
+        assert_eq!(parsed.subparts.len(), 2);
+        assert_eq!(
+            parsed.subparts[&PartAlias::from("sub.ldr")].commands.len(),
+            1
+        );
+
+        // Unless geometry follows it.
+        let document = [&body[..], b"Garbage\r\n2 24 0 0 0 1 1 1\r\n"].concat();
+        assert!(matches!(
+            parse_multipart_document(&colors, &mut document.as_slice()).await,
+            Err(DocumentParseError {
+                line: 14,
+                error: ParseError::UnexpectedCommand(_)
+            })
+        ));
+    }
+
+    #[async_std::test]
+    async fn test_parse_multipart_document_data() {
+        let colors = parse_color_definition(&mut COLOR_DEFINITIONS.as_bytes())
+            .await
+            .unwrap();
+        let document = "0 FILE main.ldr
+0 Main
+0 Name: main.ldr
+0 !TEXMAP START PLANAR -20 0 10 20 0 10 -20 0 -10 logo.png
+0 !: 3 16 -20 0 10 20 0 10 20 0 -10
+0 !TEXMAP END
+0 !DATA logo.png
+0 !: iVBO
+0 !: Rw0K
+0 FILE sub.ldr
+0 Sub
+0 Name: sub.ldr
+0 !DATA name.txt
+0 !: TERyYXcucnM=
+";
+        let parsed = parse_multipart_document(&colors, &mut document.as_bytes())
+            .await
+            .unwrap();
+        assert_eq!(parsed.body.commands.len(), 3);
+        assert_eq!(parsed.subparts.len(), 1);
+        assert_eq!(parsed.data.len(), 2);
+        assert_eq!(parsed.data["logo.png"], b"\x89PNG\r\n");
+        assert_eq!(parsed.data["name.txt"], b"LDraw.rs");
+
+        let document = "0 Main\n0 !DATA broken.png\n0 !: ?!\n";
+        assert!(parse_multipart_document(&colors, &mut document.as_bytes())
+            .await
+            .is_err());
+    }
 }
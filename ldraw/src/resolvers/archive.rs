@@ -0,0 +1,159 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Error as IoError, Read},
+    path::Path,
+    sync::Mutex,
+};
+
+use async_trait::async_trait;
+use zip::ZipArchive;
+
+use crate::{
+    color::MaterialRegistry,
+    document::MultipartDocument,
+    error::ResolutionError,
+    library::{FileLocation, LibraryLoader, PartKind},
+    parser::{parse_color_definition, parse_multipart_document},
+    PartAlias,
+};
+
+// Loads library files straight out of a library archive. Both the layout of
+// complete.zip (`ldraw/parts/...`) and of ldrawunf.zip (`parts/...`) are
+// accepted.
+pub struct ZipLoader {
+    archive: Mutex<ZipArchive<File>>,
+    entries: HashMap<String, usize>,
+}
+
+impl ZipLoader {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, ResolutionError> {
+        let mut archive = ZipArchive::new(File::open(path)?).map_err(IoError::from)?;
+
+        let mut entries = HashMap::new();
+        for i in 0..archive.len() {
+            let name = PartAlias::normalize(archive.by_index_raw(i).map_err(IoError::from)?.name());
+            let name = name.strip_prefix("ldraw/").unwrap_or(&name).to_string();
+            entries.insert(name, i);
+        }
+
+        Ok(ZipLoader {
+            archive: Mutex::new(archive),
+            entries,
+        })
+    }
+
+    fn read(&self, name: &str) -> Result<Option<Vec<u8>>, ResolutionError> {
+        let index = match self.entries.get(name) {
+            Some(e) => *e,
+            None => return Ok(None),
+        };
+
+        let mut archive = self.archive.lock().unwrap();
+        let mut file = archive.by_index(index).map_err(IoError::from)?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        Ok(Some(contents))
+    }
+}
+
+#[async_trait(?Send)]
+impl LibraryLoader for ZipLoader {
+    async fn load_materials(&self) -> Result<MaterialRegistry, ResolutionError> {
+        match self.read("ldconfig.ldr")? {
+            Some(contents) => Ok(parse_color_definition(&mut contents.as_slice()).await?),
+            None => Err(ResolutionError::FileNotFound),
+        }
+    }
+
+    async fn load_ref(
+        &self,
+        materials: &MaterialRegistry,
+        alias: PartAlias,
+        _local: bool,
+    ) -> Result<(FileLocation, MultipartDocument), ResolutionError> {
+        for (kind, dir) in [(PartKind::Part, "parts"), (PartKind::Primitive, "p")] {
+            if let Some(contents) = self.read(&format!("{}/{}", dir, alias.normalized))? {
+                let document =
+                    parse_multipart_document(materials, &mut contents.as_slice()).await?;
+                return Ok((FileLocation::Library(kind), document));
+            }
+        }
+
+        Err(ResolutionError::FileNotFound)
+    }
+
+    async fn load_texture(&self, name: &str) -> Result<Vec<u8>, ResolutionError> {
+        for dir in ["parts", "p"] {
+            if let Some(contents) = self.read(&format!("{}/textures/{}", dir, name))? {
+                return Ok(contents);
+            }
+        }
+
+        Err(ResolutionError::FileNotFound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, io::Write};
+
+    use zip::{write::FileOptions, ZipWriter};
+
+    use crate::{
+        color::MaterialRegistry,
+        library::{FileLocation, LibraryLoader, PartKind},
+        PartAlias,
+    };
+
+    use super::ZipLoader;
+
+    #[async_std::test]
+    async fn test_zip_loader() {
+        let path =
+            std::env::temp_dir().join(format!("ldraw-zip-loader-{}.zip", std::process::id()));
+        {
+            let mut writer = ZipWriter::new(File::create(&path).unwrap());
+            for (name, contents) in [
+                ("ldraw/parts/3001.dat", "0 Brick  2 x  4\n"),
+                (
+                    "ldraw/parts/s/3001s01.dat",
+                    "0 ~Brick  2 x  4 without Front Face\n",
+                ),
+                ("ldraw/p/stud.dat", "0 Stud\n"),
+            ] {
+                writer.start_file(name, FileOptions::default()).unwrap();
+                writer.write_all(contents.as_bytes()).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+
+        let loader = ZipLoader::open(&path).unwrap();
+        let materials = MaterialRegistry::new();
+
+        let (location, document) = loader
+            .load_ref(&materials, PartAlias::from("S\\3001S01.DAT"), false)
+            .await
+            .unwrap();
+        assert!(matches!(location, FileLocation::Library(PartKind::Part)));
+        assert_eq!(
+            document.body.description,
+            "~Brick  2 x  4 without Front Face"
+        );
+
+        let (location, _) = loader
+            .load_ref(&materials, PartAlias::from("stud.dat"), false)
+            .await
+            .unwrap();
+        assert!(matches!(
+            location,
+            FileLocation::Library(PartKind::Primitive)
+        ));
+        assert!(loader
+            .load_ref(&materials, PartAlias::from("3002.dat"), false)
+            .await
+            .is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,243 @@
+use std::path::Component;
+
+use async_std::{
+    fs::{self, File},
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+use async_trait::async_trait;
+use reqwest::{Client, Error as ReqwestError, Response, StatusCode, Url};
+
+use crate::{
+    color::MaterialRegistry,
+    document::MultipartDocument,
+    error::ResolutionError,
+    library::{FileLocation, LibraryLoader, PartKind},
+    parser::parse_multipart_document,
+    PartAlias,
+};
+
+const OFFICIAL_LIBRARY_URL: &str = "https://library.ldraw.org/library/official/";
+const UNOFFICIAL_LIBRARY_URL: &str = "https://library.ldraw.org/library/unofficial/";
+
+// Wraps another loader and fetches library files it cannot find from the
+// LDraw Parts Tracker, keeping them in a cache directory laid out like an
+// LDraw library (`parts/`, `p/`). Dependencies of downloaded files go
+// through the resolver again, so they are fetched the same way.
+pub struct DownloadingLoader {
+    inner: Box<dyn LibraryLoader>,
+    cache_dir: PathBuf,
+    base_urls: Vec<Url>,
+
+    client: Client,
+}
+
+impl DownloadingLoader {
+    pub fn new(inner: Box<dyn LibraryLoader>, cache_dir: PathBuf) -> Self {
+        let base_urls = [OFFICIAL_LIBRARY_URL, UNOFFICIAL_LIBRARY_URL]
+            .iter()
+            .map(|e| Url::parse(e).unwrap())
+            .collect();
+
+        DownloadingLoader {
+            inner,
+            cache_dir,
+            base_urls,
+            client: Client::new(),
+        }
+    }
+
+    // Libraries are tried in order; each URL must end with a slash.
+    pub fn with_base_urls(mut self, base_urls: Vec<Url>) -> Self {
+        self.base_urls = base_urls;
+        self
+    }
+
+    async fn load_cached(&self, alias: &PartAlias) -> Option<(PartKind, PathBuf)> {
+        for kind in [PartKind::Part, PartKind::Primitive] {
+            let path = cache_path(&self.cache_dir, kind, alias)?;
+            if path.exists().await {
+                return Some((kind, path));
+            }
+        }
+
+        None
+    }
+
+    // Fails with the last error if no library has the file but some could
+    // not be reached, as the file may well be there.
+    async fn download(&self, alias: &PartAlias) -> Result<(PartKind, Vec<u8>), ResolutionError> {
+        let mut error = None;
+        for base_url in self.base_urls.iter() {
+            for kind in [PartKind::Part, PartKind::Primitive] {
+                let url = match base_url.join(&format!("{}/{}", kind_dir(kind), alias.normalized)) {
+                    Ok(e) => e,
+                    Err(_) => continue,
+                };
+                match check_response(self.client.get(url).send().await) {
+                    Ok(Some(response)) => {
+                        return Ok((kind, response.bytes().await?.to_vec()));
+                    }
+                    Ok(None) => {}
+                    Err(e) => error = Some(e),
+                }
+            }
+        }
+
+        Err(error.map_or(ResolutionError::FileNotFound, ResolutionError::RemoteError))
+    }
+}
+
+// Whether `alias` names a file inside a library directory rather than one
+// reached through `..`, the root or a drive.
+fn is_library_path(alias: &PartAlias) -> bool {
+    alias.normalized.split('/').all(|segment| {
+        let mut components = std::path::Path::new(segment).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(e)), None) => e.to_str() == Some(segment),
+            _ => false,
+        }
+    })
+}
+
+fn cache_path(cache_dir: &Path, kind: PartKind, alias: &PartAlias) -> Option<PathBuf> {
+    if !is_library_path(alias) {
+        return None;
+    }
+
+    let mut path = cache_dir.to_path_buf();
+    path.push(kind_dir(kind));
+    path.push(&*alias.normalized);
+    Some(path)
+}
+
+// The response if it carries the file, None if the library does not have it
+// and an error if it could not be asked.
+fn check_response(
+    result: Result<Response, ReqwestError>,
+) -> Result<Option<Response>, ReqwestError> {
+    let response = result?;
+    if matches!(response.status(), StatusCode::NOT_FOUND | StatusCode::GONE) {
+        return Ok(None);
+    }
+    response.error_for_status().map(Some)
+}
+
+fn kind_dir(kind: PartKind) -> &'static str {
+    match kind {
+        PartKind::Part => "parts",
+        PartKind::Primitive => "p",
+    }
+}
+
+#[async_trait(?Send)]
+impl LibraryLoader for DownloadingLoader {
+    async fn load_materials(&self) -> Result<MaterialRegistry, ResolutionError> {
+        self.inner.load_materials().await
+    }
+
+    async fn load_ref(
+        &self,
+        materials: &MaterialRegistry,
+        alias: PartAlias,
+        local: bool,
+    ) -> Result<(FileLocation, MultipartDocument), ResolutionError> {
+        match self.inner.load_ref(materials, alias.clone(), local).await {
+            Err(ResolutionError::FileNotFound) | Err(ResolutionError::NoLDrawDir) => {}
+            result => return result,
+        };
+        if !is_library_path(&alias) {
+            return Err(ResolutionError::FileNotFound);
+        }
+
+        if let Some((kind, path)) = self.load_cached(&alias).await {
+            let document =
+                parse_multipart_document(materials, &mut BufReader::new(File::open(&path).await?))
+                    .await?;
+            return Ok((FileLocation::Library(kind), document));
+        }
+
+        let (kind, bytes) = self.download(&alias).await?;
+        // Only keep files that actually parse so that a broken download does
+        // not shadow the library forever.
+        let document = parse_multipart_document(materials, &mut BufReader::new(&*bytes)).await?;
+
+        let path = cache_path(&self.cache_dir, kind, &alias).unwrap();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::write(&path, &bytes).await?;
+
+        Ok((FileLocation::Library(kind), document))
+    }
+
+    async fn load_texture(&self, name: &str) -> Result<Vec<u8>, ResolutionError> {
+        self.inner.load_texture(name).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::path::{Path, PathBuf};
+    use reqwest::{Client, Response, StatusCode};
+
+    use super::{cache_path, check_response};
+    use crate::{library::PartKind, PartAlias};
+
+    fn response(status: u16) -> Response {
+        Response::from(
+            http::Response::builder()
+                .status(status)
+                .body(Vec::<u8>::new())
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_cache_path() {
+        let cache_dir = Path::new("cache");
+
+        assert_eq!(
+            cache_path(
+                cache_dir,
+                PartKind::Part,
+                &PartAlias::from("S\\3001s01.dat")
+            ),
+            Some(PathBuf::from("cache/parts/s/3001s01.dat"))
+        );
+        assert_eq!(
+            cache_path(
+                cache_dir,
+                PartKind::Primitive,
+                &PartAlias::from("4-4disc.dat")
+            ),
+            Some(PathBuf::from("cache/p/4-4disc.dat"))
+        );
+        for alias in [
+            "../../etc/passwd",
+            "s/../../x.dat",
+            "/etc/passwd",
+            "./3001.dat",
+            "s//3001.dat",
+            "",
+        ] {
+            assert_eq!(
+                cache_path(cache_dir, PartKind::Part, &PartAlias::from(alias)),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn test_check_response() {
+        assert_eq!(
+            check_response(Ok(response(200))).unwrap().unwrap().status(),
+            StatusCode::OK
+        );
+        assert!(check_response(Ok(response(404))).unwrap().is_none());
+        assert!(check_response(Ok(response(503))).is_err());
+
+        let error = Client::new().get("not a url").build().unwrap_err();
+        assert!(check_response(Err(error)).is_err());
+    }
+}
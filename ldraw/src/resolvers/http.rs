@@ -113,6 +113,29 @@ impl LibraryLoader for HttpLoader {
         let bytes = res.bytes().await?;
         Ok((location, parse_multipart_document(materials, &mut BufReader::new(&*bytes)).await?))
     }
+
+    async fn load_texture(&self, name: &str) -> Result<Vec<u8>, ResolutionError> {
+        let ldraw_url_base = match self.ldraw_url_base.as_ref() {
+            Some(e) => e,
+            None => return Err(ResolutionError::NoLDrawDir),
+        };
+
+        let mut urls = vec![
+            ldraw_url_base.join(&format!("parts/textures/{}", name)).unwrap(),
+            ldraw_url_base.join(&format!("p/textures/{}", name)).unwrap(),
+        ];
+        if let Some(document_url_base) = self.document_url_base.as_ref() {
+            urls.insert(0, document_url_base.join(&format!("textures/{}", name)).unwrap());
+        }
+
+        for url in urls {
+            if let Some(res) = select_response(self.client.get(url).send().await) {
+                return Ok(res.bytes().await?.to_vec());
+            }
+        }
+
+        Err(ResolutionError::FileNotFound)
+    }
 }
 
 fn select_response(response: Result<Response, Error>) -> Option<Response> {
@@ -79,19 +79,19 @@ impl LibraryLoader for LocalLoader {
 
         let cwd_path = self.cwd.as_ref().map(|v| {
             let mut path = v.clone();
-            path.push(alias.normalized.clone());
+            path.push(&*alias.normalized);
             path
         });
         let parts_path = {
             let mut path = ldrawdir.clone();
             path.push("parts");
-            path.push(alias.normalized.clone());
+            path.push(&*alias.normalized);
             path
         };
         let p_path = {
             let mut path = ldrawdir.clone();
             path.push("p");
-            path.push(alias.normalized.clone());
+            path.push(&*alias.normalized);
             path
         };
 
@@ -111,4 +111,25 @@ impl LibraryLoader for LocalLoader {
 
         Ok((kind, document))
     }
+
+    async fn load_texture(&self, name: &str) -> Result<Vec<u8>, ResolutionError> {
+        let ldrawdir = match self.ldrawdir.clone() {
+            Some(e) => e,
+            None => return Err(ResolutionError::NoLDrawDir),
+        };
+
+        // Textures next to the model come first, like local files.
+        let dirs = self.cwd.iter().cloned().chain([
+            ldrawdir.join("parts"),
+            ldrawdir.join("p"),
+        ]);
+        for dir in dirs {
+            let path = dir.join("textures").join(name);
+            if path.exists().await {
+                return Ok(async_std::fs::read(&path).await?);
+            }
+        }
+
+        Err(ResolutionError::FileNotFound)
+    }
 }
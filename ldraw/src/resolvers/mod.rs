@@ -1,4 +1,9 @@
+#[cfg(all(not(target_arch = "wasm32"), feature = "zip"))]
+pub mod archive;
+#[cfg(all(not(target_arch = "wasm32"), feature = "http"))]
+pub mod download;
 #[cfg(any(target_arch = "wasm32", feature = "http"))]
 pub mod http;
 #[cfg(not(target_arch = "wasm32"))]
 pub mod local;
+pub mod overlay;
@@ -0,0 +1,175 @@
+use std::{collections::HashMap, sync::RwLock};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    color::MaterialRegistry,
+    document::MultipartDocument,
+    error::ResolutionError,
+    library::{FileLocation, LibraryLoader},
+    PartAlias,
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LibrarySource {
+    Local,
+    Official,
+    Unofficial,
+}
+
+// Layers the unofficial library (Parts Tracker files, e.g. ldrawunf.zip or
+// its extracted directory) over the official one. Files next to the model
+// come first, then the official library, and the unofficial library only
+// fills in what the official one doesn't have, which is the search order
+// LDView and LDCad use. Materials always come from the official library.
+pub struct OverlayLoader {
+    official: Box<dyn LibraryLoader>,
+    unofficial: Box<dyn LibraryLoader>,
+
+    sources: RwLock<HashMap<PartAlias, LibrarySource>>,
+}
+
+impl OverlayLoader {
+    pub fn new(official: Box<dyn LibraryLoader>, unofficial: Box<dyn LibraryLoader>) -> Self {
+        OverlayLoader {
+            official,
+            unofficial,
+            sources: RwLock::new(HashMap::new()),
+        }
+    }
+
+    // Which library a file loaded through this loader came from.
+    pub fn source(&self, alias: &PartAlias) -> Option<LibrarySource> {
+        self.sources.read().unwrap().get(alias).copied()
+    }
+
+    pub fn sources(&self) -> HashMap<PartAlias, LibrarySource> {
+        self.sources.read().unwrap().clone()
+    }
+}
+
+#[async_trait(?Send)]
+impl LibraryLoader for OverlayLoader {
+    async fn load_materials(&self) -> Result<MaterialRegistry, ResolutionError> {
+        self.official.load_materials().await
+    }
+
+    async fn load_ref(
+        &self,
+        materials: &MaterialRegistry,
+        alias: PartAlias,
+        local: bool,
+    ) -> Result<(FileLocation, MultipartDocument), ResolutionError> {
+        let (source, result) = match self
+            .official
+            .load_ref(materials, alias.clone(), local)
+            .await
+        {
+            Ok((location, document)) => {
+                let source = match location {
+                    FileLocation::Local => LibrarySource::Local,
+                    FileLocation::Library(_) => LibrarySource::Official,
+                };
+                (source, (location, document))
+            }
+            Err(ResolutionError::FileNotFound) => (
+                LibrarySource::Unofficial,
+                self.unofficial
+                    .load_ref(materials, alias.clone(), false)
+                    .await?,
+            ),
+            Err(e) => return Err(e),
+        };
+
+        self.sources.write().unwrap().insert(alias, source);
+        Ok(result)
+    }
+
+    async fn load_texture(&self, name: &str) -> Result<Vec<u8>, ResolutionError> {
+        match self.official.load_texture(name).await {
+            Err(ResolutionError::FileNotFound) => self.unofficial.load_texture(name).await,
+            result => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        color::MaterialRegistry,
+        document::MultipartDocument,
+        error::ResolutionError,
+        library::{FileLocation, LibraryLoader, PartKind},
+        test_support::MemoryLoader,
+        PartAlias,
+    };
+
+    use super::{LibrarySource, OverlayLoader};
+
+    #[async_std::test]
+    async fn test_overlay_precedence() {
+        let part = FileLocation::Library(PartKind::Part);
+        let official = MemoryLoader::parse(&[
+            ("3001.dat", part, "0 Brick  2 x  4\n"),
+            ("custom.dat", FileLocation::Local, "0 Custom\n"),
+        ])
+        .await;
+        let unofficial = MemoryLoader::parse(&[
+            ("3001.dat", part, "0 Brick  2 x  4 (Unofficial)\n"),
+            ("99999.dat", part, "0 New Part\n"),
+            ("custom.dat", part, "0 Unofficial Custom\n"),
+        ])
+        .await;
+        let loader = OverlayLoader::new(Box::new(official), Box::new(unofficial));
+        let materials = MaterialRegistry::new();
+
+        let description = |result: Result<(FileLocation, MultipartDocument), ResolutionError>| {
+            result.unwrap().1.body.description
+        };
+        assert_eq!(
+            description(
+                loader
+                    .load_ref(&materials, PartAlias::from("3001.dat"), false)
+                    .await
+            ),
+            "Brick  2 x  4"
+        );
+        assert_eq!(
+            description(
+                loader
+                    .load_ref(&materials, PartAlias::from("99999.dat"), false)
+                    .await
+            ),
+            "New Part"
+        );
+        assert_eq!(
+            description(
+                loader
+                    .load_ref(&materials, PartAlias::from("custom.dat"), true)
+                    .await
+            ),
+            "Custom"
+        );
+        assert!(matches!(
+            loader
+                .load_ref(&materials, PartAlias::from("missing.dat"), false)
+                .await,
+            Err(ResolutionError::FileNotFound)
+        ));
+
+        assert_eq!(
+            loader.source(&PartAlias::from("3001.dat")),
+            Some(LibrarySource::Official)
+        );
+        assert_eq!(
+            loader.source(&PartAlias::from("99999.dat")),
+            Some(LibrarySource::Unofficial)
+        );
+        assert_eq!(
+            loader.source(&PartAlias::from("custom.dat")),
+            Some(LibrarySource::Local)
+        );
+        assert_eq!(loader.source(&PartAlias::from("missing.dat")), None);
+    }
+}
@@ -0,0 +1,331 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::{index::PartIndex, PartAlias};
+
+const SCORE_NUMBER_EXACT: u32 = 100;
+const SCORE_NUMBER_PREFIX: u32 = 50;
+const SCORE_WORD_EXACT: u32 = 10;
+const SCORE_WORD_PREFIX: u32 = 5;
+const SCORE_KEYWORD_EXACT: u32 = 8;
+const SCORE_KEYWORD_PREFIX: u32 = 4;
+
+// Shorter tokens such as the `2` in `brick 2 x 4` would otherwise match
+// nearly every part number.
+const MIN_NUMBER_PREFIX: usize = 3;
+
+#[derive(Clone, Debug, Default)]
+pub struct SearchQuery {
+    pub text: String,
+    pub categories: Vec<String>,
+    pub limit: Option<usize>,
+}
+
+impl SearchQuery {
+    pub fn new(text: &str) -> Self {
+        SearchQuery {
+            text: text.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn category(mut self, category: &str) -> Self {
+        self.categories.push(category.to_string());
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SearchResult {
+    pub alias: PartAlias,
+    pub score: u32,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Terms {
+    words: HashSet<String>,
+    keywords: HashSet<String>,
+}
+
+// Full text search over a PartIndex. Description words and keywords go into
+// a sorted term map so prefix lookups are range scans rather than full scans.
+#[derive(Clone, Debug, Default)]
+pub struct PartSearch {
+    terms: BTreeMap<String, HashSet<PartAlias>>,
+    documents: HashMap<PartAlias, Terms>,
+    numbers: BTreeMap<String, PartAlias>,
+    categories: HashMap<PartAlias, String>,
+}
+
+fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|e| !e.is_empty())
+        .map(str::to_lowercase)
+}
+
+fn part_number(alias: &PartAlias) -> &str {
+    alias
+        .normalized
+        .rsplit('/')
+        .next()
+        .map(|e| e.strip_suffix(".dat").unwrap_or(e))
+        .unwrap_or(&alias.normalized)
+}
+
+impl PartSearch {
+    pub fn new(index: &PartIndex) -> Self {
+        let mut search = PartSearch::default();
+
+        for entry in index.entries() {
+            let terms = Terms {
+                words: tokenize(&entry.description).collect(),
+                keywords: entry.keywords.iter().flat_map(|e| tokenize(e)).collect(),
+            };
+            for term in terms.words.iter().chain(terms.keywords.iter()) {
+                search
+                    .terms
+                    .entry(term.clone())
+                    .or_default()
+                    .insert(entry.alias.clone());
+            }
+            search
+                .numbers
+                .insert(part_number(&entry.alias).to_string(), entry.alias.clone());
+            if let Some(category) = &entry.category {
+                search
+                    .categories
+                    .insert(entry.alias.clone(), category.to_lowercase());
+            }
+            search.documents.insert(entry.alias.clone(), terms);
+        }
+
+        search
+    }
+
+    fn score_token(&self, token: &str) -> HashMap<PartAlias, u32> {
+        let mut scores: HashMap<PartAlias, u32> = HashMap::new();
+        let mut add = |alias: &PartAlias, score: u32| {
+            let entry = scores.entry(alias.clone()).or_default();
+            *entry = (*entry).max(score);
+        };
+
+        for (number, alias) in self
+            .numbers
+            .range(token.to_string()..)
+            .take_while(|(number, _)| number.starts_with(token))
+            .filter(|(number, _)| *number == token || token.len() >= MIN_NUMBER_PREFIX)
+        {
+            add(
+                alias,
+                if number == token {
+                    SCORE_NUMBER_EXACT
+                } else {
+                    SCORE_NUMBER_PREFIX
+                },
+            );
+        }
+
+        for (term, aliases) in self
+            .terms
+            .range(token.to_string()..)
+            .take_while(|(term, _)| term.starts_with(token))
+        {
+            let exact = term == token;
+            for alias in aliases {
+                let terms = &self.documents[alias];
+                if terms.words.contains(term) {
+                    add(
+                        alias,
+                        if exact {
+                            SCORE_WORD_EXACT
+                        } else {
+                            SCORE_WORD_PREFIX
+                        },
+                    );
+                }
+                if terms.keywords.contains(term) {
+                    add(
+                        alias,
+                        if exact {
+                            SCORE_KEYWORD_EXACT
+                        } else {
+                            SCORE_KEYWORD_PREFIX
+                        },
+                    );
+                }
+            }
+        }
+
+        scores
+    }
+
+    // Every query token has to match a part number, description word or
+    // keyword, either fully or as a prefix. Results are ordered by score,
+    // then by name.
+    pub fn search(&self, query: &SearchQuery) -> Vec<SearchResult> {
+        let categories = query
+            .categories
+            .iter()
+            .map(|e| e.to_lowercase())
+            .collect::<HashSet<_>>();
+
+        let mut totals: Option<HashMap<PartAlias, u32>> = None;
+        for token in tokenize(&query.text) {
+            let scores = self.score_token(&token);
+            totals = Some(match totals {
+                None => scores,
+                Some(totals) => totals
+                    .into_iter()
+                    .filter_map(|(alias, total)| scores.get(&alias).map(|e| (alias, total + e)))
+                    .collect(),
+            });
+        }
+
+        let mut results = match totals {
+            Some(totals) => totals
+                .into_iter()
+                .map(|(alias, score)| SearchResult { alias, score })
+                .collect::<Vec<_>>(),
+            // An empty query lists everything in the requested categories.
+            None if !categories.is_empty() => self
+                .documents
+                .keys()
+                .map(|alias| SearchResult {
+                    alias: alias.clone(),
+                    score: 0,
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+
+        if !categories.is_empty() {
+            results.retain(|e| {
+                self.categories
+                    .get(&e.alias)
+                    .is_some_and(|category| categories.contains(category))
+            });
+        }
+
+        results.sort_by(|a, b| {
+            b.score
+                .cmp(&a.score)
+                .then_with(|| a.alias.normalized.cmp(&b.alias.normalized))
+        });
+        if let Some(limit) = query.limit {
+            results.truncate(limit);
+        }
+        results
+    }
+
+    pub fn search_aliases(&self, query: &SearchQuery) -> Vec<PartAlias> {
+        self.search(query).into_iter().map(|e| e.alias).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        document::{Document, DocumentBuilder},
+        index::PartIndex,
+        PartAlias,
+    };
+
+    use super::{PartSearch, SearchQuery};
+
+    fn part(name: &str, description: &str, category: &str, keywords: &str) -> Document {
+        let mut builder = DocumentBuilder::new(name, description, "Test");
+        if !category.is_empty() {
+            builder = builder.header("CATEGORY", category);
+        }
+        if !keywords.is_empty() {
+            builder = builder.header("KEYWORDS", keywords);
+        }
+        builder.build()
+    }
+
+    fn sample_search() -> PartSearch {
+        let mut index = PartIndex::new();
+        for (name, description, category, keywords) in [
+            ("3001.dat", "Brick  2 x  4", "", ""),
+            ("3002.dat", "Brick  2 x  3", "", ""),
+            ("30010.dat", "Panel  1 x  2 x  3 with Window", "Panel", ""),
+            ("3003.dat", "Brick  2 x  2", "", "Bricksmith"),
+            ("3024.dat", "Plate  1 x  1", "", "round, tile"),
+            ("3068b.dat", "Tile  2 x  2 with Groove", "", "plate"),
+        ] {
+            index.insert(
+                PartAlias::from(name),
+                &part(name, description, category, keywords),
+            );
+        }
+        PartSearch::new(&index)
+    }
+
+    fn names(search: &PartSearch, query: &SearchQuery) -> Vec<String> {
+        search
+            .search_aliases(query)
+            .into_iter()
+            .map(|e| e.original.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_search_part_number() {
+        let search = sample_search();
+
+        assert_eq!(
+            names(&search, &SearchQuery::new("3001")),
+            vec!["3001.dat", "30010.dat"]
+        );
+        assert_eq!(
+            names(&search, &SearchQuery::new("300").limit(2)),
+            vec!["3001.dat", "30010.dat"]
+        );
+    }
+
+    #[test]
+    fn test_search_text() {
+        let search = sample_search();
+
+        assert_eq!(
+            names(&search, &SearchQuery::new("brick 2 x 3")),
+            vec!["3002.dat"]
+        );
+        assert_eq!(
+            names(&search, &SearchQuery::new("brick")),
+            vec!["3001.dat", "3002.dat", "3003.dat"]
+        );
+        assert_eq!(
+            names(&search, &SearchQuery::new("bricksm")),
+            vec!["3003.dat"]
+        );
+        // Description words rank above keywords.
+        assert_eq!(
+            names(&search, &SearchQuery::new("plate")),
+            vec!["3024.dat", "3068b.dat"]
+        );
+        assert_eq!(
+            names(&search, &SearchQuery::new("til")),
+            vec!["3068b.dat", "3024.dat"]
+        );
+        assert!(names(&search, &SearchQuery::new("brick window")).is_empty());
+    }
+
+    #[test]
+    fn test_search_category() {
+        let search = sample_search();
+
+        assert_eq!(
+            names(&search, &SearchQuery::new("3").category("panel")),
+            vec!["30010.dat"]
+        );
+        assert_eq!(
+            names(&search, &SearchQuery::new("").category("Plate")),
+            vec!["3024.dat"]
+        );
+    }
+}
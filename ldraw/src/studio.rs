@@ -0,0 +1,246 @@
+use std::{
+    collections::HashMap,
+    io::{Error as IoError, ErrorKind, Read, Seek},
+};
+
+use async_trait::async_trait;
+use zip::ZipArchive;
+
+use crate::{
+    color::MaterialRegistry,
+    document::MultipartDocument,
+    error::ResolutionError,
+    library::{FileLocation, LibraryLoader, PartKind},
+    parser::{parse_multipart_document_with_options, ParseOptions},
+    PartAlias,
+};
+
+// Newer versions of Stud.io encrypt the entries of .io files with ZipCrypto
+// and this fixed password. Unencrypted entries ignore it.
+const STUDIO_PASSWORD: &[u8] = b"soho0909";
+const MODEL_FILES: &[&str] = &["model2.ldr", "model.ldr"];
+const CUSTOM_PARTS_DIR: &str = "customparts/";
+
+// Contents of a Stud.io .io container. Custom parts are keyed the way the
+// model refers to them, with `PartKind` telling whether they came from
+// `CustomParts/parts` or `CustomParts/p`.
+#[derive(Clone, Debug)]
+pub struct StudioModel {
+    pub model: MultipartDocument,
+    pub custom_parts: HashMap<PartAlias, (PartKind, MultipartDocument)>,
+    pub textures: HashMap<String, Vec<u8>>,
+}
+
+impl StudioModel {
+    // A loader serving the embedded custom parts ahead of `inner`, to be
+    // handed to the resolver together with `model`.
+    pub fn custom_part_loader(&self, inner: Box<dyn LibraryLoader>) -> CustomPartLoader {
+        CustomPartLoader {
+            inner,
+            parts: self.custom_parts.clone(),
+            textures: self
+                .textures
+                .iter()
+                .map(|(name, data)| (PartAlias::normalize(name), data.clone()))
+                .collect(),
+        }
+    }
+}
+
+fn strip_bom(contents: &[u8]) -> &[u8] {
+    contents.strip_prefix(b"\xef\xbb\xbf").unwrap_or(contents)
+}
+
+async fn parse(
+    materials: &MaterialRegistry,
+    contents: &[u8],
+) -> Result<MultipartDocument, ResolutionError> {
+    // Stud.io writes a number of its own meta commands and is not too careful
+    // about the rest, so parse leniently.
+    Ok(parse_multipart_document_with_options(
+        materials,
+        &ParseOptions::lenient(),
+        &mut strip_bom(contents),
+    )
+    .await?)
+}
+
+pub async fn read_studio_model<R: Read + Seek>(
+    materials: &MaterialRegistry,
+    reader: R,
+) -> Result<StudioModel, ResolutionError> {
+    let mut archive = ZipArchive::new(reader).map_err(IoError::from)?;
+
+    let mut files = HashMap::new();
+    for i in 0..archive.len() {
+        let mut file = archive
+            .by_index_decrypt(i, STUDIO_PASSWORD)
+            .map_err(IoError::from)?
+            .map_err(|e| IoError::new(ErrorKind::InvalidData, e))?;
+        if file.is_dir() {
+            continue;
+        }
+
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        files.insert(file.name().replace('\\', "/"), contents);
+    }
+
+    let model = MODEL_FILES
+        .iter()
+        .find_map(|name| files.get(*name))
+        .ok_or(ResolutionError::FileNotFound)?;
+    let model = parse(materials, model).await?;
+
+    let mut custom_parts = HashMap::new();
+    let mut textures = HashMap::new();
+    for (name, contents) in files.iter() {
+        let lower = name.to_ascii_lowercase();
+        let path = match lower.strip_prefix(CUSTOM_PARTS_DIR) {
+            Some(e) => e,
+            None => continue,
+        };
+
+        if path.starts_with("parts/textures/") {
+            // Texture names are case sensitive, so keep the original.
+            let texture = &name[name.len() - path.len() + "parts/textures/".len()..];
+            textures.insert(texture.to_string(), contents.clone());
+            continue;
+        }
+        let (kind, alias) = if let Some(e) = path.strip_prefix("parts/") {
+            (PartKind::Part, e)
+        } else if let Some(e) = path.strip_prefix("p/") {
+            (PartKind::Primitive, e)
+        } else {
+            continue;
+        };
+        if !alias.ends_with(".dat") && !alias.ends_with(".ldr") {
+            continue;
+        }
+
+        let document = parse(materials, contents).await?;
+        custom_parts.insert(PartAlias::from(alias), (kind, document));
+    }
+
+    Ok(StudioModel {
+        model,
+        custom_parts,
+        textures,
+    })
+}
+
+pub struct CustomPartLoader {
+    inner: Box<dyn LibraryLoader>,
+    parts: HashMap<PartAlias, (PartKind, MultipartDocument)>,
+    textures: HashMap<String, Vec<u8>>,
+}
+
+#[async_trait(?Send)]
+impl LibraryLoader for CustomPartLoader {
+    async fn load_materials(&self) -> Result<MaterialRegistry, ResolutionError> {
+        self.inner.load_materials().await
+    }
+
+    async fn load_ref(
+        &self,
+        materials: &MaterialRegistry,
+        alias: PartAlias,
+        local: bool,
+    ) -> Result<(FileLocation, MultipartDocument), ResolutionError> {
+        match self.parts.get(&alias) {
+            Some((kind, document)) => Ok((FileLocation::Library(*kind), document.clone())),
+            None => self.inner.load_ref(materials, alias, local).await,
+        }
+    }
+
+    async fn load_texture(&self, name: &str) -> Result<Vec<u8>, ResolutionError> {
+        match self.textures.get(name) {
+            Some(data) => Ok(data.clone()),
+            None => self.inner.load_texture(name).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use zip::{write::FileOptions, ZipWriter};
+
+    use crate::{
+        color::MaterialRegistry,
+        library::{FileLocation, LibraryLoader, PartKind},
+        test_support::MemoryLoader,
+        PartAlias,
+    };
+
+    use super::read_studio_model;
+
+    #[async_std::test]
+    async fn test_read_studio_model() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, contents) in [
+            (
+                "model.ldr",
+                &b"\xef\xbb\xbf0 FILE main.ldr\n0 Main\n0 Name: main.ldr\n1 4 0 0 0 1 0 0 0 1 0 0 0 1 custom.dat\n0 NOFILE\n"[..],
+            ),
+            (".info", b"{\"version\": \"2.2.11_1\"}"),
+            ("CustomParts/parts/custom.dat", b"0 Custom Part\n0 Name: custom.dat\n"),
+            ("CustomParts/p/custom-prim.dat", b"0 Custom Primitive\n"),
+            ("CustomParts/parts/textures/Custom.png", b"\x89PNG"),
+            ("thumbnail.png", b"\x89PNG"),
+        ] {
+            writer.start_file(name, FileOptions::default()).unwrap();
+            writer.write_all(contents).unwrap();
+        }
+        let contents = writer.finish().unwrap();
+
+        let model = read_studio_model(&MaterialRegistry::new(), contents)
+            .await
+            .unwrap();
+        assert_eq!(model.model.body.description, "Main");
+        assert_eq!(model.custom_parts.len(), 2);
+        assert_eq!(model.textures.len(), 1);
+        assert!(model.textures.contains_key("Custom.png"));
+
+        let loader = model.custom_part_loader(Box::<MemoryLoader>::default());
+        let materials = MaterialRegistry::new();
+        let (location, document) = loader
+            .load_ref(&materials, PartAlias::from("Custom.dat"), false)
+            .await
+            .unwrap();
+        assert!(matches!(location, FileLocation::Library(PartKind::Part)));
+        assert_eq!(document.body.description, "Custom Part");
+        let (location, _) = loader
+            .load_ref(&materials, PartAlias::from("custom-prim.dat"), false)
+            .await
+            .unwrap();
+        assert!(matches!(
+            location,
+            FileLocation::Library(PartKind::Primitive)
+        ));
+        assert!(loader
+            .load_ref(&materials, PartAlias::from("3001.dat"), false)
+            .await
+            .is_err());
+    }
+
+    // A synthesized archive following the layout of Stud.io 2.2 exports:
+    // entries deflated and encrypted with ZipCrypto, model.ldr alongside
+    // model2.ldr, .info and a thumbnail. Not exported by Stud.io itself.
+    #[async_std::test]
+    async fn test_read_studio_fixture() {
+        let contents = Cursor::new(&include_bytes!("../tests/data/fixture.io")[..]);
+
+        let model = read_studio_model(&MaterialRegistry::new(), contents)
+            .await
+            .unwrap();
+        assert_eq!(model.model.body.name, "fixture");
+        assert_eq!(model.model.body.iter_refs().count(), 2);
+        assert_eq!(model.custom_parts.len(), 1);
+        let (kind, document) = &model.custom_parts[&PartAlias::from("custom.dat")];
+        assert!(matches!(kind, PartKind::Part));
+        assert_eq!(document.body.description, "Custom Brick");
+        assert!(model.textures["custom.png"].starts_with(b"\x89PNG"));
+    }
+}
@@ -0,0 +1,85 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+
+use crate::{
+    color::MaterialRegistry,
+    document::MultipartDocument,
+    error::ResolutionError,
+    library::{FileLocation, LibraryLoader, PartKind},
+    parser::parse_multipart_document,
+    PartAlias,
+};
+
+// Serves documents held in memory. Files located next to the model are only
+// found when looked up as local ones.
+#[derive(Default)]
+pub(crate) struct MemoryLoader {
+    files: HashMap<PartAlias, (FileLocation, Arc<MultipartDocument>)>,
+    textures: HashMap<String, Vec<u8>>,
+}
+
+impl MemoryLoader {
+    // Library parts, named after their `0 Name:`.
+    pub fn library(documents: impl IntoIterator<Item = Arc<MultipartDocument>>) -> Self {
+        MemoryLoader {
+            files: documents
+                .into_iter()
+                .map(|e| {
+                    let location = FileLocation::Library(PartKind::Part);
+                    (PartAlias::from(&e.body.name), (location, e))
+                })
+                .collect(),
+            textures: HashMap::new(),
+        }
+    }
+
+    // Adds an image to the `textures` folders of the library.
+    pub fn texture(mut self, name: &str, data: &[u8]) -> Self {
+        self.textures
+            .insert(PartAlias::normalize(name), data.to_vec());
+        self
+    }
+
+    // Files given by name, location and contents.
+    pub async fn parse(files: &[(&str, FileLocation, &str)]) -> Self {
+        let materials = MaterialRegistry::new();
+        let mut loader = MemoryLoader::default();
+        for (name, location, contents) in files {
+            let document = parse_multipart_document(&materials, &mut contents.as_bytes())
+                .await
+                .unwrap();
+            loader
+                .files
+                .insert(PartAlias::from(*name), (*location, Arc::new(document)));
+        }
+        loader
+    }
+}
+
+#[async_trait(?Send)]
+impl LibraryLoader for MemoryLoader {
+    async fn load_materials(&self) -> Result<MaterialRegistry, ResolutionError> {
+        Ok(MaterialRegistry::new())
+    }
+
+    async fn load_ref(
+        &self,
+        _materials: &MaterialRegistry,
+        alias: PartAlias,
+        local: bool,
+    ) -> Result<(FileLocation, MultipartDocument), ResolutionError> {
+        match self.files.get(&alias) {
+            Some((FileLocation::Local, _)) if !local => Err(ResolutionError::FileNotFound),
+            Some((location, document)) => Ok((*location, (**document).clone())),
+            None => Err(ResolutionError::FileNotFound),
+        }
+    }
+
+    async fn load_texture(&self, name: &str) -> Result<Vec<u8>, ResolutionError> {
+        self.textures
+            .get(name)
+            .cloned()
+            .ok_or(ResolutionError::FileNotFound)
+    }
+}
@@ -7,7 +7,8 @@ use cgmath::{Matrix, Vector4};
 use crate::color::ColorReference;
 use crate::document::{BfcCertification, Document, MultipartDocument};
 use crate::elements::{
-    BfcStatement, Command, Header, Line, Meta, OptionalLine, PartReference, Quad, Triangle,
+    BfcStatement, Command, Header, Line, Meta, MlcadMode, OptionalLine, PartReference, Quad,
+    TexMapStatement, Triangle,
 };
 use crate::error::SerializeError;
 use crate::Winding;
@@ -109,6 +110,32 @@ impl LDrawWriter for MultipartDocument {
     }
 }
 
+#[async_trait]
+impl LDrawWriter for TexMapStatement {
+    async fn write(&self, writer: &mut (dyn Write + Unpin + Send)) -> Result<(), SerializeError> {
+        match self {
+            TexMapStatement::Start(texmap) => {
+                writer.write_all(format!("0 !TEXMAP START {}\n", texmap).as_bytes()).await?;
+            }
+            TexMapStatement::Next(texmap) => {
+                writer.write_all(format!("0 !TEXMAP NEXT {}\n", texmap).as_bytes()).await?;
+            }
+            TexMapStatement::Fallback => {
+                writer.write_all(b"0 !TEXMAP FALLBACK\n").await?;
+            }
+            TexMapStatement::End => {
+                writer.write_all(b"0 !TEXMAP END\n").await?;
+            }
+            TexMapStatement::Geometry(command) => {
+                writer.write_all(b"0 !: ").await?;
+                command.write(writer).await?;
+            }
+        };
+
+        Ok(())
+    }
+}
+
 #[async_trait]
 impl LDrawWriter for Meta {
     async fn write(&self, writer: &mut (dyn Write + Unpin + Send)) -> Result<(), SerializeError> {
@@ -121,6 +148,9 @@ impl LDrawWriter for Meta {
             Meta::Step => {
                 writer.write_all(b"0 STEP\n").await?;
             }
+            Meta::RotStep(statement) => {
+                writer.write_all(format!("0 ROTSTEP {}\n", statement).as_bytes()).await?;
+            }
             Meta::Write(message) => {
                 for line in message.lines() {
                     writer.write_all(format!("0 WRITE {}\n", line).as_bytes()).await?;
@@ -143,6 +173,17 @@ impl LDrawWriter for Meta {
             Meta::Bfc(bfc) => {
                 bfc.write(writer).await?;
             }
+            Meta::TexMap(statement) => {
+                statement.write(writer).await?;
+            }
+            Meta::Mlcad(MlcadMode::Ghost, command) => {
+                writer.write_all(b"0 GHOST ").await?;
+                command.write(writer).await?;
+            }
+            Meta::Mlcad(MlcadMode::Hide, command) => {
+                writer.write_all(b"0 MLCAD HIDE ").await?;
+                command.write(writer).await?;
+            }
         };
 
         Ok(())
@@ -185,7 +185,9 @@ fn create_context(
     }
 
     let program_manager = ProgramManager::new(Rc::clone(&gl))?;
-    let rendering_context = RefCell::new(RenderingContext::new(Rc::clone(&gl), program_manager));
+    let mut rendering_context = RenderingContext::new(Rc::clone(&gl), program_manager);
+    rendering_context.framebuffer = framebuffer;
+    let rendering_context = RefCell::new(rendering_context);
 
     Ok(OlrContext {
         width,
@@ -4,13 +4,14 @@ use cgmath::EuclideanSpace;
 use glow::{Context as GlContext, HasContext};
 use image::RgbaImage;
 use ldraw::{color::Material, PartAlias, Point3};
+use ldraw_ir::geometry::BoundingBox3;
 use ldraw_renderer::{
     display_list::DisplayList,
     part::Part,
     state::{OrthographicCamera, OrthographicViewBounds},
 };
 
-use crate::{context::OlrContext, utils::calculate_bounding_box};
+use crate::context::OlrContext;
 
 pub fn render_single_part(
     context: &OlrContext,
@@ -55,7 +56,9 @@ pub fn render_display_list(
         gl.clear(glow::COLOR_BUFFER_BIT | glow::DEPTH_BUFFER_BIT);
     }
 
-    let bounding_box = calculate_bounding_box(parts, display_list);
+    let bounding_box = display_list
+        .calculate_bounding_box(parts)
+        .unwrap_or_else(BoundingBox3::zero);
     let camera = OrthographicCamera::new_isometric(Point3::from_vec(bounding_box.center()));
     let bounds = rc
         .apply_orthographic_camera(&camera, &OrthographicViewBounds::BoundingBox3(bounding_box))
@@ -0,0 +1,38 @@
+precision highp float;
+
+uniform sampler2D source;
+uniform bool horizontal;
+// Adds the source scaled up by `scale` instead of blurring it.
+uniform bool upsample;
+uniform float scale;
+uniform float intensity;
+
+out vec4 fragColor;
+
+vec3 fetch(ivec2 coord) {
+    ivec2 size = textureSize(source, 0);
+    return texelFetch(source, clamp(coord, ivec2(0), size - 1), 0).rgb;
+}
+
+void main(void) {
+    if (upsample) {
+        // Render targets are not filtered, so interpolate by hand.
+        vec2 position = gl_FragCoord.xy / scale - 0.5;
+        ivec2 base = ivec2(floor(position));
+        vec2 f = fract(position);
+        vec3 bottom = mix(fetch(base), fetch(base + ivec2(1, 0)), f.x);
+        vec3 top = mix(fetch(base + ivec2(0, 1)), fetch(base + ivec2(1, 1)), f.x);
+        fragColor = vec4(mix(bottom, top, f.y) * intensity, 1.0);
+        return;
+    }
+
+    // Gaussian of nine taps.
+    const float weights[5] = float[](0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+    ivec2 offset = horizontal ? ivec2(1, 0) : ivec2(0, 1);
+    ivec2 coord = ivec2(gl_FragCoord.xy);
+    vec3 sum = fetch(coord) * weights[0];
+    for (int i = 1; i < 5; i++) {
+        sum += (fetch(coord + offset * i) + fetch(coord - offset * i)) * weights[i];
+    }
+    fragColor = vec4(sum, 1.0);
+}
@@ -7,44 +7,171 @@ precision highp float;
 precision highp int;
 
 in vec4 vColor;
+// Light given off relative to the color.
+in float vEmission;
 
-out vec4 fragColor;
+layout(location = 0) out vec4 fragColor;
+layout(location = 1) out vec4 fragWeight;
+
+uniform bool weightedTransparency;
+// Writes nothing but emitted light, for bloom.
+uniform bool emissiveOnly;
+uniform float emissionStrength;
+
+// Operator of `ToneMapping`: 0 none, 1 Reinhard, 2 ACES.
+uniform int toneMapping;
+uniform float exposure;
+// Leaves colors in linear light for a tone mapping pass.
+uniform bool linearOutput;
+
+// Colors of materials and textures are sRGB; light is added up linearly.
+vec4 sRGBToLinear( in vec4 value ) {
+    return vec4( mix( pow( value.rgb * 0.9478672986 + vec3( 0.0521327014 ), vec3( 2.4 ) ), value.rgb * 0.0773993808, vec3( lessThanEqual( value.rgb, vec3( 0.04045 ) ) ) ), value.a );
+}
+
+vec4 LinearTosRGB( in vec4 value ) {
+    return vec4( mix( pow( value.rgb, vec3( 0.41666 ) ) * 1.055 - vec3( 0.055 ), value.rgb * 12.92, vec3( lessThanEqual( value.rgb, vec3( 0.0031308 ) ) ) ), value.a );
+}
+
+vec3 ACESFilmicToneMapping( vec3 color ) {
+    color *= 0.6;
+    return clamp( ( color * ( 2.51 * color + 0.03 ) ) / ( color * ( 2.43 * color + 0.59 ) + 0.14 ), 0.0, 1.0 );
+}
+
+vec4 linearToOutputTexel( vec4 value ) {
+    if ( linearOutput ) {
+        return value;
+    }
+    vec3 color = value.rgb * exposure;
+    if ( toneMapping == 1 ) {
+        color = color / ( vec3( 1.0 ) + color );
+    } else if ( toneMapping == 2 ) {
+        color = ACESFilmicToneMapping( color );
+    }
+    return LinearTosRGB( vec4( clamp( color, 0.0, 1.0 ), value.a ) );
+}
+
+in vec2 vUv;
+// Image of a `!TEXMAP` statement, laid over the color.
+uniform bool useMap;
+uniform sampler2D map;
+
+// Textures are not repeated; outside of them the color shows through.
+vec4 applyMap( vec4 color ) {
+    vec4 texel = texture( map, vUv );
+    if ( !useMap || any( lessThan( vUv, vec2( 0.0 ) ) ) || any( greaterThan( vUv, vec2( 1.0 ) ) ) ) {
+        return color;
+    }
+    return vec4( mix( color.rgb, texel.rgb, texel.a ), mix( color.a, 1.0, texel.a ) );
+}
+
+in vec3 vViewPosition;
+// Clip planes in view space as normal and distance. Fragments on the side
+// the normal points to are cut away.
+uniform vec4 clipPlanes[ MAX_CLIP_PLANES ];
+uniform int clipPlaneCount;
+
+void clipFragment() {
+    for ( int i = 0; i < MAX_CLIP_PLANES; i++ ) {
+        if ( i < clipPlaneCount && dot( clipPlanes[ i ].xyz, - vViewPosition ) > clipPlanes[ i ].w ) {
+            discard;
+        }
+    }
+}
+
+in vec3 vBarycentric;
+// Width in pixels of the lines of a wireframe, or zero to shade faces whole.
+uniform float wireframeWidth;
+// Shades the faces between the lines of the wireframe as well.
+uniform bool wireframeFill;
+
+// One on the edges of the triangle, falling to zero `wireframeWidth`
+// pixels in. Fragments between the lines are left out unless filled.
+float wireframeLine() {
+    if ( wireframeWidth <= 0.0 ) {
+        return 0.0;
+    }
+    vec3 edge = smoothstep( vec3( 0.0 ), fwidth( vBarycentric ) * wireframeWidth, vBarycentric );
+    float line = 1.0 - min( min( edge.x, edge.y ), edge.z );
+    if ( !wireframeFill && line < 0.5 ) {
+        discard;
+    }
+    return line;
+}
+
+// Weighted blended order-independent transparency (McGuire and Bavoil, 2013)
+// accumulates weighted premultiplied colors in the first target and their
+// weights in the second.
+void writeColor( vec4 color ) {
+    if ( weightedTransparency ) {
+        float weight = clamp( pow( min( 1.0, color.a * 10.0 ) + 0.01, 3.0 ) * 1e8 * pow( 1.0 - gl_FragCoord.z * 0.9, 3.0 ), 1e-2, 3e3 );
+        fragColor = vec4( color.rgb * color.a * weight, color.a );
+        fragWeight = vec4( color.a * weight );
+    } else {
+        fragColor = color;
+    }
+}
 
 #ifdef WITHOUT_BFC
     void main() {
-        fragColor = vColor;
+        clipFragment();
+        float wireframe = wireframeLine();
+        vec4 color = sRGBToLinear( applyMap( vColor ) );
+        if ( wireframeFill ) {
+            color.rgb *= 1.0 - 0.75 * wireframe;
+        }
+        if ( emissiveOnly ) {
+            fragColor = vec4( color.rgb * vEmission * emissionStrength * color.a, 1.0 );
+            return;
+        }
+        writeColor( linearToOutputTexel( color ) );
     }
 #else
     in vec3 vNormal;
-    in vec3 vViewPosition;
+    // Metalness, roughness, clearcoat and sheen.
+    in vec4 vFinish;
+    // Color and ±(size + fraction), positive for glitter.
+    in vec4 vFleck;
+    in vec3 vObjectPosition;
 
     uniform vec3 diffuse;
     uniform vec3 emissive;
-    uniform float roughness;
-    uniform float metalness;
     uniform float opacity;
 
     uniform sampler2D envMap;
+    // Mipmaps of a prefiltered equirectangular envMap, one per roughness
+    // step; zero for the fallback cube map.
+    uniform float envMapLevels;
+    uniform vec3 irradianceSH[ 9 ];
 
     uniform mat4 viewMatrix;
     uniform bool isOrthographic;
 
+    // Laid out by `Lights::to_std140`, in world space.
+    struct Light {
+        // rgb: radiance, a: 0 if unused, 1 directional, 2 point, 3 spot
+        vec4 color;
+        // xyz: position, w: range
+        vec4 position;
+        // xyz: direction it shines in, w: 1 if it casts the shadow map
+        vec4 direction;
+        // x: cosine of the inner cone angle, y: of the outer
+        vec4 cone;
+    };
+
+    layout(std140) uniform Lights {
+        Light lights[ MAX_LIGHTS ];
+    };
+
+    uniform bool shadowsEnabled;
+    // From view space to shadow map coordinates and depth.
+    uniform mat4 shadowMatrix;
+    uniform sampler2D shadowMap;
+
     #ifndef saturate
         #define saturate( a ) clamp( a, 0.0, 1.0 )
     #endif
 
-    vec4 LinearTosRGB( in vec4 value ) {
-        return vec4( mix( pow( value.rgb, vec3( 0.41666 ) ) * 1.055 - vec3( 0.055 ), value.rgb * 12.92, vec3( lessThanEqual( value.rgb, vec3( 0.0031308 ) ) ) ), value.a );
-    }
-
-    vec4 LinearToLinear( in vec4 value ) {
-        return value;
-    }
-    
-    vec4 linearToOutputTexel( vec4 value ) {
-        return LinearToLinear( value );
-    }
-
     vec4 RGBEToLinear( in vec4 value ) {
         return vec4( value.rgb * exp2( value.a * 255.0 - 128.0 ), 1.0 );
     }
@@ -111,6 +238,10 @@ out vec4 fragColor;
         float roughness;
         vec3 specularColor;
         float specularF90;
+        float clearcoat;
+        float clearcoatRoughness;
+        vec3 sheenColor;
+        float sheenRoughness;
     };
     vec3 transformDirection( in vec3 dir, in mat4 matrix ) {
         return normalize( ( matrix * vec4( dir, 0.0 ) ).xyz );
@@ -162,6 +293,33 @@ out vec4 fragColor;
         float D = D_GGX( alpha, dotNH );
         return F * ( V * D );
     }
+    float D_Charlie( const in float roughness, const in float dotNH ) {
+        float alpha = pow2( roughness );
+        float invAlpha = 1.0 / alpha;
+        float cos2h = dotNH * dotNH;
+        float sin2h = max( 1.0 - cos2h, 0.0078125 );
+        return ( 2.0 + invAlpha ) * pow( sin2h, invAlpha * 0.5 ) / ( 2.0 * PI );
+    }
+    float V_Neubelt( const in float dotNV, const in float dotNL ) {
+        return saturate( 1.0 / ( 4.0 * ( dotNL + dotNV - dotNL * dotNV ) ) );
+    }
+    vec3 BRDF_Sheen( const in vec3 lightDir, const in vec3 viewDir, const in vec3 normal, const in vec3 sheenColor, const in float sheenRoughness ) {
+        vec3 halfDir = normalize( lightDir + viewDir );
+        float dotNL = saturate( dot( normal, lightDir ) );
+        float dotNV = saturate( dot( normal, viewDir ) );
+        float dotNH = saturate( dot( normal, halfDir ) );
+        float D = D_Charlie( sheenRoughness, dotNH );
+        float V = V_Neubelt( dotNV, dotNL );
+        return sheenColor * ( D * V );
+    }
+    float IBLSheenBRDF( const in vec3 normal, const in vec3 viewDir, const in float roughness ) {
+        float dotNV = saturate( dot( normal, viewDir ) );
+        float r2 = roughness * roughness;
+        float a = roughness < 0.25 ? - 339.2 * r2 + 161.4 * roughness - 25.9 : - 8.48 * r2 + 14.3 * roughness - 9.95;
+        float b = roughness < 0.25 ? 44.0 * r2 - 23.7 * roughness + 3.26 : 1.97 * r2 - 3.27 * roughness + 0.72;
+        float DG = exp( a * dotNV + b ) + ( roughness < 0.25 ? 0.0 : 0.1 * ( roughness - 0.25 ) );
+        return saturate( DG * RECIPROCAL_PI );
+    }
     vec2 DFGApprox( const in vec3 normal, const in vec3 viewDir, const in float roughness ) {
         float dotNV = saturate( dot( normal, viewDir ) );
         const vec4 c0 = vec4( - 1, - 0.0275, - 0.572, 0.022 );
@@ -186,9 +344,14 @@ out vec4 fragColor;
         multiScatter += Fms * Ems;
     }
 
+    vec3 clearcoatSpecular = vec3( 0.0 );
+    vec3 sheenSpecular = vec3( 0.0 );
+
     void RE_Direct_Physical( const in IncidentLight directLight, const in GeometricContext geometry, const in PhysicalMaterial material, inout ReflectedLight reflectedLight ) {
         float dotNL = saturate( dot( geometry.normal, directLight.direction ) );
         vec3 irradiance = dotNL * directLight.color;
+        clearcoatSpecular += irradiance * BRDF_GGX( directLight.direction, geometry.viewDir, geometry.normal, vec3( 0.04 ), 1.0, material.clearcoatRoughness );
+        sheenSpecular += irradiance * BRDF_Sheen( directLight.direction, geometry.viewDir, geometry.normal, material.sheenColor, material.sheenRoughness );
         reflectedLight.directSpecular += irradiance * BRDF_GGX( directLight.direction, geometry.viewDir, geometry.normal, material.specularColor, material.specularF90, material.roughness );
         reflectedLight.directDiffuse += irradiance * BRDF_Lambert( material.diffuseColor );
     }
@@ -196,6 +359,8 @@ out vec4 fragColor;
         reflectedLight.indirectDiffuse += irradiance * BRDF_Lambert( material.diffuseColor );
     }
     void RE_IndirectSpecular_Physical( const in vec3 radiance, const in vec3 irradiance, const in vec3 clearcoatRadiance, const in GeometricContext geometry, const in PhysicalMaterial material, inout ReflectedLight reflectedLight) {
+        clearcoatSpecular += clearcoatRadiance * EnvironmentBRDF( geometry.normal, geometry.viewDir, vec3( 0.04 ), 1.0, material.clearcoatRoughness );
+        sheenSpecular += irradiance * material.sheenColor * IBLSheenBRDF( geometry.normal, geometry.viewDir, material.sheenRoughness );
         vec3 singleScattering = vec3( 0.0 );
         vec3 multiScattering = vec3( 0.0 );
         vec3 cosineWeightedIrradiance = irradiance * RECIPROCAL_PI;
@@ -329,8 +494,37 @@ out vec4 fragColor;
     }
     
     const float envMapIntensity = 1.0;
+
+    vec3 shGetIrradianceAt( in vec3 normal, in vec3 shCoefficients[ 9 ] ) {
+        float x = normal.x, y = normal.y, z = normal.z;
+        vec3 result = shCoefficients[ 0 ] * 0.886227;
+        result += shCoefficients[ 1 ] * 2.0 * 0.511664 * y;
+        result += shCoefficients[ 2 ] * 2.0 * 0.511664 * z;
+        result += shCoefficients[ 3 ] * 2.0 * 0.511664 * x;
+        result += shCoefficients[ 4 ] * 2.0 * 0.429043 * x * y;
+        result += shCoefficients[ 5 ] * 2.0 * 0.429043 * y * z;
+        result += shCoefficients[ 6 ] * ( 0.743125 * z * z - 0.247708 );
+        result += shCoefficients[ 7 ] * 2.0 * 0.429043 * x * z;
+        result += shCoefficients[ 8 ] * 0.429043 * ( x * x - y * y );
+        return result;
+    }
     
+    // Normals have y flipped from view space, while equirectangular
+    // environments are in world space with y up, unlike LDraw.
+    vec3 getEquirectDirection( const in vec3 dir ) {
+        vec3 worldDir = inverseTransformDirection( dir * vec3( 1.0, - 1.0, 1.0 ), viewMatrix );
+        return worldDir * vec3( 1.0, - 1.0, 1.0 );
+    }
+    vec2 equirectUv( const in vec3 dir ) {
+        float u = atan( dir.z, dir.x ) * RECIPROCAL_PI2 + 0.5;
+        float v = 0.5 - asin( clamp( dir.y, - 1.0, 1.0 ) ) * RECIPROCAL_PI;
+        return vec2( u, v );
+    }
+
     vec3 getIBLIrradiance( const in vec3 normal ) {
+        if ( envMapLevels > 0.0 ) {
+            return shGetIrradianceAt( getEquirectDirection( normal ), irradianceSH ) * envMapIntensity;
+        }
         vec3 worldNormal = inverseTransformDirection( normal, viewMatrix );
         vec4 envMapColor = textureCubeUV( envMap, worldNormal, 1.0 );
         return PI * envMapColor.rgb * envMapIntensity;
@@ -339,6 +533,10 @@ out vec4 fragColor;
         vec3 reflectVec;
         reflectVec = reflect( - viewDir, normal );
         reflectVec = normalize( mix( reflectVec, normal, roughness * roughness) );
+        if ( envMapLevels > 0.0 ) {
+            vec2 uv = equirectUv( getEquirectDirection( reflectVec ) );
+            return textureLod( envMap, uv, roughness * ( envMapLevels - 1.0 ) ).rgb * envMapIntensity;
+        }
         reflectVec = inverseTransformDirection( reflectVec, viewMatrix );
         vec4 envMapColor = textureCubeUV( envMap, reflectVec, roughness );
         return envMapColor.rgb * envMapIntensity;
@@ -346,38 +544,142 @@ out vec4 fragColor;
 
     vec3 ambientLightColor = vec3(0.0, 0.0, 0.0);
     
-    vec3 shGetIrradianceAt( in vec3 normal, in vec3 shCoefficients[ 9 ] ) {
-        float x = normal.x, y = normal.y, z = normal.z;
-        vec3 result = shCoefficients[ 0 ] * 0.886227;
-        result += shCoefficients[ 1 ] * 2.0 * 0.511664 * y;
-        result += shCoefficients[ 2 ] * 2.0 * 0.511664 * z;
-        result += shCoefficients[ 3 ] * 2.0 * 0.511664 * x;
-        result += shCoefficients[ 4 ] * 2.0 * 0.429043 * x * y;
-        result += shCoefficients[ 5 ] * 2.0 * 0.429043 * y * z;
-        result += shCoefficients[ 6 ] * ( 0.743125 * z * z - 0.247708 );
-        result += shCoefficients[ 7 ] * 2.0 * 0.429043 * x * z;
-        result += shCoefficients[ 8 ] * 0.429043 * ( x * x - y * y );
-        return result;
-    }
-    
     vec3 getAmbientLightIrradiance( const in vec3 ambientLightColor ) {
         vec3 irradiance = ambientLightColor;
         return irradiance;
     }
+    // Fraction of the shadow casting light reaching a point, averaged over
+    // 3x3 texels of the shadow map.
+    float getShadow( const in vec3 viewPosition ) {
+        if ( !shadowsEnabled ) {
+            return 1.0;
+        }
+        vec4 coord = shadowMatrix * vec4( viewPosition, 1.0 );
+        coord.xyz /= coord.w;
+        if ( any( lessThan( coord.xyz, vec3( 0.0 ) ) ) || any( greaterThan( coord.xyz, vec3( 1.0 ) ) ) ) {
+            return 1.0;
+        }
+        vec2 texelSize = 1.0 / vec2( textureSize( shadowMap, 0 ) );
+        float lit = 0.0;
+        for ( int x = -1; x <= 1; x++ ) {
+            for ( int y = -1; y <= 1; y++ ) {
+                float depth = texture( shadowMap, coord.xy + vec2( x, y ) * texelSize ).r;
+                lit += coord.z - 0.002 <= depth ? 1.0 : 0.0;
+            }
+        }
+        return lit / 9.0;
+    }
+
+    float getDistanceAttenuation( const in float lightDistance, const in float cutoffDistance ) {
+        float distanceFalloff = 1.0 / max( pow2( lightDistance ), 0.01 );
+        if ( cutoffDistance > 0.0 ) {
+            distanceFalloff *= pow2( saturate( 1.0 - pow4( lightDistance / cutoffDistance ) ) );
+        }
+        return distanceFalloff;
+    }
+
+    // Normals have y flipped from view space, so light directions follow.
+    bool getIncidentLight( const in Light light, const in GeometricContext geometry, out IncidentLight incidentLight ) {
+        float kind = light.color.a;
+        if ( kind == 0.0 ) {
+            return false;
+        }
+        vec3 color = light.color.rgb;
+        vec3 direction = -transformDirection( light.direction.xyz, viewMatrix );
+        if ( kind != 1.0 ) {
+            vec3 lightVector = ( viewMatrix * vec4( light.position.xyz, 1.0 ) ).xyz - geometry.position;
+            float lightDistance = length( lightVector );
+            color *= getDistanceAttenuation( lightDistance, light.position.w );
+            lightVector /= lightDistance;
+            if ( kind == 3.0 ) {
+                color *= smoothstep( light.cone.y, light.cone.x, dot( lightVector, direction ) );
+            }
+            direction = lightVector;
+        }
+        if ( light.direction.w > 0.0 ) {
+            color *= getShadow( geometry.position );
+        }
+        direction.y = -direction.y;
+        incidentLight.color = color;
+        incidentLight.direction = direction;
+        incidentLight.visible = any( greaterThan( color, vec3( 0.0 ) ) );
+        return incidentLight.visible;
+    }
+
+    float hash13( vec3 p ) {
+        p = fract( p * 0.1031 );
+        p += dot( p, p.zyx + 31.32 );
+        return fract( ( p.x + p.y ) * p.z );
+    }
+    vec3 hash33( vec3 p ) {
+        p = fract( p * vec3( 0.1031, 0.1030, 0.0973 ) );
+        p += dot( p, p.yxz + 33.33 );
+        return fract( ( p.xxy + p.yxx ) * p.zyx );
+    }
+    // Coverage of glitter or speckle flecks at a point of the part. Flecks
+    // are spheres of the fleck size, at most one per cell of a lattice fixed
+    // to the part, so they stay put as the camera moves. Cells hold one
+    // often enough to cover `fraction` of any surface cutting through. Once
+    // flecks shrink below a pixel they fade into their average instead of
+    // flickering.
+    float getFleckCoverage( const in vec3 position, const in float size, const in float fraction, out vec3 seed ) {
+        const float radius = 0.45;
+        // Share of a unit cell inside the sphere, which is also the share
+        // of a plane through the cell.
+        const float density = 4.18879 * radius * radius * radius;
+        vec3 p = position * ( 2.0 * radius / size );
+        float footprint = length( fwidth( p ) );
+        vec3 cell = floor( p );
+        seed = hash33( cell );
+        float coverage = 0.0;
+        if ( hash13( cell + 71.0 ) < fraction / density ) {
+            vec3 center = cell + radius + ( 1.0 - 2.0 * radius ) * seed;
+            coverage = 1.0 - smoothstep( radius - footprint, radius + footprint, length( p - center ) );
+        }
+        float fade = smoothstep( 0.25, 0.75, footprint );
+        seed = mix( seed, vec3( 0.5 ), fade );
+        return mix( coverage, fraction, fade );
+    }
+
     #define RE_Direct				RE_Direct_Physical
     #define RE_IndirectDiffuse		RE_IndirectDiffuse_Physical
     #define RE_IndirectSpecular		RE_IndirectSpecular_Physical
 
     void main() {
+        clipFragment();
+        float wireframe = wireframeLine();
         vec4 diffuseColor = vec4( diffuse, opacity );
         ReflectedLight reflectedLight = ReflectedLight( vec3( 0.0 ), vec3( 0.0 ), vec3( 0.0 ), vec3( 0.0 ) );
         vec3 totalEmissiveRadiance = emissive;
-        diffuseColor *= vColor;
-        float roughnessFactor = roughness;
-        float metalnessFactor = metalness;
+        diffuseColor *= sRGBToLinear( applyMap( vColor ) );
+        // Filled wireframes darken the lines over the faces.
+        if ( wireframeFill ) {
+            diffuseColor.rgb *= 1.0 - 0.75 * wireframe;
+        }
+        totalEmissiveRadiance += diffuseColor.rgb * vEmission * emissionStrength;
+        if ( emissiveOnly ) {
+            fragColor = vec4( totalEmissiveRadiance * diffuseColor.a, 1.0 );
+            return;
+        }
+        float metalnessFactor = vFinish.x;
+        float roughnessFactor = vFinish.y;
         float faceDirection = gl_FrontFacing ? 1.0 : - 1.0;
         vec3 normal = normalize( vNormal );
         vec3 geometryNormal = normal;
+        // Glitter flecks are tiny mirrors tilted every which way; speckles
+        // only change the color.
+        vec3 fleckSeed;
+        float fleckShape = abs( vFleck.w );
+        float fleckCoverage = getFleckCoverage( vObjectPosition, max( floor( fleckShape ), 1.0 ), fract( fleckShape ), fleckSeed );
+        if ( fleckCoverage > 0.0 ) {
+            diffuseColor.rgb = mix( diffuseColor.rgb, sRGBToLinear( vec4( vFleck.rgb, 1.0 ) ).rgb, fleckCoverage );
+            if ( vFleck.w > 0.0 ) {
+                diffuseColor.a = mix( diffuseColor.a, 1.0, fleckCoverage );
+                metalnessFactor = mix( metalnessFactor, 1.0, fleckCoverage );
+                roughnessFactor = mix( roughnessFactor, 0.15, fleckCoverage );
+                normal = normalize( normal + fleckCoverage * 0.6 * ( fleckSeed - 0.5 ) );
+            }
+        }
         PhysicalMaterial material;
         material.diffuseColor = diffuseColor.rgb * ( 1.0 - metalnessFactor );
         vec3 dxy = max( abs( dFdx( geometryNormal ) ), abs( dFdy( geometryNormal ) ) );
@@ -385,27 +687,49 @@ out vec4 fragColor;
         material.roughness = max( roughnessFactor, 0.0525 );
         material.roughness += geometryRoughness;
         material.roughness = min( material.roughness, 1.0 );
-        material.specularColor = mix( vec3( 0.04 ), diffuseColor.rgb, metalnessFactor );
-        material.specularF90 = 1.0;
+        float sheen = saturate( vFinish.w );
+        float specularIntensity = 1.0 - 0.75 * sheen;
+        material.specularColor = mix( vec3( 0.04 * specularIntensity ), diffuseColor.rgb, metalnessFactor );
+        material.specularF90 = mix( specularIntensity, 1.0, metalnessFactor );
+        material.clearcoat = saturate( vFinish.z );
+        material.clearcoatRoughness = clamp( 0.1 + geometryRoughness, 0.0525, 1.0 );
+        material.sheenColor = sheen * mix( diffuseColor.rgb, vec3( 1.0 ), 0.5 );
+        material.sheenRoughness = 0.6;
         
         GeometricContext geometry;
         geometry.position = - vViewPosition;
         geometry.normal = normal;
         geometry.viewDir = ( isOrthographic ) ? vec3( 0, 0, 1 ) : normalize( vViewPosition );
         IncidentLight directLight;
+        for ( int i = 0; i < MAX_LIGHTS; i++ ) {
+            if ( getIncidentLight( lights[ i ], geometry, directLight ) ) {
+                RE_Direct( directLight, geometry, material, reflectedLight );
+            }
+        }
         vec3 iblIrradiance = vec3( 0.0 );
         vec3 irradiance = getAmbientLightIrradiance( ambientLightColor );
         vec3 radiance = vec3( 0.0 );
         vec3 clearcoatRadiance = vec3( 0.0 );
         iblIrradiance += getIBLIrradiance( geometry.normal );
         radiance += getIBLRadiance( geometry.viewDir, geometry.normal, material.roughness );
+        if ( material.clearcoat > 0.0 ) {
+            clearcoatRadiance += getIBLRadiance( geometry.viewDir, geometry.normal, material.clearcoatRoughness );
+        }
         RE_IndirectDiffuse( irradiance, geometry, material, reflectedLight );
         RE_IndirectSpecular( radiance, iblIrradiance, clearcoatRadiance, geometry, material, reflectedLight );
         vec3 totalDiffuse = reflectedLight.directDiffuse + reflectedLight.indirectDiffuse;
         vec3 totalSpecular = reflectedLight.directSpecular + reflectedLight.indirectSpecular;
         vec3 outgoingLight = totalDiffuse + totalSpecular + totalEmissiveRadiance;
-        fragColor = vec4( outgoingLight, diffuseColor.a );
-        fragColor = linearToOutputTexel( fragColor );
+        if ( sheen > 0.0 ) {
+            float sheenEnergyComp = 1.0 - 0.157 * max3( material.sheenColor );
+            outgoingLight = outgoingLight * sheenEnergyComp + sheenSpecular;
+        }
+        if ( material.clearcoat > 0.0 ) {
+            float dotNVcc = saturate( dot( geometry.normal, geometry.viewDir ) );
+            vec3 Fcc = F_Schlick( vec3( 0.04 ), 1.0, dotNVcc );
+            outgoingLight = outgoingLight * ( 1.0 - material.clearcoat * Fcc ) + clearcoatSpecular * material.clearcoat;
+        }
+        writeColor( linearToOutputTexel( vec4( outgoingLight, diffuseColor.a ) ) );
     }
 
 #endif
\ No newline at end of file
@@ -11,43 +11,90 @@ uniform mat3 normalMatrix;
 
 in vec3 position;
 in vec3 normal;
+in vec2 uv;
 
+// Finish: metalness, roughness, clearcoat and sheen.
+// Fleck: color and ±(size + fraction), positive for glitter.
+// Emission: light given off relative to the color.
 #ifdef USE_INSTANCING
     in mat4 instancedModelMatrix;
+    in float instancedVisibility;
+    in vec4 instancedExplosion;
     #ifdef USE_INSTANCED_COLORS
         in vec4 instancedColor;
+        in vec4 instancedFinish;
+        in vec4 instancedFleck;
+        in float instancedEmission;
     #else
         uniform vec4 color;
+        uniform vec4 finish;
+        uniform vec4 fleck;
+        uniform float emission;
     #endif
 #else
     uniform vec4 color;
+    uniform vec4 finish;
+    uniform vec4 fleck;
+    uniform float emission;
 #endif
 
 out vec3 vViewPosition;
 out vec3 vNormal;
 out vec4 vColor;
+out vec4 vFinish;
+out vec4 vFleck;
+out float vEmission;
+out vec3 vObjectPosition;
+out vec2 vUv;
+// One for the corner of its triangle each vertex is, for wireframes.
+out vec3 vBarycentric;
+
+// Depth has to match between the depth pre-pass and shading.
+invariant gl_Position;
 
 void main() {
     vec4 mvPosition = vec4(position, 1.0);
     vec3 transformedNormal = normal;
     #ifdef USE_INSTANCING
-        mvPosition = instancedModelMatrix * mvPosition;
-        mat3 m = mat3(instancedModelMatrix);
+        // Exploded instances move along their offsets.
+        mat4 model = instancedModelMatrix;
+        model[3].xyz += instancedExplosion.xyz * instancedExplosion.w;
+        mvPosition = model * mvPosition;
+        mat3 m = mat3(model);
         transformedNormal /= vec3(dot(m[0], m[0]), dot(m[1], m[1]), dot(m[2], m[2]));
         transformedNormal = m * transformedNormal;
         #ifdef USE_INSTANCED_COLORS
             vColor = instancedColor;
+            vFinish = instancedFinish;
+            vFleck = instancedFleck;
+            vEmission = instancedEmission;
         #else
             vColor = color;
+            vFinish = finish;
+            vFleck = fleck;
+            vEmission = emission;
         #endif
     #else
         vColor = color;
+        vFinish = finish;
+        vFleck = fleck;
+        vEmission = emission;
     #endif 
+    vObjectPosition = position;
+    // Triangles are drawn as arrays, three vertices each.
+    vBarycentric = vec3( equal( ivec3( gl_VertexID % 3 ), ivec3( 0, 1, 2 ) ) );
+    vUv = uv;
     vNormal = normalize(normalMatrix * transformedNormal);
     vNormal.y = -vNormal.y;
 
     mvPosition = modelView * mvPosition;
     gl_Position = projection * mvPosition;
+    #ifdef USE_INSTANCING
+        // Hidden instances are moved past the far plane.
+        if (instancedVisibility < 0.5) {
+            gl_Position = vec4(0.0, 0.0, 2.0, 1.0);
+        }
+    #endif
   
     vViewPosition = -mvPosition.xyz;
 }
@@ -1,9 +1,27 @@
 precision mediump float;
 
 in vec4 vColor;
+in vec3 vViewPosition;
+// Clip planes in view space as normal and distance. Fragments on the side
+// the normal points to are cut away.
+uniform vec4 clipPlanes[MAX_CLIP_PLANES];
+uniform int clipPlaneCount;
+
+// Writes colors in linear light for a tone mapping pass.
+uniform bool linearOutput;
 
 out vec4 fragColor;
 
+vec4 sRGBToLinear(vec4 value) {
+     return vec4(mix(pow(value.rgb * 0.9478672986 + vec3(0.0521327014), vec3(2.4)), value.rgb * 0.0773993808, vec3(lessThanEqual(value.rgb, vec3(0.04045)))), value.a);
+}
+
 void main(void) {
-     fragColor = vColor;
+     for (int i = 0; i < MAX_CLIP_PLANES; i++) {
+          if (i < clipPlaneCount && dot(clipPlanes[i].xyz, -vViewPosition) > clipPlanes[i].w) {
+               discard;
+          }
+     }
+
+     fragColor = linearOutput ? sRGBToLinear(vColor) : vColor;
 }
@@ -7,6 +7,8 @@ in vec3 color;
     in vec4 instancedColor;
     in vec4 instancedEdgeColor;
     in mat4 instancedModelMatrix;
+    in float instancedVisibility;
+    in vec4 instancedExplosion;
 #else
     uniform vec4 defaultColor;
     uniform vec4 edgeColor;
@@ -17,14 +19,24 @@ uniform mat4 projection;
 uniform mat4 modelView;
 
 out vec4 vColor;
+out vec3 vViewPosition;
 
 void main(void) {
     vec4 mvPosition = vec4(position, 1.0);
     #ifdef USE_INSTANCING
-        mvPosition = instancedModelMatrix * mvPosition;
+        mat4 model = instancedModelMatrix;
+        model[3].xyz += instancedExplosion.xyz * instancedExplosion.w;
+        mvPosition = model * mvPosition;
     #endif
     mvPosition = modelView * mvPosition;
     gl_Position = projection * mvPosition;
+    vViewPosition = -mvPosition.xyz;
+    #ifdef USE_INSTANCING
+        // Hidden instances are moved past the far plane.
+        if (instancedVisibility < 0.5) {
+            gl_Position = vec4(0.0, 0.0, 2.0, 1.0);
+        }
+    #endif
     #ifdef USE_INSTANCING
         if (color.x < -1.0) {
             vColor = instancedEdgeColor;
@@ -0,0 +1,38 @@
+precision highp float;
+
+// Alpha is set where selected instances are.
+uniform sampler2D mask;
+uniform vec4 color;
+uniform int thickness;
+
+out vec4 fragColor;
+
+bool selected(ivec2 coord) {
+    ivec2 size = textureSize(mask, 0);
+    if (any(lessThan(coord, ivec2(0))) || any(greaterThanEqual(coord, size))) {
+        return false;
+    }
+    return texelFetch(mask, coord, 0).a > 0.5;
+}
+
+void main(void) {
+    ivec2 coord = ivec2(gl_FragCoord.xy);
+    if (selected(coord)) {
+        discard;
+    }
+
+    // Distance to the nearest selected pixel, smoothed over the last one.
+    float nearest = float(thickness) + 1.0;
+    for (int y = -thickness; y <= thickness; y++) {
+        for (int x = -thickness; x <= thickness; x++) {
+            if (selected(coord + ivec2(x, y))) {
+                nearest = min(nearest, length(vec2(x, y)));
+            }
+        }
+    }
+    float coverage = clamp(float(thickness) + 0.5 - nearest, 0.0, 1.0);
+    if (coverage <= 0.0) {
+        discard;
+    }
+    fragColor = vec4(color.rgb, color.a * coverage);
+}
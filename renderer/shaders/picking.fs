@@ -0,0 +1,21 @@
+precision highp float;
+precision highp int;
+
+flat in uint vId;
+in vec3 vViewPosition;
+// Clip planes in view space as normal and distance. Fragments on the side
+// the normal points to are cut away.
+uniform vec4 clipPlanes[MAX_CLIP_PLANES];
+uniform int clipPlaneCount;
+
+out uint fragId;
+
+void main(void) {
+    for (int i = 0; i < MAX_CLIP_PLANES; i++) {
+        if (i < clipPlaneCount && dot(clipPlanes[i].xyz, -vViewPosition) > clipPlanes[i].w) {
+            discard;
+        }
+    }
+    // Zero is left for where nothing is.
+    fragId = vId + 1u;
+}
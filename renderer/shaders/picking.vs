@@ -0,0 +1,25 @@
+precision highp float;
+
+uniform mat4 projection;
+uniform mat4 modelView;
+
+in vec3 position;
+in mat4 instancedModelMatrix;
+in float instancedVisibility;
+in vec4 instancedExplosion;
+in uint instancedId;
+
+flat out uint vId;
+out vec3 vViewPosition;
+
+void main(void) {
+    mat4 model = instancedModelMatrix;
+    model[3].xyz += instancedExplosion.xyz * instancedExplosion.w;
+    vec4 mvPosition = modelView * model * vec4(position, 1.0);
+    gl_Position = projection * mvPosition;
+    vViewPosition = -mvPosition.xyz;
+    if (instancedVisibility < 0.5) {
+        gl_Position = vec4(0.0, 0.0, 2.0, 1.0);
+    }
+    vId = instancedId;
+}
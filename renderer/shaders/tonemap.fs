@@ -0,0 +1,32 @@
+precision highp float;
+
+uniform sampler2D source;
+// Operator of `ToneMapping`: 0 none, 1 Reinhard, 2 ACES.
+uniform int toneMapping;
+uniform float exposure;
+
+out vec4 fragColor;
+
+vec3 LinearTosRGB(vec3 value) {
+    return mix(pow(value, vec3(0.41666)) * 1.055 - vec3(0.055), value * 12.92, vec3(lessThanEqual(value, vec3(0.0031308))));
+}
+
+vec3 ACESFilmicToneMapping(vec3 color) {
+    color *= 0.6;
+    return clamp((color * (2.51 * color + 0.03)) / (color * (2.43 * color + 0.59) + 0.14), 0.0, 1.0);
+}
+
+void main(void) {
+    vec4 texel = texelFetch(source, ivec2(gl_FragCoord.xy), 0);
+    // Glow over the background has no alpha of its own and is added as is.
+    float alpha = texel.a;
+    vec3 color = alpha > 0.0 ? texel.rgb / alpha : texel.rgb;
+    color *= exposure;
+    if (toneMapping == 1) {
+        color = color / (vec3(1.0) + color);
+    } else if (toneMapping == 2) {
+        color = ACESFilmicToneMapping(color);
+    }
+    color = LinearTosRGB(clamp(color, 0.0, 1.0));
+    fragColor = vec4(alpha > 0.0 ? color * alpha : color, alpha);
+}
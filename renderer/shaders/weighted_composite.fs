@@ -0,0 +1,14 @@
+precision highp float;
+
+uniform sampler2D accumulation;
+uniform sampler2D weights;
+
+out vec4 fragColor;
+
+void main(void) {
+    ivec2 coord = ivec2(gl_FragCoord.xy);
+    vec4 accumulated = texelFetch(accumulation, coord, 0);
+    float weight = texelFetch(weights, coord, 0).r;
+    // Alpha of the accumulation holds how much of the background shows.
+    fragColor = vec4(accumulated.rgb / clamp(weight, 1e-5, 5e4), 1.0 - accumulated.a);
+}
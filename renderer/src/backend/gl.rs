@@ -0,0 +1,671 @@
+use glow::{HasContext, PixelPackData};
+
+use crate::{
+    error::ShaderError,
+    utils::{cast_as_bytes, cast_as_bytes_mut},
+};
+
+use super::{Backend, Blending, BufferUsage, Primitive, TextureFormat};
+
+fn gl_primitive(primitive: Primitive) -> u32 {
+    match primitive {
+        Primitive::Triangles => glow::TRIANGLES,
+        Primitive::Lines => glow::LINES,
+    }
+}
+
+// Texture unit textures are bound to while being created, beyond those the
+// shaders sample from, so that creating one leaves theirs bound.
+const SCRATCH_UNIT: u32 = 15;
+
+unsafe fn bind_scratch_texture<GL: HasContext>(gl: &GL, texture: Option<GL::Texture>) {
+    gl.active_texture(glow::TEXTURE0 + SCRATCH_UNIT);
+    gl.bind_texture(glow::TEXTURE_2D, texture);
+}
+
+unsafe fn compile_shader<GL: HasContext>(
+    gl: &GL,
+    source: &str,
+    ty: u32,
+) -> Result<GL::Shader, ShaderError> {
+    let shader = gl.create_shader(ty).map_err(ShaderError::CreationError)?;
+
+    gl.shader_source(shader, source);
+    gl.compile_shader(shader);
+
+    if gl.get_shader_compile_status(shader) {
+        Ok(shader)
+    } else {
+        let log = gl.get_shader_info_log(shader);
+        gl.delete_shader(shader);
+        Err(ShaderError::CompileError(log))
+    }
+}
+
+unsafe fn complete_framebuffer<GL: HasContext>(
+    gl: &GL,
+    framebuffer: GL::Framebuffer,
+) -> Result<GL::Framebuffer, String> {
+    let status = gl.check_framebuffer_status(glow::FRAMEBUFFER);
+    gl.bind_framebuffer(glow::FRAMEBUFFER, None);
+    if status == glow::FRAMEBUFFER_COMPLETE {
+        Ok(framebuffer)
+    } else {
+        gl.delete_framebuffer(framebuffer);
+        Err(format!("Incomplete framebuffer: 0x{:x}", status))
+    }
+}
+
+impl<GL: HasContext> Backend for GL {
+    type Buffer = <GL as HasContext>::Buffer;
+    type VertexArray = <GL as HasContext>::VertexArray;
+    type Program = <GL as HasContext>::Program;
+    type UniformLocation = <GL as HasContext>::UniformLocation;
+    type Texture = <GL as HasContext>::Texture;
+    type Framebuffer = <GL as HasContext>::Framebuffer;
+    type Renderbuffer = <GL as HasContext>::Renderbuffer;
+
+    fn create_vertex_array(&self) -> Result<Self::VertexArray, String> {
+        unsafe { HasContext::create_vertex_array(self) }
+    }
+
+    fn bind_vertex_array(&self, array: Option<Self::VertexArray>) {
+        unsafe { HasContext::bind_vertex_array(self, array) }
+    }
+
+    fn delete_vertex_array(&self, array: Self::VertexArray) {
+        unsafe { HasContext::delete_vertex_array(self, array) }
+    }
+
+    fn create_buffer(&self) -> Result<Self::Buffer, String> {
+        unsafe { HasContext::create_buffer(self) }
+    }
+
+    fn buffer_data(&self, buffer: Option<Self::Buffer>, data: &[f32], usage: BufferUsage) {
+        let usage = match usage {
+            BufferUsage::Static => glow::STATIC_DRAW,
+            BufferUsage::Dynamic => glow::DYNAMIC_DRAW,
+        };
+        unsafe {
+            self.bind_buffer(glow::ARRAY_BUFFER, buffer);
+            self.buffer_data_u8_slice(glow::ARRAY_BUFFER, cast_as_bytes(data), usage);
+        }
+    }
+
+    fn buffer_data_u32(&self, buffer: Option<Self::Buffer>, data: &[u32], usage: BufferUsage) {
+        let usage = match usage {
+            BufferUsage::Static => glow::STATIC_DRAW,
+            BufferUsage::Dynamic => glow::DYNAMIC_DRAW,
+        };
+        unsafe {
+            self.bind_buffer(glow::ARRAY_BUFFER, buffer);
+            self.buffer_data_u8_slice(glow::ARRAY_BUFFER, cast_as_bytes(data), usage);
+        }
+    }
+
+    fn buffer_sub_data(&self, buffer: Option<Self::Buffer>, offset: usize, data: &[f32]) {
+        unsafe {
+            self.bind_buffer(glow::ARRAY_BUFFER, buffer);
+            self.buffer_sub_data_u8_slice(
+                glow::ARRAY_BUFFER,
+                (offset * size_of::<f32>()) as i32,
+                cast_as_bytes(data),
+            );
+        }
+    }
+
+    fn buffer_sub_data_u32(&self, buffer: Option<Self::Buffer>, offset: usize, data: &[u32]) {
+        unsafe {
+            self.bind_buffer(glow::ARRAY_BUFFER, buffer);
+            self.buffer_sub_data_u8_slice(
+                glow::ARRAY_BUFFER,
+                (offset * size_of::<u32>()) as i32,
+                cast_as_bytes(data),
+            );
+        }
+    }
+
+    fn delete_buffer(&self, buffer: Self::Buffer) {
+        unsafe { HasContext::delete_buffer(self, buffer) }
+    }
+
+    fn vertex_attribute(
+        &self,
+        location: u32,
+        buffer: Option<Self::Buffer>,
+        size: i32,
+        stride: i32,
+        offset: i32,
+    ) {
+        unsafe {
+            self.bind_buffer(glow::ARRAY_BUFFER, buffer);
+            self.vertex_attrib_pointer_f32(location, size, glow::FLOAT, false, stride, offset);
+            self.enable_vertex_attrib_array(location);
+        }
+    }
+
+    fn vertex_attribute_u32(
+        &self,
+        location: u32,
+        buffer: Option<Self::Buffer>,
+        size: i32,
+        stride: i32,
+        offset: i32,
+    ) {
+        unsafe {
+            self.bind_buffer(glow::ARRAY_BUFFER, buffer);
+            self.vertex_attrib_pointer_i32(location, size, glow::UNSIGNED_INT, stride, offset);
+            self.enable_vertex_attrib_array(location);
+        }
+    }
+
+    fn vertex_attribute_divisor(&self, location: u32, divisor: u32) {
+        unsafe { self.vertex_attrib_divisor(location, divisor) }
+    }
+
+    fn disable_vertex_attribute(&self, location: u32) {
+        unsafe { self.disable_vertex_attrib_array(location) }
+    }
+
+    fn compile_program(
+        &self,
+        vertex_shader: &str,
+        fragment_shader: &str,
+    ) -> Result<Self::Program, ShaderError> {
+        unsafe {
+            let vs = compile_shader(self, vertex_shader, glow::VERTEX_SHADER)?;
+            let fs = match compile_shader(self, fragment_shader, glow::FRAGMENT_SHADER) {
+                Ok(e) => e,
+                Err(e) => {
+                    HasContext::delete_shader(self, vs);
+                    return Err(e);
+                }
+            };
+
+            let program = HasContext::create_program(self).map_err(ShaderError::CreationError);
+            let result = program.and_then(|program| {
+                self.attach_shader(program, vs);
+                self.attach_shader(program, fs);
+                self.link_program(program);
+                self.detach_shader(program, vs);
+                self.detach_shader(program, fs);
+
+                if self.get_program_link_status(program) {
+                    Ok(program)
+                } else {
+                    let log = self.get_program_info_log(program);
+                    HasContext::delete_program(self, program);
+                    Err(ShaderError::LinkError(log))
+                }
+            });
+
+            HasContext::delete_shader(self, vs);
+            HasContext::delete_shader(self, fs);
+            result
+        }
+    }
+
+    fn use_program(&self, program: Option<Self::Program>) {
+        unsafe { HasContext::use_program(self, program) }
+    }
+
+    fn delete_program(&self, program: Self::Program) {
+        unsafe { HasContext::delete_program(self, program) }
+    }
+
+    fn attribute_location(&self, program: Self::Program, name: &str) -> Option<u32> {
+        unsafe { self.get_attrib_location(program, name) }
+    }
+
+    fn uniform_location(
+        &self,
+        program: Self::Program,
+        name: &str,
+    ) -> Option<Self::UniformLocation> {
+        unsafe { self.get_uniform_location(program, name) }
+    }
+
+    fn uniform_block_binding(&self, program: Self::Program, name: &str, binding: u32) {
+        unsafe {
+            if let Some(index) = self.get_uniform_block_index(program, name) {
+                HasContext::uniform_block_binding(self, program, index, binding);
+            }
+        }
+    }
+
+    fn bind_uniform_buffer(&self, binding: u32, buffer: Option<Self::Buffer>) {
+        unsafe { self.bind_buffer_base(glow::UNIFORM_BUFFER, binding, buffer) }
+    }
+
+    fn uniform_i32(&self, location: Option<&Self::UniformLocation>, value: i32) {
+        unsafe { self.uniform_1_i32(location, value) }
+    }
+
+    fn uniform_f32(&self, location: Option<&Self::UniformLocation>, value: f32) {
+        unsafe { self.uniform_1_f32(location, value) }
+    }
+
+    fn uniform_vec3(&self, location: Option<&Self::UniformLocation>, value: &[f32; 3]) {
+        unsafe { self.uniform_3_f32_slice(location, value) }
+    }
+
+    fn uniform_vec4(&self, location: Option<&Self::UniformLocation>, value: &[f32; 4]) {
+        unsafe { self.uniform_4_f32_slice(location, value) }
+    }
+
+    fn uniform_mat3(&self, location: Option<&Self::UniformLocation>, value: &[f32; 9]) {
+        unsafe { self.uniform_matrix_3_f32_slice(location, false, value) }
+    }
+
+    fn uniform_mat4(&self, location: Option<&Self::UniformLocation>, value: &[f32; 16]) {
+        unsafe { self.uniform_matrix_4_f32_slice(location, false, value) }
+    }
+
+    fn create_texture(
+        &self,
+        width: u32,
+        height: u32,
+        pixels: &[u8],
+    ) -> Result<Self::Texture, String> {
+        unsafe {
+            let texture = HasContext::create_texture(self)?;
+            bind_scratch_texture(self, Some(texture));
+            self.tex_image_2d(
+                glow::TEXTURE_2D,
+                0,
+                glow::RGBA as i32,
+                width as i32,
+                height as i32,
+                0,
+                glow::RGBA,
+                glow::UNSIGNED_BYTE,
+                Some(pixels),
+            );
+            self.tex_parameter_i32(
+                glow::TEXTURE_2D,
+                glow::TEXTURE_WRAP_S,
+                glow::CLAMP_TO_EDGE as i32,
+            );
+            self.tex_parameter_i32(
+                glow::TEXTURE_2D,
+                glow::TEXTURE_WRAP_T,
+                glow::CLAMP_TO_EDGE as i32,
+            );
+            self.tex_parameter_i32(
+                glow::TEXTURE_2D,
+                glow::TEXTURE_MIN_FILTER,
+                glow::LINEAR as i32,
+            );
+            bind_scratch_texture(self, None);
+            Ok(texture)
+        }
+    }
+
+    fn bind_texture(&self, unit: u32, texture: Option<Self::Texture>) {
+        unsafe {
+            self.active_texture(glow::TEXTURE0 + unit);
+            HasContext::bind_texture(self, glow::TEXTURE_2D, texture);
+        }
+    }
+
+    fn delete_texture(&self, texture: Self::Texture) {
+        unsafe { HasContext::delete_texture(self, texture) }
+    }
+
+    fn create_environment_texture(
+        &self,
+        width: u32,
+        height: u32,
+        levels: &[Vec<f32>],
+    ) -> Result<Self::Texture, String> {
+        unsafe {
+            let texture = HasContext::create_texture(self)?;
+            bind_scratch_texture(self, Some(texture));
+            for (level, pixels) in levels.iter().enumerate() {
+                self.tex_image_2d(
+                    glow::TEXTURE_2D,
+                    level as i32,
+                    glow::RGBA16F as i32,
+                    (width >> level).max(1) as i32,
+                    (height >> level).max(1) as i32,
+                    0,
+                    glow::RGBA,
+                    glow::FLOAT,
+                    Some(cast_as_bytes(pixels)),
+                );
+            }
+            for (parameter, value) in [
+                (glow::TEXTURE_WRAP_S, glow::REPEAT),
+                (glow::TEXTURE_WRAP_T, glow::CLAMP_TO_EDGE),
+                (glow::TEXTURE_MIN_FILTER, glow::LINEAR_MIPMAP_LINEAR),
+                (glow::TEXTURE_MAG_FILTER, glow::LINEAR),
+                (glow::TEXTURE_BASE_LEVEL, 0),
+                (
+                    glow::TEXTURE_MAX_LEVEL,
+                    levels.len().saturating_sub(1) as u32,
+                ),
+            ] {
+                self.tex_parameter_i32(glow::TEXTURE_2D, parameter, value as i32);
+            }
+            bind_scratch_texture(self, None);
+            Ok(texture)
+        }
+    }
+
+    fn create_render_target(
+        &self,
+        width: u32,
+        height: u32,
+        format: TextureFormat,
+    ) -> Result<Self::Texture, String> {
+        let (internal_format, format, ty) = match format {
+            TextureFormat::Rgba8 => (glow::RGBA8, glow::RGBA, glow::UNSIGNED_BYTE),
+            TextureFormat::Rgba16F => (glow::RGBA16F, glow::RGBA, glow::HALF_FLOAT),
+            TextureFormat::R16F => (glow::R16F, glow::RED, glow::HALF_FLOAT),
+            TextureFormat::R32UI => (glow::R32UI, glow::RED_INTEGER, glow::UNSIGNED_INT),
+            TextureFormat::Depth => (
+                glow::DEPTH_COMPONENT24,
+                glow::DEPTH_COMPONENT,
+                glow::UNSIGNED_INT,
+            ),
+        };
+        unsafe {
+            let texture = HasContext::create_texture(self)?;
+            bind_scratch_texture(self, Some(texture));
+            self.tex_image_2d(
+                glow::TEXTURE_2D,
+                0,
+                internal_format as i32,
+                width as i32,
+                height as i32,
+                0,
+                format,
+                ty,
+                None,
+            );
+            for (parameter, value) in [
+                (glow::TEXTURE_WRAP_S, glow::CLAMP_TO_EDGE),
+                (glow::TEXTURE_WRAP_T, glow::CLAMP_TO_EDGE),
+                (glow::TEXTURE_MIN_FILTER, glow::NEAREST),
+                (glow::TEXTURE_MAG_FILTER, glow::NEAREST),
+            ] {
+                self.tex_parameter_i32(glow::TEXTURE_2D, parameter, value as i32);
+            }
+            bind_scratch_texture(self, None);
+            Ok(texture)
+        }
+    }
+
+    fn create_framebuffer(
+        &self,
+        colors: &[Self::Texture],
+        depth: Option<Self::Texture>,
+    ) -> Result<Self::Framebuffer, String> {
+        unsafe {
+            let framebuffer = HasContext::create_framebuffer(self)?;
+            HasContext::bind_framebuffer(self, glow::FRAMEBUFFER, Some(framebuffer));
+            let mut attachments = Vec::new();
+            for (i, texture) in colors.iter().enumerate() {
+                let attachment = glow::COLOR_ATTACHMENT0 + i as u32;
+                self.framebuffer_texture_2d(
+                    glow::FRAMEBUFFER,
+                    attachment,
+                    glow::TEXTURE_2D,
+                    Some(*texture),
+                    0,
+                );
+                attachments.push(attachment);
+            }
+            if depth.is_some() {
+                self.framebuffer_texture_2d(
+                    glow::FRAMEBUFFER,
+                    glow::DEPTH_ATTACHMENT,
+                    glow::TEXTURE_2D,
+                    depth,
+                    0,
+                );
+            }
+            if attachments.is_empty() {
+                self.draw_buffers(&[glow::NONE]);
+                self.read_buffer(glow::NONE);
+            } else {
+                self.draw_buffers(&attachments);
+            }
+
+            complete_framebuffer(self, framebuffer)
+        }
+    }
+
+    fn bind_framebuffer(&self, framebuffer: Option<Self::Framebuffer>) {
+        unsafe { HasContext::bind_framebuffer(self, glow::FRAMEBUFFER, framebuffer) }
+    }
+
+    fn delete_framebuffer(&self, framebuffer: Self::Framebuffer) {
+        unsafe { HasContext::delete_framebuffer(self, framebuffer) }
+    }
+
+    fn max_samples(&self) -> u32 {
+        unsafe { self.get_parameter_i32(glow::MAX_SAMPLES).max(1) as u32 }
+    }
+
+    fn create_multisample_target(
+        &self,
+        width: u32,
+        height: u32,
+        samples: u32,
+        format: TextureFormat,
+    ) -> Result<Self::Renderbuffer, String> {
+        let internal_format = match format {
+            TextureFormat::Rgba8 => glow::RGBA8,
+            TextureFormat::Rgba16F => glow::RGBA16F,
+            TextureFormat::R16F => glow::R16F,
+            TextureFormat::R32UI => glow::R32UI,
+            TextureFormat::Depth => glow::DEPTH_COMPONENT24,
+        };
+        unsafe {
+            let renderbuffer = self.create_renderbuffer()?;
+            self.bind_renderbuffer(glow::RENDERBUFFER, Some(renderbuffer));
+            self.renderbuffer_storage_multisample(
+                glow::RENDERBUFFER,
+                samples as i32,
+                internal_format,
+                width as i32,
+                height as i32,
+            );
+            self.bind_renderbuffer(glow::RENDERBUFFER, None);
+            Ok(renderbuffer)
+        }
+    }
+
+    fn delete_renderbuffer(&self, renderbuffer: Self::Renderbuffer) {
+        unsafe { HasContext::delete_renderbuffer(self, renderbuffer) }
+    }
+
+    fn create_multisample_framebuffer(
+        &self,
+        color: Self::Renderbuffer,
+        depth: Self::Renderbuffer,
+    ) -> Result<Self::Framebuffer, String> {
+        unsafe {
+            let framebuffer = HasContext::create_framebuffer(self)?;
+            HasContext::bind_framebuffer(self, glow::FRAMEBUFFER, Some(framebuffer));
+            self.framebuffer_renderbuffer(
+                glow::FRAMEBUFFER,
+                glow::COLOR_ATTACHMENT0,
+                glow::RENDERBUFFER,
+                Some(color),
+            );
+            self.framebuffer_renderbuffer(
+                glow::FRAMEBUFFER,
+                glow::DEPTH_ATTACHMENT,
+                glow::RENDERBUFFER,
+                Some(depth),
+            );
+
+            complete_framebuffer(self, framebuffer)
+        }
+    }
+
+    fn resolve_framebuffer(
+        &self,
+        source: Self::Framebuffer,
+        destination: Option<Self::Framebuffer>,
+        width: u32,
+        height: u32,
+    ) {
+        unsafe {
+            HasContext::bind_framebuffer(self, glow::READ_FRAMEBUFFER, Some(source));
+            HasContext::bind_framebuffer(self, glow::DRAW_FRAMEBUFFER, destination);
+            self.blit_framebuffer(
+                0,
+                0,
+                width as i32,
+                height as i32,
+                0,
+                0,
+                width as i32,
+                height as i32,
+                glow::COLOR_BUFFER_BIT,
+                glow::NEAREST,
+            );
+            HasContext::bind_framebuffer(self, glow::FRAMEBUFFER, destination);
+        }
+    }
+
+    fn clear_color_attachment(&self, index: u32, value: &[f32; 4]) {
+        unsafe { self.clear_buffer_f32_slice(glow::COLOR, index, value) }
+    }
+
+    fn clear_integer_attachment(&self, index: u32, value: u32) {
+        unsafe { self.clear_buffer_u32_slice(glow::COLOR, index, &[value, 0, 0, 0]) }
+    }
+
+    fn clear_depth(&self) {
+        unsafe { self.clear_buffer_f32_slice(glow::DEPTH, 0, &[1.0]) }
+    }
+
+    fn read_integers(&self, x: u32, y: u32, width: u32, height: u32) -> Vec<u32> {
+        // Reading a single channel of integers is not always supported, but
+        // all four are.
+        let mut pixels = vec![0u32; (width * height * 4) as usize];
+        unsafe {
+            self.read_buffer(glow::COLOR_ATTACHMENT0);
+            self.read_pixels(
+                x as i32,
+                y as i32,
+                width as i32,
+                height as i32,
+                glow::RGBA_INTEGER,
+                glow::UNSIGNED_INT,
+                PixelPackData::Slice(cast_as_bytes_mut(&mut pixels)),
+            );
+        }
+        pixels.into_iter().step_by(4).collect()
+    }
+
+    fn set_initial_state(&self) {
+        unsafe {
+            self.clear_color(1.0, 1.0, 1.0, 0.0);
+            self.clear_depth_f32(1.0);
+            self.line_width(1.0);
+            self.cull_face(glow::BACK);
+            self.enable(glow::CULL_FACE);
+            self.enable(glow::DEPTH_TEST);
+            self.enable(glow::BLEND);
+            self.depth_func(glow::LEQUAL);
+            self.blend_func_separate(
+                glow::SRC_ALPHA,
+                glow::ONE_MINUS_SRC_ALPHA,
+                glow::ONE,
+                glow::ONE_MINUS_SRC_ALPHA,
+            );
+            self.blend_equation(glow::FUNC_ADD);
+            self.polygon_offset(1.0, 0.0);
+            self.enable(glow::POLYGON_OFFSET_FILL);
+        }
+    }
+
+    fn viewport(&self, width: u32, height: u32) {
+        unsafe { HasContext::viewport(self, 0, 0, width as i32, height as i32) }
+    }
+
+    fn set_culling(&self, enabled: bool) {
+        unsafe {
+            if enabled {
+                self.enable(glow::CULL_FACE);
+            } else {
+                self.disable(glow::CULL_FACE);
+            }
+        }
+    }
+
+    fn set_blending(&self, blending: Blending) {
+        unsafe {
+            match blending {
+                Blending::Alpha => self.blend_func_separate(
+                    glow::SRC_ALPHA,
+                    glow::ONE_MINUS_SRC_ALPHA,
+                    glow::ONE,
+                    glow::ONE_MINUS_SRC_ALPHA,
+                ),
+                Blending::Accumulate => self.blend_func_separate(
+                    glow::ONE,
+                    glow::ONE,
+                    glow::ZERO,
+                    glow::ONE_MINUS_SRC_ALPHA,
+                ),
+                Blending::Additive => {
+                    self.blend_func_separate(glow::ONE, glow::ONE, glow::ZERO, glow::ONE)
+                }
+                Blending::Premultiplied => self.blend_func_separate(
+                    glow::ONE,
+                    glow::ONE_MINUS_SRC_ALPHA,
+                    glow::ONE,
+                    glow::ONE_MINUS_SRC_ALPHA,
+                ),
+            }
+        }
+    }
+
+    fn set_depth_test(&self, enabled: bool) {
+        unsafe {
+            if enabled {
+                self.enable(glow::DEPTH_TEST);
+            } else {
+                self.disable(glow::DEPTH_TEST);
+            }
+        }
+    }
+
+    fn set_depth_write(&self, enabled: bool) {
+        unsafe { self.depth_mask(enabled) }
+    }
+
+    fn set_color_write(&self, enabled: bool) {
+        unsafe { self.color_mask(enabled, enabled, enabled, enabled) }
+    }
+
+    fn draw_arrays(&self, primitive: Primitive, first: usize, count: usize) {
+        unsafe {
+            HasContext::draw_arrays(self, gl_primitive(primitive), first as i32, count as i32)
+        }
+    }
+
+    fn draw_arrays_instanced(
+        &self,
+        primitive: Primitive,
+        first: usize,
+        count: usize,
+        instances: usize,
+    ) {
+        unsafe {
+            HasContext::draw_arrays_instanced(
+                self,
+                gl_primitive(primitive),
+                first as i32,
+                count as i32,
+                instances as i32,
+            )
+        }
+    }
+}
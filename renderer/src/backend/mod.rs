@@ -0,0 +1,211 @@
+use std::fmt::Debug;
+
+use crate::error::ShaderError;
+
+mod gl;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Primitive {
+    Triangles,
+    Lines,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BufferUsage {
+    Static,
+    Dynamic,
+}
+
+// Formats of textures that are rendered into.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TextureFormat {
+    Rgba8,
+    Rgba16F,
+    R16F,
+    // Unsigned integers, such as instance IDs.
+    R32UI,
+    Depth,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Blending {
+    // Source over destination by source alpha.
+    Alpha,
+    // Adds up colors and multiplies alpha by one minus source alpha, as
+    // weighted blended transparency accumulates.
+    Accumulate,
+    // Adds source colors onto the destination, keeping its alpha.
+    Additive,
+    // Source over destination, with source colors already multiplied by
+    // its alpha.
+    Premultiplied,
+}
+
+// Everything the renderer asks of a graphics API: buffer management,
+// shader programs and draw submission.
+//
+// Every `glow::HasContext` is a backend, so OpenGL and WebGL 2 contexts
+// can be handed to the renderer as they are. Shaders are still written in
+// GLSL; other backends are expected to translate them.
+pub trait Backend {
+    type Buffer: Copy + Debug;
+    type VertexArray: Copy + Debug;
+    type Program: Copy + Debug;
+    type UniformLocation: Clone + Debug;
+    type Texture: Copy + Debug;
+    type Framebuffer: Copy + Debug;
+    type Renderbuffer: Copy + Debug;
+
+    fn create_vertex_array(&self) -> Result<Self::VertexArray, String>;
+    fn bind_vertex_array(&self, array: Option<Self::VertexArray>);
+    fn delete_vertex_array(&self, array: Self::VertexArray);
+
+    fn create_buffer(&self) -> Result<Self::Buffer, String>;
+    fn buffer_data(&self, buffer: Option<Self::Buffer>, data: &[f32], usage: BufferUsage);
+    fn buffer_data_u32(&self, buffer: Option<Self::Buffer>, data: &[u32], usage: BufferUsage);
+    // Replaces the contents of `buffer` from `offset` elements in with
+    // `data`, leaving its size as is.
+    fn buffer_sub_data(&self, buffer: Option<Self::Buffer>, offset: usize, data: &[f32]);
+    fn buffer_sub_data_u32(&self, buffer: Option<Self::Buffer>, offset: usize, data: &[u32]);
+    fn delete_buffer(&self, buffer: Self::Buffer);
+
+    // Sources attribute `location` from `buffer` with `size` floats per
+    // vertex, and enables it.
+    fn vertex_attribute(
+        &self,
+        location: u32,
+        buffer: Option<Self::Buffer>,
+        size: i32,
+        stride: i32,
+        offset: i32,
+    );
+    // Like `vertex_attribute`, with unsigned integers the shader reads as
+    // they are.
+    fn vertex_attribute_u32(
+        &self,
+        location: u32,
+        buffer: Option<Self::Buffer>,
+        size: i32,
+        stride: i32,
+        offset: i32,
+    );
+    fn vertex_attribute_divisor(&self, location: u32, divisor: u32);
+    fn disable_vertex_attribute(&self, location: u32);
+
+    fn compile_program(
+        &self,
+        vertex_shader: &str,
+        fragment_shader: &str,
+    ) -> Result<Self::Program, ShaderError>;
+    fn use_program(&self, program: Option<Self::Program>);
+    fn delete_program(&self, program: Self::Program);
+    fn attribute_location(&self, program: Self::Program, name: &str) -> Option<u32>;
+    fn uniform_location(&self, program: Self::Program, name: &str)
+        -> Option<Self::UniformLocation>;
+
+    // Makes uniform block `name` of `program`, if it has one, read from
+    // uniform buffer binding point `binding`.
+    fn uniform_block_binding(&self, program: Self::Program, name: &str, binding: u32);
+    // Binds `buffer` to uniform buffer binding point `binding`.
+    fn bind_uniform_buffer(&self, binding: u32, buffer: Option<Self::Buffer>);
+
+    fn uniform_i32(&self, location: Option<&Self::UniformLocation>, value: i32);
+    fn uniform_f32(&self, location: Option<&Self::UniformLocation>, value: f32);
+    fn uniform_vec3(&self, location: Option<&Self::UniformLocation>, value: &[f32; 3]);
+    fn uniform_vec4(&self, location: Option<&Self::UniformLocation>, value: &[f32; 4]);
+    fn uniform_mat3(&self, location: Option<&Self::UniformLocation>, value: &[f32; 9]);
+    fn uniform_mat4(&self, location: Option<&Self::UniformLocation>, value: &[f32; 16]);
+
+    // Creates a clamped, linearly filtered 2D texture from RGBA pixels.
+    // Creating textures leaves those bound to units as they were.
+    fn create_texture(
+        &self,
+        width: u32,
+        height: u32,
+        pixels: &[u8],
+    ) -> Result<Self::Texture, String>;
+    // Creates a linearly filtered RGBA float texture from mipmap `levels`,
+    // each half the size of the last. It repeats horizontally, as
+    // equirectangular images wrap around.
+    fn create_environment_texture(
+        &self,
+        width: u32,
+        height: u32,
+        levels: &[Vec<f32>],
+    ) -> Result<Self::Texture, String>;
+    fn bind_texture(&self, unit: u32, texture: Option<Self::Texture>);
+    fn delete_texture(&self, texture: Self::Texture);
+
+    // Creates a texture of `format` to render into, sampled without
+    // filtering.
+    fn create_render_target(
+        &self,
+        width: u32,
+        height: u32,
+        format: TextureFormat,
+    ) -> Result<Self::Texture, String>;
+    // Creates a framebuffer drawing into `colors` in order, testing
+    // against `depth`.
+    fn create_framebuffer(
+        &self,
+        colors: &[Self::Texture],
+        depth: Option<Self::Texture>,
+    ) -> Result<Self::Framebuffer, String>;
+    // Binds `framebuffer`, or the default one if `None`.
+    fn bind_framebuffer(&self, framebuffer: Option<Self::Framebuffer>);
+    fn delete_framebuffer(&self, framebuffer: Self::Framebuffer);
+
+    // Most samples per pixel a multisampled render target can have.
+    fn max_samples(&self) -> u32;
+    // Creates a multisampled buffer of `format` to render into. It can't
+    // be sampled; resolve it into a framebuffer instead.
+    fn create_multisample_target(
+        &self,
+        width: u32,
+        height: u32,
+        samples: u32,
+        format: TextureFormat,
+    ) -> Result<Self::Renderbuffer, String>;
+    fn delete_renderbuffer(&self, renderbuffer: Self::Renderbuffer);
+    fn create_multisample_framebuffer(
+        &self,
+        color: Self::Renderbuffer,
+        depth: Self::Renderbuffer,
+    ) -> Result<Self::Framebuffer, String>;
+    // Averages the samples of the color of `source` into `destination`,
+    // of the same size, and binds `destination`.
+    fn resolve_framebuffer(
+        &self,
+        source: Self::Framebuffer,
+        destination: Option<Self::Framebuffer>,
+        width: u32,
+        height: u32,
+    );
+    // Clears color attachment `index` of the bound framebuffer.
+    fn clear_color_attachment(&self, index: u32, value: &[f32; 4]);
+    // Clears integer color attachment `index` of the bound framebuffer.
+    fn clear_integer_attachment(&self, index: u32, value: u32);
+    fn clear_depth(&self);
+    // Reads a region of integer color attachment 0 of the bound
+    // framebuffer, row by row from the bottom left.
+    fn read_integers(&self, x: u32, y: u32, width: u32, height: u32) -> Vec<u32>;
+
+    // Depth testing, alpha blending, backface culling and polygon offset
+    // the way the renderer expects them.
+    fn set_initial_state(&self);
+    fn viewport(&self, width: u32, height: u32);
+    fn set_culling(&self, enabled: bool);
+    fn set_blending(&self, blending: Blending);
+    fn set_depth_test(&self, enabled: bool);
+    fn set_depth_write(&self, enabled: bool);
+    fn set_color_write(&self, enabled: bool);
+
+    fn draw_arrays(&self, primitive: Primitive, first: usize, count: usize);
+    fn draw_arrays_instanced(
+        &self,
+        primitive: Primitive,
+        first: usize,
+        count: usize,
+        instances: usize,
+    );
+}
@@ -0,0 +1,622 @@
+use std::f32::consts::FRAC_PI_2;
+
+use cgmath::{prelude::*, Deg, Matrix3, Point3, Rad};
+use ldraw::{
+    elements::{RotStep, RotStepMode},
+    Matrix4, Point2, Vector2, Vector3,
+};
+use ldraw_ir::geometry::{BoundingBox2, BoundingBox3};
+
+use crate::state::{OrthographicCamera, PerspectiveCamera};
+
+// Distance from the center of `bounding_box` at which a perspective camera
+// sees all of it, with `margin` times its size to spare on the narrower
+// side of the view.
+pub fn fit_distance(
+    camera: &PerspectiveCamera,
+    bounding_box: &BoundingBox3,
+    width: usize,
+    height: usize,
+    margin: f32,
+) -> f32 {
+    let radius = (bounding_box.max - bounding_box.min).magnitude() * 0.5 * (1.0 + margin * 2.0);
+    let vertical = Rad::from(camera.fov);
+    let horizontal = Rad::from(camera.derive_horizontal_fov(width, height));
+    let fov = if vertical < horizontal {
+        vertical
+    } else {
+        horizontal
+    };
+
+    radius / (fov / 2.0).sin()
+}
+
+// Points the camera at the center of `bounding_box` from its current
+// direction and moves it back until the box fits.
+pub fn fit_perspective(
+    camera: &mut PerspectiveCamera,
+    bounding_box: &BoundingBox3,
+    width: usize,
+    height: usize,
+    margin: f32,
+) {
+    let distance = fit_distance(camera, bounding_box, width, height, margin);
+    let direction = camera.position - camera.look_at;
+    let direction = if direction.magnitude2() > 0.0 {
+        direction.normalize()
+    } else {
+        Vector3::new(0.0, 0.0, -1.0)
+    };
+
+    camera.look_at = Point3::from_vec(bounding_box.center());
+    camera.position = camera.look_at + direction * distance;
+}
+
+// Points the camera at the center of `bounding_box` and returns the view
+// bounds showing all of it at the given aspect ratio, with `margin` times
+// its projected size to spare on every side.
+pub fn fit_orthographic(
+    camera: &mut OrthographicCamera,
+    bounding_box: &BoundingBox3,
+    width: usize,
+    height: usize,
+    margin: f32,
+) -> BoundingBox2 {
+    let center = Point3::from_vec(bounding_box.center());
+    camera.position += center - camera.look_at;
+    camera.look_at = center;
+
+    let view = camera.derive_view_matrix();
+    let mut projected = BoundingBox2::zero();
+    for (i, point) in bounding_box.points().iter().enumerate() {
+        let p = (view * point.extend(1.0)).truncate().truncate();
+        if i == 0 {
+            projected = BoundingBox2::new(&p, &p);
+        } else {
+            projected.update_point(&p);
+        }
+    }
+
+    let aspect_ratio = if width == 0 || height == 0 {
+        1.0
+    } else {
+        width as f32 / height as f32
+    };
+    let size = Vector2::new(projected.len_x(), projected.len_y()) * (1.0 + margin * 2.0);
+    let size = if size.x / aspect_ratio > size.y {
+        Vector2::new(size.x, size.x / aspect_ratio)
+    } else {
+        Vector2::new(size.y * aspect_ratio, size.y)
+    };
+    let center = projected.center();
+    BoundingBox2::new(&(center - size * 0.5), &(center + size * 0.5))
+}
+
+const DEFAULT_YAW: f32 = 0.785;
+const DEFAULT_PITCH: f32 = 0.262;
+
+// Unit vector from the focus towards the camera.
+fn orbit_direction(yaw: f32, pitch: f32) -> Vector3 {
+    Vector3::new(
+        yaw.sin() * pitch.cos(),
+        -pitch.sin(),
+        -yaw.cos() * pitch.cos(),
+    )
+}
+
+// Orbits a perspective camera around a focus point, driven by pointer
+// drags and scrolling.
+//
+// Dragging turns the camera by `rotate_speed` radians per pixel, and the
+// motion carries on after release, slowing down by `damping`. Pitch stays
+// within `min_pitch..=max_pitch` so the camera never flips over the poles.
+// Call `update` once per frame before reading `camera`.
+pub struct OrbitController {
+    pub focus: Point3<f32>,
+    pub distance: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+
+    // Rotation around the vertical axis, in radians.
+    pub yaw: f32,
+    // Elevation above the horizon, in radians.
+    pub pitch: f32,
+    pub min_pitch: f32,
+    pub max_pitch: f32,
+
+    pub rotate_speed: f32,
+    // Relative change of the distance per scroll unit.
+    pub zoom_speed: f32,
+    // Fraction of the angular velocity lost per second after a drag ends;
+    // 1 stops right away.
+    pub damping: f32,
+    // Yaw in radians per second while nobody is dragging.
+    pub auto_rotate: f32,
+
+    pub camera: PerspectiveCamera,
+
+    pressed: bool,
+    last_position: Option<Point2>,
+    pending: Vector2,
+    velocity: Vector2,
+    tick: Option<f32>,
+}
+
+impl OrbitController {
+    pub fn new(focus: Point3<f32>, distance: f32) -> Self {
+        let mut controller = OrbitController {
+            focus,
+            distance,
+            min_distance: 10.0,
+            max_distance: 100000.0,
+
+            yaw: DEFAULT_YAW,
+            pitch: DEFAULT_PITCH,
+            min_pitch: -FRAC_PI_2 + 0.017,
+            max_pitch: FRAC_PI_2 - 0.017,
+
+            rotate_speed: 0.01,
+            zoom_speed: 0.002,
+            damping: 0.95,
+            auto_rotate: 0.0,
+
+            camera: PerspectiveCamera::new(focus, focus, Deg(45.0)),
+
+            pressed: false,
+            last_position: None,
+            pending: Vector2::new(0.0, 0.0),
+            velocity: Vector2::new(0.0, 0.0),
+            tick: None,
+        };
+        controller.update_camera();
+        controller
+    }
+
+    pub fn on_pointer_press(&mut self, pressed: bool) {
+        self.pressed = pressed;
+        if !pressed {
+            self.last_position = None;
+        }
+    }
+
+    // Rotates by the distance from the last position while pressed.
+    pub fn on_pointer_move(&mut self, x: f32, y: f32) {
+        if !self.pressed {
+            return;
+        }
+        if let Some(last) = self.last_position {
+            self.rotate(&Vector2::new(x - last.x, y - last.y));
+        }
+        self.last_position = Some(Point2::new(x, y));
+    }
+
+    // Rotates by a pointer movement in pixels.
+    pub fn rotate(&mut self, delta: &Vector2) {
+        let delta = Vector2::new(-delta.x, delta.y) * self.rotate_speed;
+        self.turn(&delta);
+        self.pending += delta;
+    }
+
+    // Moves closer for negative and away for positive scroll deltas.
+    pub fn zoom(&mut self, delta: f32) {
+        self.distance = (self.distance * (1.0 + delta * self.zoom_speed).max(0.01))
+            .clamp(self.min_distance, self.max_distance);
+        self.update_camera();
+    }
+
+    pub fn update(&mut self, tick: f32) {
+        if let Some(last) = self.tick {
+            let delta = tick - last;
+            if self.pressed {
+                if delta > 0.0 {
+                    self.velocity = self.pending / delta;
+                }
+            } else {
+                let velocity = self.velocity + Vector2::new(self.auto_rotate, 0.0);
+                self.turn(&(velocity * delta));
+                self.velocity *= (1.0 - self.damping).clamp(0.0, 1.0).powf(delta);
+            }
+        }
+        self.pending = Vector2::new(0.0, 0.0);
+        self.tick = Some(tick);
+        self.update_camera();
+    }
+
+    // Focuses on the center of `bounding_box` and moves back until the
+    // box fits.
+    pub fn fit(&mut self, bounding_box: &BoundingBox3, width: usize, height: usize, margin: f32) {
+        self.focus = Point3::from_vec(bounding_box.center());
+        self.distance = fit_distance(&self.camera, bounding_box, width, height, margin);
+        self.update_camera();
+    }
+
+    // Turns to show the model the way `rotation` does and stops any
+    // remaining motion. Roll around the viewing axis is ignored.
+    pub fn apply_step_rotation(&mut self, rotation: &StepRotation) {
+        let direction = rotation.view_direction();
+        self.yaw = Rad::atan2(direction.x, -direction.z).0;
+        self.pitch = Rad::asin(-direction.y)
+            .0
+            .clamp(self.min_pitch, self.max_pitch);
+        self.velocity = Vector2::new(0.0, 0.0);
+        self.update_camera();
+    }
+
+    pub fn derive_view_matrix(&self) -> Matrix4 {
+        self.camera.derive_view_matrix()
+    }
+
+    pub fn derive_projection_matrix(&self, width: usize, height: usize) -> Matrix4 {
+        self.camera.derive_projection_matrix(width, height)
+    }
+
+    fn turn(&mut self, delta: &Vector2) {
+        self.yaw += delta.x;
+        self.pitch = (self.pitch + delta.y).clamp(self.min_pitch, self.max_pitch);
+    }
+
+    fn update_camera(&mut self) {
+        let distance = self.distance.clamp(self.min_distance, self.max_distance);
+        let focus = self.focus;
+        self.camera.look_at = focus;
+        self.camera.position = focus + orbit_direction(self.yaw, self.pitch) * distance;
+    }
+}
+
+impl Default for OrbitController {
+    fn default() -> Self {
+        Self::new(Point3::new(0.0, 0.0, 0.0), 300.0)
+    }
+}
+
+// View rotation in effect while building a step, following the
+// `0 ROTSTEP` statements that ended the steps so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum StepRotation {
+    // The initial view of `OrbitController`.
+    #[default]
+    Default,
+    // Degrees around x, y and z, starting from the front.
+    Absolute(Vector3),
+    // Degrees around x, y and z, starting from the default view.
+    Relative(Vector3),
+}
+
+impl StepRotation {
+    // Rotation for the steps after one ended by `statement`.
+    pub fn apply(&self, statement: &RotStep) -> Self {
+        match statement {
+            RotStep::End => StepRotation::Default,
+            RotStep::Rotation(angles, RotStepMode::Absolute) => StepRotation::Absolute(*angles),
+            RotStep::Rotation(angles, RotStepMode::Relative) => StepRotation::Relative(*angles),
+            RotStep::Rotation(angles, RotStepMode::Additive) => match self {
+                StepRotation::Default => StepRotation::Relative(*angles),
+                StepRotation::Absolute(e) => StepRotation::Absolute(e + angles),
+                StepRotation::Relative(e) => StepRotation::Relative(e + angles),
+            },
+        }
+    }
+
+    // Unit vector from the model towards the viewer. The model turns
+    // around x first, then y, then z.
+    pub fn view_direction(&self) -> Vector3 {
+        let (base, angles) = match self {
+            StepRotation::Default => return orbit_direction(DEFAULT_YAW, DEFAULT_PITCH),
+            StepRotation::Absolute(angles) => (orbit_direction(0.0, 0.0), angles),
+            StepRotation::Relative(angles) => (orbit_direction(DEFAULT_YAW, DEFAULT_PITCH), angles),
+        };
+        let rotation = Matrix3::from_angle_z(Deg(angles.z))
+            * Matrix3::from_angle_y(Deg(angles.y))
+            * Matrix3::from_angle_x(Deg(angles.x));
+        rotation.transpose() * base
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    // Maps linear progress within `0..=1` onto the curve.
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Easing::EaseInOut => {
+                if t < 0.5 {
+                    4.0 * t * t * t
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+                }
+            }
+        }
+    }
+}
+
+// Where a camera is and what it looks at. A zoom of 2 halves the field
+// of view.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CameraPose {
+    pub position: Point3<f32>,
+    pub look_at: Point3<f32>,
+    pub zoom: f32,
+}
+
+impl CameraPose {
+    pub fn from_camera(camera: &PerspectiveCamera) -> Self {
+        CameraPose {
+            position: camera.position,
+            look_at: camera.look_at,
+            zoom: 1.0,
+        }
+    }
+
+    fn lerp(&self, other: &CameraPose, t: f32) -> Self {
+        CameraPose {
+            position: self.position + (other.position - self.position) * t,
+            look_at: self.look_at + (other.look_at - self.look_at) * t,
+            zoom: self.zoom + (other.zoom - self.zoom) * t,
+        }
+    }
+
+    // Moves `camera` here, narrowing `fov` by the zoom.
+    pub fn apply(&self, camera: &mut PerspectiveCamera, fov: Deg<f32>) {
+        camera.position = self.position;
+        camera.look_at = self.look_at;
+        let half = Rad::from(fov) / 2.0;
+        camera.fov = Deg::from(Rad::atan(half.tan() / self.zoom.max(0.001)) * 2.0);
+    }
+}
+
+// A pose to reach `time` seconds into the animation, approached along
+// `easing` from the previous keyframe.
+#[derive(Clone, Debug)]
+pub struct CameraKeyframe {
+    pub time: f32,
+    pub pose: CameraPose,
+    pub easing: Easing,
+}
+
+// Moves a camera through keyframes over time, for fly-throughs and
+// transitions between saved views.
+//
+// Everything but the pose, including the field of view at zoom 1, comes
+// from `camera`.
+pub struct CameraAnimation {
+    pub camera: PerspectiveCamera,
+    keyframes: Vec<CameraKeyframe>,
+    pub looping: bool,
+}
+
+impl CameraAnimation {
+    pub fn new(camera: PerspectiveCamera) -> Self {
+        CameraAnimation {
+            camera,
+            keyframes: Vec::new(),
+            looping: false,
+        }
+    }
+
+    // Goes from the pose of `from` to `to` in `duration` seconds.
+    pub fn transition(
+        from: PerspectiveCamera,
+        to: CameraPose,
+        duration: f32,
+        easing: Easing,
+    ) -> Self {
+        let start = CameraPose::from_camera(&from);
+        let mut animation = CameraAnimation::new(from);
+        animation.add_keyframe(0.0, start, Easing::Linear);
+        animation.add_keyframe(duration, to, easing);
+        animation
+    }
+
+    pub fn add_keyframe(&mut self, time: f32, pose: CameraPose, easing: Easing) {
+        let index = self.keyframes.partition_point(|e| e.time <= time);
+        self.keyframes
+            .insert(index, CameraKeyframe { time, pose, easing });
+    }
+
+    pub fn keyframes(&self) -> &[CameraKeyframe] {
+        &self.keyframes
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map(|e| e.time).unwrap_or(0.0)
+    }
+
+    pub fn is_finished(&self, time: f32) -> bool {
+        !self.looping && time >= self.duration()
+    }
+
+    // The pose `time` seconds in, holding the first and last keyframes
+    // outside of the animation.
+    pub fn sample(&self, time: f32) -> Option<CameraPose> {
+        let first = self.keyframes.first()?;
+        let duration = self.duration();
+        let time = if self.looping && duration > 0.0 {
+            time.rem_euclid(duration)
+        } else {
+            time
+        };
+
+        let index = self.keyframes.partition_point(|e| e.time <= time);
+        if index == 0 {
+            return Some(first.pose.clone());
+        }
+        let from = &self.keyframes[index - 1];
+        let to = match self.keyframes.get(index) {
+            Some(e) => e,
+            None => return Some(from.pose.clone()),
+        };
+        let t = (time - from.time) / (to.time - from.time);
+        Some(from.pose.lerp(&to.pose, to.easing.apply(t)))
+    }
+
+    // The camera `time` seconds in.
+    pub fn camera_at(&self, time: f32) -> Option<PerspectiveCamera> {
+        let pose = self.sample(time)?;
+        let mut camera = self.camera.clone();
+        pose.apply(&mut camera, self.camera.fov);
+        Some(camera)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use cgmath::{Deg, MetricSpace, Point3};
+    use ldraw::{
+        elements::{RotStep, RotStepMode},
+        Vector2, Vector3,
+    };
+    use ldraw_ir::geometry::BoundingBox3;
+
+    use crate::state::{OrthographicCamera, PerspectiveCamera};
+
+    use super::{
+        fit_orthographic, fit_perspective, CameraAnimation, CameraPose, Easing, OrbitController,
+        StepRotation,
+    };
+
+    #[test]
+    fn test_orbit_controller() {
+        let mut orbit = OrbitController::new(Point3::new(10.0, 0.0, 0.0), 100.0);
+        orbit.update(0.0);
+        assert!((orbit.camera.position.distance(orbit.focus) - 100.0).abs() < 1e-3);
+
+        // Pitch stops short of the pole.
+        orbit.on_pointer_press(true);
+        orbit.on_pointer_move(0.0, 0.0);
+        orbit.on_pointer_move(0.0, 1000.0);
+        assert_eq!(orbit.pitch, orbit.max_pitch);
+        assert!(orbit.camera.position.y > -100.0);
+
+        // A drag keeps turning after release, slower every frame.
+        let yaw = orbit.yaw;
+        orbit.rotate(&Vector2::new(-10.0, 0.0));
+        orbit.update(0.1);
+        orbit.on_pointer_press(false);
+        let turned = orbit.yaw - yaw;
+        assert!((turned - 0.1).abs() < 1e-5);
+        orbit.update(0.2);
+        let first = orbit.yaw - yaw - turned;
+        orbit.update(0.3);
+        let second = orbit.yaw - yaw - turned - first;
+        assert!(first > second && second > 0.0);
+
+        orbit.zoom(1.0e6);
+        orbit.update(0.4);
+        assert_eq!(orbit.distance, orbit.max_distance);
+    }
+
+    #[test]
+    fn test_step_rotation() {
+        let rotation = |x, y, z, mode| RotStep::Rotation(Vector3::new(x, y, z), mode);
+        let mut orbit = OrbitController::new(Point3::new(0.0, 0.0, 0.0), 100.0);
+        let (yaw, pitch) = (orbit.yaw, orbit.pitch);
+
+        // No rotation from the default view keeps it.
+        let relative = StepRotation::Default.apply(&rotation(0.0, 0.0, 0.0, RotStepMode::Relative));
+        orbit.apply_step_rotation(&relative);
+        assert!((orbit.yaw - yaw).abs() < 1e-4 && (orbit.pitch - pitch).abs() < 1e-4);
+
+        // The front, then from the side after turning the model a quarter.
+        let front = relative.apply(&rotation(0.0, 0.0, 0.0, RotStepMode::Absolute));
+        orbit.apply_step_rotation(&front);
+        assert!(orbit.yaw.abs() < 1e-4 && orbit.pitch.abs() < 1e-4);
+        assert!(
+            orbit
+                .camera
+                .position
+                .distance(Point3::new(0.0, 0.0, -100.0))
+                < 1e-3
+        );
+
+        let side = front.apply(&rotation(0.0, 45.0, 0.0, RotStepMode::Additive));
+        let side = side.apply(&rotation(0.0, 45.0, 0.0, RotStepMode::Additive));
+        assert_eq!(side, StepRotation::Absolute(Vector3::new(0.0, 90.0, 0.0)));
+        orbit.apply_step_rotation(&side);
+        assert!((orbit.yaw.abs() - FRAC_PI_2).abs() < 1e-4 && orbit.pitch.abs() < 1e-4);
+
+        // Looking straight down stops short of the pole.
+        orbit.apply_step_rotation(&StepRotation::Absolute(Vector3::new(90.0, 0.0, 0.0)));
+        assert!(orbit.pitch == orbit.min_pitch || orbit.pitch == orbit.max_pitch);
+
+        assert_eq!(side.apply(&RotStep::End), StepRotation::Default);
+    }
+
+    #[test]
+    fn test_fit() {
+        let bounding_box = BoundingBox3::new(
+            &Vector3::new(-10.0, -20.0, -10.0),
+            &Vector3::new(30.0, 20.0, 30.0),
+        );
+        let mut camera = PerspectiveCamera::new(
+            Point3::new(0.0, 0.0, -500.0),
+            Point3::new(0.0, 0.0, 0.0),
+            Deg(60.0),
+        );
+        fit_perspective(&mut camera, &bounding_box, 400, 400, 0.0);
+        assert_eq!(camera.look_at, Point3::new(10.0, 0.0, 10.0));
+        // The bounding sphere touches both sides of a 60 degree view.
+        let radius = 20.0 * 3.0f32.sqrt();
+        assert!((camera.position.distance(camera.look_at) - radius * 2.0).abs() < 1e-3);
+        assert_eq!(camera.position.x, 10.0);
+
+        // Narrow views back off further.
+        let square = camera.position.distance(camera.look_at);
+        fit_perspective(&mut camera, &bounding_box, 200, 400, 0.0);
+        assert!(camera.position.distance(camera.look_at) > square);
+
+        let mut camera =
+            OrthographicCamera::new(Point3::new(0.0, 0.0, -500.0), Point3::new(0.0, 0.0, 0.0));
+        let bounds = fit_orthographic(&mut camera, &bounding_box, 800, 400, 0.1);
+        assert_eq!(camera.position, Point3::new(10.0, 0.0, -490.0));
+        assert!((bounds.len_y() - 48.0).abs() < 1e-3);
+        assert!((bounds.len_x() - 96.0).abs() < 1e-3);
+        assert!(bounds.center().x.abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_camera_animation() {
+        let pose = |x: f32, zoom: f32| CameraPose {
+            position: Point3::new(x, -100.0, -100.0),
+            look_at: Point3::new(x, 0.0, 0.0),
+            zoom,
+        };
+        let camera = PerspectiveCamera::new(
+            Point3::new(0.0, 0.0, -100.0),
+            Point3::new(0.0, 0.0, 0.0),
+            Deg(60.0),
+        );
+        let mut animation =
+            CameraAnimation::transition(camera, pose(100.0, 1.0), 2.0, Easing::Linear);
+        animation.add_keyframe(4.0, pose(100.0, 2.0), Easing::EaseInOut);
+        assert_eq!(animation.duration(), 4.0);
+
+        assert_eq!(animation.sample(-1.0).unwrap().position.x, 0.0);
+        let halfway = animation.sample(1.0).unwrap();
+        assert_eq!(halfway.position, Point3::new(50.0, -50.0, -100.0));
+        assert_eq!(halfway.look_at.x, 50.0);
+        assert_eq!(animation.sample(3.0).unwrap().zoom, 1.5);
+        assert!(animation.sample(2.5).unwrap().zoom < 1.25);
+        assert_eq!(animation.sample(10.0).unwrap(), pose(100.0, 2.0));
+        assert!(animation.is_finished(4.0));
+
+        // Zooming in by two halves the tangent of the half angle.
+        let end = animation.camera_at(4.0).unwrap();
+        assert!((end.fov.0 - 32.2042).abs() < 1e-3);
+
+        animation.looping = true;
+        assert!(!animation.is_finished(10.0));
+        assert_eq!(animation.sample(5.0).unwrap().position.x, 50.0);
+    }
+}
@@ -1,17 +1,53 @@
-use std::{collections::hash_map::HashMap, rc::Rc, vec::Vec};
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+    ops::Range,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    vec::Vec,
+};
 
 use cgmath::SquareMatrix;
-use glow::HasContext;
 use itertools::izip;
 use ldraw::{
+    color::Rgba,
     color::{ColorReference, Material},
-    document::{Document, MultipartDocument},
-    Matrix4, PartAlias, Vector4,
+    document::MultipartDocument,
+    elements::{Command, Meta, MlcadMode, TexMapStatement},
+    Matrix4, PartAlias, Vector3, Vector4,
+};
+use ldraw_ir::{asset::AssetIndex, geometry::BoundingBox3};
+
+use crate::{
+    backend::{Backend, BufferUsage},
+    part::Part,
+    pbr::{emission, FleckParameters, PbrParameters},
 };
-use ldraw_ir::geometry::BoundingBox3;
 
-use crate::utils::cast_as_bytes;
+// Materials by code, each kept once for instances to share.
+#[derive(Debug, Default)]
+struct MaterialTable {
+    materials: HashMap<u32, Vec<Arc<Material>>>,
+}
 
+impl MaterialTable {
+    fn share(&mut self, material: &Material) -> Arc<Material> {
+        let shared = self.materials.entry(material.code).or_default();
+        match shared.iter().find(|e| ***e == *material) {
+            Some(e) => Arc::clone(e),
+            None => {
+                let material = Arc::new(material.clone());
+                shared.push(Arc::clone(&material));
+                material
+            }
+        }
+    }
+}
+
+// Collects instances of a part to make a `DisplayItem` of at once.
 pub struct DisplayItemBuilder {
     name: PartAlias,
     matrices: Vec<Matrix4>,
@@ -26,45 +62,323 @@ impl DisplayItemBuilder {
             colors: vec![],
         }
     }
+
+    pub fn add_instance(&mut self, matrix: Matrix4, color: ColorReference) {
+        self.matrices.push(matrix);
+        self.colors.push(color);
+    }
+
+    pub fn extend<I: IntoIterator<Item = (Matrix4, ColorReference)>>(&mut self, instances: I) {
+        for (matrix, color) in instances {
+            self.add_instance(matrix, color);
+        }
+    }
+
+    // Display item of the instances added, numbered from 0 in the order
+    // they were added. Instances in the current color get the default
+    // material. See `DisplayList::insert`.
+    pub fn build<GL: Backend>(self, gl: Rc<GL>) -> DisplayItem<GL> {
+        let mut table = MaterialTable::default();
+        let default = table.share(&Material::default());
+        let materials = self
+            .colors
+            .iter()
+            .map(|e| match e {
+                ColorReference::Material(m) => table.share(m),
+                _ => Arc::clone(&default),
+            })
+            .collect::<Vec<_>>();
+
+        let mut item = DisplayItem::new(gl, &self.name);
+        let translucent = materials.iter().filter(|e| e.is_translucent()).count();
+        item.opaque.reserve(materials.len() - translucent);
+        item.translucent.reserve(translucent);
+        for (id, (matrix, material)) in self.matrices.iter().zip(materials.iter()).enumerate() {
+            item.add(InstanceId(id as u32), matrix, material);
+        }
+        item
+    }
 }
 
-pub struct InstanceBuffer<GL: HasContext> {
+// Identifies an instance placed in a `DisplayList`, for as long as the
+// list lasts.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct InstanceId(pub u32);
+
+// Tags instances to show or hide together, like those of a submodel, a
+// step or an MLCad group.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct GroupId(pub u32);
+
+// Which groups of instances are drawn. Instances in no group are drawn
+// unless some groups are isolated.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GroupVisibility {
+    hidden: HashSet<GroupId>,
+    isolated: HashSet<GroupId>,
+}
+
+impl GroupVisibility {
+    pub fn hide(&mut self, group: GroupId) {
+        self.hidden.insert(group);
+    }
+
+    pub fn show(&mut self, group: GroupId) {
+        self.hidden.remove(&group);
+    }
+
+    // Draws nothing but `groups`, less those hidden.
+    pub fn isolate<I: IntoIterator<Item = GroupId>>(&mut self, groups: I) {
+        self.isolated = groups.into_iter().collect();
+    }
+
+    pub fn clear_isolation(&mut self) {
+        self.isolated.clear();
+    }
+
+    pub fn is_visible(&self, group: Option<GroupId>) -> bool {
+        match group {
+            Some(e) => {
+                !self.hidden.contains(&e)
+                    && (self.isolated.is_empty() || self.isolated.contains(&e))
+            }
+            None => self.isolated.is_empty(),
+        }
+    }
+}
+
+// How far instances of each group are moved along their explosion
+// offsets, from 0 in the assembled model to 1 in the exploded one.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GroupExplosion {
+    factor: f32,
+    groups: HashMap<GroupId, f32>,
+}
+
+impl GroupExplosion {
+    // Factor of instances in no group and of groups without one of their
+    // own.
+    pub fn set_factor(&mut self, factor: f32) {
+        self.factor = factor;
+    }
+
+    pub fn set_group_factor(&mut self, group: GroupId, factor: f32) {
+        self.groups.insert(group, factor);
+    }
+
+    pub fn clear_group_factor(&mut self, group: GroupId) {
+        self.groups.remove(&group);
+    }
+
+    pub fn factor(&self, group: Option<GroupId>) -> f32 {
+        group
+            .and_then(|e| self.groups.get(&e))
+            .copied()
+            .unwrap_or(self.factor)
+    }
+}
+
+// How an instance is drawn. Ghosted instances are drawn in a translucent
+// gray, like parts placed in earlier steps of instructions.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum RenderMode {
+    #[default]
+    Normal,
+    Ghosted,
+    Hidden,
+}
+
+impl From<MlcadMode> for RenderMode {
+    fn from(mode: MlcadMode) -> Self {
+        match mode {
+            MlcadMode::Ghost => RenderMode::Ghosted,
+            MlcadMode::Hide => RenderMode::Hidden,
+        }
+    }
+}
+
+// The material ghosted instances are drawn in unless told otherwise.
+pub fn default_ghost_material() -> Material {
+    Material {
+        code: 0,
+        name: String::from("Ghost"),
+        color: Rgba::new(0xa0, 0xa5, 0xa9, 0x40),
+        edge: Rgba::new(0x6d, 0x6e, 0x5c, 0x40),
+        ..Default::default()
+    }
+}
+
+// Distance of `center` placed by `matrix` in front of the viewer.
+fn view_depth(view_matrix: &Matrix4, matrix: &Matrix4, center: &Vector3) -> f32 {
+    -(view_matrix * matrix * center.extend(1.0)).z
+}
+
+// Indices of `matrices` from the farthest instance to the nearest. Ties keep
+// their order.
+fn back_to_front(view_matrix: &Matrix4, matrices: &[Matrix4], center: &Vector3) -> Vec<usize> {
+    let depths = matrices
+        .iter()
+        .map(|e| view_depth(view_matrix, e, center))
+        .collect::<Vec<_>>();
+    let mut order = (0..matrices.len()).collect::<Vec<_>>();
+    order.sort_by(|a, b| depths[*b].total_cmp(&depths[*a]));
+    order
+}
+
+fn flatten<const N: usize, T: AsRef<[f32; N]>>(items: &[T]) -> Vec<f32> {
+    let mut buffer = Vec::with_capacity(items.len() * N);
+    items.iter().for_each(|e| buffer.extend(e.as_ref()));
+    buffer
+}
+
+// Writes `data` into `buffer` from `offset` elements in, or replaces the whole
+// of it without one.
+fn upload<GL: Backend>(
+    gl: &GL,
+    buffer: &mut Option<GL::Buffer>,
+    data: &[f32],
+    offset: Option<usize>,
+) {
+    if buffer.is_none() {
+        *buffer = gl.create_buffer().ok();
+    }
+    match offset {
+        Some(offset) => gl.buffer_sub_data(*buffer, offset, data),
+        None => gl.buffer_data(*buffer, data, BufferUsage::Dynamic),
+    }
+}
+
+// Revisions of instance buffers, unique among all of them.
+static REVISION: AtomicU64 = AtomicU64::new(1);
+
+fn next_revision() -> u64 {
+    REVISION.fetch_add(1, Ordering::Relaxed)
+}
+
+pub struct InstanceBuffer<GL: Backend> {
     gl: Rc<GL>,
 
     pub count: usize,
 
+    pub ids: Vec<InstanceId>,
     pub model_view_matrices: Vec<Matrix4>,
-    pub materials: Vec<Material>,
+    pub materials: Vec<Arc<Material>>,
     pub colors: Vec<Vector4>,
     pub edge_colors: Vec<Vector4>,
+    // `PbrParameters` of the materials.
+    pub finishes: Vec<Vector4>,
+    // `FleckParameters` of the materials.
+    pub flecks: Vec<Vector4>,
+    // Light the materials give off; see `pbr::emission`.
+    pub emissions: Vec<f32>,
+    pub groups: Vec<Option<GroupId>>,
+    pub modes: Vec<RenderMode>,
+    // 1 for instances drawn and 0 for those hidden.
+    pub visibilities: Vec<f32>,
+    // Offsets instances move by in exploded views, with how far along
+    // them their groups are as w.
+    pub explosions: Vec<Vector4>,
 
+    pub id_buffer: Option<GL::Buffer>,
     pub model_view_matrices_buffer: Option<GL::Buffer>,
     pub color_buffer: Option<GL::Buffer>,
     pub edge_color_buffer: Option<GL::Buffer>,
+    pub finish_buffer: Option<GL::Buffer>,
+    pub fleck_buffer: Option<GL::Buffer>,
+    pub emission_buffer: Option<GL::Buffer>,
+    pub visibility_buffer: Option<GL::Buffer>,
+    pub explosion_buffer: Option<GL::Buffer>,
+
+    // Uploads every instance into a second set of buffers while the first
+    // may still be drawn from, alternating between them, instead of
+    // updating buffers in use. For instances that all move every frame.
+    pub streaming: bool,
+    // The set of buffers not drawn from, while streaming.
+    spare: [Option<GL::Buffer>; 9],
 
-    modified: bool,
+    // Instances changed since the last upload, and how many the buffers hold.
+    dirty: Option<Range<usize>>,
+    capacity: usize,
+    // Set when visibilities or explosions change, to upload them alone.
+    visibility_modified: bool,
+    explosion_modified: bool,
+    sorted_view: Option<Matrix4>,
+    layout_revision: u64,
+    placement_revision: u64,
+    // Where each instance is in the vectors above.
+    indices: HashMap<InstanceId, usize>,
 }
 
-impl<GL: HasContext> InstanceBuffer<GL> {
+impl<GL: Backend> InstanceBuffer<GL> {
     pub fn new(gl: Rc<GL>) -> Self {
         InstanceBuffer {
             gl,
 
             count: 0,
 
+            ids: vec![],
             model_view_matrices: vec![],
             materials: vec![],
             colors: vec![],
             edge_colors: vec![],
+            finishes: vec![],
+            flecks: vec![],
+            emissions: vec![],
+            groups: vec![],
+            modes: vec![],
+            visibilities: vec![],
+            explosions: vec![],
 
+            id_buffer: None,
             model_view_matrices_buffer: None,
             color_buffer: None,
             edge_color_buffer: None,
+            finish_buffer: None,
+            fleck_buffer: None,
+            emission_buffer: None,
+            visibility_buffer: None,
+            explosion_buffer: None,
 
-            modified: false,
+            streaming: false,
+            spare: Default::default(),
+
+            dirty: None,
+            capacity: 0,
+            visibility_modified: false,
+            explosion_modified: false,
+            sorted_view: None,
+            layout_revision: next_revision(),
+            placement_revision: next_revision(),
+            indices: HashMap::new(),
         }
     }
 
+    // Finds every instance anew, once `ids` was rewritten as a whole.
+    fn reindex(&mut self) {
+        self.indices = self
+            .ids
+            .iter()
+            .enumerate()
+            .map(|(index, id)| (*id, index))
+            .collect();
+    }
+
+    // Where instance `id` is in the vectors of this buffer, if it is here.
+    pub fn index_of(&self, id: InstanceId) -> Option<usize> {
+        self.indices.get(&id).copied()
+    }
+
+    // Changes whenever instances are added, removed, reordered, shown or
+    // hidden here, and is never the same for two buffers.
+    pub fn layout_revision(&self) -> u64 {
+        self.layout_revision
+    }
+
+    // Changes whenever instances here move, in exploded views too.
+    pub fn placement_revision(&self) -> u64 {
+        self.placement_revision
+    }
+
     pub fn calculate_bounding_box(&self, bounding_box: &BoundingBox3) -> Option<BoundingBox3> {
         let mut bb = BoundingBox3::zero();
 
@@ -86,107 +400,433 @@ impl<GL: HasContext> InstanceBuffer<GL> {
         self.count == 0
     }
 
-    pub fn update_buffer(&mut self, gl: &GL) {
-        if !self.modified {
+    pub fn is_emissive(&self) -> bool {
+        self.emissions.iter().any(|e| *e > 0.0)
+    }
+
+    // Reorders instances from back to front as seen through `view_matrix`,
+    // by where each of them places `center`. Does nothing while neither
+    // the instances nor the view have changed since the last sort.
+    pub fn sort_by_depth(&mut self, view_matrix: &Matrix4, center: &Vector3) {
+        if self.dirty.is_none() && self.sorted_view == Some(*view_matrix) {
             return;
         }
+        self.sorted_view = Some(*view_matrix);
 
-        if self.model_view_matrices.is_empty() {
-            self.model_view_matrices_buffer = None;
-        } else {
-            if self.model_view_matrices_buffer.is_none() {
-                self.model_view_matrices_buffer = unsafe { gl.create_buffer().ok() };
-            }
+        let order = back_to_front(view_matrix, &self.model_view_matrices, center);
+        let first = match order.iter().enumerate().position(|(i, e)| i != *e) {
+            Some(e) => e,
+            None => return,
+        };
+        let last = order
+            .iter()
+            .enumerate()
+            .rposition(|(i, e)| i != *e)
+            .unwrap();
+        self.ids = order.iter().map(|e| self.ids[*e]).collect();
+        self.model_view_matrices = order.iter().map(|e| self.model_view_matrices[*e]).collect();
+        self.materials = order.iter().map(|e| self.materials[*e].clone()).collect();
+        self.colors = order.iter().map(|e| self.colors[*e]).collect();
+        self.edge_colors = order.iter().map(|e| self.edge_colors[*e]).collect();
+        self.finishes = order.iter().map(|e| self.finishes[*e]).collect();
+        self.flecks = order.iter().map(|e| self.flecks[*e]).collect();
+        self.emissions = order.iter().map(|e| self.emissions[*e]).collect();
+        self.groups = order.iter().map(|e| self.groups[*e]).collect();
+        self.modes = order.iter().map(|e| self.modes[*e]).collect();
+        self.visibilities = order.iter().map(|e| self.visibilities[*e]).collect();
+        self.explosions = order.iter().map(|e| self.explosions[*e]).collect();
+        for index in first..last + 1 {
+            self.indices.insert(self.ids[index], index);
+        }
+        self.touch(first..last + 1);
+        self.layout_revision = next_revision();
+    }
 
-            let mut buffer = Vec::<f32>::new();
-            self.model_view_matrices
-                .iter()
-                .for_each(|e| buffer.extend(AsRef::<[f32; 16]>::as_ref(e)));
-
-            unsafe {
-                gl.bind_buffer(glow::ARRAY_BUFFER, self.model_view_matrices_buffer);
-                gl.buffer_data_u8_slice(
-                    glow::ARRAY_BUFFER,
-                    cast_as_bytes(buffer.as_ref()),
-                    glow::DYNAMIC_DRAW,
-                );
-            }
+    // Depth of the farthest instance as seen through `view_matrix`.
+    pub fn farthest_depth(&self, view_matrix: &Matrix4, center: &Vector3) -> Option<f32> {
+        self.model_view_matrices
+            .iter()
+            .map(|e| view_depth(view_matrix, e, center))
+            .reduce(f32::max)
+    }
+
+    pub fn contains(&self, id: InstanceId) -> bool {
+        self.indices.contains_key(&id)
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.ids.reserve(additional);
+        self.model_view_matrices.reserve(additional);
+        self.materials.reserve(additional);
+        self.colors.reserve(additional);
+        self.edge_colors.reserve(additional);
+        self.finishes.reserve(additional);
+        self.flecks.reserve(additional);
+        self.emissions.reserve(additional);
+        self.groups.reserve(additional);
+        self.modes.reserve(additional);
+        self.visibilities.reserve(additional);
+        self.explosions.reserve(additional);
+    }
+
+    // Marks instances `range` to be uploaded.
+    fn touch(&mut self, range: Range<usize>) {
+        self.dirty = Some(match self.dirty.take() {
+            Some(e) => e.start.min(range.start)..e.end.max(range.end),
+            None => range,
+        });
+    }
+
+    pub fn push(&mut self, id: InstanceId, matrix: &Matrix4, material: &Arc<Material>) {
+        self.indices.insert(id, self.ids.len());
+        self.ids.push(id);
+        self.model_view_matrices.push(*matrix);
+        self.materials.push(Arc::clone(material));
+        self.colors.push(Vector4::from(&material.color));
+        self.edge_colors.push(Vector4::from(&material.edge));
+        self.finishes
+            .push(Vector4::from(&PbrParameters::from(&material.finish)));
+        self.flecks
+            .push(Vector4::from(&FleckParameters::from(&material.finish)));
+        self.emissions.push(emission(material));
+        self.groups.push(None);
+        self.modes.push(RenderMode::Normal);
+        self.visibilities.push(1.0);
+        self.explosions.push(Vector4::new(0.0, 0.0, 0.0, 0.0));
+        self.count += 1;
+        self.touch(self.count - 1..self.count);
+        self.layout_revision = next_revision();
+    }
+
+    // Takes out instance `id`, moving the last instance into its place so
+    // the buffers stay packed. Returns its matrix and material, or `None`
+    // if it isn't here.
+    pub fn remove(&mut self, id: InstanceId) -> Option<(Matrix4, Arc<Material>)> {
+        let index = self.indices.remove(&id)?;
+        self.ids.swap_remove(index);
+        if let Some(moved) = self.ids.get(index) {
+            self.indices.insert(*moved, index);
         }
+        let matrix = self.model_view_matrices.swap_remove(index);
+        let material = self.materials.swap_remove(index);
+        self.colors.swap_remove(index);
+        self.edge_colors.swap_remove(index);
+        self.finishes.swap_remove(index);
+        self.flecks.swap_remove(index);
+        self.emissions.swap_remove(index);
+        self.groups.swap_remove(index);
+        self.modes.swap_remove(index);
+        self.visibilities.swap_remove(index);
+        self.explosions.swap_remove(index);
+        self.count -= 1;
+        // Nothing but the instance moved into its place, if any, to upload.
+        self.touch(index..(index + 1).min(self.count));
+        self.layout_revision = next_revision();
+        Some((matrix, material))
+    }
 
-        if self.colors.is_empty() {
-            self.color_buffer = None;
-        } else {
-            if self.color_buffer.is_none() {
-                self.color_buffer = unsafe { gl.create_buffer().ok() };
+    // Places instance `id` by `matrix` instead. Returns whether it is here.
+    pub fn set_matrix(&mut self, id: InstanceId, matrix: &Matrix4) -> bool {
+        match self.index_of(id) {
+            Some(index) => {
+                self.model_view_matrices[index] = *matrix;
+                self.touch(index..index + 1);
+                self.placement_revision = next_revision();
+                true
             }
+            None => false,
+        }
+    }
 
-            let mut buffer = Vec::<f32>::new();
-            self.colors
-                .iter()
-                .for_each(|e| buffer.extend(AsRef::<[f32; 4]>::as_ref(e)));
-
-            unsafe {
-                gl.bind_buffer(glow::ARRAY_BUFFER, self.color_buffer);
-                gl.buffer_data_u8_slice(
-                    glow::ARRAY_BUFFER,
-                    cast_as_bytes(buffer.as_ref()),
-                    glow::DYNAMIC_DRAW,
-                );
-            }
+    // Gives instance `id` `material` instead. Returns whether it is here.
+    // Doesn't move it between opaque and translucent buffers; see
+    // `DisplayItem::set_material`.
+    pub fn set_material(&mut self, id: InstanceId, material: &Arc<Material>) -> bool {
+        let index = match self.index_of(id) {
+            Some(e) => e,
+            None => return false,
+        };
+        self.materials[index] = Arc::clone(material);
+        self.colors[index] = Vector4::from(&material.color);
+        self.edge_colors[index] = Vector4::from(&material.edge);
+        self.finishes[index] = Vector4::from(&PbrParameters::from(&material.finish));
+        self.flecks[index] = Vector4::from(&FleckParameters::from(&material.finish));
+        self.emissions[index] = emission(material);
+        self.touch(index..index + 1);
+        true
+    }
+
+    fn buffers(&mut self) -> [&mut Option<GL::Buffer>; 9] {
+        [
+            &mut self.id_buffer,
+            &mut self.model_view_matrices_buffer,
+            &mut self.color_buffer,
+            &mut self.edge_color_buffer,
+            &mut self.finish_buffer,
+            &mut self.fleck_buffer,
+            &mut self.emission_buffer,
+            &mut self.visibility_buffer,
+            &mut self.explosion_buffer,
+        ]
+    }
+
+    pub fn is_visible(&self, index: usize) -> bool {
+        self.visibilities[index] > 0.0
+    }
+
+    // Placement of instance `index` moved along its explosion offset, as
+    // it is drawn.
+    pub fn exploded_matrix(&self, index: usize) -> Matrix4 {
+        let explosion = self.explosions[index];
+        let mut matrix = self.model_view_matrices[index];
+        matrix.w += (explosion.truncate() * explosion.w).extend(0.0);
+        matrix
+    }
+
+    fn update_explosion(&mut self, index: usize, explosion: &GroupExplosion) {
+        let factor = explosion.factor(self.groups[index]);
+        if self.explosions[index].w != factor {
+            self.explosions[index].w = factor;
+            self.explosion_modified = true;
+            self.placement_revision = next_revision();
         }
+    }
 
-        if self.edge_colors.is_empty() {
-            self.edge_color_buffer = None;
-        } else {
-            if self.edge_color_buffer.is_none() {
-                self.edge_color_buffer = unsafe { gl.create_buffer().ok() };
+    // Moves instance `id` by `offset` in exploded views. Returns whether
+    // it is here.
+    pub fn set_explosion_offset(&mut self, id: InstanceId, offset: &Vector3) -> bool {
+        match self.index_of(id) {
+            Some(index) => {
+                let factor = self.explosions[index].w;
+                self.explosions[index] = offset.extend(factor);
+                self.explosion_modified = true;
+                self.placement_revision = next_revision();
+                true
             }
+            None => false,
+        }
+    }
 
-            let mut buffer = Vec::<f32>::new();
-            self.edge_colors
-                .iter()
-                .for_each(|e| buffer.extend(AsRef::<[f32; 4]>::as_ref(e)));
-
-            unsafe {
-                gl.bind_buffer(glow::ARRAY_BUFFER, self.edge_color_buffer);
-                gl.buffer_data_u8_slice(
-                    glow::ARRAY_BUFFER,
-                    cast_as_bytes(buffer.as_ref()),
-                    glow::DYNAMIC_DRAW,
-                );
+    // Moves instances as far along their offsets as `explosion` has their
+    // groups. Nothing but their explosions is uploaded again.
+    pub fn apply_explosion(&mut self, explosion: &GroupExplosion) {
+        for index in 0..self.count {
+            self.update_explosion(index, explosion);
+        }
+    }
+
+    // Shows instance `index` unless it is hidden or `visibility` hides its
+    // group.
+    fn update_visibility(&mut self, index: usize, visibility: &GroupVisibility) {
+        let visible =
+            self.modes[index] != RenderMode::Hidden && visibility.is_visible(self.groups[index]);
+        let visibility = if visible { 1.0 } else { 0.0 };
+        if self.visibilities[index] != visibility {
+            self.visibilities[index] = visibility;
+            self.visibility_modified = true;
+            self.layout_revision = next_revision();
+        }
+    }
+
+    // Puts instance `id` in `group`, shown or hidden as `visibility` has
+    // it and exploded as `explosion` has it. Returns whether it is here.
+    pub fn set_group(
+        &mut self,
+        id: InstanceId,
+        group: Option<GroupId>,
+        visibility: &GroupVisibility,
+        explosion: &GroupExplosion,
+    ) -> bool {
+        match self.index_of(id) {
+            Some(index) => {
+                self.groups[index] = group;
+                self.update_visibility(index, visibility);
+                self.update_explosion(index, explosion);
+                true
             }
+            None => false,
         }
+    }
 
-        self.modified = false;
+    // Shows and hides instances as `visibility` has their groups. Nothing
+    // but their visibilities is uploaded again.
+    pub fn apply_visibility(&mut self, visibility: &GroupVisibility) {
+        for index in 0..self.count {
+            self.update_visibility(index, visibility);
+        }
     }
-}
 
-impl<GL: HasContext> Drop for InstanceBuffer<GL> {
-    fn drop(&mut self) {
-        let gl = &self.gl;
+    // Draws instance `id` as `mode` has it, without changing its material;
+    // see `DisplayList::set_render_mode`. Returns whether it is here.
+    pub fn set_render_mode(
+        &mut self,
+        id: InstanceId,
+        mode: RenderMode,
+        visibility: &GroupVisibility,
+    ) -> bool {
+        match self.index_of(id) {
+            Some(index) => {
+                self.modes[index] = mode;
+                self.update_visibility(index, visibility);
+                true
+            }
+            None => false,
+        }
+    }
 
-        unsafe {
-            if let Some(b) = self.model_view_matrices_buffer {
+    // Uploads instances changed since the last call. Buffers are
+    // reallocated when they are too small or most instances changed, and
+    // updated in place otherwise. While streaming, everything goes into
+    // the spare set of buffers, which is drawn from from then on.
+    pub fn update_buffer(&mut self, gl: &GL) {
+        if !self.streaming {
+            for b in self.spare.iter_mut().filter_map(Option::take) {
                 gl.delete_buffer(b);
             }
-            if let Some(b) = self.color_buffer {
+        }
+
+        let visibility_modified = mem::take(&mut self.visibility_modified);
+        let explosion_modified = mem::take(&mut self.explosion_modified);
+        let dirty = match self.dirty.take() {
+            Some(e) => e.start.min(self.count)..e.end.min(self.count),
+            None if visibility_modified || explosion_modified => 0..0,
+            None => return,
+        };
+
+        if self.count == 0 {
+            let spare = self
+                .spare
+                .iter_mut()
+                .filter_map(Option::take)
+                .collect::<Vec<_>>();
+            for b in self
+                .buffers()
+                .into_iter()
+                .filter_map(Option::take)
+                .chain(spare)
+            {
                 gl.delete_buffer(b);
             }
-            if let Some(b) = self.edge_color_buffer {
-                gl.delete_buffer(b);
+            self.capacity = 0;
+            return;
+        }
+
+        let (range, offset) = if self.streaming {
+            let mut spare = mem::take(&mut self.spare);
+            for (buffer, e) in self.buffers().into_iter().zip(spare.iter_mut()) {
+                mem::swap(buffer, e);
             }
+            self.spare = spare;
+            self.capacity = self.count;
+            (0..self.count, None)
+        } else if self.count > self.capacity || dirty.len() * 2 > self.count {
+            self.capacity = self.count;
+            (0..self.count, None)
+        } else {
+            (dirty.clone(), Some(dirty.start))
+        };
+
+        // Visibilities may have changed outside of the instances written.
+        if visibility_modified && offset.is_some() {
+            gl.buffer_sub_data(self.visibility_buffer, 0, &self.visibilities);
+        }
+        if explosion_modified && offset.is_some() {
+            gl.buffer_sub_data(self.explosion_buffer, 0, &flatten(&self.explosions));
+        }
+        if range.is_empty() {
+            return;
+        }
+
+        if self.id_buffer.is_none() {
+            self.id_buffer = gl.create_buffer().ok();
+        }
+        let ids = self.ids[range.clone()]
+            .iter()
+            .map(|e| e.0)
+            .collect::<Vec<_>>();
+        match offset {
+            Some(offset) => gl.buffer_sub_data_u32(self.id_buffer, offset, &ids),
+            None => gl.buffer_data_u32(self.id_buffer, &ids, BufferUsage::Dynamic),
+        }
+
+        upload(
+            gl,
+            &mut self.model_view_matrices_buffer,
+            &flatten(&self.model_view_matrices[range.clone()]),
+            offset.map(|e| e * 16),
+        );
+        upload(
+            gl,
+            &mut self.color_buffer,
+            &flatten(&self.colors[range.clone()]),
+            offset.map(|e| e * 4),
+        );
+        upload(
+            gl,
+            &mut self.edge_color_buffer,
+            &flatten(&self.edge_colors[range.clone()]),
+            offset.map(|e| e * 4),
+        );
+        upload(
+            gl,
+            &mut self.finish_buffer,
+            &flatten(&self.finishes[range.clone()]),
+            offset.map(|e| e * 4),
+        );
+        upload(
+            gl,
+            &mut self.fleck_buffer,
+            &flatten(&self.flecks[range.clone()]),
+            offset.map(|e| e * 4),
+        );
+        upload(
+            gl,
+            &mut self.emission_buffer,
+            &self.emissions[range.clone()],
+            offset,
+        );
+        upload(
+            gl,
+            &mut self.visibility_buffer,
+            &self.visibilities[range.clone()],
+            offset,
+        );
+        upload(
+            gl,
+            &mut self.explosion_buffer,
+            &flatten(&self.explosions[range]),
+            offset.map(|e| e * 4),
+        );
+    }
+}
+
+impl<GL: Backend> Drop for InstanceBuffer<GL> {
+    fn drop(&mut self) {
+        let gl = Rc::clone(&self.gl);
+        let spare = self
+            .spare
+            .iter_mut()
+            .filter_map(Option::take)
+            .collect::<Vec<_>>();
+        for b in self
+            .buffers()
+            .into_iter()
+            .filter_map(Option::take)
+            .chain(spare)
+        {
+            gl.delete_buffer(b);
         }
     }
 }
 
-pub struct DisplayItem<GL: HasContext> {
+pub struct DisplayItem<GL: Backend> {
     pub part: PartAlias,
 
     pub opaque: InstanceBuffer<GL>,
     pub translucent: InstanceBuffer<GL>,
 }
 
-impl<GL: HasContext> DisplayItem<GL> {
+impl<GL: Backend> DisplayItem<GL> {
     pub fn new(gl: Rc<GL>, alias: &PartAlias) -> Self {
         DisplayItem {
             part: alias.clone(),
@@ -200,18 +840,27 @@ impl<GL: HasContext> DisplayItem<GL> {
     pub fn update_data(
         &mut self,
         opaque: bool,
+        ids: &[InstanceId],
         model_view_matrices: &[Matrix4],
-        materials: &[Material],
+        materials: &[Arc<Material>],
     ) {
+        let mut new_ids = vec![];
         let mut new_model_view_matrices = vec![];
         let mut new_materials = vec![];
         let mut new_colors = vec![];
         let mut new_edge_colors = vec![];
-        for (model_view_matrix, material) in izip!(model_view_matrices, materials) {
+        let mut new_finishes = vec![];
+        let mut new_flecks = vec![];
+        let mut new_emissions = vec![];
+        for (id, model_view_matrix, material) in izip!(ids, model_view_matrices, materials) {
+            new_ids.push(*id);
             new_model_view_matrices.push(*model_view_matrix);
-            new_materials.push(material.clone());
+            new_materials.push(Arc::clone(material));
             new_colors.push(material.color.into());
             new_edge_colors.push(material.edge.into());
+            new_finishes.push(Vector4::from(&PbrParameters::from(&material.finish)));
+            new_flecks.push(Vector4::from(&FleckParameters::from(&material.finish)));
+            new_emissions.push(emission(material));
         }
 
         let buffer = if opaque {
@@ -220,35 +869,155 @@ impl<GL: HasContext> DisplayItem<GL> {
             &mut self.translucent
         };
 
+        buffer.ids = new_ids;
         buffer.model_view_matrices = new_model_view_matrices;
         buffer.materials = new_materials;
         buffer.colors = new_colors;
         buffer.edge_colors = new_edge_colors;
-        buffer.count = model_view_matrices.len();
-        buffer.modified = true;
+        buffer.finishes = new_finishes;
+        buffer.flecks = new_flecks;
+        buffer.emissions = new_emissions;
+        buffer.groups = vec![None; buffer.ids.len()];
+        buffer.modes = vec![RenderMode::Normal; buffer.ids.len()];
+        buffer.visibilities = vec![1.0; buffer.ids.len()];
+        buffer.explosions = vec![Vector4::new(0.0, 0.0, 0.0, 0.0); buffer.ids.len()];
+        buffer.count = buffer.ids.len();
+        buffer.reindex();
+        buffer.touch(0..buffer.count);
+        buffer.layout_revision = next_revision();
+    }
+
+    // Bounds of every instance of a part with the given bounds.
+    pub fn calculate_bounding_box(&self, bounding_box: &BoundingBox3) -> Option<BoundingBox3> {
+        let mut bb = BoundingBox3::zero();
+        for e in [&self.opaque, &self.translucent] {
+            if let Some(ibb) = e.calculate_bounding_box(bounding_box) {
+                bb.update(&ibb);
+            }
+        }
+
+        if bb.is_null() {
+            None
+        } else {
+            Some(bb)
+        }
     }
 
-    pub fn add(&mut self, matrix: &Matrix4, material: &Material) {
+    pub fn add(&mut self, id: InstanceId, matrix: &Matrix4, material: &Arc<Material>) {
         let buffer = if material.is_translucent() {
             &mut self.translucent
         } else {
             &mut self.opaque
         };
 
-        buffer.model_view_matrices.push(*matrix);
-        buffer.materials.push(material.clone());
-        buffer.colors.push(Vector4::from(&material.color));
-        buffer.edge_colors.push(Vector4::from(&material.edge));
-        buffer.count += 1;
-        buffer.modified = true;
+        buffer.push(id, matrix, material);
+    }
+
+    pub fn contains(&self, id: InstanceId) -> bool {
+        self.opaque.contains(id) || self.translucent.contains(id)
+    }
+
+    pub fn set_streaming(&mut self, streaming: bool) {
+        self.opaque.streaming = streaming;
+        self.translucent.streaming = streaming;
+    }
+
+    pub fn remove(&mut self, id: InstanceId) -> Option<(Matrix4, Arc<Material>)> {
+        self.opaque
+            .remove(id)
+            .or_else(|| self.translucent.remove(id))
+    }
+
+    pub fn set_matrix(&mut self, id: InstanceId, matrix: &Matrix4) -> bool {
+        self.opaque.set_matrix(id, matrix) || self.translucent.set_matrix(id, matrix)
+    }
+
+    // Gives instance `id` `material` instead, moving it to the other buffer
+    // if it turns translucent or opaque. Returns whether it is here.
+    pub fn set_material(&mut self, id: InstanceId, material: &Arc<Material>) -> bool {
+        let (from, to) = if material.is_translucent() {
+            (&mut self.opaque, &mut self.translucent)
+        } else {
+            (&mut self.translucent, &mut self.opaque)
+        };
+        if to.set_material(id, material) {
+            return true;
+        }
+        let index = match from.index_of(id) {
+            Some(e) => e,
+            None => return false,
+        };
+        let (group, mode, visibility, explosion) = (
+            from.groups[index],
+            from.modes[index],
+            from.visibilities[index],
+            from.explosions[index],
+        );
+        let (matrix, _) = from.remove(id).unwrap();
+        to.push(id, &matrix, material);
+        let index = to.count - 1;
+        to.groups[index] = group;
+        to.modes[index] = mode;
+        to.visibilities[index] = visibility;
+        to.explosions[index] = explosion;
+        to.layout_revision = next_revision();
+        true
+    }
+
+    pub fn set_group(
+        &mut self,
+        id: InstanceId,
+        group: Option<GroupId>,
+        visibility: &GroupVisibility,
+        explosion: &GroupExplosion,
+    ) -> bool {
+        self.opaque.set_group(id, group, visibility, explosion)
+            || self.translucent.set_group(id, group, visibility, explosion)
+    }
+
+    pub fn apply_visibility(&mut self, visibility: &GroupVisibility) {
+        self.opaque.apply_visibility(visibility);
+        self.translucent.apply_visibility(visibility);
+    }
+
+    pub fn set_explosion_offset(&mut self, id: InstanceId, offset: &Vector3) -> bool {
+        self.opaque.set_explosion_offset(id, offset)
+            || self.translucent.set_explosion_offset(id, offset)
+    }
+
+    pub fn apply_explosion(&mut self, explosion: &GroupExplosion) {
+        self.opaque.apply_explosion(explosion);
+        self.translucent.apply_explosion(explosion);
+    }
+
+    pub fn set_render_mode(
+        &mut self,
+        id: InstanceId,
+        mode: RenderMode,
+        visibility: &GroupVisibility,
+    ) -> bool {
+        self.opaque.set_render_mode(id, mode, visibility)
+            || self.translucent.set_render_mode(id, mode, visibility)
     }
 }
 
-pub struct DisplayList<GL: HasContext> {
+pub struct DisplayList<GL: Backend> {
     pub map: HashMap<PartAlias, DisplayItem<GL>>,
+
+    next_id: u32,
+    streaming: bool,
+    materials: MaterialTable,
+    visibility: GroupVisibility,
+    explosion: GroupExplosion,
+    ghost: Arc<Material>,
+    // Materials of ghosted instances, given back when they are drawn
+    // normally again.
+    ghosted: HashMap<InstanceId, Arc<Material>>,
+    // Part of each instance, to find it without going through every item.
+    parts: HashMap<InstanceId, PartAlias>,
 }
 
-impl<GL: HasContext> DisplayList<GL> {
+impl<GL: Backend> DisplayList<GL> {
     pub fn count(&self) -> usize {
         let mut count = 0;
 
@@ -258,82 +1027,838 @@ impl<GL: HasContext> DisplayList<GL> {
 
         count
     }
+
+    // Bounds of the whole model, or `None` if nothing is placed.
+    pub fn calculate_bounding_box(
+        &self,
+        parts: &HashMap<PartAlias, Part<GL>>,
+    ) -> Option<BoundingBox3> {
+        self.calculate_selection_bounding_box(parts, self.map.keys())
+    }
+
+    // Bounds of every instance of the selected parts.
+    pub fn calculate_selection_bounding_box<'a, I: IntoIterator<Item = &'a PartAlias>>(
+        &self,
+        parts: &HashMap<PartAlias, Part<GL>>,
+        selection: I,
+    ) -> Option<BoundingBox3> {
+        let mut bb = BoundingBox3::zero();
+        for alias in selection {
+            let ibb = match (self.map.get(alias), parts.get(alias)) {
+                (Some(item), Some(part)) => item.calculate_bounding_box(&part.bounding_box),
+                _ => None,
+            };
+            if let Some(ibb) = ibb {
+                bb.update(&ibb);
+            }
+        }
+
+        if bb.is_null() {
+            None
+        } else {
+            Some(bb)
+        }
+    }
 }
 
-impl<GL: HasContext> Default for DisplayList<GL> {
+impl<GL: Backend> Default for DisplayList<GL> {
     fn default() -> Self {
         DisplayList {
             map: HashMap::new(),
+            next_id: 0,
+            streaming: false,
+            materials: MaterialTable::default(),
+            visibility: GroupVisibility::default(),
+            explosion: GroupExplosion::default(),
+            ghost: Arc::new(default_ghost_material()),
+            ghosted: HashMap::new(),
+            parts: HashMap::new(),
         }
     }
 }
 
-fn build_display_list<'a, GL: HasContext>(
-    gl: Rc<GL>,
-    display_list: &mut DisplayList<GL>,
-    document: &'a Document,
+// Parts `commands` place, submodels of `parent` included, as the alias,
+// placement, material and render mode of each instance. TEXMAP fallback
+// geometry is left out as `0 !:` lines are drawn instead.
+pub(crate) fn collect_placements<'a>(
+    commands: &'a [Command],
     matrix: Matrix4,
-    material_stack: &mut Vec<Material>,
+    material: &'a Material,
+    mode: RenderMode,
     parent: &'a MultipartDocument,
+    placements: &mut Vec<(&'a PartAlias, Matrix4, &'a Material, RenderMode)>,
 ) {
-    for e in document.iter_refs() {
-        if parent.subparts.contains_key(&e.name) {
-            material_stack.push(match &e.color {
-                ColorReference::Material(m) => m.clone(),
-                _ => material_stack.last().unwrap().clone(),
-            });
-
-            build_display_list(
-                Rc::clone(&gl),
-                display_list,
-                parent.subparts.get(&e.name).unwrap(),
+    let mut texmap = false;
+    let mut in_fallback = false;
+    for command in commands {
+        match command {
+            Command::Meta(Meta::TexMap(TexMapStatement::Start(_))) => texmap = true,
+            Command::Meta(Meta::TexMap(TexMapStatement::Fallback)) => in_fallback = texmap,
+            Command::Meta(Meta::TexMap(TexMapStatement::End)) => {
+                texmap = false;
+                in_fallback = false;
+            }
+            _ => {}
+        }
+        let (e, prefix) = match command.part_reference() {
+            Some(e) if !in_fallback => e,
+            _ => continue,
+        };
+        let mode = prefix.map_or(mode, |e| mode.max(e.into()));
+        let material = match &e.color {
+            ColorReference::Material(m) => m,
+            _ => material,
+        };
+        match parent.subparts.get(&e.name) {
+            Some(subpart) => collect_placements(
+                &subpart.commands,
                 matrix * e.matrix,
-                material_stack,
+                material,
+                mode,
                 parent,
-            );
-
-            material_stack.pop();
-        } else {
-            let material = match &e.color {
-                ColorReference::Material(m) => m,
-                _ => material_stack.last().unwrap(),
-            };
-
-            display_list.add(
-                Rc::clone(&gl),
-                e.name.clone(),
-                matrix * e.matrix,
-                material.clone(),
-            );
+                placements,
+            ),
+            None => placements.push((&e.name, matrix * e.matrix, material, mode)),
         }
     }
 }
 
-impl<GL: HasContext> DisplayList<GL> {
+impl<GL: Backend> DisplayList<GL> {
+    // Instances of every part `document` places, ghosted or hidden as MLCad
+    // prefixes have them.
     pub fn from_multipart_document(gl: Rc<GL>, document: &MultipartDocument) -> Self {
-        let mut display_list = DisplayList::default();
-        let mut material_stack = vec![Material::default()];
-
-        build_display_list(
-            gl,
-            &mut display_list,
-            &document.body,
+        let default = Material::default();
+        let mut placements = vec![];
+        collect_placements(
+            &document.body.commands,
             Matrix4::identity(),
-            &mut material_stack,
+            &default,
+            RenderMode::Normal,
             document,
+            &mut placements,
         );
 
+        let mut display_list = DisplayList::default();
+        for (alias, matrix, material, mode) in placements {
+            let material = display_list.share(material);
+            let id = display_list.add_shared(Rc::clone(&gl), alias.clone(), matrix, material);
+            if mode != RenderMode::Normal {
+                display_list.set_render_mode(id, mode);
+            }
+        }
         display_list
     }
 
-    pub fn add(&mut self, gl: Rc<GL>, name: PartAlias, matrix: Matrix4, material: Material) {
-        self.map
-            .entry(name.clone())
-            .or_insert_with(|| DisplayItem::new(Rc::clone(&gl), &name))
-            .add(&matrix, &material);
+    // The scene stored in an asset file. Instances in the current color get
+    // the default material.
+    pub fn from_asset(gl: Rc<GL>, index: &AssetIndex) -> Self {
+        let mut display_list = DisplayList::default();
+        for instance in index.instances.iter() {
+            let material = match &instance.color {
+                ColorReference::Material(m) => display_list.share(m),
+                _ => display_list.share(&Material::default()),
+            };
+            display_list.add_shared(
+                Rc::clone(&gl),
+                index.parts[instance.part].alias.clone(),
+                instance.matrix,
+                material,
+            );
+        }
+        display_list
+    }
+
+    // The copy of `material` instances placed in it share, made the first
+    // time it is asked for.
+    pub fn share(&mut self, material: &Material) -> Arc<Material> {
+        self.materials.share(material)
+    }
+
+    pub fn add(
+        &mut self,
+        gl: Rc<GL>,
+        name: PartAlias,
+        matrix: Matrix4,
+        material: Material,
+    ) -> InstanceId {
+        let material = self.share(&material);
+        self.add_shared(gl, name, matrix, material)
+    }
+
+    // Places an instance like `add`, with a material other instances may
+    // share already.
+    pub fn add_shared(
+        &mut self,
+        gl: Rc<GL>,
+        name: PartAlias,
+        matrix: Matrix4,
+        material: Arc<Material>,
+    ) -> InstanceId {
+        let id = InstanceId(self.next_id);
+        self.next_id += 1;
+        let streaming = self.streaming;
+        let item = self.map.entry(name.clone()).or_insert_with(|| {
+            let mut item = DisplayItem::new(Rc::clone(&gl), &name);
+            item.set_streaming(streaming);
+            item
+        });
+        item.add(id, &matrix, &material);
+        if !self.visibility.is_visible(None) || self.explosion.factor(None) != 0.0 {
+            item.set_group(id, None, &self.visibility, &self.explosion);
+        }
+        self.parts.insert(id, name);
+        id
+    }
+
+    // Places every instance of `item`, numbered after those placed already
+    // in the order `item` numbers them. Returns their IDs, in that order.
+    pub fn insert(&mut self, mut item: DisplayItem<GL>) -> Vec<InstanceId> {
+        let base = self.next_id;
+        let mut ids = vec![];
+        for buffer in [&mut item.opaque, &mut item.translucent] {
+            for id in buffer.ids.iter_mut() {
+                id.0 += base;
+                ids.push(*id);
+            }
+            buffer.reindex();
+            buffer.touch(0..buffer.count);
+            buffer.layout_revision = next_revision();
+        }
+        ids.sort();
+        if let Some(last) = ids.last() {
+            self.next_id = last.0 + 1;
+        }
+        self.parts
+            .extend(ids.iter().map(|id| (*id, item.part.clone())));
+
+        match self.map.get_mut(&item.part) {
+            Some(existing) => {
+                for (from, to) in [
+                    (&item.opaque, &mut existing.opaque),
+                    (&item.translucent, &mut existing.translucent),
+                ] {
+                    to.reserve(from.count);
+                    for (id, matrix, material, group, explosion) in izip!(
+                        &from.ids,
+                        &from.model_view_matrices,
+                        &from.materials,
+                        &from.groups,
+                        &from.explosions
+                    ) {
+                        to.push(*id, matrix, material);
+                        to.set_group(*id, *group, &self.visibility, &self.explosion);
+                        to.set_explosion_offset(*id, &explosion.truncate());
+                    }
+                }
+            }
+            None => {
+                item.set_streaming(self.streaming);
+                item.apply_visibility(&self.visibility);
+                item.apply_explosion(&self.explosion);
+                self.map.insert(item.part.clone(), item);
+            }
+        }
+        ids
+    }
+
+    // Puts instance `id` in `group`, or in none. Returns whether it is
+    // placed.
+    pub fn set_group(&mut self, id: InstanceId, group: Option<GroupId>) -> bool {
+        let (visibility, explosion) = (&self.visibility, &self.explosion);
+        self.parts
+            .get(&id)
+            .and_then(|e| self.map.get_mut(e))
+            .is_some_and(|e| e.set_group(id, group, visibility, explosion))
+    }
+
+    pub fn group_visibility(&self) -> &GroupVisibility {
+        &self.visibility
+    }
+
+    // Shows and hides instances by their groups as `visibility` has them,
+    // leaving the rest of their data as is.
+    pub fn set_group_visibility(&mut self, visibility: GroupVisibility) {
+        for item in self.map.values_mut() {
+            item.apply_visibility(&visibility);
+        }
+        self.visibility = visibility;
+    }
+
+    pub fn group_explosion(&self) -> &GroupExplosion {
+        &self.explosion
+    }
+
+    // Moves instances along their explosion offsets as far as
+    // `explosion` has their groups, leaving the rest of their data as is.
+    pub fn set_group_explosion(&mut self, explosion: GroupExplosion) {
+        for item in self.map.values_mut() {
+            item.apply_explosion(&explosion);
+        }
+        self.explosion = explosion;
+    }
+
+    // Moves instance `id` by `offset` at full explosion. Returns whether
+    // it is placed.
+    pub fn set_explosion_offset(&mut self, id: InstanceId, offset: Vector3) -> bool {
+        self.item_mut(id)
+            .is_some_and(|e| e.set_explosion_offset(id, &offset))
+    }
+
+    // Streams instance data of every part placed, and those placed later;
+    // see `InstanceBuffer::streaming`.
+    pub fn set_streaming(&mut self, streaming: bool) {
+        self.streaming = streaming;
+        for item in self.map.values_mut() {
+            item.set_streaming(streaming);
+        }
+    }
+
+    // Takes out instance `id`. Returns whether it was placed.
+    pub fn remove(&mut self, id: InstanceId) -> bool {
+        self.ghosted.remove(&id);
+        let removed = self.item_mut(id).is_some_and(|e| e.remove(id).is_some());
+        self.parts.remove(&id);
+        removed
+    }
+
+    // Places instance `id` by `matrix` instead. Returns whether it is
+    // placed.
+    pub fn set_matrix(&mut self, id: InstanceId, matrix: Matrix4) -> bool {
+        self.item_mut(id).is_some_and(|e| e.set_matrix(id, &matrix))
+    }
+
+    // Gives instance `id` `material` instead. Returns whether it is placed.
+    pub fn set_material(&mut self, id: InstanceId, material: Material) -> bool {
+        let material = self.share(&material);
+        self.set_shared_material(id, material)
+    }
+
+    // Like `set_material`, with a material other instances may share
+    // already.
+    pub fn set_shared_material(&mut self, id: InstanceId, material: Arc<Material>) -> bool {
+        // Ghosted instances take it once they are drawn normally again.
+        if let Some(original) = self.ghosted.get_mut(&id) {
+            *original = material;
+            return true;
+        }
+        self.item_mut(id)
+            .is_some_and(|e| e.set_material(id, &material))
+    }
+
+    fn item_mut(&mut self, id: InstanceId) -> Option<&mut DisplayItem<GL>> {
+        self.map.get_mut(self.parts.get(&id)?)
+    }
+
+    // Buffer holding instance `id`, and where in it.
+    fn find(&self, id: InstanceId) -> Option<(&InstanceBuffer<GL>, usize)> {
+        let item = self.map.get(self.parts.get(&id)?)?;
+        [&item.opaque, &item.translucent]
+            .into_iter()
+            .find_map(|buffer| Some((buffer, buffer.index_of(id)?)))
+    }
+
+    // How instance `id` is drawn, or `None` if it isn't placed.
+    pub fn render_mode(&self, id: InstanceId) -> Option<RenderMode> {
+        self.find(id).map(|(buffer, index)| buffer.modes[index])
+    }
+
+    // Draws instance `id` as `mode` has it. Ghosted instances are drawn in
+    // the ghost material until they are drawn otherwise, when they get
+    // their own back. Returns whether it is placed.
+    pub fn set_render_mode(&mut self, id: InstanceId, mode: RenderMode) -> bool {
+        let (current, material) = match self.find(id) {
+            Some((buffer, index)) => (buffer.modes[index], Arc::clone(&buffer.materials[index])),
+            None => return false,
+        };
+        if current == mode {
+            return true;
+        }
+
+        let material = match (current, mode) {
+            (_, RenderMode::Ghosted) => {
+                self.ghosted.insert(id, material);
+                Some(Arc::clone(&self.ghost))
+            }
+            (RenderMode::Ghosted, _) => self.ghosted.remove(&id),
+            _ => None,
+        };
+
+        let item = self.map.get_mut(&self.parts[&id]).unwrap();
+        if let Some(material) = &material {
+            item.set_material(id, material);
+        }
+        item.set_render_mode(id, mode, &self.visibility)
+    }
+
+    pub fn ghost_material(&self) -> &Material {
+        &self.ghost
+    }
+
+    // Draws ghosted instances in `material`, those ghosted already
+    // included.
+    pub fn set_ghost_material(&mut self, material: Material) {
+        self.ghost = self.share(&material);
+        for id in self.ghosted.keys() {
+            if let Some(item) = self.map.get_mut(&self.parts[id]) {
+                item.set_material(*id, &self.ghost);
+            }
+        }
     }
 
     pub fn clear(&mut self) {
         self.map.clear();
+        self.materials = MaterialTable::default();
+        self.ghosted.clear();
+        self.parts.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{rc::Rc, sync::Arc};
+
+    use cgmath::{Deg, Matrix4, Point3, SquareMatrix};
+
+    use ldraw::{
+        color::{ColorReference, Material, Rgba},
+        PartAlias, Vector3, Vector4,
+    };
+
+    use crate::{scene::Scene, state::PerspectiveCamera, test_support::RecordingBackend};
+
+    use super::{
+        back_to_front, default_ghost_material, DisplayItemBuilder, DisplayList, GroupId,
+        GroupVisibility, InstanceId, RenderMode,
+    };
+
+    #[test]
+    fn test_back_to_front() {
+        let camera = PerspectiveCamera::new(
+            Point3::new(0.0, 0.0, -100.0),
+            Point3::new(0.0, 0.0, 0.0),
+            Deg(45.0),
+        );
+        let view_matrix = camera.derive_view_matrix();
+        let matrices = [0.0, 50.0, -50.0, 50.0]
+            .into_iter()
+            .map(|z| Matrix4::from_translation(Vector3::new(0.0, 0.0, z)))
+            .collect::<Vec<_>>();
+
+        let center = Vector3::new(0.0, 0.0, 0.0);
+        assert_eq!(
+            back_to_front(&view_matrix, &matrices, &center),
+            vec![1, 3, 0, 2]
+        );
+
+        // Seen from the other side.
+        let camera = PerspectiveCamera::new(
+            Point3::new(0.0, 0.0, 100.0),
+            Point3::new(0.0, 0.0, 0.0),
+            Deg(45.0),
+        );
+        let view_matrix = camera.derive_view_matrix();
+        assert_eq!(
+            back_to_front(&view_matrix, &matrices, &center),
+            vec![2, 0, 1, 3]
+        );
+    }
+
+    #[test]
+    fn test_instance_editing() {
+        let alias = PartAlias::from("a.dat");
+        let translucent = Material {
+            color: Rgba::new(255, 0, 0, 128),
+            ..Default::default()
+        };
+
+        let gl = Rc::new(RecordingBackend::default());
+        {
+            let mut display_list = DisplayList::default();
+            let ids = [0.0, 1.0, 2.0].map(|x| {
+                display_list.add(
+                    Rc::clone(&gl),
+                    alias.clone(),
+                    Matrix4::from_translation(Vector3::new(x, 0.0, 0.0)),
+                    Material::default(),
+                )
+            });
+            let item = display_list.map.get_mut(&alias).unwrap();
+            item.opaque.update_buffer(&gl);
+            let buffers = gl.live.borrow().len();
+            assert!(buffers > 0);
+            assert!(Arc::ptr_eq(
+                &item.opaque.materials[0],
+                &item.opaque.materials[2]
+            ));
+
+            // The last instance fills the gap.
+            assert!(display_list.remove(ids[0]));
+            assert!(!display_list.remove(ids[0]));
+            let item = display_list.map.get_mut(&alias).unwrap();
+            assert_eq!(item.opaque.ids, vec![ids[2], ids[1]]);
+            assert_eq!(item.opaque.count, 2);
+            assert_eq!(item.opaque.index_of(ids[2]), Some(0));
+            assert_eq!(item.opaque.index_of(ids[0]), None);
+            assert_eq!(
+                item.opaque.model_view_matrices[0],
+                Matrix4::from_translation(Vector3::new(2.0, 0.0, 0.0))
+            );
+
+            let moved = Matrix4::from_translation(Vector3::new(5.0, 0.0, 0.0));
+            assert!(display_list.set_matrix(ids[1], moved));
+            assert!(!display_list.set_matrix(ids[0], moved));
+            let item = display_list.map.get_mut(&alias).unwrap();
+            assert_eq!(item.opaque.model_view_matrices[1], moved);
+
+            // Turning translucent moves it to the other buffer, matrix and all.
+            assert!(display_list.set_material(ids[1], translucent.clone()));
+            let item = display_list.map.get_mut(&alias).unwrap();
+            assert_eq!(item.opaque.ids, vec![ids[2]]);
+            assert_eq!(item.translucent.ids, vec![ids[1]]);
+            assert_eq!(item.opaque.index_of(ids[1]), None);
+            assert_eq!(item.translucent.index_of(ids[1]), Some(0));
+            assert_eq!(item.translucent.model_view_matrices, vec![moved]);
+            assert_eq!(
+                item.translucent.colors,
+                vec![Vector4::from(&translucent.color)]
+            );
+            assert!(display_list.set_material(ids[1], translucent));
+            assert_eq!(display_list.count(), 2);
+
+            // Buffers emptied out are let go of.
+            assert!(display_list.remove(ids[2]));
+            let item = display_list.map.get_mut(&alias).unwrap();
+            assert!(item.opaque.is_empty());
+            item.opaque.update_buffer(&gl);
+            assert!(item.opaque.model_view_matrices_buffer.is_none());
+            assert!(gl.live.borrow().is_empty());
+        }
+        assert!(gl.live.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_partial_uploads() {
+        let alias = PartAlias::from("a.dat");
+        let gl = Rc::new(RecordingBackend::default());
+        {
+            let mut display_list = DisplayList::default();
+            let ids = (0..10)
+                .map(|_| {
+                    display_list.add(
+                        Rc::clone(&gl),
+                        alias.clone(),
+                        Matrix4::identity(),
+                        Material::default(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            let update = |display_list: &mut DisplayList<RecordingBackend>| {
+                gl.uploads.borrow_mut().clear();
+                gl.sub_uploads.borrow_mut().clear();
+                let item = display_list.map.get_mut(&alias).unwrap();
+                item.opaque.update_buffer(&gl);
+                (gl.uploads.take(), gl.sub_uploads.take())
+            };
+            assert_eq!(
+                update(&mut display_list).0,
+                vec![160, 40, 40, 40, 40, 10, 10, 40]
+            );
+            assert_eq!(update(&mut display_list), (vec![], vec![]));
+
+            // One moved instance is written in place.
+            let moved = Matrix4::from_translation(Vector3::new(5.0, 0.0, 0.0));
+            display_list.set_matrix(ids[3], moved);
+            let (uploads, sub_uploads) = update(&mut display_list);
+            assert!(uploads.is_empty());
+            assert_eq!(sub_uploads[0], (48, 16));
+
+            // So is the one filling the gap of a removed one.
+            display_list.remove(ids[0]);
+            let (uploads, sub_uploads) = update(&mut display_list);
+            assert!(uploads.is_empty());
+            assert_eq!(sub_uploads[0], (0, 16));
+
+            // Removing the last one leaves nothing to write.
+            display_list.remove(ids[8]);
+            assert_eq!(update(&mut display_list), (vec![], vec![]));
+
+            // Most of them changing, or more than fit, reallocates.
+            for id in &ids[1..7] {
+                display_list.set_matrix(*id, moved);
+            }
+            assert_eq!(update(&mut display_list).0.len(), 8);
+            display_list.add(
+                Rc::clone(&gl),
+                alias.clone(),
+                Matrix4::identity(),
+                Material::default(),
+            );
+            let (uploads, sub_uploads) = update(&mut display_list);
+            assert_eq!(uploads[0], 9 * 16);
+            assert!(sub_uploads.is_empty());
+        }
+        assert!(gl.live.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_group_visibility() {
+        let alias = PartAlias::from("a.dat");
+        let translucent = Material {
+            color: Rgba::new(255, 0, 0, 128),
+            ..Default::default()
+        };
+
+        let gl = Rc::new(RecordingBackend::default());
+        {
+            let mut display_list = DisplayList::default();
+            let ids = (0..4)
+                .map(|_| {
+                    display_list.add(
+                        Rc::clone(&gl),
+                        alias.clone(),
+                        Matrix4::identity(),
+                        Material::default(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            for (id, group) in ids.iter().zip([0, 0, 1]) {
+                assert!(display_list.set_group(*id, Some(GroupId(group))));
+            }
+            let visible = |display_list: &DisplayList<RecordingBackend>| {
+                let item = display_list.map.get(&alias).unwrap();
+                ids.iter()
+                    .map(|id| {
+                        [&item.opaque, &item.translucent]
+                            .into_iter()
+                            .find_map(|buffer| {
+                                let index = buffer.ids.iter().position(|e| e == id)?;
+                                Some(buffer.is_visible(index))
+                            })
+                            .unwrap()
+                    })
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(visible(&display_list), vec![true; 4]);
+
+            let item = display_list.map.get_mut(&alias).unwrap();
+            item.opaque.update_buffer(&gl);
+
+            let mut visibility = GroupVisibility::default();
+            visibility.hide(GroupId(0));
+            display_list.set_group_visibility(visibility.clone());
+            assert_eq!(visible(&display_list), vec![false, false, true, true]);
+
+            // Only the visibilities are written again.
+            gl.uploads.borrow_mut().clear();
+            gl.sub_uploads.borrow_mut().clear();
+            let item = display_list.map.get_mut(&alias).unwrap();
+            item.opaque.update_buffer(&gl);
+            assert!(gl.uploads.borrow().is_empty());
+            assert_eq!(*gl.sub_uploads.borrow(), vec![(0, 4)]);
+
+            // Isolating leaves out ungrouped instances as well.
+            visibility.show(GroupId(0));
+            visibility.isolate([GroupId(1)]);
+            display_list.set_group_visibility(visibility.clone());
+            assert_eq!(visible(&display_list), vec![false, false, true, false]);
+
+            // Instances keep their groups across buffers.
+            assert!(display_list.set_material(ids[2], translucent));
+            assert_eq!(visible(&display_list), vec![false, false, true, false]);
+            visibility.clear_isolation();
+            visibility.hide(GroupId(1));
+            display_list.set_group_visibility(visibility);
+            assert_eq!(visible(&display_list), vec![true, true, false, true]);
+        }
+        assert!(gl.live.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_streaming_uploads() {
+        let alias = PartAlias::from("a.dat");
+        let gl = Rc::new(RecordingBackend::default());
+        {
+            let mut display_list = DisplayList::default();
+            display_list.set_streaming(true);
+            let ids = (0..10)
+                .map(|_| {
+                    display_list.add(
+                        Rc::clone(&gl),
+                        alias.clone(),
+                        Matrix4::identity(),
+                        Material::default(),
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            // Every frame goes whole into the set of buffers not drawn last.
+            let mut drawn = vec![];
+            for frame in 0..3 {
+                let moved = Matrix4::from_translation(Vector3::new(frame as f32, 0.0, 0.0));
+                display_list.set_matrix(ids[0], moved);
+                gl.uploads.borrow_mut().clear();
+                let item = display_list.map.get_mut(&alias).unwrap();
+                item.opaque.update_buffer(&gl);
+                assert_eq!(gl.uploads.borrow()[0], 160);
+                assert!(gl.sub_uploads.borrow().is_empty());
+                drawn.push(item.opaque.model_view_matrices_buffer.unwrap());
+            }
+            assert_ne!(drawn[0], drawn[1]);
+            assert_eq!(drawn[0], drawn[2]);
+            let streamed = gl.live.borrow().len();
+
+            // Spare buffers go once streaming stops.
+            display_list.set_streaming(false);
+            let item = display_list.map.get_mut(&alias).unwrap();
+            item.opaque.update_buffer(&gl);
+            assert_eq!(gl.live.borrow().len(), streamed - 9);
+            assert_eq!(item.opaque.model_view_matrices_buffer, Some(drawn[2]));
+        }
+        assert!(gl.live.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_display_item_builder() {
+        let alias = PartAlias::from("a.dat");
+        let translucent = Material {
+            color: Rgba::new(255, 0, 0, 128),
+            ..Default::default()
+        };
+        let placed = |x: f32| Matrix4::from_translation(Vector3::new(x, 0.0, 0.0));
+
+        let mut builder = DisplayItemBuilder::new(alias.clone());
+        builder.add_instance(placed(0.0), ColorReference::Current);
+        builder.extend([
+            (placed(1.0), ColorReference::Material(translucent.clone())),
+            (placed(2.0), ColorReference::Material(Material::default())),
+            (placed(3.0), ColorReference::Material(translucent.clone())),
+        ]);
+
+        let gl = Rc::new(RecordingBackend::default());
+        {
+            let item = builder.build(Rc::clone(&gl));
+            assert_eq!(item.opaque.ids, vec![InstanceId(0), InstanceId(2)]);
+            assert_eq!(item.translucent.ids, vec![InstanceId(1), InstanceId(3)]);
+            assert_eq!(item.opaque.model_view_matrices[1], placed(2.0));
+            assert!(Arc::ptr_eq(
+                &item.opaque.materials[0],
+                &item.opaque.materials[1]
+            ));
+            assert!(Arc::ptr_eq(
+                &item.translucent.materials[0],
+                &item.translucent.materials[1]
+            ));
+
+            // Numbered after what is placed already, joining its instances.
+            let mut display_list = DisplayList::default();
+            let first = display_list.add(
+                Rc::clone(&gl),
+                alias.clone(),
+                placed(9.0),
+                Material::default(),
+            );
+            let ids = display_list.insert(item);
+            assert_eq!(ids, (1..5).map(InstanceId).collect::<Vec<_>>());
+            assert_eq!(display_list.count(), 5);
+            let item = &display_list.map[&alias];
+            assert_eq!(item.opaque.ids, vec![first, ids[0], ids[2]]);
+            assert_eq!(item.translucent.model_view_matrices[1], placed(3.0));
+
+            let next = display_list.add(
+                Rc::clone(&gl),
+                PartAlias::from("b.dat"),
+                placed(0.0),
+                Material::default(),
+            );
+            assert_eq!(next, InstanceId(5));
+        }
+        assert!(gl.live.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_render_modes() {
+        let alias = PartAlias::from("a.dat");
+        let red = Material {
+            code: 4,
+            color: Rgba::new(255, 0, 0, 255),
+            ..Default::default()
+        };
+
+        let gl = Rc::new(RecordingBackend::default());
+        {
+            let mut display_list = DisplayList::default();
+            let ids = (0..2)
+                .map(|_| {
+                    display_list.add(
+                        Rc::clone(&gl),
+                        alias.clone(),
+                        Matrix4::identity(),
+                        Material::default(),
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            // Ghosted instances are drawn translucent in the ghost material.
+            assert!(display_list.set_render_mode(ids[0], RenderMode::Ghosted));
+            assert_eq!(display_list.render_mode(ids[0]), Some(RenderMode::Ghosted));
+            let item = &display_list.map[&alias];
+            assert_eq!(item.translucent.ids, vec![ids[0]]);
+            assert_eq!(*item.translucent.materials[0], default_ghost_material());
+
+            // And get their own material back, changed or not, afterwards.
+            assert!(display_list.set_material(ids[0], red.clone()));
+            assert_eq!(display_list.map[&alias].translucent.ids, vec![ids[0]]);
+            assert!(display_list.set_render_mode(ids[0], RenderMode::Normal));
+            let item = &display_list.map[&alias];
+            assert!(item.translucent.is_empty());
+            assert_eq!(*item.opaque.materials[1], red);
+
+            // Hidden instances stay hidden whatever their groups.
+            display_list.set_group(ids[1], Some(GroupId(0)));
+            assert!(display_list.set_render_mode(ids[1], RenderMode::Hidden));
+            assert!(!display_list.map[&alias].opaque.is_visible(0));
+            display_list.set_group_visibility(GroupVisibility::default());
+            assert!(!display_list.map[&alias].opaque.is_visible(0));
+            assert!(display_list.set_render_mode(ids[1], RenderMode::Normal));
+            assert!(display_list.map[&alias].opaque.is_visible(0));
+            assert!(!display_list.set_render_mode(InstanceId(2), RenderMode::Hidden));
+        }
+        assert!(gl.live.borrow().is_empty());
+
+        // Scene nodes are drawn as the most ghosted or hidden of their
+        // parents have them.
+        let mut scene = Scene::default();
+        let root = scene.root();
+        let group = scene.add_group(root, Matrix4::identity(), ColorReference::Current);
+        let part = scene.add_part(
+            group,
+            alias.clone(),
+            Matrix4::identity(),
+            ColorReference::Current,
+        );
+        scene.set_render_mode(group, RenderMode::Ghosted);
+        {
+            let mut display_list = DisplayList::default();
+            scene.sync(Rc::clone(&gl), &mut display_list);
+            let instance = scene.node(part).unwrap().instance().unwrap();
+            assert_eq!(
+                display_list.render_mode(instance),
+                Some(RenderMode::Ghosted)
+            );
+
+            scene.set_render_mode(part, RenderMode::Hidden);
+            scene.sync(Rc::clone(&gl), &mut display_list);
+            assert_eq!(display_list.render_mode(instance), Some(RenderMode::Hidden));
+
+            scene.set_render_mode(part, RenderMode::Normal);
+            scene.set_render_mode(group, RenderMode::Normal);
+            scene.sync(Rc::clone(&gl), &mut display_list);
+            assert_eq!(display_list.render_mode(instance), Some(RenderMode::Normal));
+            assert_eq!(
+                *display_list.map[&alias].opaque.materials[0],
+                Material::default()
+            );
+        }
+        assert!(gl.live.borrow().is_empty());
     }
 }
@@ -0,0 +1,358 @@
+use std::{f32::consts::PI, io::BufRead, sync::OnceLock};
+
+use cgmath::prelude::*;
+use image::{codecs::hdr::HdrDecoder, ImageResult};
+use ldraw::Vector3;
+
+// Specular mipmaps an `Environment` has, for roughness from 0 to 1.
+pub const SPECULAR_LEVELS: usize = 6;
+
+const SPECULAR_WIDTH: u32 = 256;
+
+// Rougher levels are blurred from radiance at most this wide, as are the
+// spherical harmonics. Only the sharpest level keeps finer detail.
+const CONVOLUTION_WIDTH: u32 = 128;
+
+// Lobe weights smaller than this are left out of the blur.
+const LOBE_CUTOFF: f32 = 1e-4;
+
+const STUDIO_WIDTH: u32 = 64;
+
+// Soft boxes of the studio as longitude, latitude, width and height in
+// degrees, and radiance.
+const SOFT_BOXES: [(f32, f32, f32, f32, f32); 3] = [
+    (-60.0, 30.0, 50.0, 30.0, 8.0),
+    (120.0, 20.0, 40.0, 25.0, 3.0),
+    (0.0, 80.0, 360.0, 20.0, 2.0),
+];
+
+static STUDIO: OnceLock<Environment> = OnceLock::new();
+
+struct Equirectangular {
+    width: u32,
+    height: u32,
+    pixels: Vec<Vector3>,
+}
+
+impl Equirectangular {
+    fn latitude(&self, y: u32) -> f32 {
+        (0.5 - (y as f32 + 0.5) / self.height as f32) * PI
+    }
+
+    // Through the center of texel (x, y), with y up.
+    fn direction(&self, x: u32, y: u32) -> Vector3 {
+        let longitude = ((x as f32 + 0.5) / self.width as f32 - 0.5) * 2.0 * PI;
+        let latitude = self.latitude(y);
+        Vector3::new(
+            latitude.cos() * longitude.cos(),
+            latitude.sin(),
+            latitude.cos() * longitude.sin(),
+        )
+    }
+
+    fn solid_angle(&self, y: u32) -> f32 {
+        self.latitude(y).cos() * (2.0 * PI / self.width as f32) * (PI / self.height as f32)
+    }
+
+    // Averages the texels each target texel covers, or repeats the nearest
+    // one when enlarging.
+    fn resample(&self, width: u32, height: u32) -> Self {
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            let y0 = y * self.height / height;
+            let y1 = ((y + 1) * self.height / height).max(y0 + 1);
+            for x in 0..width {
+                let x0 = x * self.width / width;
+                let x1 = ((x + 1) * self.width / width).max(x0 + 1);
+                let mut sum = Vector3::zero();
+                for sy in y0..y1 {
+                    for sx in x0..x1 {
+                        sum += self.pixels[(sy * self.width + sx) as usize];
+                    }
+                }
+                pixels.push(sum / ((x1 - x0) * (y1 - y0)) as f32);
+            }
+        }
+
+        Equirectangular {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    fn texels(&self) -> impl Iterator<Item = (Vector3, f32, Vector3)> + '_ {
+        (0..self.height).flat_map(move |y| {
+            let solid_angle = self.solid_angle(y);
+            (0..self.width).map(move |x| {
+                (
+                    self.direction(x, y),
+                    solid_angle,
+                    self.pixels[(y * self.width + x) as usize],
+                )
+            })
+        })
+    }
+}
+
+fn sh_basis(d: &Vector3) -> [f32; 9] {
+    [
+        0.282095,
+        0.488603 * d.y,
+        0.488603 * d.z,
+        0.488603 * d.x,
+        1.092548 * d.x * d.y,
+        1.092548 * d.y * d.z,
+        0.315392 * (3.0 * d.z * d.z - 1.0),
+        1.092548 * d.x * d.z,
+        0.546274 * (d.x * d.x - d.y * d.y),
+    ]
+}
+
+fn to_rgba(pixels: &[Vector3]) -> Vec<f32> {
+    pixels.iter().flat_map(|e| [e.x, e.y, e.z, 1.0]).collect()
+}
+
+// Image based lighting prefiltered from an equirectangular HDR image,
+// whose top row looks straight up. Directions here have y up, unlike
+// LDraw.
+#[derive(Clone, Debug)]
+pub struct Environment {
+    // Size of the first specular level.
+    pub width: u32,
+    pub height: u32,
+    // RGBA radiance reflected off increasingly rough surfaces, each level
+    // half the size of the last.
+    pub specular: Vec<Vec<f32>>,
+    // Incoming radiance as spherical harmonics of order 2.
+    pub irradiance: [Vector3; 9],
+}
+
+impl Environment {
+    // Reads a Radiance HDR image.
+    pub fn from_hdr<R: BufRead>(reader: R) -> ImageResult<Self> {
+        let decoder = HdrDecoder::new(reader)?;
+        let metadata = decoder.metadata();
+        let pixels = decoder
+            .read_image_hdr()?
+            .into_iter()
+            .map(|e| Vector3::new(e[0], e[1], e[2]))
+            .collect::<Vec<_>>();
+
+        Ok(Environment::from_equirectangular(
+            metadata.width,
+            metadata.height,
+            &pixels,
+        ))
+    }
+
+    pub fn from_equirectangular(width: u32, height: u32, pixels: &[Vector3]) -> Self {
+        assert_eq!(pixels.len(), (width * height) as usize);
+        let source = Equirectangular {
+            width,
+            height,
+            pixels: pixels.to_vec(),
+        };
+        let convolution_width = width.min(CONVOLUTION_WIDTH);
+        let convolution_height = (convolution_width / 2).max(1);
+        let convolution = source
+            .resample(convolution_width, convolution_height)
+            .texels()
+            .collect::<Vec<_>>();
+        let row = |latitude: f32| (0.5 - latitude / PI) * convolution_height as f32;
+
+        let mut irradiance = [Vector3::zero(); 9];
+        for (direction, solid_angle, radiance) in convolution.iter() {
+            for (coefficient, basis) in irradiance.iter_mut().zip(sh_basis(direction)) {
+                *coefficient += radiance * (basis * solid_angle);
+            }
+        }
+
+        let mut specular = vec![to_rgba(
+            &source.resample(SPECULAR_WIDTH, SPECULAR_WIDTH / 2).pixels,
+        )];
+        for level in 1..SPECULAR_LEVELS {
+            let roughness = level as f32 / (SPECULAR_LEVELS - 1) as f32;
+            // Phong lobe about as wide as the GGX one of the roughness.
+            let exponent = (2.0 / roughness.powi(4) - 2.0).max(0.0);
+            let cutoff = if exponent > 0.0 {
+                LOBE_CUTOFF.powf(1.0 / exponent)
+            } else {
+                0.0
+            };
+            let lobe_angle = cutoff.acos();
+
+            let mut target = Equirectangular {
+                width: SPECULAR_WIDTH >> level,
+                height: SPECULAR_WIDTH >> (level + 1),
+                pixels: Vec::new(),
+            };
+            for y in 0..target.height {
+                // Only rows within the lobe in latitude can fall in it. A
+                // row more on each side keeps the nearest texel in range.
+                let latitude = target.latitude(y);
+                let top = (row(latitude + lobe_angle) - 1.0).max(0.0) as u32;
+                let bottom = (row(latitude - lobe_angle) + 1.0)
+                    .ceil()
+                    .min(convolution_height as f32) as u32;
+                let band = &convolution
+                    [(top * convolution_width) as usize..(bottom * convolution_width) as usize];
+                for x in 0..target.width {
+                    let normal = target.direction(x, y);
+                    let mut sum = Vector3::zero();
+                    let mut weights = 0.0;
+                    let mut nearest = (-1.0, Vector3::zero());
+                    for (direction, solid_angle, radiance) in band.iter() {
+                        let d = normal.dot(*direction);
+                        if d > cutoff {
+                            let weight = d.powf(exponent) * solid_angle;
+                            sum += radiance * weight;
+                            weights += weight;
+                        }
+                        if d > nearest.0 {
+                            nearest = (d, *radiance);
+                        }
+                    }
+                    // Lobes can fall between texels of small images.
+                    target.pixels.push(if weights > 0.0 {
+                        sum / weights
+                    } else {
+                        nearest.1
+                    });
+                }
+            }
+            specular.push(to_rgba(&target.pixels));
+        }
+
+        Environment {
+            width: SPECULAR_WIDTH,
+            height: SPECULAR_WIDTH / 2,
+            specular,
+            irradiance,
+        }
+    }
+
+    // A photo studio lit by a bright soft box in the upper front left, a
+    // dimmer one in the back right and the ceiling, over a dark floor.
+    // Gives metallic finishes something to reflect by default.
+    pub fn studio() -> &'static Environment {
+        STUDIO.get_or_init(|| {
+            let mut image = Equirectangular {
+                width: STUDIO_WIDTH,
+                height: STUDIO_WIDTH / 2,
+                pixels: Vec::new(),
+            };
+            for y in 0..image.height {
+                let latitude = image.latitude(y).to_degrees();
+                for x in 0..image.width {
+                    let longitude = ((x as f32 + 0.5) / image.width as f32 - 0.5) * 360.0;
+                    let mut radiance = if latitude > 0.0 {
+                        0.3 + 0.3 * latitude / 90.0
+                    } else {
+                        0.08
+                    };
+                    for (center_x, center_y, width, height, value) in SOFT_BOXES {
+                        if (longitude - center_x).abs() * 2.0 <= width
+                            && (latitude - center_y).abs() * 2.0 <= height
+                        {
+                            radiance = value;
+                        }
+                    }
+                    image
+                        .pixels
+                        .push(Vector3::new(radiance, radiance, radiance));
+                }
+            }
+
+            Environment::from_equirectangular(image.width, image.height, &image.pixels)
+        })
+    }
+
+    // Irradiance of a surface facing `normal`, as the shaders evaluate it.
+    pub fn irradiance_at(&self, normal: &Vector3) -> Vector3 {
+        let (x, y, z) = (normal.x, normal.y, normal.z);
+        let c = &self.irradiance;
+        c[0] * 0.886227
+            + c[1] * (2.0 * 0.511664 * y)
+            + c[2] * (2.0 * 0.511664 * z)
+            + c[3] * (2.0 * 0.511664 * x)
+            + c[4] * (2.0 * 0.429043 * x * y)
+            + c[5] * (2.0 * 0.429043 * y * z)
+            + c[6] * (0.743125 * z * z - 0.247708)
+            + c[7] * (2.0 * 0.429043 * x * z)
+            + c[8] * (0.429043 * (x * x - y * y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use ldraw::Vector3;
+
+    use super::{Environment, SPECULAR_LEVELS};
+
+    #[test]
+    fn test_uniform_environment() {
+        let radiance = Vector3::new(2.0, 1.0, 0.5);
+        let environment = Environment::from_equirectangular(16, 8, &[radiance; 128]);
+
+        assert_eq!(environment.specular.len(), SPECULAR_LEVELS);
+        for (level, pixels) in environment.specular.iter().enumerate() {
+            assert_eq!(pixels.len(), (256 >> level) * (128 >> level) * 4);
+            for pixel in pixels.chunks(4) {
+                assert!((pixel[0] - 2.0).abs() < 1e-3);
+                assert!((pixel[2] - 0.5).abs() < 1e-3);
+                assert_eq!(pixel[3], 1.0);
+            }
+        }
+        for normal in [
+            Vector3::unit_x(),
+            Vector3::unit_y(),
+            -Vector3::unit_z(),
+            Vector3::new(0.6, -0.8, 0.0),
+        ] {
+            let irradiance = environment.irradiance_at(&normal);
+            assert!((irradiance.x / (radiance.x * PI) - 1.0).abs() < 0.03);
+            assert!((irradiance.y / (radiance.y * PI) - 1.0).abs() < 0.03);
+        }
+    }
+
+    #[test]
+    fn test_sky_environment() {
+        let pixels = (0..128)
+            .map(|i| {
+                if i < 64 {
+                    Vector3::new(1.0, 1.0, 1.0)
+                } else {
+                    Vector3::new(0.0, 0.0, 0.0)
+                }
+            })
+            .collect::<Vec<_>>();
+        let environment = Environment::from_equirectangular(16, 8, &pixels);
+
+        assert!(environment.irradiance_at(&Vector3::unit_y()).x > 3.0);
+        assert!(environment.irradiance_at(&-Vector3::unit_y()).x < 0.15);
+        let sharp = &environment.specular[0];
+        assert_eq!(sharp[0], 1.0);
+        assert_eq!(sharp[sharp.len() - 4], 0.0);
+        // The roughest level mixes both halves at the horizon.
+        let rough = &environment.specular[SPECULAR_LEVELS - 1];
+        let horizon = rough[(rough.len() / 2) - 4];
+        assert!(horizon > 0.2 && horizon < 0.8);
+    }
+
+    #[test]
+    fn test_studio_environment() {
+        let studio = Environment::studio();
+        assert_eq!(studio.specular.len(), SPECULAR_LEVELS);
+        assert!(
+            studio.irradiance_at(&Vector3::unit_y()).x
+                > studio.irradiance_at(&-Vector3::unit_y()).x * 2.0
+        );
+        // Chrome sees the soft boxes sharply, rough surfaces only a blur.
+        let brightest = |pixels: &Vec<f32>| pixels.iter().step_by(4).copied().fold(0.0, f32::max);
+        assert_eq!(brightest(&studio.specular[0]), 8.0);
+        assert!(brightest(&studio.specular[SPECULAR_LEVELS - 1]) < 4.0);
+    }
+}
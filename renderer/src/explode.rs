@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+
+use ldraw::PartAlias;
+use ldraw_ir::explode::ExplodedPart;
+
+use crate::{
+    backend::Backend,
+    camera::Easing,
+    display_list::{DisplayList, GroupId},
+};
+
+// Gives instances of `display_list` the offsets `parts` move by, matching
+// each part to an instance of it placed the same. Returns how many
+// instances got one.
+pub fn apply_offsets<GL: Backend>(
+    display_list: &mut DisplayList<GL>,
+    parts: &[ExplodedPart],
+) -> usize {
+    let mut by_alias: HashMap<&PartAlias, Vec<Option<&ExplodedPart>>> = HashMap::new();
+    for e in parts {
+        by_alias.entry(&e.alias).or_default().push(Some(e));
+    }
+
+    let mut offsets = vec![];
+    for (alias, item) in display_list.map.iter() {
+        let parts = match by_alias.get_mut(alias) {
+            Some(e) => e,
+            None => continue,
+        };
+        for buffer in [&item.opaque, &item.translucent] {
+            for (id, matrix) in buffer.ids.iter().zip(buffer.model_view_matrices.iter()) {
+                // Each part goes to one instance, even where several overlap.
+                let part = parts
+                    .iter_mut()
+                    .find(|e| e.is_some_and(|e| e.matrix == *matrix))
+                    .and_then(Option::take);
+                if let Some(part) = part {
+                    offsets.push((*id, part.offset));
+                }
+            }
+        }
+    }
+
+    for (id, offset) in offsets.iter() {
+        display_list.set_explosion_offset(*id, *offset);
+    }
+    offsets.len()
+}
+
+#[derive(Clone, Debug)]
+struct Transition {
+    group: Option<GroupId>,
+    // Factor at the start, taken on the first update.
+    from: Option<f32>,
+    to: f32,
+    duration: f32,
+    easing: Easing,
+    started_at: Option<f32>,
+}
+
+// Animates the explosion factors of a `DisplayList` between the assembled
+// (0) and the exploded (1) model, a group at a time or all at once.
+#[derive(Clone, Debug, Default)]
+pub struct ExplodedView {
+    transitions: Vec<Transition>,
+}
+
+impl ExplodedView {
+    // Moves `group`, or with `None` every instance without a factor of its
+    // group's own, to `factor` over `duration` seconds from the next call
+    // to `update`. A transition of the group under way carries on from
+    // where it is.
+    pub fn animate(&mut self, group: Option<GroupId>, factor: f32, duration: f32, easing: Easing) {
+        self.transitions.retain(|e| e.group != group);
+        self.transitions.push(Transition {
+            group,
+            from: None,
+            to: factor,
+            duration,
+            easing,
+            started_at: None,
+        });
+    }
+
+    // Moves everything out to the exploded model.
+    pub fn explode(&mut self, duration: f32) {
+        self.animate(None, 1.0, duration, Easing::EaseInOut);
+    }
+
+    // Moves everything back to the assembled model.
+    pub fn assemble(&mut self, duration: f32) {
+        self.animate(None, 0.0, duration, Easing::EaseInOut);
+    }
+
+    pub fn is_animating(&self) -> bool {
+        !self.transitions.is_empty()
+    }
+
+    // Sets the factors of `display_list` to where they are `time` seconds
+    // on the caller's clock into the transitions under way. Returns whether
+    // any goes on.
+    pub fn update<GL: Backend>(&mut self, display_list: &mut DisplayList<GL>, time: f32) -> bool {
+        if self.transitions.is_empty() {
+            return false;
+        }
+        let mut explosion = display_list.group_explosion().clone();
+        self.transitions.retain_mut(|e| {
+            let from = *e.from.get_or_insert(explosion.factor(e.group));
+            let elapsed = time - *e.started_at.get_or_insert(time);
+            let t = if e.duration > 0.0 {
+                e.easing.apply(elapsed / e.duration)
+            } else {
+                1.0
+            };
+            let factor = from + (e.to - from) * t;
+            match e.group {
+                Some(group) => explosion.set_group_factor(group, factor),
+                None => explosion.set_factor(factor),
+            }
+            elapsed < e.duration
+        });
+        display_list.set_group_explosion(explosion);
+        self.is_animating()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use cgmath::Matrix4;
+
+    use ldraw::{
+        color::{Material, Rgba},
+        PartAlias, Vector3,
+    };
+    use ldraw_ir::explode::ExplodedPart;
+
+    use crate::{
+        camera::Easing,
+        display_list::{DisplayList, GroupExplosion, GroupId, InstanceId},
+        test_support::RecordingBackend,
+    };
+
+    use super::{apply_offsets, ExplodedView};
+
+    #[test]
+    fn test_exploded_view() {
+        let alias = PartAlias::from("a.dat");
+        let placed = |x: f32| Matrix4::from_translation(Vector3::new(x, 0.0, 0.0));
+        let parts = [0.0, 20.0].map(|x| ExplodedPart {
+            alias: alias.clone(),
+            matrix: placed(x),
+            step: 0,
+            offset: Vector3::new(x, -10.0, 0.0),
+        });
+
+        let gl = Rc::new(RecordingBackend::default());
+        {
+            let mut display_list = DisplayList::default();
+            let ids = [20.0, 0.0].map(|x| {
+                display_list.add(
+                    Rc::clone(&gl),
+                    alias.clone(),
+                    placed(x),
+                    Material::default(),
+                )
+            });
+            assert_eq!(apply_offsets(&mut display_list, &parts), 2);
+            assert!(display_list.set_group(ids[0], Some(GroupId(0))));
+            let exploded = |display_list: &DisplayList<RecordingBackend>, id: InstanceId| {
+                let buffer = &display_list.map[&alias].opaque;
+                let index = buffer.ids.iter().position(|e| *e == id).unwrap();
+                buffer.exploded_matrix(index)
+            };
+            assert_eq!(exploded(&display_list, ids[0]), placed(20.0));
+            display_list
+                .map
+                .get_mut(&alias)
+                .unwrap()
+                .opaque
+                .update_buffer(&gl);
+
+            let mut view = ExplodedView::default();
+            view.animate(None, 1.0, 1.0, Easing::Linear);
+            assert!(view.update(&mut display_list, 2.0));
+            assert!(view.update(&mut display_list, 2.5));
+            assert_eq!(
+                exploded(&display_list, ids[0]),
+                Matrix4::from_translation(Vector3::new(30.0, -5.0, 0.0))
+            );
+
+            // Only the explosions are written again.
+            gl.uploads.borrow_mut().clear();
+            gl.sub_uploads.borrow_mut().clear();
+            display_list
+                .map
+                .get_mut(&alias)
+                .unwrap()
+                .opaque
+                .update_buffer(&gl);
+            assert!(gl.uploads.borrow().is_empty());
+            assert_eq!(*gl.sub_uploads.borrow(), vec![(0, 8)]);
+
+            assert!(!view.update(&mut display_list, 3.0));
+            assert_eq!(
+                exploded(&display_list, ids[1]),
+                Matrix4::from_translation(Vector3::new(0.0, -10.0, 0.0))
+            );
+
+            // Groups move on their own.
+            view.animate(Some(GroupId(0)), 0.0, 0.0, Easing::Linear);
+            assert!(!view.update(&mut display_list, 4.0));
+            assert_eq!(exploded(&display_list, ids[0]), placed(20.0));
+            assert_eq!(display_list.group_explosion().factor(None), 1.0);
+
+            // Instances keep their offsets across buffers.
+            let translucent = Material {
+                color: Rgba::new(255, 0, 0, 128),
+                ..Default::default()
+            };
+            assert!(display_list.set_material(ids[1], translucent));
+            let buffer = &display_list.map[&alias].translucent;
+            assert_eq!(
+                buffer.exploded_matrix(0),
+                Matrix4::from_translation(Vector3::new(0.0, -10.0, 0.0))
+            );
+
+            let mut explosion = GroupExplosion::default();
+            explosion.set_group_factor(GroupId(0), 1.0);
+            display_list.set_group_explosion(explosion);
+            assert_eq!(
+                exploded(&display_list, ids[0]),
+                Matrix4::from_translation(Vector3::new(40.0, -10.0, 0.0))
+            );
+        }
+        assert!(gl.live.borrow().is_empty());
+    }
+}
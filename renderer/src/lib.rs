@@ -1,7 +1,21 @@
+pub mod backend;
+pub mod camera;
 pub mod display_list;
+pub mod environment;
 pub mod error;
+pub mod explode;
+pub mod light;
 pub mod model;
 pub mod part;
+pub mod pbr;
+pub mod pipeline;
+pub mod raycast;
+pub mod scene;
+pub mod section;
 pub mod shader;
 pub mod state;
+pub mod steps;
+#[cfg(test)]
+pub(crate) mod test_support;
+pub mod texture;
 pub mod utils;
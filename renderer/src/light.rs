@@ -0,0 +1,243 @@
+use std::rc::Rc;
+
+use cgmath::{prelude::*, Deg, Point3};
+use ldraw::Vector3;
+
+use crate::backend::{Backend, BufferUsage};
+
+// Most lights the shaders take; the rest of a `Lights` is ignored.
+pub const MAX_LIGHTS: usize = 8;
+
+// Uniform buffer binding point of the `Lights` block.
+pub const LIGHTS_BINDING: u32 = 0;
+
+// Four vec4s per light in std140 layout.
+const LIGHT_STRIDE: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightKind {
+    // Shines along `direction` everywhere, like the sun.
+    Directional { direction: Vector3 },
+    // Shines in all directions from `position`, falling off with the
+    // square of the distance and fading out completely at `range`. A
+    // range of zero never cuts the light off.
+    Point { position: Point3<f32>, range: f32 },
+    // A point light shining along `direction`, fading out from
+    // `inner_angle` to `outer_angle` off its axis.
+    Spot {
+        position: Point3<f32>,
+        direction: Vector3,
+        range: f32,
+        inner_angle: Deg<f32>,
+        outer_angle: Deg<f32>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    pub color: Vector3,
+    pub intensity: f32,
+}
+
+impl Light {
+    pub fn directional(direction: Vector3, color: Vector3, intensity: f32) -> Self {
+        Light {
+            kind: LightKind::Directional {
+                direction: direction.normalize(),
+            },
+            color,
+            intensity,
+        }
+    }
+
+    pub fn point(position: Point3<f32>, range: f32, color: Vector3, intensity: f32) -> Self {
+        Light {
+            kind: LightKind::Point { position, range },
+            color,
+            intensity,
+        }
+    }
+
+    fn write_std140(&self, casts_shadow: bool, out: &mut [f32]) {
+        let radiance = self.color * self.intensity;
+        out[0..3].copy_from_slice(AsRef::<[f32; 3]>::as_ref(&radiance));
+        let (kind, position, range, direction, cone) = match self.kind {
+            LightKind::Directional { direction } => {
+                (1.0, Point3::origin(), 0.0, direction, (1.0, 1.0))
+            }
+            LightKind::Point { position, range } => {
+                (2.0, position, range, Vector3::zero(), (1.0, 1.0))
+            }
+            LightKind::Spot {
+                position,
+                direction,
+                range,
+                inner_angle,
+                outer_angle,
+            } => (
+                3.0,
+                position,
+                range,
+                direction.normalize(),
+                (inner_angle.cos(), outer_angle.cos()),
+            ),
+        };
+        out[3] = kind;
+        out[4..7].copy_from_slice(AsRef::<[f32; 3]>::as_ref(&position));
+        out[7] = range;
+        out[8..11].copy_from_slice(AsRef::<[f32; 3]>::as_ref(&direction));
+        out[11] = if casts_shadow { 1.0 } else { 0.0 };
+        out[12] = cone.0;
+        out[13] = cone.1;
+    }
+}
+
+// Lights shading opaque and translucent parts, on top of the environment
+// map. Directions and positions are in world space.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lights {
+    pub lights: Vec<Light>,
+}
+
+impl Default for Lights {
+    fn default() -> Self {
+        Lights::three_point()
+    }
+}
+
+impl Lights {
+    // Leaves only the environment map.
+    pub fn none() -> Self {
+        Lights { lights: Vec::new() }
+    }
+
+    // A key light from the upper front left, a dimmer fill from the right
+    // and a rim light from behind, all white.
+    pub fn three_point() -> Self {
+        let white = Vector3::new(1.0, 1.0, 1.0);
+        Lights {
+            lights: vec![
+                Light::directional(Vector3::new(-0.3, 1.0, 0.5), white, 1.5),
+                Light::directional(Vector3::new(0.8, 0.3, 0.6), white, 0.5),
+                Light::directional(Vector3::new(0.2, 0.6, -1.0), white, 0.8),
+            ],
+        }
+    }
+
+    // The light shadow maps are cast from: the first directional one.
+    pub fn shadow_caster(&self) -> Option<usize> {
+        self.lights
+            .iter()
+            .take(MAX_LIGHTS)
+            .position(|e| matches!(e.kind, LightKind::Directional { .. }))
+    }
+
+    // Contents of the `Lights` uniform block; unused slots are zero.
+    pub fn to_std140(&self) -> Vec<f32> {
+        let mut data = vec![0.0; MAX_LIGHTS * LIGHT_STRIDE];
+        let shadow_caster = self.shadow_caster();
+        for ((index, light), out) in self
+            .lights
+            .iter()
+            .enumerate()
+            .zip(data.chunks_mut(LIGHT_STRIDE))
+        {
+            light.write_std140(shadow_caster == Some(index), out);
+        }
+        data
+    }
+}
+
+// Uniform buffer holding the last `Lights` uploaded.
+pub struct LightsBuffer<GL: Backend> {
+    gl: Rc<GL>,
+
+    buffer: GL::Buffer,
+    uploaded: Option<Lights>,
+}
+
+impl<GL: Backend> LightsBuffer<GL> {
+    pub fn new(gl: Rc<GL>) -> Result<Self, String> {
+        let buffer = gl.create_buffer()?;
+        gl.bind_uniform_buffer(LIGHTS_BINDING, Some(buffer));
+
+        Ok(LightsBuffer {
+            gl,
+            buffer,
+            uploaded: None,
+        })
+    }
+
+    // Uploads `lights` unless they are already in the buffer.
+    pub fn update(&mut self, lights: &Lights) {
+        if self.uploaded.as_ref() == Some(lights) {
+            return;
+        }
+        self.gl
+            .buffer_data(Some(self.buffer), &lights.to_std140(), BufferUsage::Dynamic);
+        self.gl
+            .bind_uniform_buffer(LIGHTS_BINDING, Some(self.buffer));
+        self.uploaded = Some(lights.clone());
+    }
+}
+
+impl<GL: Backend> Drop for LightsBuffer<GL> {
+    fn drop(&mut self) {
+        self.gl.delete_buffer(self.buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, Point3};
+    use ldraw::Vector3;
+
+    use super::{Light, LightKind, Lights, LIGHT_STRIDE, MAX_LIGHTS};
+
+    #[test]
+    fn test_to_std140() {
+        let lights = Lights {
+            lights: vec![
+                Light::point(
+                    Point3::new(1.0, 2.0, 3.0),
+                    100.0,
+                    Vector3::new(1.0, 0.5, 0.0),
+                    2.0,
+                ),
+                Light {
+                    kind: LightKind::Spot {
+                        position: Point3::new(0.0, -10.0, 0.0),
+                        direction: Vector3::new(0.0, 2.0, 0.0),
+                        range: 0.0,
+                        inner_angle: Deg(0.0),
+                        outer_angle: Deg(90.0),
+                    },
+                    color: Vector3::new(1.0, 1.0, 1.0),
+                    intensity: 1.0,
+                },
+                Light::directional(
+                    Vector3::new(0.0, 3.0, 0.0),
+                    Vector3::new(1.0, 1.0, 1.0),
+                    1.0,
+                ),
+            ],
+        };
+        assert_eq!(lights.shadow_caster(), Some(2));
+
+        let data = lights.to_std140();
+        assert_eq!(data.len(), MAX_LIGHTS * LIGHT_STRIDE);
+        assert_eq!(data[0..8], [2.0, 1.0, 0.0, 2.0, 1.0, 2.0, 3.0, 100.0]);
+        let spot = &data[LIGHT_STRIDE..LIGHT_STRIDE * 2];
+        assert_eq!(spot[3], 3.0);
+        assert_eq!(spot[8..12], [0.0, 1.0, 0.0, 0.0]);
+        assert_eq!(spot[12], 1.0);
+        assert!(spot[13].abs() < 1e-6);
+        let directional = &data[LIGHT_STRIDE * 2..LIGHT_STRIDE * 3];
+        assert_eq!(directional[8..12], [0.0, 1.0, 0.0, 1.0]);
+        assert!(data[LIGHT_STRIDE * 3..].iter().all(|e| *e == 0.0));
+
+        assert_eq!(Lights::none().shadow_caster(), None);
+        assert!(Lights::none().to_std140().iter().all(|e| *e == 0.0));
+    }
+}
@@ -1,57 +1,60 @@
-use std::{collections::HashMap, rc::Rc};
+use std::{
+    collections::HashMap,
+    io::{self, ErrorKind},
+    rc::Rc,
+};
 
-use glow::HasContext;
-use ldraw::Vector3;
+use futures::io::{AsyncRead, AsyncSeek};
+use ldraw::{color::MaterialRegistry, PartAlias, Vector3};
 use ldraw_ir::{
+    asset::{read_level_async, AssetIndex},
+    cache::decode,
     geometry::BoundingBox3,
+    mesh::BakedPart,
     part::{
         EdgeBufferBuilder, FeatureMap, MeshBufferBuilder, OptionalEdgeBufferBuilder,
-        PartBufferBuilder, PartBuilder, SubpartIndex,
+        PartBufferBuilder, PartBuilder, SubpartIndex, TextureSpan,
     },
+    texture::Texture,
     MeshGroup,
 };
 
-use crate::utils::cast_as_bytes;
+use crate::backend::{Backend, BufferUsage};
 
 #[derive(Debug)]
-pub struct MeshBuffer<GL: HasContext> {
+pub struct MeshBuffer<GL: Backend> {
     gl: Rc<GL>,
 
     pub array: Option<GL::VertexArray>,
     pub buffer_vertices: Option<GL::Buffer>,
     pub buffer_normals: Option<GL::Buffer>,
+    // Texture coordinates; only meshes with textured faces have them.
+    pub buffer_uvs: Option<GL::Buffer>,
     pub length: usize,
 }
 
-impl<GL: HasContext> MeshBuffer<GL> {
+impl<GL: Backend> MeshBuffer<GL> {
     pub fn create(builder: &MeshBufferBuilder, gl: Rc<GL>) -> Self {
-        let array: Option<GL::VertexArray>;
-        let buffer_vertices: Option<GL::Buffer>;
-        let buffer_normals: Option<GL::Buffer>;
-        unsafe {
-            array = gl.create_vertex_array().ok();
-            buffer_vertices = gl.create_buffer().ok();
-            buffer_normals = gl.create_buffer().ok();
-            gl.bind_vertex_array(array);
-            gl.bind_buffer(glow::ARRAY_BUFFER, buffer_vertices);
-            gl.buffer_data_u8_slice(
-                glow::ARRAY_BUFFER,
-                cast_as_bytes(builder.vertices.as_ref()),
-                glow::STATIC_DRAW,
-            );
-            gl.bind_buffer(glow::ARRAY_BUFFER, buffer_normals);
-            gl.buffer_data_u8_slice(
-                glow::ARRAY_BUFFER,
-                cast_as_bytes(builder.normals.as_ref()),
-                glow::STATIC_DRAW,
-            );
-        }
+        let array = gl.create_vertex_array().ok();
+        let buffer_vertices = gl.create_buffer().ok();
+        let buffer_normals = gl.create_buffer().ok();
+        gl.bind_vertex_array(array);
+        gl.buffer_data(buffer_vertices, &builder.vertices, BufferUsage::Static);
+        gl.buffer_data(buffer_normals, &builder.normals, BufferUsage::Static);
+        let buffer_uvs = if builder.uvs.is_empty() {
+            None
+        } else {
+            let buffer = gl.create_buffer().ok();
+            gl.buffer_data(buffer, &builder.uvs, BufferUsage::Static);
+            buffer
+        };
 
         MeshBuffer {
             gl: Rc::clone(&gl),
             array,
             buffer_vertices,
             buffer_normals,
+            buffer_uvs,
             length: builder.len(),
         }
     }
@@ -59,44 +62,37 @@ impl<GL: HasContext> MeshBuffer<GL> {
     pub fn bind(&self, location_position: &Option<u32>, location_normals: &Option<u32>) {
         let gl = &self.gl;
 
-        unsafe {
-            gl.bind_vertex_array(self.array);
-        }
+        gl.bind_vertex_array(self.array);
 
         if let Some(e) = location_position {
-            unsafe {
-                gl.bind_buffer(glow::ARRAY_BUFFER, self.buffer_vertices);
-                gl.vertex_attrib_pointer_f32(*e, 3, glow::FLOAT, false, 0, 0);
-            }
+            gl.vertex_attribute(*e, self.buffer_vertices, 3, 0, 0);
         }
         if let Some(e) = location_normals {
-            unsafe {
-                gl.bind_buffer(glow::ARRAY_BUFFER, self.buffer_normals);
-                gl.vertex_attrib_pointer_f32(*e, 3, glow::FLOAT, false, 0, 0);
-            }
+            gl.vertex_attribute(*e, self.buffer_normals, 3, 0, 0);
         }
     }
 }
 
-impl<GL: HasContext> Drop for MeshBuffer<GL> {
+impl<GL: Backend> Drop for MeshBuffer<GL> {
     fn drop(&mut self) {
         let gl = &self.gl;
-        unsafe {
-            if let Some(e) = self.array {
-                gl.delete_vertex_array(e);
-            }
-            if let Some(e) = self.buffer_vertices {
-                gl.delete_buffer(e);
-            }
-            if let Some(e) = self.buffer_normals {
-                gl.delete_buffer(e);
-            }
+        if let Some(e) = self.array {
+            gl.delete_vertex_array(e);
+        }
+        if let Some(e) = self.buffer_vertices {
+            gl.delete_buffer(e);
+        }
+        if let Some(e) = self.buffer_normals {
+            gl.delete_buffer(e);
+        }
+        if let Some(e) = self.buffer_uvs {
+            gl.delete_buffer(e);
         }
     }
 }
 
 #[derive(Debug)]
-pub struct EdgeBuffer<GL: HasContext> {
+pub struct EdgeBuffer<GL: Backend> {
     gl: Rc<GL>,
 
     pub array: Option<GL::VertexArray>,
@@ -105,29 +101,14 @@ pub struct EdgeBuffer<GL: HasContext> {
     pub length: usize,
 }
 
-impl<GL: HasContext> EdgeBuffer<GL> {
+impl<GL: Backend> EdgeBuffer<GL> {
     pub fn create(builder: &EdgeBufferBuilder, gl: Rc<GL>) -> Self {
-        let array: Option<GL::VertexArray>;
-        let buffer_vertices: Option<GL::Buffer>;
-        let buffer_colors: Option<GL::Buffer>;
-        unsafe {
-            array = gl.create_vertex_array().ok();
-            buffer_vertices = gl.create_buffer().ok();
-            buffer_colors = gl.create_buffer().ok();
-            gl.bind_vertex_array(array);
-            gl.bind_buffer(glow::ARRAY_BUFFER, buffer_vertices);
-            gl.buffer_data_u8_slice(
-                glow::ARRAY_BUFFER,
-                cast_as_bytes(builder.vertices.as_ref()),
-                glow::STATIC_DRAW,
-            );
-            gl.bind_buffer(glow::ARRAY_BUFFER, buffer_colors);
-            gl.buffer_data_u8_slice(
-                glow::ARRAY_BUFFER,
-                cast_as_bytes(builder.colors.as_ref()),
-                glow::STATIC_DRAW,
-            );
-        }
+        let array = gl.create_vertex_array().ok();
+        let buffer_vertices = gl.create_buffer().ok();
+        let buffer_colors = gl.create_buffer().ok();
+        gl.bind_vertex_array(array);
+        gl.buffer_data(buffer_vertices, &builder.vertices, BufferUsage::Static);
+        gl.buffer_data(buffer_colors, &builder.colors, BufferUsage::Static);
 
         EdgeBuffer {
             gl: Rc::clone(&gl),
@@ -141,44 +122,34 @@ impl<GL: HasContext> EdgeBuffer<GL> {
     pub fn bind(&self, location_position: &Option<u32>, location_colors: &Option<u32>) {
         let gl = &self.gl;
 
-        unsafe {
-            gl.bind_vertex_array(self.array);
-        }
+        gl.bind_vertex_array(self.array);
 
         if let Some(e) = location_position {
-            unsafe {
-                gl.bind_buffer(glow::ARRAY_BUFFER, self.buffer_vertices);
-                gl.vertex_attrib_pointer_f32(*e, 3, glow::FLOAT, false, 0, 0);
-            }
+            gl.vertex_attribute(*e, self.buffer_vertices, 3, 0, 0);
         }
         if let Some(e) = location_colors {
-            unsafe {
-                gl.bind_buffer(glow::ARRAY_BUFFER, self.buffer_colors);
-                gl.vertex_attrib_pointer_f32(*e, 3, glow::FLOAT, false, 0, 0);
-            }
+            gl.vertex_attribute(*e, self.buffer_colors, 3, 0, 0);
         }
     }
 }
 
-impl<GL: HasContext> Drop for EdgeBuffer<GL> {
+impl<GL: Backend> Drop for EdgeBuffer<GL> {
     fn drop(&mut self) {
         let gl = &self.gl;
-        unsafe {
-            if let Some(e) = self.array {
-                gl.delete_vertex_array(e);
-            }
-            if let Some(e) = self.buffer_vertices {
-                gl.delete_buffer(e);
-            }
-            if let Some(e) = self.buffer_colors {
-                gl.delete_buffer(e);
-            }
+        if let Some(e) = self.array {
+            gl.delete_vertex_array(e);
+        }
+        if let Some(e) = self.buffer_vertices {
+            gl.delete_buffer(e);
+        }
+        if let Some(e) = self.buffer_colors {
+            gl.delete_buffer(e);
         }
     }
 }
 
 #[derive(Debug)]
-pub struct OptionalEdgeBuffer<GL: HasContext> {
+pub struct OptionalEdgeBuffer<GL: Backend> {
     gl: Rc<GL>,
 
     pub array: Option<GL::VertexArray>,
@@ -190,53 +161,20 @@ pub struct OptionalEdgeBuffer<GL: HasContext> {
     pub length: usize,
 }
 
-impl<GL: HasContext> OptionalEdgeBuffer<GL> {
+impl<GL: Backend> OptionalEdgeBuffer<GL> {
     pub fn create(builder: &OptionalEdgeBufferBuilder, gl: Rc<GL>) -> Self {
-        let array: Option<GL::VertexArray>;
-        let buffer_vertices: Option<GL::Buffer>;
-        let buffer_controls_1: Option<GL::Buffer>;
-        let buffer_controls_2: Option<GL::Buffer>;
-        let buffer_directions: Option<GL::Buffer>;
-        let buffer_colors: Option<GL::Buffer>;
-        unsafe {
-            array = gl.create_vertex_array().ok();
-            buffer_vertices = gl.create_buffer().ok();
-            buffer_controls_1 = gl.create_buffer().ok();
-            buffer_controls_2 = gl.create_buffer().ok();
-            buffer_directions = gl.create_buffer().ok();
-            buffer_colors = gl.create_buffer().ok();
-            gl.bind_vertex_array(array);
-            gl.bind_buffer(glow::ARRAY_BUFFER, buffer_vertices);
-            gl.buffer_data_u8_slice(
-                glow::ARRAY_BUFFER,
-                cast_as_bytes(builder.vertices.as_ref()),
-                glow::STATIC_DRAW,
-            );
-            gl.bind_buffer(glow::ARRAY_BUFFER, buffer_controls_1);
-            gl.buffer_data_u8_slice(
-                glow::ARRAY_BUFFER,
-                cast_as_bytes(builder.controls_1.as_ref()),
-                glow::STATIC_DRAW,
-            );
-            gl.bind_buffer(glow::ARRAY_BUFFER, buffer_controls_2);
-            gl.buffer_data_u8_slice(
-                glow::ARRAY_BUFFER,
-                cast_as_bytes(builder.controls_2.as_ref()),
-                glow::STATIC_DRAW,
-            );
-            gl.bind_buffer(glow::ARRAY_BUFFER, buffer_directions);
-            gl.buffer_data_u8_slice(
-                glow::ARRAY_BUFFER,
-                cast_as_bytes(builder.direction.as_ref()),
-                glow::STATIC_DRAW,
-            );
-            gl.bind_buffer(glow::ARRAY_BUFFER, buffer_colors);
-            gl.buffer_data_u8_slice(
-                glow::ARRAY_BUFFER,
-                cast_as_bytes(builder.colors.as_ref()),
-                glow::STATIC_DRAW,
-            );
-        }
+        let array = gl.create_vertex_array().ok();
+        let buffer_vertices = gl.create_buffer().ok();
+        let buffer_controls_1 = gl.create_buffer().ok();
+        let buffer_controls_2 = gl.create_buffer().ok();
+        let buffer_directions = gl.create_buffer().ok();
+        let buffer_colors = gl.create_buffer().ok();
+        gl.bind_vertex_array(array);
+        gl.buffer_data(buffer_vertices, &builder.vertices, BufferUsage::Static);
+        gl.buffer_data(buffer_controls_1, &builder.controls_1, BufferUsage::Static);
+        gl.buffer_data(buffer_controls_2, &builder.controls_2, BufferUsage::Static);
+        gl.buffer_data(buffer_directions, &builder.direction, BufferUsage::Static);
+        gl.buffer_data(buffer_colors, &builder.colors, BufferUsage::Static);
 
         OptionalEdgeBuffer {
             gl: Rc::clone(&gl),
@@ -260,152 +198,120 @@ impl<GL: HasContext> OptionalEdgeBuffer<GL> {
     ) {
         let gl = &self.gl;
 
-        unsafe {
-            gl.bind_vertex_array(self.array);
-        }
+        gl.bind_vertex_array(self.array);
 
         if let Some(e) = location_position {
-            unsafe {
-                gl.bind_buffer(glow::ARRAY_BUFFER, self.buffer_vertices);
-                gl.vertex_attrib_pointer_f32(*e, 3, glow::FLOAT, false, 0, 0);
-            }
+            gl.vertex_attribute(*e, self.buffer_vertices, 3, 0, 0);
         }
         if let Some(e) = location_colors {
-            unsafe {
-                gl.bind_buffer(glow::ARRAY_BUFFER, self.buffer_colors);
-                gl.vertex_attrib_pointer_f32(*e, 3, glow::FLOAT, false, 0, 0);
-            }
+            gl.vertex_attribute(*e, self.buffer_colors, 3, 0, 0);
         }
         if let Some(e) = location_controls_1 {
-            unsafe {
-                gl.bind_buffer(glow::ARRAY_BUFFER, self.buffer_controls_1);
-                gl.vertex_attrib_pointer_f32(*e, 3, glow::FLOAT, false, 0, 0);
-            }
+            gl.vertex_attribute(*e, self.buffer_controls_1, 3, 0, 0);
         }
         if let Some(e) = location_controls_2 {
-            unsafe {
-                gl.bind_buffer(glow::ARRAY_BUFFER, self.buffer_controls_2);
-                gl.vertex_attrib_pointer_f32(*e, 3, glow::FLOAT, false, 0, 0);
-            }
+            gl.vertex_attribute(*e, self.buffer_controls_2, 3, 0, 0);
         }
         if let Some(e) = location_direction {
-            unsafe {
-                gl.bind_buffer(glow::ARRAY_BUFFER, self.buffer_directions);
-                gl.vertex_attrib_pointer_f32(*e, 3, glow::FLOAT, false, 0, 0);
-            }
+            gl.vertex_attribute(*e, self.buffer_directions, 3, 0, 0);
         }
     }
 }
 
-impl<GL: HasContext> Drop for OptionalEdgeBuffer<GL> {
+impl<GL: Backend> Drop for OptionalEdgeBuffer<GL> {
     fn drop(&mut self) {
         let gl = &self.gl;
-        unsafe {
-            if let Some(e) = self.array {
-                gl.delete_vertex_array(e);
-            }
-            if let Some(e) = self.buffer_vertices {
-                gl.delete_buffer(e);
-            }
-            if let Some(e) = self.buffer_controls_1 {
-                gl.delete_buffer(e);
-            }
-            if let Some(e) = self.buffer_controls_2 {
-                gl.delete_buffer(e);
-            }
-            if let Some(e) = self.buffer_directions {
-                gl.delete_buffer(e);
-            }
-            if let Some(e) = self.buffer_colors {
-                gl.delete_buffer(e);
-            }
+        if let Some(e) = self.array {
+            gl.delete_vertex_array(e);
+        }
+        if let Some(e) = self.buffer_vertices {
+            gl.delete_buffer(e);
+        }
+        if let Some(e) = self.buffer_controls_1 {
+            gl.delete_buffer(e);
+        }
+        if let Some(e) = self.buffer_controls_2 {
+            gl.delete_buffer(e);
+        }
+        if let Some(e) = self.buffer_directions {
+            gl.delete_buffer(e);
         }
+        if let Some(e) = self.buffer_colors {
+            gl.delete_buffer(e);
+        }
+    }
+}
+
+// Appends `mesh` to `merged`, padding texture coordinates of untextured
+// vertices with zeros.
+fn merge_mesh(merged: &mut MeshBufferBuilder, mesh: &MeshBufferBuilder) -> SubpartIndex {
+    let start = merged.len();
+    merged.vertices.extend(&mesh.vertices);
+    merged.normals.extend(&mesh.normals);
+    if mesh.is_textured() {
+        merged.uvs.resize(start * 2, 0.0);
+        merged.uvs.extend(&mesh.uvs);
+        merged
+            .textures
+            .extend(mesh.textures.iter().map(|e| TextureSpan {
+                start: start + e.start,
+                span: e.span,
+                texture: e.texture.clone(),
+            }));
+    }
+    if !merged.uvs.is_empty() {
+        merged.uvs.resize(merged.len() * 2, 0.0);
+    }
+
+    SubpartIndex {
+        start,
+        span: mesh.len(),
     }
 }
 
 #[derive(Debug)]
 pub struct PartBuffer<GL>
 where
-    GL: HasContext,
+    GL: Backend,
 {
     pub uncolored_index: Option<SubpartIndex>,
     pub uncolored_without_bfc_index: Option<SubpartIndex>,
     pub opaque_indices: HashMap<MeshGroup, SubpartIndex>,
     pub translucent_indices: HashMap<MeshGroup, SubpartIndex>,
+    // Runs of the mesh drawn with a texture, in order.
+    pub textures: Vec<TextureSpan>,
 
     pub mesh: Option<MeshBuffer<GL>>,
     pub edges: Option<EdgeBuffer<GL>>,
     pub optional_edges: Option<OptionalEdgeBuffer<GL>>,
 }
 
-impl<GL: HasContext> PartBuffer<GL> {
+impl<GL: Backend> PartBuffer<GL> {
     pub fn create(builder: &PartBufferBuilder, gl: Rc<GL>) -> Self {
         let mut merged = MeshBufferBuilder::default();
-        let mut opaque = HashMap::new();
-        let mut translucent = HashMap::new();
-        let mut ptr: usize = 0;
 
         let uncolored_index = if builder.uncolored_mesh.is_empty() {
             None
         } else {
-            merged.vertices.extend(&builder.uncolored_mesh.vertices);
-            merged.normals.extend(&builder.uncolored_mesh.normals);
-            let cur = ptr;
-            ptr += builder.uncolored_mesh.len();
-
-            Some(SubpartIndex {
-                start: cur,
-                span: builder.uncolored_mesh.len(),
-            })
+            Some(merge_mesh(&mut merged, &builder.uncolored_mesh))
         };
 
         let uncolored_without_bfc_index = if builder.uncolored_without_bfc_mesh.is_empty() {
             None
         } else {
-            merged
-                .vertices
-                .extend(&builder.uncolored_without_bfc_mesh.vertices);
-            merged
-                .normals
-                .extend(&builder.uncolored_without_bfc_mesh.normals);
-            let cur = ptr;
-            ptr += builder.uncolored_without_bfc_mesh.len();
-
-            Some(SubpartIndex {
-                start: cur,
-                span: builder.uncolored_without_bfc_mesh.len(),
-            })
+            Some(merge_mesh(&mut merged, &builder.uncolored_without_bfc_mesh))
         };
 
-        for (group, mesh) in builder.opaque_meshes.iter() {
-            merged.vertices.extend(&mesh.vertices);
-            merged.normals.extend(&mesh.normals);
-            let cur = ptr;
-            ptr += mesh.len();
-
-            opaque.insert(
-                group.clone(),
-                SubpartIndex {
-                    start: cur,
-                    span: mesh.len(),
-                },
-            );
-        }
-
-        for (group, mesh) in builder.translucent_meshes.iter() {
-            merged.vertices.extend(&mesh.vertices);
-            merged.normals.extend(&mesh.normals);
-            let cur = ptr;
-            ptr += mesh.len();
-
-            translucent.insert(
-                group.clone(),
-                SubpartIndex {
-                    start: cur,
-                    span: mesh.len(),
-                },
-            );
-        }
+        let opaque = builder
+            .opaque_meshes
+            .iter()
+            .map(|(group, mesh)| (group.clone(), merge_mesh(&mut merged, mesh)))
+            .collect::<HashMap<_, _>>();
+        let translucent = builder
+            .translucent_meshes
+            .iter()
+            .map(|(group, mesh)| (group.clone(), merge_mesh(&mut merged, mesh)))
+            .collect::<HashMap<_, _>>();
 
         let mesh = if !merged.is_empty() {
             Some(MeshBuffer::create(&merged, Rc::clone(&gl)))
@@ -431,12 +337,54 @@ impl<GL: HasContext> PartBuffer<GL> {
             uncolored_without_bfc_index,
             opaque_indices: opaque,
             translucent_indices: translucent,
+            textures: merged.textures,
             mesh,
             edges,
             optional_edges,
         }
     }
 
+    // Splits `index` into runs sharing a texture, or having none.
+    pub fn texture_runs(&self, index: &SubpartIndex) -> Vec<(SubpartIndex, Option<&Texture>)> {
+        let end = index.start + index.span;
+        let mut runs = Vec::new();
+        let mut cursor = index.start;
+        for span in self.textures.iter() {
+            let start = span.start.max(cursor);
+            let span_end = (span.start + span.span).min(end);
+            if start >= span_end {
+                continue;
+            }
+            if cursor < start {
+                runs.push((
+                    SubpartIndex {
+                        start: cursor,
+                        span: start - cursor,
+                    },
+                    None,
+                ));
+            }
+            runs.push((
+                SubpartIndex {
+                    start,
+                    span: span_end - start,
+                },
+                Some(&span.texture),
+            ));
+            cursor = span_end;
+        }
+        if cursor < end {
+            runs.push((
+                SubpartIndex {
+                    start: cursor,
+                    span: end - cursor,
+                },
+                None,
+            ));
+        }
+        runs
+    }
+
     pub fn has_opaque_parts(&self) -> bool {
         !self.opaque_indices.is_empty()
     }
@@ -447,14 +395,14 @@ impl<GL: HasContext> PartBuffer<GL> {
 }
 
 #[derive(Debug)]
-pub struct Part<GL: HasContext> {
+pub struct Part<GL: Backend> {
     pub part: PartBuffer<GL>,
     pub features: FeatureMap,
     pub bounding_box: BoundingBox3,
     pub rotation_center: Vector3,
 }
 
-impl<GL: HasContext> Part<GL> {
+impl<GL: Backend> Part<GL> {
     pub fn create(builder: &PartBuilder, gl: Rc<GL>) -> Self {
         Part {
             part: PartBuffer::create(&builder.part_builder, Rc::clone(&gl)),
@@ -463,4 +411,59 @@ impl<GL: HasContext> Part<GL> {
             rotation_center: builder.rotation_center,
         }
     }
+
+    // Uploads a prebaked part, as loaded from the bake cache or an asset
+    // file.
+    pub fn from_baked(part: &BakedPart, gl: Rc<GL>) -> Self {
+        Part::create(&part.to_builder(), gl)
+    }
+}
+
+// Uploads every part of an asset file held in memory at the level suiting
+// parts `screen_size` pixels across. Levels that fail to decode are left
+// out.
+pub fn load_asset_parts<GL: Backend>(
+    gl: Rc<GL>,
+    bytes: &[u8],
+    index: &AssetIndex,
+    materials: &MaterialRegistry,
+    screen_size: f32,
+) -> HashMap<PartAlias, Part<GL>> {
+    let mut parts = HashMap::new();
+    for part in index.parts.iter() {
+        let baked = part
+            .select(screen_size)
+            .and_then(|e| bytes.get(e.range.clone()))
+            .and_then(|e| decode(e, materials));
+        if let Some(baked) = baked {
+            parts.insert(part.alias.clone(), Part::from_baked(&baked, Rc::clone(&gl)));
+        }
+    }
+    parts
+}
+
+// Like `load_asset_parts`, but reads only the levels it uploads from
+// `reader`, one at a time, so the file never has to be held in memory.
+pub async fn stream_asset_parts<GL: Backend, R: AsyncRead + AsyncSeek + Unpin>(
+    gl: Rc<GL>,
+    reader: &mut R,
+    index: &AssetIndex,
+    materials: &MaterialRegistry,
+    screen_size: f32,
+) -> io::Result<HashMap<PartAlias, Part<GL>>> {
+    let mut parts = HashMap::new();
+    for part in index.parts.iter() {
+        let level = match part.select(screen_size) {
+            Some(e) => e,
+            None => continue,
+        };
+        match read_level_async(reader, level, materials).await {
+            Ok(baked) => {
+                parts.insert(part.alias.clone(), Part::from_baked(&baked, Rc::clone(&gl)));
+            }
+            Err(e) if e.kind() == ErrorKind::InvalidData => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(parts)
 }
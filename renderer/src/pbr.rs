@@ -0,0 +1,194 @@
+use cgmath::Zero;
+use ldraw::{
+    color::{CustomizedMaterial, Finish, Material},
+    Vector3, Vector4,
+};
+
+// Parameters of the physically based shading model the default shaders
+// use.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PbrParameters {
+    pub metalness: f32,
+    pub roughness: f32,
+    // Strength of a smooth dielectric layer over the surface.
+    pub clearcoat: f32,
+    // Soft, dull glow at grazing angles of cloth and rubber. Also
+    // weakens the specular highlight.
+    pub sheen: f32,
+}
+
+impl Default for PbrParameters {
+    fn default() -> Self {
+        PbrParameters::from(&Finish::Plastic)
+    }
+}
+
+impl From<&Finish> for PbrParameters {
+    fn from(finish: &Finish) -> Self {
+        let (metalness, roughness, clearcoat, sheen) = match finish {
+            Finish::Chrome => (1.0, 0.05, 0.0, 0.0),
+            Finish::Metal => (1.0, 0.25, 0.0, 0.0),
+            Finish::MatteMetallic => (0.8, 0.55, 0.0, 0.0),
+            Finish::Pearlescent => (0.35, 0.35, 0.6, 0.0),
+            Finish::Rubber => (0.0, 0.8, 0.0, 0.6),
+            Finish::Plastic | Finish::Custom(_) => (0.0, 0.35, 0.25, 0.0),
+        };
+
+        PbrParameters {
+            metalness,
+            roughness,
+            clearcoat,
+            sheen,
+        }
+    }
+}
+
+impl From<&PbrParameters> for Vector4 {
+    fn from(parameters: &PbrParameters) -> Self {
+        Vector4::new(
+            parameters.metalness,
+            parameters.roughness,
+            parameters.clearcoat,
+            parameters.sheen,
+        )
+    }
+}
+
+// Flecks a glitter or speckle color scatters over its surface.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FleckParameters {
+    pub color: Vector3,
+    // Share of the surface covered, from 0 to 1.
+    pub fraction: f32,
+    // Diameter of a fleck in LDU.
+    pub size: f32,
+    // Glitter flecks sparkle like tiny mirrors; speckles do not.
+    pub glitter: bool,
+}
+
+impl Default for FleckParameters {
+    fn default() -> Self {
+        FleckParameters {
+            color: Vector3::zero(),
+            fraction: 0.0,
+            size: 0.0,
+            glitter: false,
+        }
+    }
+}
+
+impl From<&Finish> for FleckParameters {
+    fn from(finish: &Finish) -> Self {
+        let (value, fraction, size, minsize, maxsize, glitter) = match finish {
+            Finish::Custom(CustomizedMaterial::Glitter(e)) => {
+                (&e.value, e.fraction, e.size, e.minsize, e.maxsize, true)
+            }
+            Finish::Custom(CustomizedMaterial::Speckle(e)) => {
+                (&e.value, e.fraction, e.size, e.minsize, e.maxsize, false)
+            }
+            _ => return FleckParameters::default(),
+        };
+
+        FleckParameters {
+            color: Vector4::from(value).truncate(),
+            fraction: fraction.clamp(0.0, 1.0),
+            size: if size > 0 {
+                size as f32
+            } else {
+                (minsize + maxsize) * 0.5
+            },
+            glitter,
+        }
+    }
+}
+
+// Packed as the fleck color and `±(size + fraction)`: the integral part is
+// the size rounded to a whole LDU, the fractional part the coverage and the
+// sign is positive for glitter. All zeros means no flecks.
+impl From<&FleckParameters> for Vector4 {
+    fn from(parameters: &FleckParameters) -> Self {
+        if parameters.fraction <= 0.0 {
+            return Vector4::new(0.0, 0.0, 0.0, 0.0);
+        }
+        let size = parameters.size.round().max(1.0);
+        let shape = size + parameters.fraction.min(0.99);
+        parameters
+            .color
+            .extend(if parameters.glitter { shape } else { -shape })
+    }
+}
+
+// Light `material` gives off relative to its color, from 0 to 1 by its
+// `LUMINANCE`.
+pub fn emission(material: &Material) -> f32 {
+    f32::from(material.luminance) / 255.0
+}
+
+#[cfg(test)]
+mod tests {
+    use ldraw::{
+        color::{CustomizedMaterial, Finish, MaterialGlitter, MaterialSpeckle, Rgba},
+        Vector3, Vector4,
+    };
+
+    use super::{FleckParameters, PbrParameters};
+
+    #[test]
+    fn test_finishes_look_distinct() {
+        let finishes = [
+            Finish::Plastic,
+            Finish::Chrome,
+            Finish::Pearlescent,
+            Finish::Rubber,
+            Finish::MatteMetallic,
+            Finish::Metal,
+        ]
+        .iter()
+        .map(PbrParameters::from)
+        .collect::<Vec<_>>();
+        for (i, a) in finishes.iter().enumerate() {
+            for b in finishes[i + 1..].iter() {
+                assert_ne!(a, b);
+            }
+        }
+
+        let chrome = PbrParameters::from(&Finish::Chrome);
+        let rubber = PbrParameters::from(&Finish::Rubber);
+        assert!(chrome.metalness > rubber.metalness);
+        assert!(chrome.roughness < rubber.roughness);
+        assert!(finishes.iter().all(|e| (e.sheen > 0.0) == (e == &rubber)));
+        assert_eq!(PbrParameters::default(), finishes[0]);
+    }
+
+    #[test]
+    fn test_fleck_packing() {
+        let glitter = Finish::Custom(CustomizedMaterial::Glitter(MaterialGlitter {
+            value: Rgba::new(255, 255, 255, 255),
+            luminance: 0,
+            fraction: 0.08,
+            vfraction: 0.1,
+            size: 1,
+            minsize: 0.0,
+            maxsize: 0.0,
+        }));
+        let packed = Vector4::from(&FleckParameters::from(&glitter));
+        assert_eq!(packed.truncate(), Vector3::new(1.0, 1.0, 1.0));
+        assert!((packed.w - 1.08).abs() < 1e-6);
+
+        let speckle = Finish::Custom(CustomizedMaterial::Speckle(MaterialSpeckle {
+            value: Rgba::new(0x59, 0x59, 0x59, 255),
+            luminance: 0,
+            fraction: 1.0,
+            size: 0,
+            minsize: 1.0,
+            maxsize: 3.0,
+        }));
+        let parameters = FleckParameters::from(&speckle);
+        assert_eq!(parameters.size, 2.0);
+        assert!(!parameters.glitter);
+        assert!((Vector4::from(&parameters).w + 2.99).abs() < 1e-6);
+
+        let plastic = FleckParameters::from(&Finish::Plastic);
+        assert_eq!(Vector4::from(&plastic), Vector4::new(0.0, 0.0, 0.0, 0.0));
+    }
+}
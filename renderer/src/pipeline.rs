@@ -0,0 +1,840 @@
+use std::rc::Rc;
+
+use ldraw::Vector4;
+
+use crate::backend::{Backend, TextureFormat};
+
+// How translucent geometry in a display list is blended.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Transparency {
+    // Instances are drawn back to front by their centers. Cheap, but
+    // intersecting or enclosing parts can come out in the wrong order.
+    #[default]
+    Sorted,
+    // Weighted blended order-independent transparency. Needs float render
+    // targets, and leaves out edges of translucent parts.
+    WeightedBlended,
+}
+
+// Operator compressing the linear light of a frame into what a display
+// shows.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ToneMapping {
+    // Clips light above white.
+    #[default]
+    None,
+    Reinhard,
+    // Fit of the filmic curve of ACES, with more contrast than Reinhard.
+    Aces,
+}
+
+// Draws the outlines of the triangles of parts, found from barycentric
+// coordinates in the shaders, to look into baked geometry or for a
+// stylized look.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Wireframe {
+    // Width of the lines in pixels.
+    pub width: f32,
+    // Shades the faces between the lines as well, instead of leaving them
+    // out.
+    pub fill: bool,
+}
+
+impl Default for Wireframe {
+    fn default() -> Self {
+        Wireframe {
+            width: 1.0,
+            fill: false,
+        }
+    }
+}
+
+// Options for how `RenderingContext` draws display lists.
+#[derive(Clone, Debug)]
+pub struct PipelineConfig {
+    pub transparency: Transparency,
+    // Draws depth of opaque instances before shading them, so only the
+    // nearest surface of each pixel is shaded. Pays off when lots of
+    // geometry overlaps on screen.
+    pub depth_prepass: bool,
+    // Lets opaque parts shadow the first directional light of the
+    // context.
+    pub shadows: bool,
+    // Width and height of the shadow map in texels.
+    pub shadow_map_size: u32,
+    // Spreads the light of luminous materials, like glow in the dark
+    // ones, over their surroundings once translucent parts are drawn.
+    pub bloom: bool,
+    // How much of the spread light is added.
+    pub bloom_intensity: f32,
+    // Samples per pixel, to smooth out jagged edge lines and thin
+    // geometry. Above one, frames started with `begin_frame` are drawn
+    // into a multisampled target of the context and resolved into its
+    // framebuffer by `end_frame`. Capped at what the backend supports.
+    pub samples: u32,
+    pub tone_mapping: ToneMapping,
+    // Scale of light before tone mapping.
+    pub exposure: f32,
+    // Keeps frames started with `begin_frame` in linear light in a float
+    // target, then tone maps and encodes them once in `end_frame`, so
+    // translucent parts and bloom blend in linear space. Otherwise every
+    // fragment is encoded as it is shaded.
+    pub hdr: bool,
+    // Color of the outline `render_outline` draws around selected
+    // instances.
+    pub outline_color: Vector4,
+    // Width of the outline in pixels, up to `MAX_OUTLINE_THICKNESS`.
+    pub outline_thickness: u32,
+    // Draws parts as wireframe instead of shading their faces whole.
+    pub wireframe: Option<Wireframe>,
+    // Lights parts with `Environment::studio` from the next
+    // `render_display_list` on, unless `set_environment` gave the context
+    // one. Prefiltering the studio takes a while the first time, so
+    // contexts start out reflecting a plain cube map.
+    pub studio_environment: bool,
+}
+
+// Thickest outline the outline pass draws; its cost grows with the
+// square of the thickness.
+pub const MAX_OUTLINE_THICKNESS: u32 = 16;
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        PipelineConfig {
+            transparency: Transparency::default(),
+            depth_prepass: false,
+            shadows: false,
+            shadow_map_size: 2048,
+            bloom: false,
+            bloom_intensity: 1.0,
+            samples: 1,
+            tone_mapping: ToneMapping::default(),
+            exposure: 1.0,
+            hdr: false,
+            outline_color: Vector4::new(1.0, 0.6, 0.0, 1.0),
+            outline_thickness: 3,
+            wireframe: None,
+            studio_environment: false,
+        }
+    }
+}
+
+// Render targets of the weighted blended transparency pass.
+pub struct WeightedBlendedTarget<GL: Backend> {
+    gl: Rc<GL>,
+
+    pub width: u32,
+    pub height: u32,
+
+    pub framebuffer: GL::Framebuffer,
+    pub accumulation: GL::Texture,
+    pub weights: GL::Texture,
+    pub depth: GL::Texture,
+}
+
+impl<GL: Backend> WeightedBlendedTarget<GL> {
+    pub fn new(gl: Rc<GL>, width: u32, height: u32) -> Result<Self, String> {
+        let mut textures = Vec::new();
+        for format in [
+            TextureFormat::Rgba16F,
+            TextureFormat::R16F,
+            TextureFormat::Depth,
+        ] {
+            match gl.create_render_target(width, height, format) {
+                Ok(e) => textures.push(e),
+                Err(e) => {
+                    textures.into_iter().for_each(|e| gl.delete_texture(e));
+                    return Err(e);
+                }
+            }
+        }
+        let (accumulation, weights, depth) = (textures[0], textures[1], textures[2]);
+
+        let framebuffer = match gl.create_framebuffer(&[accumulation, weights], Some(depth)) {
+            Ok(e) => e,
+            Err(e) => {
+                textures.into_iter().for_each(|e| gl.delete_texture(e));
+                return Err(e);
+            }
+        };
+
+        Ok(WeightedBlendedTarget {
+            gl,
+            width,
+            height,
+            framebuffer,
+            accumulation,
+            weights,
+            depth,
+        })
+    }
+}
+
+impl<GL: Backend> Drop for WeightedBlendedTarget<GL> {
+    fn drop(&mut self) {
+        self.gl.delete_framebuffer(self.framebuffer);
+        self.gl.delete_texture(self.accumulation);
+        self.gl.delete_texture(self.weights);
+        self.gl.delete_texture(self.depth);
+    }
+}
+
+// Depth as seen from the shadow casting light.
+pub struct ShadowMapTarget<GL: Backend> {
+    gl: Rc<GL>,
+
+    pub size: u32,
+
+    pub framebuffer: GL::Framebuffer,
+    pub depth: GL::Texture,
+}
+
+impl<GL: Backend> ShadowMapTarget<GL> {
+    pub fn new(gl: Rc<GL>, size: u32) -> Result<Self, String> {
+        let depth = gl.create_render_target(size, size, TextureFormat::Depth)?;
+        let framebuffer = match gl.create_framebuffer(&[], Some(depth)) {
+            Ok(e) => e,
+            Err(e) => {
+                gl.delete_texture(depth);
+                return Err(e);
+            }
+        };
+
+        Ok(ShadowMapTarget {
+            gl,
+            size,
+            framebuffer,
+            depth,
+        })
+    }
+}
+
+impl<GL: Backend> Drop for ShadowMapTarget<GL> {
+    fn drop(&mut self) {
+        self.gl.delete_framebuffer(self.framebuffer);
+        self.gl.delete_texture(self.depth);
+    }
+}
+
+// Targets the glow of luminous parts is drawn into and blurred back and
+// forth between, at half the resolution of the image.
+pub struct BloomTarget<GL: Backend> {
+    gl: Rc<GL>,
+
+    pub width: u32,
+    pub height: u32,
+
+    // The first one also has `depth` attached.
+    pub framebuffers: [GL::Framebuffer; 2],
+    pub colors: [GL::Texture; 2],
+    pub depth: GL::Texture,
+}
+
+impl<GL: Backend> BloomTarget<GL> {
+    pub fn new(gl: Rc<GL>, width: u32, height: u32) -> Result<Self, String> {
+        let mut textures = Vec::new();
+        for format in [
+            TextureFormat::Rgba16F,
+            TextureFormat::Rgba16F,
+            TextureFormat::Depth,
+        ] {
+            match gl.create_render_target(width, height, format) {
+                Ok(e) => textures.push(e),
+                Err(e) => {
+                    textures.into_iter().for_each(|e| gl.delete_texture(e));
+                    return Err(e);
+                }
+            }
+        }
+        let (colors, depth) = ([textures[0], textures[1]], textures[2]);
+
+        let first = match gl.create_framebuffer(&[colors[0]], Some(depth)) {
+            Ok(e) => e,
+            Err(e) => {
+                textures.into_iter().for_each(|e| gl.delete_texture(e));
+                return Err(e);
+            }
+        };
+        let second = match gl.create_framebuffer(&[colors[1]], None) {
+            Ok(e) => e,
+            Err(e) => {
+                gl.delete_framebuffer(first);
+                textures.into_iter().for_each(|e| gl.delete_texture(e));
+                return Err(e);
+            }
+        };
+
+        Ok(BloomTarget {
+            gl,
+            width,
+            height,
+            framebuffers: [first, second],
+            colors,
+            depth,
+        })
+    }
+}
+
+impl<GL: Backend> Drop for BloomTarget<GL> {
+    fn drop(&mut self) {
+        for e in self.framebuffers {
+            self.gl.delete_framebuffer(e);
+        }
+        for e in self.colors {
+            self.gl.delete_texture(e);
+        }
+        self.gl.delete_texture(self.depth);
+    }
+}
+
+// Float color and depth a frame is drawn into in linear light before tone
+// mapping.
+pub struct HdrTarget<GL: Backend> {
+    gl: Rc<GL>,
+
+    pub width: u32,
+    pub height: u32,
+
+    pub framebuffer: GL::Framebuffer,
+    pub color: GL::Texture,
+    pub depth: GL::Texture,
+}
+
+impl<GL: Backend> HdrTarget<GL> {
+    pub fn new(gl: Rc<GL>, width: u32, height: u32) -> Result<Self, String> {
+        let color = gl.create_render_target(width, height, TextureFormat::Rgba16F)?;
+        let depth = match gl.create_render_target(width, height, TextureFormat::Depth) {
+            Ok(e) => e,
+            Err(e) => {
+                gl.delete_texture(color);
+                return Err(e);
+            }
+        };
+        let framebuffer = match gl.create_framebuffer(&[color], Some(depth)) {
+            Ok(e) => e,
+            Err(e) => {
+                gl.delete_texture(color);
+                gl.delete_texture(depth);
+                return Err(e);
+            }
+        };
+
+        Ok(HdrTarget {
+            gl,
+            width,
+            height,
+            framebuffer,
+            color,
+            depth,
+        })
+    }
+}
+
+impl<GL: Backend> Drop for HdrTarget<GL> {
+    fn drop(&mut self) {
+        self.gl.delete_framebuffer(self.framebuffer);
+        self.gl.delete_texture(self.color);
+        self.gl.delete_texture(self.depth);
+    }
+}
+
+// Silhouettes of selected instances the outline is drawn around.
+pub struct OutlineTarget<GL: Backend> {
+    gl: Rc<GL>,
+
+    pub width: u32,
+    pub height: u32,
+
+    pub framebuffer: GL::Framebuffer,
+    pub mask: GL::Texture,
+}
+
+impl<GL: Backend> OutlineTarget<GL> {
+    pub fn new(gl: Rc<GL>, width: u32, height: u32) -> Result<Self, String> {
+        let mask = gl.create_render_target(width, height, TextureFormat::Rgba8)?;
+        let framebuffer = match gl.create_framebuffer(&[mask], None) {
+            Ok(e) => e,
+            Err(e) => {
+                gl.delete_texture(mask);
+                return Err(e);
+            }
+        };
+
+        Ok(OutlineTarget {
+            gl,
+            width,
+            height,
+            framebuffer,
+            mask,
+        })
+    }
+}
+
+impl<GL: Backend> Drop for OutlineTarget<GL> {
+    fn drop(&mut self) {
+        self.gl.delete_framebuffer(self.framebuffer);
+        self.gl.delete_texture(self.mask);
+    }
+}
+
+// IDs of the instances seen at each pixel, plus one so zero is nothing.
+pub struct PickingTarget<GL: Backend> {
+    gl: Rc<GL>,
+
+    pub width: u32,
+    pub height: u32,
+
+    pub framebuffer: GL::Framebuffer,
+    pub ids: GL::Texture,
+    pub depth: GL::Texture,
+}
+
+impl<GL: Backend> PickingTarget<GL> {
+    pub fn new(gl: Rc<GL>, width: u32, height: u32) -> Result<Self, String> {
+        let ids = gl.create_render_target(width, height, TextureFormat::R32UI)?;
+        let depth = match gl.create_render_target(width, height, TextureFormat::Depth) {
+            Ok(e) => e,
+            Err(e) => {
+                gl.delete_texture(ids);
+                return Err(e);
+            }
+        };
+        let framebuffer = match gl.create_framebuffer(&[ids], Some(depth)) {
+            Ok(e) => e,
+            Err(e) => {
+                gl.delete_texture(ids);
+                gl.delete_texture(depth);
+                return Err(e);
+            }
+        };
+
+        Ok(PickingTarget {
+            gl,
+            width,
+            height,
+            framebuffer,
+            ids,
+            depth,
+        })
+    }
+}
+
+impl<GL: Backend> Drop for PickingTarget<GL> {
+    fn drop(&mut self) {
+        self.gl.delete_framebuffer(self.framebuffer);
+        self.gl.delete_texture(self.ids);
+        self.gl.delete_texture(self.depth);
+    }
+}
+
+// Multisampled color and depth a frame is drawn into before resolving.
+pub struct MultisampleTarget<GL: Backend> {
+    gl: Rc<GL>,
+
+    pub width: u32,
+    pub height: u32,
+    pub samples: u32,
+    pub format: TextureFormat,
+
+    pub framebuffer: GL::Framebuffer,
+    pub color: GL::Renderbuffer,
+    pub depth: GL::Renderbuffer,
+}
+
+impl<GL: Backend> MultisampleTarget<GL> {
+    // `format` is that of the color, which must match the target it is
+    // resolved into.
+    pub fn new(
+        gl: Rc<GL>,
+        width: u32,
+        height: u32,
+        samples: u32,
+        format: TextureFormat,
+    ) -> Result<Self, String> {
+        let color = gl.create_multisample_target(width, height, samples, format)?;
+        let depth = match gl.create_multisample_target(width, height, samples, TextureFormat::Depth)
+        {
+            Ok(e) => e,
+            Err(e) => {
+                gl.delete_renderbuffer(color);
+                return Err(e);
+            }
+        };
+        let framebuffer = match gl.create_multisample_framebuffer(color, depth) {
+            Ok(e) => e,
+            Err(e) => {
+                gl.delete_renderbuffer(color);
+                gl.delete_renderbuffer(depth);
+                return Err(e);
+            }
+        };
+
+        Ok(MultisampleTarget {
+            gl,
+            width,
+            height,
+            samples,
+            format,
+            framebuffer,
+            color,
+            depth,
+        })
+    }
+}
+
+impl<GL: Backend> Drop for MultisampleTarget<GL> {
+    fn drop(&mut self) {
+        self.gl.delete_framebuffer(self.framebuffer);
+        self.gl.delete_renderbuffer(self.color);
+        self.gl.delete_renderbuffer(self.depth);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, rc::Rc};
+
+    use cgmath::{Deg, Matrix4, Point3, SquareMatrix};
+
+    use ldraw::{
+        color::{Material, Rgba},
+        PartAlias, Vector3, Vector4,
+    };
+    use ldraw_ir::{
+        geometry::BoundingBox3,
+        part::{PartBufferBuilder, PartBuilder},
+    };
+
+    use crate::{
+        backend::{Blending, Primitive},
+        display_list::DisplayList,
+        part::Part,
+        shader::ProgramManager,
+        state::{PerspectiveCamera, RenderingContext},
+        test_support::{triangle, RecordingBackend},
+    };
+
+    use super::{Transparency, Wireframe};
+
+    #[test]
+    fn test_weighted_blended_transparency() {
+        let builder = triangle();
+        let alias = PartAlias::from("a.dat");
+        let translucent = Material {
+            color: Rgba::new(255, 0, 0, 128),
+            ..Material::default()
+        };
+
+        let backend = Rc::new(RecordingBackend::default());
+        {
+            let program_manager = ProgramManager::new(Rc::clone(&backend)).unwrap();
+            let mut context = RenderingContext::new(Rc::clone(&backend), program_manager);
+            context.pipeline.transparency = Transparency::WeightedBlended;
+            context.framebuffer = Some(1000);
+            let parts =
+                HashMap::from([(alias.clone(), Part::create(&builder, Rc::clone(&backend)))]);
+            let mut display_list = DisplayList::default();
+            for (x, material) in [
+                (0.0, &translucent),
+                (1.0, &translucent),
+                (2.0, &Material::default()),
+            ] {
+                display_list.add(
+                    Rc::clone(&backend),
+                    alias.clone(),
+                    Matrix4::from_translation(Vector3::new(x, 0.0, 0.0)),
+                    material.clone(),
+                );
+            }
+
+            backend.draws.borrow_mut().clear();
+            context.render_display_list(&parts, &mut display_list, true);
+
+            // Depth of the opaque instance, the translucent ones at once,
+            // then the composite.
+            assert_eq!(
+                *backend.draws.borrow(),
+                vec![
+                    (Primitive::Triangles, 0, 3, false),
+                    (Primitive::Triangles, 0, 3, true),
+                    (Primitive::Triangles, 0, 3, false),
+                ]
+            );
+            let target = backend.blending.borrow()[0].0;
+            assert!(target.is_some() && target != Some(1000));
+            assert_eq!(
+                *backend.blending.borrow(),
+                vec![
+                    (target, Blending::Accumulate),
+                    (Some(1000), Blending::Alpha)
+                ]
+            );
+        }
+        assert!(backend.live.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_depth_prepass() {
+        let builder = triangle();
+        let alias = PartAlias::from("a.dat");
+
+        let backend = Rc::new(RecordingBackend::default());
+        let program_manager = ProgramManager::new(Rc::clone(&backend)).unwrap();
+        let mut context = RenderingContext::new(Rc::clone(&backend), program_manager);
+        context.pipeline.depth_prepass = true;
+        let parts = HashMap::from([(alias.clone(), Part::create(&builder, Rc::clone(&backend)))]);
+        let mut display_list = DisplayList::default();
+        for x in [0.0, 1.0] {
+            display_list.add(
+                Rc::clone(&backend),
+                alias.clone(),
+                Matrix4::from_translation(Vector3::new(x, 0.0, 0.0)),
+                Material::default(),
+            );
+        }
+
+        context.render_display_list(&parts, &mut display_list, false);
+        assert_eq!(
+            *backend.draws.borrow(),
+            vec![
+                (Primitive::Triangles, 0, 3, true),
+                (Primitive::Triangles, 0, 3, true)
+            ]
+        );
+        assert_eq!(*backend.masked_draws.borrow(), 1);
+        assert!(!*backend.color_masked.borrow());
+    }
+
+    #[test]
+    fn test_wireframe() {
+        let builder = triangle();
+        let alias = PartAlias::from("a.dat");
+
+        let backend = Rc::new(RecordingBackend::default());
+        let program_manager = ProgramManager::new(Rc::clone(&backend)).unwrap();
+        let mut context = RenderingContext::new(Rc::clone(&backend), program_manager);
+        context.pipeline.depth_prepass = true;
+        context.pipeline.wireframe = Some(Wireframe::default());
+        let parts = HashMap::from([(alias.clone(), Part::create(&builder, Rc::clone(&backend)))]);
+        let mut display_list = DisplayList::default();
+        for x in [0.0, 1.0] {
+            display_list.add(
+                Rc::clone(&backend),
+                alias.clone(),
+                Matrix4::from_translation(Vector3::new(x, 0.0, 0.0)),
+                Material::default(),
+            );
+        }
+
+        // Triangles are drawn instanced as they are, the lines found in the
+        // shaders.
+        context.render_display_list(&parts, &mut display_list, false);
+        assert_eq!(context.shading_data.wireframe, Some(Wireframe::default()));
+        assert_eq!(
+            *backend.draws.borrow(),
+            vec![
+                (Primitive::Triangles, 0, 3, true),
+                (Primitive::Triangles, 0, 3, true)
+            ]
+        );
+
+        context.pipeline.wireframe = None;
+        context.render_display_list(&parts, &mut display_list, false);
+        assert_eq!(context.shading_data.wireframe, None);
+    }
+
+    #[test]
+    fn test_shadow_map() {
+        let mut part_builder = PartBufferBuilder::default();
+        for x in [0.0, 1.0, 0.0] {
+            part_builder
+                .uncolored_mesh
+                .add(&Vector3::new(x, 0.0, 1.0 - x), &Vector3::unit_y());
+        }
+        let bounding_box =
+            BoundingBox3::new(&Vector3::new(0.0, 0.0, 0.0), &Vector3::new(1.0, 0.0, 1.0));
+        let builder = PartBuilder::new(
+            part_builder,
+            HashMap::new(),
+            bounding_box,
+            &Vector3::new(0.0, 0.0, 0.0),
+        );
+        let alias = PartAlias::from("a.dat");
+
+        let backend = Rc::new(RecordingBackend::default());
+        {
+            let program_manager = ProgramManager::new(Rc::clone(&backend)).unwrap();
+            let mut context = RenderingContext::new(Rc::clone(&backend), program_manager);
+            context.pipeline.shadows = true;
+            context.apply_perspective_camera(&PerspectiveCamera::new(
+                Point3::new(0.0, -50.0, -100.0),
+                Point3::new(0.0, 0.0, 0.0),
+                Deg(45.0),
+            ));
+            let parts =
+                HashMap::from([(alias.clone(), Part::create(&builder, Rc::clone(&backend)))]);
+            let mut display_list = DisplayList::default();
+            for x in [-20.0, 20.0] {
+                display_list.add(
+                    Rc::clone(&backend),
+                    alias.clone(),
+                    Matrix4::from_translation(Vector3::new(x, 0.0, 0.0)),
+                    Material::default(),
+                );
+            }
+
+            context.render_display_list(&parts, &mut display_list, false);
+            // Depth from the light, then shading.
+            assert_eq!(backend.draws.borrow().len(), 2);
+            assert_eq!(*backend.masked_draws.borrow(), 1);
+            assert_eq!(*backend.framebuffer.borrow(), None);
+
+            // Both ends of the model land inside the map.
+            let shadow_matrix = context.shading_data.shadow_matrix.unwrap();
+            let view_matrix = context.projection_data.view_matrix;
+            for corner in [Vector3::new(-20.0, 0.0, 0.0), Vector3::new(21.0, 0.0, 1.0)] {
+                let p = shadow_matrix * view_matrix * corner.extend(1.0);
+                let p = p.truncate() / p.w;
+                assert!([p.x, p.y, p.z]
+                    .iter()
+                    .all(|e| (-1e-3..=1.0 + 1e-3).contains(e)));
+            }
+
+            context.pipeline.shadows = false;
+            context.render_display_list(&parts, &mut display_list, false);
+            assert!(context.shading_data.shadow_matrix.is_none());
+        }
+        assert!(backend.live.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_bloom() {
+        let builder = triangle();
+        let alias = PartAlias::from("a.dat");
+        let glowing = Material {
+            luminance: 15,
+            ..Material::default()
+        };
+
+        let backend = Rc::new(RecordingBackend::default());
+        {
+            let program_manager = ProgramManager::new(Rc::clone(&backend)).unwrap();
+            let mut context = RenderingContext::new(Rc::clone(&backend), program_manager);
+            context.pipeline.bloom = true;
+            context.framebuffer = Some(1000);
+            let parts =
+                HashMap::from([(alias.clone(), Part::create(&builder, Rc::clone(&backend)))]);
+            let mut display_list = DisplayList::default();
+            display_list.add(
+                Rc::clone(&backend),
+                alias.clone(),
+                Matrix4::identity(),
+                Material::default(),
+            );
+
+            // Nothing glows, so nothing is added.
+            context.render_display_list(&parts, &mut display_list, true);
+            assert!(backend.draws.borrow().is_empty());
+
+            display_list.add(
+                Rc::clone(&backend),
+                alias.clone(),
+                Matrix4::from_translation(Vector3::new(1.0, 0.0, 0.0)),
+                glowing,
+            );
+            context.render_display_list(&parts, &mut display_list, true);
+            // The glow of both instances at once, two rounds of blurring
+            // both ways, then the composite.
+            assert_eq!(
+                *backend.draws.borrow(),
+                vec![
+                    (Primitive::Triangles, 0, 3, true),
+                    (Primitive::Triangles, 0, 3, false),
+                    (Primitive::Triangles, 0, 3, false),
+                    (Primitive::Triangles, 0, 3, false),
+                    (Primitive::Triangles, 0, 3, false),
+                    (Primitive::Triangles, 0, 3, false),
+                ]
+            );
+            let glow = backend.blending.borrow()[0].0;
+            assert!(glow.is_some() && glow != Some(1000));
+            assert_eq!(
+                backend.blending.borrow().last(),
+                Some(&(Some(1000), Blending::Alpha))
+            );
+            assert!(backend
+                .blending
+                .borrow()
+                .contains(&(Some(1000), Blending::Additive)));
+            assert!(!context.shading_data.emissive_only);
+        }
+        assert!(backend.live.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_hdr_tone_mapping() {
+        let builder = triangle();
+        let alias = PartAlias::from("a.dat");
+        let clear_color = Vector4::new(1.0, 1.0, 1.0, 0.0);
+
+        let backend = Rc::new(RecordingBackend::default());
+        {
+            let program_manager = ProgramManager::new(Rc::clone(&backend)).unwrap();
+            let mut context = RenderingContext::new(Rc::clone(&backend), program_manager);
+            context.framebuffer = Some(1000);
+            context.resize(640, 480);
+            let parts =
+                HashMap::from([(alias.clone(), Part::create(&builder, Rc::clone(&backend)))]);
+            let mut display_list = DisplayList::default();
+            display_list.add(
+                Rc::clone(&backend),
+                alias.clone(),
+                Matrix4::identity(),
+                Material::default(),
+            );
+
+            context.pipeline.hdr = true;
+            context.begin_frame(&clear_color);
+            let scene = backend.framebuffer.borrow().unwrap();
+            assert_ne!(scene, 1000);
+            context.render_display_list(&parts, &mut display_list, false);
+            assert!(context.shading_data.linear_output);
+            let draws = backend.draws.borrow().len();
+            context.end_frame();
+            assert_eq!(*backend.framebuffer.borrow(), Some(1000));
+            assert_eq!(
+                backend.blending.borrow().last(),
+                Some(&(Some(1000), Blending::Alpha))
+            );
+            assert!(backend
+                .blending
+                .borrow()
+                .contains(&(Some(1000), Blending::Premultiplied)));
+            assert_eq!(
+                backend.draws.borrow()[draws..],
+                [(Primitive::Triangles, 0, 3, false)]
+            );
+
+            // Multisampled frames are resolved into the float target first.
+            context.pipeline.samples = 4;
+            context.begin_frame(&clear_color);
+            let multisampled = backend.framebuffer.borrow().unwrap();
+            assert_ne!(multisampled, scene);
+            context.end_frame();
+            assert_eq!(
+                *backend.resolves.borrow(),
+                vec![(multisampled, Some(scene))]
+            );
+            assert_eq!(*backend.framebuffer.borrow(), Some(1000));
+
+            // Without it, fragments are encoded as they are shaded.
+            context.pipeline.hdr = false;
+            context.pipeline.samples = 1;
+            context.begin_frame(&clear_color);
+            assert_eq!(*backend.framebuffer.borrow(), Some(1000));
+            context.render_display_list(&parts, &mut display_list, false);
+            assert!(!context.shading_data.linear_output);
+        }
+        assert!(backend.live.borrow().is_empty());
+    }
+}
@@ -0,0 +1,340 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use cgmath::{InnerSpace, SquareMatrix};
+use ldraw::{Matrix4, PartAlias, Vector3};
+use ldraw_ir::{
+    bvh::{transform_bounds, BoundsBvh, TriangleBvh},
+    part::{MeshBufferBuilder, PartBufferBuilder},
+};
+
+use crate::{
+    backend::Backend,
+    display_list::{DisplayList, InstanceBuffer, InstanceId},
+};
+
+// Nearest instance a ray hits.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RayHit {
+    pub id: InstanceId,
+    pub part: PartAlias,
+    // Distance from the origin of the ray.
+    pub distance: f32,
+    // Where the ray hits, in the coordinates instances are placed in.
+    pub point: Vector3,
+}
+
+fn triangles(mesh: &MeshBufferBuilder) -> impl Iterator<Item = [Vector3; 3]> + '_ {
+    mesh.vertices.chunks_exact(9).map(|e| {
+        [
+            Vector3::new(e[0], e[1], e[2]),
+            Vector3::new(e[3], e[4], e[5]),
+            Vector3::new(e[6], e[7], e[8]),
+        ]
+    })
+}
+
+// Layout and placement revisions of each instance buffer, by part and
+// whether it is the translucent one.
+type Revisions = HashMap<(PartAlias, bool), (u64, u64)>;
+
+fn revisions<GL: Backend>(display_list: &DisplayList<GL>) -> Revisions {
+    buffers(display_list)
+        .map(|(key, buffer)| (key, (buffer.layout_revision(), buffer.placement_revision())))
+        .collect()
+}
+
+fn buffers<GL: Backend>(
+    display_list: &DisplayList<GL>,
+) -> impl Iterator<Item = ((PartAlias, bool), &InstanceBuffer<GL>)> + '_ {
+    display_list.map.iter().flat_map(|(alias, item)| {
+        [
+            ((alias.clone(), false), &item.opaque),
+            ((alias.clone(), true), &item.translucent),
+        ]
+    })
+}
+
+// A visible instance, by its buffer and index there.
+#[derive(Debug)]
+struct Instance {
+    id: InstanceId,
+    buffer: (PartAlias, bool),
+    index: usize,
+    matrix: Matrix4,
+}
+
+// Instances of the display list last cast into, with a hierarchy over
+// their boxes. Refitted when they only move, and built anew otherwise.
+#[derive(Debug, Default)]
+struct Scene {
+    revisions: Revisions,
+    instances: Vec<Instance>,
+    bvh: BoundsBvh,
+}
+
+impl Scene {
+    fn new<GL: Backend>(
+        parts: &HashMap<PartAlias, TriangleBvh>,
+        display_list: &DisplayList<GL>,
+    ) -> Self {
+        let mut instances = Vec::new();
+        let mut boxes = Vec::new();
+        for (key, buffer) in buffers(display_list) {
+            let bounds = match parts.get(&key.0).and_then(TriangleBvh::bounds) {
+                Some(e) => e,
+                None => continue,
+            };
+            for (index, id) in buffer.ids.iter().enumerate() {
+                if !buffer.is_visible(index) {
+                    continue;
+                }
+                let matrix = buffer.exploded_matrix(index);
+                boxes.push(transform_bounds(bounds, &matrix));
+                instances.push(Instance {
+                    id: *id,
+                    buffer: key.clone(),
+                    index,
+                    matrix,
+                });
+            }
+        }
+
+        Scene {
+            revisions: revisions(display_list),
+            instances,
+            bvh: BoundsBvh::new(boxes),
+        }
+    }
+
+    fn refit<GL: Backend>(
+        &mut self,
+        parts: &HashMap<PartAlias, TriangleBvh>,
+        display_list: &DisplayList<GL>,
+    ) {
+        let mut boxes = Vec::with_capacity(self.instances.len());
+        for instance in self.instances.iter_mut() {
+            let (alias, translucent) = &instance.buffer;
+            let item = &display_list.map[alias];
+            let buffer = if *translucent {
+                &item.translucent
+            } else {
+                &item.opaque
+            };
+            instance.matrix = buffer.exploded_matrix(instance.index);
+            let bounds = parts[alias].bounds().unwrap();
+            boxes.push(transform_bounds(bounds, &instance.matrix));
+        }
+        self.bvh.refit(&boxes);
+        self.revisions = revisions(display_list);
+    }
+}
+
+// Finds parts along a ray on the CPU, for backends that can't pick
+// through render targets and for queries that need the point hit. Keeps
+// the triangles of every part it is told about, and a hierarchy over the
+// instances of the display list it last cast into.
+#[derive(Debug, Default)]
+pub struct RayCaster {
+    parts: HashMap<PartAlias, TriangleBvh>,
+    scene: RefCell<Option<Scene>>,
+}
+
+impl RayCaster {
+    // Takes the triangles of `builder` as those of `alias`.
+    pub fn add_part(&mut self, alias: &PartAlias, builder: &PartBufferBuilder) {
+        let meshes = [&builder.uncolored_mesh, &builder.uncolored_without_bfc_mesh]
+            .into_iter()
+            .chain(builder.opaque_meshes.values())
+            .chain(builder.translucent_meshes.values());
+        let bvh = TriangleBvh::new(meshes.flat_map(triangles).collect());
+        self.parts.insert(alias.clone(), bvh);
+        *self.scene.get_mut() = None;
+    }
+
+    pub fn contains_part(&self, alias: &PartAlias) -> bool {
+        self.parts.contains_key(alias)
+    }
+
+    // Nearest instance of `display_list` a ray from `origin` along
+    // `direction` hits. Hidden instances and those of parts it has no
+    // triangles of are passed through.
+    pub fn cast<GL: Backend>(
+        &self,
+        display_list: &DisplayList<GL>,
+        origin: &Vector3,
+        direction: &Vector3,
+    ) -> Option<RayHit> {
+        let direction = direction.normalize();
+
+        // Instances that only moved since the last cast are refitted; any
+        // other change builds the hierarchy anew.
+        let mut scene = self.scene.borrow_mut();
+        let current = revisions(display_list);
+        match scene.as_mut() {
+            Some(e) if e.revisions == current => {}
+            Some(e)
+                if e.revisions.len() == current.len()
+                    && current
+                        .iter()
+                        .all(|(k, v)| e.revisions.get(k).is_some_and(|e| e.0 == v.0)) =>
+            {
+                e.refit(&self.parts, display_list)
+            }
+            _ => *scene = Some(Scene::new(&self.parts, display_list)),
+        }
+        let scene = scene.as_ref().unwrap();
+
+        let mut nearest: Option<(f32, &Instance)> = None;
+        scene.bvh.cast_ray(origin, &direction, |i| {
+            let instance = &scene.instances[i];
+            let inverse = instance.matrix.invert()?;
+            // Distances along the transformed direction are the same as
+            // along the original one.
+            let local_origin = (inverse * origin.extend(1.0)).truncate();
+            let local_direction = (inverse * direction.extend(0.0)).truncate();
+            let distance =
+                self.parts[&instance.buffer.0].cast_ray(&local_origin, &local_direction)?;
+            if nearest.is_none_or(|e| distance < e.0) {
+                nearest = Some((distance, instance));
+            }
+            Some(distance)
+        });
+
+        nearest.map(|(distance, instance)| RayHit {
+            id: instance.id,
+            part: instance.buffer.0.clone(),
+            distance,
+            point: origin + direction * distance,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use cgmath::{Deg, InnerSpace, Matrix4, Point3, SquareMatrix};
+
+    use ldraw::{color::Material, PartAlias, Vector3};
+    use ldraw_ir::part::PartBufferBuilder;
+
+    use crate::{
+        display_list::DisplayList,
+        shader::ProgramManager,
+        state::{PerspectiveCamera, RenderingContext},
+        test_support::RecordingBackend,
+    };
+
+    use super::RayCaster;
+
+    #[test]
+    fn test_ray_casting() {
+        // A unit square on y = 0.
+        let mut builder = PartBufferBuilder::default();
+        for (x, z) in [
+            (0.0, 0.0),
+            (1.0, 0.0),
+            (1.0, 1.0),
+            (0.0, 0.0),
+            (1.0, 1.0),
+            (0.0, 1.0),
+        ] {
+            builder
+                .uncolored_mesh
+                .add(&Vector3::new(x, 0.0, z), &Vector3::unit_y());
+        }
+        let alias = PartAlias::from("square.dat");
+        let mut caster = RayCaster::default();
+        caster.add_part(&alias, &builder);
+        assert!(caster.contains_part(&alias));
+
+        let gl = Rc::new(RecordingBackend::default());
+        {
+            let mut display_list = DisplayList::default();
+            let below = display_list.add(
+                Rc::clone(&gl),
+                alias.clone(),
+                Matrix4::identity(),
+                Material::default(),
+            );
+            let above = display_list.add(
+                Rc::clone(&gl),
+                alias.clone(),
+                Matrix4::from_translation(Vector3::new(0.0, 5.0, 0.0)),
+                Material::default(),
+            );
+            display_list.add(
+                Rc::clone(&gl),
+                PartAlias::from("unknown.dat"),
+                Matrix4::from_translation(Vector3::new(0.0, 8.0, 0.0)),
+                Material::default(),
+            );
+
+            let origin = Vector3::new(0.5, 10.0, 0.5);
+            let hit = caster
+                .cast(&display_list, &origin, &Vector3::new(0.0, -3.0, 0.0))
+                .unwrap();
+            assert_eq!(hit.id, above);
+            assert_eq!(hit.part, alias);
+            assert!((hit.distance - 5.0).abs() < 1e-5);
+            assert!((hit.point - Vector3::new(0.5, 5.0, 0.5)).magnitude() < 1e-5);
+
+            let hit = caster
+                .cast(
+                    &display_list,
+                    &Vector3::new(0.5, 2.0, 0.5),
+                    &-Vector3::unit_y(),
+                )
+                .unwrap();
+            assert_eq!(hit.id, below);
+
+            assert_eq!(
+                caster.cast(&display_list, &origin, &Vector3::unit_y()),
+                None
+            );
+            assert_eq!(
+                caster.cast(
+                    &display_list,
+                    &Vector3::new(2.0, 10.0, 0.5),
+                    &-Vector3::unit_y()
+                ),
+                None
+            );
+
+            // Moved and removed instances are followed.
+            display_list.set_matrix(
+                below,
+                Matrix4::from_translation(Vector3::new(2.0, 0.0, 0.0)),
+            );
+            let down = -Vector3::unit_y();
+            let hit = caster.cast(&display_list, &Vector3::new(2.5, 10.0, 0.5), &down);
+            assert_eq!(hit.unwrap().id, below);
+            display_list.remove(above);
+            let hit = caster.cast(&display_list, &origin, &down);
+            assert_eq!(hit, None);
+            display_list.set_matrix(below, Matrix4::identity());
+            let hit = caster.cast(&display_list, &origin, &down);
+            assert_eq!(hit.unwrap().id, below);
+            let above = display_list.add(
+                Rc::clone(&gl),
+                alias.clone(),
+                Matrix4::from_translation(Vector3::new(0.0, 5.0, 0.0)),
+                Material::default(),
+            );
+
+            let program_manager = ProgramManager::new(Rc::clone(&gl)).unwrap();
+            let mut context = RenderingContext::new(Rc::clone(&gl), program_manager);
+            context.resize(641, 481);
+            context.apply_perspective_camera(&PerspectiveCamera::new(
+                Point3::new(0.5, 30.0, 20.5),
+                Point3::new(0.5, 5.0, 0.5),
+                Deg(45.0),
+            ));
+            let (origin, direction) = context.ray_at(320, 240).unwrap();
+            let hit = caster.cast(&display_list, &origin, &direction).unwrap();
+            assert_eq!(hit.id, above);
+            assert!((hit.point - Vector3::new(0.5, 5.0, 0.5)).magnitude() < 1e-3);
+        }
+        assert!(gl.live.borrow().is_empty());
+    }
+}
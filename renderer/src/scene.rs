@@ -0,0 +1,498 @@
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+    sync::Arc,
+};
+
+use cgmath::SquareMatrix;
+use ldraw::{
+    color::{ColorReference, Material},
+    document::{Document, MultipartDocument},
+    Matrix4, PartAlias,
+};
+
+use crate::{
+    backend::Backend,
+    display_list::{DisplayList, InstanceId, RenderMode},
+};
+
+// Identifies a node of a `Scene`, until it is removed.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct NodeId(usize);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum NodeContent {
+    // Holds other nodes, like a submodel.
+    Group,
+    // Places a part, as one instance of a `DisplayList`.
+    Part(PartAlias),
+}
+
+#[derive(Clone, Debug)]
+pub struct Node {
+    content: NodeContent,
+    matrix: Matrix4,
+    color: ColorReference,
+    visible: bool,
+    mode: RenderMode,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+
+    // Placement, material and render mode as of the last sync, with those
+    // of the parents applied; hidden nodes are drawn as `RenderMode::Hidden`.
+    world: Matrix4,
+    material: Option<Arc<Material>>,
+    drawn: RenderMode,
+    instance: Option<InstanceId>,
+}
+
+impl Node {
+    fn new(
+        content: NodeContent,
+        parent: Option<NodeId>,
+        matrix: Matrix4,
+        color: ColorReference,
+    ) -> Self {
+        Node {
+            content,
+            matrix,
+            color,
+            visible: true,
+            mode: RenderMode::Normal,
+            parent,
+            children: vec![],
+
+            world: Matrix4::identity(),
+            material: None,
+            drawn: RenderMode::Normal,
+            instance: None,
+        }
+    }
+
+    pub fn content(&self) -> &NodeContent {
+        &self.content
+    }
+
+    // Placement relative to the parent.
+    pub fn matrix(&self) -> &Matrix4 {
+        &self.matrix
+    }
+
+    // Color of the node; anything but a material takes that of the parent.
+    pub fn color(&self) -> &ColorReference {
+        &self.color
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    // Render mode of the node; those of the parents take over where they
+    // ghost or hide more.
+    pub fn render_mode(&self) -> RenderMode {
+        self.mode
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+
+    // Instance placing the part of the node, as of the last sync. `None`
+    // for groups; hidden parts keep theirs, drawn as `RenderMode::Hidden`.
+    pub fn instance(&self) -> Option<InstanceId> {
+        self.instance
+    }
+}
+
+// Tree of parts and groups of them, kept in a `DisplayList` by `sync`.
+// Changes to nodes are carried over to the instances of the parts under
+// them on the next sync, leaving the rest of the list as is.
+//
+// The scene owns the instances it places; they are not to be removed from
+// the list by other means.
+#[derive(Debug)]
+pub struct Scene {
+    nodes: Vec<Option<Node>>,
+    free: Vec<usize>,
+
+    dirty: HashSet<NodeId>,
+    removed: Vec<InstanceId>,
+    instances: HashMap<InstanceId, NodeId>,
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Scene {
+            nodes: vec![Some(Node::new(
+                NodeContent::Group,
+                None,
+                Matrix4::identity(),
+                ColorReference::Current,
+            ))],
+            free: vec![],
+
+            dirty: HashSet::from([NodeId(0)]),
+            removed: vec![],
+            instances: HashMap::new(),
+        }
+    }
+}
+
+fn build_scene(scene: &mut Scene, parent: NodeId, document: &Document, root: &MultipartDocument) {
+    for (e, mode) in document.iter_refs_with_mode() {
+        let node = match root.subparts.get(&e.name) {
+            Some(subpart) => {
+                let group = scene.add_group(parent, e.matrix, e.color.clone());
+                build_scene(scene, group, subpart, root);
+                group
+            }
+            None => scene.add_part(parent, e.name.clone(), e.matrix, e.color.clone()),
+        };
+        if let Some(mode) = mode {
+            scene.set_render_mode(node, mode.into());
+        }
+    }
+}
+
+impl Scene {
+    // A group for each submodel placed in `document`, holding its parts.
+    // Nodes behind MLCad prefixes are ghosted or hidden.
+    pub fn from_multipart_document(document: &MultipartDocument) -> Self {
+        let mut scene = Scene::default();
+        let root = scene.root();
+        build_scene(&mut scene, root, &document.body, document);
+        scene
+    }
+
+    pub fn root(&self) -> NodeId {
+        NodeId(0)
+    }
+
+    pub fn node(&self, id: NodeId) -> Option<&Node> {
+        self.nodes.get(id.0).and_then(Option::as_ref)
+    }
+
+    fn node_mut(&mut self, id: NodeId) -> Option<&mut Node> {
+        self.nodes.get_mut(id.0).and_then(Option::as_mut)
+    }
+
+    // Node that placed `instance`, as of the last sync.
+    pub fn node_of(&self, instance: InstanceId) -> Option<NodeId> {
+        self.instances.get(&instance).copied()
+    }
+
+    fn insert(
+        &mut self,
+        parent: NodeId,
+        content: NodeContent,
+        matrix: Matrix4,
+        color: ColorReference,
+    ) -> NodeId {
+        let node = Node::new(content, Some(parent), matrix, color);
+        let id = match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = Some(node);
+                NodeId(index)
+            }
+            None => {
+                self.nodes.push(Some(node));
+                NodeId(self.nodes.len() - 1)
+            }
+        };
+        self.node_mut(parent)
+            .expect("parent of a new node must be in the scene")
+            .children
+            .push(id);
+        self.dirty.insert(id);
+        id
+    }
+
+    pub fn add_group(&mut self, parent: NodeId, matrix: Matrix4, color: ColorReference) -> NodeId {
+        self.insert(parent, NodeContent::Group, matrix, color)
+    }
+
+    pub fn add_part(
+        &mut self,
+        parent: NodeId,
+        alias: PartAlias,
+        matrix: Matrix4,
+        color: ColorReference,
+    ) -> NodeId {
+        self.insert(parent, NodeContent::Part(alias), matrix, color)
+    }
+
+    // Takes out `id` and everything under it. The root stays.
+    pub fn remove(&mut self, id: NodeId) -> bool {
+        if id == self.root() {
+            return false;
+        }
+        let parent = match self.node(id) {
+            Some(e) => e.parent,
+            None => return false,
+        };
+        if let Some(parent) = parent.and_then(|e| self.node_mut(e)) {
+            parent.children.retain(|e| *e != id);
+        }
+
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            let node = self.nodes[id.0].take().unwrap();
+            if let Some(instance) = node.instance {
+                self.instances.remove(&instance);
+                self.removed.push(instance);
+            }
+            self.dirty.remove(&id);
+            self.free.push(id.0);
+            stack.extend(node.children);
+        }
+        true
+    }
+
+    pub fn set_matrix(&mut self, id: NodeId, matrix: Matrix4) -> bool {
+        match self.node_mut(id) {
+            Some(node) => {
+                node.matrix = matrix;
+                self.dirty.insert(id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_color(&mut self, id: NodeId, color: ColorReference) -> bool {
+        match self.node_mut(id) {
+            Some(node) => {
+                node.color = color;
+                self.dirty.insert(id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Shows or hides `id` and everything under it.
+    pub fn set_visible(&mut self, id: NodeId, visible: bool) -> bool {
+        match self.node_mut(id) {
+            Some(node) => {
+                node.visible = visible;
+                self.dirty.insert(id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Draws `id` and everything under it as `mode` has it.
+    pub fn set_render_mode(&mut self, id: NodeId, mode: RenderMode) -> bool {
+        match self.node_mut(id) {
+            Some(node) => {
+                node.mode = mode;
+                self.dirty.insert(id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn has_dirty_ancestor(&self, id: NodeId) -> bool {
+        let mut parent = self.node(id).and_then(|e| e.parent);
+        while let Some(id) = parent {
+            if self.dirty.contains(&id) {
+                return true;
+            }
+            parent = self.node(id).and_then(|e| e.parent);
+        }
+        false
+    }
+
+    // Brings the instances `display_list` has of the scene up to date with
+    // the nodes changed since the last sync.
+    pub fn sync<GL: Backend>(&mut self, gl: Rc<GL>, display_list: &mut DisplayList<GL>) {
+        for instance in self.removed.drain(..) {
+            display_list.remove(instance);
+        }
+
+        // Subtrees of changed nodes, in the order of their IDs.
+        let mut dirty = self
+            .dirty
+            .iter()
+            .filter(|e| !self.has_dirty_ancestor(**e))
+            .copied()
+            .collect::<Vec<_>>();
+        dirty.sort();
+        self.dirty.clear();
+
+        let mut stack = vec![];
+        for id in dirty.into_iter().rev() {
+            let parent = self
+                .node(id)
+                .and_then(|e| e.parent)
+                .and_then(|e| self.node(e));
+            let inherited = match parent {
+                Some(parent) => (parent.world, parent.material.clone(), parent.drawn),
+                None => (Matrix4::identity(), None, RenderMode::Normal),
+            };
+            stack.push((id, inherited));
+        }
+
+        while let Some((id, (world, material, drawn))) = stack.pop() {
+            let node = self.nodes[id.0].as_mut().unwrap();
+            let (last_world, last_material, last_drawn) =
+                (node.world, node.material.take(), node.drawn);
+            node.world = world * node.matrix;
+            node.material = Some(match (&node.color, material) {
+                (ColorReference::Material(m), _) => display_list.share(m),
+                (_, Some(material)) => material,
+                (_, None) => display_list.share(&Material::default()),
+            });
+            node.drawn = match node.visible {
+                true => drawn.max(node.mode),
+                false => RenderMode::Hidden,
+            };
+
+            if let NodeContent::Part(alias) = &node.content {
+                let material = node.material.clone().unwrap();
+                match node.instance {
+                    Some(instance) => {
+                        if node.world != last_world {
+                            display_list.set_matrix(instance, node.world);
+                        }
+                        if !last_material.is_some_and(|e| Arc::ptr_eq(&e, &material)) {
+                            display_list.set_shared_material(instance, material);
+                        }
+                        if node.drawn != last_drawn {
+                            display_list.set_render_mode(instance, node.drawn);
+                        }
+                    }
+                    None => {
+                        let instance = display_list.add_shared(
+                            Rc::clone(&gl),
+                            alias.clone(),
+                            node.world,
+                            material,
+                        );
+                        if node.drawn != RenderMode::Normal {
+                            display_list.set_render_mode(instance, node.drawn);
+                        }
+                        self.instances.insert(instance, id);
+                        node.instance = Some(instance);
+                    }
+                }
+            }
+
+            let inherited = (node.world, node.material.clone(), node.drawn);
+            stack.extend(node.children.iter().rev().map(|e| (*e, inherited.clone())));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use cgmath::Matrix4;
+
+    use ldraw::{
+        color::{ColorReference, Material, Rgba},
+        PartAlias, Vector3,
+    };
+
+    use crate::{display_list::DisplayList, test_support::RecordingBackend};
+
+    use super::Scene;
+
+    #[test]
+    fn test_scene() {
+        let (a, b) = (PartAlias::from("a.dat"), PartAlias::from("b.dat"));
+        let red = Material {
+            code: 4,
+            color: Rgba::new(255, 0, 0, 255),
+            ..Default::default()
+        };
+        let placed = |x: f32| Matrix4::from_translation(Vector3::new(x, 0.0, 0.0));
+
+        let mut scene = Scene::default();
+        let root = scene.root();
+        let group = scene.add_group(root, placed(10.0), ColorReference::Material(red.clone()));
+        let inherited = scene.add_part(group, a.clone(), placed(1.0), ColorReference::Current);
+        scene.add_part(
+            group,
+            a.clone(),
+            placed(2.0),
+            ColorReference::Material(Material::default()),
+        );
+        let alone = scene.add_part(root, b.clone(), placed(0.0), ColorReference::Current);
+
+        let gl = Rc::new(RecordingBackend::default());
+        {
+            let mut display_list = DisplayList::default();
+            scene.sync(Rc::clone(&gl), &mut display_list);
+            assert_eq!(display_list.count(), 3);
+            let instance = scene.node(inherited).unwrap().instance().unwrap();
+            assert_eq!(scene.node_of(instance), Some(inherited));
+            let item = &display_list.map[&a];
+            assert_eq!(item.opaque.ids[0], instance);
+            assert_eq!(item.opaque.model_view_matrices[0], placed(11.0));
+            assert_eq!(*item.opaque.materials[0], red);
+            assert_eq!(*item.opaque.materials[1], Material::default());
+
+            // Moving the group moves its parts and nothing else.
+            scene.set_matrix(group, placed(20.0));
+            let item = display_list.map.get_mut(&b).unwrap();
+            item.opaque.update_buffer(&gl);
+            scene.sync(Rc::clone(&gl), &mut display_list);
+            let item = display_list.map.get_mut(&a).unwrap();
+            assert_eq!(
+                item.opaque.model_view_matrices,
+                vec![placed(21.0), placed(22.0)]
+            );
+            assert_eq!(
+                display_list.map[&b].opaque.model_view_matrices,
+                vec![placed(0.0)]
+            );
+            gl.uploads.borrow_mut().clear();
+            display_list
+                .map
+                .get_mut(&b)
+                .unwrap()
+                .opaque
+                .update_buffer(&gl);
+            assert!(gl.uploads.borrow().is_empty());
+
+            // Hidden parts keep their instances, only not drawn.
+            scene.set_visible(group, false);
+            scene.sync(Rc::clone(&gl), &mut display_list);
+            assert_eq!(display_list.count(), 3);
+            assert_eq!(scene.node(inherited).unwrap().instance(), Some(instance));
+            assert_eq!(scene.node_of(instance), Some(inherited));
+            let item = &display_list.map[&a];
+            assert!(!item.opaque.is_visible(0) && !item.opaque.is_visible(1));
+            assert!(display_list.map[&b].opaque.is_visible(0));
+
+            scene.set_visible(group, true);
+            scene.set_color(group, ColorReference::Current);
+            scene.sync(Rc::clone(&gl), &mut display_list);
+            assert_eq!(display_list.count(), 3);
+            let item = &display_list.map[&a];
+            assert_eq!(item.opaque.ids[0], instance);
+            assert!(item.opaque.is_visible(0) && item.opaque.is_visible(1));
+            assert!(item
+                .opaque
+                .materials
+                .iter()
+                .all(|e| **e == Material::default()));
+
+            assert!(scene.remove(group));
+            assert!(scene.node(inherited).is_none());
+            scene.sync(Rc::clone(&gl), &mut display_list);
+            assert_eq!(display_list.count(), 1);
+            assert!(scene.node(alone).unwrap().instance().is_some());
+            assert!(!scene.remove(root));
+        }
+        assert!(gl.live.borrow().is_empty());
+    }
+}
@@ -0,0 +1,235 @@
+use std::{collections::HashMap, rc::Rc};
+
+use cgmath::SquareMatrix;
+use ldraw::{color::Material, document::MultipartDocument, Matrix4, PartAlias};
+use ldraw_ir::{
+    mesh::BakedPart,
+    section::{section_part, ClipPlane},
+};
+
+use crate::{
+    backend::Backend,
+    display_list::{collect_placements, DisplayList, RenderMode},
+    part::Part,
+};
+
+// What `planes` leave of `part` placed by `matrix`: `Some(None)` if it is
+// kept whole, `Some(Some(_))` with the capped remainder if it is cut, and
+// `None` if nothing is left.
+fn section(part: &BakedPart, matrix: &Matrix4, planes: &[ClipPlane]) -> Option<Option<BakedPart>> {
+    let mut sectioned: Option<BakedPart> = None;
+    for plane in planes {
+        let local = plane.to_local(matrix)?;
+        let current = sectioned.as_ref().unwrap_or(part);
+        let distances = current
+            .bounding_box
+            .points()
+            .map(|e| local.signed_distance(&e));
+        if distances.iter().all(|e| *e <= 0.0) {
+            continue;
+        } else if distances.iter().all(|e| *e > 0.0) {
+            return None;
+        }
+        sectioned = Some(section_part(current, &local)?);
+    }
+    Some(sectioned)
+}
+
+// A model cut by clip planes on the CPU, with the cuts closed by caps, to
+// draw in place of the model where the inside should look solid. Parts the
+// planes pass through are sectioned into parts of their own; the rest
+// share theirs.
+//
+// Caps lie on the planes, so it is drawn without clip planes set.
+pub struct Cutaway<GL: Backend> {
+    pub display_list: DisplayList<GL>,
+    pub parts: HashMap<PartAlias, Part<GL>>,
+}
+
+impl<GL: Backend> Cutaway<GL> {
+    // Sections every part `document` places with `planes`, given in the
+    // coordinates instances are placed in. Parts missing from `baked` and
+    // those cut away entirely are left out.
+    pub fn new(
+        gl: Rc<GL>,
+        document: &MultipartDocument,
+        baked: &HashMap<PartAlias, BakedPart>,
+        planes: &[ClipPlane],
+    ) -> Self {
+        let default = Material::default();
+        let mut placements = vec![];
+        collect_placements(
+            &document.body.commands,
+            Matrix4::identity(),
+            &default,
+            RenderMode::Normal,
+            document,
+            &mut placements,
+        );
+
+        let mut display_list = DisplayList::default();
+        let mut parts = HashMap::new();
+        let mut cuts = 0;
+        for (alias, matrix, material, mode) in placements {
+            let part = match baked.get(alias) {
+                Some(e) => e,
+                None => continue,
+            };
+            let alias = match section(part, &matrix, planes) {
+                Some(None) => {
+                    parts
+                        .entry(alias.clone())
+                        .or_insert_with(|| Part::from_baked(part, Rc::clone(&gl)));
+                    alias.clone()
+                }
+                Some(Some(sectioned)) => {
+                    let alias = PartAlias::from(format!("{}#cut{}", alias, cuts));
+                    cuts += 1;
+                    parts.insert(alias.clone(), Part::from_baked(&sectioned, Rc::clone(&gl)));
+                    alias
+                }
+                None => continue,
+            };
+            let material = display_list.share(material);
+            let id = display_list.add_shared(Rc::clone(&gl), alias, matrix, material);
+            if mode != RenderMode::Normal {
+                display_list.set_render_mode(id, mode);
+            }
+        }
+
+        Cutaway {
+            display_list,
+            parts,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, rc::Rc};
+
+    use cgmath::{InnerSpace, Matrix4};
+
+    use ldraw::{
+        color::ColorReference,
+        document::{DocumentBuilder, MultipartDocument},
+        elements::{Command, PartReference},
+        PartAlias, Vector3, Vector4,
+    };
+    use ldraw_ir::{
+        geometry::BoundingBox3,
+        mesh::{BakedMeshGroup, BakedPart, IndexedMesh},
+        part::{EdgeBufferBuilder, MeshBufferBuilder, OptionalEdgeBufferBuilder},
+        section::{section_part, ClipPlane},
+        MeshGroup,
+    };
+
+    use crate::{state::ProjectionData, test_support::RecordingBackend};
+
+    use super::Cutaway;
+
+    #[test]
+    fn test_clip_planes() {
+        let mut projection_data = ProjectionData {
+            clip_planes: vec![
+                ClipPlane::new(Vector3::unit_x(), Vector3::new(10.0, 0.0, 0.0)),
+                ClipPlane::new(Vector3::unit_z(), Vector3::new(0.0, 0.0, 5.0)),
+            ],
+            ..Default::default()
+        };
+        projection_data
+            .update_view_matrix(&Matrix4::from_translation(Vector3::new(0.0, 0.0, -100.0)));
+        assert_eq!(
+            projection_data.derive_view_clip_planes(),
+            vec![
+                Vector4::new(1.0, 0.0, 0.0, 10.0),
+                Vector4::new(0.0, 0.0, 1.0, -95.0)
+            ]
+        );
+
+        // A 20 LDU cube with outward facing triangles.
+        let mut builder = MeshBufferBuilder::default();
+        for axis in 0..3 {
+            for side in [0.0, 20.0] {
+                let corner = |u: f32, v: f32| {
+                    let mut p = [0.0; 3];
+                    p[axis] = side;
+                    p[(axis + 1) % 3] = u;
+                    p[(axis + 2) % 3] = v;
+                    Vector3::from(p)
+                };
+                let mut outward = Vector3::new(0.0, 0.0, 0.0);
+                outward[axis] = side - 10.0;
+                for [a, b, c] in [
+                    [corner(0.0, 0.0), corner(20.0, 0.0), corner(20.0, 20.0)],
+                    [corner(0.0, 0.0), corner(20.0, 20.0), corner(0.0, 20.0)],
+                ] {
+                    let t = if (b - a).cross(c - a).dot(outward) > 0.0 {
+                        [a, b, c]
+                    } else {
+                        [a, c, b]
+                    };
+                    for p in t {
+                        builder.add(&p, &outward.normalize());
+                    }
+                }
+            }
+        }
+        let alias = PartAlias::from("cube.dat");
+        let cube = BakedPart {
+            groups: vec![BakedMeshGroup {
+                group: MeshGroup {
+                    color_ref: ColorReference::Current,
+                    bfc: true,
+                },
+                mesh: IndexedMesh::from_buffer(&builder),
+                texture: None,
+            }],
+            edges: EdgeBufferBuilder::default(),
+            optional_edges: OptionalEdgeBufferBuilder::default(),
+            bounding_box: BoundingBox3::new(
+                &Vector3::new(0.0, 0.0, 0.0),
+                &Vector3::new(20.0, 20.0, 20.0),
+            ),
+        };
+        let document = MultipartDocument::new(
+            DocumentBuilder::new("model.ldr", "Model", "")
+                .commands([0.0, 30.0, 60.0].map(|x| {
+                    Command::PartReference(PartReference {
+                        color: ColorReference::Current,
+                        matrix: Matrix4::from_translation(Vector3::new(x, 0.0, 0.0)),
+                        name: alias.clone(),
+                    })
+                }))
+                .build(),
+            HashMap::new(),
+        );
+
+        let gl = Rc::new(RecordingBackend::default());
+        {
+            // The first cube stays whole, the second is cut and capped and
+            // the last is cut away.
+            let plane = ClipPlane::new(Vector3::unit_x(), Vector3::new(40.0, 0.0, 0.0));
+            let cutaway = Cutaway::new(
+                Rc::clone(&gl),
+                &document,
+                &HashMap::from([(alias.clone(), cube.clone())]),
+                &[plane],
+            );
+            assert_eq!(cutaway.display_list.count(), 2);
+            let cut = PartAlias::from("cube.dat#cut0");
+            assert!(cutaway.display_list.map.contains_key(&alias));
+            assert!(cutaway.display_list.map.contains_key(&cut));
+            let bounding_box = &cutaway.parts[&cut].bounding_box;
+            assert_eq!(bounding_box.max.x, 10.0);
+            let local = plane
+                .to_local(&Matrix4::from_translation(Vector3::new(30.0, 0.0, 0.0)))
+                .unwrap();
+            assert_eq!(
+                cutaway.parts[&cut].part.mesh.as_ref().unwrap().length,
+                section_part(&cube, &local).unwrap().triangle_count() * 3
+            );
+        }
+        assert!(gl.live.borrow().is_empty());
+    }
+}
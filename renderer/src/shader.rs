@@ -5,30 +5,33 @@ use std::{
 };
 
 use cgmath::prelude::*;
-use glow::HasContext;
 use ldraw::{Vector3, Vector4};
 
 use crate::{
+    backend::{Backend, BufferUsage, Primitive},
     display_list::InstanceBuffer,
     error::ShaderError,
+    light::{LIGHTS_BINDING, MAX_LIGHTS},
     part::{EdgeBuffer, MeshBuffer, OptionalEdgeBuffer},
-    state::{ProjectionData, ShadingData},
+    pipeline::ToneMapping,
+    state::{ProjectionData, ShadingData, MAX_CLIP_PLANES},
 };
 
+// Texture units 1 and 2 are taken by `WeightedCompositeProgram` and unit
+// 1 by `BloomProgram`, `ToneMappingProgram` and `OutlineProgram`.
+const SHADOW_MAP_UNIT: u32 = 3;
+const MAP_UNIT: u32 = 4;
+
 #[derive(Debug)]
-struct Program<GL: HasContext> {
+struct Program<GL: Backend> {
     gl: Rc<GL>, // This is used only when unallocating
 
-    vertex_shader: GL::Shader,
-    fragment_shader: GL::Shader,
     program: GL::Program,
 }
 
-impl<GL: HasContext> Program<GL> {
+impl<GL: Backend> Program<GL> {
     fn use_program(&self) {
-        unsafe {
-            self.gl.use_program(Some(self.program));
-        }
+        self.gl.use_program(Some(self.program));
     }
 }
 
@@ -82,72 +85,66 @@ impl ShaderSource {
     }
 }
 
-impl<GL: HasContext> Program<GL> {
-    fn compile_shader(gl: &GL, src: &ShaderSource, ty: u32) -> Result<GL::Shader, ShaderError> {
-        let shader;
-
-        unsafe {
-            shader = match gl.create_shader(ty) {
-                Ok(v) => v,
-                Err(e) => return Err(ShaderError::CreationError(e)),
-            };
-
-            gl.shader_source(shader, &src.build());
-            gl.compile_shader(shader);
-
-            if !gl.get_shader_compile_status(shader) {
-                Err(ShaderError::CompileError(gl.get_shader_info_log(shader)))
-            } else {
-                Ok(shader)
-            }
-        }
-    }
-
+impl<GL: Backend> Program<GL> {
     fn compile(
         gl: Rc<GL>,
         vertex_shader: &ShaderSource,
         fragment_shader: &ShaderSource,
     ) -> Result<Program<GL>, ShaderError> {
-        let vs = Self::compile_shader(&gl, vertex_shader, glow::VERTEX_SHADER)?;
-        let fs = Self::compile_shader(&gl, fragment_shader, glow::FRAGMENT_SHADER)?;
-
-        unsafe {
-            let program = match gl.create_program() {
-                Ok(v) => v,
-                Err(e) => return Err(ShaderError::CreationError(e)),
-            };
+        let program = gl.compile_program(&vertex_shader.build(), &fragment_shader.build())?;
 
-            gl.attach_shader(program, vs);
-            gl.attach_shader(program, fs);
-            gl.link_program(program);
-
-            if gl.get_program_link_status(program) {
-                Ok(Program {
-                    gl: Rc::clone(&gl),
-                    vertex_shader: vs,
-                    fragment_shader: fs,
-                    program,
-                })
-            } else {
-                Err(ShaderError::LinkError(gl.get_program_info_log(program)))
-            }
-        }
+        Ok(Program {
+            gl: Rc::clone(&gl),
+            program,
+        })
     }
 }
 
-impl<GL: HasContext> Drop for Program<GL> {
+impl<GL: Backend> Drop for Program<GL> {
     fn drop(&mut self) {
-        let gl = &self.gl;
+        self.gl.delete_program(self.program);
+    }
+}
+
+// Uniforms of the clip planes a program cuts away fragments with.
+struct ClipPlaneUniforms<GL: Backend> {
+    planes: Vec<Option<GL::UniformLocation>>,
+    count: Option<GL::UniformLocation>,
+
+    // Planes as of the last upload.
+    local: Vec<Vector4>,
+}
+
+impl<GL: Backend> ClipPlaneUniforms<GL> {
+    fn new(gl: &GL, program: &Program<GL>) -> Self {
+        ClipPlaneUniforms {
+            planes: (0..MAX_CLIP_PLANES)
+                .map(|i| gl.uniform_location(program.program, &format!("clipPlanes[{}]", i)))
+                .collect(),
+            count: gl.uniform_location(program.program, "clipPlaneCount"),
+            local: vec![],
+        }
+    }
+
+    fn upload(&self, gl: &GL, planes: &[Vector4]) {
+        for (location, plane) in self.planes.iter().zip(planes.iter()) {
+            gl.uniform_vec4(location.as_ref(), AsRef::<[f32; 4]>::as_ref(plane));
+        }
+        gl.uniform_i32(self.count.as_ref(), planes.len() as i32);
+    }
 
-        unsafe {
-            gl.delete_shader(self.vertex_shader);
-            gl.delete_shader(self.fragment_shader);
-            gl.delete_program(self.program);
+    // Uploads the clip planes of `projection_data` unless they are the same
+    // as the last ones.
+    fn bind(&mut self, gl: &GL, projection_data: &ProjectionData) {
+        let planes = projection_data.derive_view_clip_planes();
+        if planes != self.local {
+            self.upload(gl, &planes);
+            self.local = planes;
         }
     }
 }
 
-pub struct DefaultProgram<GL: HasContext> {
+pub struct DefaultProgram<GL: Backend> {
     gl: Rc<GL>,
     program: Program<GL>,
 
@@ -159,6 +156,7 @@ pub struct DefaultProgram<GL: HasContext> {
     // Geometry
     position: Option<u32>,
     normal: Option<u32>,
+    uv: Option<u32>,
 
     // Projection for shading
     view_matrix: Option<GL::UniformLocation>,
@@ -166,152 +164,260 @@ pub struct DefaultProgram<GL: HasContext> {
 
     // Instancing
     instanced_model_matrix: Option<u32>,
+    instanced_visibility: Option<u32>,
+    instanced_explosion: Option<u32>,
 
     // Instanced colors
     instanced_color: Option<u32>,
+    instanced_finish: Option<u32>,
+    instanced_fleck: Option<u32>,
+    instanced_emission: Option<u32>,
 
     // Non-instancing
     color: Option<GL::UniformLocation>,
+    finish: Option<GL::UniformLocation>,
+    fleck: Option<GL::UniformLocation>,
+    emission: Option<GL::UniformLocation>,
+
+    // Texturing
+    use_map: Option<GL::UniformLocation>,
+    map: Option<GL::UniformLocation>,
 
     // Shading
     diffuse: Option<GL::UniformLocation>,
     emissive: Option<GL::UniformLocation>,
-    roughness: Option<GL::UniformLocation>,
-    metalness: Option<GL::UniformLocation>,
     opacity: Option<GL::UniformLocation>,
     envmap: Option<GL::UniformLocation>,
-
+    envmap_levels: Option<GL::UniformLocation>,
+    irradiance_sh: Vec<Option<GL::UniformLocation>>,
+    weighted_transparency: Option<GL::UniformLocation>,
+    emissive_only: Option<GL::UniformLocation>,
+    emission_strength: Option<GL::UniformLocation>,
+    tone_mapping: Option<GL::UniformLocation>,
+    exposure: Option<GL::UniformLocation>,
+    linear_output: Option<GL::UniformLocation>,
+    wireframe_width: Option<GL::UniformLocation>,
+    wireframe_fill: Option<GL::UniformLocation>,
+
+    // Shadows
+    shadows_enabled: Option<GL::UniformLocation>,
+    shadow_matrix: Option<GL::UniformLocation>,
+    shadow_map: Option<GL::UniformLocation>,
+
+    clip_planes: ClipPlaneUniforms<GL>,
     local_projection_state: ProjectionData,
     local_shading_state: ShadingData,
 }
 
-impl<GL: HasContext> DefaultProgram<GL> {
+impl<GL: Backend> DefaultProgram<GL> {
     fn new(
         gl: Rc<GL>,
         vertex_shader: &ShaderSource,
         fragment_shader: &ShaderSource,
     ) -> Result<Self, ShaderError> {
         let program = Program::compile(Rc::clone(&gl), vertex_shader, fragment_shader)?;
+        gl.uniform_block_binding(program.program, "Lights", LIGHTS_BINDING);
 
         let cloned_gl = Rc::clone(&gl);
         let gl: &GL = &gl;
 
-        unsafe {
-            Ok(DefaultProgram {
-                gl: cloned_gl,
-
-                projection: gl.get_uniform_location(program.program, "projection"),
-                model_view: gl.get_uniform_location(program.program, "modelView"),
-                normal_matrix: gl.get_uniform_location(program.program, "normalMatrix"),
-
-                position: gl.get_attrib_location(program.program, "position"),
-                normal: gl.get_attrib_location(program.program, "normal"),
-
-                view_matrix: gl.get_uniform_location(program.program, "viewMatrix"),
-                is_orthographic: gl.get_uniform_location(program.program, "isOrthographic"),
-
-                instanced_model_matrix: gl
-                    .get_attrib_location(program.program, "instancedModelMatrix"),
-
-                instanced_color: gl.get_attrib_location(program.program, "instancedColor"),
-
-                color: gl.get_uniform_location(program.program, "color"),
-
-                diffuse: gl.get_uniform_location(program.program, "diffuse"),
-                emissive: gl.get_uniform_location(program.program, "emissive"),
-                roughness: gl.get_uniform_location(program.program, "roughness"),
-                metalness: gl.get_uniform_location(program.program, "metalness"),
-                opacity: gl.get_uniform_location(program.program, "opacity"),
-                envmap: gl.get_uniform_location(program.program, "envMap"),
-
-                program,
+        Ok(DefaultProgram {
+            gl: cloned_gl,
+
+            projection: gl.uniform_location(program.program, "projection"),
+            model_view: gl.uniform_location(program.program, "modelView"),
+            normal_matrix: gl.uniform_location(program.program, "normalMatrix"),
+
+            position: gl.attribute_location(program.program, "position"),
+            normal: gl.attribute_location(program.program, "normal"),
+            uv: gl.attribute_location(program.program, "uv"),
+
+            view_matrix: gl.uniform_location(program.program, "viewMatrix"),
+            is_orthographic: gl.uniform_location(program.program, "isOrthographic"),
+
+            instanced_model_matrix: gl.attribute_location(program.program, "instancedModelMatrix"),
+            instanced_visibility: gl.attribute_location(program.program, "instancedVisibility"),
+            instanced_explosion: gl.attribute_location(program.program, "instancedExplosion"),
+
+            instanced_color: gl.attribute_location(program.program, "instancedColor"),
+            instanced_finish: gl.attribute_location(program.program, "instancedFinish"),
+            instanced_fleck: gl.attribute_location(program.program, "instancedFleck"),
+            instanced_emission: gl.attribute_location(program.program, "instancedEmission"),
+
+            color: gl.uniform_location(program.program, "color"),
+            finish: gl.uniform_location(program.program, "finish"),
+            fleck: gl.uniform_location(program.program, "fleck"),
+            emission: gl.uniform_location(program.program, "emission"),
+
+            use_map: gl.uniform_location(program.program, "useMap"),
+            map: gl.uniform_location(program.program, "map"),
+
+            diffuse: gl.uniform_location(program.program, "diffuse"),
+            emissive: gl.uniform_location(program.program, "emissive"),
+            opacity: gl.uniform_location(program.program, "opacity"),
+            envmap: gl.uniform_location(program.program, "envMap"),
+            envmap_levels: gl.uniform_location(program.program, "envMapLevels"),
+            irradiance_sh: (0..9)
+                .map(|i| gl.uniform_location(program.program, &format!("irradianceSH[{}]", i)))
+                .collect(),
+            weighted_transparency: gl.uniform_location(program.program, "weightedTransparency"),
+            emissive_only: gl.uniform_location(program.program, "emissiveOnly"),
+            emission_strength: gl.uniform_location(program.program, "emissionStrength"),
+            tone_mapping: gl.uniform_location(program.program, "toneMapping"),
+            exposure: gl.uniform_location(program.program, "exposure"),
+            linear_output: gl.uniform_location(program.program, "linearOutput"),
+            wireframe_width: gl.uniform_location(program.program, "wireframeWidth"),
+            wireframe_fill: gl.uniform_location(program.program, "wireframeFill"),
+
+            shadows_enabled: gl.uniform_location(program.program, "shadowsEnabled"),
+            shadow_matrix: gl.uniform_location(program.program, "shadowMatrix"),
+            shadow_map: gl.uniform_location(program.program, "shadowMap"),
+
+            clip_planes: ClipPlaneUniforms::new(gl, &program),
+            program,
 
-                local_projection_state: ProjectionData::default(),
-                local_shading_state: ShadingData {
-                    diffuse: Vector3::zero(),
-                    emissive: Vector3::zero(),
-                    roughness: 0.0,
-                    metalness: 0.0,
-                    opacity: 0.0,
-                },
-            })
-        }
+            local_projection_state: ProjectionData::default(),
+            local_shading_state: ShadingData {
+                diffuse: Vector3::zero(),
+                emissive: Vector3::zero(),
+                opacity: 0.0,
+                weighted_transparency: false,
+                emissive_only: false,
+                emission_strength: 0.0,
+                shadow_matrix: None,
+                tone_mapping: ToneMapping::None,
+                exposure: 0.0,
+                linear_output: false,
+                wireframe: None,
+            },
+        })
     }
 
     fn bind_projection_data(&mut self, projection_data: &ProjectionData) {
         let gl = &self.gl;
-        unsafe {
-            if projection_data.projection != self.local_projection_state.projection {
-                gl.uniform_matrix_4_f32_slice(
-                    self.projection.as_ref(),
-                    false,
-                    AsRef::<[f32; 16]>::as_ref(&projection_data.projection),
-                );
-                self.local_projection_state.projection = projection_data.projection;
-            }
-            if projection_data.model_view != self.local_projection_state.model_view {
-                gl.uniform_matrix_4_f32_slice(
-                    self.model_view.as_ref(),
-                    false,
-                    AsRef::<[f32; 16]>::as_ref(&projection_data.model_view),
-                );
-                self.local_projection_state.model_view = projection_data.model_view;
-            }
-            if projection_data.normal_matrix != self.local_projection_state.normal_matrix {
-                gl.uniform_matrix_3_f32_slice(
-                    self.normal_matrix.as_ref(),
-                    false,
-                    AsRef::<[f32; 9]>::as_ref(&projection_data.normal_matrix),
-                );
-                self.local_projection_state.normal_matrix = projection_data.normal_matrix;
-            }
-            if projection_data.view_matrix != self.local_projection_state.view_matrix {
-                gl.uniform_matrix_4_f32_slice(
-                    self.view_matrix.as_ref(),
-                    false,
-                    AsRef::<[f32; 16]>::as_ref(&projection_data.view_matrix),
-                );
-                self.local_projection_state.view_matrix = projection_data.view_matrix;
-            }
-            if projection_data.orthographic != self.local_projection_state.orthographic {
-                gl.uniform_1_i32(
-                    self.is_orthographic.as_ref(),
-                    if projection_data.orthographic { 1 } else { 0 },
-                );
-                self.local_projection_state.orthographic = projection_data.orthographic;
-            }
+        self.clip_planes.bind(gl, projection_data);
+        if projection_data.projection != self.local_projection_state.projection {
+            gl.uniform_mat4(
+                self.projection.as_ref(),
+                AsRef::<[f32; 16]>::as_ref(&projection_data.projection),
+            );
+            self.local_projection_state.projection = projection_data.projection;
+        }
+        if projection_data.model_view != self.local_projection_state.model_view {
+            gl.uniform_mat4(
+                self.model_view.as_ref(),
+                AsRef::<[f32; 16]>::as_ref(&projection_data.model_view),
+            );
+            self.local_projection_state.model_view = projection_data.model_view;
+        }
+        if projection_data.normal_matrix != self.local_projection_state.normal_matrix {
+            gl.uniform_mat3(
+                self.normal_matrix.as_ref(),
+                AsRef::<[f32; 9]>::as_ref(&projection_data.normal_matrix),
+            );
+            self.local_projection_state.normal_matrix = projection_data.normal_matrix;
+        }
+        if projection_data.view_matrix != self.local_projection_state.view_matrix {
+            gl.uniform_mat4(
+                self.view_matrix.as_ref(),
+                AsRef::<[f32; 16]>::as_ref(&projection_data.view_matrix),
+            );
+            self.local_projection_state.view_matrix = projection_data.view_matrix;
+        }
+        if projection_data.orthographic != self.local_projection_state.orthographic {
+            gl.uniform_i32(
+                self.is_orthographic.as_ref(),
+                if projection_data.orthographic { 1 } else { 0 },
+            );
+            self.local_projection_state.orthographic = projection_data.orthographic;
         }
     }
 
     fn bind_shading_data(&mut self, shading_data: &ShadingData) {
         let gl = &self.gl;
-        unsafe {
-            if shading_data.diffuse != self.local_shading_state.diffuse {
-                gl.uniform_3_f32_slice(
-                    self.diffuse.as_ref(),
-                    AsRef::<[f32; 3]>::as_ref(&shading_data.diffuse),
-                );
-                self.local_shading_state.diffuse = shading_data.diffuse;
-            }
-            if shading_data.emissive != self.local_shading_state.emissive {
-                gl.uniform_3_f32_slice(
-                    self.emissive.as_ref(),
-                    AsRef::<[f32; 3]>::as_ref(&shading_data.emissive),
+        if shading_data.diffuse != self.local_shading_state.diffuse {
+            gl.uniform_vec3(
+                self.diffuse.as_ref(),
+                AsRef::<[f32; 3]>::as_ref(&shading_data.diffuse),
+            );
+            self.local_shading_state.diffuse = shading_data.diffuse;
+        }
+        if shading_data.emissive != self.local_shading_state.emissive {
+            gl.uniform_vec3(
+                self.emissive.as_ref(),
+                AsRef::<[f32; 3]>::as_ref(&shading_data.emissive),
+            );
+            self.local_shading_state.emissive = shading_data.emissive;
+        }
+        if shading_data.opacity != self.local_shading_state.opacity {
+            gl.uniform_f32(self.opacity.as_ref(), shading_data.opacity);
+            self.local_shading_state.opacity = shading_data.opacity;
+        }
+        if shading_data.weighted_transparency != self.local_shading_state.weighted_transparency {
+            gl.uniform_i32(
+                self.weighted_transparency.as_ref(),
+                if shading_data.weighted_transparency {
+                    1
+                } else {
+                    0
+                },
+            );
+            self.local_shading_state.weighted_transparency = shading_data.weighted_transparency;
+        }
+        if shading_data.emissive_only != self.local_shading_state.emissive_only {
+            gl.uniform_i32(
+                self.emissive_only.as_ref(),
+                if shading_data.emissive_only { 1 } else { 0 },
+            );
+            self.local_shading_state.emissive_only = shading_data.emissive_only;
+        }
+        if shading_data.emission_strength != self.local_shading_state.emission_strength {
+            gl.uniform_f32(
+                self.emission_strength.as_ref(),
+                shading_data.emission_strength,
+            );
+            self.local_shading_state.emission_strength = shading_data.emission_strength;
+        }
+        if shading_data.shadow_matrix != self.local_shading_state.shadow_matrix {
+            gl.uniform_i32(
+                self.shadows_enabled.as_ref(),
+                if shading_data.shadow_matrix.is_some() {
+                    1
+                } else {
+                    0
+                },
+            );
+            if let Some(shadow_matrix) = &shading_data.shadow_matrix {
+                gl.uniform_mat4(
+                    self.shadow_matrix.as_ref(),
+                    AsRef::<[f32; 16]>::as_ref(shadow_matrix),
                 );
-                self.local_shading_state.emissive = shading_data.emissive;
-            }
-            if shading_data.roughness != self.local_shading_state.roughness {
-                gl.uniform_1_f32(self.roughness.as_ref(), shading_data.roughness);
-                self.local_shading_state.roughness = shading_data.roughness;
-            }
-            if shading_data.metalness != self.local_shading_state.metalness {
-                gl.uniform_1_f32(self.metalness.as_ref(), shading_data.metalness);
-                self.local_shading_state.metalness = shading_data.metalness;
-            }
-            if shading_data.opacity != self.local_shading_state.opacity {
-                gl.uniform_1_f32(self.opacity.as_ref(), shading_data.opacity);
-                self.local_shading_state.opacity = shading_data.opacity;
             }
+            self.local_shading_state.shadow_matrix = shading_data.shadow_matrix;
+        }
+        if shading_data.tone_mapping != self.local_shading_state.tone_mapping {
+            gl.uniform_i32(self.tone_mapping.as_ref(), shading_data.tone_mapping as i32);
+            self.local_shading_state.tone_mapping = shading_data.tone_mapping;
+        }
+        if shading_data.exposure != self.local_shading_state.exposure {
+            gl.uniform_f32(self.exposure.as_ref(), shading_data.exposure);
+            self.local_shading_state.exposure = shading_data.exposure;
+        }
+        if shading_data.linear_output != self.local_shading_state.linear_output {
+            gl.uniform_i32(
+                self.linear_output.as_ref(),
+                if shading_data.linear_output { 1 } else { 0 },
+            );
+            self.local_shading_state.linear_output = shading_data.linear_output;
+        }
+        if shading_data.wireframe != self.local_shading_state.wireframe {
+            let (width, fill) = match &shading_data.wireframe {
+                Some(e) => (e.width, e.fill),
+                None => (0.0, false),
+            };
+            gl.uniform_f32(self.wireframe_width.as_ref(), width);
+            gl.uniform_i32(self.wireframe_fill.as_ref(), if fill { 1 } else { 0 });
+            self.local_shading_state.wireframe = shading_data.wireframe;
         }
     }
 
@@ -319,13 +425,30 @@ impl<GL: HasContext> DefaultProgram<GL> {
         let gl = &self.gl;
 
         self.program.use_program();
-        unsafe {
-            gl.active_texture(glow::TEXTURE0);
-            gl.bind_texture(glow::TEXTURE_2D, *texture);
-            gl.uniform_1_i32(self.envmap.as_ref(), 0);
+        gl.bind_texture(0, *texture);
+        gl.uniform_i32(self.envmap.as_ref(), 0);
+    }
+
+    // Sets up lighting from the envmap as a prefiltered equirectangular
+    // image with `levels` mipmaps, or as the built-in cube map if zero.
+    pub fn bind_environment(&self, levels: u32, irradiance: &[Vector3; 9]) {
+        let gl = &self.gl;
+
+        self.program.use_program();
+        gl.uniform_f32(self.envmap_levels.as_ref(), levels as f32);
+        for (location, coefficient) in self.irradiance_sh.iter().zip(irradiance.iter()) {
+            gl.uniform_vec3(location.as_ref(), AsRef::<[f32; 3]>::as_ref(coefficient));
         }
     }
 
+    pub fn bind_shadow_map(&self, texture: Option<GL::Texture>) {
+        let gl = &self.gl;
+
+        self.program.use_program();
+        gl.bind_texture(SHADOW_MAP_UNIT, texture);
+        gl.uniform_i32(self.shadow_map.as_ref(), SHADOW_MAP_UNIT as i32);
+    }
+
     pub fn bind<'a>(
         &'a mut self,
         projection_data: &ProjectionData,
@@ -339,12 +462,12 @@ impl<GL: HasContext> DefaultProgram<GL> {
     }
 }
 
-pub struct DefaultProgramBinder<'a, GL: HasContext> {
+pub struct DefaultProgramBinder<'a, GL: Backend> {
     gl: Rc<GL>,
     program: &'a DefaultProgram<GL>,
 }
 
-impl<'a, GL: HasContext> DefaultProgramBinder<'a, GL> {
+impl<'a, GL: Backend> DefaultProgramBinder<'a, GL> {
     fn new(program: &'a DefaultProgram<GL>) -> Self {
         DefaultProgramBinder {
             gl: Rc::clone(&program.gl),
@@ -355,24 +478,23 @@ impl<'a, GL: HasContext> DefaultProgramBinder<'a, GL> {
     pub fn bind_geometry_data(&self, mesh: &MeshBuffer<GL>) -> bool {
         let gl = &self.gl;
         if mesh.buffer_vertices.is_some() && mesh.buffer_normals.is_some() {
-            unsafe {
-                gl.bind_vertex_array(mesh.array);
-
-                gl.bind_buffer(glow::ARRAY_BUFFER, mesh.buffer_vertices);
-                gl.vertex_attrib_pointer_f32(
-                    self.program.position.unwrap(),
-                    3,
-                    glow::FLOAT,
-                    false,
-                    0,
-                    0,
-                );
-                gl.enable_vertex_attrib_array(self.program.position.unwrap());
+            gl.bind_vertex_array(mesh.array);
+
+            gl.vertex_attribute(
+                self.program.position.unwrap(),
+                mesh.buffer_vertices,
+                3,
+                0,
+                0,
+            );
 
-                if let Some(p) = self.program.normal {
-                    gl.bind_buffer(glow::ARRAY_BUFFER, mesh.buffer_normals);
-                    gl.vertex_attrib_pointer_f32(p, 3, glow::FLOAT, false, 0, 0);
-                    gl.enable_vertex_attrib_array(self.program.normal.unwrap());
+            if let Some(p) = self.program.normal {
+                gl.vertex_attribute(p, mesh.buffer_normals, 3, 0, 0);
+            }
+            if let Some(p) = self.program.uv {
+                match mesh.buffer_uvs {
+                    Some(_) => gl.vertex_attribute(p, mesh.buffer_uvs, 2, 0, 0),
+                    None => gl.disable_vertex_attribute(p),
                 }
             }
             true
@@ -381,42 +503,64 @@ impl<'a, GL: HasContext> DefaultProgramBinder<'a, GL> {
         }
     }
 
+    // Draws `texture` over the color where texture coordinates fall inside
+    // of it, or no texture if `None`.
+    pub fn bind_map(&self, texture: Option<GL::Texture>) {
+        let gl = &self.gl;
+
+        gl.uniform_i32(self.program.use_map.as_ref(), texture.is_some() as i32);
+        if texture.is_some() {
+            gl.bind_texture(MAP_UNIT, texture);
+            gl.uniform_i32(self.program.map.as_ref(), MAP_UNIT as i32);
+        }
+    }
+
     pub fn bind_instanced_geometry_data(&self, instance_buffer: &mut InstanceBuffer<GL>) {
         let gl = &self.gl;
 
         instance_buffer.update_buffer(gl);
         if self.program.instanced_model_matrix.is_some() {
             let instanced_model_view = self.program.instanced_model_matrix.unwrap();
-            unsafe {
-                gl.bind_buffer(
-                    glow::ARRAY_BUFFER,
+            for i in 0..4 {
+                gl.vertex_attribute(
+                    instanced_model_view + i,
                     instance_buffer.model_view_matrices_buffer,
+                    4,
+                    4 * 16,
+                    (16 * i) as i32,
                 );
-                for i in 0..4 {
-                    gl.vertex_attrib_pointer_f32(
-                        instanced_model_view + i,
-                        4,
-                        glow::FLOAT,
-                        false,
-                        4 * 16,
-                        (16 * i) as i32,
-                    );
-                    gl.enable_vertex_attrib_array(instanced_model_view + i);
-                    gl.vertex_attrib_divisor(instanced_model_view + i, 1);
-                }
+                gl.vertex_attribute_divisor(instanced_model_view + i, 1);
             }
         }
+        if let Some(instanced_visibility) = self.program.instanced_visibility {
+            gl.vertex_attribute(
+                instanced_visibility,
+                instance_buffer.visibility_buffer,
+                1,
+                0,
+                0,
+            );
+            gl.vertex_attribute_divisor(instanced_visibility, 1);
+        }
+        if let Some(instanced_explosion) = self.program.instanced_explosion {
+            gl.vertex_attribute(
+                instanced_explosion,
+                instance_buffer.explosion_buffer,
+                4,
+                0,
+                0,
+            );
+            gl.vertex_attribute_divisor(instanced_explosion, 1);
+        }
     }
 
     pub fn bind_non_instanced_color_data(&self, color: &Vector4) {
         let gl = &self.gl;
 
-        unsafe {
-            gl.uniform_4_f32_slice(
-                self.program.color.as_ref(),
-                AsRef::<[f32; 4]>::as_ref(color),
-            )
-        }
+        gl.uniform_vec4(
+            self.program.color.as_ref(),
+            AsRef::<[f32; 4]>::as_ref(color),
+        )
     }
 
     pub fn bind_instanced_color_data(&self, instance_buffer: &mut InstanceBuffer<GL>) {
@@ -425,31 +569,95 @@ impl<'a, GL: HasContext> DefaultProgramBinder<'a, GL> {
         instance_buffer.update_buffer(gl);
         if self.program.instanced_color.is_some() && self.program.instanced_color.is_some() {
             let instanced_color = self.program.instanced_color.unwrap();
-            unsafe {
-                gl.bind_buffer(glow::ARRAY_BUFFER, instance_buffer.color_buffer);
-                gl.vertex_attrib_pointer_f32(instanced_color, 4, glow::FLOAT, false, 0, 0);
-                gl.enable_vertex_attrib_array(instanced_color);
-                gl.vertex_attrib_divisor(instanced_color, 1);
-            }
+            gl.vertex_attribute(instanced_color, instance_buffer.color_buffer, 4, 0, 0);
+            gl.vertex_attribute_divisor(instanced_color, 1);
+        }
+    }
+
+    // Shades with `PbrParameters` packed into `finish`.
+    pub fn bind_non_instanced_finish_data(&self, finish: &Vector4) {
+        let gl = &self.gl;
+
+        gl.uniform_vec4(
+            self.program.finish.as_ref(),
+            AsRef::<[f32; 4]>::as_ref(finish),
+        )
+    }
+
+    pub fn bind_instanced_finish_data(&self, instance_buffer: &mut InstanceBuffer<GL>) {
+        let gl = &self.gl;
+
+        instance_buffer.update_buffer(gl);
+        if let Some(instanced_finish) = self.program.instanced_finish {
+            gl.vertex_attribute(instanced_finish, instance_buffer.finish_buffer, 4, 0, 0);
+            gl.vertex_attribute_divisor(instanced_finish, 1);
+        }
+    }
+
+    // Scatters flecks described by `FleckParameters` packed into `fleck`.
+    pub fn bind_non_instanced_fleck_data(&self, fleck: &Vector4) {
+        let gl = &self.gl;
+
+        gl.uniform_vec4(
+            self.program.fleck.as_ref(),
+            AsRef::<[f32; 4]>::as_ref(fleck),
+        )
+    }
+
+    pub fn bind_instanced_fleck_data(&self, instance_buffer: &mut InstanceBuffer<GL>) {
+        let gl = &self.gl;
+
+        instance_buffer.update_buffer(gl);
+        if let Some(instanced_fleck) = self.program.instanced_fleck {
+            gl.vertex_attribute(instanced_fleck, instance_buffer.fleck_buffer, 4, 0, 0);
+            gl.vertex_attribute_divisor(instanced_fleck, 1);
+        }
+    }
+
+    // Gives off light by `emission`; see `pbr::emission`.
+    pub fn bind_non_instanced_emission_data(&self, emission: f32) {
+        let gl = &self.gl;
+
+        gl.uniform_f32(self.program.emission.as_ref(), emission)
+    }
+
+    pub fn bind_instanced_emission_data(&self, instance_buffer: &mut InstanceBuffer<GL>) {
+        let gl = &self.gl;
+
+        instance_buffer.update_buffer(gl);
+        if let Some(instanced_emission) = self.program.instanced_emission {
+            gl.vertex_attribute(instanced_emission, instance_buffer.emission_buffer, 1, 0, 0);
+            gl.vertex_attribute_divisor(instanced_emission, 1);
         }
     }
 }
 
-impl<'a, GL: HasContext> Drop for DefaultProgramBinder<'a, GL> {
+impl<'a, GL: Backend> Drop for DefaultProgramBinder<'a, GL> {
     fn drop(&mut self) {
         let gl = &self.gl;
         if self.program.instanced_model_matrix.is_some() {
             let instanced_model_view = self.program.instanced_model_matrix.unwrap();
-            unsafe {
-                for i in 0..4 {
-                    gl.vertex_attrib_divisor(instanced_model_view + i, 0);
-                }
+            for i in 0..4 {
+                gl.vertex_attribute_divisor(instanced_model_view + i, 0);
             }
         }
+        if let Some(instanced_visibility) = self.program.instanced_visibility {
+            gl.vertex_attribute_divisor(instanced_visibility, 0);
+        }
+        if let Some(instanced_explosion) = self.program.instanced_explosion {
+            gl.vertex_attribute_divisor(instanced_explosion, 0);
+        }
         if let Some(instanced_color) = self.program.instanced_color {
-            unsafe {
-                gl.vertex_attrib_divisor(instanced_color, 0);
-            }
+            gl.vertex_attribute_divisor(instanced_color, 0);
+        }
+        if let Some(instanced_finish) = self.program.instanced_finish {
+            gl.vertex_attribute_divisor(instanced_finish, 0);
+        }
+        if let Some(instanced_fleck) = self.program.instanced_fleck {
+            gl.vertex_attribute_divisor(instanced_fleck, 0);
+        }
+        if let Some(instanced_emission) = self.program.instanced_emission {
+            gl.vertex_attribute_divisor(instanced_emission, 0);
         }
     }
 }
@@ -461,7 +669,7 @@ pub enum DefaultProgramInstancingKind {
     InstancedWithColors,
 }
 
-pub struct EdgeProgram<GL: HasContext> {
+pub struct EdgeProgram<GL: Backend> {
     gl: Rc<GL>,
     program: Program<GL>,
 
@@ -477,15 +685,21 @@ pub struct EdgeProgram<GL: HasContext> {
     instanced_color: Option<u32>,
     instanced_edge_color: Option<u32>,
     instanced_model_matrix: Option<u32>,
+    instanced_visibility: Option<u32>,
+    instanced_explosion: Option<u32>,
 
     // Non-instancing
     default_color: Option<GL::UniformLocation>,
     edge_color: Option<GL::UniformLocation>,
 
+    linear_output: Option<GL::UniformLocation>,
+
+    clip_planes: ClipPlaneUniforms<GL>,
     local_projection_state: ProjectionData,
+    local_linear_output: bool,
 }
 
-impl<GL: HasContext> EdgeProgram<GL> {
+impl<GL: Backend> EdgeProgram<GL> {
     fn new(
         gl: Rc<GL>,
         vertex_shader: &ShaderSource,
@@ -495,66 +709,83 @@ impl<GL: HasContext> EdgeProgram<GL> {
 
         let cloned_gl = Rc::clone(&gl);
 
-        unsafe {
-            Ok(EdgeProgram {
-                gl: cloned_gl,
+        Ok(EdgeProgram {
+            gl: cloned_gl,
 
-                projection: gl.get_uniform_location(program.program, "projection"),
-                model_view: gl.get_uniform_location(program.program, "modelView"),
+            projection: gl.uniform_location(program.program, "projection"),
+            model_view: gl.uniform_location(program.program, "modelView"),
 
-                position: gl.get_attrib_location(program.program, "position"),
-                color: gl.get_attrib_location(program.program, "color"),
+            position: gl.attribute_location(program.program, "position"),
+            color: gl.attribute_location(program.program, "color"),
 
-                instanced_color: gl.get_attrib_location(program.program, "instancedColor"),
-                instanced_edge_color: gl.get_attrib_location(program.program, "instancedEdgeColor"),
-                instanced_model_matrix: gl
-                    .get_attrib_location(program.program, "instancedModelMatrix"),
+            instanced_color: gl.attribute_location(program.program, "instancedColor"),
+            instanced_edge_color: gl.attribute_location(program.program, "instancedEdgeColor"),
+            instanced_model_matrix: gl.attribute_location(program.program, "instancedModelMatrix"),
+            instanced_visibility: gl.attribute_location(program.program, "instancedVisibility"),
+            instanced_explosion: gl.attribute_location(program.program, "instancedExplosion"),
 
-                default_color: gl.get_uniform_location(program.program, "defaultColor"),
-                edge_color: gl.get_uniform_location(program.program, "edgeColor"),
+            default_color: gl.uniform_location(program.program, "defaultColor"),
+            edge_color: gl.uniform_location(program.program, "edgeColor"),
 
-                program,
+            linear_output: gl.uniform_location(program.program, "linearOutput"),
 
-                local_projection_state: ProjectionData::default(),
-            })
-        }
+            clip_planes: ClipPlaneUniforms::new(&gl, &program),
+            program,
+
+            local_projection_state: ProjectionData::default(),
+            local_linear_output: false,
+        })
     }
 
     fn bind_projection_data(&mut self, projection_data: &ProjectionData) {
         let gl = &self.gl;
-        unsafe {
-            if projection_data.projection != self.local_projection_state.projection {
-                gl.uniform_matrix_4_f32_slice(
-                    self.projection.as_ref(),
-                    false,
-                    AsRef::<[f32; 16]>::as_ref(&projection_data.projection),
-                );
-                self.local_projection_state.projection = projection_data.projection;
-            }
-            if projection_data.model_view != self.local_projection_state.model_view {
-                gl.uniform_matrix_4_f32_slice(
-                    self.model_view.as_ref(),
-                    false,
-                    AsRef::<[f32; 16]>::as_ref(&projection_data.model_view),
-                );
-                self.local_projection_state.model_view = projection_data.model_view;
-            }
+        self.clip_planes.bind(gl, projection_data);
+        if projection_data.projection != self.local_projection_state.projection {
+            gl.uniform_mat4(
+                self.projection.as_ref(),
+                AsRef::<[f32; 16]>::as_ref(&projection_data.projection),
+            );
+            self.local_projection_state.projection = projection_data.projection;
+        }
+        if projection_data.model_view != self.local_projection_state.model_view {
+            gl.uniform_mat4(
+                self.model_view.as_ref(),
+                AsRef::<[f32; 16]>::as_ref(&projection_data.model_view),
+            );
+            self.local_projection_state.model_view = projection_data.model_view;
+        }
+    }
+
+    fn bind_linear_output(&mut self, linear_output: bool) {
+        if linear_output != self.local_linear_output {
+            self.gl.uniform_i32(
+                self.linear_output.as_ref(),
+                if linear_output { 1 } else { 0 },
+            );
+            self.local_linear_output = linear_output;
         }
     }
 
-    pub fn bind<'a>(&'a mut self, projection_data: &ProjectionData) -> EdgeProgramBinder<'a, GL> {
+    // Colors are written in linear light if `linear_output` is set, or as
+    // they are otherwise.
+    pub fn bind<'a>(
+        &'a mut self,
+        projection_data: &ProjectionData,
+        linear_output: bool,
+    ) -> EdgeProgramBinder<'a, GL> {
         self.program.use_program();
         self.bind_projection_data(projection_data);
+        self.bind_linear_output(linear_output);
         EdgeProgramBinder::new(self)
     }
 }
 
-pub struct EdgeProgramBinder<'a, GL: HasContext> {
+pub struct EdgeProgramBinder<'a, GL: Backend> {
     gl: Rc<GL>,
     program: &'a EdgeProgram<GL>,
 }
 
-impl<'a, GL: HasContext> EdgeProgramBinder<'a, GL> {
+impl<'a, GL: Backend> EdgeProgramBinder<'a, GL> {
     fn new(program: &'a EdgeProgram<GL>) -> Self {
         EdgeProgramBinder {
             gl: Rc::clone(&program.gl),
@@ -565,31 +796,17 @@ impl<'a, GL: HasContext> EdgeProgramBinder<'a, GL> {
     pub fn bind_attribs(&self, edge: &EdgeBuffer<GL>) -> bool {
         let gl = &self.gl;
         if edge.buffer_vertices.is_some() && edge.buffer_colors.is_some() {
-            unsafe {
-                gl.bind_vertex_array(edge.array);
-
-                gl.bind_buffer(glow::ARRAY_BUFFER, edge.buffer_vertices);
-                gl.vertex_attrib_pointer_f32(
-                    self.program.position.unwrap(),
-                    3,
-                    glow::FLOAT,
-                    false,
-                    0,
-                    0,
-                );
-                gl.enable_vertex_attrib_array(self.program.position.unwrap());
-
-                gl.bind_buffer(glow::ARRAY_BUFFER, edge.buffer_colors);
-                gl.vertex_attrib_pointer_f32(
-                    self.program.color.unwrap(),
-                    3,
-                    glow::FLOAT,
-                    false,
-                    0,
-                    0,
-                );
-                gl.enable_vertex_attrib_array(self.program.color.unwrap());
-            }
+            gl.bind_vertex_array(edge.array);
+
+            gl.vertex_attribute(
+                self.program.position.unwrap(),
+                edge.buffer_vertices,
+                3,
+                0,
+                0,
+            );
+
+            gl.vertex_attribute(self.program.color.unwrap(), edge.buffer_colors, 3, 0, 0);
             true
         } else {
             false
@@ -602,83 +819,91 @@ impl<'a, GL: HasContext> EdgeProgramBinder<'a, GL> {
         instance_buffer.update_buffer(gl);
         if self.program.instanced_model_matrix.is_some() {
             let instanced_model_view = self.program.instanced_model_matrix.unwrap();
-            unsafe {
-                gl.bind_buffer(
-                    glow::ARRAY_BUFFER,
+            for i in 0..4 {
+                gl.vertex_attribute(
+                    instanced_model_view + i,
                     instance_buffer.model_view_matrices_buffer,
+                    4,
+                    4 * 16,
+                    (16 * i) as i32,
                 );
-                for i in 0..4 {
-                    gl.vertex_attrib_pointer_f32(
-                        instanced_model_view + i,
-                        4,
-                        glow::FLOAT,
-                        false,
-                        4 * 16,
-                        (16 * i) as i32,
-                    );
-                    gl.enable_vertex_attrib_array(instanced_model_view + i);
-                    gl.vertex_attrib_divisor(instanced_model_view + i, 1);
-                }
+                gl.vertex_attribute_divisor(instanced_model_view + i, 1);
             }
         }
+        if let Some(instanced_visibility) = self.program.instanced_visibility {
+            gl.vertex_attribute(
+                instanced_visibility,
+                instance_buffer.visibility_buffer,
+                1,
+                0,
+                0,
+            );
+            gl.vertex_attribute_divisor(instanced_visibility, 1);
+        }
+        if let Some(instanced_explosion) = self.program.instanced_explosion {
+            gl.vertex_attribute(
+                instanced_explosion,
+                instance_buffer.explosion_buffer,
+                4,
+                0,
+                0,
+            );
+            gl.vertex_attribute_divisor(instanced_explosion, 1);
+        }
         if let Some(instanced_color) = self.program.instanced_color {
-            unsafe {
-                gl.bind_buffer(glow::ARRAY_BUFFER, instance_buffer.color_buffer);
-                gl.vertex_attrib_pointer_f32(instanced_color, 4, glow::FLOAT, false, 0, 0);
-                gl.enable_vertex_attrib_array(instanced_color);
-                gl.vertex_attrib_divisor(instanced_color, 1);
-            }
+            gl.vertex_attribute(instanced_color, instance_buffer.color_buffer, 4, 0, 0);
+            gl.vertex_attribute_divisor(instanced_color, 1);
         }
         if let Some(instanced_edge_color) = self.program.instanced_edge_color {
-            unsafe {
-                gl.bind_buffer(glow::ARRAY_BUFFER, instance_buffer.edge_color_buffer);
-                gl.vertex_attrib_pointer_f32(instanced_edge_color, 4, glow::FLOAT, false, 0, 0);
-                gl.enable_vertex_attrib_array(instanced_edge_color);
-                gl.vertex_attrib_divisor(instanced_edge_color, 1);
-            }
+            gl.vertex_attribute(
+                instanced_edge_color,
+                instance_buffer.edge_color_buffer,
+                4,
+                0,
+                0,
+            );
+            gl.vertex_attribute_divisor(instanced_edge_color, 1);
         }
     }
 
     pub fn bind_non_instanced_properties(&self, color: &Vector4, edge_color: &Vector4) {
         let gl = &self.gl;
-        unsafe {
-            gl.uniform_4_f32_slice(
-                self.program.default_color.as_ref(),
-                AsRef::<[f32; 4]>::as_ref(&color),
-            );
-            gl.uniform_4_f32_slice(
-                self.program.edge_color.as_ref(),
-                AsRef::<[f32; 4]>::as_ref(&edge_color),
-            );
-        }
+        gl.uniform_vec4(
+            self.program.default_color.as_ref(),
+            AsRef::<[f32; 4]>::as_ref(&color),
+        );
+        gl.uniform_vec4(
+            self.program.edge_color.as_ref(),
+            AsRef::<[f32; 4]>::as_ref(&edge_color),
+        );
     }
 }
 
-impl<'a, GL: HasContext> Drop for EdgeProgramBinder<'a, GL> {
+impl<'a, GL: Backend> Drop for EdgeProgramBinder<'a, GL> {
     fn drop(&mut self) {
         let gl = &self.gl;
         if self.program.instanced_model_matrix.is_some() {
             let instanced_model_view = self.program.instanced_model_matrix.unwrap();
-            unsafe {
-                for i in 0..4 {
-                    gl.vertex_attrib_divisor(instanced_model_view + i, 0);
-                }
+            for i in 0..4 {
+                gl.vertex_attribute_divisor(instanced_model_view + i, 0);
             }
         }
+        if let Some(instanced_visibility) = self.program.instanced_visibility {
+            gl.vertex_attribute_divisor(instanced_visibility, 0);
+        }
+        if let Some(instanced_explosion) = self.program.instanced_explosion {
+            gl.vertex_attribute_divisor(instanced_explosion, 0);
+        }
         if let Some(instanced_color) = self.program.instanced_color {
-            unsafe {
-                gl.vertex_attrib_divisor(instanced_color, 0);
-            }
+            gl.vertex_attribute_divisor(instanced_color, 0);
         }
         if let Some(instanced_edge_color) = self.program.instanced_edge_color {
-            unsafe {
-                gl.vertex_attrib_divisor(instanced_edge_color, 0);
-            }
+            gl.vertex_attribute_divisor(instanced_edge_color, 0);
         }
     }
 }
 
-pub struct OptionalEdgeProgram<GL: HasContext> {
+pub struct OptionalEdgeProgram<GL: Backend> {
     gl: Rc<GL>,
     program: Program<GL>,
 
@@ -697,15 +922,21 @@ pub struct OptionalEdgeProgram<GL: HasContext> {
     instanced_color: Option<u32>,
     instanced_edge_color: Option<u32>,
     instanced_model_matrix: Option<u32>,
+    instanced_visibility: Option<u32>,
+    instanced_explosion: Option<u32>,
 
     // Non-instancing
     default_color: Option<GL::UniformLocation>,
     edge_color: Option<GL::UniformLocation>,
 
+    linear_output: Option<GL::UniformLocation>,
+
+    clip_planes: ClipPlaneUniforms<GL>,
     local_projection_state: ProjectionData,
+    local_linear_output: bool,
 }
 
-impl<GL: HasContext> OptionalEdgeProgram<GL> {
+impl<GL: Backend> OptionalEdgeProgram<GL> {
     fn new(
         gl: Rc<GL>,
         vertex_shader: &ShaderSource,
@@ -716,73 +947,87 @@ impl<GL: HasContext> OptionalEdgeProgram<GL> {
         let cloned_gl = Rc::clone(&gl);
         let gl: &GL = &gl;
 
-        unsafe {
-            Ok(OptionalEdgeProgram {
-                gl: cloned_gl,
+        Ok(OptionalEdgeProgram {
+            gl: cloned_gl,
 
-                projection: gl.get_uniform_location(program.program, "projection"),
-                model_view: gl.get_uniform_location(program.program, "modelView"),
+            projection: gl.uniform_location(program.program, "projection"),
+            model_view: gl.uniform_location(program.program, "modelView"),
 
-                position: gl.get_attrib_location(program.program, "position"),
-                color: gl.get_attrib_location(program.program, "color"),
-                control1: gl.get_attrib_location(program.program, "control1"),
-                control2: gl.get_attrib_location(program.program, "control2"),
-                direction: gl.get_attrib_location(program.program, "direction"),
+            position: gl.attribute_location(program.program, "position"),
+            color: gl.attribute_location(program.program, "color"),
+            control1: gl.attribute_location(program.program, "control1"),
+            control2: gl.attribute_location(program.program, "control2"),
+            direction: gl.attribute_location(program.program, "direction"),
 
-                instanced_color: gl.get_attrib_location(program.program, "instancedColor"),
-                instanced_edge_color: gl.get_attrib_location(program.program, "instancedEdgeColor"),
-                instanced_model_matrix: gl
-                    .get_attrib_location(program.program, "instancedModelMatrix"),
+            instanced_color: gl.attribute_location(program.program, "instancedColor"),
+            instanced_edge_color: gl.attribute_location(program.program, "instancedEdgeColor"),
+            instanced_model_matrix: gl.attribute_location(program.program, "instancedModelMatrix"),
+            instanced_visibility: gl.attribute_location(program.program, "instancedVisibility"),
+            instanced_explosion: gl.attribute_location(program.program, "instancedExplosion"),
 
-                default_color: gl.get_uniform_location(program.program, "defaultColor"),
-                edge_color: gl.get_uniform_location(program.program, "edgeColor"),
+            default_color: gl.uniform_location(program.program, "defaultColor"),
+            edge_color: gl.uniform_location(program.program, "edgeColor"),
 
-                program,
+            linear_output: gl.uniform_location(program.program, "linearOutput"),
 
-                local_projection_state: ProjectionData::default(),
-            })
-        }
+            clip_planes: ClipPlaneUniforms::new(gl, &program),
+            program,
+
+            local_projection_state: ProjectionData::default(),
+            local_linear_output: false,
+        })
     }
 
     fn bind_projection_data(&mut self, projection_data: &ProjectionData) {
         let gl = &self.gl;
-        unsafe {
-            if projection_data.projection != self.local_projection_state.projection {
-                gl.uniform_matrix_4_f32_slice(
-                    self.projection.as_ref(),
-                    false,
-                    AsRef::<[f32; 16]>::as_ref(&projection_data.projection),
-                );
-                self.local_projection_state.projection = projection_data.projection;
-            }
-            if projection_data.model_view != self.local_projection_state.model_view {
-                gl.uniform_matrix_4_f32_slice(
-                    self.model_view.as_ref(),
-                    false,
-                    AsRef::<[f32; 16]>::as_ref(&projection_data.model_view),
-                );
-                self.local_projection_state.model_view = projection_data.model_view;
-            }
+        self.clip_planes.bind(gl, projection_data);
+        if projection_data.projection != self.local_projection_state.projection {
+            gl.uniform_mat4(
+                self.projection.as_ref(),
+                AsRef::<[f32; 16]>::as_ref(&projection_data.projection),
+            );
+            self.local_projection_state.projection = projection_data.projection;
+        }
+        if projection_data.model_view != self.local_projection_state.model_view {
+            gl.uniform_mat4(
+                self.model_view.as_ref(),
+                AsRef::<[f32; 16]>::as_ref(&projection_data.model_view),
+            );
+            self.local_projection_state.model_view = projection_data.model_view;
+        }
+    }
+
+    fn bind_linear_output(&mut self, linear_output: bool) {
+        if linear_output != self.local_linear_output {
+            self.gl.uniform_i32(
+                self.linear_output.as_ref(),
+                if linear_output { 1 } else { 0 },
+            );
+            self.local_linear_output = linear_output;
         }
     }
 
+    // Colors are written in linear light if `linear_output` is set, or as
+    // they are otherwise.
     pub fn bind<'a>(
         &'a mut self,
         projection_data: &ProjectionData,
+        linear_output: bool,
     ) -> OptionalEdgeProgramBinder<'a, GL> {
         self.program.use_program();
         self.bind_projection_data(projection_data);
+        self.bind_linear_output(linear_output);
 
         OptionalEdgeProgramBinder::new(self)
     }
 }
 
-pub struct OptionalEdgeProgramBinder<'a, GL: HasContext> {
+pub struct OptionalEdgeProgramBinder<'a, GL: Backend> {
     gl: Rc<GL>,
     program: &'a OptionalEdgeProgram<GL>,
 }
 
-impl<'a, GL: HasContext> OptionalEdgeProgramBinder<'a, GL> {
+impl<'a, GL: Backend> OptionalEdgeProgramBinder<'a, GL> {
     fn new(program: &'a OptionalEdgeProgram<GL>) -> Self {
         program.program.use_program();
 
@@ -794,57 +1039,47 @@ impl<'a, GL: HasContext> OptionalEdgeProgramBinder<'a, GL> {
 
     pub fn bind_attribs(&self, optional_edge: &OptionalEdgeBuffer<GL>) {
         let gl = &self.gl;
-        unsafe {
-            gl.bind_vertex_array(optional_edge.array);
-
-            gl.bind_buffer(glow::ARRAY_BUFFER, optional_edge.buffer_vertices);
-            gl.vertex_attrib_pointer_f32(
-                self.program.position.unwrap(),
-                3,
-                glow::FLOAT,
-                false,
-                0,
-                0,
-            );
-            gl.enable_vertex_attrib_array(self.program.position.unwrap());
+        gl.bind_vertex_array(optional_edge.array);
+
+        gl.vertex_attribute(
+            self.program.position.unwrap(),
+            optional_edge.buffer_vertices,
+            3,
+            0,
+            0,
+        );
 
-            gl.bind_buffer(glow::ARRAY_BUFFER, optional_edge.buffer_colors);
-            gl.vertex_attrib_pointer_f32(self.program.color.unwrap(), 3, glow::FLOAT, false, 0, 0);
-            gl.enable_vertex_attrib_array(self.program.color.unwrap());
+        gl.vertex_attribute(
+            self.program.color.unwrap(),
+            optional_edge.buffer_colors,
+            3,
+            0,
+            0,
+        );
 
-            gl.bind_buffer(glow::ARRAY_BUFFER, optional_edge.buffer_controls_1);
-            gl.vertex_attrib_pointer_f32(
-                self.program.control1.unwrap(),
-                3,
-                glow::FLOAT,
-                false,
-                0,
-                0,
-            );
-            gl.enable_vertex_attrib_array(self.program.control1.unwrap());
+        gl.vertex_attribute(
+            self.program.control1.unwrap(),
+            optional_edge.buffer_controls_1,
+            3,
+            0,
+            0,
+        );
 
-            gl.bind_buffer(glow::ARRAY_BUFFER, optional_edge.buffer_controls_2);
-            gl.vertex_attrib_pointer_f32(
-                self.program.control2.unwrap(),
-                3,
-                glow::FLOAT,
-                false,
-                0,
-                0,
-            );
-            gl.enable_vertex_attrib_array(self.program.control2.unwrap());
+        gl.vertex_attribute(
+            self.program.control2.unwrap(),
+            optional_edge.buffer_controls_2,
+            3,
+            0,
+            0,
+        );
 
-            gl.bind_buffer(glow::ARRAY_BUFFER, optional_edge.buffer_directions);
-            gl.vertex_attrib_pointer_f32(
-                self.program.direction.unwrap(),
-                3,
-                glow::FLOAT,
-                false,
-                0,
-                0,
-            );
-            gl.enable_vertex_attrib_array(self.program.direction.unwrap());
-        }
+        gl.vertex_attribute(
+            self.program.direction.unwrap(),
+            optional_edge.buffer_directions,
+            3,
+            0,
+            0,
+        );
     }
 
     pub fn bind_instanced_attribs(&self, instance_buffer: &mut InstanceBuffer<GL>) {
@@ -853,83 +1088,533 @@ impl<'a, GL: HasContext> OptionalEdgeProgramBinder<'a, GL> {
         instance_buffer.update_buffer(gl);
         if self.program.instanced_model_matrix.is_some() {
             let instanced_model_view = self.program.instanced_model_matrix.unwrap();
-            unsafe {
-                gl.bind_buffer(
-                    glow::ARRAY_BUFFER,
+            for i in 0..4 {
+                gl.vertex_attribute(
+                    instanced_model_view + i,
                     instance_buffer.model_view_matrices_buffer,
+                    4,
+                    4 * 16,
+                    (16 * i) as i32,
                 );
-                for i in 0..4 {
-                    gl.vertex_attrib_pointer_f32(
-                        instanced_model_view + i,
-                        4,
-                        glow::FLOAT,
-                        false,
-                        4 * 16,
-                        (16 * i) as i32,
-                    );
-                    gl.enable_vertex_attrib_array(instanced_model_view + i);
-                    gl.vertex_attrib_divisor(instanced_model_view + i, 1);
-                }
+                gl.vertex_attribute_divisor(instanced_model_view + i, 1);
             }
         }
+        if let Some(instanced_visibility) = self.program.instanced_visibility {
+            gl.vertex_attribute(
+                instanced_visibility,
+                instance_buffer.visibility_buffer,
+                1,
+                0,
+                0,
+            );
+            gl.vertex_attribute_divisor(instanced_visibility, 1);
+        }
+        if let Some(instanced_explosion) = self.program.instanced_explosion {
+            gl.vertex_attribute(
+                instanced_explosion,
+                instance_buffer.explosion_buffer,
+                4,
+                0,
+                0,
+            );
+            gl.vertex_attribute_divisor(instanced_explosion, 1);
+        }
         if let Some(instanced_color) = self.program.instanced_color {
-            unsafe {
-                gl.bind_buffer(glow::ARRAY_BUFFER, instance_buffer.color_buffer);
-                gl.vertex_attrib_pointer_f32(instanced_color, 4, glow::FLOAT, false, 0, 0);
-                gl.enable_vertex_attrib_array(instanced_color);
-                gl.vertex_attrib_divisor(instanced_color, 1);
-            }
+            gl.vertex_attribute(instanced_color, instance_buffer.color_buffer, 4, 0, 0);
+            gl.vertex_attribute_divisor(instanced_color, 1);
         }
         if let Some(instanced_edge_color) = self.program.instanced_edge_color {
-            unsafe {
-                gl.bind_buffer(glow::ARRAY_BUFFER, instance_buffer.edge_color_buffer);
-                gl.vertex_attrib_pointer_f32(instanced_edge_color, 4, glow::FLOAT, false, 0, 0);
-                gl.enable_vertex_attrib_array(instanced_edge_color);
-                gl.vertex_attrib_divisor(instanced_edge_color, 1);
-            }
+            gl.vertex_attribute(
+                instanced_edge_color,
+                instance_buffer.edge_color_buffer,
+                4,
+                0,
+                0,
+            );
+            gl.vertex_attribute_divisor(instanced_edge_color, 1);
         }
     }
 
     pub fn bind_non_instanced_properties(&self, color: &Vector4, edge_color: &Vector4) {
         let gl = &self.gl;
-        unsafe {
-            gl.uniform_4_f32_slice(
-                self.program.default_color.as_ref(),
-                AsRef::<[f32; 4]>::as_ref(&color),
-            );
-            gl.uniform_4_f32_slice(
-                self.program.edge_color.as_ref(),
-                AsRef::<[f32; 4]>::as_ref(&edge_color),
-            );
-        }
+        gl.uniform_vec4(
+            self.program.default_color.as_ref(),
+            AsRef::<[f32; 4]>::as_ref(&color),
+        );
+        gl.uniform_vec4(
+            self.program.edge_color.as_ref(),
+            AsRef::<[f32; 4]>::as_ref(&edge_color),
+        );
     }
 }
 
-impl<'a, GL: HasContext> Drop for OptionalEdgeProgramBinder<'a, GL> {
+impl<'a, GL: Backend> Drop for OptionalEdgeProgramBinder<'a, GL> {
     fn drop(&mut self) {
         let gl = &self.gl;
         if self.program.instanced_model_matrix.is_some() {
             let instanced_model_view = self.program.instanced_model_matrix.unwrap();
-            unsafe {
-                for i in 0..4 {
-                    gl.vertex_attrib_divisor(instanced_model_view + i, 0);
-                }
+            for i in 0..4 {
+                gl.vertex_attribute_divisor(instanced_model_view + i, 0);
             }
         }
+        if let Some(instanced_visibility) = self.program.instanced_visibility {
+            gl.vertex_attribute_divisor(instanced_visibility, 0);
+        }
+        if let Some(instanced_explosion) = self.program.instanced_explosion {
+            gl.vertex_attribute_divisor(instanced_explosion, 0);
+        }
         if let Some(instanced_color) = self.program.instanced_color {
-            unsafe {
-                gl.vertex_attrib_divisor(instanced_color, 0);
-            }
+            gl.vertex_attribute_divisor(instanced_color, 0);
         }
         if let Some(instanced_edge_color) = self.program.instanced_edge_color {
-            unsafe {
-                gl.vertex_attrib_divisor(instanced_edge_color, 0);
+            gl.vertex_attribute_divisor(instanced_edge_color, 0);
+        }
+    }
+}
+
+// Blends the result of weighted blended transparency over the bound
+// framebuffer with a triangle covering the viewport.
+pub struct WeightedCompositeProgram<GL: Backend> {
+    gl: Rc<GL>,
+    program: Program<GL>,
+
+    accumulation: Option<GL::UniformLocation>,
+    weights: Option<GL::UniformLocation>,
+
+    array: Option<GL::VertexArray>,
+    buffer: Option<GL::Buffer>,
+}
+
+impl<GL: Backend> WeightedCompositeProgram<GL> {
+    fn new(
+        gl: Rc<GL>,
+        vertex_shader: &ShaderSource,
+        fragment_shader: &ShaderSource,
+    ) -> Result<Self, ShaderError> {
+        let program = Program::compile(Rc::clone(&gl), vertex_shader, fragment_shader)?;
+
+        let array = gl.create_vertex_array().ok();
+        let buffer = gl.create_buffer().ok();
+        gl.bind_vertex_array(array);
+        gl.buffer_data(
+            buffer,
+            &[-1.0, -1.0, 3.0, -1.0, -1.0, 3.0],
+            BufferUsage::Static,
+        );
+        if let Some(position) = gl.attribute_location(program.program, "position") {
+            gl.vertex_attribute(position, buffer, 2, 0, 0);
+        }
+        gl.bind_vertex_array(None);
+
+        Ok(WeightedCompositeProgram {
+            gl: Rc::clone(&gl),
+
+            accumulation: gl.uniform_location(program.program, "accumulation"),
+            weights: gl.uniform_location(program.program, "weights"),
+
+            program,
+
+            array,
+            buffer,
+        })
+    }
+
+    // Draws with the two color targets of the transparency pass. Texture
+    // unit 0 is left to the environment map.
+    pub fn composite(&self, accumulation: GL::Texture, weights: GL::Texture) {
+        let gl = &self.gl;
+
+        self.program.use_program();
+        gl.bind_texture(1, Some(accumulation));
+        gl.uniform_i32(self.accumulation.as_ref(), 1);
+        gl.bind_texture(2, Some(weights));
+        gl.uniform_i32(self.weights.as_ref(), 2);
+
+        gl.bind_vertex_array(self.array);
+        gl.draw_arrays(Primitive::Triangles, 0, 3);
+        gl.bind_vertex_array(None);
+    }
+}
+
+impl<GL: Backend> Drop for WeightedCompositeProgram<GL> {
+    fn drop(&mut self) {
+        if let Some(e) = self.array {
+            self.gl.delete_vertex_array(e);
+        }
+        if let Some(e) = self.buffer {
+            self.gl.delete_buffer(e);
+        }
+    }
+}
+
+// Blurs the glow of emissive parts and adds it over the bound framebuffer,
+// with a triangle covering the viewport.
+pub struct BloomProgram<GL: Backend> {
+    gl: Rc<GL>,
+    program: Program<GL>,
+
+    source: Option<GL::UniformLocation>,
+    horizontal: Option<GL::UniformLocation>,
+    upsample: Option<GL::UniformLocation>,
+    scale: Option<GL::UniformLocation>,
+    intensity: Option<GL::UniformLocation>,
+
+    array: Option<GL::VertexArray>,
+    buffer: Option<GL::Buffer>,
+}
+
+impl<GL: Backend> BloomProgram<GL> {
+    fn new(
+        gl: Rc<GL>,
+        vertex_shader: &ShaderSource,
+        fragment_shader: &ShaderSource,
+    ) -> Result<Self, ShaderError> {
+        let program = Program::compile(Rc::clone(&gl), vertex_shader, fragment_shader)?;
+
+        let array = gl.create_vertex_array().ok();
+        let buffer = gl.create_buffer().ok();
+        gl.bind_vertex_array(array);
+        gl.buffer_data(
+            buffer,
+            &[-1.0, -1.0, 3.0, -1.0, -1.0, 3.0],
+            BufferUsage::Static,
+        );
+        if let Some(position) = gl.attribute_location(program.program, "position") {
+            gl.vertex_attribute(position, buffer, 2, 0, 0);
+        }
+        gl.bind_vertex_array(None);
+
+        Ok(BloomProgram {
+            gl: Rc::clone(&gl),
+
+            source: gl.uniform_location(program.program, "source"),
+            horizontal: gl.uniform_location(program.program, "horizontal"),
+            upsample: gl.uniform_location(program.program, "upsample"),
+            scale: gl.uniform_location(program.program, "scale"),
+            intensity: gl.uniform_location(program.program, "intensity"),
+
+            program,
+
+            array,
+            buffer,
+        })
+    }
+
+    fn draw(&self, source: GL::Texture) {
+        let gl = &self.gl;
+
+        gl.bind_texture(1, Some(source));
+        gl.uniform_i32(self.source.as_ref(), 1);
+        gl.bind_vertex_array(self.array);
+        gl.draw_arrays(Primitive::Triangles, 0, 3);
+        gl.bind_vertex_array(None);
+    }
+
+    // Blurs `source` into the bound framebuffer of the same size, along
+    // rows or columns.
+    pub fn blur(&self, source: GL::Texture, horizontal: bool) {
+        self.program.use_program();
+        self.gl.uniform_i32(self.upsample.as_ref(), 0);
+        self.gl
+            .uniform_i32(self.horizontal.as_ref(), if horizontal { 1 } else { 0 });
+        self.draw(source);
+    }
+
+    // Scales `source` up by `scale` times and adds it, by `intensity`.
+    pub fn composite(&self, source: GL::Texture, scale: f32, intensity: f32) {
+        self.program.use_program();
+        self.gl.uniform_i32(self.upsample.as_ref(), 1);
+        self.gl.uniform_f32(self.scale.as_ref(), scale);
+        self.gl.uniform_f32(self.intensity.as_ref(), intensity);
+        self.draw(source);
+    }
+}
+
+impl<GL: Backend> Drop for BloomProgram<GL> {
+    fn drop(&mut self) {
+        if let Some(e) = self.array {
+            self.gl.delete_vertex_array(e);
+        }
+        if let Some(e) = self.buffer {
+            self.gl.delete_buffer(e);
+        }
+    }
+}
+
+// Tone maps a frame drawn in linear light and encodes it to sRGB, with a
+// triangle covering the viewport. Colors of the frame are expected to be
+// premultiplied by its alpha, as they come out of blending over
+// transparent black, and are written the same way.
+pub struct ToneMappingProgram<GL: Backend> {
+    gl: Rc<GL>,
+    program: Program<GL>,
+
+    source: Option<GL::UniformLocation>,
+    tone_mapping: Option<GL::UniformLocation>,
+    exposure: Option<GL::UniformLocation>,
+
+    array: Option<GL::VertexArray>,
+    buffer: Option<GL::Buffer>,
+}
+
+impl<GL: Backend> ToneMappingProgram<GL> {
+    fn new(
+        gl: Rc<GL>,
+        vertex_shader: &ShaderSource,
+        fragment_shader: &ShaderSource,
+    ) -> Result<Self, ShaderError> {
+        let program = Program::compile(Rc::clone(&gl), vertex_shader, fragment_shader)?;
+
+        let array = gl.create_vertex_array().ok();
+        let buffer = gl.create_buffer().ok();
+        gl.bind_vertex_array(array);
+        gl.buffer_data(
+            buffer,
+            &[-1.0, -1.0, 3.0, -1.0, -1.0, 3.0],
+            BufferUsage::Static,
+        );
+        if let Some(position) = gl.attribute_location(program.program, "position") {
+            gl.vertex_attribute(position, buffer, 2, 0, 0);
+        }
+        gl.bind_vertex_array(None);
+
+        Ok(ToneMappingProgram {
+            gl: Rc::clone(&gl),
+
+            source: gl.uniform_location(program.program, "source"),
+            tone_mapping: gl.uniform_location(program.program, "toneMapping"),
+            exposure: gl.uniform_location(program.program, "exposure"),
+
+            program,
+
+            array,
+            buffer,
+        })
+    }
+
+    pub fn tone_map(&self, source: GL::Texture, tone_mapping: ToneMapping, exposure: f32) {
+        let gl = &self.gl;
+
+        self.program.use_program();
+        gl.bind_texture(1, Some(source));
+        gl.uniform_i32(self.source.as_ref(), 1);
+        gl.uniform_i32(self.tone_mapping.as_ref(), tone_mapping as i32);
+        gl.uniform_f32(self.exposure.as_ref(), exposure);
+
+        gl.bind_vertex_array(self.array);
+        gl.draw_arrays(Primitive::Triangles, 0, 3);
+        gl.bind_vertex_array(None);
+    }
+}
+
+impl<GL: Backend> Drop for ToneMappingProgram<GL> {
+    fn drop(&mut self) {
+        if let Some(e) = self.array {
+            self.gl.delete_vertex_array(e);
+        }
+        if let Some(e) = self.buffer {
+            self.gl.delete_buffer(e);
+        }
+    }
+}
+
+// Draws an outline around the silhouettes in a mask over the bound
+// framebuffer, with a triangle covering the viewport.
+pub struct OutlineProgram<GL: Backend> {
+    gl: Rc<GL>,
+    program: Program<GL>,
+
+    mask: Option<GL::UniformLocation>,
+    color: Option<GL::UniformLocation>,
+    thickness: Option<GL::UniformLocation>,
+
+    array: Option<GL::VertexArray>,
+    buffer: Option<GL::Buffer>,
+}
+
+impl<GL: Backend> OutlineProgram<GL> {
+    fn new(
+        gl: Rc<GL>,
+        vertex_shader: &ShaderSource,
+        fragment_shader: &ShaderSource,
+    ) -> Result<Self, ShaderError> {
+        let program = Program::compile(Rc::clone(&gl), vertex_shader, fragment_shader)?;
+
+        let array = gl.create_vertex_array().ok();
+        let buffer = gl.create_buffer().ok();
+        gl.bind_vertex_array(array);
+        gl.buffer_data(
+            buffer,
+            &[-1.0, -1.0, 3.0, -1.0, -1.0, 3.0],
+            BufferUsage::Static,
+        );
+        if let Some(position) = gl.attribute_location(program.program, "position") {
+            gl.vertex_attribute(position, buffer, 2, 0, 0);
+        }
+        gl.bind_vertex_array(None);
+
+        Ok(OutlineProgram {
+            gl: Rc::clone(&gl),
+
+            mask: gl.uniform_location(program.program, "mask"),
+            color: gl.uniform_location(program.program, "color"),
+            thickness: gl.uniform_location(program.program, "thickness"),
+
+            program,
+
+            array,
+            buffer,
+        })
+    }
+
+    // Outlines where alpha of `mask` is set, `thickness` pixels wide.
+    pub fn composite(&self, mask: GL::Texture, color: &Vector4, thickness: u32) {
+        let gl = &self.gl;
+
+        self.program.use_program();
+        gl.bind_texture(1, Some(mask));
+        gl.uniform_i32(self.mask.as_ref(), 1);
+        gl.uniform_vec4(self.color.as_ref(), AsRef::<[f32; 4]>::as_ref(color));
+        gl.uniform_i32(self.thickness.as_ref(), thickness as i32);
+
+        gl.bind_vertex_array(self.array);
+        gl.draw_arrays(Primitive::Triangles, 0, 3);
+        gl.bind_vertex_array(None);
+    }
+}
+
+impl<GL: Backend> Drop for OutlineProgram<GL> {
+    fn drop(&mut self) {
+        if let Some(e) = self.array {
+            self.gl.delete_vertex_array(e);
+        }
+        if let Some(e) = self.buffer {
+            self.gl.delete_buffer(e);
+        }
+    }
+}
+
+// Draws instance IDs for picking. It has a vertex array of its own, so
+// its attributes stay out of those of the meshes.
+pub struct PickingProgram<GL: Backend> {
+    gl: Rc<GL>,
+    program: Program<GL>,
+
+    projection: Option<GL::UniformLocation>,
+    model_view: Option<GL::UniformLocation>,
+
+    position: Option<u32>,
+    instanced_model_matrix: Option<u32>,
+    instanced_visibility: Option<u32>,
+    instanced_explosion: Option<u32>,
+    instanced_id: Option<u32>,
+    clip_planes: ClipPlaneUniforms<GL>,
+
+    array: Option<GL::VertexArray>,
+}
+
+impl<GL: Backend> PickingProgram<GL> {
+    fn new(
+        gl: Rc<GL>,
+        vertex_shader: &ShaderSource,
+        fragment_shader: &ShaderSource,
+    ) -> Result<Self, ShaderError> {
+        let program = Program::compile(Rc::clone(&gl), vertex_shader, fragment_shader)?;
+
+        Ok(PickingProgram {
+            gl: Rc::clone(&gl),
+
+            projection: gl.uniform_location(program.program, "projection"),
+            model_view: gl.uniform_location(program.program, "modelView"),
+
+            position: gl.attribute_location(program.program, "position"),
+            instanced_model_matrix: gl.attribute_location(program.program, "instancedModelMatrix"),
+            instanced_visibility: gl.attribute_location(program.program, "instancedVisibility"),
+            instanced_explosion: gl.attribute_location(program.program, "instancedExplosion"),
+            instanced_id: gl.attribute_location(program.program, "instancedId"),
+            clip_planes: ClipPlaneUniforms::new(&gl, &program),
+
+            program,
+
+            array: gl.create_vertex_array().ok(),
+        })
+    }
+
+    pub fn bind(&self, projection_data: &ProjectionData) {
+        let gl = &self.gl;
+
+        self.program.use_program();
+        gl.uniform_mat4(
+            self.projection.as_ref(),
+            AsRef::<[f32; 16]>::as_ref(&projection_data.projection),
+        );
+        gl.uniform_mat4(
+            self.model_view.as_ref(),
+            AsRef::<[f32; 16]>::as_ref(&projection_data.model_view),
+        );
+        self.clip_planes
+            .upload(gl, &projection_data.derive_view_clip_planes());
+    }
+
+    // Draws every instance of `mesh` in `instance_buffer`.
+    pub fn draw(&self, mesh: &MeshBuffer<GL>, instance_buffer: &mut InstanceBuffer<GL>) {
+        let gl = &self.gl;
+
+        instance_buffer.update_buffer(gl);
+        gl.bind_vertex_array(self.array);
+        if let Some(position) = self.position {
+            gl.vertex_attribute(position, mesh.buffer_vertices, 3, 0, 0);
+        }
+        if let Some(instanced_model_matrix) = self.instanced_model_matrix {
+            for i in 0..4 {
+                gl.vertex_attribute(
+                    instanced_model_matrix + i,
+                    instance_buffer.model_view_matrices_buffer,
+                    4,
+                    4 * 16,
+                    (16 * i) as i32,
+                );
+                gl.vertex_attribute_divisor(instanced_model_matrix + i, 1);
             }
         }
+        if let Some(instanced_visibility) = self.instanced_visibility {
+            gl.vertex_attribute(
+                instanced_visibility,
+                instance_buffer.visibility_buffer,
+                1,
+                0,
+                0,
+            );
+            gl.vertex_attribute_divisor(instanced_visibility, 1);
+        }
+        if let Some(instanced_explosion) = self.instanced_explosion {
+            gl.vertex_attribute(
+                instanced_explosion,
+                instance_buffer.explosion_buffer,
+                4,
+                0,
+                0,
+            );
+            gl.vertex_attribute_divisor(instanced_explosion, 1);
+        }
+        if let Some(instanced_id) = self.instanced_id {
+            gl.vertex_attribute_u32(instanced_id, instance_buffer.id_buffer, 1, 0, 0);
+            gl.vertex_attribute_divisor(instanced_id, 1);
+        }
+        gl.draw_arrays_instanced(Primitive::Triangles, 0, mesh.length, instance_buffer.count);
+        gl.bind_vertex_array(None);
+    }
+}
+
+impl<GL: Backend> Drop for PickingProgram<GL> {
+    fn drop(&mut self) {
+        if let Some(e) = self.array {
+            self.gl.delete_vertex_array(e);
+        }
     }
 }
 
-pub struct ProgramManager<GL: HasContext> {
+pub struct ProgramManager<GL: Backend> {
     pub default: DefaultProgram<GL>,
     pub default_instanced: DefaultProgram<GL>,
     pub default_instanced_with_colors: DefaultProgram<GL>,
@@ -943,13 +1628,21 @@ pub struct ProgramManager<GL: HasContext> {
 
     pub optional_edge: OptionalEdgeProgram<GL>,
     pub optional_edge_instanced: OptionalEdgeProgram<GL>,
+
+    pub weighted_composite: WeightedCompositeProgram<GL>,
+    pub bloom: BloomProgram<GL>,
+    pub tone_mapping: ToneMappingProgram<GL>,
+    pub outline: OutlineProgram<GL>,
+    pub picking: PickingProgram<GL>,
 }
 
-impl<GL: HasContext> ProgramManager<GL> {
+impl<GL: Backend> ProgramManager<GL> {
     pub fn new(gl: Rc<GL>) -> Result<ProgramManager<GL>, ShaderError> {
         let default_fs = ShaderSource::new(
             String::from_utf8(include_bytes!("../shaders/default.fs").to_vec()).unwrap(),
-        );
+        )
+        .with_value("MAX_LIGHTS", MAX_LIGHTS.to_string())
+        .with_value("MAX_CLIP_PLANES", MAX_CLIP_PLANES.to_string());
         let default_vs = ShaderSource::new(
             String::from_utf8(include_bytes!("../shaders/default.vs").to_vec()).unwrap(),
         );
@@ -1001,7 +1694,8 @@ impl<GL: HasContext> ProgramManager<GL> {
 
         let edge_fs = ShaderSource::new(
             String::from_utf8(include_bytes!("../shaders/edge.fs").to_vec()).unwrap(),
-        );
+        )
+        .with_value("MAX_CLIP_PLANES", MAX_CLIP_PLANES.to_string());
         let edge_vs = ShaderSource::new(
             String::from_utf8(include_bytes!("../shaders/edge.vs").to_vec()).unwrap(),
         );
@@ -1015,7 +1709,8 @@ impl<GL: HasContext> ProgramManager<GL> {
 
         let optional_edge_fs = ShaderSource::new(
             String::from_utf8(include_bytes!("../shaders/optional_edge.fs").to_vec()).unwrap(),
-        );
+        )
+        .with_value("MAX_CLIP_PLANES", MAX_CLIP_PLANES.to_string());
         let optional_edge_vs = ShaderSource::new(
             String::from_utf8(include_bytes!("../shaders/optional_edge.vs").to_vec()).unwrap(),
         );
@@ -1028,6 +1723,62 @@ impl<GL: HasContext> ProgramManager<GL> {
             &optional_edge_fs.with_flag("USE_INSTANCING"),
         )?;
 
+        let weighted_composite = WeightedCompositeProgram::new(
+            Rc::clone(&gl),
+            &ShaderSource::new(
+                String::from_utf8(include_bytes!("../shaders/weighted_composite.vs").to_vec())
+                    .unwrap(),
+            ),
+            &ShaderSource::new(
+                String::from_utf8(include_bytes!("../shaders/weighted_composite.fs").to_vec())
+                    .unwrap(),
+            ),
+        )?;
+
+        let bloom = BloomProgram::new(
+            Rc::clone(&gl),
+            &ShaderSource::new(
+                String::from_utf8(include_bytes!("../shaders/weighted_composite.vs").to_vec())
+                    .unwrap(),
+            ),
+            &ShaderSource::new(
+                String::from_utf8(include_bytes!("../shaders/bloom.fs").to_vec()).unwrap(),
+            ),
+        )?;
+
+        let tone_mapping = ToneMappingProgram::new(
+            Rc::clone(&gl),
+            &ShaderSource::new(
+                String::from_utf8(include_bytes!("../shaders/weighted_composite.vs").to_vec())
+                    .unwrap(),
+            ),
+            &ShaderSource::new(
+                String::from_utf8(include_bytes!("../shaders/tonemap.fs").to_vec()).unwrap(),
+            ),
+        )?;
+
+        let outline = OutlineProgram::new(
+            Rc::clone(&gl),
+            &ShaderSource::new(
+                String::from_utf8(include_bytes!("../shaders/weighted_composite.vs").to_vec())
+                    .unwrap(),
+            ),
+            &ShaderSource::new(
+                String::from_utf8(include_bytes!("../shaders/outline.fs").to_vec()).unwrap(),
+            ),
+        )?;
+
+        let picking = PickingProgram::new(
+            Rc::clone(&gl),
+            &ShaderSource::new(
+                String::from_utf8(include_bytes!("../shaders/picking.vs").to_vec()).unwrap(),
+            ),
+            &ShaderSource::new(
+                String::from_utf8(include_bytes!("../shaders/picking.fs").to_vec()).unwrap(),
+            )
+            .with_value("MAX_CLIP_PLANES", MAX_CLIP_PLANES.to_string()),
+        )?;
+
         Ok(ProgramManager {
             default,
             default_instanced,
@@ -1041,6 +1792,12 @@ impl<GL: HasContext> ProgramManager<GL> {
 
             optional_edge,
             optional_edge_instanced,
+
+            weighted_composite,
+            bloom,
+            tone_mapping,
+            outline,
+            picking,
         })
     }
 
@@ -1086,4 +1843,17 @@ impl<GL: HasContext> ProgramManager<GL> {
         self.default_instanced.bind_envmap(texture);
         self.default_instanced_with_colors.bind_envmap(texture);
     }
+
+    pub fn bind_environment(&self, levels: u32, irradiance: &[Vector3; 9]) {
+        self.default.bind_environment(levels, irradiance);
+        self.default_instanced.bind_environment(levels, irradiance);
+        self.default_instanced_with_colors
+            .bind_environment(levels, irradiance);
+    }
+
+    pub fn bind_shadow_map(&self, texture: Option<GL::Texture>) {
+        self.default.bind_shadow_map(texture);
+        self.default_instanced.bind_shadow_map(texture);
+        self.default_instanced_with_colors.bind_shadow_map(texture);
+    }
 }
@@ -1,18 +1,42 @@
-use std::{collections::HashMap, rc::Rc, vec::Vec};
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+    vec::Vec,
+};
 
 use cgmath::{prelude::*, Deg, Ortho, PerspectiveFov, Point3, Rad, SquareMatrix};
-use glow::HasContext;
 use image::{load_from_memory_with_format, ImageFormat};
-use ldraw::{color::Material, Matrix3, Matrix4, PartAlias, Vector2, Vector3, Vector4};
-use ldraw_ir::geometry::{BoundingBox2, BoundingBox3};
+use ldraw::{
+    color::{ColorReference, Material},
+    Matrix3, Matrix4, PartAlias, Vector2, Vector3, Vector4,
+};
+use ldraw_ir::{
+    geometry::{BoundingBox2, BoundingBox3},
+    part::SubpartIndex,
+    section::ClipPlane,
+};
 
 use crate::{
-    display_list::{DisplayItem, DisplayList},
-    part::Part,
-    shader::{DefaultProgramInstancingKind, ProgramManager},
+    backend::{Backend, Blending, Primitive, TextureFormat},
+    camera::CameraAnimation,
+    display_list::{DisplayItem, DisplayList, InstanceId},
+    environment::Environment,
+    light::{LightKind, Lights, LightsBuffer},
+    part::{Part, PartBuffer},
+    pbr::{emission, FleckParameters, PbrParameters},
+    pipeline::{
+        BloomTarget, HdrTarget, MultisampleTarget, OutlineTarget, PickingTarget, PipelineConfig,
+        ShadowMapTarget, ToneMapping, Transparency, WeightedBlendedTarget, Wireframe,
+        MAX_OUTLINE_THICKNESS,
+    },
+    shader::{DefaultProgramBinder, DefaultProgramInstancingKind, ProgramManager},
+    texture::Textures,
     utils::derive_normal_matrix,
 };
 
+// Clip planes the shaders take; any more are ignored.
+pub const MAX_CLIP_PLANES: usize = 4;
+
 pub struct ProjectionData {
     pub projection: Matrix4,
     pub model_matrix: Vec<Matrix4>,
@@ -20,6 +44,9 @@ pub struct ProjectionData {
     pub normal_matrix: Matrix3,
     pub view_matrix: Matrix4,
     pub orthographic: bool,
+    // Planes cutting away what lies on the side their normals point to,
+    // in the coordinates instances are placed in.
+    pub clip_planes: Vec<ClipPlane>,
 }
 
 impl Default for ProjectionData {
@@ -31,6 +58,7 @@ impl Default for ProjectionData {
             normal_matrix: Matrix3::identity(),
             view_matrix: Matrix4::identity(),
             orthographic: false,
+            clip_planes: vec![],
         }
     }
 }
@@ -40,6 +68,24 @@ impl ProjectionData {
         derive_normal_matrix(&self.model_view)
     }
 
+    // The first `MAX_CLIP_PLANES` clip planes in view space, as normal and
+    // distance.
+    pub fn derive_view_clip_planes(&self) -> Vec<Vector4> {
+        let inverse = match self.view_matrix.invert() {
+            Some(e) => e.transpose(),
+            None => return vec![],
+        };
+        self.clip_planes
+            .iter()
+            .take(MAX_CLIP_PLANES)
+            .map(|e| {
+                let plane = inverse * e.normal.extend(-e.distance);
+                let length = plane.truncate().magnitude();
+                plane.truncate().extend(-plane.w) / length
+            })
+            .collect()
+    }
+
     pub fn update_projection_matrix(&mut self, proj: &Matrix4) {
         self.projection = *proj;
     }
@@ -85,9 +131,23 @@ impl ProjectionData {
 pub struct ShadingData {
     pub diffuse: Vector3,
     pub emissive: Vector3,
-    pub roughness: f32,
-    pub metalness: f32,
     pub opacity: f32,
+    // Writes into the targets of weighted blended transparency.
+    pub weighted_transparency: bool,
+    // Writes nothing but the light materials give off, for bloom.
+    pub emissive_only: bool,
+    // Light given off by materials of `LUMINANCE` 255, relative to their
+    // color.
+    pub emission_strength: f32,
+    // From view space to shadow map coordinates, while shadows are cast.
+    pub shadow_matrix: Option<Matrix4>,
+    pub tone_mapping: ToneMapping,
+    pub exposure: f32,
+    // Leaves colors in linear light instead of tone mapping and encoding
+    // them to sRGB, for a later pass to do so.
+    pub linear_output: bool,
+    // Lines of triangles drawn in place of the faces, if any.
+    pub wireframe: Option<Wireframe>,
 }
 
 impl Default for ShadingData {
@@ -95,18 +155,30 @@ impl Default for ShadingData {
         ShadingData {
             diffuse: Vector3::new(1.0, 1.0, 1.0),
             emissive: Vector3::zero(),
-            roughness: 0.3,
-            metalness: 0.0,
             opacity: 1.0,
+            weighted_transparency: false,
+            emissive_only: false,
+            emission_strength: 8.0,
+            shadow_matrix: None,
+            tone_mapping: ToneMapping::None,
+            exposure: 1.0,
+            linear_output: false,
+            wireframe: None,
         }
     }
 }
 
+#[derive(Clone, Debug)]
 pub struct PerspectiveCamera {
     pub position: Point3<f32>,
     pub look_at: Point3<f32>,
     pub up: Vector3,
+    // Vertical field of view.
     pub fov: Deg<f32>,
+    pub near: f32,
+    pub far: f32,
+    // Width over height of the image; follows the viewport if `None`.
+    pub aspect_ratio: Option<f32>,
 }
 
 impl PerspectiveCamera {
@@ -116,17 +188,32 @@ impl PerspectiveCamera {
             look_at,
             up: Vector3::new(0.0, -1.0, 0.0),
             fov,
+            near: 10.0,
+            far: 100000.0,
+            aspect_ratio: None,
         }
     }
 
-    pub fn derive_projection_matrix(&self, width: usize, height: usize) -> Matrix4 {
-        let aspect_ratio = width as f32 / height as f32;
+    pub fn derive_aspect_ratio(&self, width: usize, height: usize) -> f32 {
+        match self.aspect_ratio {
+            Some(e) => e,
+            None if width == 0 || height == 0 => 1.0,
+            None => width as f32 / height as f32,
+        }
+    }
+
+    // Horizontal field of view at the given viewport size.
+    pub fn derive_horizontal_fov(&self, width: usize, height: usize) -> Deg<f32> {
+        let half = Rad::from(self.fov) / 2.0;
+        Deg::from(Rad::atan(half.tan() * self.derive_aspect_ratio(width, height)) * 2.0)
+    }
 
+    pub fn derive_projection_matrix(&self, width: usize, height: usize) -> Matrix4 {
         Matrix4::from(PerspectiveFov {
             fovy: Rad::from(self.fov),
-            aspect: aspect_ratio,
-            near: 10.0,
-            far: 100000.0,
+            aspect: self.derive_aspect_ratio(width, height),
+            near: self.near,
+            far: self.far,
         })
     }
 
@@ -186,7 +273,50 @@ impl OrthographicCamera {
     }
 }
 
-pub struct RenderingContext<GL: HasContext> {
+// Finish of a subpart group with a color of its own.
+fn group_finish(color_ref: &ColorReference) -> Vector4 {
+    let parameters = color_ref
+        .get_material()
+        .map(|e| PbrParameters::from(&e.finish))
+        .unwrap_or_default();
+    Vector4::from(&parameters)
+}
+
+// Glitter or speckle of a subpart group with a color of its own.
+fn group_fleck(color_ref: &ColorReference) -> Vector4 {
+    let parameters = color_ref
+        .get_material()
+        .map(|e| FleckParameters::from(&e.finish))
+        .unwrap_or_default();
+    Vector4::from(&parameters)
+}
+
+fn group_emission(color_ref: &ColorReference) -> f32 {
+    color_ref.get_material().map(emission).unwrap_or(0.0)
+}
+
+// Draws triangles of `index` in runs sharing a texture, `instances` times if
+// instanced. Textures not loaded are left out, showing the plain color.
+fn draw_triangles<GL: Backend>(
+    gl: &Rc<GL>,
+    bind: &DefaultProgramBinder<GL>,
+    textures: &Textures<GL>,
+    part_buffer: &PartBuffer<GL>,
+    index: &SubpartIndex,
+    instances: Option<usize>,
+) {
+    for (run, texture) in part_buffer.texture_runs(index) {
+        bind.bind_map(texture.and_then(|e| textures.get(&e.texture)));
+        match instances {
+            Some(count) => {
+                gl.draw_arrays_instanced(Primitive::Triangles, run.start, run.span, count)
+            }
+            None => gl.draw_arrays(Primitive::Triangles, run.start, run.span),
+        }
+    }
+}
+
+pub struct RenderingContext<GL: Backend> {
     gl: Rc<GL>,
 
     pub program_manager: ProgramManager<GL>,
@@ -195,8 +325,29 @@ pub struct RenderingContext<GL: HasContext> {
 
     pub projection_data: ProjectionData,
     pub shading_data: ShadingData,
+    pub lights: Lights,
+    lights_buffer: Option<LightsBuffer<GL>>,
+
+    pub pipeline: PipelineConfig,
+    // Framebuffer the context draws into; `None` for the default one.
+    pub framebuffer: Option<GL::Framebuffer>,
+    weighted_target: Option<WeightedBlendedTarget<GL>>,
+    shadow_target: Option<ShadowMapTarget<GL>>,
+    bloom_target: Option<BloomTarget<GL>>,
+    multisample_target: Option<MultisampleTarget<GL>>,
+    hdr_target: Option<HdrTarget<GL>>,
+    outline_target: Option<OutlineTarget<GL>>,
+    picking_target: Option<PickingTarget<GL>>,
+    // What the frame is composited over once tone mapped.
+    clear_color: Vector4,
+    textures: Textures<GL>,
 
     envmap: Option<GL::Texture>,
+    // Zero while the envmap is the fallback cube map.
+    envmap_levels: u32,
+    irradiance: [Vector3; 9],
+
+    camera_animation: Option<(CameraAnimation, Option<f32>)>,
 }
 
 fn load_envmap() -> Vec<u8> {
@@ -207,45 +358,22 @@ fn load_envmap() -> Vec<u8> {
     rgba.into_raw()
 }
 
-impl<GL: HasContext> RenderingContext<GL> {
+impl<GL: Backend> RenderingContext<GL> {
     pub fn new(gl: Rc<GL>, program_manager: ProgramManager<GL>) -> Self {
-        let envmap = unsafe {
-            let envmap = match gl.create_texture() {
-                Ok(e) => Some(e),
-                Err(msg) => {
-                    println!("Failed creating envmap texture: {}", msg);
-                    None
-                }
-            };
-            gl.bind_texture(glow::TEXTURE_2D, envmap);
-            gl.tex_image_2d(
-                glow::TEXTURE_2D,
-                0,
-                glow::RGBA as i32,
-                768,
-                768,
-                0,
-                glow::RGBA,
-                glow::UNSIGNED_BYTE,
-                Some(&load_envmap()),
-            );
-            gl.tex_parameter_i32(
-                glow::TEXTURE_2D,
-                glow::TEXTURE_WRAP_S,
-                glow::CLAMP_TO_EDGE as i32,
-            );
-            gl.tex_parameter_i32(
-                glow::TEXTURE_2D,
-                glow::TEXTURE_WRAP_T,
-                glow::CLAMP_TO_EDGE as i32,
-            );
-            gl.tex_parameter_i32(
-                glow::TEXTURE_2D,
-                glow::TEXTURE_MIN_FILTER,
-                glow::LINEAR as i32,
-            );
+        let envmap = match gl.create_texture(768, 768, &load_envmap()) {
+            Ok(e) => Some(e),
+            Err(msg) => {
+                println!("Failed creating envmap texture: {}", msg);
+                None
+            }
+        };
 
-            envmap
+        let lights_buffer = match LightsBuffer::new(Rc::clone(&gl)) {
+            Ok(e) => Some(e),
+            Err(msg) => {
+                println!("Failed creating lights buffer: {}", msg);
+                None
+            }
         };
 
         RenderingContext {
@@ -255,7 +383,23 @@ impl<GL: HasContext> RenderingContext<GL> {
             height: 1,
             projection_data: ProjectionData::default(),
             shading_data: ShadingData::default(),
+            lights: Lights::default(),
+            lights_buffer,
+            pipeline: PipelineConfig::default(),
+            framebuffer: None,
+            weighted_target: None,
+            shadow_target: None,
+            bloom_target: None,
+            multisample_target: None,
+            hdr_target: None,
+            outline_target: None,
+            picking_target: None,
+            clear_color: Vector4::zero(),
+            textures: Textures::new(Rc::clone(&gl)),
             envmap,
+            envmap_levels: 0,
+            irradiance: [Vector3::zero(); 9],
+            camera_animation: None,
         }
     }
 
@@ -268,6 +412,33 @@ impl<GL: HasContext> RenderingContext<GL> {
         self.projection_data.orthographic = false;
     }
 
+    // Plays `animation` from the next call to `update_camera_animation`.
+    pub fn play_camera_animation(&mut self, animation: CameraAnimation) {
+        self.camera_animation = Some((animation, None));
+    }
+
+    pub fn stop_camera_animation(&mut self) {
+        self.camera_animation = None;
+    }
+
+    pub fn is_animating_camera(&self) -> bool {
+        self.camera_animation.is_some()
+    }
+
+    // Applies the camera of the playing animation at `time` seconds on
+    // the caller's clock. Returns the camera, or `None` if no animation
+    // is playing; the animation stops once it is over.
+    pub fn update_camera_animation(&mut self, time: f32) -> Option<PerspectiveCamera> {
+        let (animation, started_at) = self.camera_animation.as_mut()?;
+        let elapsed = time - *started_at.get_or_insert(time);
+        let camera = animation.camera_at(elapsed)?;
+        if animation.is_finished(elapsed) {
+            self.camera_animation = None;
+        }
+        self.apply_perspective_camera(&camera);
+        Some(camera)
+    }
+
     pub fn apply_orthographic_camera(
         &mut self,
         camera: &OrthographicCamera,
@@ -344,39 +515,49 @@ impl<GL: HasContext> RenderingContext<GL> {
         fraction
     }
 
+    // Lights parts with `environment` in place of the cube map or the
+    // studio.
+    pub fn set_environment(&mut self, environment: &Environment) -> Result<(), String> {
+        let texture = self.gl.create_environment_texture(
+            environment.width,
+            environment.height,
+            &environment.specular,
+        )?;
+        if let Some(e) = self.envmap.replace(texture) {
+            self.gl.delete_texture(e);
+        }
+        self.envmap_levels = environment.specular.len() as u32;
+        self.irradiance = environment.irradiance;
+        self.upload_shading_data();
+        Ok(())
+    }
+
+    // Uploads image file `bytes` as texture `name` for `!TEXMAP`
+    // statements to draw.
+    pub fn add_texture(&mut self, name: &str, bytes: &[u8]) -> Result<(), String> {
+        self.textures.insert(name, bytes)
+    }
+
+    pub fn has_texture(&self, name: &str) -> bool {
+        self.textures.contains(name)
+    }
+
     pub fn upload_shading_data(&self) {
         self.program_manager.bind_envmap(&self.envmap);
+        self.program_manager
+            .bind_environment(self.envmap_levels, &self.irradiance);
+        self.program_manager
+            .bind_shadow_map(self.shadow_target.as_ref().map(|e| e.depth));
     }
 
     pub fn set_initial_state(&self) {
-        let gl = &self.gl;
-        unsafe {
-            gl.clear_color(1.0, 1.0, 1.0, 0.0);
-            gl.clear_depth_f32(1.0);
-            gl.line_width(1.0);
-            gl.cull_face(glow::BACK);
-            gl.enable(glow::CULL_FACE);
-            gl.enable(glow::DEPTH_TEST);
-            gl.enable(glow::BLEND);
-            gl.depth_func(glow::LEQUAL);
-            gl.blend_func_separate(
-                glow::SRC_ALPHA,
-                glow::ONE_MINUS_SRC_ALPHA,
-                glow::ONE,
-                glow::ONE_MINUS_SRC_ALPHA,
-            );
-            gl.blend_equation(glow::FUNC_ADD);
-            gl.polygon_offset(1.0, 0.0);
-            gl.enable(glow::POLYGON_OFFSET_FILL);
-        }
+        self.gl.set_initial_state();
     }
 
     pub fn resize(&mut self, width: u32, height: u32) {
         self.width = width;
         self.height = height;
-        unsafe {
-            self.gl.viewport(0, 0, width as _, height as _);
-        }
+        self.gl.viewport(width, height);
     }
 
     pub fn render_instanced(
@@ -385,6 +566,8 @@ impl<GL: HasContext> RenderingContext<GL> {
         display_item: &mut DisplayItem<GL>,
         translucent: bool,
     ) {
+        self.update_lights();
+        self.update_output();
         let gl = &self.gl;
         let part_buffer = &part.part;
 
@@ -397,8 +580,11 @@ impl<GL: HasContext> RenderingContext<GL> {
         if instance_buffer.count == 0 {
             return;
         } else if instance_buffer.count == 1 {
+            if !instance_buffer.is_visible(0) {
+                return;
+            }
             self.projection_data
-                .push_model_matrix(&instance_buffer.model_view_matrices[0]);
+                .push_model_matrix(&instance_buffer.exploded_matrix(0));
             self.render_single_part(part, &instance_buffer.materials[0], translucent);
             self.projection_data.pop_model_matrix();
             return;
@@ -413,15 +599,18 @@ impl<GL: HasContext> RenderingContext<GL> {
             bind.bind_geometry_data(part_buffer.mesh.as_ref().unwrap());
             bind.bind_instanced_geometry_data(instance_buffer);
             bind.bind_instanced_color_data(instance_buffer);
-
-            unsafe {
-                gl.draw_arrays_instanced(
-                    glow::TRIANGLES,
-                    uncolored_index.start as i32,
-                    uncolored_index.span as i32,
-                    instance_buffer.count as i32,
-                );
-            }
+            bind.bind_instanced_finish_data(instance_buffer);
+            bind.bind_instanced_fleck_data(instance_buffer);
+            bind.bind_instanced_emission_data(instance_buffer);
+
+            draw_triangles(
+                gl,
+                &bind,
+                &self.textures,
+                part_buffer,
+                uncolored_index,
+                Some(instance_buffer.count),
+            );
         }
         if let Some(uncolored_without_bfc_index) = &part_buffer.uncolored_without_bfc_index {
             let program = self
@@ -432,17 +621,20 @@ impl<GL: HasContext> RenderingContext<GL> {
             bind.bind_geometry_data(part_buffer.mesh.as_ref().unwrap());
             bind.bind_instanced_geometry_data(instance_buffer);
             bind.bind_instanced_color_data(instance_buffer);
-
-            unsafe {
-                gl.disable(glow::CULL_FACE);
-                gl.draw_arrays_instanced(
-                    glow::TRIANGLES,
-                    uncolored_without_bfc_index.start as i32,
-                    uncolored_without_bfc_index.span as i32,
-                    instance_buffer.count as i32,
-                );
-                gl.enable(glow::CULL_FACE);
-            }
+            bind.bind_instanced_finish_data(instance_buffer);
+            bind.bind_instanced_fleck_data(instance_buffer);
+            bind.bind_instanced_emission_data(instance_buffer);
+
+            gl.set_culling(false);
+            draw_triangles(
+                gl,
+                &bind,
+                &self.textures,
+                part_buffer,
+                uncolored_without_bfc_index,
+                Some(instance_buffer.count),
+            );
+            gl.set_culling(true);
         }
         let subparts = if translucent {
             &part_buffer.translucent_indices
@@ -461,64 +653,134 @@ impl<GL: HasContext> RenderingContext<GL> {
                 None => continue,
             };
             bind.bind_non_instanced_color_data(&color);
+            bind.bind_non_instanced_finish_data(&group_finish(&group.color_ref));
+            bind.bind_non_instanced_fleck_data(&group_fleck(&group.color_ref));
+            bind.bind_non_instanced_emission_data(group_emission(&group.color_ref));
 
-            unsafe {
-                if !group.bfc {
-                    gl.disable(glow::CULL_FACE);
-                }
-                gl.draw_arrays_instanced(
-                    glow::TRIANGLES,
-                    indices.start as i32,
-                    indices.span as i32,
-                    instance_buffer.count as i32,
-                );
-                if !group.bfc {
-                    gl.enable(glow::CULL_FACE);
-                }
+            if !group.bfc {
+                gl.set_culling(false);
+            }
+            draw_triangles(
+                gl,
+                &bind,
+                &self.textures,
+                part_buffer,
+                indices,
+                Some(instance_buffer.count),
+            );
+            if !group.bfc {
+                gl.set_culling(true);
             }
         }
 
+        // Edge shaders have no weighted output, and give off no light.
+        if self.shading_data.weighted_transparency || self.shading_data.emissive_only {
+            return;
+        }
+
         if let Some(edges) = &part_buffer.edges {
             let program = self.program_manager.get_edge_program(true);
 
-            let bind = program.bind(&self.projection_data);
+            let bind = program.bind(&self.projection_data, self.shading_data.linear_output);
             bind.bind_attribs(edges);
             bind.bind_instanced_attribs(instance_buffer);
 
-            unsafe {
-                gl.draw_arrays_instanced(
-                    glow::LINES,
-                    0,
-                    edges.length as i32,
-                    instance_buffer.count as i32,
-                );
-            }
+            gl.draw_arrays_instanced(Primitive::Lines, 0, edges.length, instance_buffer.count);
         }
 
         if let Some(optional_edges) = &part_buffer.optional_edges {
             let program = self.program_manager.get_optional_edge_program(true);
 
-            let bind = program.bind(&self.projection_data);
+            let bind = program.bind(&self.projection_data, self.shading_data.linear_output);
             bind.bind_attribs(optional_edges);
             bind.bind_instanced_attribs(instance_buffer);
 
-            unsafe {
+            gl.draw_arrays_instanced(
+                Primitive::Lines,
+                0,
+                optional_edges.length,
+                instance_buffer.count,
+            );
+        }
+    }
+
+    // Draws depth of the opaque instances of `display_item` without
+    // touching colors, using the cheapest shaders.
+    pub fn render_depth_instanced(&mut self, part: &Part<GL>, display_item: &mut DisplayItem<GL>) {
+        // Wireframes leave out depth between their lines as well.
+        self.update_output();
+        let gl = Rc::clone(&self.gl);
+        let part_buffer = &part.part;
+        let instance_buffer = &mut display_item.opaque;
+
+        let mesh = match &part_buffer.mesh {
+            Some(e) if instance_buffer.count > 0 => e,
+            _ => return,
+        };
+        if instance_buffer.count == 1 && !instance_buffer.is_visible(0) {
+            return;
+        }
+        let mut ranges = Vec::new();
+        if let Some(index) = &part_buffer.uncolored_index {
+            ranges.push((index, true));
+        }
+        if let Some(index) = &part_buffer.uncolored_without_bfc_index {
+            ranges.push((index, false));
+        }
+        for (group, index) in part_buffer.opaque_indices.iter() {
+            ranges.push((index, group.bfc));
+        }
+
+        let instanced = instance_buffer.count > 1;
+        let kind = if instanced {
+            DefaultProgramInstancingKind::Instanced
+        } else {
+            self.projection_data
+                .push_model_matrix(&instance_buffer.exploded_matrix(0));
+            DefaultProgramInstancingKind::NonInstanced
+        };
+
+        let program = self.program_manager.get_default_program(kind, false);
+        let bind = program.bind(&self.projection_data, &self.shading_data);
+        bind.bind_geometry_data(mesh);
+        if instanced {
+            bind.bind_instanced_geometry_data(instance_buffer);
+        }
+
+        gl.set_color_write(false);
+        for (index, bfc) in ranges {
+            gl.set_culling(bfc);
+            if instanced {
                 gl.draw_arrays_instanced(
-                    glow::LINES,
-                    0,
-                    optional_edges.length as i32,
-                    instance_buffer.count as i32,
+                    Primitive::Triangles,
+                    index.start,
+                    index.span,
+                    instance_buffer.count,
                 );
+            } else {
+                gl.draw_arrays(Primitive::Triangles, index.start, index.span);
             }
         }
+        gl.set_culling(true);
+        gl.set_color_write(true);
+
+        drop(bind);
+        if !instanced {
+            self.projection_data.pop_model_matrix();
+        }
     }
 
     pub fn render_single_part(&mut self, part: &Part<GL>, material: &Material, translucent: bool) {
+        self.update_lights();
+        self.update_output();
         let gl = &self.gl;
         let part_buffer = &part.part;
 
         let color: Vector4 = material.color.into();
         let edge_color: Vector4 = material.edge.into();
+        let finish = Vector4::from(&PbrParameters::from(&material.finish));
+        let fleck = Vector4::from(&FleckParameters::from(&material.finish));
+        let emission = emission(material);
 
         if material.is_translucent() == translucent {
             if let Some(uncolored_index) = &part_buffer.uncolored_index {
@@ -529,14 +791,18 @@ impl<GL: HasContext> RenderingContext<GL> {
                 let bind = program.bind(&self.projection_data, &self.shading_data);
                 bind.bind_geometry_data(part_buffer.mesh.as_ref().unwrap());
                 bind.bind_non_instanced_color_data(&color);
-
-                unsafe {
-                    gl.draw_arrays(
-                        glow::TRIANGLES,
-                        uncolored_index.start as i32,
-                        uncolored_index.span as i32,
-                    );
-                }
+                bind.bind_non_instanced_finish_data(&finish);
+                bind.bind_non_instanced_fleck_data(&fleck);
+                bind.bind_non_instanced_emission_data(emission);
+
+                draw_triangles(
+                    gl,
+                    &bind,
+                    &self.textures,
+                    part_buffer,
+                    uncolored_index,
+                    None,
+                );
             }
             if let Some(uncolored_without_bfc_index) = &part_buffer.uncolored_without_bfc_index {
                 let program = self
@@ -546,16 +812,20 @@ impl<GL: HasContext> RenderingContext<GL> {
                 let bind = program.bind(&self.projection_data, &self.shading_data);
                 bind.bind_geometry_data(part_buffer.mesh.as_ref().unwrap());
                 bind.bind_non_instanced_color_data(&color);
-
-                unsafe {
-                    gl.disable(glow::CULL_FACE);
-                    gl.draw_arrays(
-                        glow::TRIANGLES,
-                        uncolored_without_bfc_index.start as i32,
-                        uncolored_without_bfc_index.span as i32,
-                    );
-                    gl.enable(glow::CULL_FACE);
-                }
+                bind.bind_non_instanced_finish_data(&finish);
+                bind.bind_non_instanced_fleck_data(&fleck);
+                bind.bind_non_instanced_emission_data(emission);
+
+                gl.set_culling(false);
+                draw_triangles(
+                    gl,
+                    &bind,
+                    &self.textures,
+                    part_buffer,
+                    uncolored_without_bfc_index,
+                    None,
+                );
+                gl.set_culling(true);
             }
         }
 
@@ -577,15 +847,16 @@ impl<GL: HasContext> RenderingContext<GL> {
             let bind = program.bind(&self.projection_data, &self.shading_data);
             bind.bind_geometry_data(part_buffer.mesh.as_ref().unwrap());
             bind.bind_non_instanced_color_data(&color);
+            bind.bind_non_instanced_finish_data(&group_finish(&group.color_ref));
+            bind.bind_non_instanced_fleck_data(&group_fleck(&group.color_ref));
+            bind.bind_non_instanced_emission_data(group_emission(&group.color_ref));
 
-            unsafe {
-                if !group.bfc {
-                    gl.disable(glow::CULL_FACE);
-                }
-                gl.draw_arrays(glow::TRIANGLES, indices.start as i32, indices.span as i32);
-                if !group.bfc {
-                    gl.enable(glow::CULL_FACE);
-                }
+            if !group.bfc {
+                gl.set_culling(false);
+            }
+            draw_triangles(gl, &bind, &self.textures, part_buffer, indices, None);
+            if !group.bfc {
+                gl.set_culling(true);
             }
         }
 
@@ -593,52 +864,986 @@ impl<GL: HasContext> RenderingContext<GL> {
             if let Some(edges) = &part_buffer.edges {
                 let program = self.program_manager.get_edge_program(false);
 
-                let bind = program.bind(&self.projection_data);
+                let bind = program.bind(&self.projection_data, self.shading_data.linear_output);
                 bind.bind_attribs(edges);
                 bind.bind_non_instanced_properties(&color, &edge_color);
 
-                unsafe {
-                    gl.draw_arrays(glow::LINES, 0, edges.length as i32);
-                }
+                gl.draw_arrays(Primitive::Lines, 0, edges.length);
             }
 
             if let Some(optional_edges) = &part_buffer.optional_edges {
                 let program = self.program_manager.get_optional_edge_program(false);
 
-                let bind = program.bind(&self.projection_data);
+                let bind = program.bind(&self.projection_data, self.shading_data.linear_output);
                 bind.bind_attribs(optional_edges);
                 bind.bind_non_instanced_properties(&color, &edge_color);
 
-                unsafe {
-                    gl.draw_arrays(glow::LINES, 0, optional_edges.length as i32);
-                }
+                gl.draw_arrays(Primitive::Lines, 0, optional_edges.length);
+            }
+        }
+    }
+
+    fn update_lights(&mut self) {
+        if let Some(buffer) = &mut self.lights_buffer {
+            buffer.update(&self.lights);
+        }
+    }
+
+    fn update_output(&mut self) {
+        self.shading_data.tone_mapping = self.pipeline.tone_mapping;
+        self.shading_data.exposure = self.pipeline.exposure;
+        self.shading_data.linear_output = self.hdr_target.is_some();
+        self.shading_data.wireframe = self.pipeline.wireframe;
+    }
+
+    // Framebuffer the current frame is drawn into before any resolve.
+    fn scene_framebuffer(&self) -> Option<GL::Framebuffer> {
+        match (&self.multisample_target, &self.hdr_target) {
+            (Some(e), _) => Some(e.framebuffer),
+            (None, Some(e)) => Some(e.framebuffer),
+            (None, None) => self.framebuffer,
+        }
+    }
+
+    fn prepare_hdr_target(&mut self) {
+        if !self.pipeline.hdr {
+            self.hdr_target = None;
+            return;
+        }
+        if let Some(target) = &self.hdr_target {
+            if target.width == self.width && target.height == self.height {
+                return;
+            }
+        }
+        self.hdr_target = None;
+
+        match HdrTarget::new(Rc::clone(&self.gl), self.width, self.height) {
+            Ok(e) => {
+                self.hdr_target = Some(e);
+            }
+            Err(msg) => {
+                println!("Failed creating HDR target: {}", msg);
+                self.pipeline.hdr = false;
             }
         }
     }
 
+    fn prepare_multisample_target(&mut self) {
+        let samples = self.pipeline.samples.min(self.gl.max_samples());
+        if samples <= 1 {
+            self.multisample_target = None;
+            return;
+        }
+        let format = if self.hdr_target.is_some() {
+            TextureFormat::Rgba16F
+        } else {
+            TextureFormat::Rgba8
+        };
+        if let Some(target) = &self.multisample_target {
+            if target.width == self.width
+                && target.height == self.height
+                && target.samples == samples
+                && target.format == format
+            {
+                return;
+            }
+        }
+        self.multisample_target = None;
+
+        match MultisampleTarget::new(
+            Rc::clone(&self.gl),
+            self.width,
+            self.height,
+            samples,
+            format,
+        ) {
+            Ok(e) => self.multisample_target = Some(e),
+            Err(msg) => {
+                println!("Failed creating multisampled target: {}", msg);
+                self.pipeline.samples = 1;
+            }
+        }
+    }
+
+    fn prepare_studio_environment(&mut self) {
+        if !self.pipeline.studio_environment || self.envmap_levels > 0 {
+            return;
+        }
+
+        if let Err(msg) = self.set_environment(Environment::studio()) {
+            println!("Failed creating studio environment: {}", msg);
+            self.pipeline.studio_environment = false;
+        }
+    }
+
+    // Binds the target of a new frame and clears it to `clear_color`.
+    // Everything drawn until `end_frame` goes there.
+    pub fn begin_frame(&mut self, clear_color: &Vector4) {
+        self.prepare_hdr_target();
+        self.prepare_multisample_target();
+        self.clear_color = *clear_color;
+        let gl = &self.gl;
+
+        gl.bind_framebuffer(self.scene_framebuffer());
+        gl.viewport(self.width, self.height);
+        // In linear light, the clear color is laid under the frame once it
+        // is tone mapped.
+        if self.hdr_target.is_some() {
+            gl.clear_color_attachment(0, &[0.0, 0.0, 0.0, 0.0]);
+        } else {
+            gl.clear_color_attachment(0, AsRef::<[f32; 4]>::as_ref(clear_color));
+        }
+        gl.clear_depth();
+    }
+
+    // Resolves the frame if it was multisampled, tone maps it if it was
+    // drawn in linear light, and leaves `framebuffer` bound with the
+    // result.
+    pub fn end_frame(&mut self) {
+        let gl = &self.gl;
+
+        let hdr = self.hdr_target.as_ref();
+        if let Some(target) = &self.multisample_target {
+            gl.resolve_framebuffer(
+                target.framebuffer,
+                hdr.map_or(self.framebuffer, |e| Some(e.framebuffer)),
+                target.width,
+                target.height,
+            );
+        }
+        gl.bind_framebuffer(self.framebuffer);
+
+        if let Some(target) = hdr {
+            gl.clear_color_attachment(0, AsRef::<[f32; 4]>::as_ref(&self.clear_color));
+            gl.clear_depth();
+            gl.set_blending(Blending::Premultiplied);
+            gl.set_depth_test(false);
+            self.program_manager.tone_mapping.tone_map(
+                target.color,
+                self.pipeline.tone_mapping,
+                self.pipeline.exposure,
+            );
+            gl.set_depth_test(true);
+            gl.set_blending(Blending::Alpha);
+        }
+    }
+
     pub fn render_display_list(
         &mut self,
         parts: &HashMap<PartAlias, Part<GL>>,
         display_list: &mut DisplayList<GL>,
         translucent: bool,
     ) {
+        self.prepare_studio_environment();
+        if translucent {
+            match self.pipeline.transparency {
+                Transparency::Sorted => self.render_sorted_translucent(parts, display_list),
+                Transparency::WeightedBlended => {
+                    self.render_weighted_translucent(parts, display_list)
+                }
+            }
+            if self.pipeline.bloom {
+                self.render_bloom(parts, display_list);
+            }
+            return;
+        }
+
+        if self.pipeline.shadows {
+            self.render_shadow_map(parts, display_list);
+        } else {
+            self.shading_data.shadow_matrix = None;
+        }
+
+        if self.pipeline.depth_prepass {
+            for (alias, object) in display_list.map.iter_mut() {
+                if let Some(part) = parts.get(alias) {
+                    self.render_depth_instanced(part, object);
+                }
+            }
+        }
+
         for (alias, object) in display_list.map.iter_mut() {
             let part = match parts.get(alias) {
                 Some(e) => e,
                 None => continue,
             };
 
-            self.render_instanced(part, object, translucent);
+            self.render_instanced(part, object, false);
         }
     }
+
+    fn prepare_shadow_target(&mut self) -> bool {
+        let size = self.pipeline.shadow_map_size;
+        if let Some(target) = &self.shadow_target {
+            if target.size == size {
+                return true;
+            }
+        }
+        self.shadow_target = None;
+
+        match ShadowMapTarget::new(Rc::clone(&self.gl), size) {
+            Ok(e) => {
+                self.shadow_target = Some(e);
+                self.upload_shading_data();
+                true
+            }
+            Err(msg) => {
+                println!("Failed creating shadow map: {}", msg);
+                self.pipeline.shadows = false;
+                false
+            }
+        }
+    }
+
+    // Draws depth of the opaque instances as seen from the shadow casting
+    // light, with an orthographic frustum fitted around the whole display
+    // list.
+    fn render_shadow_map(
+        &mut self,
+        parts: &HashMap<PartAlias, Part<GL>>,
+        display_list: &mut DisplayList<GL>,
+    ) {
+        self.shading_data.shadow_matrix = None;
+        let direction = match self
+            .lights
+            .shadow_caster()
+            .map(|e| self.lights.lights[e].kind)
+        {
+            Some(LightKind::Directional { direction }) => direction,
+            _ => return,
+        };
+        let bounding_box = match display_list.calculate_bounding_box(parts) {
+            Some(e) => e,
+            None => return,
+        };
+        if !self.prepare_shadow_target() {
+            return;
+        }
+        let gl = Rc::clone(&self.gl);
+        let scene = self.scene_framebuffer();
+        let target = self.shadow_target.as_ref().unwrap();
+        let (framebuffer, depth, size) = (target.framebuffer, target.depth, target.size);
+
+        let up = if direction.x.abs() < 1e-3 && direction.z.abs() < 1e-3 {
+            Vector3::unit_z()
+        } else {
+            Vector3::unit_y()
+        };
+        let light_view = Matrix4::look_to_rh(Point3::new(0.0, 0.0, 0.0), direction, up);
+        // Not a `BoundingBox3`, which would take a corner at the origin
+        // for an empty box.
+        let corners = bounding_box
+            .points()
+            .map(|e| (light_view * e.extend(1.0)).truncate());
+        let (mut min, mut max) = (corners[0], corners[0]);
+        for corner in corners.iter() {
+            min = Vector3::new(
+                min.x.min(corner.x),
+                min.y.min(corner.y),
+                min.z.min(corner.z),
+            );
+            max = Vector3::new(
+                max.x.max(corner.x),
+                max.y.max(corner.y),
+                max.z.max(corner.z),
+            );
+        }
+        let light_projection = cgmath::ortho(min.x, max.x, min.y, max.y, -max.z, -min.z);
+
+        let view_matrix = self.projection_data.view_matrix;
+        let saved = std::mem::take(&mut self.projection_data);
+        self.projection_data
+            .update_projection_matrix(&light_projection);
+        self.projection_data.update_view_matrix(&light_view);
+        self.projection_data.orthographic = true;
+
+        // The map can't be sampled while it is drawn into.
+        self.program_manager.bind_shadow_map(None);
+        gl.bind_framebuffer(Some(framebuffer));
+        gl.viewport(size, size);
+        gl.clear_depth();
+        for (alias, object) in display_list.map.iter_mut() {
+            if let Some(part) = parts.get(alias) {
+                self.render_depth_instanced(part, object);
+            }
+        }
+        gl.bind_framebuffer(scene);
+        gl.viewport(self.width, self.height);
+        self.program_manager.bind_shadow_map(Some(depth));
+        self.projection_data = saved;
+
+        let to_texture =
+            Matrix4::from_translation(Vector3::new(0.5, 0.5, 0.5)) * Matrix4::from_scale(0.5);
+        let inverse_view = view_matrix.invert().unwrap_or_else(Matrix4::identity);
+        self.shading_data.shadow_matrix =
+            Some(to_texture * light_projection * light_view * inverse_view);
+    }
+
+    fn render_sorted_translucent(
+        &mut self,
+        parts: &HashMap<PartAlias, Part<GL>>,
+        display_list: &mut DisplayList<GL>,
+    ) {
+        // Blending only comes out right with the farthest parts drawn
+        // first.
+        let view_matrix = self.projection_data.model_view;
+        let mut items = display_list
+            .map
+            .iter_mut()
+            .filter_map(|(alias, object)| {
+                let part = parts.get(alias)?;
+                let center = part.bounding_box.center();
+                object.translucent.sort_by_depth(&view_matrix, &center);
+                let depth = object.translucent.farthest_depth(&view_matrix, &center)?;
+                Some((depth, part, object))
+            })
+            .collect::<Vec<_>>();
+        items.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        for (_, part, object) in items {
+            self.render_instanced(part, object, true);
+        }
+    }
+
+    fn prepare_weighted_target(&mut self) -> bool {
+        if let Some(target) = &self.weighted_target {
+            if target.width == self.width && target.height == self.height {
+                return true;
+            }
+        }
+        self.weighted_target = None;
+
+        match WeightedBlendedTarget::new(Rc::clone(&self.gl), self.width, self.height) {
+            Ok(e) => {
+                self.weighted_target = Some(e);
+                true
+            }
+            Err(msg) => {
+                println!("Failed creating transparency targets: {}", msg);
+                self.pipeline.transparency = Transparency::Sorted;
+                false
+            }
+        }
+    }
+
+    fn render_weighted_translucent(
+        &mut self,
+        parts: &HashMap<PartAlias, Part<GL>>,
+        display_list: &mut DisplayList<GL>,
+    ) {
+        if !self.prepare_weighted_target() {
+            self.render_sorted_translucent(parts, display_list);
+            return;
+        }
+        let gl = Rc::clone(&self.gl);
+        let scene = self.scene_framebuffer();
+        let target = self.weighted_target.as_ref().unwrap();
+        let (accumulation, weights) = (target.accumulation, target.weights);
+
+        gl.bind_framebuffer(Some(target.framebuffer));
+        gl.clear_color_attachment(0, &[0.0, 0.0, 0.0, 1.0]);
+        gl.clear_color_attachment(1, &[0.0, 0.0, 0.0, 0.0]);
+        gl.clear_depth();
+
+        // Opaque parts only lay down depth to hide what is behind them.
+        for (alias, object) in display_list.map.iter_mut() {
+            if let Some(part) = parts.get(alias) {
+                self.render_depth_instanced(part, object);
+            }
+        }
+
+        gl.set_depth_write(false);
+        gl.set_blending(Blending::Accumulate);
+        self.shading_data.weighted_transparency = true;
+        for (alias, object) in display_list.map.iter_mut() {
+            if let Some(part) = parts.get(alias) {
+                self.render_instanced(part, object, true);
+            }
+        }
+        self.shading_data.weighted_transparency = false;
+
+        gl.bind_framebuffer(scene);
+        gl.set_blending(Blending::Alpha);
+        gl.set_depth_test(false);
+        self.program_manager
+            .weighted_composite
+            .composite(accumulation, weights);
+        gl.set_depth_test(true);
+        gl.set_depth_write(true);
+    }
+
+    fn prepare_bloom_target(&mut self) -> bool {
+        let (width, height) = ((self.width / 2).max(1), (self.height / 2).max(1));
+        if let Some(target) = &self.bloom_target {
+            if target.width == width && target.height == height {
+                return true;
+            }
+        }
+        self.bloom_target = None;
+
+        match BloomTarget::new(Rc::clone(&self.gl), width, height) {
+            Ok(e) => {
+                self.bloom_target = Some(e);
+                true
+            }
+            Err(msg) => {
+                println!("Failed creating bloom targets: {}", msg);
+                self.pipeline.bloom = false;
+                false
+            }
+        }
+    }
+
+    // Draws the light luminous instances give off, hidden behind whatever
+    // is in front, then blurs it and adds it over the image.
+    fn render_bloom(
+        &mut self,
+        parts: &HashMap<PartAlias, Part<GL>>,
+        display_list: &mut DisplayList<GL>,
+    ) {
+        if !display_list
+            .map
+            .values()
+            .any(|e| e.opaque.is_emissive() || e.translucent.is_emissive())
+        {
+            return;
+        }
+        if !self.prepare_bloom_target() {
+            return;
+        }
+        let gl = Rc::clone(&self.gl);
+        let scene = self.scene_framebuffer();
+        let target = self.bloom_target.as_ref().unwrap();
+        let (framebuffers, colors) = (target.framebuffers, target.colors);
+        let (width, height) = (target.width, target.height);
+
+        gl.bind_framebuffer(Some(framebuffers[0]));
+        gl.viewport(width, height);
+        gl.clear_color_attachment(0, &[0.0, 0.0, 0.0, 1.0]);
+        gl.clear_depth();
+
+        self.shading_data.emissive_only = true;
+        for (alias, object) in display_list.map.iter_mut() {
+            if let Some(part) = parts.get(alias) {
+                self.render_instanced(part, object, false);
+            }
+        }
+        gl.set_depth_write(false);
+        gl.set_blending(Blending::Additive);
+        for (alias, object) in display_list.map.iter_mut() {
+            if let Some(part) = parts.get(alias) {
+                self.render_instanced(part, object, true);
+            }
+        }
+        self.shading_data.emissive_only = false;
+
+        gl.set_blending(Blending::Alpha);
+        gl.set_depth_test(false);
+        for _ in 0..2 {
+            gl.bind_framebuffer(Some(framebuffers[1]));
+            self.program_manager.bloom.blur(colors[0], true);
+            gl.bind_framebuffer(Some(framebuffers[0]));
+            self.program_manager.bloom.blur(colors[1], false);
+        }
+
+        gl.bind_framebuffer(scene);
+        gl.viewport(self.width, self.height);
+        gl.set_blending(Blending::Additive);
+        self.program_manager.bloom.composite(
+            colors[0],
+            self.width as f32 / width as f32,
+            self.pipeline.bloom_intensity,
+        );
+        gl.set_blending(Blending::Alpha);
+        gl.set_depth_test(true);
+        gl.set_depth_write(true);
+    }
+
+    fn prepare_outline_target(&mut self) -> bool {
+        if let Some(target) = &self.outline_target {
+            if target.width == self.width && target.height == self.height {
+                return true;
+            }
+        }
+        self.outline_target = None;
+
+        match OutlineTarget::new(Rc::clone(&self.gl), self.width, self.height) {
+            Ok(e) => {
+                self.outline_target = Some(e);
+                true
+            }
+            Err(msg) => {
+                println!("Failed creating outline target: {}", msg);
+                false
+            }
+        }
+    }
+
+    // Outlines instances in `selection` over `framebuffer`, by the outline
+    // color and thickness of the pipeline. Parts in front of them do not
+    // hide the outline. Frames begun with `begin_frame` are to be ended
+    // first, so the outline stays crisp and keeps its color.
+    pub fn render_outline(
+        &mut self,
+        parts: &HashMap<PartAlias, Part<GL>>,
+        display_list: &DisplayList<GL>,
+        selection: &HashSet<InstanceId>,
+    ) {
+        if selection.is_empty() || !self.prepare_outline_target() {
+            return;
+        }
+        let gl = Rc::clone(&self.gl);
+        let target = self.outline_target.as_ref().unwrap();
+        let mask = target.mask;
+
+        gl.bind_framebuffer(Some(target.framebuffer));
+        gl.clear_color_attachment(0, &[0.0, 0.0, 0.0, 0.0]);
+        gl.set_depth_test(false);
+        gl.set_culling(false);
+
+        let white = Vector4::new(1.0, 1.0, 1.0, 1.0);
+        for (alias, item) in display_list.map.iter() {
+            let mesh = match parts.get(alias).and_then(|e| e.part.mesh.as_ref()) {
+                Some(e) => e,
+                None => continue,
+            };
+            for buffer in [&item.opaque, &item.translucent] {
+                for (index, id) in buffer.ids.iter().enumerate() {
+                    if !selection.contains(id) || !buffer.is_visible(index) {
+                        continue;
+                    }
+                    self.projection_data
+                        .push_model_matrix(&buffer.exploded_matrix(index));
+                    let program = self
+                        .program_manager
+                        .get_default_program(DefaultProgramInstancingKind::NonInstanced, false);
+                    let bind = program.bind(&self.projection_data, &self.shading_data);
+                    bind.bind_geometry_data(mesh);
+                    bind.bind_non_instanced_color_data(&white);
+                    gl.draw_arrays(Primitive::Triangles, 0, mesh.length);
+                    drop(bind);
+                    self.projection_data.pop_model_matrix();
+                }
+            }
+        }
+        gl.set_culling(true);
+
+        gl.bind_framebuffer(self.framebuffer);
+        self.program_manager.outline.composite(
+            mask,
+            &self.pipeline.outline_color,
+            self.pipeline
+                .outline_thickness
+                .clamp(1, MAX_OUTLINE_THICKNESS),
+        );
+        gl.set_depth_test(true);
+    }
+
+    fn prepare_picking_target(&mut self) -> bool {
+        if let Some(target) = &self.picking_target {
+            if target.width == self.width && target.height == self.height {
+                return true;
+            }
+        }
+        self.picking_target = None;
+
+        match PickingTarget::new(Rc::clone(&self.gl), self.width, self.height) {
+            Ok(e) => {
+                self.picking_target = Some(e);
+                true
+            }
+            Err(msg) => {
+                println!("Failed creating picking target: {}", msg);
+                false
+            }
+        }
+    }
+
+    // Draws IDs of the instances in `display_list` as the camera sees
+    // them now, for `pick` and `pick_rect` to look up until the next call.
+    pub fn render_picking(
+        &mut self,
+        parts: &HashMap<PartAlias, Part<GL>>,
+        display_list: &mut DisplayList<GL>,
+    ) {
+        if !self.prepare_picking_target() {
+            return;
+        }
+        let gl = Rc::clone(&self.gl);
+        let target = self.picking_target.as_ref().unwrap();
+
+        gl.bind_framebuffer(Some(target.framebuffer));
+        gl.clear_integer_attachment(0, 0);
+        gl.clear_depth();
+        gl.set_culling(false);
+
+        let program = &self.program_manager.picking;
+        program.bind(&self.projection_data);
+        for (alias, item) in display_list.map.iter_mut() {
+            let mesh = match parts.get(alias).and_then(|e| e.part.mesh.as_ref()) {
+                Some(e) => e,
+                None => continue,
+            };
+            for buffer in [&mut item.opaque, &mut item.translucent] {
+                if !buffer.is_empty() {
+                    program.draw(mesh, buffer);
+                }
+            }
+        }
+
+        gl.set_culling(true);
+        gl.bind_framebuffer(self.framebuffer);
+    }
+
+    // Instance seen at `x`, `y` in pixels from the top left, as of the
+    // last `render_picking`.
+    pub fn pick(&self, x: u32, y: u32) -> Option<InstanceId> {
+        self.pick_rect(x, y, 1, 1).into_iter().next()
+    }
+
+    // Every instance seen in the rectangle `width` by `height` pixels at
+    // `x`, `y` from the top left, as of the last `render_picking`. Sorted,
+    // without duplicates.
+    pub fn pick_rect(&self, x: u32, y: u32, width: u32, height: u32) -> Vec<InstanceId> {
+        let target = match &self.picking_target {
+            Some(e) => e,
+            None => return vec![],
+        };
+        let (left, right) = (
+            x.min(target.width),
+            x.saturating_add(width).min(target.width),
+        );
+        let (top, bottom) = (
+            y.min(target.height),
+            y.saturating_add(height).min(target.height),
+        );
+        if left == right || top == bottom {
+            return vec![];
+        }
+
+        self.gl.bind_framebuffer(Some(target.framebuffer));
+        let ids = self
+            .gl
+            .read_integers(left, target.height - bottom, right - left, bottom - top);
+        self.gl.bind_framebuffer(self.framebuffer);
+
+        let mut picked = ids
+            .into_iter()
+            .filter(|e| *e > 0)
+            .map(|e| InstanceId(e - 1))
+            .collect::<Vec<_>>();
+        picked.sort();
+        picked.dedup();
+        picked
+    }
+
+    // Ray through the center of the pixel at `x`, `y` from the top left,
+    // as an origin on the near plane and a direction to the far plane, in
+    // the coordinates instances are placed in. For `RayCaster::cast`.
+    pub fn ray_at(&self, x: u32, y: u32) -> Option<(Vector3, Vector3)> {
+        let inverse =
+            (self.projection_data.projection * self.projection_data.view_matrix).invert()?;
+        let ndc = Vector2::new(
+            (x as f32 + 0.5) / self.width as f32 * 2.0 - 1.0,
+            1.0 - (y as f32 + 0.5) / self.height as f32 * 2.0,
+        );
+        let unproject = |z: f32| {
+            let p = inverse * Vector4::new(ndc.x, ndc.y, z, 1.0);
+            p.truncate() / p.w
+        };
+        let near = unproject(-1.0);
+        Some((near, unproject(1.0) - near))
+    }
 }
 
-impl<GL: HasContext> Drop for RenderingContext<GL> {
+impl<GL: Backend> Drop for RenderingContext<GL> {
     fn drop(&mut self) {
-        unsafe {
-            if let Some(e) = self.envmap {
-                self.gl.delete_texture(e);
-            }
+        if let Some(e) = self.envmap {
+            self.gl.delete_texture(e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, HashSet},
+        rc::Rc,
+    };
+
+    use cgmath::{Deg, Matrix4, Point3, SquareMatrix};
+
+    use ldraw::{
+        color::{ColorReference, Material, Rgba},
+        PartAlias, Vector3, Vector4,
+    };
+    use ldraw_ir::{
+        geometry::BoundingBox3,
+        part::{PartBufferBuilder, PartBuilder},
+    };
+
+    use crate::{
+        backend::Primitive,
+        display_list::DisplayList,
+        environment::SPECULAR_LEVELS,
+        light::{Lights, LIGHTS_BINDING, MAX_LIGHTS},
+        part::Part,
+        shader::ProgramManager,
+        test_support::{triangle, RecordingBackend},
+    };
+
+    use super::{PerspectiveCamera, RenderingContext};
+
+    #[test]
+    fn test_perspective_camera() {
+        let mut camera = PerspectiveCamera::new(
+            Point3::new(0.0, 0.0, -100.0),
+            Point3::new(0.0, 0.0, 0.0),
+            Deg(60.0),
+        );
+        assert_eq!(camera.derive_aspect_ratio(800, 400), 2.0);
+        assert_eq!(camera.derive_aspect_ratio(800, 0), 1.0);
+        assert!((camera.derive_horizontal_fov(400, 400).0 - 60.0).abs() < 1e-3);
+
+        camera.near = 1.0;
+        camera.far = 1000.0;
+        camera.aspect_ratio = Some(4.0 / 3.0);
+        let projection = camera.derive_projection_matrix(100, 100);
+        let near = projection * Vector4::new(0.0, 0.0, -1.0, 1.0);
+        let far = projection * Vector4::new(0.0, 0.0, -1000.0, 1.0);
+        assert!((near.z / near.w + 1.0).abs() < 1e-5);
+        assert!((far.z / far.w - 1.0).abs() < 1e-5);
+        assert!((projection[1][1] / projection[0][0] - 4.0 / 3.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_render_through_backend() {
+        let mut part_builder = PartBufferBuilder::default();
+        for x in [0.0, 1.0, 0.0] {
+            part_builder
+                .uncolored_mesh
+                .add(&Vector3::new(x, 0.0, 1.0 - x), &Vector3::unit_y());
+        }
+        part_builder.edges.add(
+            &Vector3::new(0.0, 0.0, 0.0),
+            &ColorReference::Current,
+            &ColorReference::Current,
+        );
+        part_builder.edges.add(
+            &Vector3::new(1.0, 0.0, 0.0),
+            &ColorReference::Current,
+            &ColorReference::Current,
+        );
+        let builder = PartBuilder::new(
+            part_builder,
+            HashMap::new(),
+            BoundingBox3::zero(),
+            &Vector3::new(0.0, 0.0, 0.0),
+        );
+
+        let backend = Rc::new(RecordingBackend::default());
+        {
+            let program_manager = ProgramManager::new(Rc::clone(&backend)).unwrap();
+            let mut context = RenderingContext::new(Rc::clone(&backend), program_manager);
+            backend.uploads.borrow_mut().clear();
+            let part = Part::create(&builder, Rc::clone(&backend));
+            assert_eq!(*backend.uploads.borrow(), vec![9, 9, 6, 6]);
+
+            context.render_single_part(&part, &Material::default(), false);
+            assert_eq!(
+                *backend.draws.borrow(),
+                vec![
+                    (Primitive::Triangles, 0, 3, false),
+                    (Primitive::Lines, 0, 2, false)
+                ]
+            );
+
+            // Lights go up once, and again only when they change.
+            context.render_single_part(&part, &Material::default(), false);
+            context.lights = Lights::none();
+            context.render_single_part(&part, &Material::default(), false);
+            assert_eq!(
+                backend.uploads.borrow()[4..],
+                [MAX_LIGHTS * 16, MAX_LIGHTS * 16]
+            );
+            assert!(backend.uniform_buffers.borrow()[&LIGHTS_BINDING].is_some());
+        }
+        assert!(backend.live.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_studio_environment() {
+        let backend = Rc::new(RecordingBackend::default());
+        {
+            let program_manager = ProgramManager::new(Rc::clone(&backend)).unwrap();
+            let mut context = RenderingContext::new(Rc::clone(&backend), program_manager);
+            let parts = HashMap::new();
+            let mut display_list = DisplayList::default();
+
+            // The studio is prefiltered only once asked for.
+            context.render_display_list(&parts, &mut display_list, false);
+            assert_eq!(context.envmap_levels, 0);
+            context.pipeline.studio_environment = true;
+            context.render_display_list(&parts, &mut display_list, false);
+            assert_eq!(context.envmap_levels, SPECULAR_LEVELS as u32);
+        }
+        assert!(backend.live.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_multisampling() {
+        let builder = triangle();
+        let alias = PartAlias::from("a.dat");
+        let clear_color = Vector4::new(1.0, 1.0, 1.0, 0.0);
+
+        let backend = Rc::new(RecordingBackend::default());
+        {
+            let program_manager = ProgramManager::new(Rc::clone(&backend)).unwrap();
+            let mut context = RenderingContext::new(Rc::clone(&backend), program_manager);
+            context.framebuffer = Some(1000);
+            context.resize(640, 480);
+            let parts =
+                HashMap::from([(alias.clone(), Part::create(&builder, Rc::clone(&backend)))]);
+            let mut display_list = DisplayList::default();
+            display_list.add(
+                Rc::clone(&backend),
+                alias.clone(),
+                Matrix4::identity(),
+                Material::default(),
+            );
+
+            // Single sampled frames go straight into the framebuffer.
+            context.begin_frame(&clear_color);
+            assert_eq!(*backend.framebuffer.borrow(), Some(1000));
+            context.end_frame();
+            assert!(backend.resolves.borrow().is_empty());
+
+            context.pipeline.samples = 16;
+            context.begin_frame(&clear_color);
+            let scene = backend.framebuffer.borrow().unwrap();
+            assert_ne!(scene, 1000);
+            assert_eq!(*backend.samples.borrow(), vec![8, 8]);
+            context.render_display_list(&parts, &mut display_list, false);
+            context.render_display_list(&parts, &mut display_list, true);
+            assert_eq!(*backend.framebuffer.borrow(), Some(scene));
+            context.end_frame();
+            assert_eq!(*backend.resolves.borrow(), vec![(scene, Some(1000))]);
+            assert_eq!(*backend.framebuffer.borrow(), Some(1000));
+
+            // The target is kept until the size changes.
+            context.begin_frame(&clear_color);
+            assert_eq!(backend.samples.borrow().len(), 2);
+            context.resize(320, 240);
+            context.begin_frame(&clear_color);
+            assert_eq!(backend.samples.borrow().len(), 4);
+
+            context.pipeline.samples = 1;
+            context.begin_frame(&clear_color);
+            assert_eq!(*backend.framebuffer.borrow(), Some(1000));
+        }
+        assert!(backend.live.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_selection_outline() {
+        let mut part_builder = PartBufferBuilder::default();
+        for x in [0.0, 1.0, 0.0, 0.0, 1.0, 1.0] {
+            part_builder
+                .uncolored_mesh
+                .add(&Vector3::new(x, 0.0, 1.0 - x), &Vector3::unit_y());
+        }
+        let builder = PartBuilder::new(
+            part_builder,
+            HashMap::new(),
+            BoundingBox3::zero(),
+            &Vector3::new(0.0, 0.0, 0.0),
+        );
+        let alias = PartAlias::from("a.dat");
+
+        let backend = Rc::new(RecordingBackend::default());
+        {
+            let program_manager = ProgramManager::new(Rc::clone(&backend)).unwrap();
+            let mut context = RenderingContext::new(Rc::clone(&backend), program_manager);
+            context.framebuffer = Some(1000);
+            context.resize(640, 480);
+            let parts =
+                HashMap::from([(alias.clone(), Part::create(&builder, Rc::clone(&backend)))]);
+            let mut display_list = DisplayList::default();
+            let ids = [0.0, 40.0, 80.0].map(|x| {
+                display_list.add(
+                    Rc::clone(&backend),
+                    alias.clone(),
+                    Matrix4::from_translation(Vector3::new(x, 0.0, 0.0)),
+                    Material::default(),
+                )
+            });
+            assert_eq!(ids.iter().collect::<HashSet<_>>().len(), 3);
+
+            // Nothing selected, nothing drawn.
+            context.render_outline(&parts, &display_list, &HashSet::new());
+            assert!(backend.draws.borrow().is_empty());
+
+            context.render_outline(&parts, &display_list, &HashSet::from([ids[0], ids[2]]));
+            assert_eq!(
+                *backend.draws.borrow(),
+                vec![
+                    (Primitive::Triangles, 0, 6, false),
+                    (Primitive::Triangles, 0, 6, false),
+                    (Primitive::Triangles, 0, 3, false),
+                ]
+            );
+            assert_eq!(*backend.framebuffer.borrow(), Some(1000));
+        }
+        assert!(backend.live.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_picking() {
+        let builder = triangle();
+        let alias = PartAlias::from("a.dat");
+
+        let backend = Rc::new(RecordingBackend::default());
+        {
+            let program_manager = ProgramManager::new(Rc::clone(&backend)).unwrap();
+            let mut context = RenderingContext::new(Rc::clone(&backend), program_manager);
+            context.framebuffer = Some(1000);
+            context.resize(640, 480);
+            let parts =
+                HashMap::from([(alias.clone(), Part::create(&builder, Rc::clone(&backend)))]);
+            let mut display_list = DisplayList::default();
+            let translucent = Material {
+                color: Rgba::new(255, 0, 0, 128),
+                ..Default::default()
+            };
+            let ids = [Material::default(), translucent.clone(), translucent].map(|e| {
+                display_list.add(Rc::clone(&backend), alias.clone(), Matrix4::identity(), e)
+            });
+
+            // Nothing to look up yet.
+            assert_eq!(context.pick(0, 0), None);
+
+            context.render_picking(&parts, &mut display_list);
+            assert_eq!(
+                *backend.draws.borrow(),
+                vec![
+                    (Primitive::Triangles, 0, 3, true),
+                    (Primitive::Triangles, 0, 3, true)
+                ]
+            );
+            assert_eq!(*backend.framebuffer.borrow(), Some(1000));
+
+            // Rows are read from the bottom, and zero is nothing.
+            assert_eq!(context.pick(10, 20), None);
+            assert_eq!(backend.reads.borrow().last(), Some(&(10, 459, 1, 1)));
+            *backend.integers.borrow_mut() = vec![ids[1].0 + 1];
+            assert_eq!(context.pick(10, 20), Some(ids[1]));
+            assert_eq!(*backend.framebuffer.borrow(), Some(1000));
+
+            *backend.integers.borrow_mut() = vec![0, ids[2].0 + 1, ids[0].0 + 1, ids[2].0 + 1];
+            assert_eq!(context.pick_rect(630, 470, 20, 20), vec![ids[0], ids[2]]);
+            assert_eq!(backend.reads.borrow().last(), Some(&(630, 0, 10, 10)));
+            assert!(context.pick_rect(640, 0, 10, 10).is_empty());
         }
+        assert!(backend.live.borrow().is_empty());
     }
 }
@@ -0,0 +1,451 @@
+use std::{cmp::Ordering, ops::RangeInclusive, rc::Rc};
+
+use cgmath::{InnerSpace, SquareMatrix};
+use ldraw::{color::Material, document::MultipartDocument, Matrix4, Vector3};
+
+use crate::{
+    backend::Backend,
+    camera::Easing,
+    display_list::{collect_placements, DisplayList, InstanceId, RenderMode},
+};
+
+// Where parts come from in a build animation.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BuildDirection {
+    // From above the model, dropping into place.
+    Above,
+    // Along the stud axis (Y) of each part, which most connections run
+    // along.
+    ConnectionAxis,
+}
+
+#[derive(Clone, Debug)]
+pub struct BuildAnimationOptions {
+    pub direction: BuildDirection,
+    // Distance in LDU parts start off from their place.
+    pub distance: f32,
+    // Seconds each part takes to move into place.
+    pub duration: f32,
+    // Seconds between one part of a step starting to move and the next.
+    pub stagger: f32,
+    pub easing: Easing,
+}
+
+impl Default for BuildAnimationOptions {
+    fn default() -> Self {
+        BuildAnimationOptions {
+            direction: BuildDirection::Above,
+            distance: 80.0,
+            duration: 0.5,
+            stagger: 0.15,
+            easing: Easing::EaseOut,
+        }
+    }
+}
+
+// An instance placed by a step.
+#[derive(Debug)]
+struct StepInstance {
+    id: InstanceId,
+    matrix: Matrix4,
+    // Render mode the document gives it.
+    mode: RenderMode,
+}
+
+impl StepInstance {
+    fn offset(&self, options: &BuildAnimationOptions) -> Vector3 {
+        let direction = match options.direction {
+            BuildDirection::Above => -Vector3::unit_y(),
+            BuildDirection::ConnectionAxis => {
+                let axis = -self.matrix.y.truncate();
+                if axis.magnitude2() > f32::EPSILON {
+                    axis.normalize()
+                } else {
+                    -Vector3::unit_y()
+                }
+            }
+        };
+        direction * options.distance
+    }
+}
+
+// Shows a model step by step, like building instructions: parts of the
+// current step are drawn as they are, those of earlier steps in
+// `previous_mode` and those of later steps not at all.
+//
+// The player owns the render modes of the instances it places; parts
+// ghosted or hidden by MLCad prefixes stay so in every step.
+#[derive(Debug)]
+pub struct StepPlayer {
+    // Instances placed in each top level step.
+    steps: Vec<Vec<StepInstance>>,
+    current: usize,
+    previous_mode: RenderMode,
+    // Playing build animation of the current step, and when it started.
+    animation: Option<(BuildAnimationOptions, Option<f32>)>,
+}
+
+impl StepPlayer {
+    // Places the parts of `document` in `display_list`, showing the first
+    // step. Parts of a submodel belong to the step placing the submodel.
+    pub fn new<GL: Backend>(
+        gl: Rc<GL>,
+        display_list: &mut DisplayList<GL>,
+        document: &MultipartDocument,
+    ) -> Self {
+        let default = Material::default();
+        let mut player = StepPlayer {
+            steps: vec![],
+            current: 0,
+            previous_mode: RenderMode::Ghosted,
+            animation: None,
+        };
+        for commands in document.body.steps() {
+            let mut placements = vec![];
+            collect_placements(
+                commands,
+                Matrix4::identity(),
+                &default,
+                RenderMode::Normal,
+                document,
+                &mut placements,
+            );
+
+            let mut step = vec![];
+            for (alias, matrix, material, mode) in placements {
+                let material = display_list.share(material);
+                let id = display_list.add_shared(Rc::clone(&gl), alias.clone(), matrix, material);
+                step.push(StepInstance { id, matrix, mode });
+            }
+            player.steps.push(step);
+        }
+        player.apply(display_list, 0..=player.steps.len().saturating_sub(1));
+        player
+    }
+
+    pub fn step_count(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn current_step(&self) -> usize {
+        self.current
+    }
+
+    // Instances placed in `step`, in the order the document places them.
+    pub fn instances(&self, step: usize) -> impl Iterator<Item = InstanceId> + '_ {
+        self.steps
+            .get(step)
+            .into_iter()
+            .flat_map(|e| e.iter().map(|e| e.id))
+    }
+
+    pub fn previous_mode(&self) -> RenderMode {
+        self.previous_mode
+    }
+
+    // Draws parts of earlier steps as `mode` has it: dimmed with
+    // `RenderMode::Ghosted`, the default, or as they are with
+    // `RenderMode::Normal`.
+    pub fn set_previous_mode<GL: Backend>(
+        &mut self,
+        display_list: &mut DisplayList<GL>,
+        mode: RenderMode,
+    ) {
+        self.previous_mode = mode;
+        if self.current > 0 {
+            self.apply(display_list, 0..=self.current - 1);
+        }
+    }
+
+    // Shows everything up to `step`, or up to the last step if there are
+    // not that many. A playing build animation is cut short.
+    pub fn set_step<GL: Backend>(&mut self, display_list: &mut DisplayList<GL>, step: usize) {
+        self.stop_build_animation(display_list);
+        let step = step.min(self.steps.len().saturating_sub(1));
+        let changed = self.current.min(step)..=self.current.max(step);
+        self.current = step;
+        self.apply(display_list, changed);
+    }
+
+    // Moves on to the next step. Returns whether there is one.
+    pub fn next_step<GL: Backend>(&mut self, display_list: &mut DisplayList<GL>) -> bool {
+        if self.current + 1 >= self.steps.len() {
+            return false;
+        }
+        self.set_step(display_list, self.current + 1);
+        true
+    }
+
+    // Goes back to the previous step. Returns whether there is one.
+    pub fn previous_step<GL: Backend>(&mut self, display_list: &mut DisplayList<GL>) -> bool {
+        if self.current == 0 {
+            return false;
+        }
+        self.set_step(display_list, self.current - 1);
+        true
+    }
+
+    // Moves parts of the current step into place one after another from
+    // the next call to `update_build_animation`.
+    pub fn play_build_animation(&mut self, options: BuildAnimationOptions) {
+        self.animation = Some((options, None));
+    }
+
+    // Puts every part of the current step in place, if they are moving.
+    pub fn stop_build_animation<GL: Backend>(&mut self, display_list: &mut DisplayList<GL>) {
+        if self.animation.take().is_none() {
+            return;
+        }
+        for e in self.steps.get(self.current).into_iter().flatten() {
+            display_list.set_matrix(e.id, e.matrix);
+            display_list.set_render_mode(e.id, e.mode);
+        }
+    }
+
+    pub fn is_animating(&self) -> bool {
+        self.animation.is_some()
+    }
+
+    // Moves parts of the current step to where they are `time` seconds
+    // on the caller's clock into the playing build animation. Parts wait
+    // hidden until their turn. Returns whether the animation goes on.
+    pub fn update_build_animation<GL: Backend>(
+        &mut self,
+        display_list: &mut DisplayList<GL>,
+        time: f32,
+    ) -> bool {
+        let (options, started_at) = match self.animation.as_mut() {
+            Some(e) => e,
+            None => return false,
+        };
+        let elapsed = time - *started_at.get_or_insert(time);
+        let step = match self.steps.get(self.current) {
+            Some(e) => e,
+            None => {
+                self.animation = None;
+                return false;
+            }
+        };
+
+        for (index, e) in step.iter().enumerate() {
+            let start = index as f32 * options.stagger;
+            if elapsed < start {
+                display_list.set_render_mode(e.id, RenderMode::Hidden);
+                continue;
+            }
+            let t = if options.duration > 0.0 {
+                options.easing.apply((elapsed - start) / options.duration)
+            } else {
+                1.0
+            };
+            let offset = e.offset(options) * (1.0 - t);
+            display_list.set_matrix(e.id, Matrix4::from_translation(offset) * e.matrix);
+            display_list.set_render_mode(e.id, e.mode);
+        }
+
+        let end = step.len().saturating_sub(1) as f32 * options.stagger + options.duration;
+        if elapsed >= end {
+            self.animation = None;
+            return false;
+        }
+        true
+    }
+
+    fn mode(&self, step: usize) -> RenderMode {
+        match step.cmp(&self.current) {
+            Ordering::Less => self.previous_mode,
+            Ordering::Equal => RenderMode::Normal,
+            Ordering::Greater => RenderMode::Hidden,
+        }
+    }
+
+    // Brings instances of `steps` up to date with the current step.
+    fn apply<GL: Backend>(&self, display_list: &mut DisplayList<GL>, steps: RangeInclusive<usize>) {
+        for index in steps {
+            let mode = self.mode(index);
+            for e in self.steps.get(index).into_iter().flatten() {
+                display_list.set_render_mode(e.id, mode.max(e.mode));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, rc::Rc};
+
+    use cgmath::{Matrix4, SquareMatrix};
+
+    use ldraw::{
+        color::ColorReference,
+        document::{DocumentBuilder, MultipartDocument},
+        elements::{Command, Meta, MlcadMode, PartReference},
+        PartAlias, Vector3,
+    };
+
+    use crate::{
+        camera::Easing,
+        display_list::{DisplayList, InstanceId, RenderMode},
+        test_support::RecordingBackend,
+    };
+
+    use super::{BuildAnimationOptions, BuildDirection, StepPlayer};
+
+    #[test]
+    fn test_step_player() {
+        let placed = |name: &str| {
+            Command::PartReference(PartReference {
+                color: ColorReference::Current,
+                matrix: Matrix4::identity(),
+                name: PartAlias::from(name),
+            })
+        };
+        let submodel = DocumentBuilder::new("sub.ldr", "Submodel", "")
+            .command(placed("b.dat"))
+            .command(Command::Meta(Meta::Step))
+            .command(placed("c.dat"))
+            .build();
+        let document = MultipartDocument::new(
+            DocumentBuilder::new("model.ldr", "Model", "")
+                .command(placed("a.dat"))
+                .command(Command::Meta(Meta::Step))
+                .command(placed("sub.ldr"))
+                .command(Command::Meta(Meta::Mlcad(
+                    MlcadMode::Hide,
+                    Box::new(placed("a.dat")),
+                )))
+                .command(Command::Meta(Meta::Step))
+                .command(placed("d.dat"))
+                .command(Command::Meta(Meta::Step))
+                .build(),
+            HashMap::from([(PartAlias::from("sub.ldr"), submodel)]),
+        );
+
+        let gl = Rc::new(RecordingBackend::default());
+        {
+            let mut display_list = DisplayList::default();
+            let mut player = StepPlayer::new(Rc::clone(&gl), &mut display_list, &document);
+            assert_eq!(player.step_count(), 3);
+            assert_eq!(display_list.count(), 5);
+            let modes = |player: &StepPlayer, display_list: &DisplayList<RecordingBackend>| {
+                (0..player.step_count())
+                    .map(|step| {
+                        player
+                            .instances(step)
+                            .map(|id| display_list.render_mode(id).unwrap())
+                            .collect::<Vec<_>>()
+                    })
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(
+                modes(&player, &display_list),
+                vec![
+                    vec![RenderMode::Normal],
+                    vec![RenderMode::Hidden; 3],
+                    vec![RenderMode::Hidden]
+                ]
+            );
+
+            // Submodels are placed whole, and hidden parts stay hidden.
+            assert!(player.next_step(&mut display_list));
+            assert_eq!(
+                modes(&player, &display_list),
+                vec![
+                    vec![RenderMode::Ghosted],
+                    vec![RenderMode::Normal, RenderMode::Normal, RenderMode::Hidden],
+                    vec![RenderMode::Hidden]
+                ]
+            );
+
+            player.set_step(&mut display_list, 10);
+            assert_eq!(player.current_step(), 2);
+            assert!(!player.next_step(&mut display_list));
+            player.set_previous_mode(&mut display_list, RenderMode::Normal);
+            assert_eq!(
+                modes(&player, &display_list),
+                vec![
+                    vec![RenderMode::Normal],
+                    vec![RenderMode::Normal, RenderMode::Normal, RenderMode::Hidden],
+                    vec![RenderMode::Normal]
+                ]
+            );
+
+            player.set_step(&mut display_list, 0);
+            assert!(!player.previous_step(&mut display_list));
+            assert_eq!(
+                modes(&player, &display_list),
+                vec![
+                    vec![RenderMode::Normal],
+                    vec![RenderMode::Hidden; 3],
+                    vec![RenderMode::Hidden]
+                ]
+            );
+        }
+        assert!(gl.live.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_build_animation() {
+        let alias = PartAlias::from("a.dat");
+        let placed = |x: f32| Matrix4::from_translation(Vector3::new(x, 0.0, 0.0));
+        let document = MultipartDocument::new(
+            DocumentBuilder::new("model.ldr", "Model", "")
+                .commands([0.0, 20.0].map(|x| {
+                    Command::PartReference(PartReference {
+                        color: ColorReference::Current,
+                        matrix: placed(x),
+                        name: alias.clone(),
+                    })
+                }))
+                .build(),
+            HashMap::new(),
+        );
+
+        let gl = Rc::new(RecordingBackend::default());
+        {
+            let mut display_list = DisplayList::default();
+            let mut player = StepPlayer::new(Rc::clone(&gl), &mut display_list, &document);
+            let ids = player.instances(0).collect::<Vec<_>>();
+            let matrix = |display_list: &DisplayList<RecordingBackend>, id: InstanceId| {
+                let buffer = &display_list.map[&alias].opaque;
+                let index = buffer.ids.iter().position(|e| *e == id).unwrap();
+                buffer.model_view_matrices[index]
+            };
+
+            player.play_build_animation(BuildAnimationOptions {
+                direction: BuildDirection::Above,
+                distance: 10.0,
+                duration: 1.0,
+                stagger: 0.5,
+                easing: Easing::Linear,
+            });
+            assert!(player.update_build_animation(&mut display_list, 2.0));
+            assert!(player.update_build_animation(&mut display_list, 2.25));
+            assert_eq!(
+                matrix(&display_list, ids[0]),
+                Matrix4::from_translation(Vector3::new(0.0, -7.5, 0.0))
+            );
+            assert_eq!(display_list.render_mode(ids[1]), Some(RenderMode::Hidden));
+
+            // The second part starts moving after the first.
+            assert!(player.update_build_animation(&mut display_list, 3.0));
+            assert_eq!(matrix(&display_list, ids[0]), placed(0.0));
+            assert_eq!(
+                matrix(&display_list, ids[1]),
+                Matrix4::from_translation(Vector3::new(20.0, -5.0, 0.0))
+            );
+            assert_eq!(display_list.render_mode(ids[1]), Some(RenderMode::Normal));
+            assert!(!player.update_build_animation(&mut display_list, 3.5));
+            assert!(!player.is_animating());
+            assert_eq!(matrix(&display_list, ids[1]), placed(20.0));
+
+            // Stopping puts everything in place at once.
+            player.play_build_animation(BuildAnimationOptions::default());
+            player.update_build_animation(&mut display_list, 0.0);
+            player.stop_build_animation(&mut display_list);
+            assert_eq!(matrix(&display_list, ids[0]), placed(0.0));
+            assert_eq!(display_list.render_mode(ids[1]), Some(RenderMode::Normal));
+        }
+        assert!(gl.live.borrow().is_empty());
+    }
+}
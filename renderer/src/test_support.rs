@@ -0,0 +1,212 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+};
+
+use ldraw::Vector3;
+use ldraw_ir::{
+    geometry::BoundingBox3,
+    part::{PartBufferBuilder, PartBuilder},
+};
+
+use crate::{
+    backend::{Backend, Blending, BufferUsage, Primitive, TextureFormat},
+    error::ShaderError,
+};
+
+// Hands out numbered objects and records uploads and draw calls.
+#[derive(Default)]
+pub(crate) struct RecordingBackend {
+    next: RefCell<u32>,
+    pub(crate) live: RefCell<HashSet<u32>>,
+    pub(crate) uploads: RefCell<Vec<usize>>,
+    pub(crate) sub_uploads: RefCell<Vec<(usize, usize)>>,
+    pub(crate) draws: RefCell<Vec<(Primitive, usize, usize, bool)>>,
+    pub(crate) framebuffer: RefCell<Option<u32>>,
+    pub(crate) blending: RefCell<Vec<(Option<u32>, Blending)>>,
+    pub(crate) color_masked: RefCell<bool>,
+    pub(crate) masked_draws: RefCell<usize>,
+    pub(crate) uniform_buffers: RefCell<HashMap<u32, Option<u32>>>,
+    pub(crate) samples: RefCell<Vec<u32>>,
+    pub(crate) resolves: RefCell<Vec<(u32, Option<u32>)>>,
+    // Read back in place of the integer attachment, and where.
+    pub(crate) integers: RefCell<Vec<u32>>,
+    pub(crate) reads: RefCell<Vec<(u32, u32, u32, u32)>>,
+}
+
+impl RecordingBackend {
+    fn create(&self) -> Result<u32, String> {
+        let mut next = self.next.borrow_mut();
+        *next += 1;
+        self.live.borrow_mut().insert(*next);
+        Ok(*next)
+    }
+
+    fn delete(&self, object: u32) {
+        assert!(self.live.borrow_mut().remove(&object));
+    }
+}
+
+impl Backend for RecordingBackend {
+    type Buffer = u32;
+    type VertexArray = u32;
+    type Program = u32;
+    type UniformLocation = u32;
+    type Texture = u32;
+    type Framebuffer = u32;
+    type Renderbuffer = u32;
+
+    fn create_vertex_array(&self) -> Result<u32, String> {
+        self.create()
+    }
+    fn bind_vertex_array(&self, _: Option<u32>) {}
+    fn delete_vertex_array(&self, array: u32) {
+        self.delete(array)
+    }
+
+    fn create_buffer(&self) -> Result<u32, String> {
+        self.create()
+    }
+    fn buffer_data(&self, _: Option<u32>, data: &[f32], _: BufferUsage) {
+        self.uploads.borrow_mut().push(data.len());
+    }
+    fn buffer_data_u32(&self, _: Option<u32>, _: &[u32], _: BufferUsage) {}
+    fn buffer_sub_data(&self, _: Option<u32>, offset: usize, data: &[f32]) {
+        self.sub_uploads.borrow_mut().push((offset, data.len()));
+    }
+    fn buffer_sub_data_u32(&self, _: Option<u32>, _: usize, _: &[u32]) {}
+    fn delete_buffer(&self, buffer: u32) {
+        self.delete(buffer)
+    }
+
+    fn vertex_attribute(&self, _: u32, _: Option<u32>, _: i32, _: i32, _: i32) {}
+    fn vertex_attribute_u32(&self, _: u32, _: Option<u32>, _: i32, _: i32, _: i32) {}
+    fn vertex_attribute_divisor(&self, _: u32, _: u32) {}
+    fn disable_vertex_attribute(&self, _: u32) {}
+
+    fn compile_program(&self, _: &str, _: &str) -> Result<u32, ShaderError> {
+        self.create().map_err(ShaderError::CreationError)
+    }
+    fn use_program(&self, _: Option<u32>) {}
+    fn delete_program(&self, program: u32) {
+        self.delete(program)
+    }
+    fn attribute_location(&self, _: u32, _: &str) -> Option<u32> {
+        Some(0)
+    }
+    fn uniform_location(&self, _: u32, _: &str) -> Option<u32> {
+        Some(0)
+    }
+
+    fn uniform_block_binding(&self, _: u32, _: &str, _: u32) {}
+    fn bind_uniform_buffer(&self, binding: u32, buffer: Option<u32>) {
+        self.uniform_buffers.borrow_mut().insert(binding, buffer);
+    }
+
+    fn uniform_i32(&self, _: Option<&u32>, _: i32) {}
+    fn uniform_f32(&self, _: Option<&u32>, _: f32) {}
+    fn uniform_vec3(&self, _: Option<&u32>, _: &[f32; 3]) {}
+    fn uniform_vec4(&self, _: Option<&u32>, _: &[f32; 4]) {}
+    fn uniform_mat3(&self, _: Option<&u32>, _: &[f32; 9]) {}
+    fn uniform_mat4(&self, _: Option<&u32>, _: &[f32; 16]) {}
+
+    fn create_texture(&self, _: u32, _: u32, _: &[u8]) -> Result<u32, String> {
+        self.create()
+    }
+    fn create_environment_texture(&self, _: u32, _: u32, _: &[Vec<f32>]) -> Result<u32, String> {
+        self.create()
+    }
+    fn bind_texture(&self, _: u32, _: Option<u32>) {}
+    fn delete_texture(&self, texture: u32) {
+        self.delete(texture)
+    }
+
+    fn create_render_target(&self, _: u32, _: u32, _: TextureFormat) -> Result<u32, String> {
+        self.create()
+    }
+    fn create_framebuffer(&self, _: &[u32], _: Option<u32>) -> Result<u32, String> {
+        self.create()
+    }
+    fn bind_framebuffer(&self, framebuffer: Option<u32>) {
+        *self.framebuffer.borrow_mut() = framebuffer;
+    }
+    fn delete_framebuffer(&self, framebuffer: u32) {
+        self.delete(framebuffer)
+    }
+
+    fn max_samples(&self) -> u32 {
+        8
+    }
+    fn create_multisample_target(
+        &self,
+        _: u32,
+        _: u32,
+        samples: u32,
+        _: TextureFormat,
+    ) -> Result<u32, String> {
+        self.samples.borrow_mut().push(samples);
+        self.create()
+    }
+    fn delete_renderbuffer(&self, renderbuffer: u32) {
+        self.delete(renderbuffer)
+    }
+    fn create_multisample_framebuffer(&self, _: u32, _: u32) -> Result<u32, String> {
+        self.create()
+    }
+    fn resolve_framebuffer(&self, source: u32, destination: Option<u32>, _: u32, _: u32) {
+        self.resolves.borrow_mut().push((source, destination));
+        *self.framebuffer.borrow_mut() = destination;
+    }
+    fn clear_color_attachment(&self, _: u32, _: &[f32; 4]) {}
+    fn clear_integer_attachment(&self, _: u32, _: u32) {}
+    fn clear_depth(&self) {}
+    fn read_integers(&self, x: u32, y: u32, width: u32, height: u32) -> Vec<u32> {
+        self.reads.borrow_mut().push((x, y, width, height));
+        let mut integers = self.integers.borrow().clone();
+        integers.resize((width * height) as usize, 0);
+        integers
+    }
+
+    fn set_initial_state(&self) {}
+    fn viewport(&self, _: u32, _: u32) {}
+    fn set_culling(&self, _: bool) {}
+    fn set_blending(&self, blending: Blending) {
+        self.blending
+            .borrow_mut()
+            .push((*self.framebuffer.borrow(), blending));
+    }
+    fn set_depth_test(&self, _: bool) {}
+    fn set_depth_write(&self, _: bool) {}
+    fn set_color_write(&self, enabled: bool) {
+        *self.color_masked.borrow_mut() = !enabled;
+    }
+
+    fn draw_arrays(&self, primitive: Primitive, first: usize, count: usize) {
+        *self.masked_draws.borrow_mut() += *self.color_masked.borrow() as usize;
+        self.draws
+            .borrow_mut()
+            .push((primitive, first, count, false));
+    }
+    fn draw_arrays_instanced(&self, primitive: Primitive, first: usize, count: usize, _: usize) {
+        *self.masked_draws.borrow_mut() += *self.color_masked.borrow() as usize;
+        self.draws
+            .borrow_mut()
+            .push((primitive, first, count, true));
+    }
+}
+
+// A single triangle on y = 0.
+pub(crate) fn triangle() -> PartBuilder {
+    let mut part_builder = PartBufferBuilder::default();
+    for x in [0.0, 1.0, 0.0] {
+        part_builder
+            .uncolored_mesh
+            .add(&Vector3::new(x, 0.0, 1.0 - x), &Vector3::unit_y());
+    }
+    PartBuilder::new(
+        part_builder,
+        HashMap::new(),
+        BoundingBox3::zero(),
+        &Vector3::new(0.0, 0.0, 0.0),
+    )
+}
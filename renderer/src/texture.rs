@@ -0,0 +1,141 @@
+use std::{collections::HashMap, rc::Rc};
+
+use image::load_from_memory;
+use ldraw::PartAlias;
+
+use crate::backend::Backend;
+
+// Images `!TEXMAP` statements project onto parts, by file name. Names are
+// normalized like `PartAlias`, so case and path separators don't matter.
+#[derive(Debug)]
+pub struct Textures<GL: Backend> {
+    gl: Rc<GL>,
+
+    textures: HashMap<String, GL::Texture>,
+}
+
+impl<GL: Backend> Textures<GL> {
+    pub fn new(gl: Rc<GL>) -> Self {
+        Textures {
+            gl,
+            textures: HashMap::new(),
+        }
+    }
+
+    // Decodes and uploads image file `bytes` as `name`, replacing any
+    // texture of the same name.
+    pub fn insert(&mut self, name: &str, bytes: &[u8]) -> Result<(), String> {
+        let image = load_from_memory(bytes)
+            .map_err(|e| e.to_string())?
+            .to_rgba8();
+        let texture = self
+            .gl
+            .create_texture(image.width(), image.height(), image.as_raw())?;
+        if let Some(e) = self.textures.insert(PartAlias::normalize(name), texture) {
+            self.gl.delete_texture(e);
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<GL::Texture> {
+        self.textures.get(&PartAlias::normalize(name)).copied()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.textures.contains_key(&PartAlias::normalize(name))
+    }
+}
+
+impl<GL: Backend> Drop for Textures<GL> {
+    fn drop(&mut self) {
+        for texture in self.textures.values() {
+            self.gl.delete_texture(*texture);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, rc::Rc};
+
+    use image::{DynamicImage, ImageOutputFormat, RgbaImage};
+    use ldraw::{color::Material, Vector2, Vector3, Vector4};
+    use ldraw_ir::{
+        geometry::BoundingBox3,
+        part::{PartBufferBuilder, PartBuilder},
+        texture::Texture,
+    };
+
+    use crate::{
+        backend::Primitive, part::Part, shader::ProgramManager, state::RenderingContext,
+        test_support::RecordingBackend,
+    };
+
+    #[test]
+    fn test_textured_part() {
+        let texture = Texture {
+            texture: "logo.png".to_string(),
+            glossmap: None,
+        };
+        let mut part_builder = PartBufferBuilder::default();
+        for x in [0.0, 1.0, 0.0] {
+            part_builder
+                .uncolored_mesh
+                .add(&Vector3::new(x, 0.0, 1.0 - x), &Vector3::unit_y());
+        }
+        for x in [0.0, 1.0, 0.0] {
+            part_builder.uncolored_mesh.add_textured(
+                &Vector3::new(x, 1.0, 1.0 - x),
+                &Vector3::unit_y(),
+                &Vector2::new(x, 1.0 - x),
+                &Vector4::new(1.0, 0.0, 0.0, 1.0),
+                &texture,
+            );
+        }
+        for x in [0.0, 1.0, 0.0] {
+            part_builder
+                .uncolored_without_bfc_mesh
+                .add(&Vector3::new(x, 2.0, 1.0 - x), &Vector3::unit_y());
+        }
+        let builder = PartBuilder::new(
+            part_builder,
+            HashMap::new(),
+            BoundingBox3::zero(),
+            &Vector3::new(0.0, 0.0, 0.0),
+        );
+        let mut png = Vec::new();
+        DynamicImage::ImageRgba8(RgbaImage::new(2, 2))
+            .write_to(&mut png, ImageOutputFormat::Png)
+            .unwrap();
+
+        let backend = Rc::new(RecordingBackend::default());
+        {
+            let program_manager = ProgramManager::new(Rc::clone(&backend)).unwrap();
+            let mut context = RenderingContext::new(Rc::clone(&backend), program_manager);
+            backend.uploads.borrow_mut().clear();
+            let part = Part::create(&builder, Rc::clone(&backend));
+            // Untextured vertices are padded with zero coordinates.
+            assert_eq!(*backend.uploads.borrow(), vec![27, 27, 18]);
+            assert_eq!(part.part.textures.len(), 1);
+
+            assert!(context.add_texture("logo.png", b"not an image").is_err());
+            assert!(!context.has_texture("logo.png"));
+            context.add_texture("logo.png", &png).unwrap();
+            context.add_texture("logo.png", &png).unwrap();
+            assert!(context.has_texture("logo.png"));
+            assert!(context.has_texture("LOGO.PNG"));
+
+            // The textured run is drawn on its own.
+            context.render_single_part(&part, &Material::default(), false);
+            assert_eq!(
+                *backend.draws.borrow(),
+                vec![
+                    (Primitive::Triangles, 0, 3, false),
+                    (Primitive::Triangles, 3, 3, false),
+                    (Primitive::Triangles, 6, 3, false),
+                ]
+            );
+        }
+        assert!(backend.live.borrow().is_empty());
+    }
+}
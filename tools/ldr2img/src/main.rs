@@ -13,7 +13,7 @@ use async_std::{
 use clap::{App, Arg};
 use glutin::event_loop::EventLoop;
 use ldraw::{
-    library::{LibraryLoader, PartCache, resolve_dependencies},
+    library::{LibraryLoader, PartCache, load_textures, resolve_dependencies},
     parser::{parse_color_definition, parse_multipart_document},
     resolvers::local::LocalLoader,
 };
@@ -26,7 +26,9 @@ use ldraw_olr::{
 };
 use ldraw_renderer::{
     display_list::DisplayList,
+    environment::Environment,
     part::Part,
+    pipeline::ToneMapping,
 };
 
 #[tokio::main]
@@ -54,6 +56,25 @@ async fn main() {
             .short("s")
             .takes_value(true)
             .help("Maximum width/height pixel size"))
+        .arg(Arg::with_name("environment")
+            .long("environment")
+            .value_name("PATH")
+            .takes_value(true)
+            .help("Equirectangular HDR image to light the model with"))
+        .arg(Arg::with_name("bloom")
+            .long("bloom")
+            .help("Let luminous colors glow"))
+        .arg(Arg::with_name("tone_mapping")
+            .long("tone-mapping")
+            .value_name("OPERATOR")
+            .takes_value(true)
+            .possible_values(&["none", "reinhard", "aces"])
+            .help("Compress bright light instead of clipping it"))
+        .arg(Arg::with_name("exposure")
+            .long("exposure")
+            .value_name("SCALE")
+            .takes_value(true)
+            .help("Scale of light before tone mapping"))
         .get_matches();
 
     let ldrawdir = match matches.value_of("ldraw_dir") {
@@ -116,6 +137,7 @@ async fn main() {
         .collect::<HashMap<_, _>>();
 
     let mut display_list = DisplayList::from_multipart_document(Rc::clone(&gl), &document);
+    let textures = load_textures(&*loader, &document, &resolution_result).await;
 
     {
         let mut rc = context.rendering_context.borrow_mut();
@@ -123,6 +145,28 @@ async fn main() {
         rc.set_initial_state();
         rc.resize(size as _, size as _);
         rc.upload_shading_data();
+        rc.pipeline.studio_environment = true;
+        rc.pipeline.bloom = matches.is_present("bloom");
+        rc.pipeline.tone_mapping = match matches.value_of("tone_mapping") {
+            Some("reinhard") => ToneMapping::Reinhard,
+            Some("aces") => ToneMapping::Aces,
+            _ => ToneMapping::None,
+        };
+        if let Some(exposure) = matches.value_of("exposure") {
+            rc.pipeline.exposure = exposure.parse::<f32>().unwrap();
+        }
+
+        for (name, data) in textures.iter() {
+            if let Err(msg) = rc.add_texture(name, data) {
+                println!("Failed loading texture {}: {}", name, msg);
+            }
+        }
+
+        if let Some(path) = matches.value_of("environment") {
+            let reader = std::io::BufReader::new(std::fs::File::open(path).unwrap());
+            let environment = Environment::from_hdr(reader).unwrap();
+            rc.set_environment(&environment).unwrap();
+        }
     }
 
     let image = render_display_list(&context, &parts, &mut display_list);
@@ -6,22 +6,23 @@ use std::{
     vec::Vec,
 };
 
-use cgmath::{Deg, SquareMatrix};
+use cgmath::SquareMatrix;
 use glow::HasContext;
 use ldraw::{
     color::{ColorReference, Material, MaterialRegistry},
     document::{Document, MultipartDocument},
-    elements::{Command, Meta},
+    elements::{Command, Meta, RotStep},
     error::ResolutionError,
-    library::{resolve_dependencies, LibraryLoader, PartCache},
-    Matrix4, PartAlias, Point2, Point3, Vector2, Vector3,
+    library::{load_textures, resolve_dependencies, LibraryLoader, PartCache},
+    Matrix4, PartAlias, Point3, Vector3, Vector4,
 };
 use ldraw_ir::{geometry::BoundingBox3, part::bake_part};
 use ldraw_renderer::{
+    camera::{OrbitController, StepRotation},
     display_list::DisplayList,
     part::Part,
     shader::ProgramManager,
-    state::{PerspectiveCamera, RenderingContext},
+    state::RenderingContext,
 };
 
 #[derive(Clone, Debug)]
@@ -31,107 +32,10 @@ struct RenderingOrderItem {
     material: Material,
 }
 
-pub struct OrbitController {
-    last_pos: Option<Point2>,
-    pressed_at: f32,
-    pressed_position: Point2,
-    released_at: f32,
-    released_position: Point2,
-    pressing: bool,
-
-    latitude: f32,
-    longitude: f32,
-
-    pub radius: f32,
-
-    tick: Option<f32>,
-    velocity: Vector2,
-
-    pub camera: PerspectiveCamera,
-}
-
-impl OrbitController {
-    pub fn new() -> Self {
-        OrbitController {
-            last_pos: None,
-            pressed_at: 0.0,
-            pressed_position: Point2::new(0.0, 0.0),
-            released_at: 0.0,
-            released_position: Point2::new(0.0, 0.0),
-            pressing: false,
-
-            latitude: 0.785,
-            longitude: 0.262,
-            radius: 300.0,
-
-            velocity: Vector2::new(0.1, 0.0),
-            tick: None,
-
-            camera: PerspectiveCamera::new(
-                Point3::new(0.0, 0.0, 0.0),
-                Point3::new(0.0, 0.0, 0.0),
-                Deg(45.0),
-            ),
-        }
-    }
-
-    pub fn on_mouse_press(&mut self, pressed: bool) {
-        self.pressing = pressed;
-
-        if !pressed {
-            self.last_pos = None;
-        }
-    }
-
-    pub fn on_mouse_move(&mut self, x: f32, y: f32) {
-        if self.pressing {
-            if let Some(last_pos) = self.last_pos {
-                self.latitude -= (x - last_pos.x) * 0.01;
-                self.longitude = (self.longitude + (y - last_pos.y) * 0.01).clamp(
-                    -f32::consts::FRAC_PI_2 + 0.017,
-                    f32::consts::FRAC_PI_2 - 0.017,
-                );
-            }
-            self.last_pos = Some(Point2::new(x, y));
-        }
-    }
-
-    pub fn update(&mut self, tick: f32) {
-        if let Some(t) = self.tick {
-            let delta = tick - t;
-
-            self.latitude += self.velocity.x * delta;
-            self.longitude = (self.longitude + self.velocity.y * delta).clamp(
-                -f32::consts::FRAC_PI_2 + 0.017,
-                f32::consts::FRAC_PI_2 - 0.017,
-            );
-        }
-
-        self.tick = Some(tick);
-
-        self.camera.position = self.derive_coordinate();
-    }
-
-    fn derive_coordinate(&self) -> Point3 {
-        let look_at = &self.camera.look_at;
-        let x = self.latitude.sin() * self.longitude.cos() * self.radius + look_at.x;
-        let y = -self.longitude.sin() * self.radius + look_at.y;
-        let z = -self.latitude.cos() * self.longitude.cos() * self.radius + look_at.z;
-
-        Point3::new(x, y, z)
-    }
-}
-
-impl Default for OrbitController {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
 #[derive(Debug)]
 enum RenderingOrder {
     Item(RenderingOrderItem),
-    Step,
+    Step(Option<RotStep>),
 }
 
 fn traverse<'a, GL: HasContext>(
@@ -147,7 +51,10 @@ fn traverse<'a, GL: HasContext>(
     for cmd in document.commands.iter() {
         match cmd {
             Command::Meta(Meta::Step) => {
-                orders.push(RenderingOrder::Step);
+                orders.push(RenderingOrder::Step(None));
+            }
+            Command::Meta(Meta::RotStep(statement)) => {
+                orders.push(RenderingOrder::Step(Some(statement.clone())));
             }
             Command::PartReference(r) => {
                 if parent.subparts.contains_key(&r.name) {
@@ -240,6 +147,7 @@ pub struct App<GL: HasContext> {
     rendering_order: Vec<RenderingOrder>,
     animating: Vec<(RenderingOrderItem, f32, Matrix4, f32, f32)>,
     pub orbit: OrbitController,
+    step_rotation: StepRotation,
 
     pub state: State,
     pointer: Option<usize>,
@@ -251,6 +159,7 @@ pub struct App<GL: HasContext> {
 const FALL_INTERVAL: f32 = 0.2;
 const FALL_INTERVAL_UPPER_BOUND: f32 = 5.0;
 const FALL_DURATION: f32 = 0.5;
+const AUTO_ROTATE: f32 = 0.1;
 
 impl<GL: HasContext> App<GL>
 {
@@ -263,6 +172,12 @@ impl<GL: HasContext> App<GL>
         let context = RenderingContext::new(Rc::clone(&gl), program_manager);
         context.upload_shading_data();
 
+        let mut orbit = OrbitController::default();
+        orbit.min_distance = 100.0;
+        orbit.max_distance = 10000.0;
+        orbit.damping = 1.0;
+        orbit.auto_rotate = AUTO_ROTATE;
+
         App {
             gl,
             loader,
@@ -272,7 +187,8 @@ impl<GL: HasContext> App<GL>
             display_list: DisplayList::default(),
             rendering_order: Vec::new(),
             animating: Vec::new(),
-            orbit: OrbitController::default(),
+            orbit,
+            step_rotation: StepRotation::default(),
             state: State::Finished,
             pointer: None,
             fall_interval: FALL_INTERVAL,
@@ -307,6 +223,10 @@ impl<GL: HasContext> App<GL>
         document: &MultipartDocument,
         on_update: &F,
     ) -> Result<(), ResolutionError> {
+        if let Some(cycle) = document.find_circular_reference() {
+            return Err(ResolutionError::CircularReference(cycle));
+        }
+
         let resolution_result = resolve_dependencies(
             cache,
             &self.materials,
@@ -333,6 +253,12 @@ impl<GL: HasContext> App<GL>
             .collect::<HashMap<_, _>>();
 
         self.parts.extend(parts);
+        let textures = load_textures(&**self.loader, document, &resolution_result).await;
+        for (name, data) in textures.iter() {
+            if let Err(msg) = self.context.add_texture(name, data) {
+                println!("Failed loading texture {}: {}", name, msg);
+            }
+        }
         self.state = State::Playing;
         self.animating = Vec::new();
         self.display_list = DisplayList::default();
@@ -341,9 +267,16 @@ impl<GL: HasContext> App<GL>
         let (rendering_order, bounding_box) =
             create_rendering_list(Rc::clone(&self.gl), &self.parts, document);
         self.rendering_order = rendering_order;
+        self.step_rotation = StepRotation::default();
+        // Spinning would fight the views the steps ask for.
+        let rotates = self
+            .rendering_order
+            .iter()
+            .any(|e| matches!(e, RenderingOrder::Step(Some(_))));
+        self.orbit.auto_rotate = if rotates { 0.0 } else { AUTO_ROTATE };
         let center = bounding_box.center();
-        self.orbit.camera.look_at = Point3::new(center.x, center.y, center.z);
-        self.orbit.radius = (bounding_box.len_x() * bounding_box.len_x()
+        self.orbit.focus = Point3::new(center.x, center.y, center.z);
+        self.orbit.distance = (bounding_box.len_x() * bounding_box.len_x()
             + bounding_box.len_y() * bounding_box.len_y()
             + bounding_box.len_z() * bounding_box.len_z())
         .sqrt()
@@ -362,7 +295,7 @@ impl<GL: HasContext> App<GL>
 
             let mut count = 0;
             for i in start..self.rendering_order.len() {
-                if let RenderingOrder::Step = self.rendering_order[i] {
+                if let RenderingOrder::Step(_) = self.rendering_order[i] {
                     break;
                 }
                 count += 1;
@@ -388,6 +321,18 @@ impl<GL: HasContext> App<GL>
             return;
         }
 
+        if self.state == State::Step || self.pointer.is_none() {
+            // The statement ending the upcoming step sets its view.
+            let statement = self.rendering_order[next..].iter().find_map(|e| match e {
+                RenderingOrder::Step(statement) => Some(statement),
+                _ => None,
+            });
+            if let Some(Some(statement)) = statement {
+                self.step_rotation = self.step_rotation.apply(statement);
+                self.orbit.apply_step_rotation(&self.step_rotation);
+            }
+        }
+
         self.pointer = Some(next);
         match &self.rendering_order[next] {
             RenderingOrder::Item(item) => {
@@ -396,7 +341,7 @@ impl<GL: HasContext> App<GL>
                 self.state = State::Playing;
                 self.last_time = Some(time);
             }
-            RenderingOrder::Step => {
+            RenderingOrder::Step(_) => {
                 self.state = State::Step;
             }
         };
@@ -463,9 +408,7 @@ impl<GL: HasContext> App<GL>
     pub fn render(&mut self) {
         let gl = &self.gl;
 
-        unsafe {
-            gl.clear(glow::COLOR_BUFFER_BIT | glow::DEPTH_BUFFER_BIT);
-        }
+        self.context.begin_frame(&Vector4::new(1.0, 1.0, 1.0, 0.0));
 
         self.context
             .render_display_list(&self.parts, &mut self.display_list, false);
@@ -490,6 +433,7 @@ impl<GL: HasContext> App<GL>
             }
         }
         self.context.shading_data.opacity = 1.0;
+        self.context.end_frame();
 
         self.frames += 1;
 
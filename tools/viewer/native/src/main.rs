@@ -105,12 +105,12 @@ async fn main_loop(materials: MaterialRegistry, document: MultipartDocument, dep
             }
             WindowEvent::MouseInput { state, button, .. } => {
                 if button == MouseButton::Left {
-                    app.orbit.on_mouse_press(state == ElementState::Pressed);
+                    app.orbit.on_pointer_press(state == ElementState::Pressed);
                 }
             }
             WindowEvent::CursorMoved { position, .. } => {
                 app.orbit
-                    .on_mouse_move(position.x as f32, position.y as f32);
+                    .on_pointer_move(position.x as f32, position.y as f32);
             }
             _ => (),
         },
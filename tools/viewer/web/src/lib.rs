@@ -248,7 +248,7 @@ pub async fn run(path: JsValue) -> JsValue {
         let app = Rc::clone(&app);
         let closure = Closure::wrap(Box::new(move |event: web_sys::MouseEvent| {
             if let Ok(mut a) = app.try_borrow_mut() {
-                a.orbit.on_mouse_move(event.offset_x() as f32, event.offset_y() as f32);
+                a.orbit.on_pointer_move(event.offset_x() as f32, event.offset_y() as f32);
             }
         }) as Box<dyn FnMut(_)>);
         canvas.add_event_listener_with_callback("mousemove", closure.as_ref().unchecked_ref()).unwrap();
@@ -257,7 +257,7 @@ pub async fn run(path: JsValue) -> JsValue {
     {
         let app = Rc::clone(&app);
         let closure = Closure::wrap(Box::new(move |_event: web_sys::MouseEvent| {
-            app.borrow_mut().orbit.on_mouse_press(true);
+            app.borrow_mut().orbit.on_pointer_press(true);
         }) as Box<dyn FnMut(_)>);
         canvas.add_event_listener_with_callback("mousedown", closure.as_ref().unchecked_ref()).unwrap();
         closure.forget();
@@ -265,7 +265,7 @@ pub async fn run(path: JsValue) -> JsValue {
     {
         let app = Rc::clone(&app);
         let closure = Closure::wrap(Box::new(move |_event: web_sys::MouseEvent| {
-            app.borrow_mut().orbit.on_mouse_press(false);
+            app.borrow_mut().orbit.on_pointer_press(false);
         }) as Box<dyn FnMut(_)>);
         canvas.add_event_listener_with_callback("mouseup", closure.as_ref().unchecked_ref()).unwrap();
         closure.forget();
@@ -274,7 +274,7 @@ pub async fn run(path: JsValue) -> JsValue {
         let app = Rc::clone(&app);
         let closure = Closure::wrap(Box::new(move |event: web_sys::WheelEvent| {
             let app = &mut app.borrow_mut();
-            app.orbit.radius = (app.orbit.radius + event.delta_y() as f32).clamp(100.0, 10000.0);
+            app.orbit.zoom(event.delta_y() as f32);
         }) as Box<dyn FnMut(_)>);
         canvas.add_event_listener_with_callback("wheel", closure.as_ref().unchecked_ref()).unwrap();
         closure.forget();
@@ -290,7 +290,7 @@ pub async fn run(path: JsValue) -> JsValue {
                 match event.touches().length() {
                     1 => {
                         let t = event.touches().item(0).unwrap();
-                        a.orbit.on_mouse_move(t.page_x() as _, t.page_y() as _);
+                        a.orbit.on_pointer_move(t.page_x() as _, t.page_y() as _);
                     },
                     2 => {
                         let t1 = event.touches().item(0).unwrap();
@@ -306,7 +306,7 @@ pub async fn run(path: JsValue) -> JsValue {
                         if pd != 0.0 {
                             let distance_delta = sd - pd;
 
-                            a.orbit.radius = (a.orbit.radius - distance_delta).clamp(100.0, 10000.0);
+                            a.orbit.zoom(-distance_delta);
                         }
                         *distance.borrow_mut() = sd;
                     },
@@ -320,7 +320,7 @@ pub async fn run(path: JsValue) -> JsValue {
     {
         let app = Rc::clone(&app);
         let closure = Closure::wrap(Box::new(move |_event: web_sys::TouchEvent| {
-            app.borrow_mut().orbit.on_mouse_press(true);
+            app.borrow_mut().orbit.on_pointer_press(true);
         }) as Box<dyn FnMut(_)>);
         canvas.add_event_listener_with_callback("touchstart", closure.as_ref().unchecked_ref()).unwrap();
         closure.forget();
@@ -329,7 +329,7 @@ pub async fn run(path: JsValue) -> JsValue {
         let distance = Rc::clone(&distance);
         let app = Rc::clone(&app);
         let closure = Closure::wrap(Box::new(move |_event: web_sys::TouchEvent| {
-            app.borrow_mut().orbit.on_mouse_press(false);
+            app.borrow_mut().orbit.on_pointer_press(false);
             *distance.borrow_mut() = 0.0;
         }) as Box<dyn FnMut(_)>);
         canvas.add_event_listener_with_callback("touchend", closure.as_ref().unchecked_ref()).unwrap();